@@ -0,0 +1,34 @@
+//! Generates IDL field name constants when the "idl-constants" feature
+//! is enabled.  See src/idlgen.rs.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/idlgen.rs"]
+mod idlgen;
+
+const DEFAULT_IDL_PATH: &str = "/openils/conf/fm_IDL.xml";
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/idlgen.rs");
+
+    if env::var_os("CARGO_FEATURE_IDL_CONSTANTS").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=EG_IDL_FILE");
+
+    let idl_file = env::var("EG_IDL_FILE").unwrap_or(DEFAULT_IDL_PATH.to_string());
+
+    println!("cargo:rerun-if-changed={idl_file}");
+
+    let xml = fs::read_to_string(&idl_file)
+        .unwrap_or_else(|e| panic!("Cannot read IDL file '{idl_file}': {e}"));
+
+    let code = idlgen::generate(&xml).unwrap_or_else(|e| panic!("Cannot parse IDL: {e}"));
+
+    let out_dir = env::var("OUT_DIR").expect("Cargo sets OUT_DIR");
+    let dest = Path::new(&out_dir).join("idl_constants.rs");
+
+    fs::write(&dest, code).unwrap_or_else(|e| panic!("Cannot write {dest:?}: {e}"));
+}
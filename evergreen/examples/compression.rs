@@ -0,0 +1,77 @@
+//! Compare bytes on the wire for compressed and uncompressed result
+//! messages.  Requires no OpenSRF connection.
+//!
+//! cargo run --release --example compression
+use eg::osrf::message::{Message, MessageStatus, MessageType, Payload, Result};
+use eg::EgValue;
+use evergreen as eg;
+use std::time::Instant;
+
+/// Compress results of at least this many bytes.
+const THRESHOLD: usize = 65536;
+
+/// Build a result resembling a list of copies with call numbers.
+fn result_message(count: usize) -> Message {
+    let copies: Vec<json::JsonValue> = (0..count)
+        .map(|i| {
+            json::object! {
+                id: i,
+                barcode: format!("3100000{i:07}"),
+                status: {id: 0, name: "Available", holdable: "t"},
+                circ_lib: i % 30,
+                call_number: {
+                    id: i / 3,
+                    label: format!("FIC SMITH {}", i / 3),
+                    owning_lib: i % 30,
+                },
+                price: "24.95",
+                create_date: "2024-01-01T00:00:00-0500",
+            }
+        })
+        .collect();
+
+    let content = EgValue::from_json_value(copies.into()).expect("Valid JSON");
+
+    Message::new(
+        MessageType::Result,
+        1,
+        Payload::Result(Result::new(MessageStatus::Ok, "OK", "osrfResult", content)),
+    )
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>12} {:>12} {:>7} {:>10} {:>10}",
+        "copies", "plain", "compressed", "ratio", "pack ms", "unpack ms"
+    );
+
+    for count in [100, 1_000, 10_000, 50_000] {
+        let msg = result_message(count);
+
+        let plain = msg.clone().into_json_value().dump();
+
+        let mut compressed_msg = msg.clone();
+        compressed_msg.set_compress_threshold(Some(THRESHOLD));
+
+        let start = Instant::now();
+        let compressed = compressed_msg.into_json_value().dump();
+        let pack = start.elapsed();
+
+        let start = Instant::now();
+        let parsed = Message::from_json_value(json::parse(&compressed).unwrap(), true)
+            .expect("Valid message");
+        let unpack = start.elapsed();
+
+        assert!(parsed.payload() == msg.payload());
+
+        println!(
+            "{:>8} {:>12} {:>12} {:>6.1}x {:>10.1} {:>10.1}",
+            count,
+            plain.len(),
+            compressed.len(),
+            plain.len() as f64 / compressed.len() as f64,
+            pack.as_secs_f64() * 1000.0,
+            unpack.as_secs_f64() * 1000.0,
+        );
+    }
+}
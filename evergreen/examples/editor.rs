@@ -40,10 +40,7 @@ fn main() -> eg::EgResult<()> {
     editor.rollback()?;
 
     let args = auth::LoginArgs::new("br1mclark", "montyc1234", auth::LoginType::Temp, None);
-    let auth_ses = match auth::Session::login(&client, &args)? {
-        Some(s) => s,
-        None => panic!("Login failed"),
-    };
+    let auth_ses = auth::Session::login(&client, &args)?;
 
     let token = auth_ses.token();
 
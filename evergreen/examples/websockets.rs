@@ -1,5 +1,7 @@
 use eg::common::auth;
-use eg::osrf::message;
+use eg::osrf::client::Client;
+use eg::osrf::session::ClientSession;
+use eg::osrf::websocket::WebsocketOptions;
 use eg::util;
 use eg::EgValue;
 use evergreen as eg;
@@ -7,14 +9,13 @@ use std::io::Write;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use tungstenite as ws;
-use ws::protocol::Message;
-use ws::protocol::WebSocket;
-use ws::stream::MaybeTlsStream;
-
 /// Each websocket client will send this many requests in a loop.
 const REQS_PER_THREAD: usize = 100;
 
+/// Each websocket client sends requests for this many sessions at a
+/// time over its one connection.
+const SESSIONS_PER_THREAD: usize = 5;
+
 /// Number of parallel websocket clients to launch.
 /// Be cautious when setting this value, especially on a production
 /// system, since it's trivial to overwhelm a service with too many
@@ -25,6 +26,9 @@ const THREAD_COUNT: usize = 10;
 //const DEFAULT_URI: &str = "wss://redis.demo.kclseg.org:443/osrf-websocket-translator";
 const DEFAULT_URI: &str = "ws://127.0.0.1:7682";
 
+/// Skip TLS certificate verification for wss:// URIs.
+const ACCEPT_INVALID_CERTS: bool = false;
+
 /// How many times we repeat the entire batch.
 const NUM_ITERS: usize = 20;
 
@@ -73,96 +77,74 @@ fn main() {
 }
 
 fn run_thread() {
-    // TODO make SSL connections possible.
-    // https://docs.rs/tungstenite/latest/tungstenite/client/fn.client.html
-    let (mut client, _) = ws::client::connect(DEFAULT_URI).unwrap();
+    let mut options = WebsocketOptions::new();
+    options.set_raw_data_mode(true);
+
+    // Set to test wss:// URIs on development servers.
+    options.set_accept_invalid_certs(ACCEPT_INVALID_CERTS);
+
+    let client = Client::connect_via_websocket(DEFAULT_URI, &options).expect("Websocket connect");
 
     let mut counter = 0;
+    let mut rejected = 0;
 
     while counter < REQS_PER_THREAD {
-        send_one_request(&mut client, counter);
-        counter += 1;
+        rejected += send_requests(&client, counter);
+        counter += SESSIONS_PER_THREAD;
         if REQ_PAUSE > 0 {
             thread::sleep(Duration::from_millis(REQ_PAUSE));
         }
     }
 
-    client.close(None).ok();
+    if rejected > 0 {
+        println!("\nThread had {rejected} requests rejected by the server");
+    }
 }
 
-fn send_one_request(client: &mut WebSocket<MaybeTlsStream<std::net::TcpStream>>, count: usize) {
-    let echo = format!("Hello, World {count}");
-    let echostr = echo.as_str();
-
-    let message = json::object! {
-        thread: util::random_number(12),
-        service: SERVICE,
-        osrf_msg: [{
-            __c: "osrfMessage",
-            __p: {
-                threadTrace:1,
-                type: "REQUEST",
-                locale: "en-US",
-                timezone: "America/New_York",
-                api_level: 1,
-                ingress: "opensrf",
-                payload:{
-                    __c: "osrfMethod",
-                    __p:{
-                        method: "opensrf.system.echo",
-                        params: [echostr],
-                    }
-                }
+/// Send one request per session before collecting any responses, so
+/// replies for multiple sessions are multiplexed over the websocket.
+///
+/// Returns the number of requests rejected by the translator.
+fn send_requests(client: &Client, count: usize) -> usize {
+    let mut sessions: Vec<ClientSession> = (0..SESSIONS_PER_THREAD)
+        .map(|_| client.session(SERVICE))
+        .collect();
+
+    let mut requests = Vec::new();
+
+    for (idx, ses) in sessions.iter_mut().enumerate() {
+        let echo = format!("Hello, World {}", count + idx);
+
+        match ses.request("opensrf.system.echo", echo.as_str()) {
+            Ok(req) => requests.push((echo, req)),
+            Err(e) => {
+                eprintln!("Error in send: {e}");
+                return 0;
             }
-        }]
-    };
-
-    if let Err(e) = client.write_message(Message::text(message.dump())) {
-        eprintln!("Error in send: {e}");
-        return;
-    }
-
-    let response = match client.read_message() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Error in recv: {e}");
-            return;
         }
-    };
-
-    // NOTE this one-for-one request/response approach only works if we
-    // send exactly 1 thing to echo AND the server packages the Request
-    // Complete message in the same transport message as the reply.
-    if let Message::Text(text) = response {
-        if let Some(resp) = unpack_response(&text) {
-            assert_eq!(resp.as_str().unwrap(), echostr);
-            print!("+");
-            std::io::stdout().flush().ok();
-        }
-    }
-}
-
-fn unpack_response(text: &str) -> Option<EgValue> {
-    let mut ws_msg = json::parse(&text).unwrap();
-    let mut osrf_list = ws_msg["osrf_msg"].take();
-    let osrf_msg = osrf_list[0].take();
-
-    if osrf_msg.is_null() {
-        panic!("No response from request");
     }
 
-    let mut msg = message::Message::from_json_value(osrf_msg, true).unwrap();
+    let mut rejected = 0;
 
-    if let message::Payload::Result(ref mut res) = msg.payload_mut() {
-        Some(res.take_content())
-    } else if let message::Payload::Status(stat) = msg.payload() {
-        if *(stat.status()) as isize >= 300 {
-            panic!("Unexpected response status: {:?}", stat);
+    for (echo, mut req) in requests {
+        match req.first() {
+            Ok(Some(resp)) => {
+                assert_eq!(resp.as_str(), Some(echo.as_str()));
+                print!("+");
+                std::io::stdout().flush().ok();
+            }
+            Ok(None) => eprintln!("No response to echo request"),
+            // The translator is at its request limit.
+            Err(e) if e.is_transport() => {
+                rejected += 1;
+                print!("-");
+                std::io::stdout().flush().ok();
+            }
+            Err(e) => eprintln!("Error in recv: {e}"),
         }
-        return None;
-    } else {
-        panic!("No response data");
     }
+
+    rejected
 }
 
 /// Testing the HASH format for parameters and responses.
@@ -174,19 +156,19 @@ fn _test_formats() {
     println!("Logging in");
 
     let args = auth::LoginArgs::new("admin", "demo123", auth::LoginType::Temp, None);
-    let auth_ses = match auth::Session::login(&client, &args).expect("login()") {
-        Some(s) => s,
-        None => panic!("Login failed"),
-    };
+    let auth_ses = auth::Session::login(&client, &args).expect("login()");
 
     let token = auth_ses.token();
 
     println!("Logged in OK");
 
-    let (mut client, _) = ws::client::connect(DEFAULT_URI).unwrap();
+    let mut options = WebsocketOptions::new();
+    options.set_format("hash");
+
+    let client = Client::connect_via_websocket(DEFAULT_URI, &options).expect("Websocket connect");
 
     let name = format!("test-bucket-{}", util::random_number(8));
-    let bucket = json::object! {
+    let bucket = eg::hash! {
         "_classname": "cbreb",
         "owner": 1,
         "owning_lib": 1,
@@ -194,93 +176,25 @@ fn _test_formats() {
         // remaining fields are not required / have default values.
     };
 
-    let message = json::object! {
-        thread: util::random_number(12),
-        service: SERVICE,
-        format: "hash",
-        osrf_msg: [{
-            __c: "osrfMessage",
-            __p: {
-                threadTrace:1,
-                type: "REQUEST",
-                locale: "en-US",
-                timezone: "America/New_York",
-                api_level: 1,
-                ingress: "opensrf",
-                payload:{
-                    __c: "osrfMethod",
-                    __p:{
-                        method: "open-ils.actor.container.create",
-                        params: [token, "biblio", bucket.clone()]
-                    }
-                }
-            }
-        }]
-    };
-
-    if let Err(e) = client.write_message(Message::text(message.dump())) {
-        eprintln!("Error in send: {e}");
-        return;
-    }
-
-    let response = match client.read_message() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Error in recv: {e}");
-            return;
-        }
-    };
-
-    if let Message::Text(text) = response {
-        if let Some(resp) = unpack_response(&text) {
-            println!("Bucket created returned WS response: {}", resp.dump());
-        }
+    match client.send_recv_one(
+        "open-ils.actor",
+        "open-ils.actor.container.create",
+        vec![EgValue::from(token), EgValue::from("biblio"), bucket],
+    ) {
+        Ok(Some(resp)) => println!("Bucket created returned WS response: {}", resp.dump()),
+        Ok(None) => eprintln!("No response to bucket create"),
+        Err(e) => eprintln!("Error creating bucket: {e}"),
     }
 
     // Now fetch the bucket and make sure we can retrieve it as a hash
 
-    let message = json::object! {
-        thread: util::random_number(12),
-        service: "open-ils.pcrud",
-        format: "hash",
-        osrf_msg: [{
-            __c: "osrfMessage",
-            __p: {
-                threadTrace:1,
-                type: "REQUEST",
-                locale: "en-US",
-                timezone: "America/New_York",
-                api_level: 1,
-                ingress: "opensrf",
-                payload:{
-                    __c: "osrfMethod",
-                    __p:{
-                        method: "open-ils.pcrud.search.cbreb",
-                        params: [token, {"name": name.to_owned()}]
-                    }
-                }
-            }
-        }]
-    };
-
-    if let Err(e) = client.write_message(Message::text(message.dump())) {
-        eprintln!("Error in send: {e}");
-        return;
-    }
-
-    let response = match client.read_message() {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Error in recv: {e}");
-            return;
-        }
-    };
-
-    if let Message::Text(text) = response {
-        if let Some(resp) = unpack_response(&text) {
-            println!("Bucket retrieve returned WS response: {}", resp.dump());
-        }
+    match client.send_recv_one(
+        "open-ils.pcrud",
+        "open-ils.pcrud.search.cbreb",
+        vec![EgValue::from(token), eg::hash! {"name": name.as_str()}],
+    ) {
+        Ok(Some(resp)) => println!("Bucket retrieve returned WS response: {}", resp.dump()),
+        Ok(None) => eprintln!("No response to bucket retrieve"),
+        Err(e) => eprintln!("Error retrieving bucket: {e}"),
     }
-
-    client.close(None).ok();
 }
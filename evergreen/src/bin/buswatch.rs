@@ -71,6 +71,11 @@ impl BusWatch {
             obj["stats"] = EgValue::new_object();
 
             for key in keys.iter() {
+                // Presence keys are not queues and expire on their own.
+                if key.starts_with(bus::PRESENCE_PREFIX) {
+                    continue;
+                }
+
                 let l = self.bus.llen(key)?;
 
                 // The list may have cleared in the time between the
@@ -642,13 +642,16 @@ impl Shell {
             workstation,
         );
 
-        match auth::Session::login(self.client(), &args)? {
-            Some(s) => {
+        match auth::Session::login(self.client(), &args) {
+            Ok(s) => {
+                if let Some(evt) = s.workstation_event() {
+                    println!("Workstation not used: {evt}");
+                }
                 println!("Login succeeded: {}", s.token());
                 self.auth_session = Some(s);
             }
-            None => {
-                println!("Login failed");
+            Err(e) => {
+                println!("Login failed: {e}");
             }
         };
 
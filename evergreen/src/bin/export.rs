@@ -0,0 +1,629 @@
+use eg::idl;
+use eg::init::InitOptions;
+use eg::result::EgResult;
+use eg::Editor;
+use eg::EgValue;
+use evergreen as eg;
+use getopts;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+const DEFAULT_PROGRESS_INTERVAL: u64 = 10000;
+
+const HELP_TEXT: &str = r#"
+Export cstore query results as NDJSON or CSV.
+
+Rows are fetched one page at a time and written as they arrive, so
+exports of any size run in constant memory.
+
+eg-export --class circ --where checkin_time=null --flesh usr \
+    --format csv --columns id,usr.home_ou,due_date
+
+Query Options
+    --class <classname>
+        IDL class to export, e.g. "acp".  Rows are exported in
+        primary key order.
+
+    --query-file <path>
+        File containing a JSON query to run instead of a class search.
+        The query should order_by a unique column so no rows are
+        repeated or skipped between pages.
+
+    --where <field><op><value>
+        Filter on a field of the exported class.  Operators are
+        = != > >= < <= and ~ (case-insensitive LIKE).  Use "null"
+        for NULL, e.g. --where checkin_time=null.  May be repeated.
+
+    --flesh <field>[.<field>...]
+        Flesh a linked field of the exported class.  Dotted paths
+        flesh through linked classes, e.g. --flesh usr.home_ou.
+        May be repeated.  Only used with --class.
+
+    --page-size <count> [1000]
+        Rows to fetch per query.
+
+Output Options
+    --format <ndjson|csv> [ndjson]
+
+    --columns <field>[,<field>...]
+        CSV columns.  Dotted paths read fleshed fields, e.g.
+        usr.home_ou.  Defaults to the non-virtual fields of the
+        exported class in IDL order, or to the sorted keys of the
+        first row of a JSON query.
+
+    --no-header
+        Omit the CSV header row.
+
+    --null <value> [""]
+    --true <value> [t]
+    --false <value> [f]
+        How CSV renders NULL and boolean values.
+
+    --out-file <path>
+        Write to a file instead of stdout.
+
+    --progress <count> [10000]
+        Report progress to stderr every <count> rows.  0 disables.
+
+    --quiet
+        Disable progress and summary output.
+
+    Standard OpenSRF environment variables (e.g. OSRF_CONFIG) are
+    also supported.
+"#;
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Ndjson,
+    Csv,
+}
+
+/// How CSV renders values which are not strings or numbers.
+struct CsvValues {
+    null: String,
+    true_value: String,
+    false_value: String,
+}
+
+/// A CSV column: a field path and the datatype of the field, when known.
+struct Column {
+    path: Vec<String>,
+    datatype: Option<idl::DataType>,
+}
+
+struct ExportOptions {
+    classname: Option<String>,
+    query_file: Option<String>,
+    filters: Vec<EgValue>,
+    flesh: Vec<String>,
+    page_size: usize,
+    format: Format,
+    columns: Option<Vec<String>>,
+    header: bool,
+    csv_values: CsvValues,
+    out_file: Option<String>,
+    progress: u64,
+    quiet: bool,
+}
+
+fn read_options() -> EgResult<Option<ExportOptions>> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut opts = getopts::Options::new();
+
+    opts.optopt("", "class", "", "");
+    opts.optopt("", "query-file", "", "");
+    opts.optmulti("", "where", "", "");
+    opts.optmulti("", "flesh", "", "");
+    opts.optopt("", "page-size", "", "");
+    opts.optopt("", "format", "", "");
+    opts.optopt("", "columns", "", "");
+    opts.optflag("", "no-header", "");
+    opts.optopt("", "null", "", "");
+    opts.optopt("", "true", "", "");
+    opts.optopt("", "false", "", "");
+    opts.optopt("", "out-file", "", "");
+    opts.optopt("", "progress", "", "");
+    opts.optflag("", "quiet", "");
+    opts.optflag("h", "help", "");
+
+    let params = opts
+        .parse(&args[1..])
+        .map_err(|e| format!("Error parsing params: {e}"))?;
+
+    if params.opt_present("help") {
+        println!("{HELP_TEXT}");
+        return Ok(None);
+    }
+
+    let classname = params.opt_str("class");
+    let query_file = params.opt_str("query-file");
+
+    if classname.is_some() == query_file.is_some() {
+        return Err("Provide one of --class or --query-file".into());
+    }
+
+    let format = match params.opt_str("format").as_deref() {
+        None | Some("ndjson") => Format::Ndjson,
+        Some("csv") => Format::Csv,
+        Some(f) => return Err(format!("Unsupported format: {f}").into()),
+    };
+
+    let mut filters = Vec::new();
+    for filter in params.opt_strs("where") {
+        filters.push(parse_where(&filter)?);
+    }
+
+    let page_size = match params.opt_str("page-size") {
+        Some(s) => s
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("Invalid --page-size: {s}"))?,
+        None => DEFAULT_PAGE_SIZE,
+    };
+
+    let progress = match params.opt_str("progress") {
+        Some(s) => s
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid --progress {s}: {e}"))?,
+        None => DEFAULT_PROGRESS_INTERVAL,
+    };
+
+    let columns = params.opt_str("columns").map(|c| {
+        c.split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    });
+
+    Ok(Some(ExportOptions {
+        classname,
+        query_file,
+        filters,
+        flesh: params.opt_strs("flesh"),
+        page_size,
+        format,
+        columns,
+        header: !params.opt_present("no-header"),
+        csv_values: CsvValues {
+            null: params.opt_str("null").unwrap_or_default(),
+            true_value: params.opt_str("true").unwrap_or("t".to_string()),
+            false_value: params.opt_str("false").unwrap_or("f".to_string()),
+        },
+        out_file: params.opt_str("out-file"),
+        progress,
+        quiet: params.opt_present("quiet"),
+    }))
+}
+
+/// Translate a --where filter, e.g. "circ_lib>=4", into a where
+/// clause entry.
+fn parse_where(filter: &str) -> EgResult<EgValue> {
+    let pos = filter
+        .find(|c| "!=<>~".contains(c))
+        .ok_or_else(|| format!("Invalid --where filter: {filter}"))?;
+
+    let field = filter[..pos].trim();
+    let rest = &filter[pos..];
+
+    let (op, value) = ["!=", ">=", "<=", "=", ">", "<", "~"]
+        .iter()
+        .find_map(|op| rest.strip_prefix(op).map(|v| (*op, v)))
+        .ok_or_else(|| format!("Invalid --where operator: {filter}"))?;
+
+    if field.is_empty() {
+        return Err(format!("Invalid --where field: {filter}").into());
+    }
+
+    let value = match value {
+        "null" => EgValue::Null,
+        v => EgValue::from(v),
+    };
+
+    let condition = match op {
+        "=" => value,
+        "~" => eg::hash! {"ilike": value},
+        op => {
+            let mut c = eg::hash! {};
+            c[op] = value;
+            c
+        }
+    };
+
+    let mut clause = eg::hash! {};
+    clause[field] = condition;
+
+    Ok(clause)
+}
+
+/// Combine where clauses, any of which may be absent.
+fn and_where(clauses: Vec<EgValue>) -> EgValue {
+    let mut clauses: Vec<EgValue> = clauses.into_iter().filter(|c| !c.is_null()).collect();
+
+    match clauses.len() {
+        0 => EgValue::Null,
+        1 => clauses.remove(0),
+        _ => eg::hash! {"-and": clauses},
+    }
+}
+
+/// Build flesh ops for the provided dotted field paths.
+fn flesh_ops(classname: &str, paths: &[String]) -> EgResult<EgValue> {
+    let mut flesh_fields = eg::hash! {};
+    let mut depth = 0;
+
+    for path in paths {
+        let mut class = idl::get_class(classname)?;
+
+        for (level, field) in path.split('.').enumerate() {
+            let link = class
+                .links()
+                .get(field)
+                .ok_or_else(|| format!("{} has no linked field {field}", class.classname()))?;
+
+            let fields = &mut flesh_fields[class.classname()];
+            if fields.is_null() {
+                *fields = eg::array![];
+            }
+            if !fields.members().any(|f| f.as_str() == Some(field)) {
+                fields.push(field)?;
+            }
+
+            depth = depth.max(level + 1);
+            class = idl::get_class(link.class())?;
+        }
+    }
+
+    Ok(eg::hash! {
+        flesh: depth,
+        flesh_fields: flesh_fields,
+    })
+}
+
+/// Resolve CSV columns, finding each column's datatype via the IDL
+/// when exporting a class.
+fn resolve_columns(
+    classname: Option<&str>,
+    names: Option<&Vec<String>>,
+    first_row: Option<&EgValue>,
+) -> EgResult<Vec<String>> {
+    if let Some(names) = names {
+        return Ok(names.clone());
+    }
+
+    if let Some(classname) = classname {
+        let class = idl::get_class(classname)?;
+        let mut fields = class.real_fields();
+        fields.sort_by_key(|f| f.array_pos());
+        return Ok(fields.iter().map(|f| f.name().to_string()).collect());
+    }
+
+    let mut keys: Vec<String> = match first_row {
+        Some(row) => row.keys().map(|k| k.to_string()).collect(),
+        None => Vec::new(),
+    };
+
+    keys.sort();
+
+    Ok(keys)
+}
+
+fn column_datatype(classname: Option<&str>, path: &[String]) -> Option<idl::DataType> {
+    let mut class = idl::get_class(classname?).ok()?;
+
+    for (idx, field) in path.iter().enumerate() {
+        if idx == path.len() - 1 {
+            return class.get_field(field).map(|f| f.datatype().clone());
+        }
+        class = idl::get_class(class.links().get(field)?.class()).ok()?;
+    }
+
+    None
+}
+
+/// Quote a CSV value when needed.
+fn csv_quote(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r')
+        || value.starts_with(' ')
+        || value.ends_with(' ')
+    {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Render one CSV value.
+fn csv_value(value: &EgValue, datatype: Option<&idl::DataType>, values: &CsvValues) -> String {
+    let is_bool = datatype == Some(&idl::DataType::Bool);
+
+    match value {
+        EgValue::Null => values.null.clone(),
+        EgValue::Boolean(true) => values.true_value.clone(),
+        EgValue::Boolean(false) => values.false_value.clone(),
+        EgValue::String(s) if is_bool && s == "t" => values.true_value.clone(),
+        EgValue::String(s) if is_bool && s == "f" => values.false_value.clone(),
+        EgValue::String(s) => s.to_string(),
+        EgValue::Number(n) => n.to_string(),
+        // A fleshed object with no column path into it.
+        EgValue::Blessed(_) => match value.pkey_value() {
+            Some(v) => csv_value(v, None, values),
+            None => value.dump(),
+        },
+        _ => value.dump(),
+    }
+}
+
+fn csv_row(row: &EgValue, columns: &[Column], values: &CsvValues) -> String {
+    let mut cells = Vec::with_capacity(columns.len());
+
+    for column in columns {
+        let mut value = row;
+        for field in column.path.iter() {
+            value = &value[field.as_str()];
+        }
+        cells.push(csv_quote(&csv_value(
+            value,
+            column.datatype.as_ref(),
+            values,
+        )));
+    }
+
+    cells.join(",")
+}
+
+/// Translate IDL objects into plain hashes, recursively, with IDL
+/// boolean fields as JSON booleans.
+fn to_plain(value: &mut EgValue) {
+    let bool_fields: Vec<String> = match value.idl_class() {
+        Some(class) => class
+            .real_fields()
+            .iter()
+            .filter(|f| f.datatype() == &idl::DataType::Bool)
+            .map(|f| f.name().to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+
+    value.unbless();
+
+    for name in bool_fields {
+        let v = &mut value[name.as_str()];
+        match v.as_str() {
+            Some("t") => *v = EgValue::from(true),
+            Some("f") => *v = EgValue::from(false),
+            _ => {}
+        }
+    }
+
+    if value.is_array() {
+        value.members_mut().for_each(to_plain);
+    } else if value.is_hash() {
+        value.entries_mut().for_each(|(_, v)| to_plain(v));
+    }
+}
+
+struct Exporter {
+    options: ExportOptions,
+    out: BufWriter<Box<dyn Write>>,
+    columns: Option<Vec<Column>>,
+    count: u64,
+    started: Instant,
+}
+
+impl Exporter {
+    fn write_row(&mut self, mut row: EgValue) -> EgResult<()> {
+        let line = match self.options.format {
+            Format::Ndjson => {
+                to_plain(&mut row);
+                row.dump()
+            }
+            Format::Csv => {
+                if self.columns.is_none() {
+                    self.start_csv(&row)?;
+                }
+                csv_row(
+                    &row,
+                    self.columns.as_ref().unwrap(),
+                    &self.options.csv_values,
+                )
+            }
+        };
+
+        writeln!(self.out, "{line}").map_err(|e| format!("Write failed: {e}"))?;
+
+        self.count += 1;
+
+        if !self.options.quiet
+            && self.options.progress > 0
+            && self.count % self.options.progress == 0
+        {
+            let secs = self.started.elapsed().as_secs_f64();
+            eprintln!(
+                "Exported {} rows ({:.0} rows/sec)",
+                self.count,
+                self.count as f64 / secs
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Resolve our columns and write the header.
+    fn start_csv(&mut self, first_row: &EgValue) -> EgResult<()> {
+        let classname = self.options.classname.as_deref();
+
+        let names = resolve_columns(classname, self.options.columns.as_ref(), Some(first_row))?;
+
+        if self.options.header {
+            let header: Vec<String> = names.iter().map(|n| csv_quote(n)).collect();
+            writeln!(self.out, "{}", header.join(",")).map_err(|e| format!("Write failed: {e}"))?;
+        }
+
+        let columns = names
+            .iter()
+            .map(|name| {
+                let path: Vec<String> = name.split('.').map(|s| s.to_string()).collect();
+                let datatype = column_datatype(classname, &path);
+                Column { path, datatype }
+            })
+            .collect();
+
+        self.columns = Some(columns);
+
+        Ok(())
+    }
+
+    fn export(&mut self, editor: &mut Editor) -> EgResult<()> {
+        let filters = and_where(self.options.filters.drain(..).collect());
+        let page_size = self.options.page_size;
+
+        let pages = if let Some(classname) = self.options.classname.clone() {
+            let query = match filters {
+                // Searches require a query.
+                EgValue::Null => {
+                    let class = idl::get_class(&classname)?;
+                    let pkey = class
+                        .pkey()
+                        .ok_or_else(|| format!("{classname} requires a --where filter"))?;
+                    let mut query = eg::hash! {};
+                    query[pkey] = eg::hash! {"!=": EgValue::Null};
+                    query
+                }
+                f => f,
+            };
+
+            let ops = match self.options.flesh.is_empty() {
+                true => EgValue::Null,
+                false => flesh_ops(&classname, &self.options.flesh)?,
+            };
+
+            editor.search_paged(&classname, query, ops, page_size)?
+        } else {
+            let path = self.options.query_file.as_deref().unwrap();
+
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read query file {path}: {e}"))?;
+
+            let mut query = EgValue::parse(&text)?;
+
+            if !filters.is_null() {
+                query["where"] = and_where(vec![query["where"].take(), filters]);
+            }
+
+            editor.json_query_paged(query, page_size)
+        };
+
+        for row in pages {
+            self.write_row(row?)?;
+        }
+
+        if self.options.format == Format::Csv && self.columns.is_none() && self.options.header {
+            // No rows.  Still write the header if we know the columns.
+            let classname = self.options.classname.as_deref();
+            let names = resolve_columns(classname, self.options.columns.as_ref(), None)?;
+            if !names.is_empty() {
+                let header: Vec<String> = names.iter().map(|n| csv_quote(n)).collect();
+                writeln!(self.out, "{}", header.join(","))
+                    .map_err(|e| format!("Write failed: {e}"))?;
+            }
+        }
+
+        self.out
+            .flush()
+            .map_err(|e| format!("Write failed: {e}").into())
+    }
+}
+
+fn main() -> EgResult<()> {
+    let options = match read_options()? {
+        Some(o) => o,
+        None => return Ok(()),
+    };
+
+    let mut init_ops = InitOptions::new();
+    init_ops.skip_host_settings = true;
+
+    let client = eg::init::with_options(&init_ops)?;
+    let mut editor = Editor::new(&client);
+
+    let out: Box<dyn Write> = match options.out_file.as_deref() {
+        Some(path) => {
+            Box::new(fs::File::create(path).map_err(|e| format!("Cannot create {path}: {e}"))?)
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    let mut exporter = Exporter {
+        options,
+        out: BufWriter::new(out),
+        columns: None,
+        count: 0,
+        started: Instant::now(),
+    };
+
+    exporter.export(&mut editor)?;
+
+    if !exporter.options.quiet {
+        eprintln!(
+            "Export complete: {} rows in {:.2} seconds",
+            exporter.count,
+            exporter.started.elapsed().as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_csv_quote() {
+    assert_eq!(csv_quote("plain"), "plain");
+    assert_eq!(csv_quote("a,b"), "\"a,b\"");
+    assert_eq!(csv_quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+    assert_eq!(csv_quote("two\nlines"), "\"two\nlines\"");
+    assert_eq!(csv_quote(" padded"), "\" padded\"");
+    assert_eq!(csv_quote(""), "");
+}
+
+#[test]
+fn test_csv_value() {
+    let values = CsvValues {
+        null: "NULL".to_string(),
+        true_value: "yes".to_string(),
+        false_value: "no".to_string(),
+    };
+
+    let bool_type = Some(&idl::DataType::Bool);
+
+    assert_eq!(csv_value(&EgValue::Null, None, &values), "NULL");
+    assert_eq!(csv_value(&EgValue::from(true), None, &values), "yes");
+    assert_eq!(csv_value(&EgValue::from("t"), bool_type, &values), "yes");
+    assert_eq!(csv_value(&EgValue::from("f"), bool_type, &values), "no");
+    assert_eq!(csv_value(&EgValue::from("t"), None, &values), "t");
+    assert_eq!(csv_value(&EgValue::from(12), None, &values), "12");
+}
+
+#[test]
+fn test_parse_where() {
+    assert_eq!(parse_where("deleted=f").unwrap(), eg::hash! {deleted: "f"});
+    assert_eq!(
+        parse_where("checkin_time=null").unwrap(),
+        eg::hash! {checkin_time: EgValue::Null}
+    );
+    assert_eq!(
+        parse_where("stop_fines!=null").unwrap(),
+        eg::hash! {stop_fines: {"!=": EgValue::Null}}
+    );
+    assert_eq!(
+        parse_where("id>=100").unwrap(),
+        eg::hash! {id: {">=": "100"}}
+    );
+    assert_eq!(
+        parse_where("barcode~%abc%").unwrap(),
+        eg::hash! {barcode: {"ilike": "%abc%"}}
+    );
+    assert!(parse_where("nope").is_err());
+    assert!(parse_where("=5").is_err());
+}
@@ -0,0 +1,124 @@
+use eg::common::batch::{BatchRunner, BATCH_HELP_TEXT};
+use eg::common::billing;
+use eg::init::InitOptions;
+use eg::osrf::cache::Cache;
+use eg::result::EgResult;
+use eg::util;
+use eg::Editor;
+use eg::EgValue;
+use evergreen as eg;
+use getopts;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+const DEFAULT_LOCKFILE: &str = "/tmp/fine_generator-LOCK";
+
+const HELP_TEXT: &str = r#"
+Batch overdue fine generator.
+
+./eg-fine-generator --chunk-size 500 --checkpoint-file /tmp/fine_generator-CHECKPOINT
+
+General Options
+    --lockfile [/tmp/fine_generator-LOCK]
+        Full path to lock file
+
+    Standard OpenSRF environment variables (e.g. OSRF_CONFIG) are
+    also supported.
+"#;
+
+/// Find the next chunk of overdue circulations which may need fines.
+fn fetch_circ_ids(editor: &mut Editor, after: Option<i64>, limit: usize) -> EgResult<Vec<i64>> {
+    let query = eg::hash! {
+        select: {circ: ["id"]},
+        from: "circ",
+        where: {
+            id: {">": after.unwrap_or(0)},
+            xact_finish: EgValue::Null,
+            due_date: {"<": "now"},
+            "-or": [
+                {stop_fines: EgValue::Null},
+                {stop_fines: ["MAXFINES", "LONGOVERDUE"]},
+            ],
+        },
+        order_by: [{class: "circ", field: "id"}],
+        limit: limit,
+    };
+
+    let mut ids = Vec::new();
+    for circ in editor.json_query(query)? {
+        ids.push(circ.id()?);
+    }
+
+    Ok(ids)
+}
+
+/// Generate fines for a single circulation within its own transaction.
+fn generate_fines(editor: &mut Editor, circ_id: i64) -> EgResult<()> {
+    editor.xact_begin()?;
+
+    if let Err(e) = billing::generate_fines_for_circ(editor, circ_id) {
+        editor.rollback()?;
+        return Err(e);
+    }
+
+    editor.commit()
+}
+
+fn main() -> EgResult<()> {
+    let mut options = getopts::Options::new();
+
+    options.optflag("", "help", "Show this message");
+    options.optopt("", "lockfile", "", "");
+
+    BatchRunner::add_options(&mut options);
+
+    let args: Vec<String> = std::env::args().collect();
+
+    let params = options
+        .parse(&args[1..])
+        .or_else(|e| Err(format!("Error parsing params: {e}")))?;
+
+    if params.opt_present("help") {
+        println!("{HELP_TEXT}{BATCH_HELP_TEXT}");
+        return Ok(());
+    }
+
+    let mut runner = BatchRunner::from_params("fine-generator", &params)?;
+
+    // The lockfile is removed when this goes out of scope.
+    let lockfile_path = params
+        .opt_str("lockfile")
+        .unwrap_or(DEFAULT_LOCKFILE.to_string());
+
+    let _lockfile = util::LockfileGuard::create(&lockfile_path)?;
+
+    let mut init_ops = InitOptions::new();
+
+    // Host settings are only needed to find the cache servers.
+    init_ops.skip_host_settings = !runner.checkpoint().is_cache();
+
+    let client = eg::init::with_options(&init_ops)?;
+
+    if runner.checkpoint().is_cache() {
+        Cache::init_cache("global")?;
+    }
+
+    // Stop cleanly, saving our checkpoint, when asked to exit.
+    signal_hook::flag::register(SIGINT, runner.stop_flag())
+        .map_err(|e| format!("Cannot register signal handler: {e}"))?;
+    signal_hook::flag::register(SIGTERM, runner.stop_flag())
+        .map_err(|e| format!("Cannot register signal handler: {e}"))?;
+
+    // Separate editors for the ID queries and the fine generation,
+    // since the latter connects to and disconnects from cstore.
+    let mut query_editor = Editor::new(&client);
+    let mut editor = Editor::new(&client);
+
+    let summary = runner.run(
+        |after, limit| fetch_circ_ids(&mut query_editor, after, limit),
+        |circ_id| generate_fines(&mut editor, circ_id),
+    )?;
+
+    println!("Fine generator complete: {summary}");
+
+    Ok(())
+}
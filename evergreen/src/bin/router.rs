@@ -16,6 +16,7 @@ use eg::init;
 use eg::osrf::addr::BusAddress;
 use eg::osrf::bus::Bus;
 use eg::osrf::conf;
+use eg::osrf::events::{self, ServiceEvent, ServiceEventKind};
 use eg::osrf::logging::Logger;
 use eg::osrf::message;
 use eg::osrf::message::{Message, MessageStatus, MessageType, Payload, Status, TransportMessage};
@@ -235,7 +236,9 @@ impl RouterDomain {
 
     /// Remove a service entry and its linked ServiceInstance's from
     /// our registered services.
-    fn remove_service(&mut self, service: &str, address: &BusAddress) {
+    ///
+    /// Returns true if the last instance of the service was removed.
+    fn remove_service(&mut self, service: &str, address: &BusAddress) -> bool {
         if let Some(s_pos) = self.services.iter().position(|s| s.name().eq(service)) {
             let svc = self.services.get_mut(s_pos).unwrap(); // known OK
             svc.remove_instance(address);
@@ -250,8 +253,12 @@ impl RouterDomain {
                 if let Some(s_pos) = self.services.iter().position(|s| s.name().eq(service)) {
                     self.services.remove(s_pos);
                 }
+
+                return true;
             }
         }
+
+        false
     }
 
     fn to_json_value(&self) -> json::JsonValue {
@@ -426,18 +433,21 @@ impl Router {
             // domain as a whole intact since we'll likely need it again.
             // Remove services and instances as necessary, though.
 
-            self.primary_domain.remove_service(service, &address);
+            if self.primary_domain.remove_service(service, &address) {
+                self.announce(service, domain, ServiceEventKind::Unregistered);
+            }
             return Ok(());
         }
 
         // When removing the last service from a remote domain, remove
         // the domain entry as a whole.
         let mut rem_pos_op: Option<usize> = None;
+        let mut removed = false;
         let mut idx = 0;
 
         for r_domain in &mut self.remote_domains {
             if r_domain.domain().eq(domain) {
-                r_domain.remove_service(service, address);
+                removed = r_domain.remove_service(service, address);
                 if r_domain.services.len() == 0 {
                     // Cannot remove here since it would be modifying
                     // self.remote_domains while it's aready mutably borrowed.
@@ -453,9 +463,29 @@ impl Router {
             self.remote_domains.remove(pos);
         }
 
+        if removed {
+            self.announce(service, domain, ServiceEventKind::Unregistered);
+        }
+
         Ok(())
     }
 
+    /// Publish a service event to subscribers on our primary domain.
+    ///
+    /// Failures are logged, but do not affect routing.
+    fn announce(&mut self, service: &str, node: &str, kind: ServiceEventKind) {
+        let event = ServiceEvent::new(service, node, kind);
+
+        let bus = match self.primary_domain.bus_mut() {
+            Some(b) => b,
+            None => return,
+        };
+
+        if let Err(e) = events::publish(bus, &event) {
+            log::warn!("Cannot publish {event}: {e}");
+        }
+    }
+
     /// Add a service registration on the domain implied by the
     /// caller's bus address.
     ///
@@ -475,6 +505,9 @@ impl Router {
             .into());
         }
 
+        // Announced to subscribers once the instance is added.
+        let node = domain.to_string();
+
         let r_domain = self.find_or_create_domain(domain)?;
 
         // Where our new instance will listen for routed API calls.
@@ -509,6 +542,8 @@ impl Router {
                     register_time: date::now(),
                 });
 
+                self.announce(service, &node, ServiceEventKind::Registered);
+
                 return Ok(());
             }
         }
@@ -535,11 +570,24 @@ impl Router {
             }],
         });
 
+        self.announce(service, &node, ServiceEventKind::Registered);
+
         Ok(())
     }
 
+    /// Registered service entries across all domains.
+    ///
+    /// A service registered on multiple domains appears once per domain.
+    fn all_services(&self) -> impl Iterator<Item = &ServiceEntry> {
+        self.primary_domain().services().iter().chain(
+            self.remote_domains()
+                .iter()
+                .flat_map(|d| d.services().iter()),
+        )
+    }
+
     /// List of currently active services by service name.
-    fn _active_services(&self) -> Vec<&str> {
+    fn active_services(&self) -> Vec<&str> {
         let mut services: Vec<&str> = self
             .primary_domain()
             .services()
@@ -719,7 +767,36 @@ impl Router {
                 }
             };
 
-            let value = self.process_router_api_request(&method)?;
+            let myaddr = match &self.primary_domain.bus {
+                Some(b) => b.address(),
+                None => return Err(format!("Primary domain has no bus!").into()),
+            };
+
+            let value = match self.process_router_api_request(&method) {
+                Some(v) => v,
+                None => {
+                    log::warn!(
+                        "{self} received unknown router API call {}",
+                        method.method()
+                    );
+
+                    let status = Message::new(
+                        MessageType::Status,
+                        msg.thread_trace(),
+                        Payload::Status(message::Status::new(
+                            MessageStatus::MethodNotFound,
+                            &format!("Method [{}] not found for router", method.method()),
+                            "osrfMethodException",
+                        )),
+                    );
+
+                    let tmsg =
+                        TransportMessage::with_body(from, myaddr.as_str(), tm.thread(), status);
+
+                    self.primary_domain.send_to_domain(tmsg)?;
+                    continue;
+                }
+            };
 
             let reply = Message::new(
                 MessageType::Result,
@@ -732,11 +809,6 @@ impl Router {
                 )),
             );
 
-            let myaddr = match &self.primary_domain.bus {
-                Some(b) => b.address(),
-                None => return Err(format!("Primary domain has no bus!").into()),
-            };
-
             let mut tmsg = TransportMessage::with_body(from, myaddr.as_str(), tm.thread(), reply);
 
             tmsg.body_mut().push(Message::new(
@@ -755,22 +827,76 @@ impl Router {
         Ok(())
     }
 
-    fn process_router_api_request(&mut self, m: &message::MethodCall) -> EgResult<json::JsonValue> {
-        match m.method() {
-            "opensrf.router.info.class.list" => {
-                // Caller wants a list of service names
+    /// Returns the response to a router API call or None if the
+    /// method is unknown.
+    ///
+    /// Methods which accept a service name return data for that
+    /// service only.  Otherwise, data is keyed on service name.
+    fn process_router_api_request(&self, m: &message::MethodCall) -> Option<json::JsonValue> {
+        let class = m.params().first().and_then(|p| p.as_str());
+
+        let value = match m.method() {
+            // List of service names
+            "opensrf.router.info.class.list" => json::from(self.active_services()),
+
+            // Number of registered instances and their addresses.
+            "opensrf.router.info.class.nodes" => {
+                self.collect_stats(class, |svc, stats: &mut json::JsonValue| {
+                    if stats.is_null() {
+                        *stats = json::object! {node_count: 0, nodes: []};
+                    }
+                    for instance in svc.instances() {
+                        stats["nodes"].push(instance.address().as_str()).ok();
+                    }
+                    stats["node_count"] = json::from(stats["nodes"].len());
+                })
+            }
+
+            // Requests routed to each service since startup.
+            "opensrf.router.info.stats.class.summary" => {
+                self.collect_stats(class, |svc, stats: &mut json::JsonValue| {
+                    let count = stats.as_usize().unwrap_or(0);
+                    *stats = json::from(count + svc.route_count);
+                })
+            }
+
+            // Requests routed to each instance of a service since startup.
+            "opensrf.router.info.stats.class" | "opensrf.router.info.stats.class.all" => self
+                .collect_stats(class, |svc, stats: &mut json::JsonValue| {
+                    if stats.is_null() {
+                        *stats = json::JsonValue::new_object();
+                    }
+                    for instance in svc.instances() {
+                        stats[instance.address().as_str()] = json::from(instance.route_count);
+                    }
+                }),
+
+            "opensrf.router.info.summarize" => self.to_json_value(),
+            _ => return None,
+        };
 
-                let names: Vec<&str> = self
-                    .primary_domain
-                    .services()
-                    .iter()
-                    .map(|s| s.name())
-                    .collect();
+        Some(value)
+    }
 
-                Ok(json::from(names))
+    /// Build a hash of per-service values keyed on service name,
+    /// merging entries for services registered on multiple domains.
+    ///
+    /// If a service name is provided, only its value is returned.
+    fn collect_stats<F>(&self, class: Option<&str>, mut collect: F) -> json::JsonValue
+    where
+        F: FnMut(&ServiceEntry, &mut json::JsonValue),
+    {
+        let mut stats = json::JsonValue::new_object();
+
+        for svc in self.all_services() {
+            if class.map(|c| c == svc.name()).unwrap_or(true) {
+                collect(svc, &mut stats[svc.name()]);
             }
-            "opensrf.router.info.summarize" => Ok(self.to_json_value()),
-            _ => Err(format!("Router cannot handle api {}", m.method()).into()),
+        }
+
+        match class {
+            Some(c) => stats.remove(c),
+            None => stats,
         }
     }
 
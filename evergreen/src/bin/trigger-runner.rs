@@ -0,0 +1,303 @@
+use eg::common::batch::{BatchRunner, Checkpoint, BATCH_HELP_TEXT};
+use eg::common::trigger;
+use eg::common::trigger::runner;
+use eg::init::InitOptions;
+use eg::osrf::cache::Cache;
+use eg::result::EgResult;
+use eg::util;
+use eg::Editor;
+use evergreen as eg;
+use getopts;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+const DEFAULT_LOCKFILE: &str = "/tmp/trigger_runner-LOCK";
+
+const HELP_TEXT: &str = r#"
+Action/Trigger pending event runner.
+
+./eg-trigger-runner --run-pending --event-def 12 --checkpoint-file /tmp/trigger_runner-CHECKPOINT
+
+A summary of processed events is printed for each event definition.
+Exits with a non-zero status if any events ended in an error state.
+
+General Options
+    --lockfile [/tmp/trigger_runner-LOCK]
+        Full path to lock file
+
+    --process-hooks
+        Create events for active passive-hook event definitions, e.g.
+        overdue notices, before processing pending events.  Limited
+        by --event-def and --granularity when provided.
+
+    --run-pending
+        Process pending events whose run time has passed.  This is
+        the default unless --event is used.
+
+    --event-def <id>
+    --definition <id>
+        Limit processing to events for this event definition.
+        Repeatable.
+
+    --event <id>
+        Process a single event, regardless of its state or run time.
+
+    --granularity <granularity>
+        Limit processing to event definitions with this granularity.
+
+    --dry-run
+        Collect and validate events, reporting those which would
+        react, without calling reactors or changing event states.
+        Checkpoint options are ignored and passive event collection
+        is skipped in this mode.
+
+    --parallel <count>
+        Process pending events using this many worker threads, each
+        with its own OpenSRF connection.  Events for grouped event
+        definitions which may belong to the same group are always
+        processed by the same worker.  Checkpoint options are ignored
+        in this mode.
+
+        On SIGINT or SIGTERM, workers finish their current event group,
+        return any events they left mid-process to pending, and exit.
+
+    --recover-stale <interval>
+        Before processing, return events left mid-process (collecting,
+        collected, validating, valid, or reacting) for longer than this
+        interval, e.g. "1 hour", to pending.  Such events were most
+        likely abandoned by a runner which crashed.  Limited by
+        --event-def and --granularity when provided.  Ignored with
+        --dry-run.
+
+    Standard OpenSRF environment variables (e.g. OSRF_CONFIG) are
+    also supported.
+"#;
+
+fn main() -> EgResult<()> {
+    let mut options = getopts::Options::new();
+
+    options.optflag("", "help", "Show this message");
+    options.optopt("", "lockfile", "", "");
+    options.optflag("", "process-hooks", "");
+    options.optflag("", "run-pending", "");
+    options.optmulti("", "event-def", "", "");
+    options.optmulti("", "definition", "", "");
+    options.optopt("", "event", "", "");
+    options.optopt("", "granularity", "", "");
+    options.optopt("", "parallel", "", "");
+    options.optflag("", "dry-run", "");
+    options.optopt("", "recover-stale", "", "");
+
+    BatchRunner::add_options(&mut options);
+
+    let args: Vec<String> = std::env::args().collect();
+
+    let params = options
+        .parse(&args[1..])
+        .or_else(|e| Err(format!("Error parsing params: {e}")))?;
+
+    if params.opt_present("help") {
+        println!("{HELP_TEXT}{BATCH_HELP_TEXT}");
+        return Ok(());
+    }
+
+    let mut event_defs = Vec::new();
+    for def in params
+        .opt_strs("event-def")
+        .into_iter()
+        .chain(params.opt_strs("definition"))
+    {
+        let id = def
+            .parse::<i64>()
+            .map_err(|e| format!("Invalid event definition '{def}': {e}"))?;
+        event_defs.push(id);
+    }
+
+    let event_id = match params.opt_str("event") {
+        Some(id) => Some(
+            id.parse::<i64>()
+                .map_err(|e| format!("Invalid --event '{id}': {e}"))?,
+        ),
+        None => None,
+    };
+
+    if event_id.is_some() && params.opt_present("run-pending") {
+        return Err("--event and --run-pending are mutually exclusive".into());
+    }
+
+    if event_id.is_some() && params.opt_present("process-hooks") {
+        return Err("--event and --process-hooks are mutually exclusive".into());
+    }
+
+    let dry_run = params.opt_present("dry-run");
+    let granularity = params.opt_str("granularity");
+
+    let parallel = match params.opt_str("parallel") {
+        Some(p) => Some(
+            p.parse::<usize>()
+                .map_err(|e| format!("Invalid --parallel '{p}': {e}"))?,
+        ),
+        None => None,
+    };
+
+    let mut batch = BatchRunner::from_params("trigger-runner", &params)?;
+
+    if dry_run {
+        // Events remain pending after a dry run.
+        batch.set_checkpoint(Checkpoint::None);
+    }
+
+    // The lockfile is removed when this goes out of scope.
+    let lockfile_path = params
+        .opt_str("lockfile")
+        .unwrap_or(DEFAULT_LOCKFILE.to_string());
+
+    let lockfile = util::LockfileGuard::create(&lockfile_path)?;
+
+    // Host settings are needed to find the cache servers and the
+    // SMTP relay used by the SendEmail reactor.
+    let client = eg::init::with_options(&InitOptions::new())?;
+
+    if let Some(age) = params.opt_str("recover-stale").filter(|_| !dry_run) {
+        let count = runner::recover_stale_events(
+            &mut Editor::new(&client),
+            &age,
+            granularity.as_deref(),
+            &event_defs,
+        )?;
+        println!("Returned {count} stale events to pending");
+    }
+
+    if params.opt_present("process-hooks") {
+        process_hooks(&client, granularity.as_deref(), &event_defs, dry_run)?;
+    }
+
+    let stats = if let Some(id) = event_id {
+        let mut stats = HashMap::new();
+        let mut editor = Editor::new(&client);
+
+        match runner::process_event(&mut editor, id, dry_run, &mut stats) {
+            Ok(state) => println!("Event {id} finished with state {state:?}"),
+            // Errors which prevent processing altogether, e.g. no
+            // such event, leave no stats to report.
+            Err(e) if stats.is_empty() => return Err(e),
+            Err(e) => eprintln!("Event {id} failed: {e}"),
+        }
+
+        runner::sorted_stats(stats)
+    } else if let Some(count) = parallel {
+        // Stop cleanly, leaving no events mid-process, when asked to exit.
+        let shutdown = Arc::new(AtomicBool::new(false));
+        for signal in [SIGINT, SIGTERM] {
+            signal_hook::flag::register(signal, shutdown.clone())
+                .map_err(|e| format!("Cannot register signal handler: {e}"))?;
+        }
+
+        runner::run_pending(
+            &client,
+            granularity.as_deref(),
+            &event_defs,
+            count,
+            dry_run,
+            shutdown,
+        )?
+    } else {
+        run_batch(
+            &client,
+            &mut batch,
+            granularity.as_deref(),
+            &event_defs,
+            dry_run,
+        )?
+    };
+
+    if dry_run {
+        println!("Dry run; no reactors were called and no events were modified.");
+    }
+
+    print!("{}", runner::summary_table(&stats));
+
+    // exit() skips destructors.
+    drop(lockfile);
+
+    if runner::any_errors(&stats) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Create events for passive hooks.
+fn process_hooks(
+    client: &eg::Client,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+    dry_run: bool,
+) -> EgResult<()> {
+    if dry_run {
+        println!("Dry run; skipping passive event collection.");
+        return Ok(());
+    }
+
+    let mut editor = Editor::new(client);
+
+    let counts = trigger::create_passive_events(&mut editor, granularity, event_defs)?;
+
+    let mut counts: Vec<(i64, usize)> = counts.into_iter().collect();
+    counts.sort();
+
+    for (def_id, count) in counts {
+        println!("Created {count} passive events for event def {def_id}");
+    }
+
+    Ok(())
+}
+
+/// Process pending events in chunks, one at a time, with checkpoint
+/// support.
+fn run_batch(
+    client: &eg::Client,
+    batch: &mut BatchRunner,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+    dry_run: bool,
+) -> EgResult<Vec<runner::DefStats>> {
+    if batch.checkpoint().is_cache() {
+        Cache::init_cache("global")?;
+    }
+
+    // Stop cleanly, saving our checkpoint, when asked to exit.
+    signal_hook::flag::register(SIGINT, batch.stop_flag())
+        .map_err(|e| format!("Cannot register signal handler: {e}"))?;
+    signal_hook::flag::register(SIGTERM, batch.stop_flag())
+        .map_err(|e| format!("Cannot register signal handler: {e}"))?;
+
+    // Separate editors for the ID queries and the event processing,
+    // since the latter connects to and disconnects from cstore.
+    let mut query_editor = Editor::new(client);
+    let mut editor = Editor::new(client);
+    let mut stats = HashMap::new();
+
+    let summary = batch.run(
+        |after, limit| {
+            runner::fetch_pending_event_ids(
+                &mut query_editor,
+                granularity,
+                event_defs,
+                after,
+                limit,
+            )
+        },
+        |event_id| {
+            let state = runner::process_event(&mut editor, event_id, dry_run, &mut stats)?;
+            log::debug!("Event {event_id} finished with state {state:?}");
+            Ok(())
+        },
+    )?;
+
+    println!("Trigger runner complete: {summary}");
+
+    Ok(runner::sorted_stats(stats))
+}
@@ -14,7 +14,7 @@ use std::env;
 use std::fmt;
 use std::net::TcpListener;
 use std::net::{SocketAddr, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
@@ -65,6 +65,81 @@ const SIG_POLL_INTERVAL: u64 = 3;
  * tracks connected sessions.
  */
 
+/// Request limits and counters shared by all Sessions.
+struct RequestTracker {
+    /// Max in-flight requests per websocket client.
+    max_client_requests: Option<usize>,
+
+    /// Max in-flight requests across all websocket clients.
+    max_total_requests: Option<usize>,
+
+    /// Requests relayed to OpenSRF that are awaiting a final response.
+    in_flight: AtomicUsize,
+
+    /// Requests rejected because a client reached its limit.
+    rejected_client: AtomicUsize,
+
+    /// Requests rejected because the process reached its limit.
+    rejected_total: AtomicUsize,
+}
+
+impl RequestTracker {
+    fn new(limits: &conf::WebsocketLimits) -> Self {
+        RequestTracker {
+            max_client_requests: limits.max_client_requests(),
+            max_total_requests: limits.max_total_requests(),
+            in_flight: AtomicUsize::new(0),
+            rejected_client: AtomicUsize::new(0),
+            rejected_total: AtomicUsize::new(0),
+        }
+    }
+
+    fn add(&self, count: usize) {
+        self.in_flight.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn subtract(&self, count: usize) {
+        // Avoid unsigned underflow.
+        self.in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n.saturating_sub(count))
+            })
+            .ok();
+    }
+
+    /// Returns a description of the limit reached, if any, and
+    /// updates the rejection counters to match.
+    fn check_limits(&self, client_in_flight: usize) -> Option<String> {
+        if let Some(max) = self.max_client_requests {
+            if client_in_flight >= max {
+                self.rejected_client.fetch_add(1, Ordering::Relaxed);
+                return Some(format!("client limit of {max} requests reached"));
+            }
+        }
+
+        if let Some(max) = self.max_total_requests {
+            if self.in_flight.load(Ordering::Relaxed) >= max {
+                self.rejected_total.fetch_add(1, Ordering::Relaxed);
+                return Some(format!("server limit of {max} requests reached"));
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for RequestTracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "in_flight={} rejected_client={} rejected_total={}",
+            self.in_flight.load(Ordering::Relaxed),
+            self.rejected_client.load(Ordering::Relaxed),
+            self.rejected_total.load(Ordering::Relaxed),
+        )
+    }
+}
+
 /// ChannelMessage's are delivered to the main thread.  There are 2
 /// types: Inbound websocket request and Ooutbound opensrf response.
 #[derive(Debug, PartialEq)]
@@ -234,6 +309,9 @@ struct Session {
     /// Backlog of messages yet to be delivered to OpenSRF.
     request_queue: VecDeque<String>,
 
+    /// Process-wide request limits and counters.
+    tracker: Arc<RequestTracker>,
+
     /// Maximum number of active/parallel websocket requests to
     /// relay to OpenSRF at a time.  Once exceeded, new messages
     /// are queued for delivery and relayed as soon as possible.
@@ -256,7 +334,12 @@ impl fmt::Display for Session {
 }
 
 impl Session {
-    fn run(stream: TcpStream, max_parallel: usize, shutdown: Arc<AtomicBool>) -> EgResult<()> {
+    fn run(
+        stream: TcpStream,
+        max_parallel: usize,
+        tracker: Arc<RequestTracker>,
+        shutdown: Arc<AtomicBool>,
+    ) -> EgResult<()> {
         let client_ip = stream
             .peer_addr()
             .or_else(|e| Err(format!("Could not determine client IP address: {e}")))?;
@@ -323,6 +406,7 @@ impl Session {
             shutdown_session: shutdown_session,
             osrf_sessions: HashMap::new(),
             request_queue: VecDeque::new(),
+            tracker,
         };
 
         log::debug!("{session} starting channel threads");
@@ -344,6 +428,10 @@ impl Session {
         // session threads know it's time to cleanup and go home.
         self.shutdown_session.store(true, Ordering::Relaxed);
 
+        // Requests still in flight will never be answered.
+        self.tracker.subtract(self.reqs_in_flight);
+        self.reqs_in_flight = 0;
+
         // Send a Close message to the Websocket client.  This has the
         // secondary benefit of forcing the SessionInbound to exit its
         // listen loop.  (The SessionOutbound will periodically check
@@ -480,6 +568,8 @@ impl Session {
 
                 if tlen >= MAX_MESSAGE_SIZE {
                     log::error!("{self} Dropping huge websocket message size={tlen}");
+                } else if self.reject_if_over_limit(&text)? {
+                    // Client was told to try again later.
                 } else if self.request_queue.len() >= MAX_BACKLOG_SIZE {
                     // Client is getting out of handle.  Let them go.
                     return Err(format!(
@@ -511,6 +601,90 @@ impl Session {
         }
     }
 
+    /// Reply with a ServiceUnavailable status to each CONNECT and
+    /// REQUEST in the message if the client or server has reached its
+    /// limit of in-flight requests.
+    ///
+    /// Returns true if the message was rejected.
+    fn reject_if_over_limit(&mut self, json_text: &str) -> Result<bool, String> {
+        // Queued messages will be relayed, so they count as in flight.
+        let client_in_flight = self.reqs_in_flight + self.request_queue.len();
+
+        if self.tracker.max_client_requests.is_none() && self.tracker.max_total_requests.is_none() {
+            return Ok(false);
+        }
+
+        let wrapper = json::parse(json_text)
+            .map_err(|e| format!("{self} Cannot parse websocket message: {e} {json_text}"))?;
+
+        let thread = wrapper["thread"].as_str().unwrap_or("");
+
+        // msg_list is typically an array, but may be a single opensrf message.
+        let msg_list = &wrapper["osrf_msg"];
+        let msg_list: Vec<&json::JsonValue> = if msg_list.is_array() {
+            msg_list.members().collect()
+        } else {
+            vec![msg_list]
+        };
+
+        let mut traces = Vec::new();
+
+        for msg_json in msg_list {
+            // Raw data mode; we only need the type and thread trace.
+            let msg = message::Message::from_json_value(msg_json.clone(), true)?;
+
+            match msg.mtype() {
+                message::MessageType::Connect | message::MessageType::Request => {
+                    traces.push(msg.thread_trace())
+                }
+                _ => {}
+            }
+        }
+
+        if traces.is_empty() {
+            // Nothing here adds to our load.
+            return Ok(false);
+        }
+
+        let reason = match self.tracker.check_limits(client_in_flight) {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+
+        log::warn!(
+            "{self} rejecting {} request(s) for thread {thread}: {reason}; {}",
+            traces.len(),
+            self.tracker
+        );
+
+        let mut body = json::JsonValue::new_array();
+
+        for trace in traces {
+            let msg = message::Message::new(
+                message::MessageType::Status,
+                trace,
+                message::Payload::Status(message::Status::new(
+                    message::MessageStatus::ServiceUnavailable,
+                    &format!("Service Unavailable: {reason}"),
+                    "osrfStatus",
+                )),
+            );
+
+            body.push(msg.into_json_value()).ok();
+        }
+
+        let obj = json::object! {
+            thread: thread,
+            osrf_msg: body,
+        };
+
+        self.sender
+            .write_message(WebSocketMessage::Text(obj.dump()))
+            .map_err(|e| format!("{self} Error sending rejection to websocket client: {e}"))?;
+
+        Ok(true)
+    }
+
     /// Wrap a websocket request in an OpenSRF transport message and
     /// put on the OpenSRF bus for delivery.
     fn relay_to_osrf(&mut self, json_text: &str) -> Result<(), String> {
@@ -600,10 +774,12 @@ impl Session {
             match msg.mtype() {
                 message::MessageType::Connect => {
                     self.reqs_in_flight += 1;
+                    self.tracker.add(1);
                     log::debug!("{self} WS received CONNECT request: {thread}");
                 }
                 message::MessageType::Request => {
                     self.reqs_in_flight += 1;
+                    self.tracker.add(1);
 
                     // Inbound requests using a hash format need to be
                     // turned into Fieldmapper objects before they
@@ -660,6 +836,7 @@ impl Session {
         if self.reqs_in_flight > 0 {
             // Avoid unsigned underflow, which would cause panic.
             self.reqs_in_flight -= 1;
+            self.tracker.subtract(1);
         }
     }
 
@@ -798,6 +975,7 @@ impl mptc::Request for WebsocketRequest {
 
 struct WebsocketHandler {
     max_parallel: usize,
+    tracker: Arc<RequestTracker>,
     shutdown: Arc<AtomicBool>,
 }
 
@@ -820,7 +998,9 @@ impl mptc::RequestHandler for WebsocketHandler {
 
         let shutdown = self.shutdown.clone();
 
-        if let Err(e) = Session::run(stream, self.max_parallel, shutdown) {
+        let tracker = self.tracker.clone();
+
+        if let Err(e) = Session::run(stream, self.max_parallel, tracker, shutdown) {
             log::error!("Websocket session ended with error: {e}");
         }
 
@@ -837,6 +1017,9 @@ struct WebsocketStream {
     /// are queued for delivery and relayed as soon as possible.
     max_parallel: usize,
 
+    /// Request limits and counters shared by all Sessions.
+    tracker: Arc<RequestTracker>,
+
     /// Set to true of the mptc::Server tells us it's time to shutdown.
     ///
     /// Read by our Sessions
@@ -857,6 +1040,7 @@ impl WebsocketStream {
             listener,
             client,
             max_parallel,
+            tracker: Arc::new(RequestTracker::new(conf::config().websocket_limits())),
             shutdown: Arc::new(AtomicBool::new(false)),
         };
 
@@ -887,6 +1071,7 @@ impl mptc::RequestStream for WebsocketStream {
         let handler = WebsocketHandler {
             shutdown: self.shutdown.clone(),
             max_parallel: self.max_parallel,
+            tracker: self.tracker.clone(),
         };
 
         Box::new(handler)
@@ -902,7 +1087,10 @@ impl mptc::RequestStream for WebsocketStream {
         // requests then exit.
         // This only affects active Sessions.  mptc will notify its
         // own idle workers.
-        log::info!("Server received mptc shutdown request");
+        log::info!(
+            "Server received mptc shutdown request; requests: {}",
+            self.tracker
+        );
         eprintln!("Server received mptc shutdown request");
 
         self.shutdown.store(true, Ordering::Relaxed);
@@ -0,0 +1,845 @@
+//! Typed request builders for commonly used Evergreen APIs.
+//!
+//! Each request collects its parameters via typed setters, checks
+//! them with validate(), and sends itself with execute().  Responses
+//! are either the API's success payload or the events it returned.
+//!
+//! ```no_run
+//! use evergreen as eg;
+//! use eg::bindings::{ApiRequest, ApiResponse, CheckoutRequest};
+//!
+//! fn checkout(client: &eg::Client, authtoken: &str) -> eg::EgResult<()> {
+//!     let mut req = CheckoutRequest::new(authtoken);
+//!     req.set_copy_barcode("31000000000001")
+//!         .set_patron_barcode("21000000000001");
+//!
+//!     match req.execute(client)? {
+//!         ApiResponse::Success(circ) => println!("Due {:?}", circ.due_date()),
+//!         ApiResponse::Events(events) => println!("Checkout failed: {}", events[0]),
+//!     }
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Adding an API means declaring a struct with its positional
+//! parameters and an argument hash, generating setters for the hash
+//! with hash_setters!, and implementing ApiRequest.
+use crate::event::EgEvent;
+use crate::osrf::client::Client;
+use crate::result::{EgError, EgResult};
+use crate::EgValue;
+use std::time::Duration;
+
+/// Generates a typed setter for each named key in the request's
+/// argument hash (`self.args`).
+macro_rules! hash_setters {
+    ($($(#[$doc:meta])* $setter:ident($key:literal: $ty:ty);)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $setter(&mut self, value: $ty) -> &mut Self {
+                self.args[$key] = EgValue::from(value);
+                self
+            }
+        )*
+    };
+}
+
+/// Outcome of an API call.
+#[derive(Debug, Clone)]
+pub enum ApiResponse<T> {
+    Success(T),
+    /// The API call failed with one or more events.
+    Events(Vec<EgEvent>),
+}
+
+impl<T> ApiResponse<T> {
+    pub fn is_success(&self) -> bool {
+        matches!(self, ApiResponse::Success(_))
+    }
+
+    /// The event which best explains a failure: the first non-success
+    /// event if there is one, otherwise the first event.
+    ///
+    /// None for successful calls.
+    pub fn event(&self) -> Option<&EgEvent> {
+        match self {
+            ApiResponse::Success(_) => None,
+            ApiResponse::Events(events) => events
+                .iter()
+                .find(|e| !e.is_success())
+                .or_else(|| events.first()),
+        }
+    }
+
+    /// The success payload, or an Err for the event returned by
+    /// event().
+    pub fn into_result(self) -> EgResult<T> {
+        let event = self.event().cloned();
+        match self {
+            ApiResponse::Success(v) => Ok(v),
+            ApiResponse::Events(_) => Err(match event {
+                Some(e) => EgError::Event(e),
+                None => "API call returned no events".into(),
+            }),
+        }
+    }
+}
+
+/// A typed API call.
+pub trait ApiRequest {
+    /// Payload returned by a successful call.
+    type Output;
+
+    fn service(&self) -> &str;
+
+    fn method(&self) -> &str;
+
+    /// Returns Err if any required parameters are missing.
+    fn validate(&self) -> EgResult<()>;
+
+    /// API parameters in calling order.
+    fn params(&self) -> Vec<EgValue>;
+
+    /// Translate the first response from the API.
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<Self::Output>>;
+
+    /// Validate and send the request, returning the parsed response.
+    fn execute(&self, client: &Client) -> EgResult<ApiResponse<Self::Output>> {
+        self.validate()?;
+        let response = client.send_recv_one(self.service(), self.method(), self.params())?;
+        self.unpack(response)
+    }
+
+    /// Like execute(), but with a limit on how long to wait for
+    /// the response.
+    fn execute_with_timeout(
+        &self,
+        client: &Client,
+        timeout: Duration,
+    ) -> EgResult<ApiResponse<Self::Output>> {
+        self.validate()?;
+        let response = client.send_recv_one_with_timeout(
+            self.service(),
+            self.method(),
+            self.params(),
+            timeout,
+        )?;
+        self.unpack(response)
+    }
+
+    /// Parse a response, if one arrived.
+    fn unpack(&self, response: Option<EgValue>) -> EgResult<ApiResponse<Self::Output>> {
+        match response {
+            Some(r) => self.parse_response(r),
+            None => Err(format!("API call {} failed to return a response", self.method()).into()),
+        }
+    }
+}
+
+/// Returns Err unless the argument hash has a value for at least one
+/// of the keys.
+fn require_any(method: &str, args: &EgValue, keys: &[&str]) -> EgResult<()> {
+    if keys.iter().any(|k| !args[*k].is_null()) {
+        Ok(())
+    } else {
+        Err(format!("{method} requires one of: {}", keys.join(", ")).into())
+    }
+}
+
+/// Parse a response which should consist of events.
+fn parse_events(method: &str, response: &EgValue) -> EgResult<Vec<EgEvent>> {
+    EgEvent::parse_array(response)
+        .ok_or_else(|| format!("API call {method} failed to return an event").into())
+}
+
+/// Success payload of checkout and renewal calls.
+#[derive(Debug, Clone)]
+pub struct CircOutcome {
+    event: EgEvent,
+}
+
+impl CircOutcome {
+    /// The SUCCESS event, whose payload holds the circulation.
+    pub fn event(&self) -> &EgEvent {
+        &self.event
+    }
+    pub fn circ(&self) -> &EgValue {
+        &self.event.payload()["circ"]
+    }
+    pub fn circ_id(&self) -> EgResult<i64> {
+        self.circ().id()
+    }
+    pub fn due_date(&self) -> Option<&str> {
+        self.circ()["due_date"].as_str()
+    }
+    pub fn renewal_remaining(&self) -> EgResult<i64> {
+        self.circ()["renewal_remaining"].int()
+    }
+}
+
+/// A SUCCESS event which carries a circulation is a success.
+fn parse_circ_response(method: &str, response: &EgValue) -> EgResult<ApiResponse<CircOutcome>> {
+    let events = parse_events(method, response)?;
+
+    if events.len() == 1 && events[0].is_success() && events[0].payload()["circ"].is_object() {
+        let event = events.into_iter().next().unwrap();
+        return Ok(ApiResponse::Success(CircOutcome { event }));
+    }
+
+    Ok(ApiResponse::Events(events))
+}
+
+/// open-ils.circ.checkout.full
+#[derive(Debug, Clone)]
+pub struct CheckoutRequest {
+    authtoken: String,
+    args: EgValue,
+    ovride: bool,
+}
+
+impl CheckoutRequest {
+    pub fn new(authtoken: &str) -> Self {
+        CheckoutRequest {
+            authtoken: authtoken.to_string(),
+            args: EgValue::new_object(),
+            ovride: false,
+        }
+    }
+
+    /// Call the .override variant of the API.
+    pub fn set_override(&mut self, ovride: bool) -> &mut Self {
+        self.ovride = ovride;
+        self
+    }
+
+    hash_setters! {
+        set_copy_barcode("copy_barcode": &str);
+        set_copy_id("copy_id": i64);
+        set_patron_barcode("patron_barcode": &str);
+        set_patron_id("patron_id": i64);
+        /// ISO due date, replacing the calculated due date.
+        set_due_date("due_date": &str);
+        /// ISO time the checkout occurred, e.g. for offline checkouts.
+        set_checkout_time("checkout_time": &str);
+        set_circ_lib("circ_lib": i64);
+    }
+}
+
+impl ApiRequest for CheckoutRequest {
+    type Output = CircOutcome;
+
+    fn service(&self) -> &str {
+        "open-ils.circ"
+    }
+
+    fn method(&self) -> &str {
+        match self.ovride {
+            true => "open-ils.circ.checkout.full.override",
+            false => "open-ils.circ.checkout.full",
+        }
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        require_any(self.method(), &self.args, &["copy_barcode", "copy_id"])?;
+        require_any(self.method(), &self.args, &["patron_barcode", "patron_id"])
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        vec![EgValue::from(self.authtoken.as_str()), self.args.clone()]
+    }
+
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<CircOutcome>> {
+        parse_circ_response(self.method(), &response)
+    }
+}
+
+/// open-ils.circ.renew
+#[derive(Debug, Clone)]
+pub struct RenewRequest {
+    authtoken: String,
+    args: EgValue,
+    ovride: bool,
+}
+
+impl RenewRequest {
+    pub fn new(authtoken: &str) -> Self {
+        RenewRequest {
+            authtoken: authtoken.to_string(),
+            args: EgValue::new_object(),
+            ovride: false,
+        }
+    }
+
+    /// Call the .override variant of the API.
+    pub fn set_override(&mut self, ovride: bool) -> &mut Self {
+        self.ovride = ovride;
+        self
+    }
+
+    hash_setters! {
+        set_copy_barcode("copy_barcode": &str);
+        set_copy_id("copy_id": i64);
+        /// Renew on behalf of this patron.  Defaults to the patron
+        /// who has the item checked out.
+        set_patron_barcode("patron_barcode": &str);
+        set_patron_id("patron_id": i64);
+        set_due_date("due_date": &str);
+        set_checkout_time("checkout_time": &str);
+    }
+}
+
+impl ApiRequest for RenewRequest {
+    type Output = CircOutcome;
+
+    fn service(&self) -> &str {
+        "open-ils.circ"
+    }
+
+    fn method(&self) -> &str {
+        match self.ovride {
+            true => "open-ils.circ.renew.override",
+            false => "open-ils.circ.renew",
+        }
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        require_any(self.method(), &self.args, &["copy_barcode", "copy_id"])
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        vec![EgValue::from(self.authtoken.as_str()), self.args.clone()]
+    }
+
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<CircOutcome>> {
+        parse_circ_response(self.method(), &response)
+    }
+}
+
+/// Success payload of checkin calls.
+#[derive(Debug, Clone)]
+pub struct CheckinOutcome {
+    event: EgEvent,
+}
+
+impl CheckinOutcome {
+    /// The SUCCESS or ROUTE_ITEM event, whose payload holds the copy,
+    /// circulation, hold, etc. affected by the checkin.
+    pub fn event(&self) -> &EgEvent {
+        &self.event
+    }
+    pub fn into_event(self) -> EgEvent {
+        self.event
+    }
+    pub fn copy(&self) -> &EgValue {
+        &self.event.payload()["copy"]
+    }
+    pub fn circ(&self) -> &EgValue {
+        &self.event.payload()["circ"]
+    }
+    /// True if the item must be sent elsewhere.
+    pub fn needs_routing(&self) -> bool {
+        self.event.textcode() == "ROUTE_ITEM"
+    }
+    /// Org unit the item should be routed to.
+    pub fn destination(&self) -> Option<i64> {
+        *self.event.org()
+    }
+}
+
+/// open-ils.circ.checkin
+#[derive(Debug, Clone)]
+pub struct CheckinRequest {
+    authtoken: String,
+    args: EgValue,
+    ovride: bool,
+}
+
+impl CheckinRequest {
+    pub fn new(authtoken: &str) -> Self {
+        CheckinRequest {
+            authtoken: authtoken.to_string(),
+            args: EgValue::new_object(),
+            ovride: false,
+        }
+    }
+
+    /// Call the .override variant of the API.
+    pub fn set_override(&mut self, ovride: bool) -> &mut Self {
+        self.ovride = ovride;
+        self
+    }
+
+    hash_setters! {
+        set_copy_barcode("copy_barcode": &str);
+        set_copy_id("copy_id": i64);
+        /// Org unit where the checkin occurs.
+        set_circ_lib("circ_lib": i64);
+        /// ISO date to use as the effective checkin time.
+        set_backdate("backdate": &str);
+        /// Send items which fill holds into transit, even locally.
+        set_hold_as_transit("hold_as_transit": bool);
+        /// Undo a hold fulfillment from a just-completed checkout.
+        set_revert_hold_fulfillment("revert_hold_fulfillment": bool);
+    }
+}
+
+impl ApiRequest for CheckinRequest {
+    type Output = CheckinOutcome;
+
+    fn service(&self) -> &str {
+        "open-ils.circ"
+    }
+
+    fn method(&self) -> &str {
+        match self.ovride {
+            true => "open-ils.circ.checkin.override",
+            false => "open-ils.circ.checkin",
+        }
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        require_any(self.method(), &self.args, &["copy_barcode", "copy_id"])
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        vec![EgValue::from(self.authtoken.as_str()), self.args.clone()]
+    }
+
+    /// SUCCESS and ROUTE_ITEM events are successful checkins.
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<CheckinOutcome>> {
+        let events = parse_events(self.method(), &response)?;
+
+        if events.len() == 1 && (events[0].is_success() || events[0].textcode() == "ROUTE_ITEM") {
+            let event = events.into_iter().next().unwrap();
+            return Ok(ApiResponse::Success(CheckinOutcome { event }));
+        }
+
+        Ok(ApiResponse::Events(events))
+    }
+}
+
+/// open-ils.circ.holds.test_and_create.batch for a single target.
+///
+/// Succeeds with the ID of the new hold.
+#[derive(Debug, Clone)]
+pub struct HoldRequest {
+    authtoken: String,
+    args: EgValue,
+    target: Option<i64>,
+    ovride: bool,
+}
+
+impl HoldRequest {
+    pub fn new(authtoken: &str) -> Self {
+        HoldRequest {
+            authtoken: authtoken.to_string(),
+            args: EgValue::new_object(),
+            target: None,
+            ovride: false,
+        }
+    }
+
+    /// Call the .override variant of the API.
+    pub fn set_override(&mut self, ovride: bool) -> &mut Self {
+        self.ovride = ovride;
+        self
+    }
+
+    /// ID of the record, copy, etc. to place the hold on, depending
+    /// on the hold type.
+    pub fn set_target(&mut self, target: i64) -> &mut Self {
+        self.target = Some(target);
+        self
+    }
+
+    hash_setters! {
+        set_patron_id("patronid": i64);
+        set_pickup_lib("pickup_lib": i64);
+        /// T (title), C (copy), V (volume), M (metarecord), etc.
+        set_hold_type("hold_type": &str);
+        set_expire_time("expire_time": &str);
+        set_frozen("frozen": bool);
+        set_thaw_date("thaw_date": &str);
+    }
+}
+
+impl ApiRequest for HoldRequest {
+    type Output = i64;
+
+    fn service(&self) -> &str {
+        "open-ils.circ"
+    }
+
+    fn method(&self) -> &str {
+        match self.ovride {
+            true => "open-ils.circ.holds.test_and_create.batch.override",
+            false => "open-ils.circ.holds.test_and_create.batch",
+        }
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        for key in ["patronid", "pickup_lib", "hold_type"] {
+            require_any(self.method(), &self.args, &[key])?;
+        }
+
+        if self.target.is_none() {
+            return Err(format!("{} requires a hold target", self.method()).into());
+        }
+
+        Ok(())
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        vec![
+            EgValue::from(self.authtoken.as_str()),
+            self.args.clone(),
+            EgValue::from(vec![self.target.unwrap_or(0)]),
+        ]
+    }
+
+    /// Each response has the form {target: 123, result: <hold ID or events>}.
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<i64>> {
+        // Failures unrelated to the target, e.g. NO_SESSION, arrive
+        // as bare events.
+        if let Some(events) = EgEvent::parse_array(&response) {
+            return Ok(ApiResponse::Events(events));
+        }
+
+        let result = &response["result"];
+
+        if let Some(id) = result.as_int() {
+            return Ok(ApiResponse::Success(id));
+        }
+
+        Ok(ApiResponse::Events(parse_events(self.method(), result)?))
+    }
+}
+
+/// Success payload of payment calls.
+#[derive(Debug, Clone)]
+pub struct PaymentOutcome {
+    /// IDs of the payments created.  None if the API reported
+    /// success without listing them.
+    pub payment_ids: Option<Vec<i64>>,
+}
+
+/// open-ils.circ.money.payment
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    authtoken: String,
+    args: EgValue,
+    last_xact_id: Option<String>,
+}
+
+impl PaymentRequest {
+    pub fn new(authtoken: &str) -> Self {
+        let mut args = EgValue::new_object();
+        args["payments"] = EgValue::new_array();
+
+        PaymentRequest {
+            authtoken: authtoken.to_string(),
+            args,
+            last_xact_id: None,
+        }
+    }
+
+    /// The patron's current last_xact_id, which guards against
+    /// applying the same payments twice.
+    pub fn set_last_xact_id(&mut self, id: &str) -> &mut Self {
+        self.last_xact_id = Some(id.to_string());
+        self
+    }
+
+    /// Pay `amount` toward the transaction.
+    pub fn add_payment(&mut self, xact_id: i64, amount: &str) -> &mut Self {
+        let payment = vec![EgValue::from(xact_id), EgValue::from(amount)];
+        self.args["payments"]
+            .push(EgValue::from(payment))
+            .expect("payments is an array");
+        self
+    }
+
+    pub fn payment_count(&self) -> usize {
+        self.args["payments"].len()
+    }
+
+    /// Details of a card payment processed outside of Evergreen.
+    pub fn set_cc_args(&mut self, approval_code: &str, terminal_xact: &str) -> &mut Self {
+        self.args["cc_args"]["approval_code"] = EgValue::from(approval_code);
+        self.args["cc_args"]["terminal_xact"] = EgValue::from(terminal_xact);
+        self
+    }
+
+    /// Set a single credit card payment detail, e.g. "processor".
+    pub fn set_cc_arg(&mut self, key: &str, value: &str) -> &mut Self {
+        self.args["cc_args"][key] = EgValue::from(value);
+        self
+    }
+
+    hash_setters! {
+        set_user_id("userid": i64);
+        /// cash_payment, check_payment, credit_card_payment, etc.
+        set_payment_type("payment_type": &str);
+        set_note("note": &str);
+        set_check_number("check_number": &str);
+    }
+}
+
+impl ApiRequest for PaymentRequest {
+    type Output = PaymentOutcome;
+
+    fn service(&self) -> &str {
+        "open-ils.circ"
+    }
+
+    fn method(&self) -> &str {
+        "open-ils.circ.money.payment"
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        require_any(self.method(), &self.args, &["userid"])?;
+        require_any(self.method(), &self.args, &["payment_type"])?;
+
+        if self.payment_count() == 0 {
+            return Err(format!("{} requires at least one payment", self.method()).into());
+        }
+
+        if self.last_xact_id.is_none() {
+            return Err(format!("{} requires a last_xact_id", self.method()).into());
+        }
+
+        Ok(())
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        vec![
+            EgValue::from(self.authtoken.as_str()),
+            self.args.clone(),
+            EgValue::from(self.last_xact_id.clone()),
+        ]
+    }
+
+    /// Succeeds with {last_xact_id: ..., payments: [...]}.
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<PaymentOutcome>> {
+        if let Some(events) = EgEvent::parse_array(&response) {
+            if events.iter().all(|e| e.is_success()) {
+                return Ok(ApiResponse::Success(PaymentOutcome { payment_ids: None }));
+            }
+            return Ok(ApiResponse::Events(events));
+        }
+
+        let ids = response["payments"]
+            .members()
+            .filter_map(|id| id.as_int())
+            .collect();
+
+        Ok(ApiResponse::Success(PaymentOutcome {
+            payment_ids: Some(ids),
+        }))
+    }
+}
+
+/// open-ils.actor.user.fleshed.retrieve
+///
+/// Succeeds with the fleshed user object.
+#[derive(Debug, Clone)]
+pub struct UserRetrieveRequest {
+    authtoken: String,
+    user_id: Option<i64>,
+    flesh_fields: Vec<String>,
+}
+
+impl UserRetrieveRequest {
+    pub fn new(authtoken: &str) -> Self {
+        UserRetrieveRequest {
+            authtoken: authtoken.to_string(),
+            user_id: None,
+            flesh_fields: Vec::new(),
+        }
+    }
+
+    pub fn set_user_id(&mut self, id: i64) -> &mut Self {
+        self.user_id = Some(id);
+        self
+    }
+
+    /// Flesh only these fields, e.g. "card", instead of the default
+    /// set of fleshed fields.
+    pub fn add_flesh_field(&mut self, field: &str) -> &mut Self {
+        self.flesh_fields.push(field.to_string());
+        self
+    }
+}
+
+impl ApiRequest for UserRetrieveRequest {
+    type Output = EgValue;
+
+    fn service(&self) -> &str {
+        "open-ils.actor"
+    }
+
+    fn method(&self) -> &str {
+        "open-ils.actor.user.fleshed.retrieve"
+    }
+
+    fn validate(&self) -> EgResult<()> {
+        match self.user_id {
+            Some(_) => Ok(()),
+            None => Err(format!("{} requires a user ID", self.method()).into()),
+        }
+    }
+
+    fn params(&self) -> Vec<EgValue> {
+        let mut params = vec![
+            EgValue::from(self.authtoken.as_str()),
+            EgValue::from(self.user_id),
+        ];
+
+        if !self.flesh_fields.is_empty() {
+            params.push(EgValue::from(self.flesh_fields.clone()));
+        }
+
+        params
+    }
+
+    fn parse_response(&self, response: EgValue) -> EgResult<ApiResponse<EgValue>> {
+        if let Some(events) = EgEvent::parse_array(&response) {
+            return Ok(ApiResponse::Events(events));
+        }
+
+        if !response.is_blessed() {
+            return Err(format!("{} returned an unexpected value", self.method()).into());
+        }
+
+        Ok(ApiResponse::Success(response))
+    }
+}
+
+#[test]
+fn checkout_request() {
+    let mut req = CheckoutRequest::new("token");
+    req.set_copy_barcode("3100");
+
+    let err = req.validate().unwrap_err();
+    assert!(err.to_string().contains("patron_barcode"));
+
+    req.set_patron_id(5).set_due_date("2024-06-01");
+    assert!(req.validate().is_ok());
+    assert_eq!(req.method(), "open-ils.circ.checkout.full");
+
+    req.set_override(true);
+    assert_eq!(req.method(), "open-ils.circ.checkout.full.override");
+
+    let params = req.params();
+    assert_eq!(params[0].as_str(), Some("token"));
+    assert_eq!(params[1]["copy_barcode"].as_str(), Some("3100"));
+    assert_eq!(params[1]["patron_id"].as_int(), Some(5));
+
+    let success = json::object! {
+        textcode: "SUCCESS",
+        payload: {circ: {id: 9, due_date: "2024-06-01T23:59:59-0400", renewal_remaining: 2}},
+    };
+
+    let resp = req
+        .parse_response(EgValue::from_json_value(success).unwrap())
+        .unwrap();
+
+    let circ = resp.into_result().unwrap();
+    assert_eq!(circ.circ_id().unwrap(), 9);
+    assert_eq!(circ.renewal_remaining().unwrap(), 2);
+    assert_eq!(circ.due_date(), Some("2024-06-01T23:59:59-0400"));
+
+    let failure = json::array![
+        {textcode: "PATRON_EXCEEDS_FINES"},
+        {textcode: "PATRON_BARRED"},
+    ];
+
+    let resp = req
+        .parse_response(EgValue::from_json_value(failure).unwrap())
+        .unwrap();
+
+    assert!(!resp.is_success());
+    assert_eq!(resp.event().unwrap().textcode(), "PATRON_EXCEEDS_FINES");
+
+    let err = resp.into_result().unwrap_err();
+    assert_eq!(err.event().unwrap().textcode(), "PATRON_EXCEEDS_FINES");
+
+    let garbage = EgValue::from_json_value(json::object! {foo: 1}).unwrap();
+    assert!(req.parse_response(garbage).is_err());
+    assert!(req.unpack(None).is_err());
+}
+
+#[test]
+fn checkin_request() {
+    let mut req = CheckinRequest::new("token");
+    assert!(req.validate().is_err());
+
+    req.set_copy_id(12)
+        .set_circ_lib(4)
+        .set_hold_as_transit(true);
+    assert!(req.validate().is_ok());
+    assert_eq!(req.params()[1]["hold_as_transit"].as_bool(), Some(true));
+
+    let routed = json::object! {textcode: "ROUTE_ITEM", org: 7, payload: {copy: {id: 12}}};
+
+    let outcome = req
+        .parse_response(EgValue::from_json_value(routed).unwrap())
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    assert!(outcome.needs_routing());
+    assert_eq!(outcome.destination(), Some(7));
+    assert_eq!(outcome.copy()["id"].as_int(), Some(12));
+
+    let not_found = json::object! {textcode: "ASSET_COPY_NOT_FOUND"};
+    let resp = req
+        .parse_response(EgValue::from_json_value(not_found).unwrap())
+        .unwrap();
+    assert_eq!(resp.event().unwrap().textcode(), "ASSET_COPY_NOT_FOUND");
+}
+
+#[test]
+fn hold_and_payment_requests() {
+    let mut req = HoldRequest::new("token");
+    req.set_patron_id(1).set_pickup_lib(4).set_hold_type("T");
+    assert!(req.validate().is_err());
+
+    req.set_target(99);
+    assert!(req.validate().is_ok());
+    assert_eq!(req.params()[2][0].as_int(), Some(99));
+
+    let placed = json::object! {target: 99, result: 1234};
+    let resp = req
+        .parse_response(EgValue::from_json_value(placed).unwrap())
+        .unwrap();
+    assert_eq!(resp.into_result().unwrap(), 1234);
+
+    let blocked = json::object! {target: 99, result: [{textcode: "HOLD_EXISTS"}]};
+    let resp = req
+        .parse_response(EgValue::from_json_value(blocked).unwrap())
+        .unwrap();
+    assert_eq!(resp.event().unwrap().textcode(), "HOLD_EXISTS");
+
+    let mut req = PaymentRequest::new("token");
+    req.set_user_id(1).set_payment_type("cash_payment");
+    assert!(req.validate().is_err());
+
+    req.add_payment(10, "1.50").add_payment(11, "2.00");
+    assert!(req.validate().is_err());
+
+    req.set_last_xact_id("abc");
+    assert!(req.validate().is_ok());
+    assert_eq!(req.payment_count(), 2);
+    assert_eq!(req.params()[1]["payments"][1][1].as_str(), Some("2.00"));
+    assert_eq!(req.params()[2].as_str(), Some("abc"));
+
+    let paid = json::object! {last_xact_id: "def", payments: [5, 6]};
+    let outcome = req
+        .parse_response(EgValue::from_json_value(paid).unwrap())
+        .unwrap()
+        .into_result()
+        .unwrap();
+    assert_eq!(outcome.payment_ids, Some(vec![5, 6]));
+}
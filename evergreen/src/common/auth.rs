@@ -58,10 +58,16 @@ impl fmt::Display for LoginType {
 }
 
 pub struct LoginArgs {
+    /// Username, or patron barcode if barcode is true.
     pub username: String,
     pub password: String,
     pub login_type: LoginType,
     pub workstation: Option<String>,
+    /// Login with a card barcode instead of a username.
+    pub barcode: bool,
+    /// Send the password as-is to open-ils.auth.login instead of
+    /// using the seed/MD5 handshake.
+    pub native: bool,
 }
 
 impl LoginArgs {
@@ -90,6 +96,29 @@ impl LoginArgs {
                 Some(w) => Some(w.to_string()),
                 _ => None,
             },
+            barcode: false,
+            native: false,
+        }
+    }
+
+    /// Login arguments for a card barcode login.
+    pub fn with_barcode(
+        barcode: &str,
+        password: &str,
+        login_type: impl Into<LoginType>,
+        workstation: Option<&str>,
+    ) -> Self {
+        let mut args = LoginArgs::new(barcode, password, login_type, workstation);
+        args.barcode = true;
+        args
+    }
+
+    /// "barcode" or "username"
+    fn identifier_key(&self) -> &'static str {
+        if self.barcode {
+            "barcode"
+        } else {
+            "username"
         }
     }
 
@@ -110,14 +139,21 @@ impl LoginArgs {
     }
 
     pub fn to_eg_value(&self) -> EgValue {
+        self.to_eg_value_with_password(self.password())
+    }
+
+    /// Login arguments with the password replaced, e.g. with a hashed
+    /// version of the password.
+    fn to_eg_value_with_password(&self, password: &str) -> EgValue {
         let lt: &str = self.login_type().into();
 
         let mut jv = eg::hash! {
-            username: self.username(),
-            password: self.password(),
+            password: password,
             "type": lt,
         };
 
+        jv[self.identifier_key()] = EgValue::from(self.username());
+
         if let Some(w) = &self.workstation {
             jv["workstation"] = EgValue::from(w.as_str());
         }
@@ -126,6 +162,12 @@ impl LoginArgs {
     }
 }
 
+/// Password hash sent with open-ils.auth.authenticate.complete
+fn md5_password(seed: &str, password: &str) -> String {
+    let pw_hash = format!("{:x}", md5::compute(password));
+    format!("{:x}", md5::compute(format!("{seed}{pw_hash}")))
+}
+
 #[derive(Debug)]
 pub struct InternalLoginArgs {
     pub user_id: i64,
@@ -186,6 +228,13 @@ pub struct Session {
 
     /// Amount to exist Persist sessions.
     reset_interval: Option<i64>,
+
+    /// Epoch seconds where this session expires if not refreshed.
+    expires_at: i64,
+
+    /// Set if the login succeeded only after dropping a workstation
+    /// which does not exist.
+    workstation_event: Option<EgEvent>,
 }
 
 impl Session {
@@ -203,6 +252,8 @@ impl Session {
         let endtime = cache_val["endtime"].as_i64();
         let reset_interval = cache_val["reset_interval"].as_i64();
 
+        // The cache does not tell us how long the session has left,
+        // so assume it was just used.
         let ses = Session {
             user,
             authtime,
@@ -210,6 +261,8 @@ impl Session {
             reset_interval,
             workstation: None,
             token: token.to_string(),
+            expires_at: session_expires_at(authtime, endtime),
+            workstation_event: None,
         };
 
         Ok(Some(ses))
@@ -229,25 +282,103 @@ impl Session {
         Ok(())
     }
 
-    /// Login and acquire an authtoken.
+    /// Logout and remove this auth session.
+    pub fn delete(&self, client: &Client) -> EgResult<()> {
+        Session::logout(client, self.token())
+    }
+
+    /// Login with a username or barcode and password and acquire an
+    /// authtoken.
     ///
-    /// Returns None on login failure, Err on error.
-    pub fn login(client: &Client, args: &LoginArgs) -> EgResult<Option<Session>> {
-        let params = vec![args.to_eg_value()];
+    /// If the workstation does not exist, the login is retried without
+    /// it and the WORKSTATION_NOT_FOUND event is available from
+    /// workstation_event().
+    ///
+    /// Login failures, e.g. LOGIN_FAILED, are returned as
+    /// EgError::Event's.
+    pub fn login(client: &Client, args: &LoginArgs) -> EgResult<Session> {
+        let evt = match Session::login_once(client, args) {
+            Err(EgError::Event(e))
+                if e.textcode() == "WORKSTATION_NOT_FOUND" && args.workstation.is_some() =>
+            {
+                e
+            }
+            result => return result,
+        };
+
+        log::warn!("Login workstation not found; retrying without a workstation: {evt}");
+
+        let retry_args = LoginArgs {
+            username: args.username.clone(),
+            password: args.password.clone(),
+            login_type: args.login_type,
+            workstation: None,
+            barcode: args.barcode,
+            native: args.native,
+        };
+
+        let mut auth_ses = Session::login_once(client, &retry_args)?;
+        auth_ses.workstation_event = Some(evt);
+
+        Ok(auth_ses)
+    }
+
+    fn login_once(client: &Client, args: &LoginArgs) -> EgResult<Session> {
         let mut ses = client.session("open-ils.auth");
-        let mut req = ses.request("open-ils.auth.login", params)?;
+
+        let params = if args.native {
+            vec![args.to_eg_value()]
+        } else {
+            let method = if args.barcode {
+                "open-ils.auth.authenticate.init.barcode"
+            } else {
+                "open-ils.auth.authenticate.init"
+            };
+
+            let mut req = ses.request(method, args.username())?;
+
+            let seed = match req.recv_with_timeout(LOGIN_TIMEOUT)? {
+                Some(v) => v,
+                None => Err("Login Timed Out".to_string())?,
+            };
+
+            let seed = seed
+                .as_str()
+                .ok_or_else(|| format!("Unexpected login seed: {seed}"))?;
+
+            vec![args.to_eg_value_with_password(&md5_password(seed, args.password()))]
+        };
+
+        let method = if args.native {
+            "open-ils.auth.login"
+        } else {
+            "open-ils.auth.authenticate.complete"
+        };
+
+        let mut req = ses.request(method, params)?;
 
         let eg_val = match req.recv_with_timeout(LOGIN_TIMEOUT)? {
             Some(v) => v,
-            None => Err(format!("Login Timed Out"))?,
+            None => Err("Login Timed Out".to_string())?,
         };
 
-        Session::handle_auth_response(&args.workstation, &eg_val)
+        let evt = EgEvent::parse(&eg_val)
+            .ok_or_else(|| format!("Unexpected login response: {eg_val}"))?;
+
+        if !evt.is_success() {
+            log::info!("Login failed: {evt}");
+            return Err(evt.into());
+        }
+
+        Session::handle_auth_response(&args.workstation, &eg_val)?
+            .ok_or_else(|| format!("Unexpected login response: {eg_val}").into())
     }
 
     /// Create an authtoken for an internal auth session via the API.
     ///
-    /// Returns None on login failure, Err on error.
+    /// Returns None on login failure, Err on error.  A workstation
+    /// which does not exist is returned as a WORKSTATION_NOT_FOUND
+    /// EgError::Event.
     pub fn internal_session_api(
         client: &Client,
         args: &InternalLoginArgs,
@@ -261,6 +392,13 @@ impl Session {
             None => Err(format!("Login Timed Out"))?,
         };
 
+        if let Some(evt) = EgEvent::parse(&eg_val) {
+            if evt.textcode() == "WORKSTATION_NOT_FOUND" {
+                log::warn!("Internal login failed: {evt}");
+                return Err(evt.into());
+            }
+        }
+
         Session::handle_auth_response(&args.workstation, &eg_val)
     }
 
@@ -298,6 +436,8 @@ impl Session {
             workstation: None,
             endtime: None,
             reset_interval: None,
+            expires_at: session_expires_at(authtime, None),
+            workstation_event: None,
         };
 
         if let Some(w) = workstation {
@@ -320,7 +460,7 @@ impl Session {
             let mut ws = editor
                 .search("aws", eg::hash! {"name": workstation})?
                 .pop()
-                .ok_or_else(|| editor.die_event())?;
+                .ok_or_else(|| EgEvent::new("WORKSTATION_NOT_FOUND"))?;
 
             user["wsid"] = ws["id"].take();
             user["ws_ou"] = ws["owning_lib"].take();
@@ -365,6 +505,8 @@ impl Session {
             endtime,
             reset_interval,
             workstation: args.workstation.clone(),
+            expires_at: session_expires_at(duration, endtime),
+            workstation_event: None,
         };
 
         Ok(auth_ses)
@@ -382,6 +524,12 @@ impl Session {
         self.workstation.as_deref()
     }
 
+    /// WORKSTATION_NOT_FOUND event if the login only succeeded
+    /// without the requested workstation.
+    pub fn workstation_event(&self) -> Option<&EgEvent> {
+        self.workstation_event.as_ref()
+    }
+
     pub fn endtime(&self) -> Option<i64> {
         self.endtime
     }
@@ -392,6 +540,78 @@ impl Session {
     pub fn user(&self) -> &EgValue {
         &self.user
     }
+
+    /// Epoch seconds where this session expires if not refreshed.
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at
+    }
+
+    /// Seconds until this session expires.  Zero if already expired.
+    pub fn seconds_remaining(&self) -> i64 {
+        seconds_remaining(self.expires_at)
+    }
+
+    /// Extend the session timeout via the API.
+    ///
+    /// Returns false if the session no longer exists, e.g. it expired
+    /// or the auth cache was flushed, in which case the caller has to
+    /// login again.
+    pub fn refresh(&mut self, client: &Client) -> EgResult<bool> {
+        let authtime = match Session::reset_timeout(client, &self.token)? {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+
+        self.authtime = authtime;
+        self.expires_at = session_expires_at(authtime, self.endtime);
+
+        Ok(true)
+    }
+
+    /// Reset the timeout for an authtoken via the API.
+    ///
+    /// Returns the session duration in seconds, or None if the
+    /// session does not exist.
+    pub fn reset_timeout(client: &Client, token: &str) -> EgResult<Option<u32>> {
+        let mut ses = client.session("open-ils.auth");
+        let mut req = ses.request("open-ils.auth.session.reset_timeout", token)?;
+
+        let resp = match req.recv_with_timeout(LOGIN_TIMEOUT)? {
+            Some(v) => v,
+            None => Err("Session reset timed out".to_string())?,
+        };
+
+        let evt = EgEvent::parse(&resp)
+            .ok_or_else(|| format!("Unexpected session reset response: {resp}"))?;
+
+        if evt.textcode() == "NO_SESSION" {
+            log::info!("Auth session no longer exists; login required");
+            return Ok(None);
+        }
+
+        if !evt.is_success() {
+            return Err(format!("Session reset failed: {evt}").into());
+        }
+
+        Ok(Some(evt.payload()["authtime"].int()? as u32))
+    }
+}
+
+/// Epoch seconds where a session with the provided duration will
+/// expire if not refreshed.
+///
+/// Persistent sessions never extend beyond their end time.
+pub fn session_expires_at(authtime: u32, endtime: Option<i64>) -> i64 {
+    let expires = date::epoch_secs().floor() as i64 + authtime as i64;
+    match endtime {
+        Some(end) => expires.min(end),
+        None => expires,
+    }
+}
+
+/// Seconds until the provided epoch time.  Zero if in the past.
+pub fn seconds_remaining(expires_at: i64) -> i64 {
+    (expires_at - date::epoch_secs().floor() as i64).max(0)
 }
 
 /// Returns the auth session duration in seconds for the provided
@@ -430,7 +650,7 @@ pub fn get_auth_duration(
         let setkey = // TODO change key names?
             format!("apps/open-ils.auth_internal/app_settings/default_timeout/{auth_type}");
 
-        interval_binding = HostSettings::get(&setkey)?.clone();
+        interval_binding = HostSettings::get(&setkey)?;
         interval = &interval_binding;
     }
 
@@ -442,3 +662,33 @@ pub fn get_auth_duration(
         Ok(0)
     }
 }
+
+#[test]
+fn session_expiry() {
+    let now = date::epoch_secs().floor() as i64;
+
+    let expires = session_expires_at(600, None);
+    assert!(expires >= now + 600 && expires <= now + 601);
+    assert!(seconds_remaining(expires) > 590);
+
+    // Persistent sessions cannot outlive their end time.
+    assert_eq!(session_expires_at(600, Some(now + 10)), now + 10);
+
+    assert_eq!(seconds_remaining(now - 5), 0);
+}
+
+#[test]
+fn login_args() {
+    assert_eq!(
+        md5_password("seed123", "demo123"),
+        "d796edaf14aa0c7a99b6870c00a5c89a"
+    );
+
+    let args = LoginArgs::with_barcode("99999393001", "demo123", LoginType::Opac, Some("BR1-ws"));
+    let value = args.to_eg_value();
+
+    assert_eq!(value["barcode"].as_str(), Some("99999393001"));
+    assert!(!value.has_key("username"));
+    assert_eq!(value["type"].as_str(), Some("opac"));
+    assert_eq!(value["workstation"].as_str(), Some("BR1-ws"));
+}
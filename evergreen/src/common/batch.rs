@@ -0,0 +1,504 @@
+//! Shared skeleton for batch processing binaries.
+//!
+//! A BatchRunner iterates over a (potentially very large) set of
+//! ascending numeric IDs one chunk at a time, passing each ID to a
+//! processing closure.  Progress is checkpointed after every chunk so
+//! an interrupted run can resume where it left off.
+//!
+//! Because up to one chunk may be re-processed after a hard kill,
+//! item processing should be idempotent.
+use crate as eg;
+use eg::osrf::cache::Cache;
+use eg::EgResult;
+use eg::EgValue;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_CHUNK_SIZE: usize = 100;
+
+/// Log a progress line after processing this many items.
+const DEFAULT_PROGRESS_INTERVAL: usize = 1000;
+
+pub const BATCH_HELP_TEXT: &str = r#"
+Batch Options
+    --chunk-size <count>
+        Number of items to fetch and process at a time.
+        Defaults to 100.
+
+    --rate <items-per-second>
+        Maximum number of items to process per second.
+        Defaults to no limit.
+
+    --resume
+        Resume from the checkpoint left by a previous, interrupted run.
+
+    --checkpoint-file <path>
+        Store progress checkpoints in this file.
+
+    --checkpoint-key <key>
+        Store progress checkpoints in the global cache under this key.
+"#;
+
+/// Where a BatchRunner stores its progress.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checkpoint {
+    /// Progress is not stored.  Every run starts at the beginning.
+    None,
+    /// Store the last processed ID in a file.
+    File(String),
+    /// Store the last processed ID in the global cache under this key.
+    Cache(String),
+}
+
+impl Checkpoint {
+    /// True if this checkpoint is stored in the global cache, which
+    /// must be initialized (see Cache::init_cache()) before running.
+    pub fn is_cache(&self) -> bool {
+        matches!(self, Self::Cache(_))
+    }
+
+    /// Returns the last processed ID, if one has been stored.
+    pub fn load(&self) -> EgResult<Option<i64>> {
+        match self {
+            Self::None => Ok(None),
+            Self::File(path) => {
+                if !Path::new(path).exists() {
+                    return Ok(None);
+                }
+
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read checkpoint file {path}: {e}"))?;
+
+                let content = content.trim();
+
+                if content.is_empty() {
+                    return Ok(None);
+                }
+
+                content
+                    .parse::<i64>()
+                    .map(Some)
+                    .map_err(|e| format!("Invalid checkpoint value in {path}: {e}").into())
+            }
+            Self::Cache(key) => match Cache::get_global(key)? {
+                Some(v) => Ok(v.as_int()),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Store the last processed ID.
+    pub fn save(&self, id: i64) -> EgResult<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::File(path) => fs::write(path, format!("{id}\n"))
+                .map_err(|e| format!("Cannot write checkpoint file {path}: {e}").into()),
+            Self::Cache(key) => Cache::set_global(key, EgValue::from(id)),
+        }
+    }
+
+    /// Remove any stored checkpoint.
+    pub fn clear(&self) -> EgResult<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::File(path) => {
+                if Path::new(path).exists() {
+                    fs::remove_file(path)
+                        .map_err(|e| format!("Cannot remove checkpoint file {path}: {e}"))?;
+                }
+                Ok(())
+            }
+            Self::Cache(key) => Cache::del_global(key),
+        }
+    }
+}
+
+/// Summary of a single batch run.
+#[derive(Debug, Clone, Default)]
+pub struct BatchSummary {
+    /// Number of chunks fetched.
+    pub chunks: usize,
+    /// Number of items processed successfully.
+    pub processed: usize,
+    /// Number of items whose processing returned an error.
+    pub failed: usize,
+    /// ID we resumed after, if this run resumed from a checkpoint.
+    pub resumed_after: Option<i64>,
+    /// Last ID handled by this run.
+    pub last_id: Option<i64>,
+    /// True if the run was stopped before it ran out of items.
+    pub interrupted: bool,
+    pub duration: Duration,
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "chunks={} processed={} failed={} duration={:.3}s",
+            self.chunks,
+            self.processed,
+            self.failed,
+            self.duration.as_secs_f64()
+        )?;
+
+        if let Some(id) = self.resumed_after {
+            write!(f, " resumed_after={id}")?;
+        }
+
+        if let Some(id) = self.last_id {
+            write!(f, " last_id={id}")?;
+        }
+
+        if self.interrupted {
+            write!(f, " interrupted")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Chunked, rate-limited, resumable iteration over a set of IDs.
+///
+/// ```
+/// use evergreen::common::batch::BatchRunner;
+///
+/// let ids: Vec<i64> = (1..=25).collect();
+/// let mut seen = Vec::new();
+///
+/// let mut runner = BatchRunner::new("example");
+/// runner.set_chunk_size(10);
+///
+/// let summary = runner
+///     .run(
+///         |after, limit| {
+///             Ok(ids
+///                 .iter()
+///                 .filter(|id| after.map(|a| **id > a).unwrap_or(true))
+///                 .take(limit)
+///                 .copied()
+///                 .collect())
+///         },
+///         |id| {
+///             seen.push(id);
+///             Ok(())
+///         },
+///     )
+///     .unwrap();
+///
+/// assert_eq!(summary.chunks, 3);
+/// assert_eq!(summary.processed, 25);
+/// assert_eq!(seen, ids);
+/// ```
+pub struct BatchRunner {
+    /// Used for logging.
+    name: String,
+    chunk_size: usize,
+    /// Maximum items per second.  0.0 means no limit.
+    rate: f64,
+    /// Start after the stored checkpoint instead of at the beginning.
+    resume: bool,
+    checkpoint: Checkpoint,
+    progress_interval: usize,
+    /// Set to true to stop the run after the current item.
+    stop: Arc<AtomicBool>,
+}
+
+impl BatchRunner {
+    pub fn new(name: &str) -> Self {
+        BatchRunner {
+            name: name.to_string(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            rate: 0.0,
+            resume: false,
+            checkpoint: Checkpoint::None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Add the standard batch options to a set of command line options.
+    ///
+    /// See BATCH_HELP_TEXT.
+    pub fn add_options(options: &mut getopts::Options) {
+        options.optopt("", "chunk-size", "", "");
+        options.optopt("", "rate", "", "");
+        options.optflag("", "resume", "");
+        options.optopt("", "checkpoint-file", "", "");
+        options.optopt("", "checkpoint-key", "", "");
+    }
+
+    /// Create a runner from command line options added via add_options().
+    pub fn from_params(name: &str, params: &getopts::Matches) -> EgResult<Self> {
+        let mut runner = BatchRunner::new(name);
+
+        if let Some(size) = params.opt_str("chunk-size") {
+            let size = size
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid --chunk-size: {e}"))?;
+            runner.set_chunk_size(size);
+        }
+
+        if let Some(rate) = params.opt_str("rate") {
+            let rate = rate
+                .parse::<f64>()
+                .map_err(|e| format!("Invalid --rate: {e}"))?;
+            runner.set_rate(rate);
+        }
+
+        if let Some(path) = params.opt_str("checkpoint-file") {
+            runner.set_checkpoint(Checkpoint::File(path));
+        } else if let Some(key) = params.opt_str("checkpoint-key") {
+            runner.set_checkpoint(Checkpoint::Cache(key));
+        }
+
+        if params.opt_present("resume") {
+            if runner.checkpoint == Checkpoint::None {
+                return Err("--resume requires a --checkpoint-file or --checkpoint-key".into());
+            }
+            runner.set_resume(true);
+        }
+
+        Ok(runner)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+    /// Values of 0 are treated as 1.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size.max(1);
+    }
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+    /// Maximum items per second.  Zero or negative means no limit.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate.max(0.0);
+    }
+    pub fn resume(&self) -> bool {
+        self.resume
+    }
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+    pub fn checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+    pub fn set_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.checkpoint = checkpoint;
+    }
+    pub fn set_progress_interval(&mut self, interval: usize) {
+        self.progress_interval = interval;
+    }
+
+    /// Returns a handle which may be used to stop the run, e.g. from
+    /// a signal handler or from within the processing closure.
+    ///
+    /// A stopped run saves its checkpoint and may be resumed later.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Run the batch.
+    ///
+    /// `fetch_chunk` is called with the last ID handled so far (None on
+    /// the first call of a fresh run) and the maximum number of IDs to
+    /// return.  It must return IDs in ascending order which are greater
+    /// than the provided ID.  An empty list ends the run.
+    ///
+    /// `process_item` is called once per ID.  Errors are logged and
+    /// counted, but do not end the run.
+    pub fn run<F, P>(&mut self, mut fetch_chunk: F, mut process_item: P) -> EgResult<BatchSummary>
+    where
+        F: FnMut(Option<i64>, usize) -> EgResult<Vec<i64>>,
+        P: FnMut(i64) -> EgResult<()>,
+    {
+        let start = Instant::now();
+        let mut summary = BatchSummary::default();
+
+        let mut last_id = None;
+
+        if self.resume {
+            last_id = self.checkpoint.load()?;
+            summary.resumed_after = last_id;
+
+            if let Some(id) = last_id {
+                log::info!("{self} resuming after ID {id}");
+            }
+        }
+
+        let mut handled = 0;
+
+        'chunks: loop {
+            if self.stopped() {
+                summary.interrupted = true;
+                break;
+            }
+
+            let ids = fetch_chunk(last_id, self.chunk_size)?;
+
+            if ids.is_empty() {
+                break;
+            }
+
+            summary.chunks += 1;
+
+            log::debug!("{self} fetched chunk of {} items", ids.len());
+
+            for id in ids {
+                if self.stopped() {
+                    summary.interrupted = true;
+                    break 'chunks;
+                }
+
+                match process_item(id) {
+                    Ok(()) => summary.processed += 1,
+                    Err(e) => {
+                        log::error!("{self} error processing item {id}: {e}");
+                        summary.failed += 1;
+                    }
+                }
+
+                last_id = Some(id);
+                handled += 1;
+
+                if self.progress_interval > 0 && handled % self.progress_interval == 0 {
+                    log::info!(
+                        "{self} progress: processed={} failed={}",
+                        summary.processed,
+                        summary.failed
+                    );
+                }
+
+                self.throttle(start, handled);
+            }
+
+            if let Some(id) = last_id {
+                self.checkpoint.save(id)?;
+            }
+        }
+
+        if summary.interrupted {
+            // Capture progress made within the final partial chunk.
+            if let Some(id) = last_id {
+                self.checkpoint.save(id)?;
+            }
+            log::info!("{self} stopped before completion");
+        } else {
+            // Nothing left to resume.
+            self.checkpoint.clear()?;
+        }
+
+        summary.last_id = last_id;
+        summary.duration = start.elapsed();
+
+        log::info!("{self} finished: {summary}");
+
+        Ok(summary)
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Sleep as needed to keep the processing rate at or below the
+    /// configured limit.
+    fn throttle(&self, start: Instant, handled: usize) {
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        let wanted = Duration::from_secs_f64(handled as f64 / self.rate);
+        let elapsed = start.elapsed();
+
+        if wanted > elapsed {
+            thread::sleep(wanted - elapsed);
+        }
+    }
+}
+
+impl fmt::Display for BatchRunner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BatchRunner({})", self.name)
+    }
+}
+
+// Non-doc test required since it relies on a temp file.
+#[test]
+fn test_resume_after_interrupt() {
+    let ids: Vec<i64> = (1..=100).collect();
+
+    let fetch = |after: Option<i64>, limit: usize| -> EgResult<Vec<i64>> {
+        Ok(ids
+            .iter()
+            .filter(|id| after.map(|a| **id > a).unwrap_or(true))
+            .take(limit)
+            .copied()
+            .collect())
+    };
+
+    let path =
+        std::env::temp_dir().join(format!("eg-batch-test-{}.checkpoint", std::process::id()));
+    let checkpoint = Checkpoint::File(path.to_string_lossy().to_string());
+    checkpoint.clear().unwrap();
+
+    let mut seen = Vec::new();
+
+    // First run gets interrupted part way through a chunk.
+    let mut runner = BatchRunner::new("test");
+    runner.set_chunk_size(10);
+    runner.set_checkpoint(checkpoint.clone());
+
+    let stop = runner.stop_flag();
+
+    let summary = runner
+        .run(&fetch, |id| {
+            seen.push(id);
+            if id == 37 {
+                stop.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        })
+        .unwrap();
+
+    assert!(summary.interrupted);
+    assert_eq!(summary.processed, 37);
+    assert_eq!(checkpoint.load().unwrap(), Some(37));
+
+    // Second run resumes where the first left off.
+    let mut runner = BatchRunner::new("test");
+    runner.set_chunk_size(10);
+    runner.set_checkpoint(checkpoint.clone());
+    runner.set_resume(true);
+
+    let summary = runner
+        .run(&fetch, |id| {
+            seen.push(id);
+            if id % 20 == 0 {
+                return Err("synthetic failure".into());
+            }
+            Ok(())
+        })
+        .unwrap();
+
+    assert!(!summary.interrupted);
+    assert_eq!(summary.resumed_after, Some(37));
+    assert_eq!(summary.processed, 59);
+    assert_eq!(summary.failed, 4);
+    assert_eq!(summary.last_id, Some(100));
+
+    // Every item was handled exactly once across both runs.
+    assert_eq!(seen, ids);
+
+    // A completed run leaves nothing to resume.
+    assert_eq!(checkpoint.load().unwrap(), None);
+}
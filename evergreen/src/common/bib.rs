@@ -1,11 +1,13 @@
 use crate as eg;
 use eg::common::holds;
+use eg::common::org;
 use eg::idl;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
 use marc;
 use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
 
 // Bib record display attributes are used widely. May as well flesh them
 // out and provide a bit of structure.
@@ -414,3 +416,330 @@ pub fn record_copy_counts(
 
     Ok(data)
 }
+
+/// Controls how display field values are cleaned up for output.
+///
+/// Some SIP devices cannot cope with combining characters, and
+/// some cannot cope with anything beyond ASCII.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DisplayFieldOptions {
+    /// Compose characters and combining marks (Unicode NFC).
+    pub nfc: bool,
+    /// Transliterate to ASCII.  Implies nfc.
+    pub ascii: bool,
+}
+
+impl DisplayFieldOptions {
+    /// Clean up a display value per our options.
+    pub fn apply(&self, value: &str) -> String {
+        if self.ascii {
+            // Matches the transliteration applied by ascii-mode
+            // SIP connections.
+            deunicode::deunicode(&value.nfc().collect::<String>()).replace('\n', "")
+        } else if self.nfc {
+            value.nfc().collect()
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+/// Identifies a copy by ID or barcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyIdent<'a> {
+    Id(i64),
+    Barcode(&'a str),
+}
+
+/// Display-ready values for a copy and its bib record.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CopyDisplayFields {
+    copy_id: i64,
+    barcode: String,
+    record_id: Option<i64>,
+    call_number: String,
+    title: Option<String>,
+    author: Option<String>,
+    isbn: Option<String>,
+    owning_lib: Option<String>,
+    circ_lib: Option<String>,
+}
+
+impl CopyDisplayFields {
+    pub fn copy_id(&self) -> i64 {
+        self.copy_id
+    }
+    pub fn barcode(&self) -> &str {
+        &self.barcode
+    }
+    /// None for pre-cataloged copies.
+    pub fn record_id(&self) -> Option<i64> {
+        self.record_id
+    }
+    pub fn call_number(&self) -> &str {
+        &self.call_number
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+    pub fn isbn(&self) -> Option<&str> {
+        self.isbn.as_deref()
+    }
+    /// Shortname of the call number owning library.
+    pub fn owning_lib(&self) -> Option<&str> {
+        self.owning_lib.as_deref()
+    }
+    /// Shortname of the copy circulating library.
+    pub fn circ_lib(&self) -> Option<&str> {
+        self.circ_lib.as_deref()
+    }
+
+    pub fn into_value(self) -> EgValue {
+        eg::hash! {
+            "copy_id": self.copy_id,
+            "barcode": self.barcode,
+            "record_id": self.record_id,
+            "call_number": self.call_number,
+            "title": self.title,
+            "author": self.author,
+            "isbn": self.isbn,
+            "owning_lib": self.owning_lib,
+            "circ_lib": self.circ_lib,
+        }
+    }
+}
+
+/// Title, author, and ISBN for a bib record.
+#[derive(Default)]
+struct BibDisplayFields {
+    title: Option<String>,
+    author: Option<String>,
+    isbn: Option<String>,
+}
+
+impl BibDisplayFields {
+    fn from_attrs(attrs: &DisplayAttrSet) -> BibDisplayFields {
+        let value = |name| Some(attrs.first_value(name).to_string()).filter(|v| !v.is_empty());
+
+        BibDisplayFields {
+            title: value("title"),
+            author: value("author"),
+            isbn: value("isbn"),
+        }
+    }
+
+    /// Extract values from the MARC for records which have no
+    /// display fields, e.g. records not yet reingested.
+    fn from_marc(xml: &str) -> EgResult<BibDisplayFields> {
+        let record = match marc::Record::from_xml(xml).next() {
+            Some(result) => result?,
+            None => return Err("MARC XML parsing returned no result".into()),
+        };
+
+        let join = |tag, codes: &[&str]| {
+            let mut parts = Vec::new();
+            for field in record.get_fields(tag) {
+                for sf in field.subfields() {
+                    if codes.contains(&sf.code()) {
+                        parts.push(sf.content().trim());
+                    }
+                }
+                if !parts.is_empty() {
+                    break;
+                }
+            }
+            Some(parts.join(" ")).filter(|v| !v.is_empty())
+        };
+
+        let title = join("245", &["a", "b", "n", "p"]).map(|t| {
+            t.trim_end_matches([' ', '/', ':', ';', ',', '.'])
+                .to_string()
+        });
+
+        let author = join("100", &["a"])
+            .or_else(|| join("110", &["a"]))
+            .or_else(|| join("111", &["a"]))
+            .map(|a| a.trim_end_matches([' ', ',', '.']).to_string());
+
+        // ISBNs are often followed by qualifiers, e.g. "(pbk.)"
+        let isbn =
+            join("020", &["a"]).and_then(|i| i.split_whitespace().next().map(|s| s.to_string()));
+
+        Ok(BibDisplayFields {
+            title,
+            author,
+            isbn,
+        })
+    }
+}
+
+/// Returns display fields for a single copy.
+///
+/// Returns None if no such copy exists.
+pub fn get_display_fields(
+    editor: &mut Editor,
+    copy: CopyIdent,
+    options: DisplayFieldOptions,
+) -> EgResult<Option<CopyDisplayFields>> {
+    let query = match copy {
+        CopyIdent::Id(id) => eg::hash! {"id": id},
+        CopyIdent::Barcode(bc) => eg::hash! {"barcode": bc, "deleted": "f"},
+    };
+
+    Ok(display_fields_for_query(editor, query, options)?.pop())
+}
+
+/// Returns display fields for a list of copies, in the order of the
+/// provided copy IDs, skipping IDs for copies which do not exist.
+///
+/// The number of queries does not grow with the number of copies.
+pub fn get_display_fields_batch(
+    editor: &mut Editor,
+    copy_ids: &[i64],
+    options: DisplayFieldOptions,
+) -> EgResult<Vec<CopyDisplayFields>> {
+    if copy_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut fields = display_fields_for_query(editor, eg::hash! {"id": copy_ids}, options)?;
+
+    fields.sort_by_key(|f| copy_ids.iter().position(|id| *id == f.copy_id));
+
+    Ok(fields)
+}
+
+fn display_fields_for_query(
+    editor: &mut Editor,
+    query: EgValue,
+    options: DisplayFieldOptions,
+) -> EgResult<Vec<CopyDisplayFields>> {
+    let flesh = eg::hash! {"flesh": 1, "flesh_fields": {"acp": ["call_number"]}};
+
+    let copies = editor.search_with_ops("acp", query, flesh)?;
+
+    let mut bib_ids: Vec<i64> = Vec::new();
+    for copy in copies.iter() {
+        let bib_id = copy["call_number"]["record"].int()?;
+        if bib_id > 0 && !bib_ids.contains(&bib_id) {
+            bib_ids.push(bib_id);
+        }
+    }
+
+    let mut bibs: HashMap<i64, BibDisplayFields> = HashMap::new();
+
+    if !bib_ids.is_empty() {
+        for (bib_id, attrs) in get_display_attrs(editor, &bib_ids)? {
+            bibs.insert(bib_id, BibDisplayFields::from_attrs(&attrs));
+        }
+
+        let missing: Vec<i64> = bib_ids
+            .iter()
+            .filter(|id| bibs.get(id).map(|b| b.title.is_none()).unwrap_or(true))
+            .copied()
+            .collect();
+
+        if !missing.is_empty() {
+            for bre in editor.search("bre", eg::hash! {"id": missing})? {
+                let from_marc = BibDisplayFields::from_marc(bre["marc"].str()?)?;
+                let bib = bibs.entry(bre.id()?).or_default();
+
+                bib.title = bib.title.take().or(from_marc.title);
+                bib.author = bib.author.take().or(from_marc.author);
+                bib.isbn = bib.isbn.take().or(from_marc.isbn);
+            }
+        }
+    }
+
+    let tree = org::tree(editor)?;
+    let shortname = |org_id: &EgValue| {
+        org_id
+            .as_int()
+            .and_then(|id| tree.shortname(id))
+            .map(|s| s.to_string())
+    };
+
+    let clean = |value: Option<&str>| value.map(|v| options.apply(v));
+
+    let mut list = Vec::new();
+
+    for copy in copies.iter() {
+        let acn = &copy["call_number"];
+        let bib_id = acn["record"].int()?;
+
+        let mut fields = CopyDisplayFields {
+            copy_id: copy.id()?,
+            barcode: copy["barcode"].string()?,
+            call_number: options.apply(acn["label"].str()?),
+            owning_lib: shortname(&acn["owning_lib"]),
+            circ_lib: shortname(&copy["circ_lib"]),
+            ..Default::default()
+        };
+
+        if bib_id > 0 {
+            fields.record_id = Some(bib_id);
+            if let Some(bib) = bibs.get(&bib_id) {
+                fields.title = clean(bib.title.as_deref());
+                fields.author = clean(bib.author.as_deref());
+                fields.isbn = clean(bib.isbn.as_deref());
+            }
+        } else {
+            // Pre-cataloged copy
+            fields.title = clean(copy["dummy_title"].as_str());
+            fields.author = clean(copy["dummy_author"].as_str());
+            fields.isbn = clean(copy["dummy_isbn"].as_str());
+        }
+
+        list.push(fields);
+    }
+
+    Ok(list)
+}
+
+#[test]
+fn test_display_field_options() {
+    // "e" followed by a combining acute accent.
+    let title = "Les Mise\u{0301}rables\n";
+
+    let opts = DisplayFieldOptions::default();
+    assert_eq!(opts.apply(title), title);
+
+    let opts = DisplayFieldOptions {
+        nfc: true,
+        ascii: false,
+    };
+    assert_eq!(opts.apply(title), "Les Mis\u{e9}rables\n");
+
+    let opts = DisplayFieldOptions {
+        nfc: false,
+        ascii: true,
+    };
+    assert_eq!(opts.apply(title), "Les Miserables");
+    assert_eq!(opts.apply("Stra\u{df}e"), "Strasse");
+}
+
+#[test]
+fn test_bib_display_fields_from_marc() {
+    let xml = r#"<record xmlns="http://www.loc.gov/MARC21/slim">
+        <leader>00000nam a2200000 a 4500</leader>
+        <datafield tag="020" ind1=" " ind2=" ">
+            <subfield code="a">9780140449242 (pbk.)</subfield>
+        </datafield>
+        <datafield tag="100" ind1="1" ind2=" ">
+            <subfield code="a">Hugo, Victor,</subfield>
+        </datafield>
+        <datafield tag="245" ind1="1" ind2="4">
+            <subfield code="a">Les mis&#233;rables /</subfield>
+            <subfield code="c">Victor Hugo.</subfield>
+        </datafield>
+    </record>"#;
+
+    let bib = BibDisplayFields::from_marc(xml).unwrap();
+
+    assert_eq!(bib.title.as_deref(), Some("Les misérables"));
+    assert_eq!(bib.author.as_deref(), Some("Hugo, Victor"));
+    assert_eq!(bib.isbn.as_deref(), Some("9780140449242"));
+}
@@ -6,6 +6,7 @@ use eg::common::settings::Settings;
 use eg::constants as C;
 use eg::date;
 use eg::editor::Editor;
+use eg::money::Money;
 use eg::result::EgResult;
 use eg::util;
 use eg::EgValue;
@@ -125,7 +126,7 @@ pub fn xact_org(editor: &mut Editor, xact_id: i64) -> EgResult<i64> {
 /// Creates and returns a newly created money.billing.
 pub fn create_bill(
     editor: &mut Editor,
-    amount: f64,
+    amount: Money,
     btype_id: i64,
     btype_label: &str,
     xact_id: i64,
@@ -137,9 +138,8 @@ pub fn create_bill(
 
     let note = maybe_note.unwrap_or("SYSTEM GENERATED");
 
-    let bill = eg::hash! {
+    let mut bill = eg::hash! {
         "xact": xact_id,
-        "amount": amount,
         "period_start": period_start,
         "period_end": period_end,
         "billing_type": btype_label,
@@ -147,6 +147,8 @@ pub fn create_bill(
         "note": note,
     };
 
+    bill["amount"] = amount.into();
+
     let bill = EgValue::create("mb", bill)?;
     editor.create(bill)
 }
@@ -960,7 +962,7 @@ fn calc_min_void_date(
 /// Get the numeric cost of a copy, honoring various org settings
 /// for which field to pull the cost from and how to handle zero/unset
 /// cost values.
-pub fn get_copy_price(editor: &mut Editor, copy_id: i64) -> EgResult<f64> {
+pub fn get_copy_price(editor: &mut Editor, copy_id: i64) -> EgResult<Money> {
     let flesh = eg::hash! {"flesh": 1, "flesh_fields": {"acp": ["call_number"]}};
 
     let copy = editor
@@ -1017,35 +1019,29 @@ pub fn get_copy_price(editor: &mut Editor, copy_id: i64) -> EgResult<f64> {
         &copy["price"]
     };
 
-    if price.is_null() || (price.float()? == 0.0 && charge_on_zero) {
-        if secondary_field != "" {
-            price = &copy[secondary_field];
-        }
+    if (price.is_null() || (Money::from_value(price)?.is_zero() && charge_on_zero))
+        && secondary_field != ""
+    {
+        price = &copy[secondary_field];
     }
 
     // Fall back to legacy item cost calculation
-    let price_binding;
-    if price.is_null() || (price.float()? == 0.0 && charge_on_zero) {
-        let def_price = match settings.get_value("cat.default_item_price")?.as_f64() {
-            Some(p) => p,
-            _ => 0.0,
-        };
-        price_binding = Some(EgValue::from(def_price));
-        price = price_binding.as_ref().unwrap();
-    }
-
-    // Now we want numbers
-    let mut price = if let Ok(p) = price.float() { p } else { 0.00 };
+    let mut price = if price.is_null() || (Money::from_value(price)?.is_zero() && charge_on_zero) {
+        Money::from_value_opt(settings.get_value("cat.default_item_price")?)?.unwrap_or_default()
+    } else {
+        Money::from_value(price)?
+    };
 
-    if let Some(max_price) = settings.get_value("circ.max_item_price")?.as_f64() {
+    if let Some(max_price) = Money::from_value_opt(settings.get_value("circ.max_item_price")?)? {
         if price > max_price {
             price = max_price;
         }
     } else {
-        if let Some(min_price) = settings.get_value("circ.min_item_price")?.as_f64() {
+        if let Some(min_price) = Money::from_value_opt(settings.get_value("circ.min_item_price")?)?
+        {
             if price < min_price {
                 // Let $0 fall through if charge_on_zero is explicitly false.
-                if price != 0.0 || charge_on_zero || charge_on_zero_op.is_none() {
+                if !price.is_zero() || charge_on_zero || charge_on_zero_op.is_none() {
                     price = min_price;
                 }
             }
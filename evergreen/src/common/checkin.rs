@@ -9,6 +9,7 @@ use eg::common::transit;
 use eg::constants as C;
 use eg::date;
 use eg::event::EgEvent;
+use eg::money::Money;
 use eg::result::{EgError, EgResult};
 use eg::EgValue;
 use std::collections::HashSet;
@@ -1081,7 +1082,7 @@ impl Circulator<'_> {
     ) -> EgResult<()> {
         let circ = self.circ.as_ref().unwrap();
         let circ_id = circ.id()?;
-        let void_max = circ["max_fine"].float()?;
+        let void_max = Money::from_value(&circ["max_fine"])?;
 
         let query = eg::hash! {xact: circ_id, btype: C::BTYPE_OVERDUE_MATERIALS};
         let ops = eg::hash! {"order_by": {"mb": "billing_ts desc"}};
@@ -1099,7 +1100,7 @@ impl Circulator<'_> {
         };
         log::info!("{self} re-instating {} pre-{tag} overdues", overdues.len());
 
-        let mut void_amount = 0.0;
+        let mut void_amount = Money::zero();
 
         let billing_ids: Vec<EgValue> = overdues.iter().map(|b| b["id"].clone()).collect();
         let voids = self
@@ -1109,18 +1110,18 @@ impl Circulator<'_> {
         if voids.len() > 0 {
             // Overdues adjusted via account adjustment
             for void in voids.iter() {
-                void_amount += void["amount"].float()?;
+                void_amount += Money::from_value(&void["amount"])?;
             }
         } else {
             // Overdues voided the old-fashioned way, i.e. voided.
             for bill in overdues.iter() {
                 if bill["voided"].boolish() {
-                    void_amount += bill["amount"].float()?;
+                    void_amount += Money::from_value(&bill["amount"])?;
                 }
             }
         }
 
-        if void_amount == 0.0 {
+        if void_amount.is_zero() {
             log::info!("{self} voided overdues amounted to $0.00.  Nothing to restore");
             return Ok(());
         }
@@ -1677,7 +1678,7 @@ impl Circulator<'_> {
     /// open/closed state.
     fn finish_fines_and_voiding(&mut self) -> EgResult<()> {
         let void_overdues = self.get_option_bool("void_overdues");
-        let mut backdate_maybe = match self.options.get("backate") {
+        let mut backdate_maybe = match self.options.get("backdate") {
             Some(bd) => bd.as_str().map(|d| d.to_string()),
             None => None,
         };
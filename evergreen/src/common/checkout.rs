@@ -1,7 +1,8 @@
 use crate as eg;
 use eg::common::bib;
 use eg::common::billing;
-use eg::common::circulator::{CircOp, CircPolicy, Circulator, LEGACY_CIRC_EVENT_MAP};
+use eg::common::circ::permit;
+use eg::common::circulator::{CircOp, CircPolicy, Circulator};
 use eg::common::holds;
 use eg::common::noncat;
 use eg::common::org;
@@ -9,9 +10,9 @@ use eg::common::penalty;
 use eg::constants as C;
 use eg::date;
 use eg::event::EgEvent;
+use eg::money::Money;
 use eg::result::EgResult;
 use eg::EgValue;
-use std::time::Duration;
 
 /// Performs item checkins
 impl Circulator<'_> {
@@ -410,10 +411,9 @@ impl Circulator<'_> {
                 .get_value("circ.checkout_auto_renew_age")?
                 .as_str()
             {
-                let interval = date::interval_to_seconds(intvl)?;
                 let xact_start = date::parse_datetime(circ["xact_start"].as_str().unwrap())?;
 
-                let cutoff = xact_start + Duration::from_secs(interval as u64);
+                let cutoff = date::add_interval(xact_start, intvl)?;
 
                 if date::now() > cutoff {
                     payload["auto_renew"] = EgValue::from(1);
@@ -626,16 +626,7 @@ impl Circulator<'_> {
                 None => continue,
             };
 
-            // Use the mapped value if we have one or default to
-            // using the fail_part as the event code.
-            let evt_code = LEGACY_CIRC_EVENT_MAP
-                .iter()
-                .filter(|(fp, _)| fp == &fail_part)
-                .map(|(_, code)| code)
-                .next()
-                .unwrap_or(&fail_part);
-
-            self.add_event_code(evt_code);
+            self.add_event_code(permit::fail_part_textcode(fail_part));
         }
 
         self.circ_policy_results = Some(policy_results);
@@ -650,7 +641,7 @@ impl Circulator<'_> {
 
         if max_fine_rule["is_percent"].boolish() {
             let copy_price = billing::get_copy_price(self.editor(), copy_id)?;
-            return Ok((copy_price * rule_amount) / 100.0);
+            return Ok((copy_price.to_f64() * rule_amount) / 100.0);
         }
 
         if self
@@ -658,7 +649,7 @@ impl Circulator<'_> {
             .get_value("circ.max_fine.cap_at_price")?
             .boolish()
         {
-            let copy_price = billing::get_copy_price(self.editor(), copy_id)?;
+            let copy_price = billing::get_copy_price(self.editor(), copy_id)?.to_f64();
             let amount = if rule_amount > copy_price {
                 copy_price
             } else {
@@ -816,11 +807,7 @@ impl Circulator<'_> {
             None => date::now(),
         };
 
-        let start_date = date::set_timezone(start_date, timezone)?;
-
-        let dur_secs = date::interval_to_seconds(&policy.duration)?;
-
-        let mut due_date = start_date + Duration::from_secs(dur_secs as u64);
+        let mut due_date = date::add_interval_tz(start_date, &policy.duration, timezone)?;
 
         if let Some(hdd) = policy.hard_due_date.as_ref() {
             let cdate_str = hdd["ceiling_date"].as_str().unwrap();
@@ -937,8 +924,7 @@ impl Circulator<'_> {
 
         // We're configured to shorten the circ in the presence of
         // reservations on this resource.
-        let interval = date::interval_to_seconds(shorten_by)?;
-        let due_date_dt = due_date_dt - Duration::from_secs(interval as u64);
+        let due_date_dt = date::subtract_interval(due_date_dt, shorten_by)?;
 
         if due_date_dt < now_dt {
             self.exit_err_on_event_code("COPY_RESERVED")?;
@@ -1160,7 +1146,7 @@ impl Circulator<'_> {
         }
 
         // confirmed above
-        let deposit_amount = self.copy()["deposit_amount"].as_f64().unwrap();
+        let deposit_amount = Money::from_value(&self.copy()["deposit_amount"])?;
 
         if is_deposit {
             if self.settings.get_value("skip_deposit_fee")?.boolish() || self.is_deposit_exempt()? {
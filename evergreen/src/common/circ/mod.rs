@@ -0,0 +1,352 @@
+//! Shared, circ-focused utility functions
+use crate as eg;
+use eg::common::billing;
+use eg::common::circulator::Circulator;
+use eg::common::penalty;
+use eg::common::settings::Settings;
+use eg::constants as C;
+use eg::event::EgEvent;
+use eg::money::Money;
+use eg::result::EgError;
+use eg::Editor;
+use eg::EgResult;
+use eg::EgValue;
+use std::collections::HashMap;
+
+pub mod permit;
+pub use permit::{PermitBlock, PermitResult};
+
+pub fn summarize_circ_chain(e: &mut Editor, circ_id: i64) -> EgResult<EgValue> {
+    let query = eg::hash! {
+        from: ["action.summarize_all_circ_chain", circ_id]
+    };
+
+    if let Some(circ) = e.json_query(query)?.pop() {
+        Ok(EgValue::create("accs", circ)?)
+    } else {
+        Err(format!("No such circulation: {circ_id}").into())
+    }
+}
+
+pub fn circ_chain(e: &mut Editor, circ_id: i64) -> EgResult<Vec<EgValue>> {
+    let query = eg::hash! {
+        from: ["action.all_circ_chain", circ_id]
+    };
+
+    let mut circ_list = e.json_query(query)?;
+
+    if circ_list.len() == 0 {
+        Err("No such circulation: {circ_id}")?;
+    }
+
+    let mut chains = Vec::new();
+    for circ in circ_list.drain(..) {
+        chains.push(EgValue::create("aacs", circ)?);
+    }
+
+    Ok(chains)
+}
+
+/// Mark the copy linked to an open circulation as lost, bill the
+/// patron for the item and any processing fee, optionally void
+/// overdue fines, and stop fine generation on the circulation.
+///
+/// The caller is responsible for the transaction.  The editor
+/// must have a requestor.
+pub fn mark_item_lost(e: &mut Editor, circ_id: i64) -> EgResult<()> {
+    let mut circ = e.retrieve("circ", circ_id)?.ok_or_else(|| e.die_event())?;
+
+    if !circ["checkin_time"].is_null() {
+        return Err(format!("Circulation {circ_id} is already checked in").into());
+    }
+
+    let copy_id = circ["target_copy"].int()?;
+    let circ_lib = circ["circ_lib"].int()?;
+    let patron_id = circ["usr"].int()?;
+
+    let mut copy = e.retrieve("acp", copy_id)?.ok_or_else(|| e.die_event())?;
+
+    // LOST fine settings are controlled by the copy's circ lib,
+    // not the circulation's.
+    let copy_circ_lib = copy["circ_lib"].int()?;
+
+    log::info!("Marking copy {copy_id} lost for circ {circ_id}");
+
+    copy["status"] = C::COPY_STATUS_LOST.into();
+    copy["editor"] = e.requestor_id()?.into();
+    copy["edit_date"] = "now".into();
+
+    e.update(copy)?;
+
+    let mut settings = Settings::new(e);
+    let void_overdues = settings
+        .get_value_at_org("circ.void_overdue_on_lost", copy_circ_lib)?
+        .boolish();
+
+    let proc_fee = Money::from_value_opt(
+        settings.get_value_at_org("circ.lost_materials_processing_fee", copy_circ_lib)?,
+    )?
+    .unwrap_or_default();
+
+    let price = billing::get_copy_price(e, copy_id)?;
+
+    if price.is_positive() {
+        billing::create_bill(
+            e,
+            price,
+            C::BTYPE_LOST_MATERIALS,
+            "Lost Materials",
+            circ_id,
+            Some("SYSTEM GENERATED"),
+            None,
+            None,
+        )?;
+    }
+
+    if proc_fee.is_positive() {
+        billing::create_bill(
+            e,
+            proc_fee,
+            C::BTYPE_LOST_MATERIALS_PROCESSING_FEE,
+            "Lost Materials Processing Fee",
+            circ_id,
+            Some("SYSTEM GENERATED"),
+            None,
+            None,
+        )?;
+    }
+
+    circ["stop_fines"] = "LOST".into();
+    circ["stop_fines_time"] = "now".into();
+
+    e.update(circ)?;
+
+    if void_overdues {
+        billing::void_or_zero_overdues(
+            e,
+            circ_id,
+            None,
+            Some("System: OVERDUE REVERSED FOR LOST"),
+            false,
+            false,
+        )?;
+    }
+
+    billing::check_open_xact(e, circ_id)?;
+
+    penalty::calculate_penalties(e, patron_id, circ_lib, None)
+}
+
+/// Options for [`checkin`].
+#[derive(Debug, Clone, Default)]
+pub struct CheckinParams {
+    pub copy_barcode: Option<String>,
+    pub copy_id: Option<i64>,
+
+    /// Org unit where the checkin occurs.  Defaults to the
+    /// workstation org unit of the editor's requestor.
+    pub circ_lib: Option<i64>,
+
+    /// Date (YYYY-MM-DD) or date/time when the item was returned.
+    ///
+    /// Overdue fines accrued after the backdate are voided, or
+    /// zeroed per the org unit billing settings.
+    pub backdate: Option<String>,
+
+    /// Close the circulation without capturing holds or routing
+    /// the item.
+    pub noop: bool,
+
+    /// Void all overdue fines on the circulation, including those
+    /// which are restored when a lost item is returned.
+    pub void_overdues: bool,
+
+    /// Try to capture the item for a hold.  When false, items are
+    /// checked in and routed home without regard to holds.
+    pub capture_holds: bool,
+
+    /// Send items needed for holds at this location to the holds
+    /// shelf via transit instead of capturing them.
+    pub hold_as_transit: bool,
+
+    /// Undo a hold fulfillment for an item that was checked out to
+    /// fill a hold.
+    pub revert_hold_fulfillment: bool,
+
+    /// Override any events which may be overridden.
+    pub is_override: bool,
+}
+
+impl CheckinParams {
+    pub fn for_barcode(copy_barcode: &str) -> Self {
+        CheckinParams {
+            copy_barcode: Some(copy_barcode.to_string()),
+            ..Default::default()
+        }
+    }
+    pub fn for_copy(copy_id: i64) -> Self {
+        CheckinParams {
+            copy_id: Some(copy_id),
+            ..Default::default()
+        }
+    }
+
+    /// Translate our values into Circulator options.
+    pub fn to_options(&self) -> HashMap<String, EgValue> {
+        let mut options = HashMap::new();
+
+        if let Some(bc) = self.copy_barcode.as_deref() {
+            options.insert("copy_barcode".to_string(), EgValue::from(bc));
+        }
+        if let Some(id) = self.copy_id {
+            options.insert("copy_id".to_string(), EgValue::from(id));
+        }
+        if let Some(org) = self.circ_lib {
+            options.insert("circ_lib".to_string(), EgValue::from(org));
+        }
+        if let Some(bd) = self.backdate.as_deref() {
+            options.insert("backdate".to_string(), EgValue::from(bd));
+        }
+        if !self.capture_holds {
+            options.insert("capture".to_string(), EgValue::from("nocapture"));
+        }
+
+        let flags = [
+            ("noop", self.noop),
+            ("void_overdues", self.void_overdues),
+            ("hold_as_transit", self.hold_as_transit),
+            ("revert_hold_fulfillment", self.revert_hold_fulfillment),
+        ];
+
+        for (name, value) in flags {
+            if value {
+                options.insert(name.to_string(), EgValue::from(true));
+            }
+        }
+
+        options
+    }
+}
+
+/// Outcome of a [`checkin`].
+#[derive(Debug, Clone)]
+pub struct CheckinResult {
+    /// Primary checkin event, e.g. SUCCESS, ROUTE_ITEM, NO_CHANGE,
+    /// or the event which prevented the checkin.
+    pub event: EgEvent,
+
+    /// All events, including the primary event.
+    pub events: Vec<EgEvent>,
+
+    /// True if the checkin changes were committed.
+    pub committed: bool,
+
+    pub copy: Option<EgValue>,
+    pub circ: Option<EgValue>,
+    pub patron: Option<EgValue>,
+
+    /// Hold captured by the checkin.
+    pub hold: Option<EgValue>,
+
+    /// Transit created or received by the checkin.
+    pub transit: Option<EgValue>,
+
+    /// Org unit the item should be routed to.
+    pub destination: Option<i64>,
+}
+
+impl CheckinResult {
+    fn from_events(mut events: Vec<EgEvent>, committed: bool) -> EgResult<Self> {
+        if events.is_empty() {
+            return Err("Checkin returned no events".into());
+        }
+
+        let event = events[0].clone();
+
+        let value = |key: &str| event.payload_value(key).filter(|v| v.is_object()).cloned();
+
+        let mut result = CheckinResult {
+            copy: value("copy"),
+            circ: value("circ"),
+            patron: value("patron"),
+            hold: value("hold"),
+            transit: value("transit"),
+            destination: *event.org(),
+            committed,
+            event: events.remove(0),
+            events: Vec::new(),
+        };
+
+        result.events.push(result.event.clone());
+        result.events.append(&mut events);
+
+        Ok(result)
+    }
+
+    /// True if the item was checked in, whether or not it needs to
+    /// be routed elsewhere.
+    pub fn is_ok(&self) -> bool {
+        self.committed
+    }
+    pub fn needs_transit(&self) -> bool {
+        self.event.textcode() == "ROUTE_ITEM"
+    }
+    pub fn hold_captured(&self) -> bool {
+        self.hold.is_some()
+    }
+}
+
+/// Check in an item.
+///
+/// The checkin runs in its own transaction, which is committed on
+/// success and rolled back if an event prevents the checkin.  Events
+/// which prevent the checkin are returned as part of an unsuccessful
+/// CheckinResult.  Other failures are returned as Err.
+///
+/// The editor must have a requestor.
+pub fn checkin(editor: &mut Editor, params: &CheckinParams) -> EgResult<CheckinResult> {
+    let mut circulator = Circulator::new(editor, params.to_options())?;
+    circulator.is_override = params.is_override;
+
+    circulator.begin()?;
+
+    if let Err(err) = circulator.checkin() {
+        circulator.rollback()?;
+
+        return match err {
+            EgError::Event(evt) => CheckinResult::from_events(vec![evt], false),
+            _ => Err(err),
+        };
+    }
+
+    circulator.commit()?;
+
+    let result = CheckinResult::from_events(circulator.take_events(), true)?;
+
+    // Retarget holds, create A/T events, etc.  The checkin is already
+    // committed, so failures here do not change the result.
+    if let Err(e) = circulator.post_commit_tasks() {
+        log::warn!("Checkin post-commit tasks failed: {e}");
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_checkin_params() {
+    let mut params = CheckinParams::for_barcode("123");
+    params.backdate = Some("2024-03-01".to_string());
+    params.void_overdues = true;
+
+    let options = params.to_options();
+
+    assert_eq!(options["copy_barcode"].as_str(), Some("123"));
+    assert_eq!(options["backdate"].as_str(), Some("2024-03-01"));
+    assert_eq!(options["capture"].as_str(), Some("nocapture"));
+    assert!(options["void_overdues"].boolish());
+    assert!(!options.contains_key("noop"));
+    assert!(!options.contains_key("copy_id"));
+
+    params.capture_holds = true;
+    assert!(!params.to_options().contains_key("capture"));
+}
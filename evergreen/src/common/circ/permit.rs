@@ -0,0 +1,245 @@
+//! Circulation permit tests.
+//!
+//! Answers "would this checkout / renewal be permitted?" without
+//! modifying any data, by running the circulation matrix tests in
+//! the database (action.item_user_circ_test and
+//! action.item_user_renew_test).  The database functions find the
+//! best matchpoint for the circ lib, copy, and patron, honoring org
+//! unit ancestry and the matchpoint's is_renewal flag, then report
+//! each failed test, e.g. patron penalties, copy status rules, and
+//! circulate=false rules.
+use crate as eg;
+use eg::common::circulator::LEGACY_CIRC_EVENT_MAP;
+use eg::EgEvent;
+use eg::{Editor, EgResult, EgValue};
+
+/// Reported when no matchpoint applies to the circulation.
+const NO_POLICY_MATCHPOINT: &str = "NO_POLICY_MATCHPOINT";
+
+/// Reported when the copy does not exist or none was provided.
+const NO_ITEM: &str = "no_item";
+
+/// A single reason why a checkout or renewal would be blocked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitBlock {
+    fail_part: String,
+    textcode: String,
+    overridable: bool,
+}
+
+impl PermitBlock {
+    /// Database test which failed, e.g. "config.circ_matrix_test.circulate"
+    /// or a penalty name like "PATRON_EXCEEDS_FINES".
+    pub fn fail_part(&self) -> &str {
+        &self.fail_part
+    }
+
+    /// Event textcode used when reporting the block to the caller.
+    pub fn textcode(&self) -> &str {
+        &self.textcode
+    }
+
+    /// True if an override permission ("TEXTCODE.override") exists
+    /// for the block.
+    ///
+    /// Whether a given user may apply the override is a separate
+    /// question.  See Editor::allowed().
+    pub fn overridable(&self) -> bool {
+        self.overridable
+    }
+
+    /// Create an event for this block.
+    pub fn to_event(&self) -> EgEvent {
+        let mut evt = EgEvent::new(&self.textcode);
+        evt.set_payload(eg::hash! {"fail_part": self.fail_part.as_str()});
+        evt
+    }
+}
+
+/// Outcome of a checkout or renewal permit test.
+#[derive(Debug, Clone, Default)]
+pub struct PermitResult {
+    matchpoint: Option<i64>,
+    blocks: Vec<PermitBlock>,
+}
+
+impl PermitResult {
+    /// True if nothing would block the circulation.
+    pub fn permitted(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// ID of the circ matrix matchpoint used, if one was found.
+    pub fn matchpoint(&self) -> Option<i64> {
+        self.matchpoint
+    }
+
+    pub fn blocks(&self) -> &[PermitBlock] {
+        &self.blocks
+    }
+
+    /// True if every block may be overridden.
+    pub fn overridable(&self) -> bool {
+        self.blocks.iter().all(|b| b.overridable())
+    }
+
+    /// Event for the first block, if any.
+    pub fn first_event(&self) -> Option<EgEvent> {
+        self.blocks.first().map(|b| b.to_event())
+    }
+}
+
+/// Maps a circ test fail_part to the event textcode reported for it.
+///
+/// Fail parts with no legacy event code, e.g. penalty names, are
+/// used as-is.
+///
+/// ```
+/// use evergreen::common::circ::permit;
+///
+/// assert_eq!(permit::fail_part_textcode("asset.copy.status"), "COPY_NOT_AVAILABLE");
+/// assert_eq!(permit::fail_part_textcode("PATRON_EXCEEDS_FINES"), "PATRON_EXCEEDS_FINES");
+/// ```
+pub fn fail_part_textcode(fail_part: &str) -> &str {
+    LEGACY_CIRC_EVENT_MAP
+        .iter()
+        .find(|(fp, _)| *fp == fail_part)
+        .map(|(_, code)| *code)
+        .unwrap_or(fail_part)
+}
+
+/// Would the patron be permitted to check out the copy at the circ lib?
+///
+/// With no copy, e.g. for non-cataloged checkouts, only the patron
+/// and circ lib tests apply.
+pub fn test_checkout(
+    editor: &mut Editor,
+    patron_id: i64,
+    copy_id: Option<i64>,
+    circ_lib: i64,
+) -> EgResult<PermitResult> {
+    test_permit(editor, patron_id, copy_id, circ_lib, false)
+}
+
+/// Would the patron be permitted to renew the copy at the circ lib?
+pub fn test_renewal(
+    editor: &mut Editor,
+    patron_id: i64,
+    copy_id: i64,
+    circ_lib: i64,
+) -> EgResult<PermitResult> {
+    test_permit(editor, patron_id, Some(copy_id), circ_lib, true)
+}
+
+fn test_permit(
+    editor: &mut Editor,
+    patron_id: i64,
+    copy_id: Option<i64>,
+    circ_lib: i64,
+    is_renewal: bool,
+) -> EgResult<PermitResult> {
+    let func = if is_renewal {
+        "action.item_user_renew_test"
+    } else {
+        "action.item_user_circ_test"
+    };
+
+    let query = eg::hash! {
+        "from": [func, circ_lib, copy_id, patron_id]
+    };
+
+    let results = editor.json_query(query)?;
+
+    log::debug!("{func} for patron={patron_id} copy={copy_id:?} returned: {results:?}");
+
+    let (matchpoint, fail_parts) = evaluate_results(&results, copy_id.is_none());
+
+    let mut blocks: Vec<PermitBlock> = fail_parts
+        .into_iter()
+        .map(|fail_part| PermitBlock {
+            textcode: fail_part_textcode(&fail_part).to_string(),
+            fail_part,
+            overridable: false,
+        })
+        .collect();
+
+    if blocks.is_empty() {
+        return Ok(PermitResult { matchpoint, blocks });
+    }
+
+    // One lookup for all of the override permissions.
+    let codes: Vec<String> = blocks
+        .iter()
+        .map(|b| format!("{}.override", b.textcode))
+        .collect();
+
+    let perms = editor.search("ppl", eg::hash! {"code": codes})?;
+
+    for block in blocks.iter_mut() {
+        let perm = format!("{}.override", block.textcode);
+        block.overridable = perms.iter().any(|p| p["code"].as_str() == Some(&perm));
+    }
+
+    Ok(PermitResult { matchpoint, blocks })
+}
+
+/// Extract the matchpoint and the list of unique failed tests from
+/// the circ test results.
+///
+/// An empty result set means no matchpoint was found.
+fn evaluate_results(results: &[EgValue], ignore_no_item: bool) -> (Option<i64>, Vec<String>) {
+    if results.is_empty() {
+        return (None, vec![NO_POLICY_MATCHPOINT.to_string()]);
+    }
+
+    // Results share the matchpoint, when one was found.
+    let matchpoint = results.iter().find_map(|r| r["matchpoint"].as_int());
+
+    if results[0]["success"].boolish() {
+        return (matchpoint, Vec::new());
+    }
+
+    let mut fail_parts: Vec<String> = Vec::new();
+
+    for fail_part in results.iter().filter_map(|r| r["fail_part"].as_str()) {
+        if ignore_no_item && fail_part == NO_ITEM {
+            continue;
+        }
+
+        if !fail_parts.iter().any(|f| f == fail_part) {
+            fail_parts.push(fail_part.to_string());
+        }
+    }
+
+    if fail_parts.is_empty() && matchpoint.is_none() {
+        fail_parts.push(NO_POLICY_MATCHPOINT.to_string());
+    }
+
+    (matchpoint, fail_parts)
+}
+
+#[test]
+fn test_evaluate_results() {
+    let success = [eg::hash! {"success": "t", "matchpoint": 3}];
+    assert_eq!(evaluate_results(&success, false), (Some(3), vec![]));
+
+    let (mp, parts) = evaluate_results(&[], false);
+    assert_eq!(mp, None);
+    assert_eq!(parts, [NO_POLICY_MATCHPOINT]);
+
+    let failures = [
+        eg::hash! {"success": "f", "matchpoint": 7, "fail_part": "no_item"},
+        eg::hash! {"success": "f", "matchpoint": 7, "fail_part": "PATRON_EXCEEDS_FINES"},
+        eg::hash! {"success": "f", "matchpoint": 7, "fail_part": "PATRON_EXCEEDS_FINES"},
+    ];
+
+    let (mp, parts) = evaluate_results(&failures, false);
+    assert_eq!(mp, Some(7));
+    assert_eq!(parts, ["no_item", "PATRON_EXCEEDS_FINES"]);
+
+    // No-copy tests do not report the missing copy.
+    let (_, parts) = evaluate_results(&failures, true);
+    assert_eq!(parts, ["PATRON_EXCEEDS_FINES"]);
+
+    assert_eq!(fail_part_textcode("no_item"), "ITEM_NOT_CATALOGED");
+}
@@ -406,10 +406,10 @@ impl<'a> Circulator<'a> {
 
     /// Add a potentially overridable event to our events list.
     pub fn add_event(&mut self, evt: EgEvent) {
-        // Avoid duplicate success events.
-        // Retain the most recent.
-        if evt.is_success() {
-            if let Some(pos) = self.events.iter().position(|e| e.is_success()) {
+        // Avoid duplicate SUCCESS events.
+        // Retain the most recent.  NO_CHANGE is tracked separately.
+        if evt.textcode() == "SUCCESS" {
+            if let Some(pos) = self.events.iter().position(|e| e.textcode() == "SUCCESS") {
                 self.events.remove(pos);
             }
         }
@@ -1087,7 +1087,7 @@ impl<'a> Circulator<'a> {
                 None => break,
             };
 
-            if evt.is_success() {
+            if evt.textcode() == "SUCCESS" {
                 success = Some(evt);
                 continue;
             }
@@ -1222,7 +1222,7 @@ impl<'a> Circulator<'a> {
             // Remove any success events to avoid confusion.
             let mut new_events = Vec::new();
             for e in events.drain(..) {
-                if !e.is_success() {
+                if e.textcode() != "SUCCESS" {
                     new_events.push(e);
                 }
             }
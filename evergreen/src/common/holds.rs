@@ -59,6 +59,41 @@ impl TryFrom<&str> for HoldType {
     }
 }
 
+/// Org unit setting: wait interval for copies whose modifier has no
+/// average wait time.
+const DEFAULT_ESTIMATED_WAIT_SETTING: &str = "circ.holds.default_estimated_wait_interval";
+
+/// Org unit setting: minimum estimated hold wait interval.
+const MIN_ESTIMATED_WAIT_SETTING: &str = "circ.holds.min_estimated_wait_interval";
+
+/// Position of a hold within its queue.  See [`hold_queue_stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldQueueStats {
+    queue_position: usize,
+    total_holds: usize,
+    potential_copies: i64,
+    estimated_wait: Option<i64>,
+}
+
+impl HoldQueueStats {
+    /// 1-based position of the hold within the queue.
+    pub fn queue_position(&self) -> usize {
+        self.queue_position
+    }
+    /// Number of holds in the queue.
+    pub fn total_holds(&self) -> usize {
+        self.total_holds
+    }
+    /// Number of copies which could fill the hold.
+    pub fn potential_copies(&self) -> i64 {
+        self.potential_copies
+    }
+    /// Estimated wait in seconds.  None if no copies could fill the hold.
+    pub fn estimated_wait(&self) -> Option<i64> {
+        self.estimated_wait
+    }
+}
+
 /// Just enough hold information to make business decisions.
 pub struct MinimalHold {
     id: i64,
@@ -735,19 +770,7 @@ pub fn record_hold_counts(
     };
 
     if let Some(plib) = pickup_lib_descendant {
-        query["where"]["+ahr"]["pickup_lib"] = eg::hash! {
-            "in": {
-                "select": {
-                    "aou": [{
-                        "column": "id",
-                        "transform": "actor.org_unit_descendants",
-                        "result_field": "id"
-                    }]
-                },
-                "from": "aou",
-                "where": {"id": plib}
-            }
-        }
+        query["where"]["+ahr"]["pickup_lib"] = EgValue::from(org::descendants(editor, plib)?);
     }
 
     let result = editor
@@ -773,3 +796,281 @@ pub fn record_has_holdable_copy(editor: &mut Editor, rec_id: i64, is_meta: bool)
 
     Ok(data[&func].boolish())
 }
+
+/// json_query filter matching open holds of every type the copy could
+/// fill: copy-level holds on the copy, volume holds on its call number,
+/// part holds on its parts, and title and metarecord holds on its
+/// record.
+///
+/// This allows title-level and copy-level holds to be compared within
+/// a single queue.
+pub fn related_hold_types(copy_id: i64, call_number_id: i64, record_id: i64) -> EgValue {
+    eg::hash! {
+        "-or": [
+            {"hold_type": [C::HOLD_TYPE_COPY, C::HOLD_TYPE_RECALL, C::HOLD_TYPE_FORCE], "target": copy_id},
+            {"hold_type": C::HOLD_TYPE_VOLUME, "target": call_number_id},
+            {"hold_type": C::HOLD_TYPE_TITLE, "target": record_id},
+            {"hold_type": C::HOLD_TYPE_MONOPART, "target": {"in": {
+                "select": {"acpm": ["part"]},
+                "from": "acpm",
+                "where": {"target_copy": copy_id}
+            }}},
+            {"hold_type": C::HOLD_TYPE_METARECORD, "target": {"in": {
+                "select": {"mmrsm": ["metarecord"]},
+                "from": "mmrsm",
+                "where": {"source": record_id}
+            }}}
+        ]
+    }
+}
+
+/// Open, unexpired holds which the copy could fill, including the
+/// hold captured for the copy, if any.  Holds captured for other
+/// copies are excluded.
+///
+/// Holds are sorted with the captured hold first, followed by the
+/// remaining holds in the order they would be targeted.
+pub fn holds_for_copy(editor: &mut Editor, copy_id: i64) -> EgResult<Vec<EgValue>> {
+    let flesh = eg::hash! {"flesh": 1, "flesh_fields": {"acp": ["call_number"]}};
+
+    let copy = editor
+        .retrieve_with_ops("acp", copy_id, flesh)?
+        .ok_or_else(|| editor.die_event())?;
+
+    let call_number_id = copy["call_number"].id()?;
+    let record_id = copy["call_number"]["record"].int()?;
+
+    let mut order_by = eg::array! [{"class": "ahr", "field": "capture_time"}];
+    for part in json_query_order_by_targetable().members() {
+        order_by.push(part.clone())?;
+    }
+
+    let query = eg::hash! {
+        "select": {
+            "ahr": [
+                "id",
+                "usr",
+                "hold_type",
+                "target",
+                "current_copy",
+                "capture_time",
+                "pickup_lib",
+                "request_time",
+                "cut_in_line",
+                "selection_depth",
+            ],
+            "pgt": ["hold_priority"]
+        },
+        "from": {
+            "ahr": {
+                "au": {
+                    "field": "id",
+                    "fkey": "usr",
+                    "join": "pgt"
+                }
+            }
+        },
+        "where": {
+            "+ahr": {
+                "cancel_time": EgValue::Null,
+                "fulfillment_time": EgValue::Null,
+                "-and": [
+                    {"-or": [{"capture_time": EgValue::Null}, {"current_copy": copy_id}]},
+                    {"-or": [
+                        {"expire_time": EgValue::Null},
+                        {"expire_time": {">": date::to_iso(&date::now())}}
+                    ]},
+                    related_hold_types(copy_id, call_number_id, record_id),
+                ]
+            }
+        },
+        "order_by": order_by,
+    };
+
+    editor.json_query(query)
+}
+
+/// Queue position, queue length, potential copy count, and estimated
+/// wait time for a hold.
+///
+/// Mirrors open-ils.circ.hold.queue_stats.retrieve.  The queue is the
+/// set of holds sharing at least one potential copy with the hold, or
+/// when the hold has no potential copies, the unfilled holds with the
+/// same hold type and target.
+pub fn hold_queue_stats(editor: &mut Editor, hold_id: i64) -> EgResult<HoldQueueStats> {
+    let hold = editor
+        .retrieve("ahr", hold_id)?
+        .ok_or_else(|| editor.die_event())?;
+
+    // Distinct queries must select the order_by fields.
+    let order_by = eg::array! [
+        {
+            "class": "ahr",
+            "field": "cut_in_line",
+            "transform": "coalesce",
+            "params": [0],
+            "direction": "desc"
+        },
+        {"class": "ahr", "field": "request_time"}
+    ];
+
+    let query = eg::hash! {
+        "select": {"ahr": ["id", "cut_in_line", "request_time"]},
+        "from": "ahr",
+        "where": {
+            "id": {"in": {
+                "select": {"ahcm": ["hold"]},
+                "from": {
+                    "ahcm": {
+                        "ahcm2": {
+                            "class": "ahcm",
+                            "field": "target_copy",
+                            "fkey": "target_copy"
+                        }
+                    }
+                },
+                "where": {"+ahcm2": {"hold": hold_id}},
+                "distinct": 1
+            }}
+        },
+        "order_by": order_by.clone(),
+        "distinct": 1
+    };
+
+    let mut queue = editor.json_query(query)?;
+
+    if queue.is_empty() {
+        // No potential copies.  Compare with like holds.
+        let query = eg::hash! {
+            "select": {"ahr": ["id", "cut_in_line", "request_time"]},
+            "from": "ahr",
+            "where": {
+                "hold_type": hold["hold_type"].clone(),
+                "target": hold["target"].clone(),
+                "capture_time": EgValue::Null,
+                "cancel_time": EgValue::Null,
+                "-or": [
+                    {"expire_time": EgValue::Null},
+                    {"expire_time": {">": "now"}}
+                ]
+            },
+            "order_by": order_by,
+        };
+
+        queue = editor.json_query(query)?;
+    }
+
+    let queue_ids = queue
+        .iter()
+        .map(|h| h.id())
+        .collect::<EgResult<Vec<i64>>>()?;
+
+    let queue_position = queue_position(&queue_ids, hold_id);
+
+    // Potential copy counts per average wait time.
+    let query = eg::hash! {
+        "select": {
+            "acp": [{"column": "id", "transform": "count", "aggregate": 1, "alias": "count"}],
+            "ccm": ["avg_wait_time"]
+        },
+        "from": {"ahcm": {"acp": {"join": {"ccm": {"type": "left"}}}}},
+        "where": {"+ahcm": {"hold": hold_id}}
+    };
+
+    let wait_data = editor.json_query(query)?;
+
+    let home_ou = editor
+        .retrieve("au", hold["usr"].clone())?
+        .ok_or_else(|| editor.die_event())?["home_ou"]
+        .int()?;
+
+    let mut settings = Settings::new(editor);
+
+    let default_wait = settings
+        .get_value_at_org(DEFAULT_ESTIMATED_WAIT_SETTING, home_ou)?
+        .as_str()
+        .unwrap_or("0 seconds")
+        .to_string();
+
+    let min_wait = match settings
+        .get_value_at_org(MIN_ESTIMATED_WAIT_SETTING, home_ou)?
+        .as_str()
+    {
+        Some(i) => date::interval_to_seconds(i)?,
+        None => 0,
+    };
+
+    let mut copy_waits = Vec::new();
+    for row in wait_data.iter() {
+        let wait = row["avg_wait_time"].as_str().unwrap_or(&default_wait);
+        copy_waits.push((row["count"].int()?, date::interval_to_seconds(wait)?));
+    }
+
+    Ok(HoldQueueStats {
+        queue_position,
+        total_holds: queue.len(),
+        potential_copies: copy_waits.iter().map(|(count, _)| count).sum(),
+        estimated_wait: estimated_wait(queue_position, &copy_waits, min_wait),
+    })
+}
+
+/// 1-based position of the hold in the queue.  Holds missing from
+/// the queue are positioned at the end.
+fn queue_position(queue_ids: &[i64], hold_id: i64) -> usize {
+    queue_ids
+        .iter()
+        .position(|id| *id == hold_id)
+        .unwrap_or(queue_ids.len())
+        + 1
+}
+
+/// Estimated hold wait in seconds: the average wait time across the
+/// potential copies, divided by the number of potential copies, times
+/// the queue position.
+///
+/// Each copy wait is a (copy count, average wait seconds) pair.
+fn estimated_wait(queue_position: usize, copy_waits: &[(i64, i64)], min_wait: i64) -> Option<i64> {
+    let copies: i64 = copy_waits.iter().map(|(count, _)| count).sum();
+
+    if copies == 0 {
+        return None;
+    }
+
+    let combined: f64 = copy_waits
+        .iter()
+        .map(|(count, secs)| (count * secs) as f64)
+        .sum();
+
+    let avg_wait = combined / copies as f64;
+    let wait = queue_position as f64 * (avg_wait / copies as f64);
+
+    Some((wait as i64).max(min_wait))
+}
+
+#[test]
+fn test_queue_position() {
+    assert_eq!(queue_position(&[4, 9, 2], 4), 1);
+    assert_eq!(queue_position(&[4, 9, 2], 2), 3);
+    // Not in the queue, e.g. a new hold with no copy maps.
+    assert_eq!(queue_position(&[4, 9, 2], 7), 4);
+    assert_eq!(queue_position(&[], 7), 1);
+}
+
+#[test]
+fn test_estimated_wait() {
+    let day = 86400;
+
+    assert_eq!(estimated_wait(1, &[], 0), None);
+
+    // 2 copies averaging 10 days => 5 days per queue position.
+    assert_eq!(estimated_wait(3, &[(2, 10 * day)], 0), Some(15 * day));
+
+    // Mixed wait times: (1 * 4 + 3 * 8) / 4 = 7 day average over 4 copies.
+    assert_eq!(
+        estimated_wait(2, &[(1, 4 * day), (3, 8 * day)], 0),
+        Some(7 * day / 2)
+    );
+
+    // Minimum wait applies.
+    assert_eq!(estimated_wait(1, &[(2, 10 * day)], 6 * day), Some(6 * day));
+}
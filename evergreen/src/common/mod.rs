@@ -1,6 +1,7 @@
 //! Shared, common utility functions
 
 pub mod auth;
+pub mod batch;
 pub mod bib;
 pub mod billing;
 pub mod checkin;
@@ -13,6 +14,7 @@ pub mod jq;
 pub mod noncat;
 pub mod org;
 pub mod penalty;
+pub mod query;
 pub mod renew;
 pub mod settings;
 pub mod targeter;
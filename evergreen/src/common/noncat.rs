@@ -5,7 +5,6 @@ use eg::date;
 use eg::editor::Editor;
 use eg::result::EgResult;
 use eg::EgValue;
-use std::time::Duration;
 
 /// Create X number of non-cat checkouts.
 ///
@@ -69,10 +68,7 @@ pub fn noncat_due_date(editor: &mut Editor, noncat: &EgValue) -> EgResult<String
         .ok_or(format!("Invalid noncat circ_time: {}", noncat["circ_time"]))?;
 
     let duedate = date::parse_datetime(&checkout_time)?;
-    let duedate = date::set_timezone(duedate, timezone)?;
-
-    let seconds = date::interval_to_seconds(&duration)?;
-    let mut duedate = duedate + Duration::from_secs(seconds as u64);
+    let mut duedate = date::add_interval_tz(duedate, &duration, timezone)?;
 
     let org_open_data = org::next_open_date(editor, circ_lib, &duedate.into())?;
 
@@ -1,10 +1,220 @@
 use crate as eg;
 use chrono::prelude::Datelike;
 use chrono::Duration;
+use eg::common::settings::Settings;
 use eg::date;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// The org unit tree, loaded once per process on first use.
+///
+/// The tree is swapped as a whole on reload, so callers holding a
+/// copy of the Arc are not affected by a reload.
+static ORG_TREE: RwLock<Option<Arc<OrgTree>>> = RwLock::new(None);
+
+/// The parts of an org unit needed to navigate the org tree.
+#[derive(Debug, Clone)]
+pub struct OrgNode {
+    id: i64,
+    parent: Option<i64>,
+    depth: i64,
+    shortname: String,
+    name: String,
+    children: Vec<i64>,
+}
+
+impl OrgNode {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+    pub fn parent(&self) -> Option<i64> {
+        self.parent
+    }
+    pub fn depth(&self) -> i64 {
+        self.depth
+    }
+    pub fn shortname(&self) -> &str {
+        &self.shortname
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn children(&self) -> &[i64] {
+        &self.children
+    }
+}
+
+/// In-memory copy of the org unit tree.
+///
+/// Lookups which only need the shape of the tree are answered
+/// without a trip to the database.
+#[derive(Debug, Default)]
+pub struct OrgTree {
+    nodes: HashMap<i64, OrgNode>,
+    shortnames: HashMap<String, i64>,
+}
+
+impl OrgTree {
+    /// Build a tree from a list of org units fleshed with their ou_type.
+    pub fn from_org_units(org_units: &[EgValue]) -> EgResult<OrgTree> {
+        let mut tree = OrgTree::default();
+
+        for org in org_units {
+            let node = OrgNode {
+                id: org.id()?,
+                parent: org["parent_ou"].as_int(),
+                depth: org["ou_type"]["depth"].int()?,
+                shortname: org["shortname"].string()?,
+                name: org["name"].string()?,
+                children: Vec::new(),
+            };
+
+            tree.shortnames.insert(node.shortname.clone(), node.id);
+            tree.nodes.insert(node.id, node);
+        }
+
+        let mut links: Vec<(i64, i64)> = tree
+            .nodes
+            .values()
+            .filter_map(|n| n.parent.map(|p| (p, n.id)))
+            .collect();
+
+        // Keep child lists in a predictable order.
+        links.sort();
+
+        for (parent, child) in links {
+            match tree.nodes.get_mut(&parent) {
+                Some(p) => p.children.push(child),
+                None => return Err(format!("Org unit {child} has unknown parent {parent}").into()),
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// Load the org units from the database.
+    pub fn load(editor: &mut Editor) -> EgResult<OrgTree> {
+        let query = eg::hash! {"id": {"!=": EgValue::Null}};
+        let flesh = eg::hash! {"flesh": 1, "flesh_fields": {"aou": ["ou_type"]}};
+
+        let org_units = editor.search_with_ops("aou", query, flesh)?;
+
+        OrgTree::from_org_units(&org_units)
+    }
+
+    pub fn get(&self, org_id: i64) -> Option<&OrgNode> {
+        self.nodes.get(&org_id)
+    }
+
+    fn node(&self, org_id: i64) -> EgResult<&OrgNode> {
+        self.get(org_id)
+            .ok_or_else(|| format!("No such org unit: {org_id}").into())
+    }
+
+    /// IDs of the org unit and its ancestors, from the root of the
+    /// tree down to the org unit itself.
+    pub fn ancestors(&self, org_id: i64) -> EgResult<Vec<i64>> {
+        let mut ids = Vec::new();
+        let mut node = self.node(org_id)?;
+
+        loop {
+            ids.push(node.id);
+
+            match node.parent {
+                Some(p) => node = self.node(p)?,
+                None => break,
+            }
+
+            if ids.len() > self.nodes.len() {
+                return Err(format!("Org unit {org_id} has a cycle in its ancestry").into());
+            }
+        }
+
+        ids.reverse();
+
+        Ok(ids)
+    }
+
+    /// IDs of the org unit and all of its descendants, parents
+    /// before children.
+    pub fn descendants(&self, org_id: i64) -> EgResult<Vec<i64>> {
+        let mut ids = vec![self.node(org_id)?.id];
+        let mut idx = 0;
+
+        while idx < ids.len() {
+            if let Some(node) = self.get(ids[idx]) {
+                ids.extend(node.children.iter());
+            }
+            idx += 1;
+        }
+
+        Ok(ids)
+    }
+
+    /// The org unit's ancestor at the requested depth, which is the
+    /// org unit itself when it lives at that depth.
+    ///
+    /// Returns None if the org unit lives above the requested depth.
+    pub fn ancestor_at_depth(&self, org_id: i64, depth: i64) -> EgResult<Option<i64>> {
+        for id in self.ancestors(org_id)? {
+            if self.node(id)?.depth == depth {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// True if `org_id` is `ancestor_id` or lives beneath it.
+    pub fn is_descendant(&self, org_id: i64, ancestor_id: i64) -> EgResult<bool> {
+        Ok(self.ancestors(org_id)?.contains(&ancestor_id))
+    }
+
+    pub fn id_for_shortname(&self, shortname: &str) -> Option<i64> {
+        self.shortnames.get(shortname).copied()
+    }
+
+    pub fn shortname(&self, org_id: i64) -> Option<&str> {
+        self.get(org_id).map(|n| n.shortname())
+    }
+}
+
+/// Returns the cached org tree, loading it first if needed.
+pub fn tree(editor: &mut Editor) -> EgResult<Arc<OrgTree>> {
+    if let Some(tree) = cached_tree()? {
+        return Ok(tree);
+    }
+
+    reload_tree(editor)
+}
+
+/// Returns the cached org tree if it has been loaded.
+pub fn cached_tree() -> EgResult<Option<Arc<OrgTree>>> {
+    let lock = ORG_TREE
+        .read()
+        .map_err(|e| format!("Cannot read org tree: {e}"))?;
+
+    Ok(lock.clone())
+}
+
+/// Load the org tree from the database, replacing any cached tree.
+///
+/// Use after changes to the org unit hierarchy.
+pub fn reload_tree(editor: &mut Editor) -> EgResult<Arc<OrgTree>> {
+    let tree = Arc::new(OrgTree::load(editor)?);
+
+    let mut lock = ORG_TREE
+        .write()
+        .map_err(|e| format!("Cannot update org tree: {e}"))?;
+
+    *lock = Some(tree.clone());
+
+    log::debug!("Loaded org tree with {} org units", tree.nodes.len());
+
+    Ok(tree)
+}
 
 /// Apply a variety of DB transforms to an org unit and return
 /// the calculated org unit IDs.
@@ -48,12 +258,46 @@ pub fn by_shortname(editor: &mut Editor, sn: &str) -> EgResult<EgValue> {
     }
 }
 
+/// ID of the org unit with the provided shortname, using the cached org tree.
+pub fn id_for_shortname(editor: &mut Editor, sn: &str) -> EgResult<i64> {
+    tree(editor)?
+        .id_for_shortname(sn)
+        .ok_or_else(|| format!("No such org unit: {sn}").into())
+}
+
+/// Shortname of the org unit, using the cached org tree.
+pub fn shortname(editor: &mut Editor, org_id: i64) -> EgResult<String> {
+    tree(editor)?
+        .shortname(org_id)
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("No such org unit: {org_id}").into())
+}
+
+/// IDs of the org unit and its ancestors, root first, using the
+/// cached org tree.
 pub fn ancestors(editor: &mut Editor, org_id: i64) -> EgResult<Vec<i64>> {
-    org_relations_query(editor, org_id, "actor.org_unit_ancestors", None)
+    tree(editor)?.ancestors(org_id)
 }
 
+/// IDs of the org unit and its descendants, using the cached org tree.
 pub fn descendants(editor: &mut Editor, org_id: i64) -> EgResult<Vec<i64>> {
-    org_relations_query(editor, org_id, "actor.org_unit_descendants", None)
+    tree(editor)?.descendants(org_id)
+}
+
+/// The org unit's ancestor at the requested depth, using the cached
+/// org tree.
+pub fn ancestor_at_depth(editor: &mut Editor, org_id: i64, depth: i64) -> EgResult<Option<i64>> {
+    tree(editor)?.ancestor_at_depth(org_id, depth)
+}
+
+/// Value of an org unit setting, falling back to the org unit's
+/// ancestors when the setting is not applied at the org unit itself.
+///
+/// Returns EgValue::Null when the setting is not set anywhere in the
+/// org unit's ancestry.
+pub fn org_unit_setting(editor: &mut Editor, org_id: i64, name: &str) -> EgResult<EgValue> {
+    let mut settings = Settings::new(editor);
+    settings.get_value_at_org(name, org_id).cloned()
 }
 
 pub fn full_path(editor: &mut Editor, org_id: i64, depth: Option<i64>) -> EgResult<Vec<i64>> {
@@ -178,3 +422,49 @@ pub fn proximity(editor: &mut Editor, from_org: i64, to_org: i64) -> EgResult<Op
         Ok(None)
     }
 }
+
+#[test]
+fn test_org_tree() {
+    let org = |id: i64, parent: Option<i64>, depth: i64, sn: &str| {
+        eg::hash! {
+            "id": id,
+            "parent_ou": parent,
+            "shortname": sn,
+            "name": format!("Org {sn}"),
+            "ou_type": {"depth": depth},
+        }
+    };
+
+    // Modeled on the sample org tree.
+    let orgs = vec![
+        org(1, None, 0, "CONS"),
+        org(2, Some(1), 1, "SYS1"),
+        org(3, Some(1), 1, "SYS2"),
+        org(4, Some(2), 2, "BR1"),
+        org(5, Some(2), 2, "BR2"),
+        org(6, Some(3), 2, "BR3"),
+        org(7, Some(4), 3, "SL1"),
+    ];
+
+    let tree = OrgTree::from_org_units(&orgs).unwrap();
+
+    assert_eq!(tree.ancestors(7).unwrap(), vec![1, 2, 4, 7]);
+    assert_eq!(tree.ancestors(1).unwrap(), vec![1]);
+    assert_eq!(tree.descendants(2).unwrap(), vec![2, 4, 5, 7]);
+    assert_eq!(tree.descendants(6).unwrap(), vec![6]);
+    assert_eq!(tree.descendants(1).unwrap().len(), 7);
+
+    assert_eq!(tree.ancestor_at_depth(7, 1).unwrap(), Some(2));
+    assert_eq!(tree.ancestor_at_depth(4, 2).unwrap(), Some(4));
+    assert_eq!(tree.ancestor_at_depth(2, 2).unwrap(), None);
+
+    assert!(tree.is_descendant(7, 2).unwrap());
+    assert!(!tree.is_descendant(6, 2).unwrap());
+
+    assert_eq!(tree.id_for_shortname("BR2"), Some(5));
+    assert_eq!(tree.shortname(6), Some("BR3"));
+    assert_eq!(tree.id_for_shortname("NOPE"), None);
+
+    assert!(tree.ancestors(99).is_err());
+    assert!(OrgTree::from_org_units(&[org(8, Some(99), 1, "LOST")]).is_err());
+}
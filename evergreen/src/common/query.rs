@@ -0,0 +1,260 @@
+//! Builder for JSON queries passed to Editor::json_query().
+//!
+//! Covers the common cases: selecting fields and aggregates from a
+//! base class, joining to other classes, filtering, sorting, and
+//! paging.  Anything more exotic can be added to the built query
+//! directly.
+//!
+//! ```no_run
+//! use evergreen as eg;
+//! use eg::common::query::JsonQuery;
+//!
+//! let query = JsonQuery::new("ahr")
+//!     .count("ahr", "id", "count")
+//!     .filter("current_copy", 1)
+//!     .filter("cancel_time", eg::NULL)
+//!     .build();
+//! ```
+use crate as eg;
+use eg::EgValue;
+
+#[derive(Debug, Clone)]
+pub struct JsonQuery {
+    /// Base class, i.e. the FROM table.
+    class: String,
+
+    /// Selected fields, keyed on class name.
+    select: EgValue,
+
+    /// Joins from the base class, keyed on class name.
+    joins: EgValue,
+
+    /// WHERE clause.
+    filter: EgValue,
+
+    order_by: Vec<EgValue>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    distinct: bool,
+}
+
+impl JsonQuery {
+    pub fn new(class: &str) -> JsonQuery {
+        JsonQuery {
+            class: class.to_string(),
+            select: EgValue::new_object(),
+            joins: EgValue::new_object(),
+            filter: EgValue::new_object(),
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+            distinct: false,
+        }
+    }
+
+    pub fn class(&self) -> &str {
+        &self.class
+    }
+
+    /// Add a field to the SELECT list.
+    pub fn select(self, class: &str, field: &str) -> Self {
+        self.select_value(class, EgValue::from(field))
+    }
+
+    /// Add multiple fields from the same class to the SELECT list.
+    pub fn select_fields(mut self, class: &str, fields: &[&str]) -> Self {
+        for field in fields {
+            self = self.select(class, field);
+        }
+        self
+    }
+
+    /// Select an aggregate function applied to a field, e.g. "count"
+    /// or "sum", returned under the provided alias.
+    ///
+    /// Non-aggregate fields in the SELECT list are added to the
+    /// GROUP BY clause.
+    pub fn aggregate(self, class: &str, field: &str, transform: &str, alias: &str) -> Self {
+        let column = eg::hash! {
+            column: field,
+            transform: transform,
+            alias: alias,
+            aggregate: true,
+        };
+        self.select_value(class, column)
+    }
+
+    /// Shortcut for a "count" aggregate.
+    pub fn count(self, class: &str, field: &str, alias: &str) -> Self {
+        self.aggregate(class, field, "count", alias)
+    }
+
+    fn select_value(mut self, class: &str, value: EgValue) -> Self {
+        if !self.select.has_key(class) {
+            self.select[class] = EgValue::new_array();
+        }
+        // Pushing onto an array cannot fail.
+        self.select[class].push(value).ok();
+        self
+    }
+
+    /// Inner join a class linked to the base class.
+    ///
+    /// fkey and field may be None if the IDL describes the link.
+    pub fn join(self, class: &str, fkey: Option<&str>, field: Option<&str>) -> Self {
+        self.add_join(class, None, fkey, field)
+    }
+
+    /// Left join a class linked to the base class.
+    pub fn left_join(self, class: &str, fkey: Option<&str>, field: Option<&str>) -> Self {
+        self.add_join(class, Some("left"), fkey, field)
+    }
+
+    /// Join using a raw join definition, e.g. for nested joins or
+    /// join filters.
+    pub fn join_with(mut self, class: &str, def: EgValue) -> Self {
+        self.joins[class] = def;
+        self
+    }
+
+    fn add_join(
+        self,
+        class: &str,
+        join_type: Option<&str>,
+        fkey: Option<&str>,
+        field: Option<&str>,
+    ) -> Self {
+        let mut def = EgValue::new_object();
+
+        if let Some(t) = join_type {
+            def["type"] = EgValue::from(t);
+        }
+        if let Some(f) = fkey {
+            def["fkey"] = EgValue::from(f);
+        }
+        if let Some(f) = field {
+            def["field"] = EgValue::from(f);
+        }
+
+        self.join_with(class, def)
+    }
+
+    /// Filter on a field of the base class.
+    ///
+    /// The value may be a scalar, EgValue::Null for IS NULL, or any
+    /// JSON query operator hash, e.g. {"!=": null}.
+    pub fn filter(mut self, field: &str, value: impl Into<EgValue>) -> Self {
+        self.filter[field] = value.into();
+        self
+    }
+
+    /// Filter on a field of a joined class.
+    pub fn filter_class(mut self, class: &str, field: &str, value: impl Into<EgValue>) -> Self {
+        let key = format!("+{class}");
+        if !self.filter.has_key(&key) {
+            self.filter[key.as_str()] = EgValue::new_object();
+        }
+        self.filter[key.as_str()][field] = value.into();
+        self
+    }
+
+    pub fn order_by(mut self, class: &str, field: &str, descending: bool) -> Self {
+        let mut order = eg::hash! {class: class, field: field};
+        if descending {
+            order["direction"] = EgValue::from("desc");
+        }
+        self.order_by.push(order);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    /// Compile the query into its JSON form.
+    pub fn build(&self) -> EgValue {
+        let mut query = EgValue::new_object();
+
+        if !self.select.is_empty() {
+            query["select"] = self.select.clone();
+        }
+
+        if self.joins.is_empty() {
+            query["from"] = EgValue::from(self.class.as_str());
+        } else {
+            query["from"] = EgValue::new_object();
+            query["from"][self.class.as_str()] = self.joins.clone();
+        }
+
+        if !self.filter.is_empty() {
+            query["where"] = self.filter.clone();
+        }
+
+        if !self.order_by.is_empty() {
+            query["order_by"] = EgValue::from(self.order_by.clone());
+        }
+
+        if let Some(l) = self.limit {
+            query["limit"] = EgValue::from(l);
+        }
+
+        if let Some(o) = self.offset {
+            query["offset"] = EgValue::from(o);
+        }
+
+        if self.distinct {
+            query["distinct"] = EgValue::from(true);
+        }
+
+        query
+    }
+}
+
+impl From<JsonQuery> for EgValue {
+    fn from(query: JsonQuery) -> EgValue {
+        query.build()
+    }
+}
+
+#[test]
+fn json_query_builder() {
+    let query = JsonQuery::new("acp")
+        .select_fields("acp", &["id", "barcode"])
+        .count("ahr", "id", "hold_count")
+        .left_join("ahr", Some("id"), Some("current_copy"))
+        .filter("deleted", "f")
+        .filter_class("ahr", "cancel_time", EgValue::Null)
+        .order_by("acp", "barcode", true)
+        .limit(10)
+        .build();
+
+    assert_eq!(query["select"]["acp"][1].as_str(), Some("barcode"));
+    assert_eq!(
+        query["select"]["ahr"][0]["transform"].as_str(),
+        Some("count")
+    );
+    assert!(query["select"]["ahr"][0]["aggregate"].boolish());
+    assert_eq!(query["from"]["acp"]["ahr"]["type"].as_str(), Some("left"));
+    assert_eq!(query["from"]["acp"]["ahr"]["fkey"].as_str(), Some("id"));
+    assert_eq!(query["where"]["deleted"].as_str(), Some("f"));
+    assert!(query["where"]["+ahr"]["cancel_time"].is_null());
+    assert!(query["where"]["+ahr"].has_key("cancel_time"));
+    assert_eq!(query["order_by"][0]["direction"].as_str(), Some("desc"));
+    assert_eq!(query["limit"].as_usize(), Some(10));
+    assert!(query["offset"].is_null());
+
+    let query = JsonQuery::new("au").build();
+    assert_eq!(query["from"].as_str(), Some("au"));
+    assert!(!query.has_key("select"));
+}
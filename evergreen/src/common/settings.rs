@@ -1,7 +1,9 @@
 //! General purpose org / workstation / user setting fetcher and cache.
 //! Primarily uses the 'actor.get_cascade_setting()' DB function.
 use crate as eg;
+use eg::osrf::app::WorkerCache;
 use eg::{Editor, EgResult, EgValue};
+use json::JsonValue;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fmt;
@@ -341,3 +343,119 @@ impl Settings {
         Ok(())
     }
 }
+
+/// Org unit setting type definitions, cached for view permission checks.
+///
+/// Definitions rarely change, so long-lived callers (e.g. service
+/// workers) may store them in a WorkerCache which outlives any one
+/// request.
+pub struct OrgSettingTypes<'a> {
+    /// Setting name => view permission code, or NULL if unprotected.
+    cache: &'a mut WorkerCache,
+}
+
+impl<'a> OrgSettingTypes<'a> {
+    pub fn new(cache: &'a mut WorkerCache) -> Self {
+        OrgSettingTypes { cache }
+    }
+
+    fn cache_key(name: &str) -> String {
+        format!("coust.view_perm.{name}")
+    }
+
+    /// Fetch definitions for any of the named settings we have not
+    /// already cached.
+    ///
+    /// Unknown setting names are cached as unprotected.
+    pub fn load(&mut self, editor: &mut Editor, names: &[&str]) -> EgResult<()> {
+        let names: Vec<&str> = names
+            .iter()
+            .filter(|n| !self.cache.contains(&Self::cache_key(n)))
+            .copied()
+            .collect();
+
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        let ops = eg::hash! {flesh: 1, flesh_fields: {coust: ["view_perm"]}};
+        let types = editor.search_with_ops("coust", eg::hash! {name: names.clone()}, ops)?;
+
+        for name in names {
+            self.cache.set(&Self::cache_key(name), JsonValue::Null);
+        }
+
+        for stype in types {
+            let perm = match stype["view_perm"]["code"].as_str() {
+                Some(code) => JsonValue::from(code),
+                None => JsonValue::Null,
+            };
+            self.cache.set(&Self::cache_key(stype["name"].str()?), perm);
+        }
+
+        Ok(())
+    }
+
+    /// Permission required to view values for the setting, if any.
+    pub fn view_perm(&mut self, name: &str) -> Option<&str> {
+        self.cache
+            .get(&Self::cache_key(name))
+            .and_then(|p| p.as_str())
+    }
+
+    /// True if the requestor linked to the editor may view values
+    /// for the setting at the provided org unit.
+    ///
+    /// Protected settings require a requestor.  On permission failure,
+    /// the editor's last event is set.
+    pub fn can_view(&mut self, editor: &mut Editor, name: &str, org_id: i64) -> EgResult<bool> {
+        match self.view_perm(name).map(|p| p.to_string()) {
+            Some(perm) => editor.allowed_at(&perm, org_id),
+            None => Ok(true),
+        }
+    }
+}
+
+/// Returns a map of setting name => {org, value} for each of the
+/// named settings, using the value found at the org unit or its
+/// nearest ancestor.
+///
+/// Values are JSON-parsed.  Settings with no value at the org unit
+/// or any of its ancestors map to NULL.
+pub fn ancestor_values(editor: &mut Editor, org_id: i64, names: &[&str]) -> EgResult<EgValue> {
+    let mut map = EgValue::new_object();
+
+    if names.is_empty() {
+        return Ok(map);
+    }
+
+    let reg = Regex::new(SETTING_NAME_REGEX).unwrap();
+
+    for name in names {
+        if reg.is_match(name) {
+            return Err(format!("Invalid setting name: {name}").into());
+        }
+        map[*name] = EgValue::Null;
+    }
+
+    let query = eg::hash! {
+        from: [
+            "actor.org_unit_ancestor_setting_batch",
+            org_id, format!("{{{}}}", names.join(","))
+        ]
+    };
+
+    for setting in editor.json_query(query)? {
+        let value = match setting["value"].as_str() {
+            Some(v) => EgValue::parse(v).map_err(|e| format!("Cannot parse setting value: {e}"))?,
+            None => EgValue::Null,
+        };
+
+        map[setting["name"].str()?] = eg::hash! {
+            org: setting["org_unit"].int()?,
+            value: value,
+        };
+    }
+
+    Ok(map)
+}
@@ -0,0 +1,164 @@
+//! A/T Cleanup handlers
+//!
+//! Event definitions may name a cleanup to run after a successful
+//! reaction (cleanup_success) and one to run after a failed reaction
+//! (cleanup_failure).
+use crate as eg;
+use eg::common::trigger::{Event, EventState, Processor};
+use eg::EgResult;
+use eg::EgValue;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Routine which cleans up after a reaction on behalf of a Processor.
+pub type CleanupHandler =
+    Arc<dyn Fn(&mut Processor, &mut [&mut Event]) -> EgResult<()> + Send + Sync>;
+
+/// Cleanups by name, shared by all threads.
+static CLEANUPS: OnceLock<RwLock<HashMap<String, CleanupHandler>>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<String, CleanupHandler>> {
+    CLEANUPS.get_or_init(|| {
+        let mut map: HashMap<String, CleanupHandler> = HashMap::new();
+
+        map.insert("NOOP_True".to_string(), Arc::new(|_, _| Ok(())));
+        map.insert(
+            "NOOP_False".to_string(),
+            Arc::new(|_, _| Err("NOOP_False".into())),
+        );
+        map.insert(
+            "ClearAllPending".to_string(),
+            Arc::new(|p, events| p.clear_all_pending(events)),
+        );
+        map.insert(
+            "CreateHoldNotification".to_string(),
+            Arc::new(|p, events| p.create_hold_notification(events)),
+        );
+
+        RwLock::new(map)
+    })
+}
+
+/// Register a cleanup by name, making it available to all Processors
+/// whose event definition uses the name as a cleanup.
+///
+/// Returns an Err if a cleanup, including one of the built-in
+/// cleanups, is already registered under the same name.
+pub fn register_cleanup<F>(name: &str, handler: F) -> EgResult<()>
+where
+    F: Fn(&mut Processor, &mut [&mut Event]) -> EgResult<()> + Send + Sync + 'static,
+{
+    let mut cleanups = registry()
+        .write()
+        .map_err(|e| format!("Cleanup registry is poisoned: {e}"))?;
+
+    if cleanups.contains_key(name) {
+        return Err(format!("Cleanup '{name}' is already registered").into());
+    }
+
+    cleanups.insert(name.to_string(), Arc::new(handler));
+
+    Ok(())
+}
+
+/// Returns the cleanup registered under the provided name.
+pub fn lookup_cleanup(name: &str) -> Option<CleanupHandler> {
+    registry().read().ok()?.get(name).cloned()
+}
+
+/// Add cleanup routines to the Processor.
+impl Processor<'_> {
+    pub fn cleanup_success(&self) -> Option<&str> {
+        self.event_def()["cleanup_success"].as_str()
+    }
+    pub fn cleanup_failure(&self) -> Option<&str> {
+        self.event_def()["cleanup_failure"].as_str()
+    }
+
+    /// Run the success or failure cleanup for our event definition,
+    /// if it has one.
+    pub fn cleanup(&mut self, events: &mut [&mut Event], success: bool) -> EgResult<()> {
+        let name = if success {
+            self.cleanup_success()
+        } else {
+            self.cleanup_failure()
+        };
+
+        let name = match name {
+            Some(n) => n.to_string(),
+            None => return Ok(()),
+        };
+
+        log::debug!(
+            "{self} cleaning up with '{name}' on {} event(s)",
+            events.len()
+        );
+
+        match lookup_cleanup(&name) {
+            Some(handler) => handler(self, events),
+            None => Err(format!("No such cleanup: {name}").into()),
+        }
+    }
+
+    /// Mark any other pending events for the same definition and
+    /// target as complete, so a target is only reacted to once.
+    pub fn clear_all_pending(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        let targets: Vec<EgValue> = events.iter().map(|e| e.target_pkey().clone()).collect();
+
+        let query = eg::hash! {
+            "event_def": self.event_def_id(),
+            "target": targets,
+            "state": <&str>::from(EventState::Pending),
+        };
+
+        self.editor.xact_begin()?;
+
+        for mut atev in self.editor.search("atev", query)? {
+            log::info!("{self} clearing pending event {}", atev.id()?);
+
+            atev["state"] = EgValue::from(<&str>::from(EventState::Complete));
+            atev["complete_time"] = EgValue::from("now");
+
+            self.editor.update(atev)?;
+        }
+
+        self.editor.commit()
+    }
+
+    /// Record a hold notification for each target hold.
+    pub fn create_hold_notification(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        if self.core_type() != "ahr" {
+            return Err(format!("{self} hold notifications require hold targets").into());
+        }
+
+        let method = self.reactor().to_string();
+        let note = format!("Notification by event definition {}", self.event_def_id());
+
+        self.editor.xact_begin()?;
+
+        for event in events.iter() {
+            let notification = eg::hash! {
+                "hold": event.target_pkey().clone(),
+                "method": method.as_str(),
+                "note": note.as_str(),
+            };
+
+            self.editor.create(EgValue::create("ahn", notification)?)?;
+        }
+
+        self.editor.commit()
+    }
+}
+
+#[test]
+fn register_cleanup_rejects_duplicates() {
+    assert!(lookup_cleanup("NOOP_True").is_some());
+    assert!(lookup_cleanup("ClearAllPending").is_some());
+    assert!(lookup_cleanup("Test::NoSuchCleanup").is_none());
+
+    register_cleanup("Test::Registered", |_, _| Ok(())).expect("Registered");
+    assert!(lookup_cleanup("Test::Registered").is_some());
+
+    assert!(register_cleanup("Test::Registered", |_, _| Ok(())).is_err());
+    assert!(register_cleanup("NOOP_False", |_, _| Ok(())).is_err());
+}
@@ -56,6 +56,9 @@ impl From<EventState> for &'static str {
     }
 }
 
+/// Key in an event's user data where reaction attempts are counted.
+pub const ATTEMPTS_KEY: &str = "_attempts";
+
 pub struct Event {
     id: i64,
     event_def: i64,
@@ -129,6 +132,33 @@ impl Event {
     pub fn user_data(&self) -> Option<&EgValue> {
         self.user_data.as_ref()
     }
+
+    /// Number of times we've attempted to react to this event.
+    ///
+    /// Only counted for event definitions which allow retries.
+    pub fn attempts(&self) -> i64 {
+        self.user_data
+            .as_ref()
+            .and_then(|d| d[ATTEMPTS_KEY].as_int())
+            .unwrap_or(0)
+    }
+
+    /// Add one to the attempt count stored in the user data.
+    ///
+    /// Returns false if the user data is not a hash and cannot hold
+    /// the count.
+    pub fn increment_attempts(&mut self) -> bool {
+        let attempts = self.attempts();
+        let data = self.user_data.get_or_insert_with(EgValue::new_object);
+
+        if !data.is_hash() {
+            return false;
+        }
+
+        data[ATTEMPTS_KEY] = EgValue::from(attempts + 1);
+
+        true
+    }
     pub fn group_value(&self) -> Option<&EgValue> {
         self.group_value.as_ref()
     }
@@ -136,3 +166,33 @@ impl Event {
         self.group_value = Some(value);
     }
 }
+
+#[test]
+fn test_event_attempts() {
+    let source = eg::hash! {"id": 1, "event_def": 2, "state": "pending", "target": 3};
+    let mut event = Event::from_source(source).unwrap();
+
+    assert_eq!(event.attempts(), 0);
+    assert!(event.increment_attempts());
+    assert!(event.increment_attempts());
+    assert_eq!(event.attempts(), 2);
+
+    // Existing user data is retained.
+    let source = eg::hash! {
+        "id": 1, "event_def": 2, "state": "pending", "target": 3,
+        "user_data": r#"{"copy": 4}"#,
+    };
+    let mut event = Event::from_source(source).unwrap();
+    assert!(event.increment_attempts());
+    assert_eq!(event.user_data().unwrap()["copy"].as_int(), Some(4));
+    assert_eq!(event.attempts(), 1);
+
+    // Non-hash user data cannot hold a count.
+    let source = eg::hash! {
+        "id": 1, "event_def": 2, "state": "pending", "target": 3,
+        "user_data": "[1, 2]",
+    };
+    let mut event = Event::from_source(source).unwrap();
+    assert!(!event.increment_attempts());
+    assert_eq!(event.attempts(), 0);
+}
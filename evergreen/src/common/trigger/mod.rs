@@ -2,16 +2,22 @@
 use crate as eg;
 use eg::common::org;
 use eg::date;
-use eg::idl;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
 
+pub mod cleanup;
+pub use cleanup::{register_cleanup, CleanupHandler};
 pub mod event;
-pub use event::{Event, EventState};
+pub use event::{Event, EventState, ATTEMPTS_KEY};
+pub mod passive;
+pub use passive::{create_passive_events, create_passive_events_for_def};
 pub mod processor;
 pub use processor::Processor;
-mod reactor;
+pub mod reactor;
+pub use reactor::{register_reactor, ReactorHandler};
+pub mod runner;
+pub mod template;
 mod validator;
 
 /// Create A/T events for an object and A/T hook.
@@ -64,6 +70,203 @@ pub fn create_events_for_object(
     Ok(())
 }
 
+/// Events created by [`create_events_for_hook`] for a single event
+/// definition.
+pub struct HookEvents {
+    event_def: i64,
+    grouped: bool,
+    events: Vec<Event>,
+    /// IDs of events whose run time had passed at creation.
+    due: Vec<i64>,
+}
+
+impl HookEvents {
+    pub fn event_def(&self) -> i64 {
+        self.event_def
+    }
+    /// True if the event definition has a group field.
+    pub fn grouped(&self) -> bool {
+        self.grouped
+    }
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+/// Create A/T events for one or more targets and an A/T hook,
+/// grouped by event definition.
+///
+/// This is the equivalent of create_events_for_hook in the Perl A/T
+/// code.  Multiple targets of the same class may be passed as an array.
+///
+/// Only active definitions owned by the org unit or its ancestors
+/// are used.  When a granularity is provided, only definitions with
+/// the same granularity are used.
+///
+/// Events are created in the caller's transaction when one is open,
+/// otherwise within a transaction of our own.
+///
+/// If `wait` is true, events which are due to run now are processed
+/// before returning, which requires that the events are committed,
+/// i.e. that the caller has no open transaction.
+pub fn create_events_for_hook(
+    editor: &mut Editor,
+    hook: &str,
+    target: &EgValue,
+    org_id: i64,
+    granularity: Option<&str>,
+    user_data: Option<&EgValue>,
+    wait: bool,
+) -> EgResult<Vec<HookEvents>> {
+    let own_xact = !editor.in_transaction();
+
+    if wait && !own_xact {
+        return Err(format!("Cannot process events for hook {hook} within a transaction").into());
+    }
+
+    // One query, so hooks with no definitions cost little.
+    let mut query = eg::hash! {
+        "hook": hook,
+        "active": "t",
+        "owner": org::ancestors(editor, org_id)?,
+    };
+
+    if let Some(gran) = granularity {
+        query["granularity"] = EgValue::from(gran);
+    }
+
+    let flesh = eg::hash! {
+        "flesh": 1,
+        "flesh_fields": {"atevdef": ["hook"]},
+        "order_by": {"atevdef": "id"},
+    };
+
+    let event_defs = editor.search_with_ops("atevdef", query, flesh)?;
+
+    if event_defs.is_empty() {
+        log::debug!("No active event definitions for hook {hook} at org {org_id}");
+        return Ok(Vec::new());
+    }
+
+    let targets: Vec<&EgValue> = if target.is_array() {
+        target.members().collect()
+    } else {
+        vec![target]
+    };
+
+    for target in targets.iter() {
+        let class = target
+            .classname()
+            .ok_or_else(|| format!("Invalid target: {target}"))?;
+
+        // All definitions share the hook.
+        if event_defs[0]["hook"]["core_type"].as_str() != Some(class) {
+            return Err(format!("A/T hook {hook} does not match object core type: {class}").into());
+        }
+    }
+
+    if own_xact {
+        editor.xact_begin()?;
+    }
+
+    let mut hook_events = Vec::new();
+
+    let now = date::now();
+
+    for def in event_defs.iter() {
+        let mut events = Vec::new();
+        let mut due = Vec::new();
+
+        for target in targets.iter() {
+            let created = match create_event_for_object_and_def(
+                editor,
+                def,
+                target,
+                granularity,
+                user_data,
+                false,
+            ) {
+                Ok(c) => c,
+                Err(e) => {
+                    if own_xact {
+                        editor.rollback()?;
+                    }
+                    return Err(e);
+                }
+            };
+
+            if let Some(atev) = created {
+                let event = Event::from_source(atev.clone())?;
+
+                let run_time = match atev["run_time"].as_str() {
+                    Some(rt) => date::parse_datetime(rt)?,
+                    None => now,
+                };
+
+                if run_time <= now {
+                    due.push(event.id());
+                }
+
+                events.push(event);
+            }
+        }
+
+        if !events.is_empty() {
+            hook_events.push(HookEvents {
+                event_def: def.id()?,
+                grouped: def["group_field"].is_string(),
+                events,
+                due,
+            });
+        }
+    }
+
+    if own_xact {
+        editor.commit()?;
+    }
+
+    log::info!(
+        "Created {} event(s) for hook {hook}",
+        hook_events.iter().map(|h| h.events.len()).sum::<usize>()
+    );
+
+    if wait {
+        for def_events in hook_events.iter_mut() {
+            process_hook_events(editor, def_events)?;
+        }
+    }
+
+    Ok(hook_events)
+}
+
+/// Process the events for one definition which are due to run now.
+///
+/// Events for passive definitions with a delay are left for the
+/// A/T runner.
+fn process_hook_events(editor: &mut Editor, hook_events: &mut HookEvents) -> EgResult<()> {
+    let (mut due, mut later): (Vec<Event>, Vec<Event>) = hook_events
+        .events
+        .drain(..)
+        .partition(|e| hook_events.due.contains(&e.id()));
+
+    let result = if due.is_empty() {
+        Ok(())
+    } else {
+        let mut proc = Processor::new(editor, hook_events.event_def)?;
+
+        if hook_events.grouped {
+            proc.process_grouped_events(&mut due)
+        } else {
+            due.iter_mut().try_for_each(|e| proc.process_event(e))
+        }
+    };
+
+    hook_events.events = due;
+    hook_events.events.append(&mut later);
+
+    result
+}
+
 /// Take one target and one event def and create an event if we can.
 ///
 /// Assumes that the target is appropriate for the event def.
@@ -191,175 +394,3 @@ fn user_is_opted_in(editor: &mut Editor, event_def: &EgValue, target: &EgValue)
 
     Ok(editor.search("aus", query)?.len() > 0)
 }
-
-/// Create events for a passive-hook event definition, returning the
-/// IDs of the created events on success.
-///
-/// Caller is responsible for beginning / committing the transaction.
-pub fn create_passive_events_for_def(
-    editor: &mut Editor,
-    event_def_id: i64,
-    location_field: &str,
-    mut filter_op: Option<EgValue>,
-) -> EgResult<Option<Vec<i64>>> {
-    let flesh = eg::hash! {
-        "flesh": 1,
-        "flesh_fields": {
-            "atevdef": ["hook"]
-        }
-    };
-
-    let event_def = editor
-        .retrieve_with_ops("atevdef", event_def_id, flesh)?
-        .ok_or_else(|| editor.die_event())?;
-
-    let mut filters = match filter_op.take() {
-        Some(f) => f,
-        None => eg::hash! {},
-    };
-
-    // Limit to targets within range of our event def.
-    filters[location_field] = eg::hash! {
-        "in": {
-            "select": {
-                "aou": [{
-                    "column": "id",
-                    "transform": "actor.org_unit_descendants",
-                    "result_field": "id"
-                }],
-            },
-            "from": "aou",
-            "where": {"id": event_def["owner"].clone()}
-        }
-    };
-
-    // Determine the date range of the items we want to target.
-
-    let def_delay = event_def["delay"].as_str().unwrap(); // required
-    let delay_dt = date::add_interval(date::now(), def_delay)?;
-
-    let delay_filter;
-    if let Some(max_delay) = event_def["max_delay"].as_str() {
-        let max_delay_dt = date::add_interval(date::now(), max_delay)?;
-
-        if max_delay_dt < delay_dt {
-            delay_filter = eg::hash! {
-                "between": [
-                    date::to_iso(&max_delay_dt),
-                    date::to_iso(&delay_dt),
-                ]
-            };
-        } else {
-            delay_filter = eg::hash! {
-                "between": [
-                    date::to_iso(&delay_dt),
-                    date::to_iso(&max_delay_dt),
-                ]
-            };
-        }
-    } else {
-        delay_filter = eg::hash! {"<=": date::to_iso(&delay_dt)};
-    }
-
-    let delay_field = event_def["delay_field"]
-        .as_str()
-        .ok_or_else(|| format!("Passive event defs require a delay_field"))?;
-
-    filters[delay_field] = delay_filter;
-
-    // Make sure we don't create events that are already represented.
-
-    let core_type = event_def["hook"]["core_type"].as_str().unwrap(); // required
-                                                                      //let idl_class = idl::get_class(core_type)?.clone();
-    let idl_class = idl::get_class(core_type)?;
-
-    let pkey_field = idl_class
-        .pkey()
-        .ok_or_else(|| format!("IDL class {core_type} has no primary key"))?;
-
-    let mut join = eg::hash! {
-        "join": {
-            "atev": {
-                "field": "target",
-                "fkey": pkey_field,
-                "type": "left",
-                "filter": {"event_def": event_def_id}
-            }
-        }
-    };
-
-    // Some event types are repeatable depending on a repeat delay.
-    if let Some(rpt_delay) = event_def["repeat_delay"].as_str() {
-        let delay_dt = date::add_interval(date::now(), rpt_delay)?;
-
-        join["join"]["atev"]["filter"] = eg::hash! {
-            "start_time": {">": date::to_iso(&delay_dt)}
-        }
-    }
-
-    // Skip targets where the user is not opted in.
-    if let Some(usr_field) = event_def["usr_field"].as_str() {
-        if let Some(setting) = event_def["opt_in_setting"].as_str() {
-            // {"+circ": "usr"}
-            let mut user_matches = eg::hash! {};
-            user_matches[&format!("+{core_type}")] = EgValue::from(usr_field);
-
-            let opt_filter = eg::hash! {
-                "-exists": {
-                    "from": "aus",
-                    "where": {
-                        "name": setting,
-                        "usr": {"=": user_matches},
-                        "value": "true"
-                    }
-                }
-            };
-
-            if filters["-and"].is_array() {
-                filters["-and"].push(opt_filter).expect("Is Array");
-            } else {
-                filters["-and"] = eg::array![opt_filter];
-            }
-        }
-    }
-
-    log::debug!("Event def {event_def_id} filter is: {}", filters.dump());
-
-    editor.set_timeout(3600); // 1hr
-
-    let targets = editor.search(core_type, filters)?;
-
-    editor.reset_timeout();
-
-    if targets.len() == 0 {
-        log::info!("No targets found for event def {event_def_id}");
-        return Ok(None);
-    } else {
-        log::info!(
-            "Found {} targets for vent def {event_def_id}",
-            targets.len()
-        );
-    }
-
-    let mut result_ids = Vec::new();
-
-    for target in targets {
-        let id = target[pkey_field].to_string();
-
-        let mut event = eg::hash! {
-            "target": id,
-            "event_def": event_def_id,
-            "run_time": "now",
-        };
-
-        event.bless("atev")?;
-
-        let event = editor.create(event)?;
-
-        result_ids.push(event.id()?);
-    }
-
-    log::info!("Done creating events for event_def {event_def_id}");
-
-    Ok(Some(result_ids))
-}
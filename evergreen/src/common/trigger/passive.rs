@@ -0,0 +1,421 @@
+//! Passive A/T event collection.
+//!
+//! Passive hooks are not fired by application code.  Instead, targets
+//! are found by querying the hook's core type for rows whose delay
+//! field value falls within the range defined by the event
+//! definition's delay and max_delay, e.g. circulations which are
+//! 7 days overdue.  Targets which already have an event for the
+//! definition are skipped, unless the definition has a repeat delay
+//! which has passed since the previous event.
+use crate as eg;
+use eg::common::org;
+use eg::date;
+use eg::idl;
+use eg::Editor;
+use eg::EgResult;
+use eg::EgValue;
+use std::collections::HashMap;
+
+/// Max number of events created per transaction.
+const PASSIVE_EVENT_BATCH: usize = 500;
+
+/// A passive event target, along with the value of the first
+/// component of the event definition's group field, when grouped.
+struct PassiveTarget {
+    target: EgValue,
+    group_key: Option<String>,
+}
+
+/// Org unit field and base target filter for passive hooks which
+/// support passive event collection, keyed on the hook's key.
+///
+/// Only targets whose org unit field value is the event definition's
+/// owner or one of its descendants are collected.
+pub fn passive_hook_filter(hook: &str) -> Option<(&'static str, EgValue)> {
+    match hook {
+        "checkout.due" => Some((
+            "circ_lib",
+            eg::hash! {
+                "checkin_time": EgValue::Null,
+                "-or": [
+                    {"stop_fines": ["MAXFINES", "LONGOVERDUE"]},
+                    {"stop_fines": EgValue::Null}
+                ]
+            },
+        )),
+        "hold_request.shelf_expires_soon" => Some((
+            "pickup_lib",
+            eg::hash! {
+                "shelf_time": {"!=": EgValue::Null},
+                "fulfillment_time": EgValue::Null,
+                "cancel_time": EgValue::Null,
+            },
+        )),
+        _ => None,
+    }
+}
+
+/// Create events for all active passive-hook event definitions,
+/// optionally limited by granularity and event definition ID.
+///
+/// Definitions for hooks with no known passive filter (see
+/// [`passive_hook_filter`]) are skipped.
+///
+/// Events are created in batches, each in its own transaction.
+/// Batches never split the targets of a grouped event definition
+/// which share a group field value, so groups are never partially
+/// visible to the pending event runner.
+///
+/// Returns the number of events created per event definition.
+pub fn create_passive_events(
+    editor: &mut Editor,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+) -> EgResult<HashMap<i64, usize>> {
+    let mut query = eg::hash! {
+        "active": "t",
+        "hook": {
+            "in": {
+                "select": {"ath": ["key"]},
+                "from": "ath",
+                "where": {"passive": "t"}
+            }
+        }
+    };
+
+    if let Some(g) = granularity {
+        query["granularity"] = EgValue::from(g);
+    }
+
+    if !event_defs.is_empty() {
+        query["id"] = EgValue::from(event_defs.to_vec());
+    }
+
+    let flesh = eg::hash! {
+        "flesh": 1,
+        "flesh_fields": {"atevdef": ["hook"]},
+        "order_by": {"atevdef": "id"},
+    };
+
+    let defs = editor.search_with_ops("atevdef", query, flesh)?;
+
+    let mut counts = HashMap::new();
+
+    for def in defs {
+        let def_id = def.id()?;
+        let hook = def["hook"]["key"].str()?;
+
+        let Some((location_field, filter)) = passive_hook_filter(hook) else {
+            log::warn!("Skipping event def {def_id}: no passive filter for hook {hook}");
+            continue;
+        };
+
+        let targets = find_passive_targets(editor, &def, location_field, Some(filter))?;
+
+        let mut created = 0;
+
+        for batch in batch_targets(&targets, PASSIVE_EVENT_BATCH) {
+            editor.xact_begin()?;
+
+            match create_passive_events_for_targets(editor, def_id, batch) {
+                Ok(ids) => {
+                    editor.commit()?;
+                    created += ids.len();
+                }
+                Err(e) => {
+                    editor.rollback()?;
+                    return Err(e);
+                }
+            }
+        }
+
+        log::info!("Created {created} passive events for event def {def_id}");
+
+        counts.insert(def_id, created);
+    }
+
+    Ok(counts)
+}
+
+/// Create events for a passive-hook event definition, returning the
+/// IDs of the created events on success.
+///
+/// Caller is responsible for beginning / committing the transaction.
+pub fn create_passive_events_for_def(
+    editor: &mut Editor,
+    event_def_id: i64,
+    location_field: &str,
+    filter_op: Option<EgValue>,
+) -> EgResult<Option<Vec<i64>>> {
+    let flesh = eg::hash! {
+        "flesh": 1,
+        "flesh_fields": {
+            "atevdef": ["hook"]
+        }
+    };
+
+    let event_def = editor
+        .retrieve_with_ops("atevdef", event_def_id, flesh)?
+        .ok_or_else(|| editor.die_event())?;
+
+    let targets = find_passive_targets(editor, &event_def, location_field, filter_op)?;
+
+    if targets.is_empty() {
+        return Ok(None);
+    }
+
+    let result_ids = create_passive_events_for_targets(editor, event_def_id, &targets)?;
+
+    log::info!("Done creating events for event_def {event_def_id}");
+
+    Ok(Some(result_ids))
+}
+
+/// Split targets into batches of roughly `size` targets, extending a
+/// batch as needed to keep targets with the same group key together.
+///
+/// Targets are expected to be sorted by group key.
+fn batch_targets(targets: &[PassiveTarget], size: usize) -> Vec<&[PassiveTarget]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+
+    while start < targets.len() {
+        let mut end = (start + size).min(targets.len());
+
+        while end < targets.len()
+            && targets[end].group_key.is_some()
+            && targets[end].group_key == targets[end - 1].group_key
+        {
+            end += 1;
+        }
+
+        batches.push(&targets[start..end]);
+        start = end;
+    }
+
+    batches
+}
+
+/// Find targets for a passive-hook event definition which do not
+/// already have an event for the definition, sorted by the first
+/// component of the definition's group field, when grouped.
+///
+/// The event definition must have its hook fleshed.
+fn find_passive_targets(
+    editor: &mut Editor,
+    event_def: &EgValue,
+    location_field: &str,
+    mut filter_op: Option<EgValue>,
+) -> EgResult<Vec<PassiveTarget>> {
+    let event_def_id = event_def.id()?;
+
+    let mut filters = match filter_op.take() {
+        Some(f) => f,
+        None => eg::hash! {},
+    };
+
+    // Limit to targets within range of our event def.
+    filters[location_field] = EgValue::from(org::descendants(editor, event_def["owner"].int()?)?);
+
+    // Targets are due an event once the delay has passed since the
+    // delay field value, e.g. due_date + "7 days" for a 7-day overdue
+    // notice.  Negative delays produce pre-due courtesy notices.
+
+    let def_delay = event_def["delay"].str()?; // required
+    let delay_dt = date::subtract_interval(date::now(), def_delay)?;
+
+    let delay_filter;
+    if let Some(max_delay) = event_def["max_delay"].as_str() {
+        let max_delay_dt = date::subtract_interval(date::now(), max_delay)?;
+
+        if max_delay_dt < delay_dt {
+            delay_filter = eg::hash! {
+                "between": [
+                    date::to_iso(&max_delay_dt),
+                    date::to_iso(&delay_dt),
+                ]
+            };
+        } else {
+            delay_filter = eg::hash! {
+                "between": [
+                    date::to_iso(&delay_dt),
+                    date::to_iso(&max_delay_dt),
+                ]
+            };
+        }
+    } else {
+        delay_filter = eg::hash! {"<=": date::to_iso(&delay_dt)};
+    }
+
+    let delay_field = event_def["delay_field"]
+        .as_str()
+        .ok_or_else(|| "Passive event defs require a delay_field".to_string())?;
+
+    filters[delay_field] = delay_filter;
+
+    let core_type = event_def["hook"]["core_type"].str()?; // required
+    let idl_class = idl::get_class(core_type)?;
+
+    let pkey_field = idl_class
+        .pkey()
+        .ok_or_else(|| format!("IDL class {core_type} has no primary key"))?;
+
+    // Make sure we don't create events that are already represented.
+
+    let mut atev_filter = eg::hash! {"event_def": event_def_id};
+
+    // Some event types are repeatable depending on a repeat delay.
+    if let Some(rpt_delay) = event_def["repeat_delay"].as_str() {
+        let delay_dt = date::subtract_interval(date::now(), rpt_delay)?;
+        atev_filter["start_time"] = eg::hash! {">": date::to_iso(&delay_dt)};
+    }
+
+    filters["+atev"] = eg::hash! {"id": EgValue::Null};
+
+    // Skip targets where the user is not opted in.
+    if let Some(usr_field) = event_def["usr_field"].as_str() {
+        if let Some(setting) = event_def["opt_in_setting"].as_str() {
+            // {"+circ": "usr"}
+            let mut user_matches = eg::hash! {};
+            user_matches[&format!("+{core_type}")] = EgValue::from(usr_field);
+
+            let opt_filter = eg::hash! {
+                "-exists": {
+                    "from": "aus",
+                    "where": {
+                        "name": setting,
+                        "usr": {"=": user_matches},
+                        "value": "true"
+                    }
+                }
+            };
+
+            if filters["-and"].is_array() {
+                filters["-and"].push(opt_filter).expect("Is Array");
+            } else {
+                filters["-and"] = eg::array![opt_filter];
+            }
+        }
+    }
+
+    // Sort by the group field so targets sharing a group are adjacent.
+    let group_field = event_def["group_field"]
+        .as_str()
+        .map(|g| g.split('.').next().unwrap_or(g))
+        .filter(|g| idl_class.has_real_field(g) && *g != pkey_field);
+
+    let mut select = vec![EgValue::from(pkey_field)];
+    let mut order_by = vec![eg::hash! {"class": core_type, "field": pkey_field}];
+
+    if let Some(gfield) = group_field {
+        select.push(EgValue::from(gfield));
+        order_by.insert(0, eg::hash! {"class": core_type, "field": gfield});
+    }
+
+    let mut from = eg::hash! {};
+    from[core_type] = eg::hash! {
+        "atev": {
+            "field": "target",
+            "fkey": pkey_field,
+            "type": "left",
+            "filter": atev_filter,
+        }
+    };
+
+    let mut query = eg::hash! {
+        "select": {},
+        "from": from,
+        "where": filters,
+        "order_by": order_by,
+    };
+
+    query["select"][core_type] = EgValue::from(select);
+
+    log::debug!("Event def {event_def_id} query is: {}", query.dump());
+
+    editor.set_timeout(3600); // 1hr
+
+    let result = editor.json_query(query);
+
+    editor.reset_timeout();
+
+    let mut targets = Vec::new();
+
+    for mut row in result? {
+        let group_key = group_field.map(|g| row[g].dump());
+
+        targets.push(PassiveTarget {
+            target: row[pkey_field].take(),
+            group_key,
+        });
+    }
+
+    if targets.is_empty() {
+        log::info!("No targets found for event def {event_def_id}");
+    } else {
+        log::info!(
+            "Found {} targets for event def {event_def_id}",
+            targets.len()
+        );
+    }
+
+    Ok(targets)
+}
+
+/// Create a pending event for each target.
+///
+/// Caller is responsible for beginning / committing the transaction.
+fn create_passive_events_for_targets(
+    editor: &mut Editor,
+    event_def_id: i64,
+    targets: &[PassiveTarget],
+) -> EgResult<Vec<i64>> {
+    let mut result_ids = Vec::new();
+
+    for target in targets {
+        let mut event = eg::hash! {
+            "target": target.target.to_string(),
+            "event_def": event_def_id,
+            "run_time": "now",
+        };
+
+        event.bless("atev")?;
+
+        let event = editor.create(event)?;
+
+        result_ids.push(event.id()?);
+    }
+
+    Ok(result_ids)
+}
+
+#[test]
+fn test_batch_targets() {
+    let targets: Vec<PassiveTarget> = [1, 1, 1, 2, 3, 3]
+        .iter()
+        .enumerate()
+        .map(|(id, key)| PassiveTarget {
+            target: EgValue::from(id as i64),
+            group_key: Some(key.to_string()),
+        })
+        .collect();
+
+    let sizes: Vec<usize> = batch_targets(&targets, 2).iter().map(|b| b.len()).collect();
+    assert_eq!(sizes, [3, 3]);
+
+    let sizes: Vec<usize> = batch_targets(&targets, 4).iter().map(|b| b.len()).collect();
+    assert_eq!(sizes, [4, 2]);
+
+    let ungrouped: Vec<PassiveTarget> = (0..5)
+        .map(|id| PassiveTarget {
+            target: EgValue::from(id),
+            group_key: None,
+        })
+        .collect();
+
+    let sizes: Vec<usize> = batch_targets(&ungrouped, 2)
+        .iter()
+        .map(|b| b.len())
+        .collect();
+    assert_eq!(sizes, [2, 2, 1]);
+}
@@ -2,21 +2,43 @@
 /// given event definition.
 use crate as eg;
 use eg::common::trigger::{Event, EventState};
+use eg::date;
 use eg::idl;
 use eg::util::thread_id;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
+use std::collections::HashMap;
 use std::fmt;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Wait this long before retrying a failed reaction, unless the
+/// event definition has a retry_delay param.
+pub const DEFAULT_RETRY_DELAY: &str = "5 minutes";
 
 // Add feature to roll-back failures and reset event states.
-// Add a retry state that's only processed intentionally?
 pub struct Processor<'a> {
     pub editor: &'a mut Editor,
     event_def_id: i64,
     event_def: EgValue,
     target_flesh: EgValue,
+
+    /// Failed reactions are retried up to this many times unless the
+    /// event definition has a max_retries param.
+    max_retries: i64,
+
+    /// Interval string.  Used when the event definition has no
+    /// retry_delay param.
+    retry_delay: String,
+
+    /// Collect and validate events without reacting to them or
+    /// storing any event state changes.
+    dry_run: bool,
+
+    /// When set, grouped processing stops before the next group.
+    shutdown: Option<Arc<AtomicBool>>,
 }
 
 impl fmt::Display for Processor<'_> {
@@ -44,6 +66,10 @@ impl<'a> Processor<'a> {
             event_def,
             event_def_id,
             target_flesh: EgValue::Null,
+            max_retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY.to_string(),
+            dry_run: false,
+            shutdown: None,
             editor,
         };
 
@@ -75,7 +101,6 @@ impl<'a> Processor<'a> {
 
         if self.validate(event)? {
             self.react(&mut [event])?;
-            self.set_event_state(event, EventState::Complete)?;
         }
 
         Ok(())
@@ -101,7 +126,7 @@ impl<'a> Processor<'a> {
             events.push(Event::from_source(jevent)?);
         }
 
-        let mut proc = Processor::new(editor, events[0].id())?;
+        let mut proc = Processor::new(editor, events[0].event_def())?;
 
         let mut slice = events.iter_mut().collect::<Vec<&mut Event>>();
         proc.process_event_group(&mut slice[..])?;
@@ -110,9 +135,18 @@ impl<'a> Processor<'a> {
     }
 
     pub fn process_event_group(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
-        let mut valid_events: Vec<&mut Event> = Vec::new();
         for event in events.iter_mut() {
             self.collect(event)?;
+        }
+
+        self.process_collected_group(events)
+    }
+
+    /// Validate and react to a group of events which have already
+    /// been collected.
+    pub fn process_collected_group(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        let mut valid_events: Vec<&mut Event> = Vec::new();
+        for event in events.iter_mut() {
             if self.validate(event)? {
                 valid_events.push(event);
             }
@@ -123,14 +157,67 @@ impl<'a> Processor<'a> {
             return Ok(());
         }
 
-        let slice = &mut valid_events[..];
-        self.react(slice)?;
+        // Reacted events are completed within react().  Events the
+        // reactor failed retain their Error state.
+        self.react(&mut valid_events[..])
+    }
 
-        for event in valid_events {
-            self.set_event_state(event, EventState::Complete)?;
+    /// Collect events for a grouped event definition, sort them into
+    /// groups by group value, then validate and react to each group.
+    ///
+    /// Every group is processed, even if an earlier group fails.
+    /// Returns the error from the last failed group, if any.
+    pub fn process_grouped_events(&mut self, events: &mut [Event]) -> EgResult<()> {
+        for event in events.iter_mut() {
+            self.collect(event)?;
         }
 
-        Ok(())
+        let mut groups: HashMap<String, Vec<&mut Event>> = HashMap::new();
+        for event in events.iter_mut() {
+            let key = event.group_value().map(|v| v.dump()).unwrap_or_default();
+            groups.entry(key).or_default().push(event);
+        }
+
+        let mut result = Ok(());
+        for (_, mut group) in groups {
+            if self.shutdown_requested() {
+                // Remaining events stay Collected for the caller to
+                // return to pending.
+                log::info!("{self} shutdown requested; skipping remaining groups");
+                break;
+            }
+
+            if let Err(e) = self.process_collected_group(&mut group[..]) {
+                log::error!("{self} group processing failed: {e}");
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// In dry run mode, events are collected and validated as usual,
+    /// but state changes are applied to the in-memory events only and
+    /// valid events are left in the Valid state instead of reacting.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Stop grouped processing between groups once this flag is set,
+    /// e.g. by a signal handler.
+    pub fn set_shutdown_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.shutdown = Some(flag);
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .map(|f| f.load(Ordering::Relaxed))
+            .unwrap_or(false)
     }
 
     pub fn event_def_id(&self) -> i64 {
@@ -193,6 +280,76 @@ impl<'a> Processor<'a> {
         Ok(())
     }
 
+    /// Set the retry limit and delay used for event definitions
+    /// which do not specify their own.
+    pub fn set_retry_policy(&mut self, max_retries: i64, retry_delay: &str) {
+        self.max_retries = max_retries;
+        self.retry_delay = retry_delay.to_string();
+    }
+
+    /// Max number of times a failed reaction is retried.
+    pub fn max_retries(&mut self) -> i64 {
+        let default = self.max_retries;
+        self.param_value_as_str("max_retries")
+            .and_then(|v| v.parse().ok())
+            .or_else(|| self.param_value("max_retries").and_then(|v| v.as_int()))
+            .unwrap_or(default)
+    }
+
+    /// Interval to wait before retrying a failed reaction.
+    pub fn retry_delay(&mut self) -> String {
+        match self.param_value_as_str("retry_delay") {
+            Some(d) => d.to_string(),
+            None => self.retry_delay.clone(),
+        }
+    }
+
+    /// Count a reaction attempt and set the event to Reacting.
+    ///
+    /// Both changes are committed before the reactor runs, so an
+    /// attempt is counted even if the process dies mid-reaction.
+    pub fn start_reaction(&mut self, event: &mut Event) -> EgResult<()> {
+        if self.max_retries() > 0 && !event.increment_attempts() {
+            log::warn!("{self} cannot count attempts for {event}; retries disabled");
+        }
+
+        self.set_event_state(event, EventState::Reacting)
+    }
+
+    /// Reschedule a failed event if it has retry attempts remaining,
+    /// otherwise set it to the Error state.
+    ///
+    /// Either way, the error text is stored on the event.
+    pub fn set_event_state_error_or_retry(
+        &mut self,
+        event: &mut Event,
+        error_text: &str,
+    ) -> EgResult<()> {
+        let attempts = event.attempts();
+        let max_retries = self.max_retries();
+
+        // Attempts are only counted when retries are enabled.
+        // The first attempt is not a retry.
+        if attempts == 0 || attempts > max_retries {
+            return self.set_event_state_error(event, error_text);
+        }
+
+        let delay = self.retry_delay();
+        let run_time = date::add_interval(date::now(), &delay)?;
+
+        log::info!(
+            "{self} retrying {event} at {} after {attempts} failed attempt(s)",
+            date::to_iso(&run_time)
+        );
+
+        self.set_event_state_impl(
+            event,
+            EventState::Pending,
+            Some(error_text),
+            Some(date::to_iso(&run_time)),
+        )
+    }
+
     /// Returns the parameter value with the provided name or None if no
     /// such parameter exists.
     pub fn param_value(&mut self, param_name: &str) -> Option<&EgValue> {
@@ -207,30 +364,77 @@ impl<'a> Processor<'a> {
     /// Returns the parameter value with the provided name as a &str or
     /// None if no such parameter exists OR the parameter is not a JSON
     /// string.
+    ///
+    /// Param values are stored as JSON text, so surrounding quotes
+    /// are removed.
     pub fn param_value_as_str(&mut self, param_name: &str) -> Option<&str> {
-        if let Some(pval) = self.param_value(param_name) {
-            pval["value"].as_str()
-        } else {
-            None
-        }
+        self.param_value(param_name)
+            .and_then(|pval| pval.as_str())
+            .map(|s| s.trim_matches('"'))
     }
 
     /// Returns true if a parameter value exists and has truthy,
     /// false otherwise.
     pub fn param_value_as_bool(&mut self, param_name: &str) -> bool {
-        if let Some(pval) = self.param_value(param_name) {
-            pval["value"].boolish()
-        } else {
-            false
+        match self.param_value(param_name) {
+            Some(pval) => match pval.as_str().map(|s| s.trim_matches('"')) {
+                Some("0") | Some("") => false,
+                Some(s) => EgValue::from(s).boolish(),
+                None => pval.boolish(),
+            },
+            None => false,
         }
     }
 
     pub fn set_event_state(&mut self, event: &mut Event, state: EventState) -> EgResult<()> {
-        self.set_event_state_impl(event, state, None)
+        self.set_event_state_impl(event, state, None, None)
     }
 
     pub fn set_event_state_error(&mut self, event: &mut Event, error_text: &str) -> EgResult<()> {
-        self.set_event_state_impl(event, EventState::Error, Some(error_text))
+        self.set_event_state_impl(event, EventState::Error, Some(error_text), None)
+    }
+
+    /// Record a failed cleanup for a successfully reacted event.
+    ///
+    /// The event is left in the Cleaning state, distinct from Error,
+    /// since the reaction itself succeeded.
+    pub fn set_event_state_cleanup_error(
+        &mut self,
+        event: &mut Event,
+        error_text: &str,
+    ) -> EgResult<()> {
+        self.set_event_state_impl(event, EventState::Cleaning, Some(error_text), None)
+    }
+
+    /// Store the rendered template output for a set of events.
+    ///
+    /// Events in a group share a single output.
+    pub fn set_events_template_output(
+        &mut self,
+        events: &[&mut Event],
+        output: &str,
+    ) -> EgResult<()> {
+        let mut editor = self.editor.xact_guard()?;
+
+        let mut ateo = eg::hash! {
+            "data": output,
+            "is_error": false,
+        };
+        ateo.bless("ateo")?;
+
+        let ateo = editor.create(ateo)?;
+
+        for event in events.iter() {
+            let mut atev = editor
+                .retrieve("atev", event.id())?
+                .ok_or_else(|| "Our event disappeared from the DB?".to_string())?;
+
+            atev["template_output"] = ateo["id"].clone();
+
+            editor.update(atev)?;
+        }
+
+        editor.xact_commit()
     }
 
     /// Update the event state and related state-tracking values.
@@ -239,15 +443,19 @@ impl<'a> Processor<'a> {
         event: &mut Event,
         state: EventState,
         error_text: Option<&str>,
+        run_time: Option<String>,
     ) -> EgResult<()> {
         event.set_state(state);
 
+        if self.dry_run {
+            return Ok(());
+        }
+
         let state_str: &str = state.into();
 
-        self.editor.xact_begin()?;
+        let mut editor = self.editor.xact_guard()?;
 
-        let mut atev = self
-            .editor
+        let mut atev = editor
             .retrieve("atev", event.id())?
             .ok_or_else(|| format!("Our event disappeared from the DB?"))?;
 
@@ -259,7 +467,7 @@ impl<'a> Processor<'a> {
             };
             output.bless("ateo")?;
 
-            let mut result = self.editor.create(output)?;
+            let mut result = editor.create(output)?;
 
             atev["error_output"] = result["id"].take();
         }
@@ -276,9 +484,22 @@ impl<'a> Processor<'a> {
             atev["complete_time"] = EgValue::from("now");
         }
 
-        self.editor.update(atev)?;
+        if let Some(rt) = run_time {
+            atev["run_time"] = EgValue::from(rt);
+        }
+
+        if event.attempts() > 0 {
+            // Keep the stored attempt count in sync.
+            if let Some(data) = event.user_data() {
+                atev["user_data"] = EgValue::from(data.dump());
+            }
+        }
+
+        editor.update(atev)?;
+
+        editor.xact_commit()?;
 
-        self.editor.xact_commit()?;
+        drop(editor);
 
         if state == EventState::Complete || state == EventState::Error {
             // If we're likely done, force a disconnect.
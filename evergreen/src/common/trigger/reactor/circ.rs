@@ -1,11 +1,28 @@
 //! Base module for A/T Reactors
 use crate as eg;
 use eg::common::auth;
+use eg::common::circ::permit;
+use eg::common::settings::Settings;
 use eg::common::{trigger, trigger::Event, trigger::Processor};
 use eg::EgEvent;
 use eg::EgResult;
 use eg::EgValue;
 
+/// Hook for autorenewal notification events.
+const AUTORENEWAL_HOOK: &str = "autorenewal";
+
+/// Reactor parameter naming a specific event definition to use for
+/// autorenewal notifications instead of all definitions on the
+/// autorenewal hook.
+const NOTIFY_EVENT_DEF_PARAM: &str = "notify_event_def";
+
+/// Org unit setting: do not notify patrons of autorenewals which
+/// failed because the item is needed for a hold.
+const SUPPRESS_HOLD_FAILURE_NOTICE: &str = "circ.autorenewal.suppress_hold_failure_notice";
+
+/// Renewal failure textcode for items needed to fill a hold.
+const COPY_NEEDED_FOR_HOLD: &str = "COPY_NEEDED_FOR_HOLD";
+
 impl Processor<'_> {
     pub fn autorenew(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
         let usr = &events[0].target()["usr"];
@@ -31,8 +48,13 @@ impl Processor<'_> {
         let auth_ses = auth::Session::internal_session_api(self.editor.client_mut(), &auth_args)?
             .ok_or_else(|| format!("Cannot create internal auth session"))?;
 
-        for event in events {
-            self.renew_one_circ(auth_ses.token(), patron_id, event)?;
+        // A failure to renew one circ does not prevent renewal of
+        // the others.  Failed events are flagged individually.
+        for event in events.iter_mut() {
+            if let Err(e) = self.renew_one_circ(auth_ses.token(), patron_id, event) {
+                log::error!("{self} autorenewal of event {} failed: {e}", event.id());
+                self.set_event_state_error(event, &e.to_string())?;
+            }
         }
 
         Ok(())
@@ -47,36 +69,21 @@ impl Processor<'_> {
             event.target()["id"]
         );
 
-        let params = vec![
-            EgValue::from(authtoken),
-            eg::hash! {
-                "patron_id": patron_id,
-                "copy_id": copy_id.clone(),
-                "auto_renewal": true
-            },
-        ];
-
-        log::info!("{self} renewing with params: {params:?}");
+        let target = &event.target()["circ_lib"];
+        let circ_lib = target.as_int().unwrap_or(target.id()?);
 
-        let mut response = self
-            .editor
-            .client_mut()
-            .send_recv_one("open-ils.circ", "open-ils.circ.renew", params)?
-            .ok_or_else(|| format!("Renewal returned no response"))?;
+        // Check the renewal policy first so blocked renewals report
+        // the specific reason instead of a generic API failure.
+        let permit = permit::test_renewal(self.editor, patron_id, copy_id, circ_lib)?;
 
-        // API may return an EgEvent or a list of them.  We're only
-        // interested in the first event.
-        let evt = if response.is_array() {
-            response.array_remove(0)
-        } else {
-            response
+        let eg_evt = match permit.first_event() {
+            Some(evt) => {
+                log::info!("{self} autorenewal of copy {copy_id} not permitted: {evt}");
+                evt
+            }
+            None => self.renew_via_api(authtoken, patron_id, copy_id)?,
         };
 
-        let eg_evt = EgEvent::parse(&evt)
-            .ok_or_else(|| format!("Renew returned unexpected data: {}", evt.dump()))?;
-
-        log::info!("{self} autorenewal returned {eg_evt}");
-
         let source_circ = event.target();
         let new_circ = &eg_evt.payload()["circ"];
 
@@ -131,20 +138,113 @@ impl Processor<'_> {
             "auto_renewal_remaining": auto_remaining,
         };
 
-        let target = &event.target()["circ_lib"];
-        let circ_lib = target.as_int().unwrap_or(target.id()?);
+        if !success
+            && eg_evt.textcode() == COPY_NEEDED_FOR_HOLD
+            && self.suppress_hold_failure_notice(circ_lib)?
+        {
+            log::info!("{self} suppressing autorenewal notice for circ needed for hold");
+            return Ok(());
+        }
 
-        // Create the event from the source circ instead of the new
-        // circ, since the renewal may have failed.  Fire and do not
-        // forget so we don't flood A/T.
-        trigger::create_events_for_object(
-            &mut self.editor,
-            "autorenewal",
+        self.create_autorenewal_notice(event, circ_lib, &user_data)
+    }
+
+    /// Renew the copy via the renewal API, returning the first event
+    /// from the response.
+    fn renew_via_api(
+        &mut self,
+        authtoken: &str,
+        patron_id: i64,
+        copy_id: i64,
+    ) -> EgResult<EgEvent> {
+        let params = vec![
+            EgValue::from(authtoken),
+            eg::hash! {
+                "patron_id": patron_id,
+                "copy_id": copy_id,
+                "auto_renewal": true
+            },
+        ];
+
+        log::info!("{self} renewing with params: {params:?}");
+
+        let mut response = self
+            .editor
+            .client_mut()
+            .send_recv_one("open-ils.circ", "open-ils.circ.renew", params)?
+            .ok_or_else(|| "Renewal returned no response".to_string())?;
+
+        // API may return an EgEvent or a list of them.  We're only
+        // interested in the first event.
+        let evt = if response.is_array() {
+            response.array_remove(0)
+        } else {
+            response
+        };
+
+        let eg_evt = EgEvent::parse(&evt)
+            .ok_or_else(|| format!("Renew returned unexpected data: {}", evt.dump()))?;
+
+        log::info!("{self} autorenewal returned {eg_evt}");
+
+        Ok(eg_evt)
+    }
+
+    fn suppress_hold_failure_notice(&mut self, circ_lib: i64) -> EgResult<bool> {
+        let mut settings = Settings::new(self.editor);
+        Ok(settings
+            .get_value_at_org(SUPPRESS_HOLD_FAILURE_NOTICE, circ_lib)?
+            .boolish())
+    }
+
+    /// Create the autorenewal notification event(s) for a circ.
+    ///
+    /// Events are created from the source circ instead of the new
+    /// circ, since the renewal may have failed.  Fire and do not
+    /// forget so we don't flood A/T.
+    fn create_autorenewal_notice(
+        &mut self,
+        event: &Event,
+        circ_lib: i64,
+        user_data: &EgValue,
+    ) -> EgResult<()> {
+        // Param values are stored as text, possibly JSON-quoted.
+        let def_id = match self.param_value(NOTIFY_EVENT_DEF_PARAM) {
+            Some(v) => v
+                .as_int()
+                .or_else(|| v.as_str().and_then(|s| s.trim_matches('"').parse().ok())),
+            None => None,
+        };
+
+        let def_id = match def_id {
+            Some(id) => id,
+            None => {
+                return trigger::create_events_for_object(
+                    self.editor,
+                    AUTORENEWAL_HOOK,
+                    event.target(),
+                    circ_lib,
+                    None,
+                    Some(user_data),
+                    false,
+                );
+            }
+        };
+
+        let event_def = self
+            .editor
+            .retrieve("atevdef", def_id)?
+            .ok_or_else(|| self.editor.die_event())?;
+
+        trigger::create_event_for_object_and_def(
+            self.editor,
+            &event_def,
             event.target(),
-            circ_lib,
             None,
-            Some(&user_data),
+            Some(user_data),
             false,
-        )
+        )?;
+
+        Ok(())
     }
 }
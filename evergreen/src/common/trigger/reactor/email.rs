@@ -0,0 +1,451 @@
+//! SendEmail Reactor
+use crate as eg;
+use eg::common::settings::Settings;
+use eg::common::trigger::template::Template;
+use eg::common::trigger::{Event, Processor};
+use eg::osrf::sclient::HostSettings;
+use eg::EgResult;
+use eg::EgValue;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const DEFAULT_SMTP_SERVER: &str = "localhost";
+const DEFAULT_SMTP_PORT: u16 = 25;
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Org unit setting for the sender address.  Overrides the host
+/// config email_notify/sender_address.
+const SENDER_ADDRESS_SETTING: &str = "email.sender_address";
+
+/// Org unit setting for the Reply-To address.
+const REPLY_TO_SETTING: &str = "email.reply_to";
+
+/// SMTP relay configuration, from the email_notify section
+/// of the host config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmtpConfig {
+    server: String,
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    sender_address: Option<String>,
+}
+
+impl SmtpConfig {
+    /// Read the SMTP config from the host settings, falling back
+    /// to a relay on localhost if none are loaded.
+    ///
+    /// Read for every email so reloaded host settings apply to the
+    /// next email sent.
+    fn from_host_settings() -> SmtpConfig {
+        let mut conf = SmtpConfig {
+            server: DEFAULT_SMTP_SERVER.to_string(),
+            port: DEFAULT_SMTP_PORT,
+            username: None,
+            password: None,
+            sender_address: None,
+        };
+
+        if !HostSettings::is_loaded() {
+            return conf;
+        }
+
+        let value = |name: &str| {
+            HostSettings::get(&format!("email_notify/{name}"))
+                .ok()
+                .and_then(|v| v.to_string())
+        };
+
+        if let Some(s) = value("smtp_server") {
+            conf.server = s;
+        }
+        if let Some(p) = value("smtp_port").and_then(|p| p.parse().ok()) {
+            conf.port = p;
+        }
+
+        conf.username = value("smtp_username");
+        conf.password = value("smtp_password");
+        conf.sender_address = value("sender_address");
+
+        conf
+    }
+}
+
+/// An email message built from rendered template output.
+///
+/// Like the Perl SendEmail reactor, templates produce the message
+/// headers, followed by a blank line, followed by the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Email {
+    pub fn parse(text: &str) -> EgResult<Email> {
+        let text = text.trim_start();
+        let (head, body) = match text.split_once("\n\n") {
+            Some((h, b)) => (h, b),
+            None => match text.split_once("\r\n\r\n") {
+                Some((h, b)) => (h, b),
+                None => return Err("Email template output has no headers".into()),
+            },
+        };
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+
+        for line in head.lines() {
+            if line.starts_with([' ', '\t']) {
+                // Folded header continuation
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                    continue;
+                }
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid email header: {line}"))?;
+
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        Ok(Email {
+            headers,
+            body: body.to_string(),
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, v)| n.eq_ignore_ascii_case(name) && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set a header value if the template did not provide one.
+    pub fn default_header(&mut self, name: &str, value: &str) {
+        if self.header(name).is_none() {
+            self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    /// Bare addresses of all To, Cc, and Bcc recipients.
+    pub fn recipients(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| ["to", "cc", "bcc"].contains(&n.to_lowercase().as_str()))
+            .flat_map(|(_, v)| v.split(','))
+            .map(bare_address)
+            .filter(|a| !a.is_empty())
+            .collect()
+    }
+
+    /// Full MIME message text, minus Bcc headers, with CRLF line
+    /// endings and SMTP dot-stuffing applied.
+    pub fn to_smtp_data(&self) -> String {
+        let mut data = String::new();
+
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("bcc") {
+                data += &format!("{name}: {value}\r\n");
+            }
+        }
+
+        if self.header("MIME-Version").is_none() {
+            data += "MIME-Version: 1.0\r\n";
+        }
+        if self.header("Content-Type").is_none() {
+            data += "Content-Type: text/plain; charset=UTF-8\r\n";
+        }
+        if self.header("Content-Transfer-Encoding").is_none() {
+            data += "Content-Transfer-Encoding: 8bit\r\n";
+        }
+
+        data += "\r\n";
+
+        for line in self.body.lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data += line;
+            data += "\r\n";
+        }
+
+        data
+    }
+}
+
+/// Extracts "jane@example.org" from "Jane Doe <jane@example.org>".
+fn bare_address(addr: &str) -> String {
+    let addr = addr.trim();
+    match (addr.rfind('<'), addr.rfind('>')) {
+        (Some(s), Some(e)) if s < e => addr[s + 1..e].trim().to_string(),
+        _ => addr.to_string(),
+    }
+}
+
+/// Minimal SMTP client.  Supports AUTH PLAIN, but not TLS, so relays
+/// requiring credentials should be reachable over a trusted network.
+struct SmtpClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpClient {
+    fn connect(conf: &SmtpConfig) -> EgResult<SmtpClient> {
+        let addr = format!("{}:{}", conf.server, conf.port);
+
+        let stream = TcpStream::connect(&addr)
+            .map_err(|e| format!("Cannot connect to SMTP server {addr}: {e}"))?;
+
+        stream.set_read_timeout(Some(SMTP_TIMEOUT)).ok();
+        stream.set_write_timeout(Some(SMTP_TIMEOUT)).ok();
+
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("SMTP socket error: {e}"))?;
+
+        let mut client = SmtpClient {
+            reader: BufReader::new(stream),
+            writer,
+        };
+
+        client.read_reply(220)?;
+
+        Ok(client)
+    }
+
+    /// Read a (possibly multi-line) reply and verify its code.
+    fn read_reply(&mut self, expect: u16) -> EgResult<String> {
+        let mut reply = String::new();
+
+        loop {
+            let mut line = String::new();
+            let count = self
+                .reader
+                .read_line(&mut line)
+                .map_err(|e| format!("SMTP read failed: {e}"))?;
+
+            if count == 0 {
+                return Err("SMTP server closed the connection".into());
+            }
+
+            reply += &line;
+
+            // Final line of a reply is "NNN text", others "NNN-text".
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+
+        let code = reply.get(..3).and_then(|c| c.parse::<u16>().ok());
+
+        if code != Some(expect) {
+            return Err(format!("Unexpected SMTP reply: {}", reply.trim()).into());
+        }
+
+        Ok(reply)
+    }
+
+    fn command(&mut self, cmd: &str, expect: u16) -> EgResult<String> {
+        self.writer
+            .write_all(format!("{cmd}\r\n").as_bytes())
+            .map_err(|e| format!("SMTP write failed: {e}"))?;
+
+        self.read_reply(expect)
+    }
+
+    fn send(&mut self, conf: &SmtpConfig, from: &str, email: &Email) -> EgResult<()> {
+        let hostname = gethostname::gethostname();
+        self.command(&format!("EHLO {}", hostname.to_string_lossy()), 250)?;
+
+        if let (Some(user), Some(pass)) = (&conf.username, &conf.password) {
+            let creds = base64_encode(format!("\0{user}\0{pass}").as_bytes());
+            self.command(&format!("AUTH PLAIN {creds}"), 235)?;
+        }
+
+        self.command(&format!("MAIL FROM:<{}>", bare_address(from)), 250)?;
+
+        let recipients = email.recipients();
+        if recipients.is_empty() {
+            return Err("Email has no recipients".into());
+        }
+
+        for rcpt in recipients {
+            self.command(&format!("RCPT TO:<{rcpt}>"), 250)?;
+        }
+
+        self.command("DATA", 354)?;
+        self.command(&format!("{}.", email.to_smtp_data()), 250)?;
+        self.command("QUIT", 221).ok();
+
+        Ok(())
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[((n >> (18 - i * 6)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+impl Processor<'_> {
+    /// Render the event definition template and send the result
+    /// as an email.
+    ///
+    /// Linked events produce a single email.  The rendered output is
+    /// stored on each event regardless of whether sending succeeds.
+    pub fn send_email(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        let template = self.event_def()["template"]
+            .as_str()
+            .ok_or_else(|| format!("{self} has no template"))?;
+
+        let template = Template::parse(template)?;
+        let output = template.render(&self.template_env(events))?;
+
+        self.set_events_template_output(events, &output)?;
+
+        let mut email = Email::parse(&output)?;
+        let conf = SmtpConfig::from_host_settings();
+
+        let owner = self.event_def()["owner"].int()?;
+        let mut settings = Settings::new(self.editor);
+
+        let mut sender = settings
+            .get_value_at_org(SENDER_ADDRESS_SETTING, owner)?
+            .as_str()
+            .map(|s| s.to_string())
+            .or_else(|| conf.sender_address.clone());
+
+        if let Some(from) = email.header("From") {
+            if sender.is_none() {
+                sender = Some(from.to_string());
+            }
+        } else if let Some(s) = sender.as_deref() {
+            email.default_header("From", s);
+        }
+
+        if let Some(reply_to) = settings.get_value_at_org(REPLY_TO_SETTING, owner)?.as_str() {
+            email.default_header("Reply-To", reply_to);
+        }
+
+        let sender = sender.ok_or_else(|| format!("{self} email has no sender address"))?;
+
+        log::info!(
+            "{self} sending email to {:?} via {}:{}",
+            email.recipients(),
+            conf.server,
+            conf.port
+        );
+
+        SmtpClient::connect(&conf)?.send(&conf, &sender, &email)
+    }
+
+    /// Variables available to templates.
+    ///
+    /// For linked event groups, "target" and "event" are lists.
+    fn template_env(&mut self, events: &[&mut Event]) -> EgValue {
+        let mut params = EgValue::new_object();
+        for param in self.params().members() {
+            if let Some(name) = param["param"].as_str() {
+                // Param values are stored as JSON text.
+                let value = match param["value"].as_str() {
+                    Some(v) => EgValue::parse(v).unwrap_or_else(|_| EgValue::from(v)),
+                    None => param["value"].clone(),
+                };
+                params[name] = value;
+            }
+        }
+
+        let event_hash = |e: &Event| {
+            eg::hash! {
+                "id": e.id(),
+                "event_def": e.event_def(),
+                "user_data": e.user_data().cloned(),
+            }
+        };
+
+        let (target, event, user_data) = if events.len() == 1 {
+            let e = &events[0];
+            (
+                e.target().clone(),
+                event_hash(e),
+                e.user_data().cloned().unwrap_or(EgValue::Null),
+            )
+        } else {
+            let mut targets = EgValue::new_array();
+            let mut evts = EgValue::new_array();
+            let mut user_data = EgValue::new_array();
+
+            for e in events.iter() {
+                targets.push(e.target().clone()).ok();
+                evts.push(event_hash(e)).ok();
+                user_data
+                    .push(e.user_data().cloned().unwrap_or(EgValue::Null))
+                    .ok();
+            }
+
+            (targets, evts, user_data)
+        };
+
+        eg::hash! {
+            "target": target,
+            "event": event,
+            "user_data": user_data,
+            "params": params,
+            "event_def": self.event_def().clone(),
+        }
+    }
+}
+
+#[test]
+fn test_email_parse() {
+    let text = "To: Jane Doe <jane@example.org>, bob@example.org\n\
+        Bcc: audit@example.org\n\
+        Subject: Items due\n  soon\n\n\
+        Hello\n.hidden line\n";
+
+    let mut email = Email::parse(text).unwrap();
+
+    assert_eq!(email.header("subject"), Some("Items due soon"));
+    assert_eq!(
+        email.recipients(),
+        vec!["jane@example.org", "bob@example.org", "audit@example.org"]
+    );
+
+    email.default_header("From", "library@example.org");
+    email.default_header("Subject", "ignored");
+
+    let data = email.to_smtp_data();
+
+    assert!(data.contains("From: library@example.org\r\n"));
+    assert!(data.contains("Subject: Items due soon\r\n"));
+    assert!(!data.contains("audit@example.org"));
+    assert!(data.ends_with("\r\nHello\r\n..hidden line\r\n"));
+
+    assert!(Email::parse("No headers here").is_err());
+
+    assert_eq!(base64_encode(b"\0user\0pass"), "AHVzZXIAcGFzcw==");
+}
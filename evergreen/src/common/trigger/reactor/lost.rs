@@ -0,0 +1,81 @@
+//! Circ::MarkItemLost Reactor
+use crate as eg;
+use eg::common::circ;
+use eg::common::trigger::{Event, Processor};
+use eg::EgResult;
+
+/// Reactor parameter containing the ID of the staff user recorded
+/// as the editor of lost copies and the voider of overdue fines.
+const EDITOR_PARAM: &str = "editor";
+
+/// Used when no editor parameter is configured.
+const DEFAULT_EDITOR: i64 = 1;
+
+const SAVEPOINT: &str = "mark_item_lost";
+
+impl Processor<'_> {
+    /// Mark the copy for each target circulation as lost.
+    ///
+    /// All events in the group are processed within a single
+    /// transaction, with a savepoint per event.  A failure rolls back
+    /// the changes for its event only and sets its event to the
+    /// error (or retry) state, leaving the rest of the group intact.
+    pub fn mark_item_lost(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        let editor_id = match self.param_value(EDITOR_PARAM) {
+            Some(v) => v
+                .as_int()
+                .or_else(|| v.as_str().and_then(|s| s.trim_matches('"').parse().ok()))
+                .ok_or_else(|| format!("Invalid {EDITOR_PARAM} param: {v}"))?,
+            None => DEFAULT_EDITOR,
+        };
+
+        let requestor = self
+            .editor
+            .retrieve("au", editor_id)?
+            .ok_or_else(|| self.editor.die_event())?;
+
+        self.editor.give_requestor(requestor);
+
+        // Log the circ, copy, and billing changes made for each event.
+        let capture_diffs = self.editor.capture_diffs();
+        self.editor.set_capture_diffs(true);
+
+        let result = self.mark_each_item_lost(events);
+
+        self.editor.set_capture_diffs(capture_diffs);
+
+        // Event states are updated in their own transactions.
+        for (idx, error) in result? {
+            self.set_event_state_error_or_retry(events[idx], &error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index and error of each event which failed.
+    fn mark_each_item_lost(&mut self, events: &[&mut Event]) -> EgResult<Vec<(usize, String)>> {
+        self.editor.xact_begin()?;
+
+        let mut failures = Vec::new();
+
+        for (idx, event) in events.iter().enumerate() {
+            let circ_id = event.target().id()?;
+
+            self.editor.savepoint(SAVEPOINT)?;
+
+            match circ::mark_item_lost(self.editor, circ_id) {
+                Ok(()) => self.editor.release_savepoint(SAVEPOINT)?,
+                Err(e) => {
+                    log::error!("{self} cannot mark circ {circ_id} lost: {e}");
+                    self.editor.rollback_savepoint(SAVEPOINT)?;
+                    self.editor.release_savepoint(SAVEPOINT)?;
+                    failures.push((idx, e.to_string()));
+                }
+            }
+        }
+
+        self.editor.commit()?;
+
+        Ok(failures)
+    }
+}
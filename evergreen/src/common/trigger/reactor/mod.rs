@@ -1,8 +1,76 @@
 //! Base module for A/T Reactors
 use crate::common::trigger::{Event, EventState, Processor};
 use crate::result::EgResult;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
 
 mod circ;
+mod email;
+mod lost;
+
+/// Routine which reacts to one or more events on behalf of a Processor.
+///
+/// See [`Processor::react`] for how event states are managed around
+/// the call.
+pub type ReactorHandler =
+    Arc<dyn Fn(&mut Processor, &mut [&mut Event]) -> EgResult<()> + Send + Sync>;
+
+/// Reactors by name, shared by all threads.
+static REACTORS: OnceLock<RwLock<HashMap<String, ReactorHandler>>> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<String, ReactorHandler>> {
+    REACTORS.get_or_init(|| {
+        let mut map: HashMap<String, ReactorHandler> = HashMap::new();
+
+        map.insert("NOOP_True".to_string(), Arc::new(|_, _| Ok(())));
+        map.insert(
+            "NOOP_False".to_string(),
+            Arc::new(|_, _| Err("NOOP_False".into())),
+        );
+        map.insert(
+            "Circ::AutoRenew".to_string(),
+            Arc::new(|p, events| p.autorenew(events)),
+        );
+        map.insert(
+            "Circ::MarkItemLost".to_string(),
+            Arc::new(|p, events| p.mark_item_lost(events)),
+        );
+        map.insert(
+            "SendEmail".to_string(),
+            Arc::new(|p, events| p.send_email(events)),
+        );
+
+        RwLock::new(map)
+    })
+}
+
+/// Register a reactor by name, making it available to all Processors
+/// whose event definition uses the name as its reactor.
+///
+/// Call this before processing any events.  Returns an Err if a
+/// reactor, including one of the built-in reactors, is already
+/// registered under the same name.
+pub fn register_reactor<F>(name: &str, handler: F) -> EgResult<()>
+where
+    F: Fn(&mut Processor, &mut [&mut Event]) -> EgResult<()> + Send + Sync + 'static,
+{
+    let mut reactors = registry()
+        .write()
+        .map_err(|e| format!("Reactor registry is poisoned: {e}"))?;
+
+    if reactors.contains_key(name) {
+        return Err(format!("Reactor '{name}' is already registered").into());
+    }
+
+    reactors.insert(name.to_string(), Arc::new(handler));
+
+    Ok(())
+}
+
+/// Returns the reactor registered under the provided name.
+pub fn lookup_reactor(name: &str) -> Option<ReactorHandler> {
+    registry().read().ok()?.get(name).cloned()
+}
 
 /// Add reactor routines to the Processor.
 impl Processor<'_> {
@@ -13,6 +81,16 @@ impl Processor<'_> {
     /// Reactors in Perl return true/false to indicate success,
     /// but the return value doesn't appear to be used, just the
     /// event state.
+    ///
+    /// Reactors may flag individual events as failed by setting their
+    /// state to Error.  Events still in the Reacting state once the
+    /// reactor completes are marked as Reacted.  If the reactor fails
+    /// outright, all events still in the Reacting state are rescheduled
+    /// if they have retry attempts remaining or marked as Error.
+    ///
+    /// Reacted events are then cleaned up and set to Complete.  Events
+    /// which ended in Error get the failure cleanup.  See
+    /// [`Processor::finish_reaction`].
     pub fn react(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
         let event_ids: Vec<String> = events.iter().map(|e| e.id().to_string()).collect();
 
@@ -22,30 +100,113 @@ impl Processor<'_> {
             return Ok(());
         }
 
+        if self.dry_run() {
+            log::info!("{self} dry run; skipping reactor {}", self.reactor());
+            return Ok(());
+        }
+
         for event in events.iter_mut() {
-            self.set_event_state(*event, EventState::Reacting)?;
+            self.start_reaction(event)?;
         }
 
-        let reactor = self.reactor();
+        let reactor = self.reactor().to_string();
 
         log::debug!(
             "{self} reacting with '{reactor}' on {} event(s)",
             events.len()
         );
 
-        let react_result = match reactor {
-            "NOOP_True" => Ok(()),
-            "NOOP_False" => Err(format!("NOOP_False").into()),
-            "Circ::AutoRenew" => self.autorenew(events),
-            _ => Err(format!("No such reactor: {reactor}").into()),
+        // Clone the handler out of the registry so the lock is not
+        // held while the reactor runs.
+        let react_result = match lookup_reactor(&reactor) {
+            Some(handler) => handler(self, events),
+            None => Err(format!("No such reactor: {reactor}").into()),
         };
 
-        if react_result.is_ok() {
-            for event in events.iter_mut() {
-                self.set_event_state(*event, EventState::Reacted)?;
+        for event in events.iter_mut() {
+            if event.state() != EventState::Reacting {
+                // Reactor set the state for this event.
+                continue;
+            }
+
+            match react_result.as_ref() {
+                Ok(()) => self.set_event_state(event, EventState::Reacted)?,
+                Err(e) => self.set_event_state_error_or_retry(event, &e.to_string())?,
+            }
+        }
+
+        let cleanup_result = self.finish_reaction(events);
+
+        react_result.and(cleanup_result)
+    }
+
+    /// Run the cleanups for reacted and failed events, setting the
+    /// reacted events to Complete once their cleanup succeeds.
+    ///
+    /// Events whose success cleanup fails are left in the Cleaning
+    /// state with the error text, which records that the reaction
+    /// succeeded though the event did not complete.  Events which
+    /// failed to react remain in the Error state regardless of the
+    /// outcome of the failure cleanup.
+    pub fn finish_reaction(&mut self, events: &mut [&mut Event]) -> EgResult<()> {
+        let mut reacted: Vec<&mut Event> = Vec::new();
+        let mut failed: Vec<&mut Event> = Vec::new();
+
+        for event in events.iter_mut() {
+            match event.state() {
+                EventState::Reacted => reacted.push(event),
+                EventState::Error => failed.push(event),
+                _ => {} // e.g. rescheduled for retry
+            }
+        }
+
+        let mut result = Ok(());
+
+        if !reacted.is_empty() {
+            if self.cleanup_success().is_some() {
+                for event in reacted.iter_mut() {
+                    self.set_event_state(event, EventState::Cleaning)?;
+                }
+                result = self.cleanup(&mut reacted, true);
+            }
+
+            match result.as_ref() {
+                Ok(()) => {
+                    for event in reacted.iter_mut() {
+                        self.set_event_state(event, EventState::Complete)?;
+                    }
+                }
+                Err(e) => {
+                    log::error!("{self} success cleanup failed: {e}");
+                    let error_text = format!("Cleanup failed: {e}");
+                    for event in reacted.iter_mut() {
+                        self.set_event_state_cleanup_error(event, &error_text)?;
+                    }
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            if let Err(e) = self.cleanup(&mut failed, false) {
+                log::error!("{self} failure cleanup failed: {e}");
+                if result.is_ok() {
+                    result = Err(e);
+                }
             }
         }
 
-        react_result
+        result
     }
 }
+
+#[test]
+fn register_reactor_rejects_duplicates() {
+    assert!(lookup_reactor("NOOP_True").is_some());
+    assert!(lookup_reactor("Test::NoSuchReactor").is_none());
+
+    register_reactor("Test::Registered", |_, _| Ok(())).expect("Registered");
+    assert!(lookup_reactor("Test::Registered").is_some());
+
+    assert!(register_reactor("Test::Registered", |_, _| Ok(())).is_err());
+    assert!(register_reactor("SendEmail", |_, _| Ok(())).is_err());
+}
@@ -0,0 +1,710 @@
+//! Parallel processing of pending A/T events.
+//!
+//! Pending events are partitioned into units of work by event
+//! definition and, for grouped definitions, by the first component of
+//! the group field, so all events which may land in the same group
+//! are processed by the same worker.
+//!
+//! In dry run mode, events are collected and validated without
+//! reacting to them or changing their stored state.  Events which
+//! would react are counted as reacted.
+//!
+//! When a run is stopped via its shutdown flag, workers finish their
+//! current event group, then return any events they left mid-process
+//! to pending so the next run picks them up.  Events left mid-process
+//! by a run which died outright can be recovered at startup with
+//! recover_stale_events().
+use crate as eg;
+use eg::common::trigger::{Event, EventState, Processor};
+use eg::date;
+use eg::idl;
+use eg::util::thread_id;
+use eg::Client;
+use eg::Editor;
+use eg::EgResult;
+use eg::EgValue;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Max number of target IDs to include in a single group field query.
+const GROUP_QUERY_CHUNK: usize = 500;
+
+/// States of events which are mid-process.  Interrupted events in
+/// these states are returned to pending.
+///
+/// Reacted and Cleaning events are excluded since their reactors
+/// have already run.
+pub const TRANSIENT_STATES: &[EventState] = &[
+    EventState::Collecting,
+    EventState::Collected,
+    EventState::Validating,
+    EventState::Valid,
+    EventState::Reacting,
+];
+
+/// Transactions returning interrupted events to pending are rolled
+/// back if they take longer than this, so shutdown is not held up.
+const FINALIZE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Processing stats for a single event definition.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DefStats {
+    pub event_def: i64,
+    /// Events handled, regardless of outcome.
+    pub processed: usize,
+    /// Events which completed successfully, or which passed
+    /// validation in a dry run.
+    pub reacted: usize,
+    /// Events which failed validation.
+    pub invalid: usize,
+    /// Events which ended in an error state or were rescheduled
+    /// for a retry.
+    pub errored: usize,
+    /// Total time spent processing events for this definition,
+    /// summed across workers.
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for DefStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "event_def={} processed={} reacted={} invalid={} errored={} elapsed={:.3}s",
+            self.event_def,
+            self.processed,
+            self.reacted,
+            self.invalid,
+            self.errored,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+impl DefStats {
+    fn new(event_def: i64) -> Self {
+        DefStats {
+            event_def,
+            ..Default::default()
+        }
+    }
+
+    fn add_state(&mut self, state: EventState) {
+        self.processed += 1;
+        match state {
+            // Valid events only remain Valid in a dry run.
+            EventState::Complete | EventState::Valid => self.reacted += 1,
+            EventState::Invalid => self.invalid += 1,
+            _ => self.errored += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &DefStats) {
+        self.processed += other.processed;
+        self.reacted += other.reacted;
+        self.invalid += other.invalid;
+        self.errored += other.errored;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// Events which must be processed together by a single worker.
+#[derive(Debug, Clone)]
+struct WorkUnit {
+    event_def: i64,
+    event_ids: Vec<i64>,
+    grouped: bool,
+}
+
+/// Stats for each event definition, sorted by ID.
+pub fn sorted_stats(stats: HashMap<i64, DefStats>) -> Vec<DefStats> {
+    let mut stats: Vec<DefStats> = stats.into_values().collect();
+    stats.sort_by_key(|s| s.event_def);
+    stats
+}
+
+/// True if any events ended in an error state.
+pub fn any_errors(stats: &[DefStats]) -> bool {
+    stats.iter().any(|s| s.errored > 0)
+}
+
+/// Format stats as a table with one row per event definition and
+/// a row of totals.
+pub fn summary_table(stats: &[DefStats]) -> String {
+    let mut table = format!(
+        "{:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+        "event_def", "processed", "reacted", "invalid", "errored", "elapsed"
+    );
+
+    let mut totals = DefStats::default();
+
+    for s in stats {
+        totals.merge(s);
+        table += &format!(
+            "{:>10} {:>10} {:>10} {:>10} {:>10} {:>9.3}s\n",
+            s.event_def,
+            s.processed,
+            s.reacted,
+            s.invalid,
+            s.errored,
+            s.elapsed.as_secs_f64()
+        );
+    }
+
+    table += &format!(
+        "{:>10} {:>10} {:>10} {:>10} {:>10} {:>9.3}s\n",
+        "total",
+        totals.processed,
+        totals.reacted,
+        totals.invalid,
+        totals.errored,
+        totals.elapsed.as_secs_f64()
+    );
+
+    table
+}
+
+/// Query selecting the IDs of active event definitions, optionally
+/// limited by granularity and ID.
+fn event_def_query(granularity: Option<&str>, event_defs: &[i64]) -> EgValue {
+    let mut query = eg::hash! {
+        select: {atevdef: ["id"]},
+        from: "atevdef",
+        where: {active: "t"},
+    };
+
+    if let Some(g) = granularity {
+        query["where"]["granularity"] = EgValue::from(g);
+    }
+
+    if !event_defs.is_empty() {
+        query["where"]["id"] = EgValue::from(event_defs.to_vec());
+    }
+
+    query
+}
+
+/// Find the next chunk of pending events, with IDs greater than
+/// `after`, whose run time has passed.
+///
+/// This includes failed events rescheduled for a retry, which are
+/// returned to the pending state with a future run time.
+pub fn fetch_pending_event_ids(
+    editor: &mut Editor,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+    after: Option<i64>,
+    limit: usize,
+) -> EgResult<Vec<i64>> {
+    let query = eg::hash! {
+        select: {atev: ["id"]},
+        from: "atev",
+        where: {
+            id: {">": after.unwrap_or(0)},
+            state: "pending",
+            run_time: {"<=": "now"},
+            event_def: {in: event_def_query(granularity, event_defs)},
+        },
+        order_by: [{class: "atev", field: "id"}],
+        limit: limit,
+    };
+
+    let mut ids = Vec::new();
+    for event in editor.json_query(query)? {
+        ids.push(event.id()?);
+    }
+
+    Ok(ids)
+}
+
+/// Process a single event, regardless of its current state, adding
+/// the outcome to the stats for its event definition.
+///
+/// Returns the final state of the event.
+pub fn process_event(
+    editor: &mut Editor,
+    event_id: i64,
+    dry_run: bool,
+    stats: &mut HashMap<i64, DefStats>,
+) -> EgResult<EventState> {
+    let atev = editor
+        .retrieve("atev", event_id)?
+        .ok_or_else(|| editor.die_event())?;
+
+    let def_id = atev["event_def"].int()?;
+    let mut event = Event::from_source(atev)?;
+
+    let start = Instant::now();
+
+    let mut proc = Processor::new(editor, def_id)?;
+    proc.set_dry_run(dry_run);
+
+    let result = proc.process_event(&mut event);
+
+    let def_stats = stats.entry(def_id).or_insert_with(|| DefStats::new(def_id));
+
+    def_stats.add_state(event.state());
+    def_stats.elapsed += start.elapsed();
+
+    result.map(|_| event.state())
+}
+
+/// True if events in this state are mid-process.
+pub fn is_transient(state: EventState) -> bool {
+    TRANSIENT_STATES.contains(&state)
+}
+
+fn transient_state_names() -> Vec<&'static str> {
+    TRANSIENT_STATES.iter().map(|s| (*s).into()).collect()
+}
+
+/// Return events from the list which this process left mid-process
+/// to pending, e.g. after a shutdown request.
+///
+/// Returns the number of events returned to pending.
+pub fn finalize_interrupted(editor: &mut Editor, event_ids: &[i64]) -> EgResult<usize> {
+    if event_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let query = eg::hash! {
+        select: {atev: ["id", "state", "update_time"]},
+        from: "atev",
+        where: {
+            id: event_ids.to_vec(),
+            state: transient_state_names(),
+            update_process: {like: format!("{}-%", process::id())},
+        },
+    };
+
+    let timeout = editor.xact_timeout();
+    editor.set_xact_timeout(Some(FINALIZE_GRACE_PERIOD));
+
+    let result = reset_events(editor, query, "Interrupted by trigger runner shutdown");
+
+    editor.set_xact_timeout(timeout);
+
+    result
+}
+
+/// Return events left mid-process for longer than `max_age`, an
+/// interval string, to pending.  Such events were most likely
+/// abandoned by a runner which exited without cleaning up.
+///
+/// Limited by granularity and event definition as in run_pending().
+/// Returns the number of events returned to pending.
+pub fn recover_stale_events(
+    editor: &mut Editor,
+    max_age: &str,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+) -> EgResult<usize> {
+    let cutoff = date::subtract_interval(date::now(), max_age)?;
+
+    let query = eg::hash! {
+        select: {atev: ["id", "state", "update_time"]},
+        from: "atev",
+        where: {
+            state: transient_state_names(),
+            update_time: {"<": date::to_iso(&cutoff)},
+            event_def: {in: event_def_query(granularity, event_defs)},
+        },
+        order_by: [{class: "atev", field: "id"}],
+    };
+
+    let count = reset_events(editor, query, "Recovered from an interrupted trigger run")?;
+
+    if count > 0 {
+        log::info!("Trigger runner recovered {count} stale event(s)");
+    }
+
+    Ok(count)
+}
+
+/// Return the events found by the query to pending.
+fn reset_events(editor: &mut Editor, query: EgValue, note: &str) -> EgResult<usize> {
+    let mut count = 0;
+
+    for seen in editor.json_query(query)? {
+        if reset_event_if_unchanged(editor, &seen, note)? {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Return an event to pending, storing `note` as its error output,
+/// unless its state or update time changed since it was read, e.g.
+/// because a runner on another host picked it up.
+///
+/// The event row is locked before it's compared, so a change made
+/// by another runner is either seen here or waits for our update.
+///
+/// Returns true if the event was returned to pending.
+pub fn reset_event_if_unchanged(editor: &mut Editor, seen: &EgValue, note: &str) -> EgResult<bool> {
+    let event_id = seen.id()?;
+
+    let mut editor = editor.xact_guard()?;
+
+    let mut atev = match editor.retrieve_for_update("atev", event_id)? {
+        Some(e) => e,
+        None => return Ok(false),
+    };
+
+    if atev["state"].as_str() != seen["state"].as_str()
+        || atev["update_time"].as_str() != seen["update_time"].as_str()
+    {
+        log::info!("Trigger event {event_id} changed underneath us; leaving it alone");
+        return Ok(false);
+    }
+
+    let output = EgValue::create("ateo", eg::hash! {data: note, is_error: true})?;
+    let output = editor.create(output)?;
+
+    log::info!(
+        "Returning trigger event {event_id} to pending from state {}",
+        atev["state"]
+    );
+
+    atev["state"] = EgValue::from("pending");
+    atev["error_output"] = output["id"].clone();
+    atev["update_time"] = EgValue::from("now");
+    atev["update_process"] = EgValue::from(format!("{}-{}", process::id(), thread_id()));
+
+    editor.update(atev)?;
+    editor.commit()?;
+
+    Ok(true)
+}
+
+/// Process all pending events whose run time has passed using
+/// `parallel` worker threads, each with its own OpenSRF connection.
+///
+/// If a granularity is provided, only events for definitions with
+/// that granularity are processed.  If event definition IDs are
+/// provided, only events for those definitions are processed.
+///
+/// Once `shutdown` is set, workers stop after their current event
+/// group and return any events they left mid-process to pending.
+///
+/// Returns stats for each event definition, sorted by ID.
+pub fn run_pending(
+    client: &Client,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+    parallel: usize,
+    dry_run: bool,
+    shutdown: Arc<AtomicBool>,
+) -> EgResult<Vec<DefStats>> {
+    let start = Instant::now();
+    let mut editor = Editor::new(client);
+
+    let units = build_work_units(&mut editor, granularity, event_defs)?;
+
+    log::info!(
+        "Trigger runner processing {} work units with {parallel} worker(s)",
+        units.len()
+    );
+
+    let queue = Arc::new(Mutex::new(VecDeque::from(units)));
+    let mut handles = Vec::new();
+
+    for worker in 0..parallel.max(1) {
+        let queue = queue.clone();
+        let shutdown = shutdown.clone();
+        handles.push(thread::spawn(move || {
+            run_worker(worker, queue, dry_run, shutdown)
+        }));
+    }
+
+    let mut totals: HashMap<i64, DefStats> = HashMap::new();
+
+    for handle in handles {
+        let stats = handle
+            .join()
+            .map_err(|_| "Trigger runner worker panicked".to_string())??;
+
+        for (def_id, def_stats) in stats {
+            totals
+                .entry(def_id)
+                .or_insert_with(|| DefStats::new(def_id))
+                .merge(&def_stats);
+        }
+    }
+
+    let stats = sorted_stats(totals);
+
+    for s in stats.iter() {
+        log::info!("Trigger runner finished {s}");
+    }
+
+    log::info!(
+        "Trigger runner completed in {:.3}s",
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(stats)
+}
+
+/// Process work units until the queue is empty or a shutdown is
+/// requested.
+fn run_worker(
+    worker: usize,
+    queue: Arc<Mutex<VecDeque<WorkUnit>>>,
+    dry_run: bool,
+    shutdown: Arc<AtomicBool>,
+) -> EgResult<HashMap<i64, DefStats>> {
+    // OpenSRF clients cannot be shared across threads.
+    let client = eg::init::init_from_parts()?;
+    let mut editor = Editor::new(&client);
+
+    let mut stats: HashMap<i64, DefStats> = HashMap::new();
+
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            log::info!("Trigger worker {worker} stopping on shutdown request");
+            break;
+        }
+
+        let unit = match queue.lock().unwrap().pop_front() {
+            Some(u) => u,
+            None => break,
+        };
+
+        let def_stats = stats
+            .entry(unit.event_def)
+            .or_insert_with(|| DefStats::new(unit.event_def));
+
+        let start = Instant::now();
+
+        let result = process_unit(&mut editor, &unit, dry_run, &shutdown, def_stats);
+
+        if let Err(e) = result {
+            log::error!(
+                "Trigger worker {worker} failed processing events {:?}: {e}",
+                unit.event_ids
+            );
+            // Start fresh in case we died mid-transaction.
+            editor = Editor::new(&client);
+        }
+
+        def_stats.elapsed += start.elapsed();
+
+        if shutdown.load(Ordering::Relaxed) && !dry_run {
+            if let Err(e) = finalize_interrupted(&mut editor, &unit.event_ids) {
+                log::error!("Trigger worker {worker} cannot reset interrupted events: {e}");
+            }
+        }
+    }
+
+    log::debug!("Trigger worker {worker} exiting");
+
+    Ok(stats)
+}
+
+fn process_unit(
+    editor: &mut Editor,
+    unit: &WorkUnit,
+    dry_run: bool,
+    shutdown: &Arc<AtomicBool>,
+    stats: &mut DefStats,
+) -> EgResult<()> {
+    let mut events = Vec::new();
+    for id in unit.event_ids.iter() {
+        let atev = editor
+            .retrieve("atev", *id)?
+            .ok_or_else(|| editor.die_event())?;
+
+        events.push(Event::from_source(atev)?);
+    }
+
+    let mut proc = Processor::new(editor, unit.event_def)?;
+    proc.set_dry_run(dry_run);
+    proc.set_shutdown_flag(shutdown.clone());
+
+    if !unit.grouped {
+        for mut event in events {
+            let result = proc.process_event(&mut event);
+            stats.add_state(event.state());
+            result?;
+        }
+        return Ok(());
+    }
+
+    // A unit may contain multiple groups when the group field
+    // is more than one level deep.
+    let result = proc.process_grouped_events(&mut events);
+
+    // Events skipped by a shutdown will be processed by a later run.
+    let interrupted = proc.shutdown_requested();
+
+    for event in events.iter() {
+        if !(interrupted && is_transient(event.state())) {
+            stats.add_state(event.state());
+        }
+    }
+
+    result
+}
+
+/// Find pending events and partition them into work units.
+fn build_work_units(
+    editor: &mut Editor,
+    granularity: Option<&str>,
+    event_defs: &[i64],
+) -> EgResult<Vec<WorkUnit>> {
+    let query = eg::hash! {
+        select: {atev: ["id", "event_def", "target"]},
+        from: "atev",
+        where: {
+            state: "pending",
+            run_time: {"<=": "now"},
+            event_def: {in: event_def_query(granularity, event_defs)},
+        },
+        order_by: [{class: "atev", field: "id"}],
+    };
+
+    // Event ID and target, by event definition.
+    let mut pending: HashMap<i64, Vec<(i64, EgValue)>> = HashMap::new();
+
+    for mut event in editor.json_query(query)? {
+        pending
+            .entry(event["event_def"].int()?)
+            .or_default()
+            .push((event.id()?, event["target"].take()));
+    }
+
+    let mut units = Vec::new();
+
+    for (def_id, events) in pending {
+        let flesh = eg::hash! {"flesh": 1, "flesh_fields": {"atevdef": ["hook"]}};
+
+        let def = editor
+            .retrieve_with_ops("atevdef", def_id, flesh)?
+            .ok_or_else(|| editor.die_event())?;
+
+        let group_field = match def["group_field"].as_str() {
+            Some(g) => g,
+            None => {
+                units.extend(events.into_iter().map(|(id, _)| WorkUnit {
+                    event_def: def_id,
+                    event_ids: vec![id],
+                    grouped: false,
+                }));
+                continue;
+            }
+        };
+
+        let core_type = def["hook"]["core_type"].str()?;
+        let first_field = group_field.split('.').next().unwrap_or(group_field);
+
+        let keys = group_keys(editor, core_type, first_field, &events)?;
+
+        let mut buckets: HashMap<String, Vec<i64>> = HashMap::new();
+        for (id, target) in events {
+            let key = keys.get(&target.dump()).cloned().unwrap_or_default();
+            buckets.entry(key).or_default().push(id);
+        }
+
+        units.extend(buckets.into_values().map(|event_ids| WorkUnit {
+            event_def: def_id,
+            event_ids,
+            grouped: true,
+        }));
+    }
+
+    Ok(units)
+}
+
+/// Map each event target (as JSON text) to its value for the
+/// provided field.
+fn group_keys(
+    editor: &mut Editor,
+    core_type: &str,
+    field: &str,
+    events: &[(i64, EgValue)],
+) -> EgResult<HashMap<String, String>> {
+    let class = idl::get_class(core_type)?;
+    let pkey = class
+        .pkey()
+        .ok_or_else(|| format!("IDL class {core_type} has no primary key"))?;
+
+    if !class.has_field(field) {
+        return Err(format!("Invalid group field for {core_type}: {field}").into());
+    }
+
+    let mut keys = HashMap::new();
+
+    for chunk in events.chunks(GROUP_QUERY_CHUNK) {
+        let targets: Vec<EgValue> = chunk.iter().map(|(_, t)| t.clone()).collect();
+
+        let mut query = eg::hash! {
+            select: {},
+            from: core_type,
+            where: {},
+        };
+
+        query["select"][core_type] = EgValue::from(vec![EgValue::from(pkey), EgValue::from(field)]);
+        query["where"][pkey] = EgValue::from(targets);
+
+        for row in editor.json_query(query)? {
+            keys.insert(row[pkey].dump(), row[field].dump());
+        }
+    }
+
+    Ok(keys)
+}
+
+#[test]
+fn test_summary_table() {
+    let mut stats = HashMap::new();
+
+    let mut def2 = DefStats::new(2);
+    def2.add_state(EventState::Complete);
+    def2.add_state(EventState::Error);
+    stats.insert(2, def2);
+
+    let mut def1 = DefStats::new(1);
+    def1.add_state(EventState::Invalid);
+    // Left valid by a dry run.
+    def1.add_state(EventState::Valid);
+    stats.insert(1, def1);
+
+    let stats = sorted_stats(stats);
+    assert_eq!(stats[0].event_def, 1);
+    assert_eq!(stats[0].reacted, 1);
+    assert!(any_errors(&stats));
+
+    let table = summary_table(&stats);
+    let lines: Vec<&str> = table.lines().collect();
+
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].contains("errored"));
+    assert_eq!(
+        lines[3].split_whitespace().collect::<Vec<&str>>()[..5],
+        ["total", "4", "2", "1", "1"]
+    );
+}
+
+#[test]
+fn test_transient_states() {
+    assert!(is_transient(EventState::Collected));
+    assert!(is_transient(EventState::Reacting));
+
+    // Reactors have already run for these.
+    assert!(!is_transient(EventState::Reacted));
+    assert!(!is_transient(EventState::Cleaning));
+
+    assert!(!is_transient(EventState::Pending));
+    assert!(!is_transient(EventState::Complete));
+
+    assert_eq!(
+        transient_state_names(),
+        ["collecting", "collected", "validating", "valid", "reacting"]
+    );
+}
@@ -0,0 +1,733 @@
+//! Minimal template engine for A/T event templates.
+//!
+//! Supports a subset of Template Toolkit (TT2) syntax, enough for
+//! typical notice templates:
+//!
+//! * `[% target.usr.email %]` -- print a value.  Path components may
+//!   be hash keys, IDL field names, or array indexes (`target.0.usr`).
+//! * `[% value | html %]` -- filters: html, upper, lower, trim.
+//! * `[% x = target.0.usr %]` / `[% SET x = ... %]` -- local variables.
+//! * `[% IF expr %]`, `[% ELSIF expr %]`, `[% ELSE %]`, `[% UNLESS expr %]`
+//! * `[% FOR item IN list %]` / `[% FOREACH ... %]`, with `loop.index`,
+//!   `loop.count`, `loop.first`, and `loop.last`.
+//! * Expressions: string and number literals, `==`, `!=`, `AND` / `&&`,
+//!   `OR` / `||`, `NOT` / `!`, and parentheses.
+//! * `[%# comment %]`, and `[%-` / `-%]` whitespace chomping.
+//!
+//! `USE` directives are ignored.  Plugins (e.g. `date.format()`),
+//! method calls, `helpers`, macros, `INCLUDE`, and arithmetic are not
+//! supported and produce a render error, rather than silently
+//! producing a partial notice.
+use crate as eg;
+use eg::EgResult;
+use eg::EgValue;
+use std::collections::HashMap;
+
+const TAG_START: &str = "[%";
+const TAG_END: &str = "%]";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(EgValue),
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Print(Expr, Vec<String>),
+    Set(String, Expr),
+    /// Each branch is a condition and its body.  ELSE has no condition.
+    If(Vec<(Option<Expr>, Vec<Node>)>),
+    For(String, Expr, Vec<Node>),
+}
+
+/// A parsed template which may be rendered any number of times.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> EgResult<Template> {
+        let tokens = tokenize(text)?;
+        let mut pos = 0;
+
+        let (nodes, end) = parse_nodes(&tokens, &mut pos)?;
+
+        if let Some(tag) = end {
+            return Err(format!("Template has unexpected '{tag}'").into());
+        }
+
+        Ok(Template { nodes })
+    }
+
+    /// Render the template.  Top-level keys of `env` are available
+    /// as template variables.
+    pub fn render(&self, env: &EgValue) -> EgResult<String> {
+        let mut ctx = Context {
+            env,
+            locals: Vec::new(),
+        };
+
+        let mut output = String::new();
+        render_nodes(&self.nodes, &mut ctx, &mut output)?;
+
+        Ok(output)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+/// Split the template into text and directive tokens, applying
+/// comments and whitespace chomping along the way.
+fn tokenize(text: &str) -> EgResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    let mut chomp_next = false;
+
+    while !rest.is_empty() {
+        let (mut chunk, tag) = match rest.find(TAG_START) {
+            Some(start) => {
+                let chunk = &rest[..start];
+                let after = &rest[start + TAG_START.len()..];
+                let end = after
+                    .find(TAG_END)
+                    .ok_or_else(|| format!("Unterminated template tag: {}", &rest[start..]))?;
+
+                rest = &after[end + TAG_END.len()..];
+                (chunk, Some(&after[..end]))
+            }
+            None => {
+                let chunk = rest;
+                rest = "";
+                (chunk, None)
+            }
+        };
+
+        if chomp_next {
+            chunk = chomp_start(chunk);
+            chomp_next = false;
+        }
+
+        let tag = match tag {
+            Some(t) => t,
+            None => {
+                push_text(&mut tokens, chunk);
+                break;
+            }
+        };
+
+        let mut tag = tag;
+        if let Some(t) = tag.strip_prefix('-') {
+            chunk = chomp_end(chunk);
+            tag = t;
+        }
+        if let Some(t) = tag.strip_suffix('-') {
+            chomp_next = true;
+            tag = t;
+        }
+
+        push_text(&mut tokens, chunk);
+
+        let tag = tag.trim();
+        if !tag.starts_with('#') && !tag.is_empty() {
+            tokens.push(Token::Tag(tag.to_string()));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn push_text(tokens: &mut Vec<Token>, text: &str) {
+    if !text.is_empty() {
+        tokens.push(Token::Text(text.to_string()));
+    }
+}
+
+/// Remove leading spaces/tabs and one newline.
+fn chomp_start(s: &str) -> &str {
+    let s = s.trim_start_matches([' ', '\t']);
+    s.strip_prefix("\r\n")
+        .or_else(|| s.strip_prefix('\n'))
+        .unwrap_or(s)
+}
+
+/// Remove trailing spaces/tabs and one newline.
+fn chomp_end(s: &str) -> &str {
+    let s = s.trim_end_matches([' ', '\t']);
+    s.strip_suffix("\r\n")
+        .or_else(|| s.strip_suffix('\n'))
+        .unwrap_or(s)
+}
+
+/// Parse nodes until the end of input or a block-ending tag
+/// (END, ELSE, ELSIF), which is returned along with the nodes.
+fn parse_nodes(tokens: &[Token], pos: &mut usize) -> EgResult<(Vec<Node>, Option<String>)> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.get(*pos) {
+        *pos += 1;
+
+        let tag = match token {
+            Token::Text(t) => {
+                nodes.push(Node::Text(t.to_string()));
+                continue;
+            }
+            Token::Tag(t) => t.as_str(),
+        };
+
+        let (keyword, args) = match tag.split_once(char::is_whitespace) {
+            Some((k, a)) => (k, a.trim()),
+            None => (tag, ""),
+        };
+
+        match keyword {
+            "END" | "ELSE" | "ELSIF" => return Ok((nodes, Some(tag.to_string()))),
+            "USE" => {}
+            "IF" | "UNLESS" => nodes.push(parse_if(keyword, args, tokens, pos)?),
+            "FOR" | "FOREACH" => nodes.push(parse_for(args, tokens, pos)?),
+            "SET" => nodes.push(parse_set(args)?),
+            "GET" => nodes.push(parse_print(args)?),
+            _ => {
+                if is_assignment(tag) {
+                    nodes.push(parse_set(tag)?);
+                } else {
+                    nodes.push(parse_print(tag)?);
+                }
+            }
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_if(keyword: &str, args: &str, tokens: &[Token], pos: &mut usize) -> EgResult<Node> {
+    let mut cond = parse_expr(args)?;
+    if keyword == "UNLESS" {
+        cond = Expr::Not(Box::new(cond));
+    }
+
+    let mut branches = Vec::new();
+    let mut cond = Some(cond);
+
+    loop {
+        let (body, end) = parse_nodes(tokens, pos)?;
+        branches.push((cond, body));
+
+        let end = end.ok_or_else(|| format!("Missing END for {keyword} {args}"))?;
+
+        if end == "END" {
+            break;
+        } else if end == "ELSE" {
+            cond = None;
+        } else if let Some(expr) = end.strip_prefix("ELSIF") {
+            cond = Some(parse_expr(expr)?);
+        } else {
+            return Err(format!("Unexpected '{end}' in {keyword} block").into());
+        }
+
+        if branches.last().map(|(c, _)| c.is_none()).unwrap_or(false) {
+            return Err(format!("ELSE must be the last branch of {keyword} {args}").into());
+        }
+    }
+
+    Ok(Node::If(branches))
+}
+
+fn parse_for(args: &str, tokens: &[Token], pos: &mut usize) -> EgResult<Node> {
+    let (var, list) = args
+        .split_once(" IN ")
+        .ok_or_else(|| format!("Invalid FOR directive: {args}"))?;
+
+    let var = var.trim();
+    if !is_identifier(var) {
+        return Err(format!("Invalid FOR variable: {var}").into());
+    }
+
+    let list = parse_expr(list)?;
+
+    let (body, end) = parse_nodes(tokens, pos)?;
+
+    if end.as_deref() != Some("END") {
+        return Err(format!("Missing END for FOR {args}").into());
+    }
+
+    Ok(Node::For(var.to_string(), list, body))
+}
+
+fn is_assignment(tag: &str) -> bool {
+    match tag.split_once('=') {
+        Some((var, value)) => is_identifier(var.trim()) && !value.starts_with('='),
+        None => false,
+    }
+}
+
+fn parse_set(args: &str) -> EgResult<Node> {
+    let (var, value) = args
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid SET directive: {args}"))?;
+
+    let var = var.trim();
+    if !is_identifier(var) {
+        return Err(format!("Invalid variable name: {var}").into());
+    }
+
+    Ok(Node::Set(var.to_string(), parse_expr(value)?))
+}
+
+fn parse_print(args: &str) -> EgResult<Node> {
+    let mut parts = args.split('|');
+
+    let expr = parse_expr(parts.next().unwrap_or(""))?;
+
+    let mut filters = Vec::new();
+    for filter in parts {
+        let filter = filter.trim();
+        match filter {
+            "html" | "upper" | "lower" | "trim" => filters.push(filter.to_string()),
+            _ => return Err(format!("Unsupported template filter: {filter}").into()),
+        }
+    }
+
+    Ok(Node::Print(expr, filters))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expression tokens
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    Str(String),
+    Num(String),
+    Word(String),
+    Op(&'static str),
+}
+
+fn tokenize_expr(s: &str) -> EgResult<Vec<ExprToken>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c == '\'' || c == '"' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\\') => {
+                        if let Some(n) = chars.get(i + 1) {
+                            value.push(*n);
+                        }
+                        i += 2;
+                    }
+                    Some(ch) if *ch == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        value.push(*ch);
+                        i += 1;
+                    }
+                    None => return Err(format!("Unterminated string in '{s}'").into()),
+                }
+            }
+            tokens.push(ExprToken::Str(value));
+            continue;
+        }
+
+        let two: String = chars[i..].iter().take(2).collect();
+        let op = match two.as_str() {
+            "==" => Some("=="),
+            "!=" => Some("!="),
+            "&&" => Some("AND"),
+            "||" => Some("OR"),
+            _ => None,
+        };
+
+        if let Some(op) = op {
+            tokens.push(ExprToken::Op(op));
+            i += 2;
+            continue;
+        }
+
+        match c {
+            '!' => {
+                tokens.push(ExprToken::Op("NOT"));
+                i += 1;
+                continue;
+            }
+            '(' => {
+                tokens.push(ExprToken::Op("("));
+                i += 1;
+                continue;
+            }
+            ')' => {
+                tokens.push(ExprToken::Op(")"));
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        if c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).map(|n| n.is_ascii_digit()) == Some(true))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(ExprToken::Num(chars[start..i].iter().collect()));
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+
+            let word: String = chars[start..i].iter().collect();
+            match word.as_str() {
+                "AND" | "and" => tokens.push(ExprToken::Op("AND")),
+                "OR" | "or" => tokens.push(ExprToken::Op("OR")),
+                "NOT" | "not" => tokens.push(ExprToken::Op("NOT")),
+                _ => tokens.push(ExprToken::Word(word)),
+            }
+            continue;
+        }
+
+        return Err(format!("Unsupported template expression: '{s}'").into());
+    }
+
+    Ok(tokens)
+}
+
+fn parse_expr(s: &str) -> EgResult<Expr> {
+    let tokens = tokenize_expr(s)?;
+    let mut pos = 0;
+
+    let expr = parse_or(&tokens, &mut pos, s)?;
+
+    if pos != tokens.len() {
+        return Err(format!("Unsupported template expression: '{}'", s.trim()).into());
+    }
+
+    Ok(expr)
+}
+
+fn parse_or(tokens: &[ExprToken], pos: &mut usize, src: &str) -> EgResult<Expr> {
+    let mut left = parse_and(tokens, pos, src)?;
+
+    while tokens.get(*pos) == Some(&ExprToken::Op("OR")) {
+        *pos += 1;
+        let right = parse_and(tokens, pos, src)?;
+        left = Expr::Or(Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+}
+
+fn parse_and(tokens: &[ExprToken], pos: &mut usize, src: &str) -> EgResult<Expr> {
+    let mut left = parse_not(tokens, pos, src)?;
+
+    while tokens.get(*pos) == Some(&ExprToken::Op("AND")) {
+        *pos += 1;
+        let right = parse_not(tokens, pos, src)?;
+        left = Expr::And(Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+}
+
+fn parse_not(tokens: &[ExprToken], pos: &mut usize, src: &str) -> EgResult<Expr> {
+    if tokens.get(*pos) == Some(&ExprToken::Op("NOT")) {
+        *pos += 1;
+        return Ok(Expr::Not(Box::new(parse_not(tokens, pos, src)?)));
+    }
+
+    let left = parse_primary(tokens, pos, src)?;
+
+    match tokens.get(*pos) {
+        Some(ExprToken::Op("==")) => {
+            *pos += 1;
+            let right = parse_primary(tokens, pos, src)?;
+            Ok(Expr::Eq(Box::new(left), Box::new(right)))
+        }
+        Some(ExprToken::Op("!=")) => {
+            *pos += 1;
+            let right = parse_primary(tokens, pos, src)?;
+            Ok(Expr::Ne(Box::new(left), Box::new(right)))
+        }
+        _ => Ok(left),
+    }
+}
+
+fn parse_primary(tokens: &[ExprToken], pos: &mut usize, src: &str) -> EgResult<Expr> {
+    let token = tokens
+        .get(*pos)
+        .ok_or_else(|| format!("Incomplete template expression: '{}'", src.trim()))?;
+
+    *pos += 1;
+
+    match token {
+        ExprToken::Str(s) => Ok(Expr::Literal(EgValue::from(s.as_str()))),
+        ExprToken::Num(n) => {
+            let num = n
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number in template: {n}"))?;
+            Ok(Expr::Literal(EgValue::from(num)))
+        }
+        ExprToken::Word(w) => {
+            let parts: Vec<String> = w.split('.').map(|p| p.to_string()).collect();
+            if parts.iter().any(|p| p.is_empty()) {
+                return Err(format!("Invalid template variable: {w}").into());
+            }
+            Ok(Expr::Path(parts))
+        }
+        ExprToken::Op("(") => {
+            let expr = parse_or(tokens, pos, src)?;
+            if tokens.get(*pos) != Some(&ExprToken::Op(")")) {
+                return Err(format!("Unbalanced parentheses in '{}'", src.trim()).into());
+            }
+            *pos += 1;
+            Ok(expr)
+        }
+        _ => Err(format!("Unsupported template expression: '{}'", src.trim()).into()),
+    }
+}
+
+struct Context<'a> {
+    env: &'a EgValue,
+    /// Local variable scopes, innermost last.
+    locals: Vec<HashMap<String, EgValue>>,
+}
+
+impl Context<'_> {
+    fn set(&mut self, name: &str, value: EgValue) {
+        if self.locals.is_empty() {
+            self.locals.push(HashMap::new());
+        }
+        self.locals
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), value);
+    }
+
+    fn lookup(&self, path: &[String]) -> EgResult<EgValue> {
+        let first = &path[0];
+
+        let mut value = self
+            .locals
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(first))
+            .unwrap_or(&self.env[first.as_str()]);
+
+        for part in &path[1..] {
+            value = child(value, part, path)?;
+        }
+
+        Ok(value.clone())
+    }
+
+    fn eval(&self, expr: &Expr) -> EgResult<EgValue> {
+        Ok(match expr {
+            Expr::Literal(v) => v.clone(),
+            Expr::Path(p) => self.lookup(p)?,
+            Expr::Not(e) => EgValue::from(!truthy(&self.eval(e)?)),
+            Expr::And(l, r) => EgValue::from(truthy(&self.eval(l)?) && truthy(&self.eval(r)?)),
+            Expr::Or(l, r) => EgValue::from(truthy(&self.eval(l)?) || truthy(&self.eval(r)?)),
+            Expr::Eq(l, r) => EgValue::from(stringify(&self.eval(l)?) == stringify(&self.eval(r)?)),
+            Expr::Ne(l, r) => EgValue::from(stringify(&self.eval(l)?) != stringify(&self.eval(r)?)),
+        })
+    }
+}
+
+/// Access a hash key, IDL field, or array index.
+fn child<'a>(value: &'a EgValue, part: &str, path: &[String]) -> EgResult<&'a EgValue> {
+    if value.is_array() {
+        return match part.parse::<usize>() {
+            Ok(i) => Ok(&value[i]),
+            Err(_) => Err(format!("Cannot access '{part}' on a list in {}", path.join(".")).into()),
+        };
+    }
+
+    if let Some(class) = value.idl_class() {
+        // Indexing an IDL object with an unknown field panics.
+        if !class.has_field(part) {
+            return Err(format!(
+                "IDL class {} has no field '{part}' in {}",
+                class.classname(),
+                path.join(".")
+            )
+            .into());
+        }
+    }
+
+    Ok(&value[part])
+}
+
+/// TT2 truthiness: undef, empty strings, and zero are false.
+fn truthy(v: &EgValue) -> bool {
+    match v {
+        EgValue::Null => false,
+        EgValue::Boolean(b) => *b,
+        EgValue::Number(_) => v.as_f64().map(|n| n != 0.0).unwrap_or(false),
+        EgValue::String(s) => !s.is_empty() && s != "0",
+        _ => true,
+    }
+}
+
+fn stringify(v: &EgValue) -> String {
+    match v {
+        EgValue::Null => String::new(),
+        EgValue::Boolean(b) => if *b { "1" } else { "" }.to_string(),
+        EgValue::String(s) => s.to_string(),
+        EgValue::Number(n) => format!("{n}"),
+        _ => v.dump(),
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_nodes(nodes: &[Node], ctx: &mut Context, output: &mut String) -> EgResult<()> {
+    for node in nodes {
+        match node {
+            Node::Text(t) => output.push_str(t),
+            Node::Print(expr, filters) => {
+                let mut value = stringify(&ctx.eval(expr)?);
+                for filter in filters {
+                    value = match filter.as_str() {
+                        "html" => escape_html(&value),
+                        "upper" => value.to_uppercase(),
+                        "lower" => value.to_lowercase(),
+                        _ => value.trim().to_string(),
+                    };
+                }
+                output.push_str(&value);
+            }
+            Node::Set(name, expr) => {
+                let value = ctx.eval(expr)?;
+                ctx.set(name, value);
+            }
+            Node::If(branches) => {
+                for (cond, body) in branches {
+                    let matched = match cond {
+                        Some(c) => truthy(&ctx.eval(c)?),
+                        None => true,
+                    };
+                    if matched {
+                        render_nodes(body, ctx, output)?;
+                        break;
+                    }
+                }
+            }
+            Node::For(var, list, body) => {
+                let list = ctx.eval(list)?;
+
+                let items: Vec<EgValue> = if list.is_array() {
+                    list.members().cloned().collect()
+                } else if list.is_null() {
+                    Vec::new()
+                } else {
+                    // TT2 treats a single value as a one-item list.
+                    vec![list]
+                };
+
+                let count = items.len();
+                for (idx, item) in items.into_iter().enumerate() {
+                    let mut scope = HashMap::new();
+                    scope.insert(var.to_string(), item);
+                    scope.insert(
+                        "loop".to_string(),
+                        eg::hash! {
+                            "index": idx,
+                            "count": idx + 1,
+                            "first": idx == 0,
+                            "last": idx + 1 == count,
+                            "size": count,
+                        },
+                    );
+
+                    ctx.locals.push(scope);
+                    let result = render_nodes(body, ctx, output);
+                    ctx.locals.pop();
+                    result?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_template_render() {
+    let env = eg::hash! {
+        "target": [
+            {"title": "Fish & Chips", "due_date": "2024-01-02", "usr": {"first_given_name": "Jane"}},
+            {"title": "Moby Dick", "due_date": "2024-01-03", "usr": {"first_given_name": "Jane"}},
+        ],
+        "user_data": {"is_renewed": true, "count": 0},
+        "params": {"subject": "Overdue"},
+    };
+
+    let text = r#"[%- user = target.0.usr -%]
+Subject: [% params.subject | upper %]
+
+Dear [% user.first_given_name %],
+[%# comments are dropped %]
+[% FOR circ IN target -%]
+[% loop.count %]. [% circ.title | html %][% IF loop.last %].[% ELSE %],[% END %]
+[% END -%]
+[% IF user_data.count %]count[% ELSIF user_data.is_renewed AND NOT user_data.missing %]renewed[% END %]
+[% UNLESS params.subject == 'Overdue' %]not overdue[% END %]"#;
+
+    let output = Template::parse(text).unwrap().render(&env).unwrap();
+
+    assert_eq!(
+        output,
+        "Subject: OVERDUE\n\nDear Jane,\n\n1. Fish &amp; Chips,\n2. Moby Dick.\nrenewed\n"
+    );
+
+    // Unsupported syntax fails instead of producing a partial notice.
+    assert!(Template::parse("[% date.format(circ.due_date) %]").is_err());
+    assert!(Template::parse("[% IF target %]no end").is_err());
+    assert!(Template::parse("[% END %]").is_err());
+    assert!(Template::parse("[% x | currency %]").is_err());
+
+    let tmpl = Template::parse("[% target.title.0 %]").unwrap();
+    assert!(tmpl.render(&env).is_err());
+}
@@ -4,6 +4,7 @@ use eg::common::holdings;
 use eg::common::trigger::{Event, EventState, Processor};
 use eg::constants as C;
 use eg::date;
+use eg::date::EgDate;
 use eg::EgResult;
 
 /// Add validation routines to the Processor.
@@ -30,6 +31,7 @@ impl Processor<'_> {
             "HoldIsAvailable" => self.hold_is_available(event),
             "HoldIsCancelled" => self.hold_is_canceled(event),
             "HoldNotifyCheck" => self.hold_notify_check(event),
+            "MaxPassiveDelayAge" => self.max_passive_delay_age(event),
             "MinPassiveTargetAge" => self.min_passive_target_age(event),
             "PatronBarred" => self.patron_is_barred(event),
             "PatronNotBarred" => self.patron_is_barred(event).map(|val| !val),
@@ -39,7 +41,7 @@ impl Processor<'_> {
 
         if let Ok(valid) = validate_result {
             if valid {
-                self.set_event_state(event, EventState::Validating)?;
+                self.set_event_state(event, EventState::Valid)?;
             } else {
                 self.set_event_state(event, EventState::Invalid)?;
             }
@@ -114,9 +116,37 @@ impl Processor<'_> {
 
         // due_date is a required string field.
         let due_date = event.target()["due_date"].as_str().unwrap();
-        let due_date_ts = date::parse_datetime(due_date)?;
+        let grace_period = self.param_value_as_str("grace_period");
 
-        Ok(due_date_ts < date::now())
+        due_date_has_passed(due_date, grace_period, &date::now())
+    }
+
+    /// True if the event's passive delay field value is no older
+    /// than the max_delay_age parameter.
+    ///
+    /// Prevents old events from firing when a passive event
+    /// definition is (re-)activated or the runner falls behind.
+    fn max_passive_delay_age(&mut self, event: &Event) -> EgResult<bool> {
+        let max_age = self
+            .param_value_as_str("max_delay_age")
+            .ok_or("'max_delay_age' parameter required for MaxPassiveDelayAge")?
+            .to_string();
+
+        let delay_field = self.event_def()["delay_field"]
+            .as_str()
+            .ok_or("MaxPassiveDelayAge requires a passive event definition")?;
+
+        let delay_val = &event.target()[delay_field];
+        let delay_str = delay_val.as_str().ok_or_else(|| {
+            format!(
+                "MaxPassiveDelayAge delay field {delay_field} has unexpected value: {}",
+                delay_val.dump()
+            )
+        })?;
+
+        let cutoff = date::add_interval(date::parse_datetime(delay_str)?, &max_age)?;
+
+        Ok(cutoff > date::now())
     }
 
     /// True if the hold is ready for pickup.
@@ -226,3 +256,39 @@ impl Processor<'_> {
 
     // TODO PatronNotInCollections
 }
+
+/// True if the due date, plus the optional grace period, is earlier
+/// than the provided time.
+///
+/// A circulation is not overdue at exactly its due date.
+/// Comparisons are timezone-aware.
+fn due_date_has_passed(due_date: &str, grace_period: Option<&str>, now: &EgDate) -> EgResult<bool> {
+    let mut due_date = date::parse_datetime(due_date)?;
+
+    if let Some(grace) = grace_period {
+        due_date = date::add_interval(due_date, grace)?;
+    }
+
+    Ok(due_date < *now)
+}
+
+#[test]
+fn test_due_date_has_passed() {
+    let now = date::parse_datetime("2024-03-01T12:00:00-0500").unwrap();
+
+    // Exactly at the due date is not overdue.
+    assert!(!due_date_has_passed("2024-03-01T12:00:00-0500", None, &now).unwrap());
+    assert!(due_date_has_passed("2024-03-01T11:59:59-0500", None, &now).unwrap());
+
+    // Same instant expressed in different timezones.
+    assert!(!due_date_has_passed("2024-03-01T17:00:00+0000", None, &now).unwrap());
+    assert!(!due_date_has_passed("2024-03-01T09:00:00-0800", None, &now).unwrap());
+    assert!(due_date_has_passed("2024-03-01T16:59:59Z", None, &now).unwrap());
+    assert!(!due_date_has_passed("2024-03-01T18:00:00+0100", None, &now).unwrap());
+
+    // Grace period pushes the boundary out.
+    let due = "2024-03-01T11:00:00-0500";
+    assert!(due_date_has_passed(due, None, &now).unwrap());
+    assert!(!due_date_has_passed(due, Some("1 hour"), &now).unwrap());
+    assert!(due_date_has_passed(due, Some("59 minutes"), &now).unwrap());
+}
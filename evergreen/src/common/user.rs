@@ -163,3 +163,23 @@ pub fn active_hold_counts(e: &mut Editor, user_id: i64) -> EgResult<EgValue> {
 
     Ok(eg::hash! {total: total, ready: ready})
 }
+
+/// Returns fines, open checkout counts, and active hold counts
+/// for a user in a single object.
+///
+/// Hold counts include "pending" holds, i.e. those not yet ready
+/// for pickup.
+pub fn summary(e: &mut Editor, user_id: i64) -> EgResult<EgValue> {
+    let fines = fines_summary(e, user_id)?;
+    let checkouts = open_checkout_counts(e, user_id)?;
+    let mut holds = active_hold_counts(e, user_id)?;
+
+    holds["pending"] = EgValue::from(holds["total"].int()? - holds["ready"].int()?);
+
+    Ok(eg::hash! {
+        user: user_id,
+        fines: fines,
+        checkouts: checkouts,
+        holds: holds,
+    })
+}
@@ -58,6 +58,15 @@ pub const BTYPE_LABEL_DEPOSIT: &str = "System: Deposit";
 pub const BTYPE_LABEL_RENTAL: &str = "System: Rental";
 pub const BTYPE_NOTE_SYSTEM: &str = "SYSTEM GENERATED";
 
+// ---------------------------------------------------------------------
+// Standing Penalties
+// ---------------------------------------------------------------------
+pub const PENALTY_PATRON_EXCEEDS_FINES: i64 = 1;
+pub const PENALTY_PATRON_EXCEEDS_OVERDUE_COUNT: i64 = 2;
+pub const PENALTY_PATRON_EXCEEDS_CHECKOUT_COUNT: i64 = 3;
+pub const PENALTY_PATRON_EXCEEDS_COLLECTIONS_WARNING: i64 = 4;
+pub const PENALTY_PATRON_EXCEEDS_LOST_COUNT: i64 = 5;
+
 // ---------------------------------------------------------------------
 // Hold Types
 // ---------------------------------------------------------------------
@@ -1,7 +1,10 @@
 //! Date handling utilities
 
 use crate::result::EgResult;
-use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, TimeZone};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, LocalResult, Months, NaiveDate,
+    NaiveDateTime, Offset, TimeZone,
+};
 use chrono_tz::Tz;
 use regex::{Captures, Regex};
 use std::time::SystemTime;
@@ -12,71 +15,168 @@ const INTERVAL_HMS_REGEX: &str = r#"(\d{2,}):(\d{2}):(\d{2})"#;
 /// Shortcut -- one fewer import for most mods.
 pub type EgDate = DateTime<FixedOffset>;
 
-/// Turn an interval string into a number of seconds.
+/// An interval broken into months, days, and seconds, which is how
+/// PostgreSQL stores intervals.
 ///
-/// Supports a subset of the language, which is typically enough
-/// for our use cases.  For better parsing, if needed, we could use
-/// (e.g.) <https://crates.io/crates/parse_duration>
+/// Months and days are calendar units whose length depends on the
+/// date they are applied to.  Seconds are always elapsed time.
 ///
 /// ```
-/// use evergreen::date;
+/// use evergreen::date::Interval;
 ///
-/// let seconds = date::interval_to_seconds("02:20:05").expect("Parse OK");
-/// assert_eq!(seconds, 8405);
+/// let intvl = Interval::parse("1 mon 3 days 02:00:00").expect("Parse OK");
+/// assert_eq!(intvl.months(), 1);
+/// assert_eq!(intvl.days(), 3);
+/// assert_eq!(intvl.seconds(), 7200);
 ///
-/// let seconds = date::interval_to_seconds("1 min 2 seconds").expect("Parse OK");
-/// assert_eq!(seconds, 62);
+/// let intvl = Interval::parse("2 weeks").expect("Parse OK");
+/// assert_eq!(intvl.days(), 14);
+///
+/// let intvl = Interval::parse("90 minutes").expect("Parse OK");
+/// assert_eq!(intvl.seconds(), 5400);
+///
+/// let intvl = Interval::parse("1 year -1 days").expect("Parse OK");
+/// assert_eq!(intvl.months(), 12);
+/// assert_eq!(intvl.days(), -1);
 /// ```
-pub fn interval_to_seconds(interval: &str) -> EgResult<i64> {
-    let hms_reg = Regex::new(INTERVAL_HMS_REGEX).unwrap();
-    let part_reg = Regex::new(INTERVAL_PART_REGEX).unwrap();
-
-    let mut interval = interval.to_lowercase();
-    interval = interval.replace("and", ",");
-    interval = interval.replace(",", " ");
-
-    // Format hh:mm:ss
-    let interval = hms_reg.replace(&interval, |caps: &Captures| {
-        // caps[0] is the full source string
-        format!("{} h {} min {} s", &caps[1], &caps[2], &caps[3])
-    });
-
-    let mut amount = 0;
-    for (_, [sign, count, itype]) in part_reg.captures_iter(&interval).map(|c| c.extract()) {
-        let count = match count.parse::<i64>() {
-            Ok(c) => c,
-            Err(e) => {
-                log::warn!("Invalid interval number: {count} {e} from {interval}");
-                continue;
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Interval {
+    months: i64,
+    days: i64,
+    seconds: i64,
+}
+
+impl std::ops::Neg for Interval {
+    type Output = Interval;
+
+    fn neg(self) -> Interval {
+        Interval {
+            months: -self.months,
+            days: -self.days,
+            seconds: -self.seconds,
+        }
+    }
+}
+
+impl Interval {
+    /// Parse an interval string.
+    ///
+    /// Supports a subset of the language, which is typically enough
+    /// for our use cases.  Unknown units are ignored.
+    pub fn parse(interval: &str) -> EgResult<Interval> {
+        let hms_reg = Regex::new(INTERVAL_HMS_REGEX).unwrap();
+        let part_reg = Regex::new(INTERVAL_PART_REGEX).unwrap();
+
+        let mut interval = interval.to_lowercase();
+        interval = interval.replace("and", ",");
+        interval = interval.replace(",", " ");
+
+        // Format hh:mm:ss
+        let interval = hms_reg.replace(&interval, |caps: &Captures| {
+            // caps[0] is the full source string
+            format!("{} h {} min {} s", &caps[1], &caps[2], &caps[3])
+        });
+
+        let mut parsed = Interval::default();
+
+        for (_, [sign, count, itype]) in part_reg.captures_iter(&interval).map(|c| c.extract()) {
+            let mut count = match count.parse::<i64>() {
+                Ok(c) => c,
+                Err(e) => {
+                    log::warn!("Invalid interval number: {count} {e} from {interval}");
+                    continue;
+                }
+            };
+
+            if sign == "-" {
+                count = -count;
             }
-        };
 
-        let change = if itype.starts_with("s") {
-            count
-        } else if itype.starts_with("min") {
-            count * 60
-        } else if itype.starts_with("h") {
-            count * 60 * 60
-        } else if itype.starts_with("d") {
-            count * 60 * 60 * 24
-        } else if itype.starts_with("w") {
-            count * 60 * 60 * 24 * 7
-        } else if itype.starts_with("mon") {
-            (count * 60 * 60 * 24 * 365) / 12
-        } else if itype.starts_with("y") {
-            count * 60 * 60 * 24 * 365
+            if itype.starts_with("s") {
+                parsed.seconds += count;
+            } else if itype.starts_with("min") {
+                parsed.seconds += count * 60;
+            } else if itype.starts_with("h") {
+                parsed.seconds += count * 60 * 60;
+            } else if itype.starts_with("d") {
+                parsed.days += count;
+            } else if itype.starts_with("w") {
+                parsed.days += count * 7;
+            } else if itype.starts_with("mon") {
+                parsed.months += count;
+            } else if itype.starts_with("y") {
+                parsed.months += count * 12;
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn months(&self) -> i64 {
+        self.months
+    }
+
+    pub fn days(&self) -> i64 {
+        self.days
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// Total number of seconds, treating a day as 24 hours and a
+    /// month as 1/12th of a 365-day year.
+    pub fn to_seconds(&self) -> i64 {
+        (self.months * 60 * 60 * 24 * 365) / 12 + self.days * 60 * 60 * 24 + self.seconds
+    }
+
+    /// Add months and days to a wall clock time.
+    ///
+    /// Adding months to a day which does not exist in the resulting
+    /// month lands on the last day of that month, e.g. Jan 31 + 1 mon
+    /// is Feb 28 (or 29).
+    fn add_to_naive(&self, naive: NaiveDateTime) -> EgResult<NaiveDateTime> {
+        let months = Months::new(self.months.unsigned_abs() as u32);
+
+        let naive = if self.months < 0 {
+            naive.checked_sub_months(months)
         } else {
-            0
+            naive.checked_add_months(months)
         };
 
-        if sign == "-" {
-            amount -= change;
-        } else {
-            amount += change;
-        }
+        naive
+            .and_then(|n| Duration::try_days(self.days).and_then(|d| n.checked_add_signed(d)))
+            .ok_or_else(|| format!("Interval {self:?} is out of range").into())
+    }
+
+    fn seconds_duration(&self) -> EgResult<Duration> {
+        Duration::try_seconds(self.seconds)
+            .ok_or_else(|| format!("Invalid duration seconds: {}", self.seconds).into())
     }
+}
 
-    Ok(amount)
+/// Turn an interval string into a number of seconds.
+///
+/// Months and years are approximated.  Use [`add_interval`] to apply
+/// an interval to a date.
+///
+/// ```
+/// use evergreen::date;
+///
+/// let seconds = date::interval_to_seconds("02:20:05").expect("Parse OK");
+/// assert_eq!(seconds, 8405);
+///
+/// let seconds = date::interval_to_seconds("1 min 2 seconds").expect("Parse OK");
+/// assert_eq!(seconds, 62);
+///
+/// let seconds = date::interval_to_seconds("3 days").expect("Parse OK");
+/// assert_eq!(seconds, 259200);
+///
+/// let seconds = date::interval_to_seconds("1 mon").expect("Parse OK");
+/// assert_eq!(seconds, 2628000);
+/// ```
+pub fn interval_to_seconds(interval: &str) -> EgResult<i64> {
+    Ok(Interval::parse(interval)?.to_seconds())
 }
 
 /// Current date/time with a fixed offset matching the local time zone.
@@ -121,10 +221,26 @@ pub fn now() -> EgDate {
 ///
 /// let res = evergreen::date::parse_datetime("2023-02-03T123");
 /// assert!(res.is_err());
+///
+/// // PostgreSQL style, with fractional seconds and an hour-only offset.
+/// let dt = date::parse_datetime("2023-09-08 10:59:01.687234-04").unwrap();
+/// assert_eq!(date::to_iso_precise(&dt), "2023-09-08T10:59:01.687234-0400");
 /// ```
 pub fn parse_datetime(dt: &str) -> EgResult<EgDate> {
     if dt.len() > 10 {
         // Assume its a full date + time
+        let mut dt = dt.replacen(' ', "T", 1);
+
+        // Expand hour-only UTC offsets, e.g. -04 => -0400
+        let bytes = dt.as_bytes();
+        let len = bytes.len();
+        if len > 13
+            && (bytes[len - 3] == b'+' || bytes[len - 3] == b'-')
+            && bytes[len - 2..].iter().all(|b| b.is_ascii_digit())
+        {
+            dt += "00";
+        }
+
         return match dt.parse::<EgDate>() {
             Ok(d) => Ok(d),
             Err(e) => return Err(format!("Could not parse datetime string: {e} {dt}").into()),
@@ -170,6 +286,29 @@ pub fn to_iso_millis(dt: &EgDate) -> String {
     dt.format("%FT%T%.3f%z").to_string()
 }
 
+/// Same as to_iso but includes fractional seconds, when present, at
+/// the precision they were provided, so timestamps from cstore
+/// survive a round trip unchanged.
+///
+/// ```
+/// use evergreen::date;
+///
+/// let stamp = "2023-09-08T10:59:01.687234-0400";
+/// let dt = date::parse_datetime(stamp).unwrap();
+/// assert_eq!(date::to_iso_precise(&dt), stamp);
+///
+/// let stamp = "2023-09-08T10:59:01.687-0400";
+/// let dt = date::parse_datetime(stamp).unwrap();
+/// assert_eq!(date::to_iso_precise(&dt), stamp);
+///
+/// let stamp = "2023-09-08T10:59:01-0400";
+/// let dt = date::parse_datetime(stamp).unwrap();
+/// assert_eq!(date::to_iso_precise(&dt), stamp);
+/// ```
+pub fn to_iso_precise(dt: &EgDate) -> String {
+    dt.format("%FT%T%.f%z").to_string()
+}
+
 /// Translate a DateTime into the Local timezone while leaving the
 /// DateTime as a FixedOffset DateTime.
 /// ```
@@ -228,6 +367,70 @@ pub fn set_timezone(dt: EgDate, timezone: &str) -> EgResult<EgDate> {
     Ok(fixed)
 }
 
+/// Interpret a date/time without a time zone as a wall clock time
+/// in the provided time zone.
+///
+/// Times which occur twice when clocks are turned back resolve to
+/// the earlier of the two.  Times which do not exist because clocks
+/// were turned forward are moved forward by the size of the gap.
+///
+/// ```
+/// use evergreen::date;
+/// use chrono::NaiveDateTime;
+///
+/// let naive: NaiveDateTime = "2024-07-01T12:00:00".parse().unwrap();
+/// let dt = date::localize(naive, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&dt), "2024-07-01T12:00:00-0400");
+///
+/// // 01:30 happens twice on 2024-11-03 in New York.
+/// let naive: NaiveDateTime = "2024-11-03T01:30:00".parse().unwrap();
+/// let dt = date::localize(naive, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&dt), "2024-11-03T01:30:00-0400");
+///
+/// // 02:30 does not happen on 2024-03-10 in New York.
+/// let naive: NaiveDateTime = "2024-03-10T02:30:00".parse().unwrap();
+/// let dt = date::localize(naive, "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&dt), "2024-03-10T03:30:00-0400");
+///
+/// assert!(date::localize(naive, "Mars/Olympus_Mons").is_err());
+/// ```
+pub fn localize(naive: NaiveDateTime, timezone: &str) -> EgResult<EgDate> {
+    if timezone == "local" {
+        return localize_in(&Local, naive);
+    }
+
+    let tz: Tz = timezone
+        .parse()
+        .map_err(|e| format!("Cannot parse timezone: {timezone} {e}"))?;
+
+    localize_in(&tz, naive)
+}
+
+fn localize_in<T: TimeZone>(tz: &T, naive: NaiveDateTime) -> EgResult<EgDate> {
+    let localized = match tz.from_local_datetime(&naive) {
+        LocalResult::Single(d) => d,
+        LocalResult::Ambiguous(earlier, _) => earlier,
+        LocalResult::None => {
+            // Skipped by a clock change.  Reading the time with the
+            // offset in effect before the change lands it the same
+            // distance past the change.
+            let before = naive - Duration::try_days(1).unwrap();
+
+            let offset = match tz.offset_from_local_datetime(&before).earliest() {
+                Some(o) => o.fix(),
+                None => Err(format!("Cannot determine time zone offset for {naive}"))?,
+            };
+
+            match offset.from_local_datetime(&naive).single() {
+                Some(d) => d.with_timezone(tz),
+                None => Err(format!("Cannot localize datetime {naive}"))?,
+            }
+        }
+    };
+
+    Ok(localized.fixed_offset())
+}
+
 /// Set the hour/minute/seconds on a DateTime, retaining the original date and timezone.
 ///
 /// (There's gotta be a better way...)
@@ -258,6 +461,11 @@ pub fn set_hms(date: &EgDate, hours: u32, minutes: u32, seconds: u32) -> EgResul
 
 /// Add an interval (string) to a date.
 ///
+/// Months and days are added to the wall clock time in the date's
+/// own UTC offset, then hours, minutes, and seconds are added as
+/// elapsed time.  Use [`add_interval_tz`] to account for daylight
+/// saving time changes.
+///
 /// ```
 /// use evergreen as eg;
 /// use eg::date;
@@ -266,21 +474,85 @@ pub fn set_hms(date: &EgDate, hours: u32, minutes: u32, seconds: u32) -> EgResul
 ///     "1 day 1 hour 5 minutes 1 second"
 /// ).unwrap();
 /// assert_eq!("2023-08-20T01:05:00-0400", &date::to_iso(&dt));
+///
+/// // Months land on the last day of shorter months.
+/// let dt = date::add_interval(
+///     date::parse_datetime("2023-01-31T12:00:00-0500").unwrap(),
+///     "1 mon"
+/// ).unwrap();
+/// assert_eq!("2023-02-28T12:00:00-0500", &date::to_iso(&dt));
 /// ```
 pub fn add_interval(date: EgDate, interval: &str) -> EgResult<EgDate> {
-    let seconds = interval_to_seconds(interval)?;
-    let duration = Duration::try_seconds(seconds)
-        .ok_or_else(|| format!("Invalid duration seconds: {seconds}"))?;
-
-    Ok(date + duration)
+    add_parsed_interval(date, &Interval::parse(interval)?)
 }
 
+/// Subtract an interval (string) from a date.
+///
+/// See [`add_interval`].
+///
+/// ```
+/// use evergreen::date;
+/// let dt = date::subtract_interval(
+///     date::parse_datetime("2023-03-31T12:00:00-0400").unwrap(),
+///     "1 mon 2 hours"
+/// ).unwrap();
+/// assert_eq!("2023-02-28T10:00:00-0400", &date::to_iso(&dt));
+/// ```
 pub fn subtract_interval(date: EgDate, interval: &str) -> EgResult<EgDate> {
-    let seconds = interval_to_seconds(interval)?;
-    let duration = Duration::try_seconds(seconds)
-        .ok_or_else(|| format!("Invalid duration seconds: {seconds}"))?;
+    add_parsed_interval(date, &-Interval::parse(interval)?)
+}
+
+fn add_parsed_interval(date: EgDate, interval: &Interval) -> EgResult<EgDate> {
+    let naive = interval.add_to_naive(date.naive_local())?;
+
+    let date = match date.offset().from_local_datetime(&naive).single() {
+        Some(d) => d,
+        None => Err(format!("Cannot apply offset to datetime {naive}"))?,
+    };
+
+    Ok(date + interval.seconds_duration()?)
+}
+
+/// Add an interval (string) to a date, adding months and days to the
+/// wall clock time in the provided time zone, as PostgreSQL does for
+/// timestamptz values.
+///
+/// The resulting date uses the time zone's offset in effect at the
+/// new date.
+///
+/// ```
+/// use evergreen::date;
+///
+/// // Clocks in New York move forward on 2024-03-10.
+/// let dt = date::parse_datetime("2024-03-09T12:00:00-0500").unwrap();
+///
+/// let next = date::add_interval_tz(dt, "1 day", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&next), "2024-03-10T12:00:00-0400");
+///
+/// let next = date::add_interval_tz(dt, "24 hours", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&next), "2024-03-10T13:00:00-0400");
+///
+/// let prev = date::subtract_interval_tz(next, "1 day", "America/New_York").unwrap();
+/// assert_eq!(date::to_iso(&prev), "2024-03-09T13:00:00-0500");
+/// ```
+pub fn add_interval_tz(date: EgDate, interval: &str, timezone: &str) -> EgResult<EgDate> {
+    add_parsed_interval_tz(date, &Interval::parse(interval)?, timezone)
+}
+
+/// Subtract an interval (string) from a date in the provided time zone.
+///
+/// See [`add_interval_tz`].
+pub fn subtract_interval_tz(date: EgDate, interval: &str, timezone: &str) -> EgResult<EgDate> {
+    add_parsed_interval_tz(date, &-Interval::parse(interval)?, timezone)
+}
+
+fn add_parsed_interval_tz(date: EgDate, interval: &Interval, timezone: &str) -> EgResult<EgDate> {
+    let date = set_timezone(date, timezone)?;
+    let naive = interval.add_to_naive(date.naive_local())?;
+    let date = localize(naive, timezone)? + interval.seconds_duration()?;
 
-    Ok(date - duration)
+    // Elapsed seconds may cross a time change.
+    set_timezone(date, timezone)
 }
 
 /// Epoch seconds with fractional milliseconds.
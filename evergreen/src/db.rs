@@ -381,6 +381,37 @@ impl DatabaseConnection {
         }
     }
 
+    /// Create a savepoint within the current transaction.
+    pub fn savepoint_set(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_command("SAVEPOINT", name)
+    }
+
+    /// Release a savepoint, keeping its changes.
+    pub fn savepoint_release(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_command("RELEASE SAVEPOINT", name)
+    }
+
+    /// Discard changes made since the savepoint was created.
+    pub fn savepoint_rollback(&mut self, name: &str) -> EgResult<()> {
+        self.savepoint_command("ROLLBACK TO SAVEPOINT", name)
+    }
+
+    fn savepoint_command(&mut self, command: &str, name: &str) -> EgResult<()> {
+        if !self.in_transaction {
+            return Err(format!("{command} {name} requires a transaction").into());
+        }
+
+        // Savepoint names cannot be sent as query parameters.
+        if !is_identifier(name) || name.contains('.') {
+            return Err(format!("Invalid savepoint name: {name}").into());
+        }
+
+        match self.client().execute(&format!("{command} {name}"), &[]) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{command} {name} error: {e}").into()),
+        }
+    }
+
     pub fn into_shared(self) -> Rc<RefCell<DatabaseConnection>> {
         Rc::new(RefCell::new(self))
     }
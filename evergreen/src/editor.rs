@@ -1,15 +1,33 @@
 //! Create, Retrieve, Update, Delete IDL-classed objects via (by default) open-ils.cstore.
 use crate as eg;
+use eg::common::auth;
 use eg::event::EgEvent;
 use eg::idl;
 use eg::osrf::params::ApiParams;
+use eg::osrf::session::MultiSession;
 use eg::result::{EgError, EgResult};
 use eg::Client;
 use eg::ClientSession;
 use eg::EgValue;
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const DEFAULT_TIMEOUT: i32 = 60;
 
+/// A connected session which sits idle for longer than this may have
+/// been dropped by its worker, whose keepalive timer has expired.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Transactions open for longer than this are rolled back instead of
+/// holding database locks indefinitely.
+pub const DEFAULT_XACT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Receives the classname, primary key value, and changed fields of
+/// each object updated while diff capture is enabled.
+pub type DiffCallback = Arc<dyn Fn(&str, &EgValue, &[idl::FieldChange]) + Send + Sync>;
+
 /// Specifies Which service are we communicating with.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Personality {
@@ -53,6 +71,8 @@ pub struct Editor {
     personality: Personality,
     authtoken: Option<String>,
     authtime: Option<usize>,
+    /// Epoch seconds where our auth session expires, if known.
+    auth_expires_at: Option<i64>,
     requestor: Option<EgValue>,
     timeout: i32,
 
@@ -63,10 +83,82 @@ pub struct Editor {
     /// ID for currently active transaction.
     xact_id: Option<String>,
 
+    /// Savepoints within the active transaction, oldest first.
+    savepoints: Vec<String>,
+
     /// Most recent non-success event
     last_event: Option<EgEvent>,
 
+    /// Permission check results for the current requestor, keyed
+    /// on permission code and context org unit.
+    perm_cache: HashMap<(String, i64), bool>,
+
     has_pending_changes: bool,
+
+    lifecycle: Lifecycle,
+
+    /// Retrieve each object before it's updated so the changes
+    /// can be logged.
+    capture_diffs: bool,
+
+    diff_callback: Option<DiffCallback>,
+}
+
+/// Tracks how long our connected session has been idle and how long
+/// the active transaction has been open.
+#[derive(Debug, Clone)]
+struct Lifecycle {
+    idle_timeout: Duration,
+    xact_timeout: Option<Duration>,
+    /// When the connected session last sent a request.
+    last_used: Option<Instant>,
+    /// When the active transaction started.
+    xact_started: Option<Instant>,
+}
+
+impl Lifecycle {
+    fn new() -> Self {
+        Lifecycle {
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            xact_timeout: Some(DEFAULT_XACT_TIMEOUT),
+            last_used: None,
+            xact_started: None,
+        }
+    }
+
+    fn touch(&mut self, now: Instant) {
+        self.last_used = Some(now);
+    }
+
+    /// True if the session has been idle longer than the idle timeout.
+    fn idle_expired(&self, now: Instant) -> bool {
+        self.last_used
+            .map(|t| now.duration_since(t) > self.idle_timeout)
+            .unwrap_or(false)
+    }
+
+    fn start_xact(&mut self, now: Instant) {
+        self.xact_started = Some(now);
+    }
+
+    fn end_xact(&mut self) {
+        self.xact_started = None;
+    }
+
+    /// Age of the active transaction if it exceeds the transaction
+    /// timeout.
+    fn xact_expired(&self, now: Instant) -> Option<Duration> {
+        let started = self.xact_started?;
+        let limit = self.xact_timeout?;
+        let age = now.duration_since(started);
+        (age > limit).then_some(age)
+    }
+
+    /// Forget the session, e.g. after disconnecting.
+    fn reset(&mut self) {
+        self.last_used = None;
+        self.xact_started = None;
+    }
 }
 
 impl Clone for Editor {
@@ -75,12 +167,18 @@ impl Clone for Editor {
         e.personality = self.personality().clone();
         e.authtoken = self.authtoken().map(str::to_string);
         e.requestor = self.requestor().map(|r| r.clone());
+        e.lifecycle.idle_timeout = self.lifecycle.idle_timeout;
+        e.lifecycle.xact_timeout = self.lifecycle.xact_timeout;
+        e.capture_diffs = self.capture_diffs;
+        e.diff_callback = self.diff_callback.clone();
         e
     }
 }
 
 impl Editor {
     /// Create a new minimal Editor
+    ///
+    /// Requests carry the client's locale and timezone, if set.
     pub fn new(client: &Client) -> Self {
         Editor {
             client: client.clone(),
@@ -88,12 +186,18 @@ impl Editor {
             timeout: DEFAULT_TIMEOUT,
             xact_wanted: false,
             xact_id: None,
+            savepoints: Vec::new(),
             session: None,
             authtoken: None,
             authtime: None,
+            auth_expires_at: None,
             requestor: None,
             last_event: None,
+            perm_cache: HashMap::new(),
             has_pending_changes: false,
+            lifecycle: Lifecycle::new(),
+            capture_diffs: false,
+            diff_callback: None,
         }
     }
 
@@ -107,6 +211,49 @@ impl Editor {
         self.timeout = DEFAULT_TIMEOUT;
     }
 
+    /// How long a connected session may sit idle before we replace
+    /// it with a new session instead of using it again.
+    ///
+    /// This should be shorter than the keepalive of our service's
+    /// workers.
+    pub fn idle_timeout(&self) -> Duration {
+        self.lifecycle.idle_timeout
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.lifecycle.idle_timeout = timeout;
+    }
+
+    /// How long a transaction may stay open before the next request
+    /// rolls it back and fails with a TRANSACTION_TIMEOUT event.
+    ///
+    /// None means transactions may stay open indefinitely.
+    pub fn xact_timeout(&self) -> Option<Duration> {
+        self.lifecycle.xact_timeout
+    }
+
+    pub fn set_xact_timeout(&mut self, timeout: Option<Duration>) {
+        self.lifecycle.xact_timeout = timeout;
+    }
+
+    /// True if update() logs the fields it changes.
+    pub fn capture_diffs(&self) -> bool {
+        self.capture_diffs
+    }
+
+    /// When enabled, update() retrieves each object before updating
+    /// it, then logs the changed fields at debug level and passes
+    /// them to the diff callback, if set.
+    ///
+    /// This costs an extra retrieve per update.
+    pub fn set_capture_diffs(&mut self, capture: bool) {
+        self.capture_diffs = capture;
+    }
+
+    pub fn set_diff_callback(&mut self, callback: Option<DiffCallback>) {
+        self.diff_callback = callback;
+    }
+
     pub fn client_mut(&mut self) -> &mut Client {
         &mut self.client
     }
@@ -143,7 +290,10 @@ impl Editor {
     pub fn checkauth(&mut self) -> EgResult<bool> {
         let token = match self.authtoken() {
             Some(t) => t,
-            None => return Ok(false),
+            None => {
+                self.set_last_event(EgEvent::new("NO_SESSION"));
+                return Ok(false);
+            }
         };
 
         let service = "open-ils.auth";
@@ -161,7 +311,7 @@ impl Editor {
             }
 
             if user.has_key("usrname") {
-                self.requestor = Some(user);
+                self.give_requestor(user);
                 return Ok(true);
             }
         }
@@ -183,7 +333,54 @@ impl Editor {
 
     /// Set the authtoken value.
     pub fn set_authtoken(&mut self, token: &str) {
-        self.authtoken = Some(token.to_string())
+        self.authtoken = Some(token.to_string());
+        self.authtime = None;
+        self.auth_expires_at = None;
+    }
+
+    /// Set the authtoken value and track the session expiry time
+    /// from a newly created auth session.
+    pub fn apply_auth_session(&mut self, ses: &auth::Session) {
+        self.set_authtoken(ses.token());
+        self.authtime = Some(ses.authtime() as usize);
+        self.auth_expires_at = Some(ses.expires_at());
+    }
+
+    /// Epoch seconds where our auth session expires if not refreshed.
+    ///
+    /// None if the expiry time is not known, e.g. the authtoken was
+    /// applied directly.
+    pub fn auth_expires_at(&self) -> Option<i64> {
+        self.auth_expires_at
+    }
+
+    /// Seconds until our auth session expires, if known.
+    pub fn auth_seconds_remaining(&self) -> Option<i64> {
+        self.auth_expires_at.map(auth::seconds_remaining)
+    }
+
+    /// Extend our auth session timeout.
+    ///
+    /// Returns false if we have no authtoken or the auth session no
+    /// longer exists, e.g. because the auth cache was flushed, in
+    /// which case a new login is required.
+    pub fn refresh_auth(&mut self) -> EgResult<bool> {
+        let token = match self.authtoken() {
+            Some(t) => t.to_string(),
+            None => return Ok(false),
+        };
+
+        match auth::Session::reset_timeout(&self.client, &token)? {
+            Some(authtime) => {
+                self.authtime = Some(authtime as usize);
+                self.auth_expires_at = Some(auth::session_expires_at(authtime, None));
+                Ok(true)
+            }
+            None => {
+                self.set_last_event(EgEvent::new("NO_SESSION"));
+                Ok(false)
+            }
+        }
     }
 
     /// Set the authtoken value and verify the authtoken is valid
@@ -257,12 +454,24 @@ impl Editor {
     }
 
     pub fn set_requestor(&mut self, r: &EgValue) {
-        self.requestor = Some(r.clone())
+        self.give_requestor(r.clone());
     }
 
     /// Same as set_requestor, but takes ownership of the value.
     pub fn give_requestor(&mut self, r: EgValue) {
         self.requestor = Some(r);
+        self.perm_cache.clear();
+    }
+
+    /// Number of cached permission check results.
+    pub fn perm_cache_len(&self) -> usize {
+        self.perm_cache.len()
+    }
+
+    /// Forget cached permission check results, e.g. after modifying
+    /// the requestor's permissions.
+    pub fn clear_perm_cache(&mut self) {
+        self.perm_cache.clear();
     }
 
     pub fn last_event(&self) -> Option<&EgEvent> {
@@ -325,6 +534,62 @@ impl Editor {
         }
     }
 
+    /// Start a new transaction and return a guard which rolls the
+    /// transaction back when dropped, unless it was committed or
+    /// rolled back first.
+    ///
+    /// This prevents early returns, e.g. via `?`, from leaving a
+    /// transaction open on the connected worker.
+    ///
+    /// ```no_run
+    /// use evergreen as eg;
+    ///
+    /// fn set_barcode(editor: &mut eg::Editor, id: i64) -> eg::EgResult<()> {
+    ///     let mut editor = editor.xact_guard()?;
+    ///
+    ///     let mut acp = editor.retrieve("acp", id)?.ok_or_else(|| editor.die_event())?;
+    ///     acp["barcode"] = eg::EgValue::from("NEW-BARCODE");
+    ///
+    ///     // Any failure above rolls back the transaction.
+    ///     editor.update(acp)?;
+    ///
+    ///     editor.commit()
+    /// }
+    /// ```
+    pub fn xact_guard(&mut self) -> EgResult<XactGuard<'_>> {
+        self.xact_begin()?;
+        Ok(XactGuard { editor: self })
+    }
+
+    /// Create a batch of read-only queries which run concurrently,
+    /// each on its own session with our service.
+    ///
+    /// Queries in the batch do not see changes made within our
+    /// active transaction, if any.
+    ///
+    /// ```no_run
+    /// use evergreen as eg;
+    ///
+    /// fn counts(editor: &eg::Editor, user_id: i64) -> eg::EgResult<(usize, usize)> {
+    ///     let mut batch = editor.query_batch();
+    ///
+    ///     let circs = batch.search("circ", eg::hash! {usr: user_id, checkin_time: eg::NULL})?;
+    ///     let holds = batch.search("ahr", eg::hash! {usr: user_id, cancel_time: eg::NULL})?;
+    ///
+    ///     let mut results = batch.join()?;
+    ///
+    ///     Ok((results.take_list(circs)?.len(), results.take_list(holds)?.len()))
+    /// }
+    /// ```
+    pub fn query_batch(&self) -> QueryBatch {
+        QueryBatch {
+            multi: MultiSession::new(self.client.clone(), self.personality().into()),
+            service: self.personality().into(),
+            threads: Vec::new(),
+            timeout: self.timeout,
+        }
+    }
+
     /// Rollback the active transaction and disconnect from the worker.
     pub fn rollback(&mut self) -> EgResult<()> {
         self.xact_rollback()?;
@@ -362,17 +627,33 @@ impl Editor {
         self.xact_id = None;
         self.xact_wanted = false;
         self.has_pending_changes = false;
+        self.savepoints.clear();
+        self.lifecycle.end_xact();
 
         Ok(())
     }
 
     /// Start a new transaction, connecting to a worker if necessary.
+    ///
+    /// Returns Err if a transaction is already open.  Transactions
+    /// do not nest; use savepoint() instead.
     pub fn xact_begin(&mut self) -> EgResult<()> {
+        if self.in_transaction() {
+            return Err(format!(
+                "{} cannot begin a transaction while transaction {} is open",
+                self.logtag(),
+                self.xact_id.as_deref().unwrap_or("")
+            )
+            .into());
+        }
+
         self.connect()?;
+        self.savepoints.clear();
         if let Some(id) = self.request_np(&self.app_method("transaction.begin"))? {
             if let Some(id_str) = id.as_str() {
                 log::debug!("New transaction started with id {}", id_str);
                 self.xact_id = Some(id_str.to_string());
+                self.lifecycle.start_xact(Instant::now());
             }
         }
         Ok(())
@@ -382,6 +663,8 @@ impl Editor {
     ///
     /// This variation does not send a DISCONNECT to the connected worker.
     pub fn xact_commit(&mut self) -> EgResult<()> {
+        self.check_xact_timeout()?;
+
         if self.in_transaction() {
             // We can take() the xact_id here because we're clearing
             // it below anyway.  This avoids a .to_string() as a way
@@ -394,10 +677,108 @@ impl Editor {
         self.xact_id = None;
         self.xact_wanted = false;
         self.has_pending_changes = false;
+        self.savepoints.clear();
+        self.lifecycle.end_xact();
+
+        Ok(())
+    }
+
+    /// If the active transaction has been open for longer than our
+    /// transaction timeout, roll it back and return a
+    /// TRANSACTION_TIMEOUT event.
+    fn check_xact_timeout(&mut self) -> EgResult<()> {
+        if !self.has_xact_id() {
+            return Ok(());
+        }
+
+        let Some(age) = self.lifecycle.xact_expired(Instant::now()) else {
+            return Ok(());
+        };
+
+        let msg = format!(
+            "Transaction {} rolled back after {} seconds",
+            self.xact_id.as_deref().unwrap_or(""),
+            age.as_secs()
+        );
+
+        log::warn!("{} {msg}", self.logtag());
+
+        let mut evt = EgEvent::new("TRANSACTION_TIMEOUT");
+        evt.set_debug(&msg);
+        self.set_last_event(evt.clone());
+
+        if let Err(e) = self.rollback() {
+            log::error!("{} rollback failed: {e}", self.logtag());
+        }
+
+        Err(evt.into())
+    }
+
+    /// Create a savepoint within the active transaction.
+    ///
+    /// Changes made after the savepoint may be discarded with
+    /// rollback_savepoint() without affecting the rest of the
+    /// transaction.
+    pub fn savepoint(&mut self, name: &str) -> EgResult<()> {
+        if !self.in_transaction() {
+            return Err(format!("Cannot create savepoint {name} outside of a transaction").into());
+        }
+
+        let method = self.app_method("savepoint.set");
+        self.request(&method, name)?;
+
+        self.savepoints.push(name.to_string());
+
+        Ok(())
+    }
+
+    /// Release a savepoint, keeping its changes.
+    ///
+    /// Savepoints created after this one are released as well.
+    pub fn release_savepoint(&mut self, name: &str) -> EgResult<()> {
+        let pos = self.savepoint_position(name)?;
+
+        let method = self.app_method("savepoint.release");
+        self.request(&method, name)?;
+
+        self.savepoints.truncate(pos);
 
         Ok(())
     }
 
+    /// Discard all changes made since the savepoint was created.
+    ///
+    /// Savepoints created after this one are discarded as well.  The
+    /// savepoint itself remains, as in PostgreSQL, and may be rolled
+    /// back to again or released.
+    pub fn rollback_savepoint(&mut self, name: &str) -> EgResult<()> {
+        let pos = self.savepoint_position(name)?;
+
+        let method = self.app_method("savepoint.rollback");
+        self.request(&method, name)?;
+
+        self.savepoints.truncate(pos + 1);
+
+        Ok(())
+    }
+
+    /// Savepoints within the active transaction, oldest first.
+    pub fn savepoints(&self) -> &[String] {
+        &self.savepoints
+    }
+
+    /// Position of the most recent savepoint with the provided name.
+    fn savepoint_position(&self, name: &str) -> EgResult<usize> {
+        if !self.in_transaction() {
+            return Err(format!("Savepoint {name} used outside of a transaction").into());
+        }
+
+        self.savepoints
+            .iter()
+            .rposition(|s| s == name)
+            .ok_or_else(|| format!("No such savepoint: {name}").into())
+    }
+
     /// End the stateful conversation with the remote worker.
     pub fn disconnect(&mut self) -> EgResult<()> {
         self.xact_rollback()?;
@@ -406,11 +787,17 @@ impl Editor {
             ses.disconnect()?;
         }
         self.session = None;
+        self.lifecycle.reset();
         Ok(())
     }
 
     /// Start a stateful conversation with a worker.
+    ///
+    /// A connected session which has been idle for longer than our
+    /// idle timeout is replaced with a new one.
     pub fn connect(&mut self) -> EgResult<()> {
+        self.drop_idle_session();
+
         if let Some(ref ses) = self.session {
             if ses.connected() {
                 // Already connected.
@@ -418,9 +805,40 @@ impl Editor {
             }
         }
         self.session().connect()?;
+        self.lifecycle.touch(Instant::now());
         Ok(())
     }
 
+    /// Discard our connected session if it has been idle long enough
+    /// that its worker may have dropped it.
+    ///
+    /// Sessions with an open transaction are kept, since the
+    /// transaction cannot be moved to a new session.
+    fn drop_idle_session(&mut self) {
+        let connected = self
+            .session
+            .as_ref()
+            .map(|s| s.connected())
+            .unwrap_or(false);
+
+        if !connected || self.has_xact_id() || !self.lifecycle.idle_expired(Instant::now()) {
+            return;
+        }
+
+        log::info!(
+            "{} replacing session idle for more than {:?}",
+            self.logtag(),
+            self.lifecycle.idle_timeout
+        );
+
+        // The worker may be gone, so a failed disconnect is expected.
+        if let Some(ses) = self.session.take() {
+            ses.disconnect().ok();
+        }
+
+        self.lifecycle.reset();
+    }
+
     /// Send an API request without any parameters.
     ///
     /// See request() for more.
@@ -471,6 +889,12 @@ impl Editor {
     fn request(&mut self, method: &str, params: impl Into<ApiParams>) -> EgResult<Option<EgValue>> {
         let params: ApiParams = params.into();
 
+        if !method.ends_with("transaction.rollback") {
+            self.check_xact_timeout()?;
+        }
+
+        self.drop_idle_session();
+
         log::info!(
             "{} request {} {}",
             self.logtag(),
@@ -502,11 +926,35 @@ impl Editor {
         }
 
         let mut req = self.session().request(method, params).or_else(|e| {
+            self.set_failure_event(&e);
             self.rollback()?;
             Err(e)
         })?;
 
-        req.first_with_timeout(self.timeout)
+        let result = req.first_with_timeout(self.timeout);
+
+        if let Err(ref e) = result {
+            self.set_failure_event(e);
+        }
+
+        self.lifecycle.touch(Instant::now());
+
+        result
+    }
+
+    /// Store a failed request as our last event so die_event() and
+    /// friends can report it.
+    fn set_failure_event(&mut self, err: &EgError) {
+        let evt = match err {
+            EgError::Event(e) => e.clone(),
+            _ => {
+                let mut e = EgEvent::new("DATABASE_QUERY_FAILED");
+                e.set_debug(&err.to_string());
+                e
+            }
+        };
+
+        self.set_last_event(evt);
     }
 
     /// Returns our mutable session, creating a new one if needed.
@@ -530,22 +978,43 @@ impl Editor {
         Err(format!("Cannot determine fieldmapper from {}", value.dump()).into())
     }
 
-    fn get_fieldmapper_from_classname(&self, classname: &str) -> EgResult<String> {
-        let cls = idl::get_class(classname)?;
-        if let Some(fm) = cls.fieldmapper() {
-            return Ok(fm.replace("::", "."));
+    /// Verify an object only contains fields defined by its IDL class,
+    /// and when creating, that it has values for all required fields.
+    fn validate_object(&self, object: &EgValue, creating: bool) -> EgResult<()> {
+        let validation = match object.idl_class() {
+            Some(cls) => cls.validate(object),
+            None => return Err(format!("Cannot validate non-IDL object {}", object.dump()).into()),
+        };
+
+        if validation.unknown_fields().is_empty()
+            && (!creating || validation.missing_required().is_empty())
+        {
+            return Ok(());
         }
-        Err(format!("Cannot determine fieldmapper from {classname}").into())
+
+        Err(validation.to_string().into())
     }
 
-    pub fn json_query(&mut self, query: EgValue) -> EgResult<Vec<EgValue>> {
+    fn get_fieldmapper_from_classname(&self, classname: &str) -> EgResult<String> {
+        fieldmapper_from_classname(classname)
+    }
+
+    /// Run a JSON query.
+    ///
+    /// The query may be a raw JSON query hash or a
+    /// common::query::JsonQuery.  Rows are returned as unblessed hashes.
+    pub fn json_query(&mut self, query: impl Into<EgValue>) -> EgResult<Vec<EgValue>> {
         self.json_query_with_ops(query, EgValue::Null)
     }
 
-    pub fn json_query_with_ops(&mut self, query: EgValue, ops: EgValue) -> EgResult<Vec<EgValue>> {
+    pub fn json_query_with_ops(
+        &mut self,
+        query: impl Into<EgValue>,
+        ops: EgValue,
+    ) -> EgResult<Vec<EgValue>> {
         let method = self.app_method(&format!("json_query.atomic"));
 
-        let mut params: ApiParams = query.into();
+        let mut params: ApiParams = query.into().into();
         if !ops.is_null() {
             params.add(ops);
         }
@@ -559,6 +1028,22 @@ impl Editor {
         Err(format!("Unexpected response to method {method}").into())
     }
 
+    /// Run a JSON query whose rows are instances of the provided
+    /// class and return them as IDL objects, like search().
+    ///
+    /// Selected fields must belong to the class.
+    pub fn json_query_as(
+        &mut self,
+        idlclass: &str,
+        query: impl Into<EgValue>,
+    ) -> EgResult<Vec<EgValue>> {
+        let mut objects = Vec::new();
+        for row in self.json_query(query)? {
+            objects.push(EgValue::create(idlclass, row)?);
+        }
+        Ok(objects)
+    }
+
     pub fn retrieve(
         &mut self,
         idlclass: &str,
@@ -593,6 +1078,24 @@ impl Editor {
         Ok(resp_op)
     }
 
+    /// Retrieve an object with SELECT ... FOR UPDATE, locking its row
+    /// until the active transaction ends.
+    ///
+    /// Other transactions which try to lock or modify the row wait
+    /// for ours to end, so nothing changes the row between our read
+    /// and our write.
+    pub fn retrieve_for_update(
+        &mut self,
+        idlclass: &str,
+        id: impl Into<ApiParams>,
+    ) -> EgResult<Option<EgValue>> {
+        if !self.has_xact_id() {
+            return Err(format!("Transaction required to lock {idlclass}").into());
+        }
+
+        self.retrieve_with_ops(idlclass, id, eg::hash! {for_update: true})
+    }
+
     pub fn search(&mut self, idlclass: &str, query: EgValue) -> EgResult<Vec<EgValue>> {
         self.search_with_ops(idlclass, query, EgValue::Null)
     }
@@ -621,33 +1124,145 @@ impl Editor {
         Err(format!("Unexpected response to method {method}").into())
     }
 
+    /// Search in pages of page_size rows, so large result sets are
+    /// never held in memory all at once.
+    ///
+    /// Pages are ordered by, and resume after, the class's primary
+    /// key, so each page is an index scan regardless of how deep into
+    /// the results we are.  Classes without a primary key fall back to
+    /// offset paging.  Any order_by, limit, or offset in ops is replaced.
+    ///
+    /// ```no_run
+    /// use evergreen as eg;
+    ///
+    /// fn count_copies(editor: &mut eg::Editor) -> eg::EgResult<usize> {
+    ///     let query = eg::hash! {deleted: "f"};
+    ///     let mut count = 0;
+    ///
+    ///     for copy in editor.search_paged("acp", query, eg::NULL, 1000)? {
+    ///         copy?;
+    ///         count += 1;
+    ///     }
+    ///
+    ///     Ok(count)
+    /// }
+    /// ```
+    pub fn search_paged(
+        &mut self,
+        idlclass: &str,
+        query: EgValue,
+        ops: EgValue,
+        page_size: usize,
+    ) -> EgResult<PagedSearch<'_>> {
+        // Verifies the class is one we can search.
+        self.get_fieldmapper_from_classname(idlclass)?;
+
+        let pkey = idl::get_class(idlclass)?.pkey().map(|p| p.to_string());
+
+        Ok(PagedSearch::new(
+            self,
+            PagedQuery::Search {
+                idlclass: idlclass.to_string(),
+                query,
+                ops,
+                pkey,
+                last_pkey: None,
+            },
+            page_size,
+        ))
+    }
+
+    /// Run a JSON query in pages of page_size rows using limit and
+    /// offset.
+    ///
+    /// The query should have an order_by on a unique column so rows
+    /// are neither repeated nor skipped between pages.  Any limit or
+    /// offset in the query is replaced.
+    pub fn json_query_paged(&mut self, query: EgValue, page_size: usize) -> PagedSearch<'_> {
+        PagedSearch::new(self, PagedQuery::JsonQuery { query }, page_size)
+    }
+
     /// Update an object.
     pub fn update(&mut self, object: EgValue) -> EgResult<()> {
         if !self.has_xact_id() {
             Err(format!("Transaction required for UPDATE"))?;
         }
 
+        self.validate_object(&object, false)?;
+
         let fmapper = self.get_fieldmapper(&object)?;
 
         let method = self.app_method(&format!("direct.{fmapper}.update"));
 
+        let before = match self.capture_diffs {
+            true => self.before_image(&object),
+            false => None,
+        };
+
         // Update calls return the pkey of the object on success,
         // nothing on error.
+        let after = before.as_ref().map(|_| object.clone());
+
         if self.request(&method, object)?.is_none() {
             Err(format!("Update returned no response"))?;
         }
 
         self.has_pending_changes = true;
 
+        if let (Some(before), Some(after)) = (before, after) {
+            self.report_diff(&before, &after);
+        }
+
         Ok(())
     }
 
+    /// The stored version of an object we are about to update.
+    ///
+    /// Failing to capture the diff does not prevent the update.
+    fn before_image(&mut self, object: &EgValue) -> Option<EgValue> {
+        let classname = object.classname()?.to_string();
+        let pkey = object.pkey_value()?.clone();
+
+        match self.retrieve(&classname, pkey) {
+            Ok(before) => before,
+            Err(e) => {
+                log::warn!("Cannot capture {classname} before update: {e}");
+                None
+            }
+        }
+    }
+
+    fn report_diff(&self, before: &EgValue, after: &EgValue) {
+        let (Some(class), Some(pkey)) = (after.idl_class(), after.pkey_value()) else {
+            return;
+        };
+
+        let changes = class.diff(before, after);
+
+        if changes.is_empty() {
+            log::debug!("Updated {} {pkey} without changes", class.classname());
+        } else {
+            let summary: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+            log::debug!(
+                "Updated {} {pkey}: {}",
+                class.classname(),
+                summary.join(", ")
+            );
+        }
+
+        if let Some(callback) = self.diff_callback.as_ref() {
+            callback(class.classname(), pkey, &changes);
+        }
+    }
+
     /// Returns the newly created object.
     pub fn create(&mut self, object: EgValue) -> EgResult<EgValue> {
         if !self.has_xact_id() {
             Err(format!("Transaction required for CREATE"))?;
         }
 
+        self.validate_object(&object, true)?;
+
         let fmapper = self.get_fieldmapper(&object)?;
 
         let method = self.app_method(&format!("direct.{fmapper}.create"));
@@ -688,9 +1303,134 @@ impl Editor {
         }
     }
 
+    /// Create multiple objects of the same class.
+    ///
+    /// Returns the newly created objects in input order.
+    ///
+    /// See batch_request() for failure handling.
+    pub fn create_batch(&mut self, objects: Vec<EgValue>) -> EgResult<Vec<EgValue>> {
+        self.batch_request("create", objects)
+    }
+
+    /// Update multiple objects of the same class.
+    pub fn update_batch(&mut self, objects: Vec<EgValue>) -> EgResult<()> {
+        self.batch_request("update", objects).map(|_| ())
+    }
+
+    /// Delete multiple objects of the same class.
+    ///
+    /// Returns the PKEY values of the deleted objects in input order.
+    pub fn delete_batch(&mut self, objects: Vec<EgValue>) -> EgResult<Vec<EgValue>> {
+        self.batch_request("delete", objects)
+    }
+
+    /// Send one create/update/delete request per object, then collect
+    /// the responses, so the whole batch costs a single round trip.
+    ///
+    /// If any request fails, the transaction is rolled back and the
+    /// error reports the index of the failed object.
+    fn batch_request(&mut self, action: &str, objects: Vec<EgValue>) -> EgResult<Vec<EgValue>> {
+        if !self.has_xact_id() {
+            Err(format!(
+                "Transaction required for batch {}",
+                action.to_uppercase()
+            ))?;
+        }
+
+        self.check_xact_timeout()?;
+
+        let classname = match objects.first() {
+            Some(o) => o.classname().unwrap_or("").to_string(),
+            None => return Ok(Vec::new()),
+        };
+
+        for (idx, object) in objects.iter().enumerate() {
+            if object.classname() != Some(classname.as_str()) {
+                return Err(format!(
+                    "Batch {action} object at index {idx} is not a '{classname}' object"
+                )
+                .into());
+            }
+
+            if action != "delete" {
+                if let Err(e) = self.validate_object(object, action == "create") {
+                    return Err(format!("Batch {action} object at index {idx}: {e}").into());
+                }
+            }
+        }
+
+        let fmapper = self.get_fieldmapper(&objects[0])?;
+        let method = self.app_method(&format!("direct.{fmapper}.{action}"));
+
+        log::info!(
+            "{} batch request {method} with {} objects",
+            self.logtag(),
+            objects.len()
+        );
+
+        let mut requests = Vec::new();
+
+        for (idx, object) in objects.into_iter().enumerate() {
+            let params: ApiParams = object.into();
+
+            // Write calls also get logged to the activity log
+            log::info!(
+                "ACT:{} request {} {}",
+                self.logtag(),
+                method,
+                self.args_to_string(&params)
+            );
+
+            match self.session().request(&method, params) {
+                Ok(r) => requests.push(r),
+                Err(e) => {
+                    drop(requests);
+                    self.rollback()?;
+                    return Err(format!("Batch {action} failed at index {idx}: {e}").into());
+                }
+            }
+        }
+
+        let mut responses = Vec::new();
+        let mut failure = None;
+
+        for (idx, req) in requests.iter_mut().enumerate() {
+            match req.first_with_timeout(self.timeout) {
+                Ok(Some(resp)) => responses.push(resp),
+                Ok(None) => {
+                    failure = Some(format!(
+                        "Batch {action} at index {idx} returned no response"
+                    ));
+                    break;
+                }
+                Err(e) => {
+                    failure = Some(format!("Batch {action} failed at index {idx}: {e}"));
+                    break;
+                }
+            }
+        }
+
+        drop(requests);
+
+        self.lifecycle.touch(Instant::now());
+
+        if let Some(msg) = failure {
+            self.rollback()?;
+            return Err(msg.into());
+        }
+
+        self.has_pending_changes = true;
+
+        Ok(responses)
+    }
+
     /// Returns Result of true if our authenticated requestor has the
     /// specified permission at their logged in workstation org unit,
     /// or their home org unit if no workstation is active.
+    ///
+    /// Results are cached per permission and org unit for the life
+    /// of the editor or until the requestor changes.  On failure, a
+    /// PERM_FAILURE event is available via last_event().
     pub fn allowed(&mut self, perm: &str) -> EgResult<bool> {
         self.allowed_maybe_at(perm, None)
     }
@@ -712,6 +1452,30 @@ impl Editor {
             None => self.perm_org(),
         };
 
+        let key = (perm.to_string(), org_id);
+
+        let has_perm = match self.perm_cache.get(&key) {
+            Some(b) => *b,
+            None => {
+                let b = self.usr_has_perm(user_id, perm, org_id)?;
+                self.perm_cache.insert(key, b);
+                b
+            }
+        };
+
+        if !has_perm {
+            let mut evt = EgEvent::new("PERM_FAILURE");
+            evt.set_ils_perm(perm);
+            if org_id > 0 {
+                evt.set_ils_perm_loc(org_id);
+            }
+            self.set_last_event(evt);
+        }
+
+        Ok(has_perm)
+    }
+
+    fn usr_has_perm(&mut self, user_id: i64, perm: &str, org_id: i64) -> EgResult<bool> {
         let query = eg::hash! {
             "select": {
                 "au": [ {
@@ -726,17 +1490,397 @@ impl Editor {
         };
 
         let resp = self.json_query(query)?;
-        let has_perm = resp[0]["has_perm"].boolish();
 
-        if !has_perm {
-            let mut evt = EgEvent::new("PERM_FAILURE");
-            evt.set_ils_perm(perm);
-            if org_id > 0 {
-                evt.set_ils_perm_loc(org_id);
+        Ok(resp[0]["has_perm"].boolish())
+    }
+}
+
+/// Transaction guard returned by [`Editor::xact_guard`].
+///
+/// Derefs to the Editor.  Dropping the guard while the transaction
+/// is still active rolls back the transaction and disconnects from
+/// the worker.  Committing or rolling back the transaction via any
+/// of the Editor's methods defuses the guard.
+pub struct XactGuard<'a> {
+    editor: &'a mut Editor,
+}
+
+impl Deref for XactGuard<'_> {
+    type Target = Editor;
+
+    fn deref(&self) -> &Editor {
+        self.editor
+    }
+}
+
+impl DerefMut for XactGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Editor {
+        self.editor
+    }
+}
+
+impl Drop for XactGuard<'_> {
+    fn drop(&mut self) {
+        if !self.editor.in_transaction() {
+            return;
+        }
+
+        log::warn!(
+            "{} rolling back unfinished transaction",
+            self.editor.logtag()
+        );
+
+        if let Err(e) = self.editor.rollback() {
+            log::error!("{} rollback failed: {e}", self.editor.logtag());
+        }
+    }
+}
+
+fn fieldmapper_from_classname(classname: &str) -> EgResult<String> {
+    let cls = idl::get_class(classname)?;
+    if let Some(fm) = cls.fieldmapper() {
+        return Ok(fm.replace("::", "."));
+    }
+    Err(format!("Cannot determine fieldmapper from {classname}").into())
+}
+
+/// Read-only queries sent concurrently.  See [`Editor::query_batch`].
+///
+/// Each query is sent on a new session as soon as it is added, and
+/// each add method returns the position of the query's result within
+/// the [`BatchResults`] returned by join().  If any query fails, the
+/// remaining outstanding queries are cancelled.
+pub struct QueryBatch {
+    multi: MultiSession,
+    service: &'static str,
+    /// Session thread for each query, by position.
+    threads: Vec<String>,
+    timeout: i32,
+}
+
+impl QueryBatch {
+    /// Number of queries added to the batch.
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// Add a JSON query.  Rows are returned as unblessed hashes.
+    pub fn json_query(&mut self, query: impl Into<EgValue>) -> EgResult<usize> {
+        let method = format!("{}.json_query.atomic", self.service);
+        self.request(&method, query.into())
+    }
+
+    pub fn search(&mut self, idlclass: &str, query: EgValue) -> EgResult<usize> {
+        self.search_with_ops(idlclass, query, EgValue::Null)
+    }
+
+    pub fn search_with_ops(
+        &mut self,
+        idlclass: &str,
+        query: EgValue,
+        ops: EgValue,
+    ) -> EgResult<usize> {
+        let fmapper = fieldmapper_from_classname(idlclass)?;
+        let method = format!("{}.direct.{fmapper}.search.atomic", self.service);
+
+        let mut params: ApiParams = query.into();
+        if !ops.is_null() {
+            params.add(ops);
+        }
+
+        self.request(&method, params)
+    }
+
+    pub fn retrieve(&mut self, idlclass: &str, id: impl Into<ApiParams>) -> EgResult<usize> {
+        let fmapper = fieldmapper_from_classname(idlclass)?;
+        let method = format!("{}.direct.{fmapper}.retrieve", self.service);
+
+        self.request(&method, id)
+    }
+
+    fn request(&mut self, method: &str, params: impl Into<ApiParams>) -> EgResult<usize> {
+        log::info!("query batch request {method}");
+
+        match self.multi.request(method, params) {
+            Ok(thread) => {
+                self.threads.push(thread);
+                Ok(self.threads.len() - 1)
+            }
+            Err(e) => {
+                self.cancel();
+                Err(e)
             }
-            self.set_last_event(evt);
         }
+    }
 
-        Ok(has_perm)
+    /// Wait for all of the queries to complete.
+    ///
+    /// Fails if any query fails or if the batch takes longer than
+    /// the Editor's request timeout.  Outstanding queries are
+    /// cancelled on failure.
+    pub fn join(mut self) -> EgResult<BatchResults> {
+        let result = self.collect();
+
+        if result.is_err() {
+            self.cancel();
+        }
+
+        result
+    }
+
+    fn collect(&mut self) -> EgResult<BatchResults> {
+        let mut values = vec![None; self.threads.len()];
+        let started = Instant::now();
+
+        while !self.multi.complete() {
+            let remaining = self.timeout - started.elapsed().as_secs() as i32;
+
+            if remaining <= 0 {
+                return Err(EgError::Timeout(format!(
+                    "Query batch did not complete within {} seconds",
+                    self.timeout
+                )));
+            }
+
+            if let Some((thread, value)) = self.multi.recv(remaining)? {
+                let pos = self
+                    .threads
+                    .iter()
+                    .position(|t| *t == thread)
+                    .ok_or_else(|| {
+                        format!("Query batch received reply for unknown thread {thread}")
+                    })?;
+
+                values[pos] = Some(value);
+            }
+        }
+
+        Ok(BatchResults { values })
+    }
+
+    fn cancel(&mut self) {
+        if let Err(e) = self.multi.cancel() {
+            log::warn!("Error cancelling query batch requests: {e}");
+        }
+    }
+}
+
+/// Results of a [`QueryBatch`], by query position.
+#[derive(Debug, Default)]
+pub struct BatchResults {
+    values: Vec<Option<EgValue>>,
+}
+
+impl BatchResults {
+    /// Take the object found by a retrieve query.
+    ///
+    /// Returns None if no object was found.
+    pub fn take_one(&mut self, pos: usize) -> Option<EgValue> {
+        self.values.get_mut(pos).and_then(|v| v.take())
+    }
+
+    /// Take the rows returned by a search or JSON query.
+    pub fn take_list(&mut self, pos: usize) -> EgResult<Vec<EgValue>> {
+        match self.take_one(pos) {
+            Some(EgValue::Array(list)) => Ok(list),
+            Some(v) => Err(format!("Unexpected query batch response: {}", v.dump()).into()),
+            None => Err(format!("Query batch has no response at position {pos}").into()),
+        }
+    }
+}
+
+enum PagedQuery {
+    Search {
+        idlclass: String,
+        query: EgValue,
+        ops: EgValue,
+        pkey: Option<String>,
+        /// Primary key of the last row returned.
+        last_pkey: Option<EgValue>,
+    },
+    JsonQuery {
+        query: EgValue,
+    },
+}
+
+/// Iterates over the rows of a search or JSON query, fetching one page
+/// at a time.  See [`Editor::search_paged`].
+///
+/// Iteration stops after the first error.
+pub struct PagedSearch<'a> {
+    editor: &'a mut Editor,
+    query: PagedQuery,
+    page_size: usize,
+    /// Rows skipped by offset paging so far.
+    offset: usize,
+    page: VecDeque<EgValue>,
+    done: bool,
+}
+
+impl<'a> PagedSearch<'a> {
+    fn new(editor: &'a mut Editor, query: PagedQuery, page_size: usize) -> Self {
+        PagedSearch {
+            editor,
+            query,
+            page_size: page_size.max(1),
+            offset: 0,
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Fetch the next page of rows.
+    fn fetch_page(&mut self) -> EgResult<Vec<EgValue>> {
+        let rows = match &mut self.query {
+            PagedQuery::Search {
+                idlclass,
+                query,
+                ops,
+                pkey,
+                last_pkey,
+            } => {
+                let mut ops = match ops {
+                    EgValue::Null => eg::hash! {},
+                    o => o.clone(),
+                };
+
+                ops["limit"] = EgValue::from(self.page_size);
+
+                let query = match pkey {
+                    Some(pkey) => {
+                        ops["order_by"] = eg::hash! {};
+                        ops["order_by"][idlclass.as_str()] = EgValue::from(pkey.as_str());
+                        ops.remove("offset");
+                        paged_search_query(query, pkey, last_pkey.as_ref())
+                    }
+                    None => {
+                        ops["offset"] = EgValue::from(self.offset);
+                        query.clone()
+                    }
+                };
+
+                let rows = self.editor.search_with_ops(idlclass, query, ops)?;
+
+                if let (Some(pkey), Some(row)) = (pkey, rows.last()) {
+                    *last_pkey = Some(row[pkey.as_str()].clone());
+                }
+
+                rows
+            }
+            PagedQuery::JsonQuery { query } => {
+                let mut query = query.clone();
+                query["limit"] = EgValue::from(self.page_size);
+                query["offset"] = EgValue::from(self.offset);
+
+                self.editor.json_query(query)?
+            }
+        };
+
+        self.offset += rows.len();
+
+        Ok(rows)
+    }
+}
+
+impl Iterator for PagedSearch<'_> {
+    type Item = EgResult<EgValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(row) = self.page.pop_front() {
+            return Some(Ok(row));
+        }
+
+        if self.done {
+            return None;
+        }
+
+        match self.fetch_page() {
+            Ok(rows) => {
+                // A short page is the last page.
+                self.done = rows.len() < self.page_size;
+                self.page.extend(rows);
+                self.page.pop_front().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
     }
 }
+
+/// Search query which only matches rows after the last primary key
+/// returned.
+fn paged_search_query(query: &EgValue, pkey: &str, last_pkey: Option<&EgValue>) -> EgValue {
+    let last = match last_pkey {
+        Some(v) => v.clone(),
+        None => return query.clone(),
+    };
+
+    let mut after = eg::hash! {};
+    after[pkey] = eg::hash! {">": last};
+
+    eg::hash! {"-and": [query.clone(), after]}
+}
+
+#[test]
+fn paged_search_query_resumes_after_pkey() {
+    let query = eg::hash! {deleted: "f"};
+
+    assert_eq!(paged_search_query(&query, "id", None), query);
+
+    let paged = paged_search_query(&query, "id", Some(&EgValue::from(42)));
+
+    assert_eq!(paged["-and"][0], query);
+    assert_eq!(paged["-and"][1]["id"][">"], EgValue::from(42));
+}
+
+#[test]
+fn lifecycle_idle_expiry() {
+    let mut lc = Lifecycle::new();
+    lc.idle_timeout = Duration::from_millis(20);
+
+    let start = Instant::now();
+
+    // Never used.
+    assert!(!lc.idle_expired(start));
+
+    lc.touch(start);
+    assert!(!lc.idle_expired(start + Duration::from_millis(10)));
+    assert!(lc.idle_expired(start + Duration::from_millis(30)));
+
+    // Using the session again restarts the clock.
+    lc.touch(start + Duration::from_millis(30));
+    assert!(!lc.idle_expired(start + Duration::from_millis(40)));
+
+    lc.reset();
+    assert!(!lc.idle_expired(start + Duration::from_secs(60)));
+}
+
+#[test]
+fn lifecycle_xact_timeout() {
+    let mut lc = Lifecycle::new();
+    lc.xact_timeout = Some(Duration::from_millis(50));
+
+    let start = Instant::now();
+    assert_eq!(lc.xact_expired(start + Duration::from_secs(1)), None);
+
+    lc.start_xact(start);
+    assert_eq!(lc.xact_expired(start + Duration::from_millis(40)), None);
+    assert_eq!(
+        lc.xact_expired(start + Duration::from_millis(60)),
+        Some(Duration::from_millis(60))
+    );
+
+    lc.end_xact();
+    assert_eq!(lc.xact_expired(start + Duration::from_millis(60)), None);
+
+    // No limit.
+    lc.xact_timeout = None;
+    lc.start_xact(start);
+    assert_eq!(lc.xact_expired(start + Duration::from_secs(3600)), None);
+}
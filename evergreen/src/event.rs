@@ -1,9 +1,14 @@
 //! Evergreen API Response Events
 use crate as eg;
-use eg::date;
+use eg::date::{self, EgDate};
 use eg::EgValue;
 use std::fmt;
 
+/// Textcodes which indicate an API call completed successfully.
+///
+/// Matches the Perl convention, where NO_CHANGE is also a success.
+pub const SUCCESS_TEXTCODES: &[&str] = &["SUCCESS", "NO_CHANGE"];
+
 /// Common argument to API calls that allow for targeted overrides.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Overrides {
@@ -40,6 +45,10 @@ impl fmt::Display for EgEvent {
             s = format!("{} {}@{}", s, p, self.ilspermloc);
         }
 
+        if let Some(ref d) = self.debug {
+            s = s + " [" + d + "]";
+        }
+
         if let Some(ref n) = self.note {
             s = s + "\n" + n;
         }
@@ -153,6 +162,35 @@ impl EgEvent {
     pub fn payload_mut(&mut self) -> &mut EgValue {
         &mut self.payload
     }
+
+    /// Take ownership of the payload, leaving EgValue::Null in its place.
+    pub fn take_payload(&mut self) -> EgValue {
+        self.payload.take()
+    }
+
+    /// Returns a non-null value from a hash payload, e.g. the "circ"
+    /// from a checkout response.
+    pub fn payload_value(&self, key: &str) -> Option<&EgValue> {
+        if !self.payload.is_object() {
+            return None;
+        }
+
+        let value = &self.payload[key];
+        if value.is_null() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Numeric event code as returned by the server, if one was set.
+    pub fn ilsevent(&self) -> Option<isize> {
+        if self.code < 0 {
+            None
+        } else {
+            Some(self.code)
+        }
+    }
     pub fn set_payload(&mut self, payload: EgValue) {
         self.payload = payload
     }
@@ -173,6 +211,16 @@ impl EgEvent {
         self.servertime.as_deref()
     }
 
+    /// Server time as a date.
+    ///
+    /// Returns None if the time is unset or not ISO-formatted, e.g.
+    /// the localtime strings produced by Perl events.
+    pub fn servertime_date(&self) -> Option<EgDate> {
+        self.servertime
+            .as_deref()
+            .and_then(|t| date::parse_datetime(t).ok())
+    }
+
     pub fn ilsperm(&self) -> Option<&str> {
         self.ilsperm.as_deref()
     }
@@ -181,8 +229,26 @@ impl EgEvent {
         self.ilspermloc
     }
 
+    /// True if this is a SUCCESS or NO_CHANGE event, or if the server
+    /// sent the success event code 0.
     pub fn is_success(&self) -> bool {
-        self.textcode.eq("SUCCESS")
+        self.code == 0 || SUCCESS_TEXTCODES.contains(&self.textcode())
+    }
+
+    /// Returns the failed permission and, if known, the org unit ID
+    /// where it was checked if this is a PERM_FAILURE event.
+    pub fn is_permission_failure(&self) -> Option<(&str, Option<i64>)> {
+        if self.textcode != "PERM_FAILURE" {
+            return None;
+        }
+
+        let org = if self.ilspermloc > 0 {
+            Some(self.ilspermloc)
+        } else {
+            None
+        };
+
+        Some((self.ilsperm().unwrap_or(""), org))
     }
 
     pub fn org(&self) -> &Option<i64> {
@@ -301,4 +367,81 @@ impl EgEvent {
 
         Some(evt)
     }
+
+    /// Parses a response which may be a lone event or an array of
+    /// events, e.g. from a failed checkout.
+    ///
+    /// Returns None if the value is not an event, is an empty array,
+    /// or contains any non-event values.
+    pub fn parse_array(jv: &EgValue) -> Option<Vec<EgEvent>> {
+        if !jv.is_array() {
+            return EgEvent::parse(jv).map(|e| vec![e]);
+        }
+
+        if jv.is_empty() {
+            return None;
+        }
+
+        jv.members().map(EgEvent::parse).collect()
+    }
+}
+
+#[test]
+fn parse_single_event() {
+    let jv = eg::hash! {
+        ilsevent: "5000",
+        textcode: "PERM_FAILURE",
+        desc: "Permission Denied",
+        debug: "checkout",
+        ilsperm: "COPY_CHECKOUT",
+        ilspermloc: 4,
+        servertime: "2024-01-25T10:00:00-0500",
+        payload: {circ: {id: 1}},
+    };
+
+    let evts = EgEvent::parse_array(&jv).expect("Lone Event");
+    assert_eq!(evts.len(), 1);
+
+    let evt = &evts[0];
+    assert!(!evt.is_success());
+    assert_eq!(evt.ilsevent(), Some(5000));
+    assert_eq!(
+        evt.is_permission_failure(),
+        Some(("COPY_CHECKOUT", Some(4)))
+    );
+    assert_eq!(evt.payload_value("circ").unwrap()["id"].as_i64(), Some(1));
+    assert!(evt.payload_value("copy").is_none());
+    assert!(evt.servertime_date().is_some());
+    assert_eq!(
+        evt.to_string(),
+        "Event: 5000:PERM_FAILURE -> Permission Denied COPY_CHECKOUT@4 [checkout]"
+    );
+
+    let evt = EgEvent::parse(&eg::hash! {ilsevent: 0, textcode: "OTHER"}).unwrap();
+    assert!(evt.is_success());
+    assert!(EgEvent::new("NO_CHANGE").is_success());
+    assert!(EgEvent::new("NO_CHANGE").ilsevent().is_none());
+}
+
+#[test]
+fn parse_event_array() {
+    let jv = eg::array! [
+        {textcode: "PATRON_EXCEEDS_FINES", ilsevent: 7013},
+        {textcode: "ITEM_DEPOSIT_FEE_REQUIRED", ilsevent: 1234},
+    ];
+
+    let evts = EgEvent::parse_array(&jv).expect("Event Array");
+    assert_eq!(evts.len(), 2);
+    assert_eq!(evts[1].textcode(), "ITEM_DEPOSIT_FEE_REQUIRED");
+    assert!(evts.iter().all(|e| e.is_permission_failure().is_none()));
+}
+
+#[test]
+fn parse_non_event() {
+    assert!(EgEvent::parse_array(&eg::hash! {howdy: 123}).is_none());
+    assert!(EgEvent::parse_array(&EgValue::new_array()).is_none());
+    assert!(EgEvent::parse_array(&EgValue::from("SUCCESS")).is_none());
+
+    let jv = eg::array! [{textcode: "SUCCESS"}, 42];
+    assert!(EgEvent::parse_array(&jv).is_none());
 }
@@ -19,6 +19,10 @@ const OILS_NS_PERSIST: &str = "http://open-ils.org/spec/opensrf/IDL/persistence/
 const OILS_NS_REPORTER: &str = "http://open-ils.org/spec/opensrf/IDL/reporter/v1";
 const AUTO_FIELDS: [&str; 3] = ["isnew", "ischanged", "isdeleted"];
 
+// Field name constants, e.g. idl::mbts::BALANCE_OWED.  See idlgen.rs.
+#[cfg(feature = "idl-constants")]
+include!(concat!(env!("OUT_DIR"), "/idl_constants.rs"));
+
 /// Returns a ref to the global IDL parser instance
 pub fn parser() -> &'static Parser {
     if let Some(idl) = GLOBAL_IDL.get() {
@@ -33,10 +37,14 @@ pub fn parser() -> &'static Parser {
 ///
 /// Err is returned if no such classes exists.
 pub fn get_class(classname: &str) -> EgResult<&Arc<Class>> {
-    parser()
-        .classes
-        .get(classname)
-        .ok_or_else(|| format!("No such IDL class: {classname}").into())
+    parser().class(classname)
+}
+
+/// Changes between two versions of an object of the provided class.
+///
+/// See Class::diff().
+pub fn diff(classname: &str, before: &EgValue, after: &EgValue) -> EgResult<Vec<FieldChange>> {
+    Ok(get_class(classname)?.diff(before, after))
 }
 
 /// Various forms an IDL-classed object can take internally and on
@@ -147,6 +155,9 @@ pub struct Field {
     array_pos: usize,
     is_virtual: bool,
     suppress_controller: Option<String>,
+
+    /// A value is required when creating or updating objects.
+    required: bool,
 }
 
 impl fmt::Display for Field {
@@ -181,6 +192,9 @@ impl Field {
     pub fn suppress_controller(&self) -> Option<&str> {
         self.suppress_controller.as_deref()
     }
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -375,6 +389,196 @@ impl Class {
             .filter(|f| f.name().eq(field) && !f.is_virtual())
             .next()
     }
+
+    /// Check the keys of a Hash or Blessed value against our fields.
+    ///
+    /// The primary key is not required, since it's typically
+    /// generated when the object is created.
+    pub fn validate(&self, obj: &EgValue) -> Validation {
+        let mut unknown_fields: Vec<String> = obj
+            .keys()
+            .filter(|k| !self.has_field(k))
+            .map(|k| k.to_string())
+            .collect();
+
+        let mut missing_required: Vec<String> = self
+            .fields()
+            .values()
+            .filter(|f| f.is_required() && !f.is_virtual())
+            .filter(|f| Some(f.name()) != self.pkey())
+            .filter(|f| obj[f.name()].is_null())
+            .map(|f| f.name().to_string())
+            .collect();
+
+        unknown_fields.sort();
+        missing_required.sort();
+
+        Validation {
+            classname: self.classname().to_string(),
+            unknown_fields,
+            missing_required,
+        }
+    }
+
+    /// Changes to our real fields between two versions of an object,
+    /// sorted by field name.
+    ///
+    /// Values are compared by datatype, so e.g. "3" and 3 are the
+    /// same number, and "t" and true are the same bool.  Fleshed
+    /// values are compared by their linked key.  A field absent from
+    /// either version is not compared, but a field which changed to
+    /// or from null is.
+    pub fn diff(&self, before: &EgValue, after: &EgValue) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+
+        for field in self.real_fields_sorted() {
+            let name = field.name();
+
+            if !before.has_key(name) || !after.has_key(name) {
+                continue;
+            }
+
+            let old = self.comparable_value(name, &before[name]);
+            let new = self.comparable_value(name, &after[name]);
+
+            if !Self::same_value(field.datatype(), &old, &new) {
+                changes.push(FieldChange {
+                    field: name.to_string(),
+                    old,
+                    new,
+                });
+            }
+        }
+
+        changes
+    }
+
+    /// Replaces fleshed objects with the key they are linked by.
+    fn comparable_value(&self, field: &str, value: &EgValue) -> EgValue {
+        if value.is_array() {
+            let mut list = EgValue::new_array();
+            for v in value.members() {
+                list.push(self.comparable_value(field, v)).ok();
+            }
+            return list;
+        }
+
+        if !value.is_object() {
+            return value.clone();
+        }
+
+        let key = match self.links().get(field) {
+            Some(link) => Some(&value[link.key()]),
+            None => value.pkey_value(),
+        };
+
+        key.cloned().unwrap_or_else(|| value["id"].clone())
+    }
+
+    fn same_value(datatype: &DataType, a: &EgValue, b: &EgValue) -> bool {
+        if a.is_null() || b.is_null() {
+            return a.is_null() && b.is_null();
+        }
+
+        if a.is_array() && b.is_array() {
+            return a.len() == b.len()
+                && a.members()
+                    .zip(b.members())
+                    .all(|(a, b)| Self::same_value(datatype, a, b));
+        }
+
+        if *datatype == DataType::Bool {
+            let as_bool = |v: &EgValue| v.as_bool().unwrap_or_else(|| v.boolish());
+            return as_bool(a) == as_bool(b);
+        }
+
+        if datatype.is_numeric() || *datatype == DataType::Link {
+            if let (Some(a), Some(b)) = (a.as_f64(), b.as_f64()) {
+                return a == b;
+            }
+        }
+
+        match (a.to_string(), b.to_string()) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        }
+    }
+}
+
+/// A field whose value differs between two versions of an object.
+///
+/// Fleshed values are reported as their linked key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    field: String,
+    old: EgValue,
+    new: EgValue,
+}
+
+impl FieldChange {
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+    pub fn old_value(&self) -> &EgValue {
+        &self.old
+    }
+    pub fn new_value(&self) -> &EgValue {
+        &self.new
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} => {}", self.field, self.old, self.new)
+    }
+}
+
+/// Result of checking an object against its IDL class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validation {
+    classname: String,
+    unknown_fields: Vec<String>,
+    missing_required: Vec<String>,
+}
+
+impl Validation {
+    /// True if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.unknown_fields.is_empty() && self.missing_required.is_empty()
+    }
+
+    /// Fields present on the object which the IDL class does not define.
+    pub fn unknown_fields(&self) -> &Vec<String> {
+        &self.unknown_fields
+    }
+
+    /// Required fields with no value.
+    pub fn missing_required(&self) -> &Vec<String> {
+        &self.missing_required
+    }
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IDL class '{}'", self.classname)?;
+
+        if self.is_valid() {
+            return write!(f, " object is valid");
+        }
+
+        if !self.unknown_fields.is_empty() {
+            write!(f, " has no fields named {:?}", self.unknown_fields)?;
+        }
+
+        if !self.missing_required.is_empty() {
+            if !self.unknown_fields.is_empty() {
+                write!(f, ";")?;
+            }
+            write!(f, " requires values for {:?}", self.missing_required)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl fmt::Display for Class {
@@ -408,6 +612,26 @@ impl Parser {
         &self.classes
     }
 
+    /// Returns an IDL class by classname.
+    ///
+    /// Err is returned if no such classes exists.
+    pub fn class(&self, classname: &str) -> EgResult<&Arc<Class>> {
+        self.classes
+            .get(classname)
+            .ok_or_else(|| format!("No such IDL class: {classname}").into())
+    }
+
+    /// Check a blessed object against its IDL class.
+    ///
+    /// Returns Err if the value is not a blessed object.
+    pub fn validate(&self, obj: &EgValue) -> EgResult<Validation> {
+        let classname = obj
+            .classname()
+            .ok_or_else(|| format!("Cannot validate non-IDL object: {}", obj.dump()))?;
+
+        Ok(self.class(classname)?.validate(obj))
+    }
+
     /// Load the IDL from a file.
     ///
     /// Returns an Err if the IDL has already been parsed and loaded, in
@@ -560,6 +784,7 @@ impl Parser {
                     array_pos: pos,
                     is_virtual: true,
                     suppress_controller: None,
+                    required: false,
                 },
             );
 
@@ -596,6 +821,8 @@ impl Parser {
             .attribute((OILS_NS_PERSIST, "suppress_controller"))
             .map(|c| c.to_string());
 
+        let required = node.attribute((OILS_NS_OBJ, "required")) == Some("true");
+
         let field = Field {
             name: node.attribute("name").unwrap().to_string(),
             label,
@@ -604,6 +831,7 @@ impl Parser {
             array_pos: pos,
             is_virtual,
             suppress_controller,
+            required,
         };
 
         class.fields.insert(field.name.to_string(), field);
@@ -725,3 +953,117 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+const TEST_IDL: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<IDL xmlns="http://opensrf.org/spec/IDL/base/v1"
+    xmlns:oils_persist="http://open-ils.org/spec/opensrf/IDL/persistence/v1"
+    xmlns:oils_obj="http://open-ils.org/spec/opensrf/IDL/objects/v1"
+    xmlns:reporter="http://open-ils.org/spec/opensrf/IDL/reporter/v1">
+  <class id="mbts" controller="open-ils.cstore open-ils.pcrud"
+      oils_obj:fieldmapper="money::billable_transaction_summary"
+      oils_persist:tablename="money.materialized_billable_xact_summary"
+      reporter:label="Billable Transaction Summary" oils_persist:readonly="true">
+    <fields oils_persist:primary="id" oils_persist:sequence="money.billable_xact_id_seq">
+      <field reporter:label="Transaction ID" name="id" reporter:datatype="id"/>
+      <field reporter:label="User" name="usr" reporter:datatype="link"/>
+      <field reporter:label="Balance Owed" name="balance_owed" reporter:datatype="money"/>
+      <field reporter:label="Transaction Finish Time" name="xact_finish" reporter:datatype="timestamp"/>
+    </fields>
+    <links>
+      <link field="usr" reltype="has_a" key="id" map="" class="au"/>
+    </links>
+  </class>
+  <class id="acp" controller="open-ils.cstore open-ils.pcrud"
+      oils_obj:fieldmapper="asset::copy" oils_persist:tablename="asset.copy"
+      reporter:label="Item">
+    <fields oils_persist:primary="id" oils_persist:sequence="asset.copy_id_seq">
+      <field reporter:label="Item ID" name="id" reporter:datatype="id"/>
+      <field reporter:label="Barcode" name="barcode" reporter:datatype="text" oils_obj:required="true"/>
+      <field reporter:label="Call Number" name="call_number" reporter:datatype="link" oils_obj:required="true"/>
+      <field reporter:label="Circulating Library" name="circ_lib" reporter:datatype="org_unit" oils_obj:required="true"/>
+      <field reporter:label="Price" name="price" reporter:datatype="money"/>
+    </fields>
+  </class>
+</IDL>"#;
+
+#[test]
+fn class_metadata() {
+    let parser = Parser::parse_string(TEST_IDL).expect("IDL parses");
+
+    let mbts = parser.class("mbts").expect("mbts exists");
+
+    assert_eq!(mbts.pkey(), Some("id"));
+    assert_eq!(
+        mbts.fieldmapper(),
+        Some("money::billable_transaction_summary")
+    );
+    assert!(mbts.has_field("balance_owed"));
+    assert!(!mbts.has_field("balance_due"));
+    assert_eq!(
+        mbts.get_field("balance_owed").map(|f| f.datatype()),
+        Some(&DataType::Money)
+    );
+    assert_eq!(mbts.real_field_names_sorted().len(), 4);
+    assert_eq!(mbts.links().get("usr").map(|l| l.class()), Some("au"));
+
+    assert!(parser.class("no-such-class").is_err());
+
+    let acp = parser.class("acp").expect("acp exists");
+    assert!(acp.get_field("barcode").unwrap().is_required());
+    assert!(!acp.get_field("price").unwrap().is_required());
+
+    let copy = eg::hash! {barcode: "123", call_number: 1, circ_lib: 4};
+    assert!(acp.validate(&copy).is_valid());
+
+    let copy = eg::hash! {barcode: "123", bogus: 1};
+    let validation = acp.validate(&copy);
+
+    assert!(!validation.is_valid());
+    assert_eq!(validation.unknown_fields(), &vec!["bogus".to_string()]);
+    assert_eq!(
+        validation.missing_required(),
+        &vec!["call_number".to_string(), "circ_lib".to_string()]
+    );
+    assert!(validation.to_string().contains("has no fields named"));
+}
+
+#[test]
+fn object_diff() {
+    let parser = Parser::parse_string(TEST_IDL).expect("IDL parses");
+    let mbts = parser.class("mbts").expect("mbts exists");
+
+    let before = eg::hash! {id: 1, usr: 5, balance_owed: "3", xact_finish: null};
+
+    // Numeric strings match numbers and fleshed values match their key.
+    let after = eg::hash! {
+        id: "1",
+        usr: {id: "5", usrname: "fleshed"},
+        balance_owed: 3.0,
+        xact_finish: null,
+    };
+    assert!(mbts.diff(&before, &after).is_empty());
+
+    let after = eg::hash! {
+        id: 1,
+        usr: {id: 6},
+        balance_owed: "2.50",
+        xact_finish: "2024-01-01T00:00:00-0500",
+    };
+    let changes = mbts.diff(&before, &after);
+    let fields: Vec<&str> = changes.iter().map(|c| c.field()).collect();
+
+    assert_eq!(fields, ["balance_owed", "usr", "xact_finish"]);
+    assert_eq!(changes[1].old_value().as_int(), Some(5));
+    assert_eq!(changes[1].new_value().as_int(), Some(6));
+    assert!(changes[2].old_value().is_null());
+    assert_eq!(changes[0].to_string(), "balance_owed: 3 => 2.50");
+
+    // Absent fields are not compared, but null is a value.
+    let after = eg::hash! {id: 1, balance_owed: null};
+    let changes = mbts.diff(&before, &after);
+
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].field(), "balance_owed");
+    assert!(changes[0].new_value().is_null());
+}
@@ -0,0 +1,145 @@
+//! Generates Rust field name constants from the fieldmapper IDL.
+//!
+//! Each IDL class becomes a module named for the class whose constants
+//! are the upper-cased field names, e.g.
+//!
+//! ```text
+//! pub mod mbts {
+//!     pub const CLASSNAME: &str = "mbts";
+//!     pub const BALANCE_OWED: &str = "balance_owed";
+//!     ...
+//! }
+//! ```
+//!
+//! When the crate is built with the "idl-constants" feature, build.rs
+//! runs the generator against $EG_IDL_FILE (or the default IDL path)
+//! and the output is included in the idl module, so code can refer to
+//! idl::mbts::BALANCE_OWED instead of "balance_owed".
+//!
+//! This module only depends on roxmltree and std so build.rs can
+//! include it directly.
+use std::fmt::Write;
+
+/// Identifiers which cannot be used as-is for module names.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+    "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Replace characters which are not valid in a Rust identifier.
+fn identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+
+    ident
+}
+
+fn module_name(classname: &str) -> String {
+    let mut name = identifier(classname).to_lowercase();
+    if KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+fn const_name(field: &str) -> String {
+    identifier(field).to_uppercase()
+}
+
+/// Generate the Rust source for the IDL in the provided XML string.
+pub fn generate(xml: &str) -> Result<String, String> {
+    let doc = roxmltree::Document::parse(xml)
+        .map_err(|e| format!("Error parsing XML string for IDL: {e}"))?;
+
+    let mut code = String::new();
+
+    writeln!(
+        code,
+        "// Generated from the IDL by idlgen.rs.  Do not edit."
+    )
+    .ok();
+
+    let classes = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("class") && n.parent_element().is_some());
+
+    for class_node in classes {
+        let classname = match class_node.attribute("id") {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let fields: Vec<&str> = class_node
+            .children()
+            .filter(|n| n.has_tag_name("fields"))
+            .flat_map(|n| n.children())
+            .filter(|n| n.has_tag_name("field"))
+            .filter_map(|n| n.attribute("name"))
+            .collect();
+
+        writeln!(code, "\npub mod {} {{", module_name(classname)).ok();
+
+        if !fields.iter().any(|f| const_name(f) == "CLASSNAME") {
+            writeln!(code, "    pub const CLASSNAME: &str = {classname:?};").ok();
+        }
+
+        let mut seen = Vec::new();
+
+        for field in fields {
+            let name = const_name(field);
+
+            // Should not happen, but avoid generating code which will
+            // not compile.
+            if seen.contains(&name) {
+                continue;
+            }
+
+            writeln!(code, "    pub const {name}: &str = {field:?};").ok();
+            seen.push(name);
+        }
+
+        writeln!(code, "}}").ok();
+    }
+
+    Ok(code)
+}
+
+#[test]
+fn generate_constants() {
+    let xml = r#"<IDL xmlns="http://opensrf.org/spec/IDL/base/v1">
+        <class id="mbts">
+            <fields>
+                <field name="balance_owed"/>
+                <field name="usr"/>
+            </fields>
+        </class>
+        <class id="mod">
+            <fields>
+                <field name="classname"/>
+                <field name="x-y"/>
+            </fields>
+        </class>
+    </IDL>"#;
+
+    let code = generate(xml).expect("IDL parses");
+
+    assert!(code.contains("pub mod mbts {"));
+    assert!(code.contains(r#"pub const CLASSNAME: &str = "mbts";"#));
+    assert!(code.contains(r#"pub const BALANCE_OWED: &str = "balance_owed";"#));
+    assert!(code.contains(r#"pub const USR: &str = "usr";"#));
+
+    assert!(code.contains("pub mod mod_ {"));
+    assert!(code.contains(r#"pub const CLASSNAME: &str = "classname";"#));
+    assert!(code.contains(r#"pub const X_Y: &str = "x-y";"#));
+
+    assert!(generate("<IDL>").is_err());
+}
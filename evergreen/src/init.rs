@@ -32,6 +32,30 @@ impl InitOptions {
     }
 }
 
+/// Apply a logging value from the environment, if set, to all
+/// variations of a client connection.
+fn logging_from_env(
+    config: &mut conf::Config,
+    var: &str,
+    apply: impl Fn(&mut conf::LogOptions, &str) -> Result<(), String>,
+) -> EgResult<()> {
+    let Ok(value) = env::var(var) else {
+        return Ok(());
+    };
+
+    apply(config.client_mut().logging_mut(), &value).map_err(|e| format!("{var}: {e}"))?;
+
+    if let Some(gateway) = config.gateway_mut() {
+        apply(gateway.logging_mut(), &value).map_err(|e| format!("{var}: {e}"))?;
+    }
+
+    for router in config.routers_mut() {
+        apply(router.client_mut().logging_mut(), &value).map_err(|e| format!("{var}: {e}"))?;
+    }
+
+    Ok(())
+}
+
 /// Read environment variables, parse the core config, setup logging.
 ///
 /// This does not connect to the bus.
@@ -70,6 +94,25 @@ pub fn osrf_init(options: &InitOptions) -> EgResult<Client> {
         }
     }
 
+    logging_from_env(&mut config, "OSRF_LOG_FORMAT", |l, v| l.set_log_format(v))?;
+
+    logging_from_env(&mut config, "OSRF_LOG_FILE", |l, v| {
+        l.set_log_file(v);
+        Ok(())
+    })?;
+
+    logging_from_env(&mut config, "OSRF_LOG_ROTATE_SIZE", |l, v| {
+        l.log_rotation_mut().set_max_size(v)
+    })?;
+
+    logging_from_env(&mut config, "OSRF_LOG_ROTATE_AGE", |l, v| {
+        l.log_rotation_mut().set_max_age(v)
+    })?;
+
+    logging_from_env(&mut config, "OSRF_LOG_ROTATE_KEEP", |l, v| {
+        l.log_rotation_mut().set_keep(v)
+    })?;
+
     if let Ok(facility) = env::var("OSRF_LOG_FACILITY") {
         config
             .client_mut()
@@ -12,6 +12,7 @@ pub use value::EgValue;
 
 pub const NULL: EgValue = EgValue::Null;
 
+pub mod bindings;
 pub mod common;
 pub mod constants;
 pub mod date;
@@ -20,7 +21,9 @@ pub mod editor;
 pub mod event;
 pub mod idl;
 pub mod idldb;
+pub mod idlgen;
 pub mod init;
+pub mod money;
 pub mod norm;
 pub mod osrf;
 pub mod result;
@@ -0,0 +1,297 @@
+//! Currency amounts stored as integer cents.
+//!
+//! Evergreen stores money values as NUMERIC(6,2), which cstore
+//! returns as strings ("3.50") or, for computed values, numbers.
+//! Doing math on those values as floats produces results like
+//! 0.29 * 100.0 == 28.999999999999996.  Money avoids that by parsing
+//! the values directly into cents.
+//!
+//! Arithmetic operators saturate at the i64 limits instead of
+//! panicking or wrapping.  Use the checked_* variants where overflow
+//! must be detected.
+use crate::result::EgResult;
+use crate::EgValue;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Floats further than this from a whole number of cents are assumed
+/// to contain fractional cents instead of floating point noise.
+const CENTS_TOLERANCE: f64 = 0.000001;
+
+/// A currency amount.  May be negative, e.g. a patron credit.
+///
+/// ```
+/// use evergreen::money::Money;
+///
+/// let owed: Money = "3.5".parse().unwrap();
+/// let paid = Money::from_cents(125);
+///
+/// assert_eq!(owed - paid, Money::from_cents(225));
+/// assert_eq!((paid - owed).to_string(), "-2.25");
+/// assert!(owed > paid);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub fn zero() -> Money {
+        Money(0)
+    }
+
+    pub fn from_cents(cents: i64) -> Money {
+        Money(cents)
+    }
+
+    pub fn cents(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    /// Parse a dollar amount string, e.g. "10.10", "-3.5", ".05".
+    ///
+    /// Amounts with more than 2 decimal places, a sign but no digits,
+    /// or a negative zero value are rejected.
+    ///
+    /// ```
+    /// use evergreen::money::Money;
+    ///
+    /// assert_eq!(Money::parse("10.1").unwrap().cents(), 1010);
+    /// assert_eq!(Money::parse("-0.05").unwrap().cents(), -5);
+    /// assert!(Money::parse("1.005").is_err());
+    /// assert!(Money::parse("-0.00").is_err());
+    /// ```
+    pub fn parse(amount: &str) -> EgResult<Money> {
+        let amount = amount.trim();
+
+        let (negative, unsigned) = match amount.strip_prefix('-') {
+            Some(a) => (true, a),
+            None => (false, amount.strip_prefix('+').unwrap_or(amount)),
+        };
+
+        let (whole, frac) = match unsigned.split_once('.') {
+            Some((w, f)) => (w, f),
+            None => (unsigned, ""),
+        };
+
+        if (whole.is_empty() && frac.is_empty())
+            || frac.len() > 2
+            || !whole
+                .chars()
+                .chain(frac.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(format!("Invalid money amount: '{amount}'").into());
+        }
+
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole
+                .parse()
+                .map_err(|e| format!("Invalid money amount: '{amount}' {e}"))?
+        };
+
+        // "1.5" means 50 cents, not 5.
+        let frac: i64 = format!("{frac:0<2}").parse().unwrap_or(0);
+
+        let cents = whole
+            .checked_mul(100)
+            .and_then(|c| c.checked_add(frac))
+            .ok_or_else(|| format!("Money amount out of range: '{amount}'"))?;
+
+        if negative && cents == 0 {
+            return Err(format!("Invalid negative zero money amount: '{amount}'").into());
+        }
+
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+
+    /// Create a Money value from a float, e.g. one computed by the
+    /// database.
+    ///
+    /// Floating point noise is rounded away, but values with
+    /// fractional cents are rejected.
+    ///
+    /// ```
+    /// use evergreen::money::Money;
+    ///
+    /// assert_eq!(Money::from_f64(0.29).unwrap().cents(), 29);
+    /// assert_eq!(Money::from_f64(0.1 + 0.2).unwrap().cents(), 30);
+    /// assert!(Money::from_f64(0.125).is_err());
+    /// ```
+    pub fn from_f64(amount: f64) -> EgResult<Money> {
+        let cents = amount * 100.0;
+        let rounded = cents.round();
+
+        if !rounded.is_finite() || rounded.abs() >= i64::MAX as f64 {
+            return Err(format!("Money amount out of range: {amount}").into());
+        }
+
+        if (cents - rounded).abs() > CENTS_TOLERANCE {
+            return Err(format!("Money amount has fractional cents: {amount}").into());
+        }
+
+        Ok(Money(rounded as i64))
+    }
+
+    /// Create a Money value from a string or numeric EgValue.
+    ///
+    /// ```
+    /// use evergreen::money::Money;
+    /// use evergreen::EgValue;
+    ///
+    /// let m = Money::from_value(&EgValue::from("3.50")).unwrap();
+    /// assert_eq!(m, Money::from_value(&EgValue::from(3.5)).unwrap());
+    ///
+    /// assert!(Money::from_value(&EgValue::Null).is_err());
+    /// ```
+    pub fn from_value(value: &EgValue) -> EgResult<Money> {
+        if let Some(s) = value.as_str() {
+            Money::parse(s)
+        } else if let Some(f) = value.as_f64() {
+            Money::from_f64(f)
+        } else {
+            Err(format!("Invalid money value: {value}").into())
+        }
+    }
+
+    /// Same as from_value, but null values produce None.
+    pub fn from_value_opt(value: &EgValue) -> EgResult<Option<Money>> {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            Money::from_value(value).map(Some)
+        }
+    }
+
+    /// Dollar value as a float, e.g. for percentage calculations.
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    pub fn checked_add(&self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(&self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn checked_mul(&self, factor: i64) -> Option<Money> {
+        self.0.checked_mul(factor).map(Money)
+    }
+}
+
+impl fmt::Display for Money {
+    /// Dollar amount with 2 decimal places, e.g. "-3.50".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{:02}", cents / 100, cents % 100)
+    }
+}
+
+impl FromStr for Money {
+    type Err = crate::EgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Money::parse(s)
+    }
+}
+
+/// Money values are sent as strings, like the database returns them.
+impl From<Money> for EgValue {
+    fn from(m: Money) -> EgValue {
+        EgValue::from(m.to_string())
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0.saturating_sub(other.0))
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(self.0.saturating_neg())
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::zero(), |a, b| a + b)
+    }
+}
+
+#[test]
+fn test_parse_money() {
+    assert_eq!(Money::parse("10.10").unwrap().cents(), 1010);
+    assert_eq!(Money::parse("10").unwrap().cents(), 1000);
+    assert_eq!(Money::parse(".05").unwrap().cents(), 5);
+    assert_eq!(Money::parse("0.29").unwrap().cents(), 29);
+    assert_eq!(Money::parse("+1.00").unwrap().cents(), 100);
+    assert_eq!(Money::parse(" -3.5 ").unwrap().cents(), -350);
+    assert_eq!(Money::parse("-0.01").unwrap().cents(), -1);
+
+    for bad in [
+        "", ".", "-", "-.", "abc", "1.2.3", "1.005", "-0", "-0.00", "--1", "1e3",
+    ] {
+        assert!(Money::parse(bad).is_err(), "{bad} should not parse");
+    }
+}
+
+#[test]
+fn test_money_math() {
+    let total: Money = ["3.37", "3.37", "3.36"]
+        .iter()
+        .map(|s| Money::parse(s).unwrap())
+        .sum();
+
+    assert_eq!(total.to_string(), "10.10");
+    assert_eq!((Money::zero() - total).to_string(), "-10.10");
+    assert_eq!(Money::from_cents(5).to_string(), "0.05");
+    assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+
+    let max = Money::from_cents(i64::MAX);
+    assert_eq!(max + Money::from_cents(1), max);
+    assert_eq!(max.checked_add(Money::from_cents(1)), None);
+    assert_eq!(-Money::from_cents(i64::MIN), max);
+}
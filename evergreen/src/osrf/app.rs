@@ -3,9 +3,12 @@ use crate::osrf::message;
 use crate::osrf::method;
 use crate::EgError;
 use crate::EgResult;
+use json::JsonValue;
 use std::any::Any;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// * Server spawns a worker thread
 /// * Worker thread calls an ApplicationWorkerFactory function to
@@ -22,7 +25,13 @@ use std::sync::Arc;
 /// * Once all requests are complete in the current session,
 ///   the Worker goes back to sleep to wait for more requests.
 /// * Just before the thread ends/joins, app_worker.worker_end() is called.
-
+///
+/// Workers may keep caches which survive across requests in a
+/// WorkerCache made available via app_worker.worker_cache().  When
+/// the cache generation changes, e.g. via the SERVICE.cache.clear
+/// API, app_worker.cache_clear() is called on each worker the next
+/// time it's between sessions.
+///
 /// Function that generates ApplicationWorker implementers.
 ///
 /// This type of function may be cloned and passed through the thread
@@ -60,6 +69,16 @@ pub trait ApplicationWorker: Any {
     /// Called on the worker when a MethodCall invocation exits with an Err.
     fn api_call_error(&mut self, request: &message::MethodCall, error: EgError);
 
+    /// Called when the caller went away before the named API call
+    /// completed.
+    ///
+    /// Responses stop as soon as the caller is found missing: the
+    /// handler's next respond() returns an Err, so streaming handlers
+    /// exit early.  Override this to discard any state left over from
+    /// the abandoned call.  api_call_error() is not called for
+    /// abandoned calls.
+    fn request_abandoned(&mut self, _api_name: &str) {}
+
     /// Called every time our worker wakes up to check for signals,
     /// timeouts, etc.
     ///
@@ -74,6 +93,24 @@ pub trait ApplicationWorker: Any {
     ///
     /// Offers a chance to clean up any resources.
     fn worker_end(&mut self) -> EgResult<()>;
+
+    /// Cache of values which live across requests, if this worker
+    /// keeps one.
+    fn worker_cache(&mut self) -> Option<&mut WorkerCache> {
+        None
+    }
+
+    /// Called between sessions when the cache generation has changed.
+    ///
+    /// Workers which cache data outside of their worker_cache()
+    /// should override this to discard it.
+    fn cache_clear(&mut self) -> EgResult<()> {
+        if let Some(cache) = self.worker_cache() {
+            log::debug!("Clearing worker cache");
+            cache.clear();
+        }
+        Ok(())
+    }
 }
 
 pub trait Application {
@@ -95,3 +132,215 @@ pub trait Application {
     /// that generate them can.
     fn worker_factory(&self) -> fn() -> Box<dyn ApplicationWorker>;
 }
+
+/// Incremented each time worker caches are cleared for this process.
+static CACHE_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of times worker caches have been cleared for this process.
+pub fn cache_generation() -> usize {
+    CACHE_GENERATION.load(Ordering::Relaxed)
+}
+
+/// Tell all workers in this process to clear their caches.
+///
+/// Returns the new cache generation.
+pub fn bump_cache_generation() -> usize {
+    let generation = CACHE_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    log::info!("Worker cache generation is now {generation}");
+    generation
+}
+
+/// Calls cache_clear() on the worker if the cache generation has
+/// changed since the worker last saw it.
+///
+/// Returns true if the cache was cleared.
+pub fn sync_worker_cache(
+    worker: &mut Box<dyn ApplicationWorker>,
+    seen_generation: &mut usize,
+) -> EgResult<bool> {
+    let generation = cache_generation();
+
+    if generation == *seen_generation {
+        return Ok(false);
+    }
+
+    *seen_generation = generation;
+    worker.cache_clear()?;
+
+    Ok(true)
+}
+
+/// Per-worker cache of JSON values which live across requests.
+#[derive(Debug, Default)]
+pub struct WorkerCache {
+    entries: HashMap<String, (JsonValue, Option<Instant>)>,
+    /// Applied to values stored without an explicit time to live.
+    default_ttl: Option<Duration>,
+}
+
+impl WorkerCache {
+    /// Cache whose values live until removed or cleared.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Cache whose values expire after the provided duration unless
+    /// otherwise specified.
+    pub fn with_ttl(ttl: Duration) -> Self {
+        WorkerCache {
+            entries: HashMap::new(),
+            default_ttl: Some(ttl),
+        }
+    }
+
+    pub fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Returns the cached value if it exists and has not expired.
+    pub fn get(&mut self, key: &str) -> Option<&JsonValue> {
+        let expired = match self.entries.get(key) {
+            Some((_, Some(expires))) => *expires <= Instant::now(),
+            Some(_) => false,
+            None => return None,
+        };
+
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.entries.get(key).map(|(v, _)| v)
+    }
+
+    /// True if an unexpired value exists for the key.
+    pub fn contains(&mut self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Store a value using the default time to live.
+    pub fn set(&mut self, key: &str, value: JsonValue) {
+        self.set_with_ttl(key, value, self.default_ttl);
+    }
+
+    /// Store a value which expires after the provided duration, or
+    /// never if None.
+    pub fn set_with_ttl(&mut self, key: &str, value: JsonValue, ttl: Option<Duration>) {
+        let expires = ttl.map(|t| Instant::now() + t);
+        self.entries.insert(key.to_string(), (value, expires));
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<JsonValue> {
+        self.entries.remove(key).map(|(v, _)| v)
+    }
+
+    /// Remove all values.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of stored values, including any which have expired
+    /// but have not yet been purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[test]
+fn worker_cache_ttl() {
+    let mut cache = WorkerCache::with_ttl(Duration::from_millis(20));
+
+    cache.set("short", JsonValue::from("a"));
+    cache.set_with_ttl("forever", JsonValue::from("b"), None);
+
+    assert_eq!(cache.get("short").and_then(|v| v.as_str()), Some("a"));
+
+    std::thread::sleep(Duration::from_millis(30));
+
+    assert!(cache.get("short").is_none());
+    assert!(cache.contains("forever"));
+    assert_eq!(cache.len(), 1);
+
+    assert_eq!(cache.remove("forever"), Some(JsonValue::from("b")));
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn worker_cache_clear() {
+    struct TestWorker {
+        methods: Arc<HashMap<String, method::MethodDef>>,
+        cache: WorkerCache,
+    }
+
+    impl ApplicationWorker for TestWorker {
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+        fn methods(&self) -> &Arc<HashMap<String, method::MethodDef>> {
+            &self.methods
+        }
+        fn worker_start(
+            &mut self,
+            _: client::Client,
+            _: Arc<HashMap<String, method::MethodDef>>,
+        ) -> EgResult<()> {
+            Ok(())
+        }
+        fn start_session(&mut self) -> EgResult<()> {
+            Ok(())
+        }
+        fn end_session(&mut self) -> EgResult<()> {
+            Ok(())
+        }
+        fn keepalive_timeout(&mut self) -> EgResult<()> {
+            Ok(())
+        }
+        fn api_call_error(&mut self, _: &message::MethodCall, _: EgError) {}
+        fn worker_idle_wake(&mut self, _: bool) -> EgResult<()> {
+            Ok(())
+        }
+        fn worker_end(&mut self) -> EgResult<()> {
+            Ok(())
+        }
+        fn worker_cache(&mut self) -> Option<&mut WorkerCache> {
+            Some(&mut self.cache)
+        }
+    }
+
+    let methods = Arc::new(HashMap::new());
+
+    // Each simulated worker tracks the generation it last saw, as
+    // a worker thread would.
+    let mut workers: Vec<(Box<dyn ApplicationWorker>, usize)> = Vec::new();
+
+    for _ in 0..2 {
+        let mut worker: Box<dyn ApplicationWorker> = Box::new(TestWorker {
+            methods: methods.clone(),
+            cache: WorkerCache::new(),
+        });
+
+        worker
+            .worker_cache()
+            .unwrap()
+            .set("org_tree", JsonValue::from("tree"));
+
+        workers.push((worker, cache_generation()));
+    }
+
+    for (worker, seen) in workers.iter_mut() {
+        assert!(!sync_worker_cache(worker, seen).unwrap());
+        assert_eq!(worker.worker_cache().unwrap().len(), 1);
+    }
+
+    let generation = bump_cache_generation();
+
+    for (worker, seen) in workers.iter_mut() {
+        assert!(sync_worker_cache(worker, seen).unwrap());
+        assert!(worker.worker_cache().unwrap().is_empty());
+        assert!(*seen >= generation);
+    }
+}
@@ -0,0 +1,509 @@
+//! Worker pool scaling decisions for OpenSRF services.
+//!
+//! The Server reports the state of its workers and the number of
+//! requests waiting on the service queue.  The Autoscaler decides how
+//! many workers to spawn and which idle workers to retire.  No threads
+//! are managed here, which keeps the decisions easy to test.
+use crate::osrf::worker::WorkerState;
+use crate::EgValue;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Worker counts for this process, published by the Server for
+/// reporting by the worker stats API.
+static WORKER_COUNTS: OnceLock<Mutex<WorkerCounts>> = OnceLock::new();
+
+/// Minimum time between backlog-driven spawn rounds.
+///
+/// Newly spawned workers need a moment to connect to the bus before
+/// they start draining the queue.  Without a pause, a single burst
+/// would be counted again on every check and spawn far more workers
+/// than needed.
+pub const DEFAULT_SPAWN_COOLDOWN: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct ScalingPolicy {
+    min_workers: usize,
+    max_workers: usize,
+
+    /// Number of idle workers to keep on hand beyond the current
+    /// request backlog.
+    spare_target: usize,
+
+    /// Retire idle workers after this long.  None means idle workers
+    /// are never retired.
+    idle_timeout: Option<Duration>,
+
+    spawn_cooldown: Duration,
+}
+
+impl ScalingPolicy {
+    pub fn new(min_workers: usize, max_workers: usize, spare_target: usize) -> Self {
+        ScalingPolicy {
+            min_workers,
+            max_workers: max_workers.max(min_workers),
+            spare_target,
+            idle_timeout: None,
+            spawn_cooldown: DEFAULT_SPAWN_COOLDOWN,
+        }
+    }
+    pub fn min_workers(&self) -> usize {
+        self.min_workers
+    }
+    pub fn max_workers(&self) -> usize {
+        self.max_workers
+    }
+    pub fn spare_target(&self) -> usize {
+        self.spare_target
+    }
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+    pub fn spawn_cooldown(&self) -> Duration {
+        self.spawn_cooldown
+    }
+    pub fn set_spawn_cooldown(&mut self, cooldown: Duration) {
+        self.spawn_cooldown = cooldown;
+    }
+}
+
+/// What the Autoscaler knows about a single worker.
+#[derive(Debug, Clone)]
+pub struct WorkerSummary {
+    pub worker_id: u64,
+    pub state: WorkerState,
+
+    /// When the worker entered its current state.
+    pub since: Instant,
+
+    /// True if the worker has been asked to exit once its current
+    /// session, if any, is complete.
+    pub retiring: bool,
+}
+
+/// Changes to make to the worker pool.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScalePlan {
+    /// Number of workers to spawn.
+    pub spawn: usize,
+
+    /// Idle workers to retire.
+    pub retire: Vec<u64>,
+}
+
+impl ScalePlan {
+    pub fn is_empty(&self) -> bool {
+        self.spawn == 0 && self.retire.is_empty()
+    }
+}
+
+pub struct Autoscaler {
+    policy: ScalingPolicy,
+
+    /// When we last spawned workers to cover a backlog.
+    last_spawn: Option<Instant>,
+}
+
+impl Autoscaler {
+    pub fn new(policy: ScalingPolicy) -> Self {
+        Autoscaler {
+            policy,
+            last_spawn: None,
+        }
+    }
+
+    pub fn policy(&self) -> &ScalingPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ScalingPolicy) {
+        self.policy = policy;
+    }
+
+    /// Decide how to resize the pool.
+    ///
+    /// `backlog` is the number of requests queued for the service
+    /// which no worker has picked up yet.
+    ///
+    /// Workers are only retired when no workers are spawned, and only
+    /// once they've been idle for the idle timeout, so a pool which
+    /// just grew to meet a burst of requests stays put until the burst
+    /// is well and truly over.
+    pub fn plan(&mut self, workers: &[WorkerSummary], backlog: usize, now: Instant) -> ScalePlan {
+        let mut plan = ScalePlan::default();
+
+        // Retiring workers still occupy a thread, so they count
+        // toward the max, but they take no new sessions.
+        let threads = workers.len();
+        let live = workers.iter().filter(|w| !w.retiring).count();
+        let idle = workers
+            .iter()
+            .filter(|w| !w.retiring && w.state == WorkerState::Idle)
+            .count();
+
+        let room = self.policy.max_workers.saturating_sub(threads);
+
+        // Topping up to the minimum and to the spare target ignores
+        // the cooldown.
+        plan.spawn = self.policy.min_workers.saturating_sub(live).min(room);
+
+        let spares = self
+            .policy
+            .spare_target
+            .saturating_sub(idle + plan.spawn)
+            .min(room - plan.spawn);
+
+        plan.spawn += spares;
+
+        let wanted = backlog + self.policy.spare_target;
+        let have = idle + plan.spawn;
+
+        if have < wanted && self.cooled_down(now) {
+            let extra = (wanted - have).min(room - plan.spawn);
+            if extra > 0 {
+                plan.spawn += extra;
+                self.last_spawn = Some(now);
+            }
+        }
+
+        if plan.spawn > 0 {
+            return plan;
+        }
+
+        let Some(timeout) = self.policy.idle_timeout else {
+            return plan;
+        };
+
+        // Never retire below the minimum or eat into the spares
+        // needed for the current backlog.
+        let allowed = idle
+            .saturating_sub(wanted)
+            .min(live.saturating_sub(self.policy.min_workers));
+
+        if allowed == 0 {
+            return plan;
+        }
+
+        let mut expired: Vec<&WorkerSummary> = workers
+            .iter()
+            .filter(|w| !w.retiring && w.state == WorkerState::Idle)
+            .filter(|w| now.saturating_duration_since(w.since) >= timeout)
+            .collect();
+
+        // Longest-idle workers go first.
+        expired.sort_by_key(|w| w.since);
+
+        plan.retire = expired.iter().take(allowed).map(|w| w.worker_id).collect();
+
+        plan
+    }
+
+    fn cooled_down(&self, now: Instant) -> bool {
+        match self.last_spawn {
+            Some(t) => now.saturating_duration_since(t) >= self.policy.spawn_cooldown,
+            None => true,
+        }
+    }
+}
+
+/// Snapshot of the worker pool for a server process.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerCounts {
+    pub workers: usize,
+    pub idle: usize,
+    pub active: usize,
+    pub retiring: usize,
+
+    /// Requests waiting on the service queue.
+    pub backlog: usize,
+
+    /// Workers spawned since startup.
+    pub spawned: u64,
+
+    /// Workers which have exited since startup, for any reason.
+    pub exited: u64,
+
+    /// Workers retired for sitting idle since startup.
+    pub retired_idle: u64,
+
+    pub min_workers: usize,
+    pub max_workers: usize,
+    pub spare_target: usize,
+}
+
+impl WorkerCounts {
+    /// Replace the counts reported for this process.
+    pub fn publish(&self) {
+        if let Ok(mut c) = WORKER_COUNTS.get_or_init(Default::default).lock() {
+            *c = self.clone();
+        }
+    }
+
+    /// Most recently published counts for this process.
+    pub fn current() -> WorkerCounts {
+        match WORKER_COUNTS.get_or_init(Default::default).lock() {
+            Ok(c) => c.clone(),
+            Err(_) => WorkerCounts::default(),
+        }
+    }
+
+    pub fn to_eg_value(&self) -> EgValue {
+        let mut v = EgValue::new_object();
+        v["workers"] = EgValue::from(self.workers);
+        v["idle"] = EgValue::from(self.idle);
+        v["active"] = EgValue::from(self.active);
+        v["retiring"] = EgValue::from(self.retiring);
+        v["backlog"] = EgValue::from(self.backlog);
+        v["spawned"] = EgValue::from(self.spawned as i64);
+        v["exited"] = EgValue::from(self.exited as i64);
+        v["retired_idle"] = EgValue::from(self.retired_idle as i64);
+        v["min_workers"] = EgValue::from(self.min_workers);
+        v["max_workers"] = EgValue::from(self.max_workers);
+        v["spare_target"] = EgValue::from(self.spare_target);
+        v
+    }
+}
+
+/// Simulated worker pool whose every request takes `request_time`.
+#[cfg(test)]
+struct SlowPool {
+    workers: Vec<WorkerSummary>,
+    /// When each active worker finishes its current request.
+    busy_until: std::collections::HashMap<u64, Instant>,
+    queue: usize,
+    next_id: u64,
+    request_time: Duration,
+    peak: usize,
+}
+
+#[cfg(test)]
+impl SlowPool {
+    fn new(size: usize, request_time: Duration, now: Instant) -> Self {
+        let mut pool = SlowPool {
+            workers: Vec::new(),
+            busy_until: Default::default(),
+            queue: 0,
+            next_id: 0,
+            request_time,
+            peak: 0,
+        };
+        pool.spawn(size, now);
+        pool
+    }
+
+    fn spawn(&mut self, count: usize, now: Instant) {
+        for _ in 0..count {
+            self.next_id += 1;
+            self.workers.push(WorkerSummary {
+                worker_id: self.next_id,
+                state: WorkerState::Idle,
+                since: now,
+                retiring: false,
+            });
+        }
+        self.peak = self.peak.max(self.workers.len());
+    }
+
+    /// Finish requests which are done, retire workers which were
+    /// asked to, and hand queued requests to idle workers.
+    fn tick(&mut self, now: Instant) {
+        for w in self.workers.iter_mut() {
+            if w.state == WorkerState::Active && self.busy_until[&w.worker_id] <= now {
+                w.state = WorkerState::Idle;
+                w.since = now;
+            }
+        }
+
+        // Retiring workers exit once they're between sessions.
+        self.workers
+            .retain(|w| !(w.retiring && w.state == WorkerState::Idle));
+
+        for w in self.workers.iter_mut() {
+            if self.queue == 0 {
+                break;
+            }
+            if w.state == WorkerState::Idle && !w.retiring {
+                self.queue -= 1;
+                w.state = WorkerState::Active;
+                w.since = now;
+                self.busy_until.insert(w.worker_id, now + self.request_time);
+            }
+        }
+    }
+
+    fn apply(&mut self, plan: &ScalePlan, now: Instant) {
+        self.spawn(plan.spawn, now);
+        for w in self.workers.iter_mut() {
+            if plan.retire.contains(&w.worker_id) {
+                w.retiring = true;
+            }
+        }
+    }
+
+    fn active(&self) -> usize {
+        self.workers
+            .iter()
+            .filter(|w| w.state == WorkerState::Active)
+            .count()
+    }
+}
+
+#[test]
+fn autoscale_burst() {
+    let start = Instant::now();
+    let step = Duration::from_millis(500);
+
+    let mut policy = ScalingPolicy::new(3, 20, 1);
+    policy.set_idle_timeout(Some(Duration::from_secs(10)));
+
+    let mut scaler = Autoscaler::new(policy);
+    let mut pool = SlowPool::new(3, Duration::from_secs(5), start);
+
+    // 30 slow requests arrive at once.
+    pool.queue = 30;
+
+    let mut now = start;
+    for _ in 0..120 {
+        pool.tick(now);
+        let plan = scaler.plan(&pool.workers, pool.queue, now);
+
+        // Every worker has at most one request, so the pool never
+        // needs more workers than requests plus spares.
+        assert!(pool.workers.len() + plan.spawn <= 20);
+        pool.apply(&plan, now);
+
+        now += step;
+    }
+
+    // The burst was covered without exceeding the max.
+    assert_eq!(pool.queue, 0);
+    assert_eq!(pool.peak, 20);
+
+    // Once the burst passed, the idle extras were retired back down
+    // to the minimum.
+    pool.tick(now);
+    assert_eq!(pool.active(), 0);
+    assert_eq!(pool.workers.len(), 3);
+}
+
+#[test]
+fn autoscale_hysteresis() {
+    let start = Instant::now();
+
+    let mut policy = ScalingPolicy::new(2, 50, 1);
+    policy.set_spawn_cooldown(Duration::from_secs(2));
+    policy.set_idle_timeout(Some(Duration::from_secs(30)));
+
+    let mut scaler = Autoscaler::new(policy);
+    let mut pool = SlowPool::new(2, Duration::from_secs(3), start);
+
+    pool.queue = 6;
+    pool.tick(start);
+
+    // 2 workers picked up requests; 4 remain queued plus 1 spare.
+    let plan = scaler.plan(&pool.workers, pool.queue, start);
+    assert_eq!(plan.spawn, 5);
+    pool.apply(&plan, start);
+
+    // The new workers haven't picked up the queued requests yet.
+    // The same backlog must not cause another round of spawns.
+    let now = start + Duration::from_millis(100);
+    assert!(scaler.plan(&pool.workers, pool.queue, now).is_empty());
+
+    pool.tick(now);
+    assert_eq!(pool.queue, 0);
+    assert_eq!(pool.active(), 6);
+
+    // Another request takes the last spare, which is replaced right
+    // away, cooldown or not.
+    pool.queue = 1;
+    pool.tick(now);
+    let plan = scaler.plan(&pool.workers, pool.queue, now);
+    assert_eq!(plan.spawn, 1);
+    pool.apply(&plan, now);
+
+    // Requests finish.  The extra workers are idle, but not for long
+    // enough to retire, so a follow-up burst finds them waiting.
+    let now = start + Duration::from_secs(4);
+    pool.tick(now);
+    assert_eq!(pool.active(), 0);
+    assert!(scaler.plan(&pool.workers, 0, now).is_empty());
+
+    pool.queue = 5;
+    pool.tick(now);
+    assert_eq!(pool.queue, 0);
+    assert!(scaler.plan(&pool.workers, 0, now).is_empty());
+}
+
+#[test]
+fn autoscale_retire_idle() {
+    let start = Instant::now();
+    let now = start + Duration::from_secs(60);
+
+    let mut policy = ScalingPolicy::new(2, 10, 1);
+    let worker = |worker_id, state, since| WorkerSummary {
+        worker_id,
+        state,
+        since,
+        retiring: false,
+    };
+
+    let workers = vec![
+        worker(1, WorkerState::Idle, start + Duration::from_secs(50)),
+        worker(2, WorkerState::Idle, start),
+        worker(3, WorkerState::Active, start),
+        worker(4, WorkerState::Idle, start + Duration::from_secs(10)),
+        worker(5, WorkerState::Idle, start + Duration::from_secs(40)),
+    ];
+
+    // No idle timeout, no retirement.
+    let mut scaler = Autoscaler::new(policy.clone());
+    assert!(scaler.plan(&workers, 0, now).is_empty());
+
+    // Workers 1 and 5 haven't been idle long enough.
+    policy.set_idle_timeout(Some(Duration::from_secs(30)));
+    scaler.set_policy(policy);
+    assert_eq!(scaler.plan(&workers, 0, now).retire, vec![2, 4]);
+
+    // Idle workers are kept to cover the backlog.
+    assert_eq!(scaler.plan(&workers, 2, now).retire, vec![2]);
+
+    // Retiring workers are not retired twice or counted as spares.
+    let mut workers = workers;
+    workers[1].retiring = true;
+    workers[3].retiring = true;
+    assert!(scaler.plan(&workers, 0, now).is_empty());
+
+    // Once other idle workers exit, the pool is topped back up to
+    // the minimum, with no retirements in the same round.
+    workers.retain(|w| w.worker_id != 1 && w.worker_id != 5);
+    assert_eq!(
+        scaler.plan(&workers, 0, now),
+        ScalePlan {
+            spawn: 1,
+            retire: vec![]
+        }
+    );
+}
+
+#[test]
+fn worker_counts_publish() {
+    let counts = WorkerCounts {
+        workers: 4,
+        idle: 3,
+        active: 1,
+        spawned: 6,
+        exited: 2,
+        ..Default::default()
+    };
+
+    counts.publish();
+    assert_eq!(WorkerCounts::current(), counts);
+
+    let v = counts.to_eg_value();
+    assert_eq!(v["idle"].int().unwrap(), 3);
+    assert_eq!(v["exited"].int().unwrap(), 2);
+}
@@ -2,18 +2,158 @@ use crate::osrf::addr::BusAddress;
 use crate::osrf::conf;
 use crate::osrf::logging::Logger;
 use crate::osrf::message::TransportMessage;
+use crate::osrf::metrics::OsrfMetrics;
+use crate::result::EgError;
 use crate::util;
 use crate::EgResult;
-use redis::{Commands, ConnectionAddr, ConnectionInfo, RedisConnectionInfo};
+use redis::{Commands, ConnectionAddr, ConnectionInfo, RedisConnectionInfo, RedisResult};
+use std::collections::VecDeque;
 use std::fmt;
+use std::thread;
+use std::time::Instant;
+
+/// Prefix for the keys clients keep alive while they wait for
+/// responses.  The full key is the prefix plus the client address.
+pub const PRESENCE_PREFIX: &str = "opensrf:presence:";
+
+/// Seconds a presence key lives unless refreshed.
+///
+/// Clients refresh their key at least every third of this, including
+/// while blocked waiting on a response.
+pub const PRESENCE_TTL: u64 = 60;
+
+/// The Redis list operations which carry OpenSRF messages.
+///
+/// Implemented by Redis connections, and by fake connections in tests.
+pub trait BusConnection: Send {
+    /// Append a value to the recipient's queue.
+    fn push(&mut self, recipient: &str, value: &str) -> RedisResult<()>;
+
+    /// Pop a value from the recipient's queue without blocking.
+    fn pop(&mut self, recipient: &str) -> RedisResult<Option<String>>;
+
+    /// Pop a value from the recipient's queue, waiting up to `timeout`
+    /// seconds.  0 means wait indefinitely.
+    fn pop_wait(&mut self, recipient: &str, timeout: usize) -> RedisResult<Option<String>>;
+
+    /// Append a value to the recipient's queue and expire the queue
+    /// `ttl` seconds from now.
+    fn push_expiring(&mut self, recipient: &str, value: &str, ttl: u64) -> RedisResult<()>;
+
+    /// Remove the recipient's queue.
+    fn clear(&mut self, recipient: &str) -> RedisResult<()>;
+
+    /// Create or refresh a flag key which expires `ttl` seconds from now.
+    fn set_flag(&mut self, key: &str, ttl: u64) -> RedisResult<()>;
+
+    /// True if the key exists.
+    fn exists(&mut self, key: &str) -> RedisResult<bool>;
+
+    /// The underlying Redis connection for all other commands.
+    fn redis(&mut self) -> Option<&mut redis::Connection>;
+}
+
+impl BusConnection for redis::Connection {
+    fn push(&mut self, recipient: &str, value: &str) -> RedisResult<()> {
+        self.rpush::<_, _, i32>(recipient, value).map(|_| ())
+    }
+
+    fn pop(&mut self, recipient: &str) -> RedisResult<Option<String>> {
+        self.lpop(recipient, None)
+    }
+
+    fn pop_wait(&mut self, recipient: &str, timeout: usize) -> RedisResult<Option<String>> {
+        // BLPOP returns the name of the popped list and the value.
+        let mut resp: Vec<String> = self.blpop(recipient, timeout)?;
+        Ok(if resp.len() > 1 {
+            Some(resp.remove(1))
+        } else {
+            None
+        })
+    }
+
+    fn push_expiring(&mut self, recipient: &str, value: &str, ttl: u64) -> RedisResult<()> {
+        redis::pipe()
+            .atomic()
+            .rpush(recipient, value)
+            .ignore()
+            .expire(recipient, ttl as usize)
+            .ignore()
+            .query(self)
+    }
+
+    fn clear(&mut self, recipient: &str) -> RedisResult<()> {
+        self.del::<_, i32>(recipient).map(|_| ())
+    }
+
+    fn set_flag(&mut self, key: &str, ttl: u64) -> RedisResult<()> {
+        self.set_ex(key, 1, ttl as usize)
+    }
+
+    fn exists(&mut self, key: &str) -> RedisResult<bool> {
+        redis::Commands::exists(self, key)
+    }
+
+    fn redis(&mut self) -> Option<&mut redis::Connection> {
+        Some(self)
+    }
+}
+
+/// Opens bus connections, initially and after a connection is lost.
+pub trait BusConnector: Send {
+    fn connect(&self) -> EgResult<Box<dyn BusConnection>>;
+}
+
+/// Opens authenticated Redis connections.
+struct RedisConnector {
+    info: ConnectionInfo,
+}
+
+impl BusConnector for RedisConnector {
+    fn connect(&self) -> EgResult<Box<dyn BusConnection>> {
+        log::trace!("Bus connecting to {:?}", self.info);
+
+        let client = redis::Client::open(self.info.clone())
+            .map_err(|e| EgError::Transport(format!("Error opening Redis connection: {e}")))?;
+
+        let connection = client
+            .get_connection()
+            .map_err(|e| EgError::Transport(format!("Bus connect error: {e}")))?;
+
+        Ok(Box::new(connection))
+    }
+}
+
+/// True if the error means our connection to the bus is gone.
+fn connection_lost(e: &redis::RedisError) -> bool {
+    e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal()
+}
 
 /// Manages a Redis connection.
+///
+/// If the connection is lost, the Bus reconnects according to its
+/// reconnect policy and retries the failed command once.  Our bus
+/// address survives the reconnect, so replies to requests sent
+/// before the connection was lost are still delivered to us.
 pub struct Bus {
-    connection: redis::Connection,
+    /// None while the bus is unreachable.
+    connection: Option<Box<dyn BusConnection>>,
+
+    connector: Box<dyn BusConnector>,
+
+    reconnect: conf::BusReconnect,
+
+    /// Messages, with their recipients, sent while the bus was
+    /// unreachable.  Delivered in order once we reconnect.
+    outbound: VecDeque<(String, String)>,
 
     /// Every bus connection has a unique client address.
     address: BusAddress,
 
+    /// When we last refreshed our presence key.  None if we have
+    /// never announced our presence.
+    presence_refreshed: Option<Instant>,
+
     /// Name of the router running on our primary domain.
     router_name: String,
 
@@ -26,29 +166,97 @@ pub struct Bus {
 
 impl Bus {
     pub fn new(config: &conf::BusClient) -> EgResult<Self> {
-        let info = Bus::connection_info(config)?;
-
-        log::trace!("Bus::new() connecting to {:?}", info);
+        let connector = RedisConnector {
+            info: Bus::connection_info(config)?,
+        };
 
-        let client = redis::Client::open(info)
-            .or_else(|e| Err(format!("Error opening Redis connection: {e}")))?;
+        let mut bus = Bus::with_connector(
+            Box::new(connector),
+            config.username(),
+            config.domain().name(),
+        )?;
 
-        let connection = client
-            .get_connection()
-            .or_else(|e| Err(format!("Bus connect error: {e}")))?;
+        bus.router_name = config.router_name().to_string();
+        bus.reconnect = config.reconnect().clone();
 
-        let username = config.username();
-        let domain = config.domain().name();
-        let addr = BusAddress::for_client(username, domain);
+        Ok(bus)
+    }
 
-        let bus = Bus {
-            connection,
+    /// Create a Bus whose connections come from the provided connector.
+    pub fn with_connector(
+        connector: Box<dyn BusConnector>,
+        username: &str,
+        domain: &str,
+    ) -> EgResult<Self> {
+        let connection = connector.connect()?;
+
+        Ok(Bus {
+            connection: Some(connection),
+            connector,
+            reconnect: conf::BusReconnect::default(),
+            outbound: VecDeque::new(),
             raw_data_mode: false,
-            address: addr,
-            router_name: config.router_name().to_string(),
-        };
+            address: BusAddress::for_client(username, domain),
+            presence_refreshed: None,
+            router_name: "router".to_string(),
+        })
+    }
 
-        Ok(bus)
+    pub fn reconnect_policy(&self) -> &conf::BusReconnect {
+        &self.reconnect
+    }
+
+    /// Change how this bus connection recovers when the bus goes away.
+    pub fn set_reconnect_policy(&mut self, policy: conf::BusReconnect) {
+        self.reconnect = policy;
+    }
+
+    /// True unless we lost our connection and have yet to reconnect.
+    pub fn connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Number of messages waiting for the bus to return.
+    pub fn buffered(&self) -> usize {
+        self.outbound.len()
+    }
+
+    /// Presence key for a bus address.
+    pub fn presence_key(address: &str) -> String {
+        format!("{PRESENCE_PREFIX}{address}")
+    }
+
+    /// Tell services we're listening for responses.
+    ///
+    /// Once announced, our presence key is refreshed as we receive
+    /// messages and removed when the Bus is dropped.  Refreshes are
+    /// skipped if the key was refreshed recently.
+    pub fn announce_presence(&mut self) -> EgResult<()> {
+        if let Some(t) = self.presence_refreshed {
+            if t.elapsed().as_secs() < PRESENCE_TTL / 3 {
+                return Ok(());
+            }
+        }
+
+        let key = Bus::presence_key(self.address().as_str());
+
+        self.with_connection("announce_presence()", |c| c.set_flag(&key, PRESENCE_TTL))?;
+        self.presence_refreshed = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// True if we have announced our presence.
+    pub fn presence_announced(&self) -> bool {
+        self.presence_refreshed.is_some()
+    }
+
+    /// True if the client at the address is still listening.
+    ///
+    /// Only meaningful for clients which announce their presence.
+    pub fn recipient_present(&mut self, address: &str) -> EgResult<bool> {
+        let key = Bus::presence_key(address);
+        self.with_connection("recipient_present()", |c| c.exists(&key))
     }
 
     pub fn set_raw_data_mode(&mut self, on: bool) {
@@ -104,8 +312,118 @@ impl Bus {
         self.address().username()
     }
 
+    /// The underlying Redis connection.
+    ///
+    /// Panics if the bus is disconnected or is not using Redis.
     pub fn connection(&mut self) -> &mut redis::Connection {
-        &mut self.connection
+        self.connection
+            .as_mut()
+            .and_then(|c| c.redis())
+            .expect("Bus has a Redis connection")
+    }
+
+    /// The underlying Redis connection, reconnecting if needed.
+    fn redis(&mut self) -> EgResult<&mut redis::Connection> {
+        self.ensure_connected()?;
+
+        let name = self.to_string();
+
+        self.connection
+            .as_mut()
+            .and_then(|c| c.redis())
+            .ok_or_else(|| EgError::Transport(format!("{name} does not support Redis commands")))
+    }
+
+    /// Reconnect to the bus if our connection was lost, then deliver
+    /// any messages sent while we were disconnected.
+    fn ensure_connected(&mut self) -> EgResult<()> {
+        if self.connection.is_some() {
+            return Ok(());
+        }
+
+        for attempt in 0..self.reconnect.max_attempts() {
+            thread::sleep(self.reconnect.delay(attempt));
+
+            match self.connector.connect() {
+                Ok(connection) => {
+                    log::info!("{self} reconnected after {} attempt(s)", attempt + 1);
+                    OsrfMetrics::get().bus_reconnected();
+                    self.connection = Some(connection);
+                    return self.flush_outbound();
+                }
+                Err(e) => log::warn!("{self} reconnect attempt {} failed: {e}", attempt + 1),
+            }
+        }
+
+        Err(EgError::Transport(format!(
+            "{self} unable to reconnect to the bus"
+        )))
+    }
+
+    /// Deliver messages sent while we were disconnected.
+    fn flush_outbound(&mut self) -> EgResult<()> {
+        while let Some((recipient, json_str)) = self.outbound.pop_front() {
+            let connection = match self.connection.as_mut() {
+                Some(c) => c,
+                None => break,
+            };
+
+            if let Err(e) = connection.push(&recipient, &json_str) {
+                if connection_lost(&e) {
+                    self.connection = None;
+                }
+                self.outbound.push_front((recipient, json_str));
+                return Err(EgError::Transport(format!(
+                    "{self} unable to send buffered message: {e}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a command against our connection.
+    ///
+    /// If the connection was lost, reconnect and retry the command once.
+    fn with_connection<T>(
+        &mut self,
+        command: &str,
+        f: impl Fn(&mut dyn BusConnection) -> RedisResult<T>,
+    ) -> EgResult<T> {
+        self.ensure_connected()?;
+
+        let connection = self.connection.as_deref_mut().unwrap();
+
+        let error = match f(connection) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+
+        if !connection_lost(&error) || self.reconnect.max_attempts() == 0 {
+            if connection_lost(&error) {
+                self.connection = None;
+            }
+            return Err(EgError::Transport(format!("{command} failed: {error}")));
+        }
+
+        log::warn!("{self} lost its bus connection in {command}: {error}");
+
+        self.connection = None;
+        self.ensure_connected()?;
+
+        let connection = self.connection.as_deref_mut().unwrap();
+
+        match f(connection) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                if connection_lost(&e) {
+                    self.connection = None;
+                }
+                Err(EgError::Transport(format!(
+                    "{command} failed after reconnect: {e}"
+                )))
+            }
+        }
     }
 
     /// Returns at most one String pulled from the queue or None if the
@@ -122,45 +440,36 @@ impl Bus {
             None => self.address().as_str().to_string(),
         };
 
-        let value: String;
-
         if timeout == 0 {
-            // non-blocking
-
-            // LPOP returns a scalar response.
-            value = match self.connection().lpop(&recipient, None) {
-                Ok(c) => c,
-                Err(e) => match e.kind() {
-                    redis::ErrorKind::TypeError => {
-                        // Will read a Nil value on timeout.  That's OK.
-                        return Ok(None);
-                    }
-                    _ => return Err(format!("recv_one_chunk failed: {e}").into()),
-                },
-            };
-        } else {
-            // Blocking
+            // Non-blocking
+            return self.with_connection("recv_one_chunk()", |c| c.pop(&recipient));
+        }
 
-            if timeout < 0 {
-                // Timeout 0 means block indefinitely in Redis.
-                timeout = 0;
+        if self.presence_announced() {
+            if let Err(e) = self.announce_presence() {
+                log::warn!("{self} could not refresh presence: {e}");
             }
 
-            let mut resp: Vec<String> = self
-                .connection()
-                .blpop(&recipient, timeout as usize)
-                .or_else(|e| Err(format!("Redis blpop error recipient={recipient} : {e}")))?;
-
-            if resp.len() > 1 {
-                // BLPOP returns the name of the popped list and the value.
-                // resp = [key, value]
-                value = resp.remove(1);
-            } else {
-                // No message received
-                return Ok(None);
+            // Wake in time to refresh our presence key before it
+            // expires.  Callers waiting longer loop around.
+            let max_wait = (PRESENCE_TTL / 3) as i32;
+            if timeout < 0 || timeout > max_wait {
+                timeout = max_wait;
             }
         }
 
+        if timeout < 0 {
+            // Timeout 0 means block indefinitely in Redis.
+            timeout = 0;
+        }
+
+        let value = match self.with_connection("recv_one_chunk()", |c| {
+            c.pop_wait(&recipient, timeout as usize)
+        })? {
+            Some(v) => v,
+            None => return Ok(None), // No message received
+        };
+
         log::trace!("recv_one_value() pulled from bus: {}", value);
 
         Ok(Some(value))
@@ -282,24 +591,40 @@ impl Bus {
 
     /// Send a TransportMessage to the "to" value in the message.
     pub fn send(&mut self, msg: TransportMessage) -> EgResult<()> {
-        self.send_internal(msg, None)
+        self.send_internal(msg, None, None)
     }
 
     /// Send a TransportMessage to the specified BusAddress, regardless
     /// of what value is in the msg.to() field.
     pub fn send_to(&mut self, msg: TransportMessage, recipient: &str) -> EgResult<()> {
-        self.send_internal(msg, Some(recipient))
+        self.send_internal(msg, Some(recipient), None)
+    }
+
+    /// Send a TransportMessage to the "to" value in the message and
+    /// expire the recipient's queue `ttl` seconds from now, so
+    /// responses nobody reads don't linger.
+    ///
+    /// Messages held while disconnected are sent without a TTL.
+    pub fn send_expiring(&mut self, msg: TransportMessage, ttl: u64) -> EgResult<()> {
+        self.send_internal(msg, None, Some(ttl))
     }
 
     /// Sends a TransportMessage to the specified BusAddress, regardless
     /// of what value is in the msg.to() field.
-    fn send_internal(&mut self, msg: TransportMessage, recipient: Option<&str>) -> EgResult<()> {
+    fn send_internal(
+        &mut self,
+        msg: TransportMessage,
+        recipient: Option<&str>,
+        ttl: Option<u64>,
+    ) -> EgResult<()> {
         let mut json_val = msg.into_json_value();
 
         // Play a little inside baseball here and tag the message
-        // with our log trace.  This way the layers above don't have
-        // to worry about it.
-        json_val["osrf_xid"] = json::from(Logger::get_log_trace());
+        // with our log trace, unless the caller provided one.  This
+        // way the layers above don't have to worry about it.
+        if json_val["osrf_xid"].as_str().unwrap_or("").is_empty() {
+            json_val["osrf_xid"] = json::from(Logger::get_log_trace());
+        }
 
         // Similarly, this allows us to avoid an unnecessary clone
         // on the recipient if it resides in the now-moved source message.
@@ -311,21 +636,27 @@ impl Bus {
 
         log::trace!("send() writing chunk to={}: {}", recipient, json_str);
 
-        let res: Result<i32, _> = self.connection().rpush(recipient, json_str);
+        let result = self.with_connection("send()", |c| match ttl {
+            Some(t) => c.push_expiring(recipient, &json_str, t),
+            None => c.push(recipient, &json_str),
+        });
 
-        if let Err(e) = res {
-            return Err(format!("Error in send() {e}").into());
+        match result {
+            Err(e) if !self.connected() && self.outbound.len() < self.reconnect.max_buffered() => {
+                log::warn!("{self} holding message for {recipient} until we reconnect: {e}");
+                self.outbound.push_back((recipient.to_string(), json_str));
+                Ok(())
+            }
+            _ => result,
         }
-
-        Ok(())
     }
 
     /// Returns a list of keys that match the provided pattern.
     pub fn keys(&mut self, pattern: &str) -> EgResult<Vec<String>> {
-        let res: Result<Vec<String>, _> = self.connection().keys(pattern);
+        let res: Result<Vec<String>, _> = self.redis()?.keys(pattern);
 
         if let Err(e) = res {
-            return Err(format!("Error in keys(): {e}").into());
+            return Err(EgError::Transport(format!("Error in keys(): {e}")));
         }
 
         Ok(res.unwrap())
@@ -333,10 +664,10 @@ impl Bus {
 
     /// Returns the length of the array specified by 'key'.
     pub fn llen(&mut self, key: &str) -> EgResult<i32> {
-        let res: Result<i32, _> = self.connection().llen(key);
+        let res: Result<i32, _> = self.redis()?.llen(key);
 
         if let Err(e) = res {
-            return Err(format!("Error in llen(): {e}").into());
+            return Err(EgError::Transport(format!("Error in llen(): {e}")));
         }
 
         Ok(res.unwrap())
@@ -346,10 +677,10 @@ impl Bus {
     ///
     /// Return -1 if no expire time is set, -2 if no such key exists.
     pub fn ttl(&mut self, key: &str) -> EgResult<i32> {
-        let res: Result<i32, _> = self.connection().ttl(key);
+        let res: Result<i32, _> = self.redis()?.ttl(key);
 
         if let Err(e) = res {
-            return Err(format!("Error in ttl(): {e}").into());
+            return Err(EgError::Transport(format!("Error in ttl(): {e}")));
         }
 
         Ok(res.unwrap())
@@ -357,10 +688,10 @@ impl Bus {
 
     /// Returns an array slice as a Vec of Strings.
     pub fn lrange(&mut self, key: &str, start: isize, stop: isize) -> EgResult<Vec<String>> {
-        let res: Result<Vec<String>, _> = self.connection().lrange(key, start, stop);
+        let res: Result<Vec<String>, _> = self.redis()?.lrange(key, start, stop);
 
         if let Err(e) = res {
-            return Err(format!("Error in lrange(): {e}").into());
+            return Err(EgError::Transport(format!("Error in lrange(): {e}")));
         }
 
         Ok(res.unwrap())
@@ -368,27 +699,45 @@ impl Bus {
 
     /// Set the expire time on the specified key to 'timeout' seconds from now.
     pub fn set_key_timeout(&mut self, key: &str, timeout: u64) -> EgResult<i32> {
-        let res: Result<i32, _> = self.connection().expire(key, timeout as usize);
+        let res: Result<i32, _> = self.redis()?.expire(key, timeout as usize);
 
         if let Err(ref e) = res {
-            Err(format!("Error in set_key_timeout(): {e}"))?;
+            Err(EgError::Transport(format!(
+                "Error in set_key_timeout(): {e}"
+            )))?;
         }
 
         let val = res.unwrap();
         Ok(val)
     }
 
-    /// Remove all pending data from the recipient queue.
-    pub fn clear_bus(&mut self) -> EgResult<()> {
-        let stream = self.address().as_str().to_string(); // mut borrow
-        let res: Result<i32, _> = self.connection().del(stream);
+    /// Verify our Redis connection is still usable.
+    pub fn ping(&mut self) -> EgResult<()> {
+        let res: Result<String, _> = redis::cmd("PING").query(self.redis()?);
 
         if let Err(e) = res {
-            return Err(format!("Error in queue clear(): {e}").into());
+            return Err(EgError::Transport(format!("Error in ping(): {e}")));
         }
 
         Ok(())
     }
+
+    /// Publish a message to all subscribers of a pub/sub channel.
+    pub fn publish(&mut self, channel: &str, msg: &str) -> EgResult<()> {
+        let res: Result<i32, _> = self.redis()?.publish(channel, msg);
+
+        if let Err(e) = res {
+            return Err(EgError::Transport(format!("Error in publish(): {e}")));
+        }
+
+        Ok(())
+    }
+
+    /// Remove all pending data from the recipient queue.
+    pub fn clear_bus(&mut self) -> EgResult<()> {
+        let stream = self.address().as_str().to_string(); // mut borrow
+        self.with_connection("clear_bus()", |c| c.clear(&stream))
+    }
 }
 
 /// Good for debugging / logging
@@ -405,7 +754,342 @@ impl Drop for Bus {
     /// Similar to clear_bus but avoids any logging / error reporting.
     fn drop(&mut self) {
         let stream = self.address().as_str().to_string();
-        let res: Result<i32, _> = self.connection().del(&stream);
-        res.ok();
+        let presence = self.presence_announced();
+        if let Some(connection) = self.connection.as_mut() {
+            connection.clear(&stream).ok();
+            if presence {
+                connection.clear(&Bus::presence_key(&stream)).ok();
+            }
+        }
+    }
+}
+
+/// In-memory stand-in for Redis which can be restarted, dropping
+/// every connection opened before the restart.
+#[cfg(test)]
+#[derive(Default)]
+struct FakeBus {
+    queues: std::collections::HashMap<String, VecDeque<String>>,
+    /// TTLs applied to queues and flags.
+    ttls: std::collections::HashMap<String, u64>,
+    flags: std::collections::HashSet<String>,
+    generation: usize,
+    /// Number of upcoming connection attempts which should fail.
+    refuse: usize,
+    connects: usize,
+}
+
+#[cfg(test)]
+type SharedFakeBus = std::sync::Arc<std::sync::Mutex<FakeBus>>;
+
+#[cfg(test)]
+impl FakeBus {
+    /// Drop all connections.  Queued messages survive, like a Redis
+    /// restart with persistence enabled.
+    fn restart(bus: &SharedFakeBus, refuse: usize) {
+        let mut bus = bus.lock().unwrap();
+        bus.generation += 1;
+        bus.refuse = refuse;
+    }
+
+    fn queue(bus: &SharedFakeBus, recipient: &str) -> Vec<String> {
+        let bus = bus.lock().unwrap();
+        bus.queues
+            .get(recipient)
+            .map(|q| q.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+struct FakeConnection {
+    bus: SharedFakeBus,
+    generation: usize,
+}
+
+#[cfg(test)]
+impl FakeConnection {
+    fn check(&self) -> RedisResult<std::sync::MutexGuard<'_, FakeBus>> {
+        let bus = self.bus.lock().unwrap();
+        if bus.generation != self.generation {
+            let e = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "fake bus restarted");
+            return Err(e.into());
+        }
+        Ok(bus)
+    }
+}
+
+#[cfg(test)]
+impl BusConnection for FakeConnection {
+    fn push(&mut self, recipient: &str, value: &str) -> RedisResult<()> {
+        let mut bus = self.check()?;
+        let queue = bus.queues.entry(recipient.to_string()).or_default();
+        queue.push_back(value.to_string());
+        Ok(())
+    }
+
+    fn pop(&mut self, recipient: &str) -> RedisResult<Option<String>> {
+        let mut bus = self.check()?;
+        Ok(bus.queues.get_mut(recipient).and_then(|q| q.pop_front()))
+    }
+
+    fn pop_wait(&mut self, recipient: &str, _timeout: usize) -> RedisResult<Option<String>> {
+        self.pop(recipient)
+    }
+
+    fn push_expiring(&mut self, recipient: &str, value: &str, ttl: u64) -> RedisResult<()> {
+        self.push(recipient, value)?;
+        self.check()?.ttls.insert(recipient.to_string(), ttl);
+        Ok(())
+    }
+
+    fn clear(&mut self, recipient: &str) -> RedisResult<()> {
+        let mut bus = self.check()?;
+        bus.queues.remove(recipient);
+        bus.flags.remove(recipient);
+        bus.ttls.remove(recipient);
+        Ok(())
+    }
+
+    fn set_flag(&mut self, key: &str, ttl: u64) -> RedisResult<()> {
+        let mut bus = self.check()?;
+        bus.flags.insert(key.to_string());
+        bus.ttls.insert(key.to_string(), ttl);
+        Ok(())
+    }
+
+    fn exists(&mut self, key: &str) -> RedisResult<bool> {
+        let bus = self.check()?;
+        Ok(bus.flags.contains(key) || bus.queues.contains_key(key))
     }
+
+    fn redis(&mut self) -> Option<&mut redis::Connection> {
+        None
+    }
+}
+
+#[cfg(test)]
+struct FakeConnector {
+    bus: SharedFakeBus,
+}
+
+#[cfg(test)]
+impl BusConnector for FakeConnector {
+    fn connect(&self) -> EgResult<Box<dyn BusConnection>> {
+        let mut bus = self.bus.lock().unwrap();
+
+        if bus.refuse > 0 {
+            bus.refuse -= 1;
+            return Err(EgError::Transport("fake bus is down".to_string()));
+        }
+
+        bus.connects += 1;
+
+        Ok(Box::new(FakeConnection {
+            bus: self.bus.clone(),
+            generation: bus.generation,
+        }))
+    }
+}
+
+#[cfg(test)]
+fn fake_bus(max_attempts: u32, max_buffered: usize) -> (SharedFakeBus, Bus) {
+    let fake = SharedFakeBus::default();
+
+    let connector = FakeConnector { bus: fake.clone() };
+    let mut bus = Bus::with_connector(Box::new(connector), "opensrf", "localhost").unwrap();
+
+    let mut policy = conf::BusReconnect::default();
+    policy.set_max_attempts(max_attempts);
+    policy.set_initial_delay(std::time::Duration::from_millis(1));
+    policy.set_max_buffered(max_buffered);
+    bus.set_reconnect_policy(policy);
+
+    (fake, bus)
+}
+
+#[cfg(test)]
+fn thread_of(json_str: &str) -> String {
+    json::parse(json_str).unwrap()["thread"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn reconnect_disabled_by_default() {
+    assert_eq!(conf::BusReconnect::default().max_attempts(), 0);
+}
+
+#[test]
+fn reconnect_delays() {
+    let mut policy = conf::BusReconnect::default();
+    policy.set_initial_delay(std::time::Duration::from_millis(100));
+    policy.set_max_delay(std::time::Duration::from_millis(500));
+
+    let delays: Vec<u128> = (0..6).map(|n| policy.delay(n).as_millis()).collect();
+    assert_eq!(delays, [0, 100, 200, 400, 500, 500]);
+}
+
+#[test]
+fn reconnect_mid_request() {
+    let (fake, mut bus) = fake_bus(3, 0);
+    let my_addr = bus.address().as_str().to_string();
+
+    let request = TransportMessage::new("opensrf:service:test", &my_addr, "thread-1");
+    bus.send(request).unwrap();
+    assert_eq!(FakeBus::queue(&fake, "opensrf:service:test").len(), 1);
+
+    // The bus restarts, refusing the first connection attempt, while
+    // the service is still working on our request.
+    FakeBus::restart(&fake, 1);
+
+    // The service delivers its reply once the bus is back.
+    let reply = TransportMessage::new(&my_addr, "opensrf:client:service", "thread-1");
+    let reply = reply.into_json_value().dump();
+    fake.lock()
+        .unwrap()
+        .queues
+        .entry(my_addr.clone())
+        .or_default()
+        .push_back(reply);
+
+    // Our address survives the reconnect, so the reply reaches us.
+    let tmsg = bus.recv(1, None).unwrap().expect("Reply received");
+    assert_eq!(tmsg.thread(), "thread-1");
+    assert!(bus.connected());
+    assert_eq!(fake.lock().unwrap().connects, 2);
+}
+
+#[test]
+fn reconnect_retries_send() {
+    let (fake, mut bus) = fake_bus(3, 0);
+
+    FakeBus::restart(&fake, 0);
+
+    let tmsg = TransportMessage::new("opensrf:service:test", "me", "thread-2");
+    bus.send(tmsg).unwrap();
+
+    let queue = FakeBus::queue(&fake, "opensrf:service:test");
+    assert_eq!(queue.len(), 1);
+    assert_eq!(thread_of(&queue[0]), "thread-2");
+}
+
+#[test]
+fn reconnect_fail_fast() {
+    let (fake, mut bus) = fake_bus(2, 0);
+
+    FakeBus::restart(&fake, 5);
+
+    let tmsg = TransportMessage::new("opensrf:service:test", "me", "thread-3");
+    assert!(bus.send(tmsg).is_err());
+    assert!(!bus.connected());
+    assert!(bus.recv(0, None).is_err());
+
+    // Once the bus is back, the next call reconnects.
+    fake.lock().unwrap().refuse = 0;
+    assert!(bus.recv(0, None).unwrap().is_none());
+    assert!(bus.connected());
+}
+
+#[test]
+fn reconnect_buffers_sends() {
+    let (fake, mut bus) = fake_bus(1, 2);
+
+    FakeBus::restart(&fake, 100);
+
+    for thread in ["t1", "t2"] {
+        let tmsg = TransportMessage::new("opensrf:service:test", "me", thread);
+        bus.send(tmsg).unwrap();
+    }
+
+    assert_eq!(bus.buffered(), 2);
+
+    // Buffer is full.
+    let tmsg = TransportMessage::new("opensrf:service:test", "me", "t3");
+    assert!(bus.send(tmsg).is_err());
+
+    fake.lock().unwrap().refuse = 0;
+
+    // Buffered messages are delivered first and in order.
+    let tmsg = TransportMessage::new("opensrf:service:test", "me", "t4");
+    bus.send(tmsg).unwrap();
+
+    let threads: Vec<String> = FakeBus::queue(&fake, "opensrf:service:test")
+        .iter()
+        .map(|j| thread_of(j))
+        .collect();
+
+    assert_eq!(threads, ["t1", "t2", "t4"]);
+    assert_eq!(bus.buffered(), 0);
+}
+
+#[test]
+fn presence_lifecycle() {
+    let (fake, mut client_bus) = fake_bus(0, 0);
+    let client_addr = client_bus.address().as_str().to_string();
+
+    let connector = FakeConnector { bus: fake.clone() };
+    let mut server_bus = Bus::with_connector(Box::new(connector), "opensrf", "localhost").unwrap();
+
+    assert!(!client_bus.presence_announced());
+    assert!(!server_bus.recipient_present(&client_addr).unwrap());
+
+    client_bus.announce_presence().unwrap();
+    assert!(server_bus.recipient_present(&client_addr).unwrap());
+
+    let key = Bus::presence_key(&client_addr);
+    assert_eq!(fake.lock().unwrap().ttls.get(&key), Some(&PRESENCE_TTL));
+
+    // A client which exits cleanly takes its presence with it.
+    drop(client_bus);
+    assert!(!server_bus.recipient_present(&client_addr).unwrap());
+}
+
+#[test]
+fn orphaned_stream_stops() {
+    use crate::osrf::client::Client;
+    use crate::osrf::session::ServerSession;
+
+    let (fake, mut client_bus) = fake_bus(0, 0);
+    client_bus.announce_presence().unwrap();
+    let client_addr = client_bus.address().as_str().to_string();
+
+    let connector = FakeConnector { bus: fake.clone() };
+    let server_bus = Bus::with_connector(Box::new(connector), "opensrf", "localhost").unwrap();
+
+    let mut session = ServerSession::new(
+        Client::from_bus(server_bus),
+        "test",
+        "thread-1",
+        1,
+        BusAddress::from_str(&client_addr).unwrap(),
+    );
+
+    session.set_sender_presence(true);
+    session.set_reply_ttl(30);
+
+    // A handler streaming a slow, expensive response.
+    let mut sent = 0;
+    for i in 0..100 {
+        if i == 3 {
+            // The kiosk loses power.  It never removes its presence
+            // key, which expires instead.
+            fake.lock().unwrap().flags.clear();
+        }
+
+        if session.respond(format!("chunk {i}")).is_err() {
+            break;
+        }
+
+        sent += 1;
+    }
+
+    assert_eq!(sent, 3);
+    assert!(session.abandoned());
+    assert!(session.send_complete().is_err());
+
+    // Replies sent before the client vanished expire unread.
+    assert_eq!(FakeBus::queue(&fake, &client_addr).len(), 3);
+    assert_eq!(fake.lock().unwrap().ttls.get(&client_addr), Some(&30));
 }
@@ -1,10 +1,12 @@
 use crate::osrf::addr::BusAddress;
 use crate::osrf::bus;
 use crate::osrf::conf;
+use crate::osrf::events::ServiceEvents;
 use crate::osrf::message;
 use crate::osrf::params::ApiParams;
 use crate::osrf::session::ClientSession;
 use crate::osrf::session::ResponseIterator;
+use crate::osrf::websocket::{WebsocketOptions, WebsocketTransport};
 use crate::util;
 use crate::{EgResult, EgValue};
 use log::info;
@@ -12,6 +14,7 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
+use std::time::Duration;
 
 /// Generally speaking, we only need 1 ClientSingleton per thread (hence
 /// the name).  This manages one bus connection per domain and stores
@@ -31,6 +34,19 @@ pub struct ClientSingleton {
     /// Queue of receieved transport messages that have yet to be
     /// processed by any sessions.
     backlog: Vec<message::TransportMessage>,
+
+    /// Set when talking to OpenSRF via the websocket translator
+    /// instead of the bus.
+    websocket: Option<WebsocketTransport>,
+
+    /// Ask services to compress large results.
+    accept_compression: bool,
+
+    /// Locale sent with our requests.
+    locale: Option<String>,
+
+    /// Timezone sent with our requests.
+    timezone: Option<String>,
 }
 
 impl ClientSingleton {
@@ -41,13 +57,32 @@ impl ClientSingleton {
 
     /// Create a new singleton instance from a previously setup Bus.
     fn from_bus(bus: bus::Bus) -> ClientSingleton {
-        let domain = conf::config().client().domain().name();
+        let domain = bus.domain().to_string();
 
         ClientSingleton {
-            domain: domain.to_string(),
+            domain,
             bus: Some(bus),
             backlog: Vec::new(),
             remote_bus_map: HashMap::new(),
+            websocket: None,
+            accept_compression: false,
+            locale: None,
+            timezone: None,
+        }
+    }
+
+    /// Create a new singleton instance which communicates via the
+    /// websocket translator.
+    fn from_websocket(websocket: WebsocketTransport) -> ClientSingleton {
+        ClientSingleton {
+            domain: websocket.address().domain().to_string(),
+            bus: None,
+            backlog: Vec::new(),
+            remote_bus_map: HashMap::new(),
+            websocket: Some(websocket),
+            accept_compression: false,
+            locale: None,
+            timezone: None,
         }
     }
 
@@ -59,7 +94,68 @@ impl ClientSingleton {
 
     /// Our full bus address as a string
     fn address(&self) -> &str {
-        self.bus().address().as_str()
+        match self.websocket.as_ref() {
+            Some(ws) => ws.address().as_str(),
+            None => self.bus().address().as_str(),
+        }
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        self.websocket.is_some()
+    }
+
+    /// Send a message to a router on our primary domain for delivery.
+    ///
+    /// Via websockets, the translator handles routing.
+    pub fn send_to_router(
+        &mut self,
+        tmsg: message::TransportMessage,
+        router_addr: &str,
+    ) -> EgResult<()> {
+        match self.websocket.as_mut() {
+            Some(ws) => ws.send(tmsg),
+            None => self.bus_mut().send_to(tmsg, router_addr),
+        }
+    }
+
+    /// Send a message directly to its recipient via the bus
+    /// connection for the provided domain.
+    pub fn send_to_domain(
+        &mut self,
+        tmsg: message::TransportMessage,
+        domain: &str,
+    ) -> EgResult<()> {
+        match self.websocket.as_mut() {
+            Some(ws) => ws.send(tmsg),
+            None => self.get_domain_bus(domain)?.send(tmsg),
+        }
+    }
+
+    /// Announce our presence on our primary bus so services can tell
+    /// we're still waiting for their responses.
+    ///
+    /// Returns false if our presence cannot be announced, e.g. when
+    /// using websockets.
+    pub fn announce_presence(&mut self) -> bool {
+        if self.websocket.is_some() {
+            return false;
+        }
+
+        match self.bus_mut().announce_presence() {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Cannot announce presence: {e}");
+                false
+            }
+        }
+    }
+
+    /// Receive one transport message from our primary connection.
+    fn recv_transport(&mut self, timeout: i32) -> EgResult<Option<message::TransportMessage>> {
+        match self.websocket.as_mut() {
+            Some(ws) => ws.recv(timeout),
+            None => self.bus_mut().recv(timeout, None),
+        }
     }
 
     /// Our primary bus domain
@@ -162,7 +258,7 @@ impl ClientSingleton {
         let timer = util::Timer::new(timeout);
 
         while self.backlog.is_empty() && !timer.done() {
-            if let Some(tm) = self.bus_mut().recv(timer.remaining(), None)? {
+            if let Some(tm) = self.recv_transport(timer.remaining())? {
                 self.backlog.push(tm);
                 break;
             }
@@ -189,7 +285,7 @@ impl ClientSingleton {
 
             // See what we can pull from the message bus
 
-            if let Some(tm) = self.bus_mut().recv(timer.remaining(), None)? {
+            if let Some(tm) = self.recv_transport(timer.remaining())? {
                 self.backlog.push(tm);
             }
 
@@ -207,6 +303,10 @@ impl ClientSingleton {
         router_command: &str,
         router_class: Option<&str>,
     ) -> EgResult<()> {
+        if self.is_websocket() {
+            return Err("Router commands are not supported via websockets".into());
+        }
+
         let addr = BusAddress::for_router(username, domain);
 
         // Always use the from address of our primary Bus
@@ -270,6 +370,25 @@ impl Client {
         })
     }
 
+    /// Create a new Client which talks to OpenSRF via a websocket
+    /// translator, e.g. "wss://eg.example.org:443/osrf-websocket-translator".
+    ///
+    /// Requires no OpenSRF configuration.  Sessions share the one
+    /// websocket connection.  Router commands are not supported.
+    pub fn connect_via_websocket(uri: &str, options: &WebsocketOptions) -> EgResult<Client> {
+        let websocket = WebsocketTransport::connect(uri, options)?;
+        let singleton = ClientSingleton::from_websocket(websocket);
+
+        let address = BusAddress::from_str(singleton.address())?;
+        let domain = singleton.domain().to_string();
+
+        Ok(Client {
+            address,
+            domain,
+            singleton: Rc::new(RefCell::new(singleton)),
+        })
+    }
+
     /// Create a new Client from an existing Bus connection.
     ///
     /// This can be handy because a Bus is Send-able, but a Client is not.
@@ -305,6 +424,50 @@ impl Client {
         &self.singleton
     }
 
+    /// True if this client talks to OpenSRF via the websocket translator.
+    pub fn is_websocket(&self) -> bool {
+        self.singleton.borrow().is_websocket()
+    }
+
+    /// True if our requests tell services we can read compressed results.
+    pub fn accept_compression(&self) -> bool {
+        self.singleton.borrow().accept_compression
+    }
+
+    /// Tell services we can read compressed results.
+    ///
+    /// Services compress results above their configured
+    /// compress_threshold.  Decompression is transparent to callers.
+    /// Applies to all clones of this client.
+    pub fn set_accept_compression(&self, accept: bool) {
+        self.singleton.borrow_mut().accept_compression = accept;
+    }
+
+    /// Locale sent with our requests, if any.
+    pub fn locale(&self) -> Option<String> {
+        self.singleton.borrow().locale.clone()
+    }
+
+    /// Send this locale with our requests instead of the thread locale.
+    ///
+    /// Applies to all clones of this client.
+    pub fn set_locale(&self, locale: Option<&str>) {
+        self.singleton.borrow_mut().locale = locale.map(|l| l.to_string());
+    }
+
+    /// Timezone sent with our requests, if any.
+    pub fn timezone(&self) -> Option<String> {
+        self.singleton.borrow().timezone.clone()
+    }
+
+    /// Send this timezone with our requests instead of the default.
+    ///
+    /// Services use it when rendering dates, e.g. circulation due
+    /// dates.  Applies to all clones of this client.
+    pub fn set_timezone(&self, timezone: Option<&str>) {
+        self.singleton.borrow_mut().timezone = timezone.map(|t| t.to_string());
+    }
+
     /// Clone an existing Client.
     ///
     /// Clones live atop a shared Bus connection and do not need
@@ -334,6 +497,12 @@ impl Client {
     /// stream of pending messages on the bus.
     pub fn clear(&self) -> EgResult<()> {
         self.singleton().borrow_mut().clear_backlog();
+
+        if self.is_websocket() {
+            // Nothing is queued on our behalf.
+            return Ok(());
+        }
+
         self.singleton().borrow_mut().bus_mut().clear_bus()
     }
 
@@ -386,4 +555,53 @@ impl Client {
 
         req.first()
     }
+
+    /// Sends an API request and returns the first response, waiting
+    /// at most `timeout` for the request to complete.
+    ///
+    /// On timeout, the request is cancelled and an EgError::Timeout
+    /// is returned.
+    pub fn send_recv_one_with_timeout(
+        &self,
+        service: &str,
+        method: &str,
+        params: impl Into<ApiParams>,
+        timeout: Duration,
+    ) -> EgResult<Option<EgValue>> {
+        let mut ses = self.session(service);
+        let mut req = ses.request(method, params)?;
+
+        req.first_timeout(timeout)
+    }
+
+    /// Call a router info API on our primary domain, e.g.
+    /// "opensrf.router.info.stats.class.summary", optionally limited
+    /// to a single service.
+    pub fn router_info(&self, method: &str, service: Option<&str>) -> EgResult<EgValue> {
+        let params = service.map(EgValue::from);
+
+        self.send_recv_one("router", method, params)?
+            .ok_or_else(|| format!("Router returned no response to {method}").into())
+    }
+
+    /// Subscribe to service registration events announced by the
+    /// router on our primary domain.
+    ///
+    /// Routers which do not announce events leave the subscription
+    /// quiet.  Not supported for websocket clients.
+    pub fn subscribe_service_events(&self) -> EgResult<ServiceEvents> {
+        if self.is_websocket() {
+            return Err("Service events are not available via websocket".into());
+        }
+
+        ServiceEvents::subscribe(conf::config().client())
+    }
+
+    /// Names of the services registered with our router.
+    pub fn router_services(&self) -> EgResult<Vec<String>> {
+        self.router_info("opensrf.router.info.class.list", None)?
+            .members()
+            .map(|s| s.string())
+            .collect()
+    }
 }
@@ -0,0 +1,165 @@
+//! zlib compression for OpenSRF message payloads.
+//!
+//! Compressed payloads are zlib (RFC 1950) streams, base64-encoded so
+//! they can travel as JSON strings.  Payloads which inflate beyond
+//! max_inflated_size() bytes are rejected, so a small message from a
+//! bus peer cannot exhaust our memory.
+use crate::EgResult;
+use base64::engine::general_purpose::STANDARD as B64;
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use json::JsonValue;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Name of our compression scheme as it appears in message envelopes.
+pub const COMPRESSION_ZLIB: &str = "zlib";
+
+/// Default limit on the inflated size of a compressed payload.
+pub const DEFAULT_MAX_INFLATED_SIZE: usize = 64 * 1024 * 1024;
+
+static MAX_INFLATED_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_INFLATED_SIZE);
+
+/// Compressed payloads which inflate beyond this many bytes are rejected.
+pub fn max_inflated_size() -> usize {
+    MAX_INFLATED_SIZE.load(Ordering::Relaxed)
+}
+
+/// Applies to all threads.  Set from the client's max_inflated_size
+/// config value when the OpenSRF config is stored.
+pub fn set_max_inflated_size(size: usize) {
+    MAX_INFLATED_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Compress a JSON value, returning the base64 text of the compressed
+/// JSON string.
+pub fn compress_json(value: &JsonValue) -> String {
+    compress_json_str(&value.dump())
+}
+
+/// Compress an already serialized JSON string.
+pub fn compress_json_str(json_str: &str) -> String {
+    B64.encode(zlib_compress(json_str.as_bytes()))
+}
+
+/// Inverse of compress_json().
+pub fn decompress_json(text: &str) -> EgResult<JsonValue> {
+    let bytes = B64
+        .decode(text)
+        .map_err(|e| format!("Invalid base64 payload: {e}"))?;
+
+    let bytes = zlib_decompress(&bytes, max_inflated_size())?;
+
+    let json_str =
+        String::from_utf8(bytes).map_err(|e| format!("Compressed payload is not UTF-8: {e}"))?;
+
+    json::parse(&json_str).map_err(|e| format!("Compressed payload is not JSON: {e}").into())
+}
+
+/// Compress bytes into a zlib stream.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+
+    // Writes to a Vec cannot fail.
+    encoder.write_all(data).expect("zlib write to Vec");
+    encoder.finish().expect("zlib finish to Vec")
+}
+
+/// Decompress a zlib stream, failing if it inflates beyond max_size
+/// bytes.
+pub fn zlib_decompress(data: &[u8], max_size: usize) -> EgResult<Vec<u8>> {
+    let mut out = Vec::new();
+
+    // Read one byte past the limit so we can tell when it was exceeded.
+    ZlibDecoder::new(data)
+        .take((max_size as u64).saturating_add(1))
+        .read_to_end(&mut out)
+        .map_err(|e| format!("Invalid zlib stream: {e}"))?;
+
+    if out.len() > max_size {
+        return Err(format!("Compressed payload inflates beyond {max_size} bytes").into());
+    }
+
+    Ok(out)
+}
+
+#[test]
+fn test_zlib_round_trip() {
+    // Pseudo-random bytes compress poorly, but must still survive.
+    let mut seed: u32 = 12345;
+    let noise: Vec<u8> = (0..100_000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+
+    let json = r#"{"__c":"acp","__p":[null,"Barcode",1,"2024-01-01T00:00:00-0500"]}"#;
+    let repetitive = json.repeat(2000);
+
+    for data in [
+        &b""[..],
+        b"a",
+        b"aaaaaaaaaaaaaaaaaaaa",
+        &noise,
+        repetitive.as_bytes(),
+    ] {
+        let compressed = zlib_compress(data);
+        assert_eq!(zlib_decompress(&compressed, usize::MAX).unwrap(), data);
+    }
+
+    assert!(zlib_compress(repetitive.as_bytes()).len() < repetitive.len() / 20);
+
+    let mut corrupt = zlib_compress(repetitive.as_bytes());
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 0xff;
+    assert!(zlib_decompress(&corrupt, usize::MAX).is_err());
+    assert!(zlib_decompress(&[0x78, 0x01], usize::MAX).is_err());
+}
+
+#[test]
+fn test_zlib_decompress_foreign() {
+    // Produced by zlib.compress() at level 9: a dynamic Huffman block.
+    let dynamic = B64
+        .decode(concat!(
+            "eNqF1rtqAzEQRuF3Ue1i56KV1l2eI6RYXwqDsSHepAl+9wTcnuhXKTjVfEjzU47323a+bWX//lMup7Kf",
+            "duWwfh7vp3PZl7DpdcquPLZ1+3r8Xb59r5freriey3P3aoyaNm4cGstxE9C4jZukpo+bCk3UcTNDkz5u",
+            "GjXLuOnQ1HncLNDMIWZKEJqCQBKakGBEoQsKRhYWYcEIwyIwGGiwSWgw4GAmOFijSHgwAGEuQBiIsBAi",
+            "HERYChFuFKm3AURYFSIcRNgsRHhSJEQ4iWhChJOILkQ4iehChJOIRYhwEOGTEBEgwk2ICKNIiAgQ4a6+",
+            "CxDhIUREUiREBIjwFCICRHgVIqJRJEQEiPBZiAgS0YSIJBFdiEgS0YWIJBGLEJEgIia1QSRFQkSCiDAh",
+            "IkFEuBCRjSIhIkFEhBCRICJSiKggIqoQUY0iIaKCiJiFiEoimhBRSURTSyWJ6EJEJRGLEFFJxCJEVBCR",
+            "kxBRQUTafyI+nr8CwZ8S",
+        ))
+        .unwrap();
+
+    let text = String::from_utf8(zlib_decompress(&dynamic, usize::MAX).unwrap()).unwrap();
+    assert_eq!(text.len(), 3183);
+    assert!(text.starts_with("{\"content\":[{\"id\":0,"));
+
+    // Stored (uncompressed) block from zlib.compress() at level 0.
+    let stored = B64.decode("eAEBBQD6/2hlbGxvBiwCFQ==").unwrap();
+    assert_eq!(zlib_decompress(&stored, usize::MAX).unwrap(), b"hello");
+}
+
+#[test]
+fn test_compress_json() {
+    let value = json::object! {content: [1, "two", {three: null}], status: "OK"};
+    let text = compress_json(&value);
+    assert_eq!(decompress_json(&text).unwrap(), value);
+    assert!(decompress_json("not base64!").is_err());
+}
+
+#[test]
+fn test_zlib_decompress_limit() {
+    // 10MB of zeros compresses to about 10KB.
+    let bomb = zlib_compress(&vec![0u8; 10_000_000]);
+    assert!(bomb.len() < 20_000);
+
+    assert!(zlib_decompress(&bomb, 1_000_000).is_err());
+    assert_eq!(
+        zlib_decompress(&bomb, 10_000_000).unwrap().len(),
+        10_000_000
+    );
+}
@@ -1,9 +1,11 @@
+use crate::osrf::compress;
 use gethostname::gethostname;
 use roxmltree;
 use std::fmt;
 use std::fs;
 use std::str::FromStr;
 use std::sync::OnceLock;
+use std::time::Duration;
 use syslog;
 
 static GLOBAL_OSRF_CONFIG: OnceLock<Config> = OnceLock::new();
@@ -21,17 +23,155 @@ pub fn config() -> &'static Config {
 }
 
 const DEFAULT_BUS_PORT: u16 = 6379;
+const DEFAULT_RECONNECT_ATTEMPTS: u32 = 5;
+const DEFAULT_RECONNECT_DELAY: u64 = 250; // ms
+const DEFAULT_RECONNECT_MAX_DELAY: u64 = 5000; // ms
+
+const DEFAULT_LOG_KEEP: usize = 5;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum LogFile {
     Syslog,
+    Stdout,
+    Stderr,
     Filename(String),
 }
 
+impl From<&str> for LogFile {
+    fn from(s: &str) -> LogFile {
+        match s {
+            "syslog" => LogFile::Syslog,
+            "stdout" => LogFile::Stdout,
+            "stderr" => LogFile::Stderr,
+            _ => LogFile::Filename(s.to_string()),
+        }
+    }
+}
+
+/// Rotation settings for logging to a file.
+///
+/// Rotation assumes a single process writes to the file.  Otherwise,
+/// rotate with an external tool like logrotate, which signals each
+/// process with SIGHUP to reopen its log file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogRotation {
+    max_size: Option<u64>,
+    max_age: Option<u64>,
+    keep: Option<usize>,
+}
+
+impl LogRotation {
+    /// Rotate once the file reaches this many bytes.
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    /// Set the max file size from a number of bytes with an optional
+    /// K, M, or G suffix.
+    ///
+    /// ```
+    /// use evergreen::osrf::conf::LogRotation;
+    ///
+    /// let mut rotation = LogRotation::default();
+    /// rotation.set_max_size("10M").unwrap();
+    /// assert_eq!(rotation.max_size(), Some(10 * 1024 * 1024));
+    ///
+    /// assert!(rotation.set_max_size("10X").is_err());
+    /// assert!(rotation.set_max_size("18446744073709551615G").is_err());
+    /// ```
+    pub fn set_max_size(&mut self, size: &str) -> Result<(), String> {
+        let size = size.trim();
+
+        let (num, mult) = match size.to_uppercase().chars().last() {
+            Some('K') => (&size[..size.len() - 1], 1024),
+            Some('M') => (&size[..size.len() - 1], 1024 * 1024),
+            Some('G') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+            _ => (size, 1),
+        };
+
+        let num: u64 = num
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid log rotation size: {size} {e}"))?;
+
+        let bytes = num
+            .checked_mul(mult)
+            .ok_or_else(|| format!("Invalid log rotation size: {size} is too large"))?;
+
+        self.max_size = Some(bytes);
+
+        Ok(())
+    }
+
+    /// Rotate once the file is this many seconds old.
+    pub fn max_age(&self) -> Option<u64> {
+        self.max_age
+    }
+
+    /// Set the max file age from an interval string, e.g. "1 day".
+    pub fn set_max_age(&mut self, age: &str) -> Result<(), String> {
+        let secs = crate::date::interval_to_seconds(age)
+            .map_err(|e| format!("Invalid log rotation age: {age} {e}"))?;
+
+        if secs <= 0 {
+            return Err(format!("Invalid log rotation age: {age}"));
+        }
+
+        self.max_age = Some(secs as u64);
+
+        Ok(())
+    }
+
+    /// Number of rotated files to keep.
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_LOG_KEEP)
+    }
+
+    pub fn set_keep(&mut self, keep: &str) -> Result<(), String> {
+        let keep = keep
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid log rotation keep count: {keep} {e}"))?;
+
+        self.keep = Some(keep);
+
+        Ok(())
+    }
+
+    /// True if any rotation trigger is set.
+    pub fn enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Log record output format.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    /// Traditional OpenSRF syslog-style text.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format: {format}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LogOptions {
     log_level: Option<log::LevelFilter>,
     log_file: Option<LogFile>,
+    log_format: Option<LogFormat>,
+    log_rotation: LogRotation,
     syslog_facility: Option<syslog::Facility>,
     activity_log_facility: Option<syslog::Facility>,
 }
@@ -55,9 +195,26 @@ impl LogOptions {
     pub fn log_file(&self) -> &Option<LogFile> {
         &self.log_file
     }
+    /// Set the log file from "syslog", "stdout", "stderr", or a path.
+    pub fn set_log_file(&mut self, file: &str) {
+        self.log_file = Some(LogFile::from(file));
+    }
+    pub fn log_rotation(&self) -> &LogRotation {
+        &self.log_rotation
+    }
+    pub fn log_rotation_mut(&mut self) -> &mut LogRotation {
+        &mut self.log_rotation
+    }
     pub fn log_level(&self) -> &Option<log::LevelFilter> {
         &self.log_level
     }
+    pub fn log_format(&self) -> Option<LogFormat> {
+        self.log_format
+    }
+    pub fn set_log_format(&mut self, format: &str) -> Result<(), String> {
+        self.log_format = Some(LogFormat::from_str(format)?);
+        Ok(())
+    }
     pub fn set_log_level(&mut self, level: &str) {
         self.log_level = Some(LogOptions::log_level_from_str(level));
     }
@@ -110,6 +267,9 @@ pub struct BusClient {
     logging: LogOptions,
     settings_config: Option<String>,
     routers: Vec<ClientRouter>,
+    reconnect: BusReconnect,
+    /// Compressed payloads may inflate to at most this many bytes.
+    max_inflated_size: usize,
 }
 
 impl BusClient {
@@ -138,6 +298,19 @@ impl BusClient {
     pub fn routers(&self) -> &Vec<ClientRouter> {
         &self.routers
     }
+    pub fn reconnect(&self) -> &BusReconnect {
+        &self.reconnect
+    }
+    pub fn reconnect_mut(&mut self) -> &mut BusReconnect {
+        &mut self.reconnect
+    }
+    /// Read from the optional <max_inflated_size> element, in bytes.
+    pub fn max_inflated_size(&self) -> usize {
+        self.max_inflated_size
+    }
+    pub fn set_max_inflated_size(&mut self, size: usize) {
+        self.max_inflated_size = size;
+    }
     pub fn set_domain(&mut self, domain: &str) {
         // Assumes other aspects of the domain are identical
         self.domain.name = domain.to_string();
@@ -150,6 +323,107 @@ impl BusClient {
     }
 }
 
+/// How a bus connection recovers when the bus goes away.
+///
+/// Read from the optional <reconnect> element of a client config.
+/// Without the element, a lost bus connection is not retried at the
+/// bus level, leaving recovery to the caller, e.g. session reconnects.
+/// With it, attempts default to DEFAULT_RECONNECT_ATTEMPTS.
+#[derive(Debug, Clone)]
+pub struct BusReconnect {
+    /// Reconnect attempts before giving up.  0 disables reconnecting.
+    max_attempts: u32,
+
+    /// Delay before the second attempt, doubling for each attempt
+    /// after that.  The first attempt is immediate.
+    initial_delay: Duration,
+
+    /// Upper bound on the delay between attempts.
+    max_delay: Duration,
+
+    /// Messages sent while the bus is unreachable are held for
+    /// delivery once we reconnect, up to this many.  0 means such
+    /// sends fail immediately.
+    max_buffered: usize,
+}
+
+impl Default for BusReconnect {
+    fn default() -> Self {
+        BusReconnect {
+            max_attempts: 0,
+            initial_delay: Duration::from_millis(DEFAULT_RECONNECT_DELAY),
+            max_delay: Duration::from_millis(DEFAULT_RECONNECT_MAX_DELAY),
+            max_buffered: 0,
+        }
+    }
+}
+
+impl BusReconnect {
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+    pub fn set_max_attempts(&mut self, attempts: u32) {
+        self.max_attempts = attempts;
+    }
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+    pub fn set_initial_delay(&mut self, delay: Duration) {
+        self.initial_delay = delay;
+    }
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+    pub fn set_max_delay(&mut self, delay: Duration) {
+        self.max_delay = delay;
+    }
+    pub fn max_buffered(&self) -> usize {
+        self.max_buffered
+    }
+    pub fn set_max_buffered(&mut self, max: usize) {
+        self.max_buffered = max;
+    }
+
+    /// How long to wait before the reconnect attempt with the
+    /// provided (0-based) index.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO;
+        }
+
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay)
+    }
+}
+
+/// Request limits applied by the websocket translator.
+///
+/// Read from the optional <websockets> element of the <gateway> config.
+#[derive(Debug, Clone, Default)]
+pub struct WebsocketLimits {
+    /// Max in-flight requests per websocket client.
+    max_client_requests: Option<usize>,
+
+    /// Max in-flight requests across all websocket clients.
+    max_total_requests: Option<usize>,
+}
+
+impl WebsocketLimits {
+    pub fn max_client_requests(&self) -> Option<usize> {
+        self.max_client_requests
+    }
+    pub fn set_max_client_requests(&mut self, max: usize) {
+        self.max_client_requests = Some(max);
+    }
+    pub fn max_total_requests(&self) -> Option<usize> {
+        self.max_total_requests
+    }
+    pub fn set_max_total_requests(&mut self, max: usize) {
+        self.max_total_requests = Some(max);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ClientRouter {
     domain: String,
@@ -196,6 +470,7 @@ pub struct ConfigBuilder {
     client: Option<BusClient>,
     routers: Vec<Router>,
     gateway: Option<BusClient>,
+    websocket_limits: WebsocketLimits,
     log_protect: Vec<String>,
 }
 
@@ -210,6 +485,7 @@ impl ConfigBuilder {
             client: self.client.unwrap(),
             routers: self.routers,
             gateway: self.gateway,
+            websocket_limits: self.websocket_limits,
             log_protect: self.log_protect,
         })
     }
@@ -245,6 +521,7 @@ impl ConfigBuilder {
         let mut builder = ConfigBuilder {
             client: None,
             gateway: None,
+            websocket_limits: WebsocketLimits::default(),
             routers: Vec::new(),
             log_protect: Vec::new(),
         };
@@ -266,9 +543,32 @@ impl ConfigBuilder {
 
     fn unpack_gateway(&mut self, node: &roxmltree::Node) -> Result<(), String> {
         self.gateway = Some(self.unpack_client_node(node)?);
+
+        if let Some(wsnode) = node.children().find(|c| c.has_tag_name("websockets")) {
+            self.websocket_limits = self.unpack_websocket_limits(&wsnode)?;
+        }
+
         Ok(())
     }
 
+    fn unpack_websocket_limits(&self, node: &roxmltree::Node) -> Result<WebsocketLimits, String> {
+        let mut limits = WebsocketLimits::default();
+
+        for (name, value) in [
+            ("max_client_requests", &mut limits.max_client_requests),
+            ("max_total_requests", &mut limits.max_total_requests),
+        ] {
+            if let Some(v) = self.child_node_text(node, name) {
+                let num = v
+                    .parse::<usize>()
+                    .map_err(|e| format!("Invalid websockets {name} value '{v}': {e}"))?;
+                *value = Some(num);
+            }
+        }
+
+        Ok(limits)
+    }
+
     fn unpack_shared(&mut self, node: &roxmltree::Node) -> Result<(), String> {
         if let Some(lp) = node
             .children()
@@ -404,6 +704,8 @@ impl ConfigBuilder {
         let mut password = "";
         let mut router_name = "router";
         let mut settings_config: Option<String> = None;
+        let mut reconnect = BusReconnect::default();
+        let mut max_inflated_size = compress::DEFAULT_MAX_INFLATED_SIZE;
 
         for child in node.children() {
             match child.tag_name().name() {
@@ -427,6 +729,14 @@ impl ConfigBuilder {
                         settings_config = Some(t.to_string());
                     }
                 }
+                "reconnect" => reconnect = self.unpack_reconnect(&child)?,
+                "max_inflated_size" => {
+                    if let Some(t) = child.text() {
+                        max_inflated_size = t
+                            .parse::<usize>()
+                            .map_err(|e| format!("Invalid max_inflated_size '{t}': {e}"))?;
+                    }
+                }
                 _ => {}
             }
         }
@@ -435,6 +745,8 @@ impl ConfigBuilder {
             domain,
             logging,
             settings_config,
+            reconnect,
+            max_inflated_size,
             routers: Vec::new(),
             username: username.to_string(),
             password: password.to_string(),
@@ -442,6 +754,39 @@ impl ConfigBuilder {
         })
     }
 
+    fn unpack_reconnect(&self, node: &roxmltree::Node) -> Result<BusReconnect, String> {
+        // Reconnecting is opt-in via the <reconnect> element.
+        let mut reconnect = BusReconnect {
+            max_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            ..Default::default()
+        };
+
+        let number = |name: &str| -> Result<Option<u64>, String> {
+            match self.child_node_text(node, name) {
+                Some(v) => v
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|e| format!("Invalid reconnect {name} value '{v}': {e}")),
+                None => Ok(None),
+            }
+        };
+
+        if let Some(n) = number("max_attempts")? {
+            reconnect.max_attempts = n as u32;
+        }
+        if let Some(n) = number("initial_delay_ms")? {
+            reconnect.initial_delay = Duration::from_millis(n);
+        }
+        if let Some(n) = number("max_delay_ms")? {
+            reconnect.max_delay = Duration::from_millis(n);
+        }
+        if let Some(n) = number("max_buffered")? {
+            reconnect.max_buffered = n as usize;
+        }
+
+        Ok(reconnect)
+    }
+
     fn unpack_domain_node(&mut self, node: &roxmltree::Node) -> Result<BusDomain, String> {
         let domain_name = match node.children().filter(|c| c.has_tag_name("domain")).next() {
             Some(n) => match n.text() {
@@ -476,6 +821,8 @@ impl ConfigBuilder {
         let mut ops = LogOptions {
             log_level: None,
             log_file: None,
+            log_format: None,
+            log_rotation: LogRotation::default(),
             syslog_facility: None,
             activity_log_facility: None,
         };
@@ -484,11 +831,7 @@ impl ConfigBuilder {
             match child.tag_name().name() {
                 "logfile" => {
                     if let Some(filename) = child.text() {
-                        if filename.eq("syslog") {
-                            ops.log_file = Some(LogFile::Syslog);
-                        } else {
-                            ops.log_file = Some(LogFile::Filename(filename.to_string()))
-                        }
+                        ops.log_file = Some(LogFile::from(filename));
                     }
                 }
                 "syslog" => {
@@ -510,6 +853,26 @@ impl ConfigBuilder {
                         ops.log_level = Some(LogOptions::log_level_from_str(level_num));
                     }
                 }
+                "logformat" => {
+                    if let Some(format) = child.text() {
+                        ops.log_format = Some(LogFormat::from_str(format)?);
+                    }
+                }
+                "logrotatesize" => {
+                    if let Some(size) = child.text() {
+                        ops.log_rotation.set_max_size(size)?;
+                    }
+                }
+                "logrotateage" => {
+                    if let Some(age) = child.text() {
+                        ops.log_rotation.set_max_age(age)?;
+                    }
+                }
+                "logrotatekeep" => {
+                    if let Some(keep) = child.text() {
+                        ops.log_rotation.set_keep(keep)?;
+                    }
+                }
                 _ => {}
             }
         }
@@ -524,6 +887,7 @@ pub struct Config {
     client: BusClient,
     routers: Vec<Router>,
     gateway: Option<BusClient>,
+    websocket_limits: WebsocketLimits,
     log_protect: Vec<String>,
 }
 
@@ -532,9 +896,12 @@ impl Config {
     ///
     /// Returns Err if the Config has already been stored.
     pub fn store(self) -> Result<(), String> {
+        let max_inflated_size = self.client.max_inflated_size();
+
         if GLOBAL_OSRF_CONFIG.set(self).is_err() {
             Err(format!("Cannot initialize OpenSRF Config more than once").into())
         } else {
+            compress::set_max_inflated_size(max_inflated_size);
             Ok(())
         }
     }
@@ -557,6 +924,10 @@ impl Config {
         self.gateway.as_mut()
     }
 
+    pub fn websocket_limits(&self) -> &WebsocketLimits {
+        &self.websocket_limits
+    }
+
     pub fn client(&self) -> &BusClient {
         &self.client
     }
@@ -0,0 +1,269 @@
+//! Bus-level events published by the router.
+//!
+//! Routers announce service registrations and unregistrations on a
+//! well-known Redis pub/sub channel.  Routers which do not publish
+//! announcements (e.g. older or non-Rust routers) simply leave the
+//! channel quiet.
+use crate::date;
+use crate::osrf::bus::Bus;
+use crate::osrf::conf;
+use crate::result::EgError;
+use crate::EgResult;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Pub/sub channel where routers announce service events.
+pub const SERVICE_EVENTS_CHANNEL: &str = "opensrf:events:service";
+
+/// How often the subscriber thread wakes to see if it's still wanted.
+const SUBSCRIBER_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceEventKind {
+    /// A service instance registered with the router.
+    Registered,
+    /// The last instance of a service on a node unregistered.
+    Unregistered,
+}
+
+impl fmt::Display for ServiceEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Registered => "register",
+            Self::Unregistered => "unregister",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl TryFrom<&str> for ServiceEventKind {
+    type Error = EgError;
+    fn try_from(s: &str) -> EgResult<Self> {
+        match s {
+            "register" => Ok(Self::Registered),
+            "unregister" => Ok(Self::Unregistered),
+            _ => Err(format!("Invalid service event kind: {s}").into()),
+        }
+    }
+}
+
+/// A service registration change on a router.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceEvent {
+    service: String,
+    /// Domain where the service instance runs.
+    node: String,
+    kind: ServiceEventKind,
+    timestamp: date::EgDate,
+}
+
+impl fmt::Display for ServiceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ServiceEvent {} {} on {}",
+            self.kind, self.service, self.node
+        )
+    }
+}
+
+impl ServiceEvent {
+    pub fn new(service: &str, node: &str, kind: ServiceEventKind) -> Self {
+        ServiceEvent {
+            service: service.to_string(),
+            node: node.to_string(),
+            kind,
+            timestamp: date::now(),
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+    pub fn kind(&self) -> ServiceEventKind {
+        self.kind
+    }
+    pub fn timestamp(&self) -> &date::EgDate {
+        &self.timestamp
+    }
+
+    pub fn to_json_value(&self) -> json::JsonValue {
+        json::object! {
+            "service": self.service(),
+            "node": self.node(),
+            "kind": self.kind().to_string(),
+            "timestamp": date::to_iso(self.timestamp()),
+        }
+    }
+
+    /// ```
+    /// use evergreen::osrf::events::*;
+    ///
+    /// let event = ServiceEvent::new("open-ils.actor", "private.localhost", ServiceEventKind::Registered);
+    /// let event2 = ServiceEvent::from_json_value(&event.to_json_value()).unwrap();
+    ///
+    /// assert_eq!(event2.service(), "open-ils.actor");
+    /// assert_eq!(event2.kind(), ServiceEventKind::Registered);
+    /// assert!(ServiceEvent::from_json_value(&json::object! {"service": "x"}).is_err());
+    /// ```
+    pub fn from_json_value(value: &json::JsonValue) -> EgResult<Self> {
+        let get = |key: &str| {
+            value[key]
+                .as_str()
+                .ok_or_else(|| EgError::from(format!("Service event has no {key}: {value}")))
+        };
+
+        Ok(ServiceEvent {
+            service: get("service")?.to_string(),
+            node: get("node")?.to_string(),
+            kind: ServiceEventKind::try_from(get("kind")?)?,
+            timestamp: date::parse_datetime(get("timestamp")?)?,
+        })
+    }
+}
+
+/// Announce a service event to all subscribers.
+pub fn publish(bus: &mut Bus, event: &ServiceEvent) -> EgResult<()> {
+    bus.publish(SERVICE_EVENTS_CHANNEL, &event.to_json_value().dump())
+}
+
+/// Service events delivered from a subscription.
+///
+/// Events arrive via a dedicated Redis connection managed by a
+/// background thread, which exits once this value is dropped.
+pub struct ServiceEvents {
+    receiver: mpsc::Receiver<ServiceEvent>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ServiceEvents {
+    /// Subscribe to service events on the bus of our primary domain.
+    pub fn subscribe(config: &conf::BusClient) -> EgResult<Self> {
+        // A connection in pub/sub mode cannot be used for anything
+        // else, so the subscription gets its own.
+        let bus = Bus::new(config)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_thread = closed.clone();
+
+        thread::spawn(move || {
+            if let Err(e) = ServiceEvents::listen(bus, sender, closed_thread) {
+                log::error!("Service event subscription exited: {e}");
+            }
+        });
+
+        Ok(ServiceEvents { receiver, closed })
+    }
+
+    fn listen(
+        mut bus: Bus,
+        sender: mpsc::Sender<ServiceEvent>,
+        closed: Arc<AtomicBool>,
+    ) -> EgResult<()> {
+        let mut pubsub = bus.connection().as_pubsub();
+
+        pubsub
+            .set_read_timeout(Some(SUBSCRIBER_POLL_INTERVAL))
+            .map_err(|e| EgError::Transport(format!("Cannot configure subscription: {e}")))?;
+
+        pubsub
+            .subscribe(SERVICE_EVENTS_CHANNEL)
+            .map_err(|e| EgError::Transport(format!("Cannot subscribe to service events: {e}")))?;
+
+        while !closed.load(Ordering::Relaxed) {
+            let msg = match pubsub.get_message() {
+                Ok(m) => m,
+                Err(e) if e.is_timeout() => continue,
+                Err(e) => {
+                    return Err(EgError::Transport(format!(
+                        "Error reading service events: {e}"
+                    )))
+                }
+            };
+
+            let payload: String = match msg.get_payload() {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("Ignoring unreadable service event: {e}");
+                    continue;
+                }
+            };
+
+            let event = match json::parse(&payload)
+                .map_err(|e| EgError::from(format!("{e}")))
+                .and_then(|v| ServiceEvent::from_json_value(&v))
+            {
+                Ok(e) => e,
+                Err(e) => {
+                    log::warn!("Ignoring invalid service event: {e}");
+                    continue;
+                }
+            };
+
+            log::debug!("Received {event}");
+
+            if sender.send(event).is_err() {
+                // Receiver is gone.
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait up to the timeout for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ServiceEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Next event if one is pending.
+    pub fn try_recv(&self) -> Option<ServiceEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Blocks until the next event arrives.  Ends if the subscription
+/// fails.
+impl Iterator for ServiceEvents {
+    type Item = ServiceEvent;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for ServiceEvents {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_service_event_json() {
+    let event = ServiceEvent::new(
+        "open-ils.actor",
+        "private.localhost",
+        ServiceEventKind::Unregistered,
+    );
+
+    let value = event.to_json_value();
+    assert_eq!(value["kind"].as_str(), Some("unregister"));
+
+    let event2 = ServiceEvent::from_json_value(&value).unwrap();
+    assert_eq!(event2.node(), "private.localhost");
+    assert_eq!(event2.kind(), ServiceEventKind::Unregistered);
+    assert_eq!(
+        date::to_iso(event2.timestamp()),
+        date::to_iso(event.timestamp())
+    );
+
+    let mut value = value;
+    value["kind"] = "restart".into();
+    assert!(ServiceEvent::from_json_value(&value).is_err());
+}
@@ -1,13 +1,20 @@
 use crate::date;
 ///! OpenSRF Syslog
 use crate::osrf::conf;
+use crate::osrf::conf::LogFormat;
 use crate::util;
+use json;
 use log;
+use signal_hook;
 use std::cell::RefCell;
 use std::fs;
+use std::io;
 use std::io::Write;
 use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use syslog;
 
@@ -29,6 +36,16 @@ pub struct Logger {
     activity_facility: syslog::Facility,
     writer: Option<UnixDatagram>,
     application: String,
+    format: LogFormat,
+    rotation: conf::LogRotation,
+    /// Open log file, when logging to a file.
+    ///
+    /// Held while writing any record to a file, stdout, or stderr
+    /// so records from different threads are never interleaved.
+    output: Mutex<Option<LogFileWriter>>,
+    /// Set by SIGHUP to reopen the log file, e.g. after logrotate
+    /// moves it.
+    reopen: Arc<AtomicBool>,
 }
 
 impl Logger {
@@ -59,6 +76,10 @@ impl Logger {
             activity_facility: act_facility.clone(),
             writer: None,
             application: Logger::find_app_name(),
+            format: options.log_format().unwrap_or_default(),
+            rotation: options.log_rotation().clone(),
+            output: Mutex::new(None),
+            reopen: Arc::new(AtomicBool::new(false)),
         })
     }
 
@@ -87,32 +108,20 @@ impl Logger {
         self.facility = facility;
     }
 
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format;
+    }
+
     /// Setup our global log handler.
     ///
     /// Attempts to connect to syslog unix socket if possible.
+    ///
+    /// Returns an error if the log destination cannot be opened.
     pub fn init(mut self) -> Result<(), String> {
-        match self.logfile {
-            conf::LogFile::Syslog => {
-                self.writer = match Logger::writer() {
-                    Ok(w) => Some(w),
-                    Err(e) => {
-                        eprintln!("Cannot init Logger: {e}");
-                        return Err(format!("Cannot init Logger: {e}"));
-                    }
-                }
-            }
-            conf::LogFile::Filename(ref name) => {
-                if let Err(e) = fs::File::options()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open(name)
-                {
-                    let err = format!("Cannot open file for writing: {name} {e}");
-                    eprintln!("{err}");
-                    return Err(err);
-                }
-            }
+        if let Err(e) = self.open_output() {
+            let err = format!("Cannot init Logger: {e}");
+            eprintln!("{err}");
+            return Err(err);
         }
 
         log::set_max_level(self.loglevel);
@@ -125,6 +134,29 @@ impl Logger {
         Ok(())
     }
 
+    /// Connect to syslog or open the log file.
+    ///
+    /// Log files are reopened on SIGHUP.
+    fn open_output(&mut self) -> Result<(), String> {
+        match self.logfile {
+            conf::LogFile::Syslog => {
+                self.writer = Some(Logger::writer()?);
+            }
+            conf::LogFile::Filename(ref name) => {
+                let file = LogFileWriter::open(Path::new(name), self.rotation.clone())
+                    .map_err(|e| format!("Cannot open file for writing: {name} {e}"))?;
+
+                *self.output.get_mut().unwrap_or_else(|e| e.into_inner()) = Some(file);
+
+                signal_hook::flag::register(signal_hook::consts::SIGHUP, self.reopen.clone())
+                    .map_err(|e| format!("Cannot register SIGHUP handler: {e}"))?;
+            }
+            conf::LogFile::Stdout | conf::LogFile::Stderr => {}
+        }
+
+        Ok(())
+    }
+
     /// Encode the facility and severity as the syslog priority.
     ///
     /// Essentially copied from the syslog crate.
@@ -174,6 +206,135 @@ impl Logger {
     }
 }
 
+/// Log file with optional size and age based rotation.
+///
+/// On rotation, the file is renamed with a ".1" suffix, shifting
+/// older files to ".2", ".3", etc., deleting those beyond the number
+/// of files to keep.
+struct LogFileWriter {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    opened: SystemTime,
+    rotation: conf::LogRotation,
+}
+
+impl LogFileWriter {
+    fn open(path: &Path, rotation: conf::LogRotation) -> io::Result<LogFileWriter> {
+        let file = fs::File::options().create(true).append(true).open(path)?;
+
+        let meta = file.metadata()?;
+
+        Ok(LogFileWriter {
+            path: path.to_path_buf(),
+            size: meta.len(),
+            // Creation time is not available on all file systems.
+            opened: meta.created().unwrap_or_else(|_| SystemTime::now()),
+            file,
+            rotation,
+        })
+    }
+
+    /// Reopen our file path, which may have been moved.
+    fn reopen(&mut self) -> io::Result<()> {
+        *self = LogFileWriter::open(&self.path, self.rotation.clone())?;
+        Ok(())
+    }
+
+    /// Write a record as a single line, rotating first if needed.
+    fn write_record(&mut self, record: &str) -> io::Result<()> {
+        let line = format!("{record}\n");
+
+        if self.needs_rotation(line.len() as u64, SystemTime::now()) {
+            self.rotate()?;
+        }
+
+        // One write per record.
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// True if writing this many more bytes at the provided time
+    /// should rotate the file first.  Empty files are never rotated.
+    fn needs_rotation(&self, len: u64, now: SystemTime) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+
+        if let Some(max) = self.rotation.max_size() {
+            if self.size + len > max {
+                return true;
+            }
+        }
+
+        if let Some(max) = self.rotation.max_age() {
+            if let Ok(age) = now.duration_since(self.opened) {
+                if age.as_secs() >= max {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn rotated_path(&self, num: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{num}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let keep = self.rotation.keep();
+
+        if keep == 0 {
+            fs::remove_file(&self.path).ok();
+        } else {
+            fs::remove_file(self.rotated_path(keep)).ok();
+
+            for num in (1..keep).rev() {
+                let from = self.rotated_path(num);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(num + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.reopen()?;
+
+        // We just created the file.
+        self.opened = SystemTime::now();
+
+        Ok(())
+    }
+}
+
+impl Logger {
+    /// Log record as a single-line JSON object.
+    ///
+    /// Newlines and other control characters in the message are
+    /// escaped, so each record occupies exactly one line.
+    fn json_record(&self, level: &str, target: &str, record: &log::Record, msg: &str) -> String {
+        json::object! {
+            "timestamp": date::to_iso_precise(&date::now()),
+            "level": level,
+            "target": target,
+            "module": record.module_path(),
+            "line": record.line(),
+            "message": msg,
+            "application": self.application.as_str(),
+            "pid": process::id(),
+            "thread": util::thread_id(),
+            "trace": Logger::get_log_trace(),
+        }
+        .dump()
+    }
+}
+
 impl log::Log for Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
         &metadata.level().to_level_filter() <= &self.loglevel
@@ -220,48 +381,269 @@ impl log::Log for Logger {
             })
         };
 
-        let mut message = format!(
-            "{}{} [{}:{}:{}:{}",
-            match self.writer.is_some() {
-                true => format!("<{}>", severity),
-                _ => format!("{} ", date::epoch_secs()),
-            },
-            &self.application,
-            levelname,
-            process::id(),
-            target,
-            match record.line() {
-                Some(l) => l,
-                _ => 0,
-            }
-        );
+        let line = record.line().unwrap_or(0);
 
-        // Add the thread-local log trace
-        THREAD_LOCAL_LOG_TRACE.with(|tr| message += &format!(":{}] ", *tr.borrow()));
+        let mut message = match self.writer.is_some() {
+            true => format!("<{}>", severity),
+            _ => match self.format {
+                LogFormat::Text => format!("{} ", date::epoch_secs()),
+                // JSON records carry their own timestamp.
+                LogFormat::Json => String::new(),
+            },
+        };
 
-        message += &logmsg;
+        match self.format {
+            LogFormat::Text => {
+                message += &format!(
+                    "{} [{}:{}:{}:{}",
+                    &self.application,
+                    levelname,
+                    process::id(),
+                    target,
+                    line
+                );
+
+                // Add the thread-local log trace
+                THREAD_LOCAL_LOG_TRACE.with(|tr| message += &format!(":{}] ", *tr.borrow()));
+
+                message += &logmsg;
+            }
+            LogFormat::Json => {
+                message += &self.json_record(&levelname, target, record, &logmsg);
+            }
+        }
 
         if let Some(ref w) = self.writer {
             if w.send(message.as_bytes()).is_ok() {
                 return;
             }
-        } else if let conf::LogFile::Filename(ref name) = self.logfile {
-            if let Ok(mut file) = fs::File::options()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(name)
-            {
-                message += "\n";
-                if file.write_all(message.as_bytes()).is_ok() {
-                    return;
+        }
+
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(file) = output.as_mut() {
+            if self.reopen.swap(false, Ordering::Relaxed) {
+                if let Err(e) = file.reopen() {
+                    eprintln!("Cannot reopen log file {}: {e}", file.path.display());
                 }
             }
+
+            if file.write_record(&message).is_ok() {
+                return;
+            }
         }
 
-        // If all else fails, print the log message.
-        println!("{message}");
+        if self.logfile == conf::LogFile::Stderr {
+            eprintln!("{message}");
+        } else {
+            // Stdout, or if all else fails.
+            println!("{message}");
+        }
     }
 
     fn flush(&self) {}
 }
+
+#[cfg(test)]
+fn test_logger(logfile: conf::LogFile) -> Logger {
+    Logger {
+        logfile,
+        loglevel: log::LevelFilter::Info,
+        facility: syslog::Facility::LOG_LOCAL0,
+        activity_facility: syslog::Facility::LOG_LOCAL1,
+        writer: None,
+        application: "test-app".to_string(),
+        format: LogFormat::Json,
+        rotation: conf::LogRotation::default(),
+        output: Mutex::new(None),
+        reopen: Arc::new(AtomicBool::new(false)),
+    }
+}
+
+#[cfg(test)]
+fn test_log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("eg-log-{name}-{}", process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_json_record() {
+    let logger = test_logger(conf::LogFile::Syslog);
+
+    let args = format_args!("first line\nsecond \"line\"");
+    let record = log::Record::builder()
+        .args(args)
+        .level(log::Level::Warn)
+        .target("eg::test")
+        .module_path(Some("evergreen::osrf::logging"))
+        .line(Some(42))
+        .build();
+
+    let line = logger.json_record("WARN", "eg::test", &record, "first line\nsecond \"line\"");
+
+    assert!(!line.contains('\n'));
+
+    let value = json::parse(&line).unwrap();
+
+    assert_eq!(value["level"], "WARN");
+    assert_eq!(value["target"], "eg::test");
+    assert_eq!(value["module"], "evergreen::osrf::logging");
+    assert_eq!(value["line"], 42);
+    assert_eq!(value["message"], "first line\nsecond \"line\"");
+    assert_eq!(value["application"], "test-app");
+    assert_eq!(value["pid"], process::id());
+    assert_eq!(
+        value["trace"].as_str(),
+        Some(Logger::get_log_trace().as_str())
+    );
+    assert!(date::parse_datetime(value["timestamp"].as_str().unwrap()).is_ok());
+}
+
+#[test]
+fn test_json_lines_from_threads() {
+    use log::Log;
+
+    let dir = test_log_dir("threads");
+    let path = dir.join("test.log");
+
+    let mut logger = test_logger(conf::LogFile::Filename(path.to_string_lossy().to_string()));
+
+    logger.open_output().unwrap();
+
+    let logger = Arc::new(logger);
+
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let logger = logger.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    let msg = format!("thread {t} record {i}\n{}", "x".repeat(2000));
+                    logger.log(
+                        &log::Record::builder()
+                            .args(format_args!("{msg}"))
+                            .level(log::Level::Info)
+                            .target("eg::test")
+                            .build(),
+                    );
+                }
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 400);
+
+    for line in lines {
+        let value = json::parse(line).unwrap();
+        assert_eq!(value["level"], "INFO");
+        assert!(value["message"].as_str().unwrap().starts_with("thread "));
+    }
+}
+
+#[test]
+fn test_log_rotation() {
+    let dir = test_log_dir("rotate");
+    let path = dir.join("test.log");
+
+    let mut rotation = conf::LogRotation::default();
+    rotation.set_max_size("100").unwrap();
+    rotation.set_keep("2").unwrap();
+
+    let mut writer = LogFileWriter::open(&path, rotation).unwrap();
+
+    // 30 bytes per line, so 3 lines per file.
+    for i in 0..10 {
+        writer
+            .write_record(&format!("record {i:0>2} {}", "x".repeat(19)))
+            .unwrap();
+    }
+
+    let current = fs::read_to_string(&path).unwrap();
+    let rotated1 = fs::read_to_string(dir.join("test.log.1")).unwrap();
+    let rotated2 = fs::read_to_string(dir.join("test.log.2")).unwrap();
+
+    assert!(current.starts_with("record 09"));
+    assert!(rotated1.starts_with("record 06"));
+    assert!(rotated2.starts_with("record 03"));
+    assert_eq!(rotated1.lines().count(), 3);
+
+    // Only 2 rotated files are kept.
+    assert!(!dir.join("test.log.3").exists());
+
+    let mut rotation = conf::LogRotation::default();
+    rotation.set_max_age("1 minute").unwrap();
+
+    let mut writer = LogFileWriter::open(&dir.join("age.log"), rotation).unwrap();
+
+    let now = SystemTime::now();
+    let later = now + std::time::Duration::from_secs(61);
+
+    // Empty files are not rotated.
+    assert!(!writer.needs_rotation(10, later));
+
+    writer.write_record("hello").unwrap();
+
+    assert!(!writer.needs_rotation(10, now));
+    assert!(writer.needs_rotation(10, later));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_reopen_on_sighup() {
+    use log::Log;
+
+    let dir = test_log_dir("sighup");
+    let path = dir.join("test.log");
+
+    let mut logger = test_logger(conf::LogFile::Filename(path.to_string_lossy().to_string()));
+
+    logger.open_output().unwrap();
+
+    let log = |msg: &str| {
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("{msg}"))
+                .level(log::Level::Info)
+                .target("eg::test")
+                .build(),
+        )
+    };
+
+    log("before rotate");
+
+    // As logrotate would.
+    fs::rename(&path, dir.join("test.log.moved")).unwrap();
+    signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+
+    log("after rotate");
+
+    let moved = fs::read_to_string(dir.join("test.log.moved")).unwrap();
+    let current = fs::read_to_string(&path).unwrap();
+
+    assert!(moved.contains("before rotate"));
+    assert!(!moved.contains("after rotate"));
+    assert!(current.contains("after rotate"));
+    assert_eq!(current.lines().count(), 1);
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_log_open_failure() {
+    let mut logger = test_logger(conf::LogFile::Filename(
+        "/nonexistent-eg-log-dir/test.log".to_string(),
+    ));
+
+    let err = logger.open_output().unwrap_err();
+    assert!(err.contains("/nonexistent-eg-log-dir/test.log"));
+}
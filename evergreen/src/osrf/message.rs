@@ -1,3 +1,4 @@
+use crate::osrf::compress;
 use crate::util;
 use crate::{EgResult, EgValue};
 use json::JsonValue;
@@ -253,6 +254,11 @@ pub struct TransportMessage {
     router_class: Option<String>,
     router_reply: Option<String>,
     body: Vec<Message>,
+
+    /// True if the sender keeps a presence key on the bus while it
+    /// waits for responses.  Services may stop responding to senders
+    /// whose key has gone away.
+    presence: bool,
 }
 
 impl TransportMessage {
@@ -266,6 +272,7 @@ impl TransportMessage {
             router_class: None,
             router_reply: None,
             body: Vec::new(),
+            presence: false,
         }
     }
 
@@ -345,6 +352,14 @@ impl TransportMessage {
         self.router_reply = Some(reply.to_string());
     }
 
+    pub fn presence(&self) -> bool {
+        self.presence
+    }
+
+    pub fn set_presence(&mut self, presence: bool) {
+        self.presence = presence;
+    }
+
     /// Create a TransportMessage from a JSON object, consuming the JSON value.
     ///
     /// Returns None if the JSON value cannot be coerced into a TransportMessage.
@@ -373,6 +388,8 @@ impl TransportMessage {
             tmsg.set_router_reply(rc);
         }
 
+        tmsg.set_presence(json_obj["presence"].as_bool().unwrap_or(false));
+
         let body = json_obj["body"].take();
 
         if let JsonValue::Array(arr) = body {
@@ -416,6 +433,10 @@ impl TransportMessage {
             obj["router_reply"] = rc.into();
         }
 
+        if self.presence {
+            obj["presence"] = true.into();
+        }
+
         obj
     }
 }
@@ -425,9 +446,19 @@ pub struct Message {
     mtype: MessageType,
     thread_trace: usize,
     timezone: Option<String>,
+    /// Overrides the thread locale for this message.
+    locale: Option<String>,
     api_level: u8,
     ingress: Option<String>,
     payload: Payload,
+
+    /// Set on requests from callers able to read compressed results.
+    accept_compression: bool,
+
+    /// Result content whose JSON is at least this many bytes is
+    /// compressed when the message is serialized.  Never set this
+    /// unless the recipient advertised support for compression.
+    compress_threshold: Option<usize>,
 }
 
 impl Message {
@@ -438,7 +469,10 @@ impl Message {
             payload,
             api_level: DEFAULT_API_LEVEL,
             timezone: None,
+            locale: None,
             ingress: None,
+            accept_compression: false,
+            compress_threshold: None,
         }
     }
 
@@ -473,6 +507,15 @@ impl Message {
         self.timezone = Some(timezone.to_string())
     }
 
+    /// Locale sent with this message.  Defaults to the thread locale.
+    pub fn locale(&self) -> String {
+        self.locale.clone().unwrap_or_else(thread_locale)
+    }
+
+    pub fn set_locale(&mut self, locale: &str) {
+        self.locale = Some(locale.to_string())
+    }
+
     pub fn ingress(&self) -> &str {
         self.ingress.as_deref().unwrap_or(DEFAULT_INGRESS)
     }
@@ -481,6 +524,23 @@ impl Message {
         self.ingress = Some(ingress.to_string())
     }
 
+    /// True if the sender of this message can read compressed results.
+    pub fn accepts_compression(&self) -> bool {
+        self.accept_compression
+    }
+
+    pub fn set_accept_compression(&mut self, accept: bool) {
+        self.accept_compression = accept;
+    }
+
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.compress_threshold
+    }
+
+    pub fn set_compress_threshold(&mut self, threshold: Option<usize>) {
+        self.compress_threshold = threshold;
+    }
+
     /// Creates a Message from a JSON value, consuming the JSON value.
     ///
     /// Returns Err if the JSON value cannot be coerced into a Message.
@@ -500,11 +560,22 @@ impl Message {
         let mtype: MessageType = mtype_str.into();
         let payload = msg_hash["payload"].take();
 
-        let payload = Message::payload_from_json_value(mtype, payload, raw_data_mode)?;
+        let payload = match msg_hash["compression"].as_str() {
+            None => Message::payload_from_json_value(mtype, payload, raw_data_mode)?,
+            Some(compress::COMPRESSION_ZLIB) if mtype == MessageType::Result => {
+                Payload::Result(Result::from_compressed_json_value(payload, raw_data_mode)?)
+            }
+            Some(c) => return Err(format!("Unsupported message compression: {c}").into()),
+        };
 
         let mut msg = Message::new(mtype, thread_trace, payload);
 
-        if let Some(tz) = msg_hash["tz"].as_str() {
+        if msg_hash["accept_compression"].as_str() == Some(compress::COMPRESSION_ZLIB) {
+            msg.set_accept_compression(true);
+        }
+
+        // Older versions of this crate sent "timezone" instead of "tz".
+        if let Some(tz) = msg_hash["tz"].as_str().or(msg_hash["timezone"].as_str()) {
             msg.set_timezone(tz);
         }
 
@@ -512,6 +583,7 @@ impl Message {
         // value, adopt that value as our new thread-scoped locale.
         if let Some(lc) = msg_hash["locale"].as_str() {
             set_thread_locale(lc);
+            msg.set_locale(lc);
         }
 
         if let Some(ing) = msg_hash["ingress"].as_str() {
@@ -558,15 +630,29 @@ impl Message {
         let mut obj = json::object! {
             threadTrace: self.thread_trace,
             type: mtype,
-            locale: thread_locale(),
-            timezone: self.timezone(),
+            locale: self.locale(),
+            tz: self.timezone(),
             api_level: self.api_level(),
             ingress: self.ingress(),
         };
 
+        // Only add the compression keys when needed so peers which
+        // know nothing of compression see the messages they expect.
+        if self.accept_compression {
+            obj["accept_compression"] = compress::COMPRESSION_ZLIB.into();
+        }
+
         match self.payload {
             // Avoid adding the "payload" key for non-payload messages.
             Payload::NoPayload => {}
+            Payload::Result(result) if self.compress_threshold.is_some() => {
+                let threshold = self.compress_threshold.unwrap();
+                let (payload, compressed) = result.into_compressed_json_value(threshold);
+                if compressed {
+                    obj["compression"] = compress::COMPRESSION_ZLIB.into();
+                }
+                obj["payload"] = payload;
+            }
             _ => obj["payload"] = self.payload.into_json_value(),
         }
 
@@ -649,6 +735,22 @@ impl Result {
         Ok(Result::new(stat, stat_str, &msg_class, content))
     }
 
+    /// Creates a Result from a JSON value whose content was compressed
+    /// by into_compressed_json_value().
+    pub fn from_compressed_json_value(json_obj: JsonValue, raw_data_mode: bool) -> EgResult<Self> {
+        let err = || "Invalid compressed Result message".to_string();
+
+        let (msg_class, mut msg_hash) = EgValue::remove_class_wrapper(json_obj).ok_or_else(err)?;
+
+        let text = msg_hash["content"].as_str().ok_or_else(err)?;
+        msg_hash["content"] = compress::decompress_json(text)?;
+
+        Result::from_json_value(
+            EgValue::add_class_wrapper(msg_hash, &msg_class),
+            raw_data_mode,
+        )
+    }
+
     pub fn into_json_value(mut self) -> JsonValue {
         let obj = json::object! {
             status: self.status_label(),
@@ -658,6 +760,29 @@ impl Result {
 
         EgValue::add_class_wrapper(obj, &self.msg_class)
     }
+
+    /// Like into_json_value(), but the content is compressed if its
+    /// JSON is at least `threshold` bytes.
+    ///
+    /// Returns the JSON value and true if the content was compressed.
+    pub fn into_compressed_json_value(mut self, threshold: usize) -> (JsonValue, bool) {
+        let mut content = self.content.take().into_json_value();
+
+        let json_str = content.dump();
+        let compressed = json_str.len() >= threshold;
+
+        if compressed {
+            content = compress::compress_json_str(&json_str).into();
+        }
+
+        let obj = json::object! {
+            status: self.status_label(),
+            statusCode: self.status as isize,
+            content: content,
+        };
+
+        (EgValue::add_class_wrapper(obj, &self.msg_class), compressed)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -804,3 +929,135 @@ impl MethodCall {
         EgValue::add_class_wrapper(obj, &self.msg_class)
     }
 }
+
+#[test]
+fn transport_message_osrf_xid() {
+    let jv = json::object! {
+        to: "opensrf:client:test",
+        from: "opensrf:service:open-ils.actor",
+        thread: "12345",
+        body: [],
+    };
+
+    // Perl and JS peers may not send a trace.
+    let tmsg = TransportMessage::from_json_value(jv, false).unwrap();
+    assert_eq!(tmsg.osrf_xid(), "");
+
+    let mut tmsg = TransportMessage::new("to", "from", "thread");
+    tmsg.set_osrf_xid("1700000000000-00001");
+
+    let tmsg = TransportMessage::from_json_value(tmsg.into_json_value(), false).unwrap();
+    assert_eq!(tmsg.osrf_xid(), "1700000000000-00001");
+}
+
+#[test]
+fn message_locale_and_timezone() {
+    let call = MethodCall::new("opensrf.system.echo", vec![]);
+    let mut msg = Message::new(MessageType::Request, 1, Payload::Method(call));
+
+    let jv = msg.clone().into_json_value();
+    assert_eq!(jv["__p"]["tz"].as_str(), Some(DEFAULT_TIMEZONE));
+    assert_eq!(jv["__p"]["locale"].as_str(), Some(thread_locale().as_str()));
+
+    msg.set_timezone("America/Denver");
+    msg.set_locale("fr-CA");
+
+    let jv = msg.into_json_value();
+    assert_eq!(jv["__p"]["tz"].as_str(), Some("America/Denver"));
+    assert_eq!(jv["__p"]["locale"].as_str(), Some("fr-CA"));
+
+    let parsed = Message::from_json_value(jv, false).unwrap();
+    assert_eq!(parsed.timezone(), "America/Denver");
+    assert_eq!(parsed.locale(), "fr-CA");
+
+    reset_thread_locale();
+}
+
+#[test]
+fn transport_message_presence() {
+    let tmsg = TransportMessage::new("to", "from", "thread");
+    let jv = tmsg.into_json_value();
+
+    // Only senders which keep a presence key say so.
+    assert!(jv["presence"].is_null());
+    assert!(!TransportMessage::from_json_value(jv, false)
+        .unwrap()
+        .presence());
+
+    let mut tmsg = TransportMessage::new("to", "from", "thread");
+    tmsg.set_presence(true);
+
+    let tmsg = TransportMessage::from_json_value(tmsg.into_json_value(), false).unwrap();
+    assert!(tmsg.presence());
+}
+
+#[cfg(test)]
+fn large_result_message() -> Message {
+    let copies: Vec<JsonValue> = (0..40_000)
+        .map(|i| {
+            json::object! {
+                id: i,
+                barcode: format!("3100000{i:07}"),
+                status: "Available",
+                circ_lib: i % 30,
+            }
+        })
+        .collect();
+
+    Message::new(
+        MessageType::Result,
+        1,
+        Payload::Result(Result::new(
+            MessageStatus::Ok,
+            "OK",
+            "osrfResult",
+            EgValue::from_json_value(copies.into()).unwrap(),
+        )),
+    )
+}
+
+#[test]
+fn message_compression_round_trip() {
+    let plain = large_result_message();
+    let plain_json = plain.clone().into_json_value().dump();
+
+    assert!(plain_json.len() > 2_000_000);
+    assert!(!plain_json.contains("compression"));
+
+    let mut msg = plain.clone();
+    msg.set_compress_threshold(Some(65536));
+
+    let jv = msg.into_json_value();
+    assert_eq!(jv["__p"]["compression"].as_str(), Some("zlib"));
+
+    let compressed_json = jv.dump();
+    assert!(compressed_json.len() < plain_json.len() / 4);
+
+    let parsed = Message::from_json_value(json::parse(&compressed_json).unwrap(), false).unwrap();
+    assert!(parsed.payload() == plain.payload());
+
+    // Results smaller than the threshold are sent as-is.
+    let mut msg = plain.clone();
+    msg.set_compress_threshold(Some(plain_json.len() * 2));
+    assert!(msg.into_json_value()["__p"]["compression"].is_null());
+}
+
+#[test]
+fn message_compression_negotiation() {
+    let mut msg = Message::new(
+        MessageType::Request,
+        1,
+        Payload::Method(MethodCall::new("opensrf.system.echo", vec![])),
+    );
+
+    // Peers which know nothing of compression see no new keys.
+    assert!(msg.clone().into_json_value()["__p"]["accept_compression"].is_null());
+
+    msg.set_accept_compression(true);
+    let parsed = Message::from_json_value(msg.into_json_value(), false).unwrap();
+    assert!(parsed.accepts_compression());
+
+    let mut jv = large_result_message().into_json_value();
+    jv["__p"]["compression"] = "gzip".into();
+    assert!(Message::from_json_value(jv, false).is_err());
+}
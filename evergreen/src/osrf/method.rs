@@ -1,10 +1,18 @@
+use crate::editor::Editor;
 use crate::osrf::app;
 use crate::osrf::message;
 use crate::osrf::session;
 use crate::EgResult;
 use crate::EgValue;
 use json::JsonValue;
+use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Call stats for all methods handled by this process, keyed on
+/// method name.  Shared by all worker threads.
+static METHOD_STATS: OnceLock<Mutex<HashMap<String, MethodStats>>> = OnceLock::new();
 
 pub type MethodHandler = fn(
     &mut Box<dyn app::ApplicationWorker>,
@@ -19,6 +27,7 @@ pub enum ParamCount {
     Exactly(u8),
     AtLeast(u8),
     Range(u8, u8), // Inclusive
+    Max(u8),       // Inclusive
 }
 
 impl ParamCount {
@@ -32,6 +41,8 @@ impl ParamCount {
     /// assert!(ParamCount::matches(&ParamCount::AtLeast(10), 20));
     /// assert!(!ParamCount::matches(&ParamCount::AtLeast(20), 10));
     /// assert!(ParamCount::matches(&ParamCount::Range(4, 6), 5));
+    /// assert!(ParamCount::matches(&ParamCount::Max(2), 0));
+    /// assert!(!ParamCount::matches(&ParamCount::Max(2), 3));
     /// ```
     pub fn matches(pc: &ParamCount, count: u8) -> bool {
         match *pc {
@@ -40,6 +51,7 @@ impl ParamCount {
             ParamCount::Exactly(c) => count == c,
             ParamCount::AtLeast(c) => count >= c,
             ParamCount::Range(s, e) => s <= count && e >= count,
+            ParamCount::Max(c) => count <= c,
         }
     }
 
@@ -52,6 +64,7 @@ impl ParamCount {
             ParamCount::Exactly(c) => c,
             ParamCount::AtLeast(c) => c,
             ParamCount::Range(s, _) => s,
+            ParamCount::Max(_) => 0,
         }
     }
 }
@@ -64,6 +77,7 @@ impl fmt::Display for ParamCount {
             ParamCount::Exactly(c) => write!(f, "Exactly {}", c),
             ParamCount::AtLeast(c) => write!(f, "AtLeast {}", c),
             ParamCount::Range(s, e) => write!(f, "Range {}..{}", s, e),
+            ParamCount::Max(c) => write!(f, "Max {}", c),
         }
     }
 }
@@ -122,6 +136,9 @@ pub struct StaticParam {
     pub name: &'static str,
     pub datatype: ParamDataType,
     pub desc: &'static str,
+    /// Default value as JSON text, used when the caller omits
+    /// this parameter.  E.g. Some("100")
+    pub default: Option<&'static str>,
 }
 
 #[derive(Clone, Debug)]
@@ -129,18 +146,25 @@ pub struct Param {
     pub name: String,
     pub datatype: ParamDataType,
     pub desc: Option<String>,
+    /// Value passed to the handler when the caller omits this
+    /// parameter.  Only applies to optional parameters.
+    pub default: Option<EgValue>,
 }
 
 impl Param {
     pub fn to_eg_value(&self) -> EgValue {
-        EgValue::from_json_value_plain(json::object! {
+        let mut v = EgValue::from_json_value_plain(json::object! {
             "name": self.name.as_str(),
             "datatype": self.datatype.to_string(),
             "desc": match self.desc.as_ref() {
                 Some(d) => d.as_str().into(),
                 _ => JsonValue::Null,
             }
-        })
+        });
+
+        v["default"] = self.default.clone().unwrap_or(EgValue::Null);
+
+        v
     }
 }
 
@@ -152,6 +176,19 @@ pub struct StaticMethodDef {
     pub param_count: ParamCount,
     pub handler: MethodHandler,
     pub params: &'static [StaticParam],
+    /// If true, reject calls with parameters which do not match
+    /// their declared type, including NULL placeholders.
+    pub strict_params: bool,
+    /// If true, the first parameter is an authtoken which is verified
+    /// before the handler is called.
+    pub requires_auth: bool,
+    /// Permissions the requestor must have before the handler is
+    /// called.  Implies requires_auth.
+    pub required_permissions: &'static [&'static str],
+    /// Index of the parameter containing the org unit ID where
+    /// permissions are checked.  Defaults to the requestor's
+    /// workstation org unit, then home org unit.
+    pub perm_org_param: Option<usize>,
 }
 
 impl StaticMethodDef {
@@ -174,12 +211,23 @@ impl StaticMethodDef {
                 name: p.name.to_string(),
                 datatype: p.datatype,
                 desc: None,
+                default: None,
             };
 
             if p.desc.ne("") {
                 param.desc = Some(p.desc.to_string());
             }
 
+            if let Some(d) = p.default {
+                let value = EgValue::parse(d).unwrap_or_else(|e| {
+                    panic!(
+                        "Invalid default for param '{}' of {}: {e}",
+                        p.name, self.name
+                    )
+                });
+                param.default = Some(value);
+            }
+
             params.push(param)
         }
 
@@ -197,6 +245,15 @@ impl StaticMethodDef {
             m.desc = Some(self.desc.to_string());
         }
 
+        m.strict_params = self.strict_params;
+        m.requires_auth = self.requires_auth;
+        m.perm_org_param = self.perm_org_param;
+        m.required_permissions = self
+            .required_permissions
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+
         m
     }
 }
@@ -208,6 +265,12 @@ pub struct MethodDef {
     pub param_count: ParamCount,
     pub handler: MethodHandler,
     pub params: Option<Vec<Param>>,
+    pub strict_params: bool,
+    /// Overrides the service-level max chunk size for this method.
+    pub max_chunk_size: Option<usize>,
+    pub requires_auth: bool,
+    pub required_permissions: Vec<String>,
+    pub perm_org_param: Option<usize>,
 }
 
 impl MethodDef {
@@ -217,6 +280,11 @@ impl MethodDef {
             param_count,
             params: None,
             desc: None,
+            strict_params: false,
+            max_chunk_size: None,
+            requires_auth: false,
+            required_permissions: Vec::new(),
+            perm_org_param: None,
             name: name.to_string(),
         }
     }
@@ -247,6 +315,88 @@ impl MethodDef {
     pub fn set_desc(&mut self, desc: &str) {
         self.desc = Some(desc.to_string());
     }
+
+    /// True if parameters must strictly match their declared types.
+    ///
+    /// Otherwise, NULL placeholders are allowed for optional parameters.
+    pub fn strict_params(&self) -> bool {
+        self.strict_params
+    }
+    pub fn set_strict_params(&mut self, strict: bool) {
+        self.strict_params = strict;
+    }
+
+    /// Max size in bytes of a single response message for this method
+    /// before it's broken into partial response chunks.
+    ///
+    /// Use Some(0) to disable chunking for this method.
+    pub fn max_chunk_size(&self) -> Option<usize> {
+        self.max_chunk_size
+    }
+    pub fn set_max_chunk_size(&mut self, size: Option<usize>) {
+        self.max_chunk_size = size;
+    }
+
+    /// True if the authtoken passed as the first parameter must be
+    /// verified before the handler is called.
+    pub fn requires_auth(&self) -> bool {
+        self.requires_auth || !self.required_permissions.is_empty()
+    }
+    pub fn set_requires_auth(&mut self, requires: bool) {
+        self.requires_auth = requires;
+    }
+
+    pub fn required_permissions(&self) -> &[String] {
+        &self.required_permissions
+    }
+    pub fn add_required_permission(&mut self, perm: &str) {
+        self.required_permissions.push(perm.to_string());
+    }
+
+    pub fn perm_org_param(&self) -> Option<usize> {
+        self.perm_org_param
+    }
+    pub fn set_perm_org_param(&mut self, index: Option<usize>) {
+        self.perm_org_param = index;
+    }
+
+    /// Verify the authtoken and required permissions for a call to
+    /// this method.
+    ///
+    /// Returns false if the caller is not authorized, in which case
+    /// the editor's last event (NO_SESSION, PERM_FAILURE, etc.) is
+    /// the response for the caller.  Otherwise, the editor's
+    /// requestor is set.
+    pub fn authorize(&self, editor: &mut Editor, params: &[EgValue]) -> EgResult<bool> {
+        if !self.requires_auth() {
+            return Ok(true);
+        }
+
+        if let Some(token) = params.first().and_then(|p| p.as_str()) {
+            editor.set_authtoken(token);
+        }
+
+        if !editor.checkauth()? {
+            return Ok(false);
+        }
+
+        let org_id = match self.perm_org_param() {
+            Some(idx) => match params.get(idx).and_then(|p| p.as_int()) {
+                Some(id) => id,
+                None => editor.perm_org(),
+            },
+            None => editor.perm_org(),
+        };
+
+        for perm in self.required_permissions() {
+            if !editor.allowed_at(perm, org_id)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
     pub fn add_param(&mut self, param: Param) {
         let params = match self.params.as_mut() {
             Some(p) => p,
@@ -259,6 +409,67 @@ impl MethodDef {
         params.push(param);
     }
 
+    /// Append declared default values to the list of parameters for
+    /// any optional parameters the caller omitted.
+    ///
+    /// Omitted parameters which have no default, but precede a
+    /// parameter which does, are filled with NULL placeholders.
+    pub fn apply_param_defaults(&self, params: &mut Vec<EgValue>) {
+        let param_defs = match self.params() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let last_default = match param_defs.iter().rposition(|p| p.default.is_some()) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let minimum = self.param_count.minimum() as usize;
+
+        for (idx, param_def) in param_defs.iter().enumerate().take(last_default + 1) {
+            if idx < params.len() || idx < minimum {
+                continue;
+            }
+            params.push(param_def.default.clone().unwrap_or(EgValue::Null));
+        }
+    }
+
+    /// Returns an error naming the first parameter whose value does
+    /// not match its declared type.
+    ///
+    /// NULL placeholders for optional parameters are allowed unless
+    /// this method requires strict parameters.
+    pub fn check_param_types(&self, params: &[EgValue]) -> EgResult<()> {
+        let param_defs = match self.params() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let minimum = self.param_count.minimum() as usize;
+
+        // There may be more param defs than parameters if
+        // some params are optional.
+        for (idx, (param_def, param_val)) in param_defs.iter().zip(params).enumerate() {
+            if !self.strict_params && idx >= minimum && param_val.is_null() {
+                continue;
+            }
+
+            if !param_def.datatype.matches(param_val) {
+                return Err(format!(
+                    "Invalid type for parameter {idx} '{}' of {}: wanted={} got={}",
+                    param_def.name,
+                    self.name(),
+                    param_def.datatype,
+                    param_val.dump()
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn to_eg_value(&self) -> EgValue {
         let mut pa = EgValue::new_array();
         if let Some(params) = self.params() {
@@ -318,3 +529,197 @@ impl MethodDef {
         s
     }
 }
+
+/// Call count and timing information for a single method.
+#[derive(Debug, Clone, Default)]
+pub struct MethodStats {
+    call_count: u64,
+    error_count: u64,
+    /// Calls whose caller went away before the call completed.
+    abandoned_count: u64,
+    total_duration: Duration,
+    max_duration: Duration,
+    last_error: Option<String>,
+}
+
+impl MethodStats {
+    pub fn call_count(&self) -> u64 {
+        self.call_count
+    }
+    pub fn error_count(&self) -> u64 {
+        self.error_count
+    }
+    pub fn abandoned_count(&self) -> u64 {
+        self.abandoned_count
+    }
+    pub fn total_duration(&self) -> Duration {
+        self.total_duration
+    }
+    pub fn max_duration(&self) -> Duration {
+        self.max_duration
+    }
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Durations are reported in milliseconds.
+    pub fn to_eg_value(&self) -> EgValue {
+        let avg = if self.call_count > 0 {
+            self.total_duration.as_secs_f64() * 1000.0 / self.call_count as f64
+        } else {
+            0.0
+        };
+
+        let mut v = EgValue::new_object();
+        v["call_count"] = EgValue::from(self.call_count as i64);
+        v["error_count"] = EgValue::from(self.error_count as i64);
+        v["abandoned_count"] = EgValue::from(self.abandoned_count as i64);
+        v["total_ms"] = EgValue::from(self.total_duration.as_secs_f64() * 1000.0);
+        v["avg_ms"] = EgValue::from(avg);
+        v["max_ms"] = EgValue::from(self.max_duration.as_secs_f64() * 1000.0);
+        v["last_error"] = EgValue::from(self.last_error.clone());
+        v
+    }
+
+    /// Record the outcome of a single call to the named method.
+    pub fn record(method: &str, duration: Duration, error: Option<String>) {
+        let mut stats = match METHOD_STATS.get_or_init(Default::default).lock() {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Method stats lock is poisoned: {e}");
+                return;
+            }
+        };
+
+        let entry = stats.entry(method.to_string()).or_default();
+
+        entry.call_count += 1;
+        entry.total_duration += duration;
+
+        if duration > entry.max_duration {
+            entry.max_duration = duration;
+        }
+
+        if error.is_some() {
+            entry.error_count += 1;
+            entry.last_error = error;
+        }
+    }
+
+    /// Record a call to the named method whose caller went away
+    /// before the call completed.
+    ///
+    /// The call itself is recorded separately via record().
+    pub fn record_abandoned(method: &str) {
+        match METHOD_STATS.get_or_init(Default::default).lock() {
+            Ok(mut s) => s.entry(method.to_string()).or_default().abandoned_count += 1,
+            Err(e) => log::error!("Method stats lock is poisoned: {e}"),
+        }
+    }
+
+    /// Returns a copy of the stats collected so far for all methods.
+    pub fn collect() -> HashMap<String, MethodStats> {
+        match METHOD_STATS.get_or_init(Default::default).lock() {
+            Ok(s) => s.clone(),
+            Err(_) => HashMap::new(),
+        }
+    }
+
+    /// Discard all collected stats.
+    pub fn clear() {
+        if let Ok(mut s) = METHOD_STATS.get_or_init(Default::default).lock() {
+            s.clear();
+        }
+    }
+}
+
+#[test]
+fn param_defaults_and_types() {
+    fn noop(
+        _: &mut Box<dyn app::ApplicationWorker>,
+        _: &mut session::ServerSession,
+        _: &message::MethodCall,
+    ) -> EgResult<()> {
+        Ok(())
+    }
+
+    static PARAMS: &[StaticParam] = &[
+        StaticParam {
+            name: "query",
+            datatype: ParamDataType::String,
+            desc: "",
+            default: None,
+        },
+        StaticParam {
+            name: "offset",
+            datatype: ParamDataType::Number,
+            desc: "",
+            default: None,
+        },
+        StaticParam {
+            name: "limit",
+            datatype: ParamDataType::Number,
+            desc: "",
+            default: Some("100"),
+        },
+    ];
+
+    let def = StaticMethodDef {
+        name: "search",
+        desc: "",
+        param_count: ParamCount::Range(1, 3),
+        handler: noop,
+        params: PARAMS,
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
+    };
+
+    let mut method = def.into_method("test");
+    assert_eq!(
+        method.to_eg_value()["params"][2]["default"].int_required(),
+        100
+    );
+
+    let mut params = vec![EgValue::from("foo")];
+    method.apply_param_defaults(&mut params);
+    assert_eq!(params.len(), 3);
+    assert!(params[1].is_null());
+    assert_eq!(params[2].int_required(), 100);
+
+    // Values provided by the caller are left as-is.
+    let mut params = vec![EgValue::from("foo"), EgValue::from(5), EgValue::from(10)];
+    method.apply_param_defaults(&mut params);
+    assert_eq!(params[2].int_required(), 10);
+
+    let params = vec![EgValue::from("foo"), EgValue::Null];
+    assert!(method.check_param_types(&params).is_ok());
+
+    method.set_strict_params(true);
+    let err = method.check_param_types(&params).unwrap_err();
+    assert!(err.to_string().contains("'offset'"));
+
+    let params = vec![EgValue::from(1)];
+    assert!(method.check_param_types(&params).is_err());
+}
+
+#[test]
+fn method_stats() {
+    let name = "test.method_stats";
+
+    MethodStats::record(name, Duration::from_millis(10), None);
+    MethodStats::record(name, Duration::from_millis(30), Some("Oops".to_string()));
+    MethodStats::record_abandoned(name);
+
+    let all = MethodStats::collect();
+    let stats = all.get(name).unwrap();
+
+    assert_eq!(stats.call_count(), 2);
+    assert_eq!(stats.error_count(), 1);
+    assert_eq!(stats.abandoned_count(), 1);
+    assert_eq!(stats.max_duration(), Duration::from_millis(30));
+    assert_eq!(stats.total_duration(), Duration::from_millis(40));
+    assert_eq!(stats.last_error(), Some("Oops"));
+    assert_eq!(stats.to_eg_value()["avg_ms"].as_f64(), Some(20.0));
+}
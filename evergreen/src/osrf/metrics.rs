@@ -0,0 +1,604 @@
+//! Process metrics in the Prometheus text exposition format.
+//!
+//! A small internal registry of counters, gauges, and histograms
+//! shared by everything running in the process.  Metric handles are
+//! cheap to clone and safe to use from any thread.  The registry is
+//! rendered for scraping via GET /metrics, either by a caller's own
+//! HTTP listener or the one started by spawn_listener().
+//!
+//! Each metric keeps at most MAX_SERIES label combinations.  Values
+//! for label combinations beyond that are folded into a single series
+//! whose label values are all OVERFLOW_LABEL, so callers passing
+//! unexpected values cannot grow the registry without bound.
+use crate::osrf::worker::WorkerState;
+use crate::util;
+use crate::EgResult;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+static OSRF_METRICS: OnceLock<OsrfMetrics> = OnceLock::new();
+
+/// Content-Type for rendered metrics.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Most label combinations tracked per metric.
+pub const MAX_SERIES: usize = 100;
+
+/// Label value used for combinations beyond MAX_SERIES.
+pub const OVERFLOW_LABEL: &str = "other";
+
+/// Histogram buckets, in seconds, suitable for request latency.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Wake this often to check for new metrics requests.
+const LISTENER_POLL_INTERVAL: u64 = 5;
+
+/// Metrics clients have this long to send their request.
+const LISTENER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+/// Current value of one label combination.
+#[derive(Debug, Default, Clone)]
+struct Series {
+    /// Counter and gauge value.
+    value: f64,
+    /// Histogram observations per bucket, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+struct Family {
+    name: String,
+    help: String,
+    kind: Kind,
+    label_names: Vec<String>,
+    /// Histogram bucket upper bounds, ascending.
+    buckets: Vec<f64>,
+    series: Mutex<BTreeMap<Vec<String>, Series>>,
+    /// True once we've warned about too many label combinations.
+    overflowed: AtomicBool,
+}
+
+impl Family {
+    /// Apply a change to the series for these label values.
+    fn update(&self, labels: &[&str], f: impl FnOnce(&mut Series)) {
+        if labels.len() != self.label_names.len() {
+            log::error!(
+                "Metric {} expects {} labels; got {}",
+                self.name,
+                self.label_names.len(),
+                labels.len()
+            );
+            return;
+        }
+
+        let mut key: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+
+        let mut series = match self.series.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+
+        if !series.contains_key(&key) && series.len() >= MAX_SERIES {
+            if !self.overflowed.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Metric {} exceeded {MAX_SERIES} label combinations; \
+                    recording new values as '{OVERFLOW_LABEL}'",
+                    self.name
+                );
+            }
+            key = vec![OVERFLOW_LABEL.to_string(); labels.len()];
+        }
+
+        let nbuckets = self.buckets.len();
+
+        f(series.entry(key).or_insert_with(|| Series {
+            buckets: vec![0; nbuckets],
+            ..Default::default()
+        }));
+    }
+
+    fn render(&self, out: &mut String) {
+        let series = match self.series.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+
+        let _ = writeln!(out, "# HELP {} {}", self.name, escape_help(&self.help));
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind.as_str());
+
+        for (values, s) in series.iter() {
+            let labels = self.label_pairs(values);
+
+            if self.kind != Kind::Histogram {
+                let _ = writeln!(
+                    out,
+                    "{}{} {}",
+                    self.name,
+                    wrap_labels(&labels),
+                    format_value(s.value)
+                );
+                continue;
+            }
+
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(s.buckets.iter()) {
+                cumulative += count;
+                let mut pairs = labels.clone();
+                pairs.push(format!("le=\"{}\"", format_value(*bound)));
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {cumulative}",
+                    self.name,
+                    wrap_labels(&pairs)
+                );
+            }
+
+            let mut pairs = labels.clone();
+            pairs.push("le=\"+Inf\"".to_string());
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                self.name,
+                wrap_labels(&pairs),
+                s.count
+            );
+
+            let labels = wrap_labels(&labels);
+            let _ = writeln!(out, "{}_sum{labels} {}", self.name, format_value(s.sum));
+            let _ = writeln!(out, "{}_count{labels} {}", self.name, s.count);
+        }
+    }
+
+    /// name="value" pairs for a series.
+    fn label_pairs(&self, values: &[String]) -> Vec<String> {
+        self.label_names
+            .iter()
+            .zip(values.iter())
+            .map(|(n, v)| format!("{n}=\"{}\"", escape_label(v)))
+            .collect()
+    }
+}
+
+/// Monotonically increasing count.
+#[derive(Clone)]
+pub struct Counter {
+    family: Arc<Family>,
+}
+
+impl Counter {
+    /// Add one to the series for these label values.
+    pub fn inc(&self, labels: &[&str]) {
+        self.inc_by(labels, 1);
+    }
+
+    pub fn inc_by(&self, labels: &[&str], amount: u64) {
+        self.family.update(labels, |s| s.value += amount as f64);
+    }
+}
+
+/// Value which may go up and down.
+#[derive(Clone)]
+pub struct Gauge {
+    family: Arc<Family>,
+}
+
+impl Gauge {
+    pub fn set(&self, labels: &[&str], value: f64) {
+        self.family.update(labels, |s| s.value = value);
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.family.update(labels, |s| s.value += 1.0);
+    }
+
+    pub fn dec(&self, labels: &[&str]) {
+        self.family.update(labels, |s| s.value -= 1.0);
+    }
+}
+
+/// Distribution of observed values across fixed buckets.
+#[derive(Clone)]
+pub struct Histogram {
+    family: Arc<Family>,
+}
+
+impl Histogram {
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let pos = self.family.buckets.iter().position(|b| value <= *b);
+
+        self.family.update(labels, |s| {
+            if let Some(p) = pos {
+                s.buckets[p] += 1;
+            }
+            s.sum += value;
+            s.count += 1;
+        });
+    }
+
+    /// Observe a duration in seconds.
+    pub fn observe_duration(&self, labels: &[&str], duration: Duration) {
+        self.observe(labels, duration.as_secs_f64());
+    }
+}
+
+/// Collection of metrics rendered together.
+#[derive(Default)]
+pub struct Registry {
+    /// In order of registration.
+    families: Mutex<Vec<Arc<Family>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str, labels: &[&str]) -> Counter {
+        Counter {
+            family: self.family(name, help, Kind::Counter, labels, &[]),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[&str]) -> Gauge {
+        Gauge {
+            family: self.family(name, help, Kind::Gauge, labels, &[]),
+        }
+    }
+
+    /// Histogram with the provided bucket upper bounds.  See
+    /// LATENCY_BUCKETS.
+    pub fn histogram(&self, name: &str, help: &str, labels: &[&str], buckets: &[f64]) -> Histogram {
+        Histogram {
+            family: self.family(name, help, Kind::Histogram, labels, buckets),
+        }
+    }
+
+    /// Find or create a metric family.
+    ///
+    /// Registering an existing name returns the existing family.  If
+    /// the existing family is of a different kind or has different
+    /// labels, the returned family is not rendered.
+    fn family(
+        &self,
+        name: &str,
+        help: &str,
+        kind: Kind,
+        labels: &[&str],
+        buckets: &[f64],
+    ) -> Arc<Family> {
+        let mut buckets: Vec<f64> = buckets.iter().copied().filter(|b| b.is_finite()).collect();
+        buckets.sort_by(|a, b| a.total_cmp(b));
+        buckets.dedup();
+
+        let family = Arc::new(Family {
+            name: name.to_string(),
+            help: help.to_string(),
+            kind,
+            label_names: labels.iter().map(|l| l.to_string()).collect(),
+            buckets,
+            series: Default::default(),
+            overflowed: AtomicBool::new(false),
+        });
+
+        // Metrics without labels are reported from the start.
+        if labels.is_empty() {
+            family.update(&[], |_| {});
+        }
+
+        let mut families = match self.families.lock() {
+            Ok(f) => f,
+            Err(_) => return family,
+        };
+
+        if let Some(existing) = families.iter().find(|f| f.name == name) {
+            if existing.kind == kind && existing.label_names == family.label_names {
+                return existing.clone();
+            }
+
+            log::error!("Metric {name} is already registered with a different type or labels");
+
+            return family;
+        }
+
+        families.push(family.clone());
+
+        family
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        if let Ok(families) = self.families.lock() {
+            for family in families.iter() {
+                family.render(&mut out);
+            }
+        }
+
+        out
+    }
+}
+
+/// Registry shared by everything in this process.
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// OpenSRF client, bus, and server metrics.
+pub struct OsrfMetrics {
+    requests_sent: Counter,
+    responses_received: Counter,
+    request_duration: Histogram,
+    bus_reconnects: Counter,
+    server_requests: Counter,
+    server_request_duration: Histogram,
+    server_workers: Gauge,
+    server_backlog: Gauge,
+}
+
+impl OsrfMetrics {
+    /// Metrics are labeled by service rather than API name, since
+    /// services are few and API names are many.
+    fn new(registry: &Registry) -> Self {
+        OsrfMetrics {
+            requests_sent: registry.counter(
+                "osrf_client_requests_sent_total",
+                "OpenSRF requests sent by this process",
+                &["service"],
+            ),
+            responses_received: registry.counter(
+                "osrf_client_responses_received_total",
+                "OpenSRF responses received by this process",
+                &["service"],
+            ),
+            request_duration: registry.histogram(
+                "osrf_client_request_duration_seconds",
+                "Time from sending an OpenSRF request until it completes",
+                &["service"],
+                LATENCY_BUCKETS,
+            ),
+            bus_reconnects: registry.counter(
+                "osrf_bus_reconnects_total",
+                "Successful reconnects to the message bus",
+                &[],
+            ),
+            server_requests: registry.counter(
+                "osrf_server_requests_total",
+                "API requests handled by this service by outcome",
+                &["service", "outcome"],
+            ),
+            server_request_duration: registry.histogram(
+                "osrf_server_request_duration_seconds",
+                "Time spent running API handlers",
+                &["service"],
+                LATENCY_BUCKETS,
+            ),
+            server_workers: registry.gauge(
+                "osrf_server_workers",
+                "Service worker threads by state",
+                &["service", "state"],
+            ),
+            server_backlog: registry.gauge(
+                "osrf_server_backlog",
+                "Requests waiting on the service queue",
+                &["service"],
+            ),
+        }
+    }
+
+    /// Metrics registered with the global registry.
+    pub fn get() -> &'static OsrfMetrics {
+        OSRF_METRICS.get_or_init(|| OsrfMetrics::new(registry()))
+    }
+
+    pub fn request_sent(&self, service: &str) {
+        self.requests_sent.inc(&[service]);
+    }
+
+    pub fn response_received(&self, service: &str) {
+        self.responses_received.inc(&[service]);
+    }
+
+    pub fn request_complete(&self, service: &str, duration: Duration) {
+        self.request_duration.observe_duration(&[service], duration);
+    }
+
+    pub fn bus_reconnected(&self) {
+        self.bus_reconnects.inc(&[]);
+    }
+
+    /// outcome is one of "ok", "error", "abandoned", "not_found",
+    /// or "bad_request".
+    pub fn server_request(&self, service: &str, outcome: &str, duration: Option<Duration>) {
+        self.server_requests.inc(&[service, outcome]);
+
+        if let Some(d) = duration {
+            self.server_request_duration.observe_duration(&[service], d);
+        }
+    }
+
+    /// Record worker pool occupancy for the service.
+    pub fn server_pool(&self, service: &str, idle: usize, active: usize, backlog: usize) {
+        for (state, count) in [(WorkerState::Idle, idle), (WorkerState::Active, active)] {
+            let state = format!("{state:?}").to_lowercase();
+            self.server_workers
+                .set(&[service, state.as_str()], count as f64);
+        }
+
+        self.server_backlog.set(&[service], backlog as f64);
+    }
+}
+
+/// Serve GET /metrics from the global registry in a new thread.
+///
+/// Other paths return 404.  The thread runs until the process exits.
+pub fn spawn_listener(address: &str, port: u16) -> EgResult<()> {
+    let listener = util::tcp_listener(address, port, LISTENER_POLL_INTERVAL)?;
+
+    log::info!("Metrics listener running at {address}:{port}");
+
+    thread::spawn(move || loop {
+        let stream = match listener.accept() {
+            Ok((s, _)) => s,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::WouldBlock {
+                    log::error!("Metrics listener accept() failed: {e}");
+                }
+                continue;
+            }
+        };
+
+        if let Err(e) = handle_request(stream) {
+            log::warn!("Metrics request failed: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(LISTENER_READ_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    let mut buf = [0u8; 1024];
+    let count = stream.read(&mut buf).map_err(|e| e.to_string())?;
+    let text = String::from_utf8_lossy(&buf[..count]);
+
+    let mut parts = text.lines().next().unwrap_or("").split_whitespace();
+
+    let is_metrics = parts.next() == Some("GET")
+        && parts.next().and_then(|p| p.split('?').next()) == Some("/metrics");
+
+    let (status, body) = if is_metrics {
+        ("200 OK", registry().render())
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn wrap_labels(pairs: &[String]) -> String {
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[test]
+fn render_metrics() {
+    let registry = Registry::new();
+
+    let counter = registry.counter("sip_messages_total", "SIP messages", &["code"]);
+    counter.inc(&["23"]);
+    counter.inc_by(&["09"], 2);
+    counter.inc(&["23"]);
+    counter.inc(&["23", "extra"]); // ignored
+
+    let gauge = registry.gauge("sessions", "Active\nsessions", &[]);
+    gauge.inc(&[]);
+    gauge.inc(&[]);
+    gauge.dec(&[]);
+
+    let hist = registry.histogram("latency_seconds", "Latency", &["svc"], &[1.0, 0.1]);
+    hist.observe(&["a\"b"], 0.05);
+    hist.observe(&["a\"b"], 0.5);
+    hist.observe(&["a\"b"], 5.0);
+
+    // Same name and kind returns the existing metric.
+    registry
+        .counter("sip_messages_total", "SIP messages", &["code"])
+        .inc(&["09"]);
+
+    let expected = "\
+# HELP sip_messages_total SIP messages
+# TYPE sip_messages_total counter
+sip_messages_total{code=\"09\"} 3
+sip_messages_total{code=\"23\"} 2
+# HELP sessions Active\\nsessions
+# TYPE sessions gauge
+sessions 1
+# HELP latency_seconds Latency
+# TYPE latency_seconds histogram
+latency_seconds_bucket{svc=\"a\\\"b\",le=\"0.1\"} 1
+latency_seconds_bucket{svc=\"a\\\"b\",le=\"1\"} 2
+latency_seconds_bucket{svc=\"a\\\"b\",le=\"+Inf\"} 3
+latency_seconds_sum{svc=\"a\\\"b\"} 5.55
+latency_seconds_count{svc=\"a\\\"b\"} 3
+";
+
+    assert_eq!(registry.render(), expected);
+}
+
+#[test]
+fn bounded_label_cardinality() {
+    let registry = Registry::new();
+    let counter = registry.counter("requests_total", "Requests", &["code"]);
+
+    for i in 0..MAX_SERIES + 10 {
+        counter.inc(&[&i.to_string()]);
+    }
+
+    let text = registry.render();
+
+    assert_eq!(text.lines().count(), MAX_SERIES + 3);
+    assert!(text.contains(&format!("requests_total{{code=\"{OVERFLOW_LABEL}\"}} 10")));
+}
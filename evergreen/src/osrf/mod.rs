@@ -1,15 +1,21 @@
 //! OpenSRF Components
 pub mod addr;
 pub mod app;
+pub mod autoscale;
 pub mod bus;
 pub mod cache;
 pub mod client;
+pub mod compress;
 pub mod conf;
+pub mod events;
 pub mod logging;
 pub mod message;
 pub mod method;
+pub mod metrics;
 pub mod params;
+pub mod pool;
 pub mod sclient;
 pub mod server;
 pub mod session;
+pub mod websocket;
 pub mod worker;
@@ -0,0 +1,235 @@
+//! Pool of message bus connections shared across threads.
+//!
+//! Clients are not thread-safe, so the pool hands out Bus connections,
+//! which a thread wraps in its own Client via Client::from_bus().
+use crate::osrf::bus::Bus;
+use crate::osrf::conf;
+use crate::EgResult;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Point-in-time pool counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PoolStats {
+    /// Connections currently checked out.
+    pub in_use: usize,
+    /// Connections waiting in the pool.
+    pub idle: usize,
+    /// New connections opened by the pool.
+    pub created: u64,
+    /// Checkouts served by reusing an idle connection.
+    pub recycled: u64,
+    /// Connections dropped because they failed validation or cleanup.
+    pub discarded: u64,
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "in_use={} idle={} created={} recycled={} discarded={}",
+            self.in_use, self.idle, self.created, self.recycled, self.discarded
+        )
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: Vec<Bus>,
+    stats: PoolStats,
+}
+
+/// Thread-safe pool of Bus connections.  Cloning is cheap.
+#[derive(Clone)]
+pub struct BusPool {
+    min_size: usize,
+    max_size: usize,
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl BusPool {
+    /// Create an empty pool.
+    ///
+    /// max_size limits the number of connections which may be checked
+    /// out or idle at once.  Use fill() to open min_size connections
+    /// in advance.
+    pub fn new(min_size: usize, max_size: usize) -> BusPool {
+        BusPool {
+            min_size: min_size.min(max_size),
+            max_size,
+            state: Arc::new(Mutex::new(PoolState::default())),
+        }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.state.lock().unwrap();
+        let mut stats = state.stats;
+        stats.idle = state.idle.len();
+        stats
+    }
+
+    /// Open connections until at least min_size are in use or idle.
+    pub fn fill(&self) -> EgResult<()> {
+        loop {
+            {
+                let state = self.state.lock().unwrap();
+                if state.idle.len() + state.stats.in_use >= self.min_size {
+                    return Ok(());
+                }
+            }
+
+            let bus = Bus::new(conf::config().client())?;
+
+            let mut state = self.state.lock().unwrap();
+            state.stats.created += 1;
+            state.idle.push(bus);
+        }
+    }
+
+    /// Check out a connection, opening a new one if no idle
+    /// connections are available.
+    ///
+    /// Idle connections are verified before they are returned.  Dead
+    /// connections are discarded and replaced.
+    ///
+    /// Returns None if the pool is at its max size.
+    pub fn checkout(&self) -> EgResult<Option<PooledBus>> {
+        loop {
+            let idle = {
+                let mut state = self.state.lock().unwrap();
+
+                match state.idle.pop() {
+                    Some(b) => Some(b),
+                    None => {
+                        if state.stats.in_use >= self.max_size {
+                            log::warn!("Bus pool exhausted at {} connections", self.max_size);
+                            return Ok(None);
+                        }
+                        None
+                    }
+                }
+            };
+
+            let mut bus = match idle {
+                Some(b) => b,
+                None => break,
+            };
+
+            if let Err(e) = bus.ping() {
+                log::info!("Discarding dead pooled bus connection: {e}");
+                self.state.lock().unwrap().stats.discarded += 1;
+                continue;
+            }
+
+            let mut state = self.state.lock().unwrap();
+            state.stats.in_use += 1;
+            state.stats.recycled += 1;
+
+            return Ok(Some(self.wrap(bus)));
+        }
+
+        // Reserve our slot before connecting so concurrent callers
+        // cannot exceed the max size.
+        self.state.lock().unwrap().stats.in_use += 1;
+
+        match Bus::new(conf::config().client()) {
+            Ok(bus) => {
+                self.state.lock().unwrap().stats.created += 1;
+                Ok(Some(self.wrap(bus)))
+            }
+            Err(e) => {
+                self.state.lock().unwrap().stats.in_use -= 1;
+                Err(e)
+            }
+        }
+    }
+
+    fn wrap(&self, bus: Bus) -> PooledBus {
+        PooledBus {
+            bus: Some(bus),
+            pool: self.clone(),
+        }
+    }
+
+    /// Return a connection to the pool, releasing its slot.
+    ///
+    /// Pending messages are removed and the bus is given a new address
+    /// so nothing from its previous user lingers.
+    fn checkin(&self, bus: Option<Bus>) {
+        let mut bus = match bus {
+            Some(b) => b,
+            None => {
+                // Caller kept or destroyed the connection.
+                let mut state = self.state.lock().unwrap();
+                state.stats.in_use -= 1;
+                state.stats.discarded += 1;
+                return;
+            }
+        };
+
+        let cleaned = bus.clear_bus();
+        bus.generate_address();
+
+        let mut state = self.state.lock().unwrap();
+        state.stats.in_use -= 1;
+
+        match cleaned {
+            Ok(()) => state.idle.push(bus),
+            Err(e) => {
+                log::info!("Discarding pooled bus connection: {e}");
+                state.stats.discarded += 1;
+            }
+        }
+    }
+}
+
+/// A Bus connection checked out from a BusPool.
+///
+/// The connection returns to the pool when this is dropped.
+pub struct PooledBus {
+    bus: Option<Bus>,
+    pool: BusPool,
+}
+
+impl PooledBus {
+    /// Panics if the Bus has been taken and not replaced.
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.bus.as_mut().expect("PooledBus has a Bus")
+    }
+
+    /// Take the Bus, e.g. to build a Client around it.
+    ///
+    /// Use put_bus() to return the Bus so it can go back into the
+    /// pool.  Otherwise, the connection is dropped from the pool.
+    pub fn take_bus(&mut self) -> Bus {
+        self.bus.take().expect("PooledBus has a Bus")
+    }
+
+    pub fn put_bus(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+}
+
+impl Drop for PooledBus {
+    fn drop(&mut self) {
+        self.pool.checkin(self.bus.take());
+    }
+}
+
+#[test]
+fn bus_pool_exhausted() {
+    let pool = BusPool::new(5, 0);
+
+    assert_eq!(pool.min_size(), 0);
+    assert!(pool.fill().is_ok());
+    assert!(pool.checkout().unwrap().is_none());
+    assert_eq!(pool.stats(), PoolStats::default());
+}
@@ -1,15 +1,31 @@
 //! Host Settings Module
+//!
+//! Host settings are fetched from opensrf.settings at startup and may
+//! be refreshed at any time via HostSettings::refresh().  Lookups via
+//! HostSettings::get() always see the most recently fetched values.
+//!
+//! Values derived from the settings and stored elsewhere, e.g. the
+//! IDL file path, worker counts, or cache server addresses, are not
+//! updated by a refresh.  Consumers which need to react to changes
+//! can compare HostSettings::generation() with the value they saw
+//! when the derived value was built.
 use crate::osrf::conf;
 use crate::Client;
 use crate::EgResult;
 use crate::EgValue;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 
 const SETTINGS_TIMEOUT: i32 = 10;
 
 /// If we fetch host settings, they will live here.
-/// They may be fetched and stored at most one time.
-static OSRF_HOST_CONFIG: OnceLock<HostSettings> = OnceLock::new();
+///
+/// The settings are swapped as a whole on refresh, so readers holding
+/// a copy of the Arc are not affected by a refresh.
+static OSRF_HOST_CONFIG: RwLock<Option<Arc<HostSettings>>> = RwLock::new(None);
+
+/// Incremented each time the host settings are (re)loaded.
+static SETTINGS_GENERATION: AtomicUsize = AtomicUsize::new(0);
 
 /// Read-only wrapper around a JSON blob of server setting values, which
 /// provides accessor methods for pulling setting values.
@@ -20,13 +36,43 @@ pub struct HostSettings {
 impl HostSettings {
     /// True if the host settings have been loaded.
     pub fn is_loaded() -> bool {
-        OSRF_HOST_CONFIG.get().is_some()
+        HostSettings::current().is_ok()
     }
 
     /// Fetch the host config for our host and store the result in
     /// our global host settings.
     ///
+    /// Returns an Err if the settings have already been loaded.
+    /// Use refresh() to replace loaded settings.
     pub fn load(client: &Client) -> EgResult<()> {
+        if HostSettings::is_loaded() {
+            return Err("Cannot apply host settings more than once".into());
+        }
+
+        HostSettings::refresh(client)
+    }
+
+    /// Fetch the host config for our host and replace any previously
+    /// loaded settings with the result.
+    ///
+    /// On error, the previously loaded settings remain in place.
+    pub fn refresh(client: &Client) -> EgResult<()> {
+        let settings = HostSettings::fetch(client)?;
+
+        let mut lock = OSRF_HOST_CONFIG
+            .write()
+            .map_err(|e| format!("Cannot update host settings: {e}"))?;
+
+        *lock = Some(Arc::new(settings));
+
+        let generation = SETTINGS_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+
+        log::info!("Host settings loaded; generation={generation}");
+
+        Ok(())
+    }
+
+    fn fetch(client: &Client) -> EgResult<HostSettings> {
         let mut ses = client.session("opensrf.settings");
 
         let mut req = ses.request(
@@ -34,38 +80,49 @@ impl HostSettings {
             conf::config().hostname(),
         )?;
 
-        if let Some(s) = req.recv_with_timeout(SETTINGS_TIMEOUT)? {
-            let sets = HostSettings { settings: s };
-            if OSRF_HOST_CONFIG.set(sets).is_err() {
-                return Err(format!("Cannot apply host settings more than once").into());
-            }
-
-            Ok(())
-        } else {
-            Err(format!("Settings server returned no response!").into())
+        match req.recv_with_timeout(SETTINGS_TIMEOUT)? {
+            Some(s) => Ok(HostSettings { settings: s }),
+            None => Err("Settings server returned no response!".into()),
         }
     }
 
+    /// Number of times the host settings have been loaded.
+    ///
+    /// 0 means the settings have never been loaded.
+    pub fn generation() -> usize {
+        SETTINGS_GENERATION.load(Ordering::Relaxed)
+    }
+
+    /// The most recently loaded host settings.
+    pub fn current() -> EgResult<Arc<HostSettings>> {
+        let lock = OSRF_HOST_CONFIG
+            .read()
+            .map_err(|e| format!("Cannot read host settings: {e}"))?;
+
+        lock.as_ref()
+            .cloned()
+            .ok_or_else(|| "Host settings have not been retrieved".into())
+    }
+
     /// Returns the full host settings config as a JsonValue.
     pub fn settings(&self) -> &EgValue {
         &self.settings
     }
 
-    /// Returns the JsonValue at the specified path.
+    /// Returns a copy of the value at the specified path.
     ///
-    /// Panics of the host config has not yet been retrieved.
+    /// Returns EgValue::Null if no value exists at the path and an
+    /// Err if the host settings have not been retrieved.
     ///
     /// E.g. sclient.value("apps/opensrf.settings/unix_config/max_children");
-    pub fn get(slashpath: &str) -> EgResult<&EgValue> {
-        let hsets = OSRF_HOST_CONFIG
-            .get()
-            .ok_or_else(|| format!("Host settings have not been retrieved"))?;
+    pub fn get(slashpath: &str) -> EgResult<EgValue> {
+        let hsets = HostSettings::current()?;
 
         let mut value = hsets.settings();
         for part in slashpath.split("/") {
             value = &value[part]; // -> JsonValue::Null if key is not found.
         }
 
-        Ok(value)
+        Ok(value.clone())
     }
 }
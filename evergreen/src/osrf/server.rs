@@ -1,27 +1,33 @@
 use crate::init;
+use crate::osrf::addr::BusAddress;
 use crate::osrf::app;
+use crate::osrf::autoscale::{Autoscaler, ScalingPolicy, WorkerCounts, WorkerSummary};
 use crate::osrf::client::Client;
 use crate::osrf::conf;
 use crate::osrf::message;
 use crate::osrf::method;
+use crate::osrf::metrics::{self, OsrfMetrics};
 use crate::osrf::sclient::HostSettings;
 use crate::osrf::session;
 use crate::osrf::worker::{Worker, WorkerState, WorkerStateEvent};
 use crate::util;
 use crate::EgResult;
+use crate::EgValue;
 use mptc::signals::SignalTracker;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Warn when there are fewer than this many idle threads
 const IDLE_THREAD_WARN_THRESHOLD: usize = 1;
-/// How often do we wake to check for shutdown, etc. signals when
-/// no other activity is occurring.
-const IDLE_WAKE_TIME: u64 = 3;
+/// How often do we wake to check for shutdown, etc. signals and
+/// the request backlog when no other activity is occurring.
+const IDLE_WAKE_TIME: u64 = 1;
 /// Max time in seconds to allow active workers to finish their tasks.
 const SHUTDOWN_MAX_WAIT: i32 = 30;
 const DEFAULT_MIN_WORKERS: usize = 3;
@@ -32,6 +38,12 @@ const DEFAULT_MIN_IDLE_WORKERS: usize = 1;
 pub struct WorkerThread {
     pub state: WorkerState,
     pub join_handle: thread::JoinHandle<()>,
+
+    /// When the worker entered its current state.
+    pub since: Instant,
+
+    /// Tells the worker to exit once it's between sessions.
+    pub retire: Arc<AtomicBool>,
 }
 
 pub struct Server {
@@ -44,18 +56,18 @@ pub struct Server {
     worker_id_gen: u64,
     to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
     to_parent_rx: mpsc::Receiver<WorkerStateEvent>,
-    min_workers: usize,
-    max_workers: usize,
 
     sig_tracker: SignalTracker,
 
-    /// Minimum number of idle workers.  Note we don't support
-    /// max_idle_workers at this time -- it would require adding an
-    /// additional mpsc channel for every thread to deliver the shutdown
-    /// request to individual threads.  Hardly seems worth it -- maybe.
-    /// For comparision, the OSRF C code has no min/max idle support
-    /// either.
-    min_idle_workers: usize,
+    /// Decides when to grow and shrink our pool of workers.
+    autoscaler: Autoscaler,
+
+    /// Requests waiting on our service queue as of the last check.
+    backlog: usize,
+
+    spawned_count: u64,
+    exited_count: u64,
+    retired_idle_count: u64,
 }
 
 impl Server {
@@ -67,19 +79,6 @@ impl Server {
 
         let client = init::osrf_init(&options)?;
 
-        let min_workers = HostSettings::get(&format!("apps/{service}/unix_config/min_children"))?
-            .as_usize()
-            .unwrap_or(DEFAULT_MIN_WORKERS);
-
-        let min_idle_workers =
-            HostSettings::get(&format!("apps/{service}/unix_config/min_spare_children"))?
-                .as_usize()
-                .unwrap_or(DEFAULT_MIN_IDLE_WORKERS);
-
-        let max_workers = HostSettings::get(&format!("apps/{service}/unix_config/max_children"))?
-            .as_usize()
-            .unwrap_or(DEFAULT_MAX_WORKERS);
-
         // We have a single to-parent channel whose trasmitter is cloned
         // per thread.  Communication from worker threads to the parent
         // are synchronous so the parent always knows exactly how many
@@ -94,9 +93,15 @@ impl Server {
         let mut server = Server {
             client,
             application,
-            min_workers,
-            max_workers,
-            min_idle_workers,
+            autoscaler: Autoscaler::new(ScalingPolicy::new(
+                DEFAULT_MIN_WORKERS,
+                DEFAULT_MAX_WORKERS,
+                DEFAULT_MIN_IDLE_WORKERS,
+            )),
+            backlog: 0,
+            spawned_count: 0,
+            exited_count: 0,
+            retired_idle_count: 0,
             methods: None,
             worker_id_gen: 0,
             to_parent_tx: tx,
@@ -105,9 +110,82 @@ impl Server {
             sig_tracker: SignalTracker::new(),
         };
 
+        server.apply_host_settings()?;
+        server.start_metrics_listener()?;
         server.listen()
     }
 
+    /// Read our worker counts from the host settings.
+    fn apply_host_settings(&mut self) -> EgResult<()> {
+        let service = self.service().to_string();
+
+        let min_workers = HostSettings::get(&format!("apps/{service}/unix_config/min_children"))?
+            .as_usize()
+            .unwrap_or(DEFAULT_MIN_WORKERS);
+
+        let min_idle_workers =
+            HostSettings::get(&format!("apps/{service}/unix_config/min_spare_children"))?
+                .as_usize()
+                .unwrap_or(DEFAULT_MIN_IDLE_WORKERS);
+
+        let max_workers = HostSettings::get(&format!("apps/{service}/unix_config/max_children"))?
+            .as_usize()
+            .unwrap_or(DEFAULT_MAX_WORKERS);
+
+        // Seconds a worker may sit idle before it's retired.
+        // 0 means idle workers are never retired.
+        let idle_timeout = HostSettings::get(&format!("apps/{service}/unix_config/idle_timeout"))?
+            .as_usize()
+            .unwrap_or(0);
+
+        let mut policy = ScalingPolicy::new(min_workers, max_workers, min_idle_workers);
+
+        if idle_timeout > 0 {
+            policy.set_idle_timeout(Some(Duration::from_secs(idle_timeout as u64)));
+        }
+
+        self.autoscaler.set_policy(policy);
+
+        Ok(())
+    }
+
+    /// Serve GET /metrics if the service has a metrics_port.
+    fn start_metrics_listener(&self) -> EgResult<()> {
+        let service = self.service();
+
+        let port = match HostSettings::get(&format!("apps/{service}/unix_config/metrics_port"))?
+            .as_u16()
+        {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let address = HostSettings::get(&format!("apps/{service}/unix_config/metrics_address"))?
+            .as_str()
+            .unwrap_or("127.0.0.1")
+            .to_string();
+
+        metrics::spawn_listener(&address, port)
+    }
+
+    /// Re-fetch the host settings in response to a SIGHUP.
+    ///
+    /// Worker threads see the new values on their next lookup.  Values
+    /// workers read once at startup, e.g. max_requests, apply to
+    /// workers started after the reload.
+    fn reload_host_settings(&mut self) {
+        log::info!("server: reloading host settings");
+
+        if let Err(e) = HostSettings::refresh(&self.client) {
+            log::error!("server: cannot reload host settings; using old settings: {e}");
+            return;
+        }
+
+        if let Err(e) = self.apply_host_settings() {
+            log::error!("server: cannot apply reloaded host settings: {e}");
+        }
+    }
+
     fn app(&self) -> &Box<dyn app::Application> {
         &self.application
     }
@@ -125,13 +203,107 @@ impl Server {
         self.worker_id_gen
     }
 
-    fn spawn_threads(&mut self) {
+    /// Spawn and retire workers to suit the current request backlog.
+    fn autoscale(&mut self) {
         if self.sig_tracker.any_shutdown_requested() {
             return;
         }
-        while self.workers.len() < self.min_workers {
+
+        self.backlog = self.service_backlog();
+
+        let summaries: Vec<WorkerSummary> = self
+            .workers
+            .iter()
+            .map(|(id, w)| WorkerSummary {
+                worker_id: *id,
+                state: w.state,
+                since: w.since,
+                retiring: w.retire.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        let plan = self
+            .autoscaler
+            .plan(&summaries, self.backlog, Instant::now());
+
+        if plan.spawn > 0 {
+            log::debug!(
+                "server: spawning {} workers; backlog={}",
+                plan.spawn,
+                self.backlog
+            );
+        }
+
+        for _ in 0..plan.spawn {
             self.spawn_one_thread();
         }
+
+        for worker_id in plan.retire.iter() {
+            if let Some(worker) = self.workers.get(worker_id) {
+                log::debug!("server: retiring idle worker {worker_id}");
+                worker.retire.store(true, Ordering::Relaxed);
+                self.retired_idle_count += 1;
+            }
+        }
+
+        if self.backlog > 0 && self.workers.len() >= self.autoscaler.policy().max_workers() {
+            log::warn!(
+                "server: reached max workers with {} requests waiting",
+                self.backlog
+            );
+        }
+
+        self.publish_counts();
+    }
+
+    /// Number of requests waiting on our service queue.
+    fn service_backlog(&mut self) -> usize {
+        let addr = BusAddress::for_service(
+            self.client.address().username(),
+            self.client.address().domain(),
+            self.service(),
+        );
+
+        let addr = addr.as_str().to_string();
+
+        match self.client.singleton().borrow_mut().bus_mut().llen(&addr) {
+            Ok(n) => n.max(0) as usize,
+            Err(e) => {
+                log::error!("server: cannot read request backlog: {e}");
+                0
+            }
+        }
+    }
+
+    /// Make our worker counts available to the stats API.
+    fn publish_counts(&self) {
+        let policy = self.autoscaler.policy();
+
+        WorkerCounts {
+            workers: self.workers.len(),
+            idle: self.idle_thread_count(),
+            active: self.active_thread_count(),
+            retiring: self
+                .workers
+                .values()
+                .filter(|w| w.retire.load(Ordering::Relaxed))
+                .count(),
+            backlog: self.backlog,
+            spawned: self.spawned_count,
+            exited: self.exited_count,
+            retired_idle: self.retired_idle_count,
+            min_workers: policy.min_workers(),
+            max_workers: policy.max_workers(),
+            spare_target: policy.spare_target(),
+        }
+        .publish();
+
+        OsrfMetrics::get().server_pool(
+            self.service(),
+            self.idle_thread_count(),
+            self.active_thread_count(),
+            self.backlog,
+        );
     }
 
     fn spawn_one_thread(&mut self) {
@@ -141,6 +313,8 @@ impl Server {
         let service = self.service().to_string();
         let factory = self.app().worker_factory();
         let sig_tracker = self.sig_tracker.clone();
+        let retire = Arc::new(AtomicBool::new(false));
+        let worker_retire = retire.clone();
 
         log::trace!("server: spawning a new worker {worker_id}");
 
@@ -152,14 +326,19 @@ impl Server {
                 worker_id,
                 methods,
                 to_parent_tx,
+                worker_retire,
             );
         });
 
+        self.spawned_count += 1;
+
         self.workers.insert(
             worker_id,
             WorkerThread {
                 state: WorkerState::Idle,
                 join_handle: handle,
+                since: Instant::now(),
+                retire,
             },
         );
     }
@@ -171,10 +350,20 @@ impl Server {
         worker_id: u64,
         methods: Arc<HashMap<String, method::MethodDef>>,
         to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+        retire: Arc<AtomicBool>,
     ) {
         log::trace!("Creating new worker {worker_id}");
 
-        let mut worker = match Worker::new(service, worker_id, sig_tracker, methods, to_parent_tx) {
+        let worker = Worker::new(
+            service,
+            worker_id,
+            sig_tracker,
+            methods,
+            to_parent_tx,
+            retire,
+        );
+
+        let mut worker = match worker {
             Ok(w) => w,
             Err(e) => {
                 log::error!("Cannot create worker: {e}. Exiting.");
@@ -252,29 +441,12 @@ impl Server {
             hash.insert(m.name().to_string(), m);
         }
         self.add_system_methods(&mut hash);
-        //self.add_atomic_methods(&mut hash);
+        // Atomic variants are not registered.  Workers map them to
+        // their root methods at dispatch time.
         self.methods = Some(Arc::new(hash));
         Ok(())
     }
 
-    // currently unused
-    /*
-    fn add_atomic_methods(&self, hash: &mut HashMap<String, method::MethodDef>) {
-        let mut atomic_hash: HashMap<String, method::MethodDef> = HashMap::new();
-
-        for method in hash.values() {
-            let mut atomic_method = method.clone();
-            let name = method.name();
-            let atomic_name = format!("{name}.atomic");
-            atomic_method.set_atomic(true);
-            atomic_method.set_name(&atomic_name);
-            atomic_hash.insert(atomic_name, atomic_method);
-        }
-
-        hash.extend(atomic_hash);
-    }
-    */
-
     fn add_system_methods(&self, hash: &mut HashMap<String, method::MethodDef>) {
         let name = "opensrf.system.echo";
         let mut method = method::MethodDef::new(name, method::ParamCount::Any, system_method_echo);
@@ -298,10 +470,64 @@ impl Server {
             name: String::from("prefix"),
             datatype: method::ParamDataType::String,
             desc: Some(String::from("API name prefix filter")),
+            default: None,
+        });
+
+        hash.insert(name.to_string(), method);
+
+        let name = "opensrf.system.method";
+        let mut method = method::MethodDef::new(
+            name,
+            method::ParamCount::Exactly(1),
+            system_method_introspect,
+        );
+        method.set_desc("List published API definitions whose name contains the filter");
+
+        method.add_param(method::Param {
+            name: String::from("filter"),
+            datatype: method::ParamDataType::String,
+            desc: Some(String::from("API name substring filter")),
+            default: None,
         });
 
         hash.insert(name.to_string(), method);
 
+        let name = format!("{}.stats", self.service());
+        let mut method =
+            method::MethodDef::new(&name, method::ParamCount::Zero, system_method_stats);
+        method.set_desc("Call counts and timing for each method handled by this process");
+        hash.insert(name, method);
+
+        let name = format!("{}.stats.workers", self.service());
+        let mut method =
+            method::MethodDef::new(&name, method::ParamCount::Zero, system_method_worker_stats);
+        method.set_desc("Worker pool counts and request backlog for this process");
+        hash.insert(name, method);
+
+        let name = format!("{}.stats.clear", self.service());
+        let mut method =
+            method::MethodDef::new(&name, method::ParamCount::Zero, system_method_stats);
+        method.set_desc("Discard method call stats collected by this process");
+        hash.insert(name, method);
+
+        let name = format!("{}.settings.reload", self.service());
+        let mut method = method::MethodDef::new(
+            &name,
+            method::ParamCount::Zero,
+            system_method_settings_reload,
+        );
+        method.set_desc("Re-fetch host settings from opensrf.settings.  Private domain only");
+        hash.insert(name, method);
+
+        let name = format!("{}.cache.clear", self.service());
+        let mut method =
+            method::MethodDef::new(&name, method::ParamCount::Zero, system_method_cache_clear);
+        method.set_desc(
+            "Clear worker caches for this process.  Responds with the new cache generation.  \
+            Private domain only",
+        );
+        hash.insert(name, method);
+
         let name = "opensrf.system.method.all.summary";
         let mut method = method::MethodDef::new(
             name,
@@ -314,6 +540,7 @@ impl Server {
             name: String::from("prefix"),
             datatype: method::ParamDataType::String,
             desc: Some(String::from("API name prefix filter")),
+            default: None,
         });
 
         hash.insert(name.to_string(), method);
@@ -323,7 +550,7 @@ impl Server {
         self.service_init()?;
         self.register_methods()?;
         self.register_routers()?;
-        self.spawn_threads();
+        self.autoscale();
         self.sig_tracker.track_graceful_shutdown();
         self.sig_tracker.track_fast_shutdown();
         self.sig_tracker.track_reload();
@@ -354,10 +581,15 @@ impl Server {
                 break;
             }
 
+            if self.sig_tracker.reload_requested() {
+                self.sig_tracker.handle_reload_requested();
+                self.reload_host_settings();
+                work_performed = true;
+            }
+
             if !work_performed {
-                // Only perform idle worker maintenance if no other
-                // tasks were performed during this loop iter.
-                self.perform_idle_worker_maint();
+                // Worker events trigger their own scaling checks.
+                self.autoscale();
             }
         }
 
@@ -367,21 +599,6 @@ impl Server {
         Ok(())
     }
 
-    /// Add additional idle workers if needed.
-    ///
-    /// Spawn at most one worker per maintenance cycle.
-    fn perform_idle_worker_maint(&mut self) {
-        let idle_workers = self.idle_thread_count();
-
-        if self.min_idle_workers > 0
-            && self.workers.len() < self.max_workers
-            && idle_workers < self.min_idle_workers
-        {
-            self.spawn_one_thread();
-            log::debug!("Sawned idle worker; idle={idle_workers}");
-        }
-    }
-
     fn shutdown(&mut self) {
         let timer = util::Timer::new(SHUTDOWN_MAX_WAIT);
         let duration = Duration::from_secs(1);
@@ -436,8 +653,10 @@ impl Server {
 
     fn remove_thread(&mut self, worker_id: &u64) {
         log::trace!("server: removing thread {}", worker_id);
-        self.workers.remove(worker_id);
-        self.spawn_threads();
+        if self.workers.remove(worker_id).is_some() {
+            self.exited_count += 1;
+        }
+        self.autoscale();
     }
 
     /// Set the state of our thread worker based on the state reported
@@ -461,6 +680,8 @@ impl Server {
         } else {
             log::trace!("server: updating thread state: {:?}", worker_id);
             worker.state = evt.state();
+            worker.since = Instant::now();
+            self.autoscale();
         }
 
         let idle = self.idle_thread_count();
@@ -472,14 +693,6 @@ impl Server {
             return;
         }
 
-        if idle == 0 {
-            if active < self.max_workers {
-                self.spawn_one_thread();
-            } else {
-                log::warn!("server: reached max workers!");
-            }
-        }
-
         if idle < IDLE_THREAD_WARN_THRESHOLD {
             log::warn!(
                 "server: idle thread count={} is below warning threshold={}",
@@ -535,38 +748,147 @@ fn system_method_time(
     }
 }
 
+/// Stats are collected per process, so each call reports on
+/// whichever server process handles the request.
+fn system_method_stats(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    if method
+        .method()
+        .trim_end_matches(".atomic")
+        .ends_with(".clear")
+    {
+        method::MethodStats::clear();
+        return session.respond_complete(true);
+    }
+
+    let mut stats = EgValue::new_object();
+    for (name, method_stats) in method::MethodStats::collect() {
+        stats[&name] = method_stats.to_eg_value();
+    }
+
+    session.respond_complete(stats)
+}
+
+/// Worker counts are published by the server process which handles
+/// the request.
+fn system_method_worker_stats(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    _method: &message::MethodCall,
+) -> EgResult<()> {
+    session.respond_complete(WorkerCounts::current().to_eg_value())
+}
+
+/// Only clients connected to our own (private) domain may call
+/// administrative methods.  Requests relayed from public gateways
+/// arrive from public domains.
+fn require_private_domain(
+    session: &session::ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let domain = conf::config().client().domain().name();
+    let sender_domain = session.sender().domain();
+
+    if sender_domain != domain {
+        return Err(format!(
+            "{} is not permitted from domain {sender_domain}",
+            method.method()
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Tell all workers in this process to clear their caches.
+///
+/// Each worker, including the one handling this request, clears its
+/// cache once it's between sessions.  Responds with the new cache
+/// generation number.
+fn system_method_cache_clear(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    require_private_domain(session, method)?;
+
+    session.respond_complete(app::bump_cache_generation())
+}
+
+/// Reload the host settings for this process.
+///
+/// Responds with the new settings generation number.
+fn system_method_settings_reload(
+    _worker: &mut Box<dyn app::ApplicationWorker>,
+    session: &mut session::ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    require_private_domain(session, method)?;
+
+    HostSettings::refresh(session.client())?;
+
+    session.respond_complete(HostSettings::generation())
+}
+
 fn system_method_introspect(
     worker: &mut Box<dyn app::ApplicationWorker>,
     session: &mut session::ServerSession,
     method: &message::MethodCall,
 ) -> EgResult<()> {
-    let prefix = match method.params().get(0) {
-        Some(p) => p.as_str(),
-        None => None,
-    };
-
-    // Collect the names first so we can sort them
-    let mut names: Vec<&str> = match prefix {
-        // If a prefix string is provided, only return methods whose
-        // name starts with the provided prefix.
-        Some(pfx) => worker
-            .methods()
-            .keys()
-            .filter(|n| n.starts_with(pfx))
-            .map(|n| n.as_str())
-            .collect(),
-        None => worker.methods().keys().map(|n| n.as_str()).collect(),
-    };
+    let filter = method.params().first().and_then(|p| p.as_str());
+
+    // opensrf.system.method matches on any part of the API name.
+    let substring = method.method().trim_end_matches(".atomic") == "opensrf.system.method";
+    let summary = method.method().contains("summary");
+
+    // Every method may also be called as an atomic request, so
+    // report the atomic flavor of each method alongside the original.
+    let mut names: Vec<(String, &str)> = Vec::new();
+    for name in worker.methods().keys() {
+        names.push((name.to_string(), name));
+        names.push((format!("{name}.atomic"), name));
+    }
+
+    if let Some(f) = filter {
+        names.retain(|(n, _)| {
+            if substring {
+                n.contains(f)
+            } else {
+                n.starts_with(f)
+            }
+        });
+    }
 
     names.sort();
 
-    for name in names {
-        if let Some(meth) = worker.methods().get(name) {
-            if method.method().contains("summary") {
+    for (name, root_name) in names {
+        let meth = match worker.methods().get(root_name) {
+            Some(m) => m,
+            None => continue,
+        };
+
+        if name == root_name {
+            if summary {
                 session.respond(meth.to_summary_string())?;
             } else {
                 session.respond(meth.to_eg_value())?;
             }
+            continue;
+        }
+
+        let mut atomic = meth.clone();
+        atomic.set_name(&name);
+
+        if summary {
+            session.respond(atomic.to_summary_string())?;
+        } else {
+            let mut value = atomic.to_eg_value();
+            // Atomic responses arrive as a single array.
+            value["stream"] = EgValue::from(false);
+            session.respond(value)?;
         }
     }
 
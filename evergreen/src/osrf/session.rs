@@ -9,14 +9,16 @@ use crate::osrf::message::MethodCall;
 use crate::osrf::message::Payload;
 use crate::osrf::message::Status;
 use crate::osrf::message::TransportMessage;
+use crate::osrf::metrics::OsrfMetrics;
 use crate::osrf::params::ApiParams;
 use crate::util;
-use crate::{EgResult, EgValue};
+use crate::{EgError, EgResult, EgValue};
 use std::cell::RefCell;
 use std::cell::RefMut;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 const CONNECT_TIMEOUT: i32 = 10;
 pub const DEFAULT_REQUEST_TIMEOUT: i32 = 60;
@@ -101,19 +103,116 @@ impl Request {
     /// This still waits for all responses to arrive so the request can
     /// be marked as complete and no responses are left lingering on the
     /// message bus.
+    ///
+    /// Returns an EgError::Timeout if no data arrives within the
+    /// timeout while waiting for the request to complete.  A negative
+    /// timeout waits indefinitely.
     pub fn first_with_timeout(&mut self, timeout: i32) -> EgResult<Option<EgValue>> {
         let mut resp: Option<EgValue> = None;
         while !self.complete {
-            if let Some(r) = self.recv_with_timeout(timeout)? {
-                if resp.is_none() {
-                    resp = Some(r);
-                } // else discard the non-first response.
+            match self.recv_with_timeout(timeout)? {
+                Some(r) => {
+                    if resp.is_none() {
+                        resp = Some(r);
+                    } // else discard the non-first response.
+                }
+                None => {
+                    if !self.complete && timeout >= 0 {
+                        return Err(EgError::Timeout(format!(
+                            "Request {} timed out after {timeout} seconds",
+                            self.thread_trace
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(resp)
+    }
+
+    /// Returns the first response, waiting at most `timeout` for the
+    /// request to complete.
+    ///
+    /// On timeout, the request is cancelled and an EgError::Timeout
+    /// is returned.
+    pub fn first_timeout(&mut self, timeout: Duration) -> EgResult<Option<EgValue>> {
+        let deadline = Instant::now() + timeout;
+        let mut resp: Option<EgValue> = None;
+
+        while !self.complete {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match self.recv_timeout(remaining) {
+                Ok(Some(r)) => {
+                    if resp.is_none() {
+                        resp = Some(r);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    if e.is_timeout() {
+                        self.cancel()?;
+                    }
+                    return Err(e);
+                }
             }
         }
 
         Ok(resp)
     }
 
+    /// Receive the next response, waiting at most `timeout`.
+    ///
+    /// Returns Ok(None) once the request is complete and all of its
+    /// responses have been read.  Returns an EgError::Timeout if no
+    /// response arrives in time.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> EgResult<Option<EgValue>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.exhausted() {
+                return Ok(None);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() && !self.complete {
+                return Err(EgError::Timeout(format!(
+                    "Request {} timed out after {:.3} seconds",
+                    self.thread_trace,
+                    timeout.as_secs_f64()
+                )));
+            }
+
+            // Bus timeouts are measured in whole seconds.
+            let secs = remaining.as_secs_f64().ceil() as i32;
+
+            if let Some(r) = self.recv_with_timeout(secs)? {
+                return Ok(Some(r));
+            }
+
+            // Loop around in case a status message (e.g. CONTINUE or
+            // COMPLETE) woke us up before any data arrived.
+            if self.complete {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Stop waiting on this request.
+    ///
+    /// Tells the remote worker we are going away and discards any
+    /// responses we have already received.  Responses arriving for
+    /// this request after cancellation are ignored.
+    pub fn cancel(&mut self) -> EgResult<()> {
+        if self.exhausted() {
+            return Ok(());
+        }
+
+        self.complete = true;
+        self.session.borrow_mut().cancel(self.thread_trace)
+    }
+
     /// Receive the next response to this Request
     ///
     /// timeout:
@@ -156,6 +255,63 @@ impl Request {
     }
 }
 
+/// Responses to an atomic request, sent as a single array once the
+/// request completes.
+#[derive(Debug)]
+struct AtomicQueue {
+    values: Vec<EgValue>,
+    /// Bytes of JSON queued so far.
+    size: usize,
+    /// 0 means no limit.
+    max_size: usize,
+    /// Set once the queue exceeds its max size.  Queued values are
+    /// discarded and further values are refused.
+    overflowed: bool,
+}
+
+impl AtomicQueue {
+    fn new(max_size: usize) -> Self {
+        AtomicQueue {
+            values: Vec::new(),
+            size: 0,
+            max_size,
+            overflowed: false,
+        }
+    }
+
+    fn push(&mut self, value: EgValue) -> EgResult<()> {
+        if self.overflowed {
+            return Err("atomic response size limit already exceeded".into());
+        }
+
+        if self.max_size > 0 {
+            self.size += value.dump().len();
+
+            if self.size > self.max_size {
+                self.overflowed = true;
+                self.values = Vec::new();
+                return Err(format!(
+                    "atomic response exceeds the limit of {} bytes",
+                    self.max_size
+                )
+                .into());
+            }
+        }
+
+        self.values.push(value);
+
+        Ok(())
+    }
+
+    fn into_values(self) -> EgResult<Vec<EgValue>> {
+        if self.overflowed {
+            Err("atomic response size limit exceeded".into())
+        } else {
+            Ok(self.values)
+        }
+    }
+}
+
 /// Client communication state maintenance.
 struct ClientSessionInternal {
     /// Client so we can ask it to pull data from the Bus for us.
@@ -195,6 +351,12 @@ struct ClientSessionInternal {
 
     /// Staging ground for "partial" messages arriving in chunks.
     partial_buffer: Option<String>,
+
+    /// Requests whose replies should be discarded on arrival.
+    cancelled: Vec<usize>,
+
+    /// When each outstanding request was sent, for latency metrics.
+    sent_at: HashMap<usize, Instant>,
 }
 
 impl fmt::Display for ClientSessionInternal {
@@ -205,8 +367,15 @@ impl fmt::Display for ClientSessionInternal {
 
 impl ClientSessionInternal {
     fn new(client: Client, service: &str) -> ClientSessionInternal {
-        let router_addr =
-            BusAddress::for_router(conf::config().client().router_name(), client.domain());
+        // Websocket clients have no OpenSRF config and their messages
+        // are routed by the translator.
+        let router_name = if client.is_websocket() {
+            "router"
+        } else {
+            conf::config().client().router_name()
+        };
+
+        let router_addr = BusAddress::for_router(router_name, client.domain());
 
         let service_addr = BusAddress::for_bare_service(service);
 
@@ -219,6 +388,8 @@ impl ClientSessionInternal {
             connected: false,
             last_thread_trace: 0,
             partial_buffer: None,
+            cancelled: Vec::new(),
+            sent_at: HashMap::new(),
             backlog: VecDeque::new(),
             thread: util::random_number(16),
         }
@@ -241,6 +412,7 @@ impl ClientSessionInternal {
         self.worker_addr = None;
         self.connected = false;
         self.backlog.clear();
+        self.sent_at.clear();
     }
 
     fn router_addr(&self) -> &BusAddress {
@@ -321,6 +493,10 @@ impl ClientSessionInternal {
 
             // Toss the messages onto our backlog as we receive them.
             for msg in tmsg.body_mut().drain(..) {
+                if self.cancelled.contains(&msg.thread_trace()) {
+                    log::debug!("{self} discarding reply to cancelled request");
+                    continue;
+                }
                 self.backlog.push_back(msg);
             }
 
@@ -392,6 +568,8 @@ impl ClientSessionInternal {
                 log::trace!("Partial message is now complete");
             }
 
+            OsrfMetrics::get().response_received(self.service());
+
             return Ok(Some(Response {
                 value: Some(value),
                 complete: false,
@@ -433,12 +611,23 @@ impl ClientSessionInternal {
             }
             MessageStatus::Complete => {
                 log::trace!("{self} request {trace} complete");
+                if let Some(sent) = self.sent_at.remove(&trace) {
+                    OsrfMetrics::get().request_complete(self.service(), sent.elapsed());
+                }
                 Ok(Some(Response {
                     value: None,
                     complete: true,
                     partial: false,
                 }))
             }
+            MessageStatus::ServiceUnavailable => {
+                // The service, or a websocket translator, is too busy
+                // to take the request.  The caller may try again.
+                self.reset();
+                Err(EgError::Transport(format!(
+                    "{self} request {trace} rejected: {statmsg}"
+                )))
+            }
             _ => {
                 self.reset();
                 return Err(format!("{self} request {trace} failed: {}", statmsg).into());
@@ -466,38 +655,53 @@ impl ClientSessionInternal {
             self.worker_addr = None;
         }
 
-        let tmsg = TransportMessage::with_body(
+        let mut msg = Message::new(
+            MessageType::Request,
+            trace,
+            Payload::Method(MethodCall::new(method, params)),
+        );
+
+        msg.set_accept_compression(self.client.accept_compression());
+
+        if let Some(locale) = self.client.locale() {
+            msg.set_locale(&locale);
+        }
+
+        if let Some(tz) = self.client.timezone() {
+            msg.set_timezone(&tz);
+        }
+
+        let mut tmsg = TransportMessage::with_body(
             self.destination_addr().as_str(),
             self.client.address().as_str(),
             self.thread(),
-            Message::new(
-                MessageType::Request,
-                trace,
-                Payload::Method(MethodCall::new(method, params)),
-            ),
+            msg,
         );
 
+        tmsg.set_presence(self.client_internal_mut().announce_presence());
+
         if !self.connected() {
             // Top-level API calls always go through the router on
             // our primary domain
 
             let router_addr = self.router_addr().as_str();
             self.client_internal_mut()
-                .bus_mut()
-                .send_to(tmsg, router_addr)?;
+                .send_to_router(tmsg, router_addr)?;
         } else {
             if let Some(a) = self.worker_addr() {
                 // Requests directly to client addresses must be routed
                 // to the domain of the client address.
                 self.client_internal_mut()
-                    .get_domain_bus(a.domain())?
-                    .send(tmsg)?;
+                    .send_to_domain(tmsg, a.domain())?;
             } else {
                 self.reset();
                 return Err(format!("We are connected, but have no worker_addr()").into());
             }
         }
 
+        OsrfMetrics::get().request_sent(self.service());
+        self.sent_at.insert(trace, Instant::now());
+
         Ok(trace)
     }
 
@@ -524,11 +728,8 @@ impl ClientSessionInternal {
         );
 
         // Connect calls always go to our router.
-        self.client
-            .singleton()
-            .borrow_mut()
-            .bus_mut()
-            .send_to(tm, self.router_addr().as_str())?;
+        self.client_internal_mut()
+            .send_to_router(tm, self.router_addr().as_str())?;
 
         self.recv(trace, CONNECT_TIMEOUT)?;
 
@@ -541,6 +742,44 @@ impl ClientSessionInternal {
         }
     }
 
+    /// Abandon a request.
+    ///
+    /// Replies already received are dropped and any which arrive
+    /// later are discarded.  If we know which worker is handling the
+    /// request, it's sent a DISCONNECT so it can stop streaming
+    /// responses once its handler yields.
+    fn cancel(&mut self, thread_trace: usize) -> EgResult<()> {
+        log::info!("{self} cancelling request {thread_trace}");
+
+        self.backlog.retain(|m| m.thread_trace() != thread_trace);
+        self.partial_buffer = None;
+        self.cancelled.push(thread_trace);
+        self.sent_at.remove(&thread_trace);
+
+        let dest_addr = match self.worker_addr() {
+            Some(a) => a.clone(),
+            None => return Ok(()),
+        };
+
+        let trace = self.incr_thread_trace();
+
+        let tmsg = TransportMessage::with_body(
+            dest_addr.as_str(),
+            self.client.address().as_str(),
+            self.thread(),
+            Message::new(MessageType::Disconnect, trace, Payload::NoPayload),
+        );
+
+        self.client_internal_mut()
+            .send_to_domain(tmsg, dest_addr.domain())?;
+
+        // Any stateful conversation is now over.
+        self.worker_addr = None;
+        self.connected = false;
+
+        Ok(())
+    }
+
     /// Send a DISCONNECT to our remote worker.
     ///
     /// Does not wait for any response.  NO-OP if not connected.
@@ -564,8 +803,7 @@ impl ClientSessionInternal {
         );
 
         self.client_internal_mut()
-            .get_domain_bus(dest_addr.domain())?
-            .send(tmsg)?;
+            .send_to_domain(tmsg, dest_addr.domain())?;
 
         self.reset();
 
@@ -717,6 +955,24 @@ impl MultiSession {
         Ok(None)
     }
 
+    /// Cancel all outstanding requests.
+    ///
+    /// Every request is cancelled, even if cancelling an earlier one
+    /// fails.  Returns the first error, if any.
+    pub fn cancel(&mut self) -> EgResult<()> {
+        let mut result = Ok(());
+
+        for mut req in self.requests.drain(..) {
+            if let Err(e) = req.cancel() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
     fn remove_completed(&mut self) {
         // We consider a request to be complete only when it has
         // received a COMPLETE messsage and its backlog has been
@@ -758,7 +1014,34 @@ pub struct ServerSession {
     last_thread_trace: usize,
 
     /// Responses collected to be packed into an "atomic" response array.
-    atomic_resp_queue: Option<Vec<EgValue>>,
+    atomic_resp_queue: Option<AtomicQueue>,
+
+    /// Atomic responses may total at most this many bytes of JSON.
+    /// 0 means no limit.
+    max_atomic_size: usize,
+
+    /// Responses whose JSON exceeds this many bytes are sent as a
+    /// series of partial messages.  0 means no chunking.
+    max_chunk_size: usize,
+
+    /// Compress results whose JSON is at least this many bytes.
+    /// Only set when the caller accepts compressed results.
+    compress_threshold: Option<usize>,
+
+    /// User object for the caller, verified before the handler is
+    /// called for methods which require authentication.
+    requestor: Option<EgValue>,
+
+    /// True if the caller keeps a presence key on the bus, which we
+    /// check before sending each reply.
+    sender_presence: bool,
+
+    /// Set once we find our caller has gone away.
+    abandoned: bool,
+
+    /// Expire the caller's queue this many seconds after each reply,
+    /// so replies nobody reads don't linger.  0 means no expiry.
+    reply_ttl: u64,
 }
 
 impl fmt::Display for ServerSession {
@@ -783,13 +1066,75 @@ impl ServerSession {
             responded_complete: false,
             thread: thread.to_string(),
             atomic_resp_queue: None,
+            max_atomic_size: 0,
+            max_chunk_size: 0,
+            compress_threshold: None,
+            requestor: None,
+            sender_presence: false,
+            abandoned: false,
+            reply_ttl: 0,
         }
     }
 
+    pub fn max_chunk_size(&self) -> usize {
+        self.max_chunk_size
+    }
+
+    pub fn set_max_chunk_size(&mut self, size: usize) {
+        self.max_chunk_size = size;
+    }
+
+    pub fn compress_threshold(&self) -> Option<usize> {
+        self.compress_threshold
+    }
+
+    pub fn max_atomic_size(&self) -> usize {
+        self.max_atomic_size
+    }
+
+    pub fn set_max_atomic_size(&mut self, size: usize) {
+        self.max_atomic_size = size;
+    }
+
+    pub fn set_compress_threshold(&mut self, threshold: Option<usize>) {
+        self.compress_threshold = threshold;
+    }
+
     pub fn last_thread_trace(&self) -> usize {
         self.last_thread_trace
     }
 
+    pub fn sender_presence(&self) -> bool {
+        self.sender_presence
+    }
+
+    pub fn set_sender_presence(&mut self, presence: bool) {
+        self.sender_presence = presence;
+    }
+
+    /// True if our caller went away while we were responding.
+    pub fn abandoned(&self) -> bool {
+        self.abandoned
+    }
+
+    pub fn reply_ttl(&self) -> u64 {
+        self.reply_ttl
+    }
+
+    pub fn set_reply_ttl(&mut self, ttl: u64) {
+        self.reply_ttl = ttl;
+    }
+
+    /// Authenticated user for the current request, if the method
+    /// requires authentication.
+    pub fn requestor(&self) -> Option<&EgValue> {
+        self.requestor.as_ref()
+    }
+
+    pub fn set_requestor(&mut self, requestor: Option<EgValue>) {
+        self.requestor = requestor;
+    }
+
     pub fn set_last_thread_trace(&mut self, trace: usize) {
         self.last_thread_trace = trace
     }
@@ -810,9 +1155,26 @@ impl ServerSession {
         &self.sender
     }
 
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
     pub fn new_atomic_resp_queue(&mut self) {
         log::debug!("{self} starting new atomic queue...");
-        self.atomic_resp_queue = Some(Vec::new());
+        self.atomic_resp_queue = Some(AtomicQueue::new(self.max_atomic_size));
+    }
+
+    /// Discard any atomic queue left by a previous request.
+    pub fn clear_atomic_resp_queue(&mut self) {
+        self.atomic_resp_queue = None;
+    }
+
+    /// True if the current request is atomic, i.e. all responses are
+    /// sent as a single array when the request completes.
+    ///
+    /// The array is chunked as a whole if it exceeds the max chunk size.
+    pub fn atomic(&self) -> bool {
+        self.atomic_resp_queue.is_some()
     }
 
     /// Mutable Ref to our under-the-covers client singleton.
@@ -834,21 +1196,19 @@ impl ServerSession {
     ) -> EgResult<Option<Message>> {
         let result_value;
 
-        if self.atomic_resp_queue.is_some() {
+        if let Some(q) = self.atomic_resp_queue.as_mut() {
             // Add the reply to the queue.
-            let q = self.atomic_resp_queue.as_mut().unwrap();
-
             if let Some(res) = result.take() {
-                q.push(res);
+                q.push(res)
+                    .map_err(|e| format!("{} atomic response failed: {e}", self.service))?;
             }
 
             if complete {
                 // If we're completing the call and we have an atomic
                 // response queue, return the entire contents of the
-                // queue to the caller and leave the queue cleared
-                // [take() above].
+                // queue to the caller and leave the queue cleared.
 
-                result_value = self.atomic_resp_queue.take().unwrap().into();
+                result_value = self.atomic_resp_queue.take().unwrap().into_values()?.into();
             } else {
                 // Nothing left to do since this atmoic request
                 // is still producing results.
@@ -863,16 +1223,24 @@ impl ServerSession {
             }
         }
 
-        Ok(Some(Message::new(
+        Ok(Some(self.result_message(message::Result::new(
+            MessageStatus::Ok,
+            "OK",
+            "osrfResult",
+            result_value,
+        ))))
+    }
+
+    /// Wrap a Result in a Message, compressed as needed.
+    fn result_message(&self, result: message::Result) -> Message {
+        let mut msg = Message::new(
             MessageType::Result,
             self.last_thread_trace(),
-            Payload::Result(message::Result::new(
-                MessageStatus::Ok,
-                "OK",
-                "osrfResult",
-                result_value,
-            )),
-        )))
+            Payload::Result(result),
+        );
+
+        msg.set_compress_threshold(self.compress_threshold);
+        msg
     }
 
     /// Respond with a value and/or a complete message.
@@ -920,16 +1288,109 @@ impl ServerSession {
         );
 
         if let Some(msg) = result_msg.take() {
-            tmsg.body_mut().push(msg);
+            match self.result_chunks(&msg) {
+                None => tmsg.body_mut().push(msg),
+                Some(chunks) => {
+                    self.send_partial_chunks(chunks)?;
+                    // The finalizer message completes the response.
+                    tmsg.body_mut()
+                        .push(self.result_message(message::Result::new(
+                            MessageStatus::PartialComplete,
+                            "partial response finalizer",
+                            "osrfResultPartialComplete",
+                            EgValue::from(""),
+                        )));
+                }
+            }
         }
 
         if let Some(msg) = complete_msg.take() {
             tmsg.body_mut().push(msg);
         }
 
-        self.client_internal_mut()
-            .get_domain_bus(self.sender.domain())?
-            .send(tmsg)
+        self.send_reply(tmsg)
+    }
+
+    /// Send a message to our caller, unless our caller has gone away.
+    ///
+    /// Returns an Err if the caller has gone away.
+    pub fn send_reply(&mut self, tmsg: TransportMessage) -> EgResult<()> {
+        if !self.abandoned && self.sender_presence {
+            let present = self
+                .client_internal_mut()
+                .get_domain_bus(self.sender.domain())?
+                .recipient_present(self.sender.as_str())?;
+
+            self.abandoned = !present;
+        }
+
+        if self.abandoned {
+            return Err(format!("{self} caller {} has gone away", self.sender).into());
+        }
+
+        let ttl = self.reply_ttl;
+        let mut client = self.client_internal_mut();
+        let bus = client.get_domain_bus(self.sender.domain())?;
+
+        if ttl > 0 {
+            bus.send_expiring(tmsg, ttl)
+        } else {
+            bus.send(tmsg)
+        }
+    }
+
+    /// Returns the JSON form of a result message's content split into
+    /// chunks no larger than our max chunk size, or None if the
+    /// message is small enough to send whole.
+    fn result_chunks(&self, msg: &Message) -> Option<Vec<String>> {
+        if self.max_chunk_size == 0 {
+            return None;
+        }
+
+        let content = match msg.payload() {
+            Payload::Result(r) => r.content(),
+            _ => return None,
+        };
+
+        let json = content.clone().into_json_value().dump();
+
+        if json.len() <= self.max_chunk_size {
+            return None;
+        }
+
+        Some(
+            split_json_chunks(&json, self.max_chunk_size)
+                .into_iter()
+                .map(|c| c.to_string())
+                .collect(),
+        )
+    }
+
+    /// Send each chunk of a large response in its own partial
+    /// response message.  Clients concatenate the chunks and parse
+    /// the result once the partial response finalizer arrives.
+    fn send_partial_chunks(&mut self, chunks: Vec<String>) -> EgResult<()> {
+        log::debug!("{self} sending response in {} chunks", chunks.len());
+
+        for chunk in chunks {
+            let msg = self.result_message(message::Result::new(
+                MessageStatus::Partial,
+                "partial response",
+                "osrfResultPartial",
+                EgValue::from(chunk),
+            ));
+
+            let tmsg = TransportMessage::with_body(
+                self.sender.as_str(),
+                self.client.address().as_str(),
+                self.thread(),
+                msg,
+            );
+
+            self.send_reply(tmsg)?;
+        }
+
+        Ok(())
     }
 
     pub fn send_complete(&mut self) -> EgResult<()> {
@@ -944,3 +1405,77 @@ impl ServerSession {
         self.respond_with_parts(Some(value.into()), true)
     }
 }
+
+/// Split a JSON string into chunks of at most `size` bytes, without
+/// splitting any multi-byte characters.
+fn split_json_chunks(json: &str, size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = json;
+
+    while !rest.is_empty() {
+        let mut end = size.min(rest.len());
+
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if end == 0 {
+            // Size is smaller than a single character.
+            end = rest
+                .chars()
+                .next()
+                .map(|c| c.len_utf8())
+                .unwrap_or(rest.len());
+        }
+
+        let (chunk, remainder) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+
+    chunks
+}
+
+#[test]
+fn split_json_chunks_round_trip() {
+    let mut list = EgValue::new_array();
+    for i in 0..200_000 {
+        list.push(EgValue::from(format!("item {i} \u{00e9}\u{1F600}")))
+            .expect("Is Array");
+    }
+
+    let json = list.clone().into_json_value().dump();
+    assert!(json.len() > 2_000_000);
+
+    let chunks = split_json_chunks(&json, 65536);
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|c| c.len() <= 65536));
+
+    let joined: String = chunks.concat();
+    let value = EgValue::from_json_value(json::parse(&joined).unwrap()).unwrap();
+
+    assert_eq!(value.len(), 200_000);
+    assert_eq!(
+        value[199_999].as_str(),
+        Some("item 199999 \u{00e9}\u{1F600}")
+    );
+
+    // Tiny chunk sizes never split a character.
+    assert_eq!(split_json_chunks("\u{00e9}a", 1), vec!["\u{00e9}", "a"]);
+}
+
+#[test]
+fn atomic_queue_limit() {
+    let mut queue = AtomicQueue::new(0);
+    queue.push(EgValue::from("one")).unwrap();
+    queue.push(EgValue::from(2)).unwrap();
+    assert_eq!(queue.into_values().unwrap().len(), 2);
+
+    // "\"one\"" is 5 bytes of JSON.
+    let mut queue = AtomicQueue::new(8);
+    queue.push(EgValue::from("one")).unwrap();
+    assert!(queue.push(EgValue::from("two")).is_err());
+    assert!(queue.values.is_empty());
+    assert!(queue.push(EgValue::from(3)).is_err());
+    assert!(queue.into_values().is_err());
+}
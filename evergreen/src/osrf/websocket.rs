@@ -0,0 +1,398 @@
+//! OpenSRF client transport via the websocket translator.
+//!
+//! Speaks the translator envelope instead of talking to the bus
+//! directly:
+//!
+//! ```text
+//! {"thread": "...", "service": "...", "log_xid": "...", "osrf_msg": [...]}
+//! ```
+//!
+//! The translator tracks which worker is handling each thread, so
+//! any number of sessions may share one websocket connection as
+//! long as each uses its own thread.
+use crate::osrf::addr::BusAddress;
+use crate::osrf::logging::Logger;
+use crate::osrf::message::{Message, MessageStatus, MessageType, Payload, TransportMessage};
+use crate::result::EgError;
+use crate::util;
+use crate::EgResult;
+use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite as ws;
+use ws::protocol::{Message as WsMessage, WebSocket};
+
+/// Domain used for the synthetic bus addresses applied to messages
+/// sent and received via the websocket translator.
+pub const WEBSOCKET_DOMAIN: &str = "websocket";
+
+#[derive(Debug, Clone, Default)]
+pub struct WebsocketOptions {
+    /// Skip TLS certificate and hostname verification.
+    ///
+    /// For development servers with self-signed certificates only.
+    accept_invalid_certs: bool,
+
+    /// Ask the translator to return IDL objects in this format,
+    /// e.g. "hash".
+    format: Option<String>,
+
+    /// Parse responses without the IDL.
+    ///
+    /// Useful for tools which do not load the IDL.
+    raw_data_mode: bool,
+}
+
+impl WebsocketOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn accept_invalid_certs(&self) -> bool {
+        self.accept_invalid_certs
+    }
+    pub fn set_accept_invalid_certs(&mut self, accept: bool) {
+        self.accept_invalid_certs = accept;
+    }
+
+    pub fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+    pub fn set_format(&mut self, format: &str) {
+        self.format = Some(format.to_string());
+    }
+
+    pub fn raw_data_mode(&self) -> bool {
+        self.raw_data_mode
+    }
+    pub fn set_raw_data_mode(&mut self, on: bool) {
+        self.raw_data_mode = on;
+    }
+}
+
+/// Plain or TLS TCP stream.
+enum Stream {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>),
+}
+
+impl Stream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(s) => s,
+            Stream::Tls(s) => s.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// What we know about a thread with messages in flight.
+struct ThreadState {
+    service: String,
+    /// Stateful threads last until DISCONNECT.  Others end when the
+    /// request completes.
+    connected: bool,
+}
+
+/// Websocket connection to an OpenSRF websocket translator.
+pub struct WebsocketTransport {
+    uri: String,
+    socket: WebSocket<Stream>,
+
+    /// Stands in for the bus address of a bus-connected client.
+    address: BusAddress,
+
+    /// Threads we have sent messages for, so replies, which only
+    /// include the thread, can be addressed.
+    threads: HashMap<String, ThreadState>,
+
+    options: WebsocketOptions,
+}
+
+impl fmt::Display for WebsocketTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Websocket({})", self.uri)
+    }
+}
+
+impl WebsocketTransport {
+    /// Connect to a ws:// or wss:// translator URI.
+    pub fn connect(uri: &str, options: &WebsocketOptions) -> EgResult<Self> {
+        let url = url::Url::parse(uri).map_err(|e| format!("Invalid websocket URI {uri}: {e}"))?;
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("Websocket URI has no host: {uri}"))?;
+
+        let tls = match url.scheme() {
+            "ws" => false,
+            "wss" => true,
+            s => return Err(format!("Unsupported websocket scheme: {s}").into()),
+        };
+
+        let port = url.port().unwrap_or(if tls { 443 } else { 80 });
+
+        let tcp = TcpStream::connect((host, port))
+            .map_err(|e| EgError::Transport(format!("Cannot connect to {uri}: {e}")))?;
+
+        let stream = if tls {
+            Stream::Tls(WebsocketTransport::tls_connect(host, tcp, options)?)
+        } else {
+            Stream::Plain(tcp)
+        };
+
+        let (socket, _) = ws::client::client(uri, stream)
+            .map_err(|e| EgError::Transport(format!("Websocket handshake failed: {e}")))?;
+
+        log::debug!("Connected to websocket {uri}");
+
+        Ok(WebsocketTransport {
+            uri: uri.to_string(),
+            socket,
+            address: BusAddress::for_client("websocket", WEBSOCKET_DOMAIN),
+            threads: HashMap::new(),
+            options: options.clone(),
+        })
+    }
+
+    fn tls_connect(
+        host: &str,
+        tcp: TcpStream,
+        options: &WebsocketOptions,
+    ) -> EgResult<SslStream<TcpStream>> {
+        let mut builder = SslConnector::builder(SslMethod::tls())
+            .map_err(|e| format!("Cannot create TLS connector: {e}"))?;
+
+        if options.accept_invalid_certs() {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+
+        let config = builder
+            .build()
+            .configure()
+            .map_err(|e| format!("Cannot configure TLS connector: {e}"))?
+            .verify_hostname(!options.accept_invalid_certs());
+
+        config
+            .connect(host, tcp)
+            .map_err(|e| EgError::Transport(format!("TLS connect to {host} failed: {e}")))
+    }
+
+    pub fn address(&self) -> &BusAddress {
+        &self.address
+    }
+
+    pub fn options(&self) -> &WebsocketOptions {
+        &self.options
+    }
+
+    /// Wrap the transport message in a translator envelope and send it.
+    ///
+    /// The service is taken from the recipient address, or from an
+    /// earlier message on the same thread.
+    pub fn send(&mut self, mut tmsg: TransportMessage) -> EgResult<()> {
+        let service = BusAddress::from_str(tmsg.to())?
+            .service()
+            .map(|s| s.to_string())
+            .or_else(|| self.threads.get(tmsg.thread()).map(|t| t.service.clone()))
+            .ok_or_else(|| format!("{self} no service for thread {}", tmsg.thread()))?;
+
+        let log_xid = if tmsg.osrf_xid().is_empty() {
+            Logger::get_log_trace()
+        } else {
+            tmsg.osrf_xid().to_string()
+        };
+
+        let mut connected = self
+            .threads
+            .get(tmsg.thread())
+            .map(|t| t.connected)
+            .unwrap_or(false);
+
+        let mut disconnect = false;
+        let mut body: Vec<json::JsonValue> = Vec::new();
+
+        for msg in tmsg.body_mut().drain(..) {
+            match msg.mtype() {
+                MessageType::Connect => connected = true,
+                MessageType::Disconnect => disconnect = true,
+                _ => {}
+            }
+            body.push(msg.into_json_value());
+        }
+
+        let mut envelope = json::object! {
+            thread: tmsg.thread(),
+            service: service.as_str(),
+            log_xid: log_xid,
+            osrf_msg: body,
+        };
+
+        if let Some(format) = self.options.format() {
+            envelope["format"] = json::from(format);
+        }
+
+        let text = envelope.dump();
+
+        log::trace!("{self} sending: {text}");
+
+        if disconnect {
+            self.threads.remove(tmsg.thread());
+        } else {
+            let state = ThreadState { service, connected };
+            self.threads.insert(tmsg.thread().to_string(), state);
+        }
+
+        self.socket
+            .write_message(WsMessage::text(text))
+            .map_err(|e| EgError::Transport(format!("{self} send failed: {e}")))
+    }
+
+    /// Returns at most one transport message built from a translator
+    /// reply.
+    ///
+    /// * `timeout` - Time in seconds to wait for a reply.
+    ///   A negative value means to block indefinitely.
+    ///   0 means do not block.
+    pub fn recv(&mut self, timeout: i32) -> EgResult<Option<TransportMessage>> {
+        let timer = util::Timer::new(timeout);
+
+        loop {
+            let wait = if timeout < 0 {
+                None
+            } else {
+                // A zero read timeout means block forever, so poll
+                // briefly instead.
+                Some(
+                    Duration::from_secs(timer.remaining().max(0) as u64)
+                        .max(Duration::from_millis(1)),
+                )
+            };
+
+            self.socket
+                .get_ref()
+                .tcp()
+                .set_read_timeout(wait)
+                .map_err(|e| EgError::Transport(format!("{self} cannot set timeout: {e}")))?;
+
+            match self.socket.read_message() {
+                Ok(WsMessage::Text(text)) => return self.unpack_reply(&text),
+                Ok(WsMessage::Close(_)) => {
+                    return Err(EgError::Transport(format!("{self} closed by server")));
+                }
+                // Pings are answered by tungstenite.
+                Ok(_) => {}
+                Err(ws::Error::Io(e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(EgError::Transport(format!("{self} recv failed: {e}"))),
+            }
+
+            if timeout >= 0 && timer.done() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn unpack_reply(&mut self, text: &str) -> EgResult<Option<TransportMessage>> {
+        log::trace!("{self} received: {text}");
+
+        let mut reply = json::parse(text).map_err(|e| format!("{self} invalid reply: {e}"))?;
+
+        let thread = reply["thread"]
+            .as_str()
+            .ok_or_else(|| format!("{self} reply has no thread: {text}"))?
+            .to_string();
+
+        if reply["transport_error"].as_bool().unwrap_or(false) {
+            log::warn!("{self} translator reported a transport error on thread {thread}");
+        }
+
+        let (service, connected) = match self.threads.get(&thread) {
+            Some(t) => (t.service.as_str(), t.connected),
+            None => ("_", false),
+        };
+
+        // Replies appear to come from the service, since the worker
+        // address is known only to the translator.
+        let from = BusAddress::for_service("_", WEBSOCKET_DOMAIN, service);
+
+        let mut tmsg = TransportMessage::new(self.address.as_str(), from.as_str(), &thread);
+
+        if let Some(xid) = reply["oxrf_xid"].as_str() {
+            tmsg.set_osrf_xid(xid);
+        }
+
+        let raw_data_mode = self.options.raw_data_mode();
+        let mut complete = false;
+
+        for msg in reply["osrf_msg"].members_mut() {
+            match Message::from_json_value(msg.take(), raw_data_mode) {
+                Ok(m) => {
+                    if let Payload::Status(s) = m.payload() {
+                        complete |= *s.status() == MessageStatus::Complete;
+                    }
+                    tmsg.body_mut().push(m);
+                }
+                Err(e) => log::error!("{self} discarding invalid message: {e}"),
+            }
+        }
+
+        if complete && !connected {
+            self.threads.remove(&thread);
+        }
+
+        Ok(Some(tmsg))
+    }
+
+    /// Close the websocket.
+    pub fn close(&mut self) {
+        self.socket.close(None).ok();
+    }
+}
+
+#[test]
+fn websocket_options() {
+    let mut options = WebsocketOptions::new();
+    assert!(!options.accept_invalid_certs());
+    assert!(options.format().is_none());
+
+    options.set_accept_invalid_certs(true);
+    options.set_format("hash");
+
+    assert!(options.accept_invalid_certs());
+    assert_eq!(options.format(), Some("hash"));
+
+    let err = WebsocketTransport::connect("http://localhost:7682", &options)
+        .err()
+        .expect("http:// is not a websocket URI");
+
+    assert!(err.to_string().contains("Unsupported websocket scheme"));
+}
@@ -1,3 +1,4 @@
+use crate::editor::Editor;
 use crate::osrf::addr::BusAddress;
 use crate::osrf::app;
 use crate::osrf::client::{Client, ClientSingleton};
@@ -11,6 +12,7 @@ use crate::osrf::message::Payload;
 use crate::osrf::message::TransportMessage;
 use crate::osrf::method;
 use crate::osrf::method::ParamCount;
+use crate::osrf::metrics::OsrfMetrics;
 use crate::osrf::sclient::HostSettings;
 use crate::osrf::session::ServerSession;
 use crate::util;
@@ -19,6 +21,7 @@ use mptc::signals::SignalTracker;
 use std::cell::RefMut;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
@@ -27,6 +30,12 @@ use std::time;
 // How often each worker wakes to check for shutdown signals, etc.
 const IDLE_WAKE_TIME: i32 = 5;
 
+/// Default seconds before replies nobody has read expire.
+const DEFAULT_REPLY_TTL: usize = 300;
+
+/// Default max bytes of JSON buffered for an atomic response.
+const DEFAULT_MAX_ATOMIC_SIZE: usize = 64 * 1024 * 1024;
+
 /// Each worker thread is in one of these states.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum WorkerState {
@@ -76,6 +85,29 @@ pub struct Worker {
 
     /// Channel for sending worker state info to our parent.
     to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+
+    /// Responses whose JSON exceeds this many bytes are sent as a
+    /// series of partial messages.  0 means no chunking.
+    max_chunk_size: usize,
+
+    /// Results whose JSON is at least this many bytes are compressed
+    /// for callers which accept compression.  0 means no compression.
+    compress_threshold: usize,
+
+    /// Replies nobody reads expire after this many seconds.
+    /// 0 means replies never expire.
+    reply_ttl: usize,
+
+    /// Atomic requests whose responses total more than this many
+    /// bytes of JSON fail.  0 means no limit.
+    max_atomic_size: usize,
+
+    /// Cache generation last seen by our application worker.
+    cache_generation: usize,
+
+    /// Set by our parent when we should exit once we're between
+    /// sessions, e.g. because we've been idle for too long.
+    retire: Arc<AtomicBool>,
 }
 
 impl fmt::Display for Worker {
@@ -91,6 +123,7 @@ impl Worker {
         sig_tracker: SignalTracker,
         methods: Arc<HashMap<String, method::MethodDef>>,
         to_parent_tx: mpsc::SyncSender<WorkerStateEvent>,
+        retire: Arc<AtomicBool>,
     ) -> EgResult<Worker> {
         let client = Client::connect()?;
 
@@ -103,6 +136,12 @@ impl Worker {
             to_parent_tx,
             session: None,
             connected: false,
+            max_chunk_size: 0,
+            compress_threshold: 0,
+            reply_ttl: DEFAULT_REPLY_TTL,
+            max_atomic_size: DEFAULT_MAX_ATOMIC_SIZE,
+            cache_generation: app::cache_generation(),
+            retire,
         })
     }
 
@@ -150,6 +189,33 @@ impl Worker {
                 .as_usize()
                 .unwrap_or(5);
 
+        self.max_chunk_size =
+            HostSettings::get(&format!("apps/{}/unix_config/max_chunk_size", self.service))
+                .expect("Host Settings Not Retrieved")
+                .as_usize()
+                .unwrap_or(0);
+
+        self.compress_threshold = HostSettings::get(&format!(
+            "apps/{}/unix_config/compress_threshold",
+            self.service
+        ))
+        .expect("Host Settings Not Retrieved")
+        .as_usize()
+        .unwrap_or(0);
+
+        self.reply_ttl = HostSettings::get(&format!("apps/{}/unix_config/reply_ttl", self.service))
+            .expect("Host Settings Not Retrieved")
+            .as_usize()
+            .unwrap_or(DEFAULT_REPLY_TTL);
+
+        self.max_atomic_size = HostSettings::get(&format!(
+            "apps/{}/unix_config/max_atomic_size",
+            self.service
+        ))
+        .expect("Host Settings Not Retrieved")
+        .as_usize()
+        .unwrap_or(DEFAULT_MAX_ATOMIC_SIZE);
+
         let mut requests: usize = 0;
 
         // We listen for API calls at an addressed scoped to our
@@ -239,6 +305,14 @@ impl Worker {
                 }
             }
 
+            // Clear worker caches if requested since we last checked.
+            // We're between sessions here, so no request sees a
+            // partially cleared cache.
+            if let Err(e) = app::sync_worker_cache(&mut appworker, &mut self.cache_generation) {
+                log::error!("cache_clear() returned an error: {e}");
+                break;
+            }
+
             // Did we get a shutdown signal?  Check this after
             // "end_session()" so we don't interrupt a conversation to
             // shutdown.
@@ -246,6 +320,11 @@ impl Worker {
                 log::info!("{selfstr} received a stop signal");
                 break;
             }
+
+            if self.retire.load(Ordering::Relaxed) {
+                log::debug!("{selfstr} retiring at the request of the server");
+                break;
+            }
         }
 
         log::debug!("{self} exiting listen loop and cleaning up");
@@ -357,8 +436,13 @@ impl Worker {
         mut tmsg: message::TransportMessage,
         appworker: &mut Box<dyn app::ApplicationWorker>,
     ) -> EgResult<()> {
-        // Always adopt the log trace of an inbound API call.
-        Logger::set_log_trace(tmsg.osrf_xid());
+        // Always adopt the log trace of an inbound API call.  Older
+        // clients may not send one.
+        if tmsg.osrf_xid().is_empty() {
+            Logger::mk_log_trace();
+        } else {
+            Logger::set_log_trace(tmsg.osrf_xid());
+        }
 
         if self.session.is_none() || self.session().thread().ne(tmsg.thread()) {
             log::trace!("server: creating new server session for {}", tmsg.thread());
@@ -370,8 +454,15 @@ impl Worker {
                 0, // thread trace -- updated later as needed
                 BusAddress::from_str(tmsg.from())?,
             ));
+
+            let reply_ttl = self.reply_ttl as u64;
+            let max_atomic_size = self.max_atomic_size;
+            self.session_mut().set_reply_ttl(reply_ttl);
+            self.session_mut().set_max_atomic_size(max_atomic_size);
         }
 
+        self.session_mut().set_sender_presence(tmsg.presence());
+
         for msg in tmsg.body_mut().drain(..) {
             self.handle_message(msg, appworker)?;
         }
@@ -435,9 +526,7 @@ impl Worker {
             ),
         );
 
-        self.client_internal_mut()
-            .get_domain_bus(self.session().sender().domain())?
-            .send(tmsg)
+        self.session_mut().send_reply(tmsg)
     }
 
     fn handle_request(
@@ -445,6 +534,17 @@ impl Worker {
         mut msg: message::Message,
         appworker: &mut Box<dyn app::ApplicationWorker>,
     ) -> EgResult<()> {
+        // Compress large results only for callers which told us they
+        // can read them.
+        let compress_threshold = if msg.accepts_compression() && self.compress_threshold > 0 {
+            Some(self.compress_threshold)
+        } else {
+            None
+        };
+
+        self.session_mut()
+            .set_compress_threshold(compress_threshold);
+
         let method_call = match msg.payload_mut() {
             message::Payload::Method(m) => m,
             _ => return self.reply_bad_request("Request sent without a MethoCall payload"),
@@ -466,6 +566,9 @@ impl Worker {
             self.client.clear()?;
         }
 
+        // Each request in a connected session starts out non-atomic.
+        self.session_mut().clear_atomic_resp_queue();
+
         // Clone the method since we have mutable borrows below.  Note
         // this is the method definition, not the param-laden request.
         let mut method_def = self.methods.get(api_name).map(|m| m.clone());
@@ -474,9 +577,8 @@ impl Worker {
             // Atomic methods are not registered/published in advance
             // since every method has an atomic variant.
             // Find the root method and use it.
-            if api_name.ends_with(".atomic") {
-                let meth = api_name.replace(".atomic", "");
-                if let Some(m) = self.methods.get(&meth) {
+            if let Some(meth) = api_name.strip_suffix(".atomic") {
+                if let Some(m) = self.methods.get(meth) {
                     method_def = Some(m.clone());
 
                     // Creating a new queue tells our session to treat
@@ -489,6 +591,8 @@ impl Worker {
         if method_def.is_none() {
             log::warn!("Method not found: {}", api_name);
 
+            OsrfMetrics::get().server_request(&self.service, "not_found", None);
+
             return self.reply_with_status(
                 MessageStatus::MethodNotFound,
                 &format!("Method not found: {}", api_name),
@@ -501,40 +605,84 @@ impl Worker {
         // Make sure the number of params sent by the caller matches the
         // parameter count for the method.
         if !ParamCount::matches(&pcount, param_count as u8) {
+            OsrfMetrics::get().server_request(&self.service, "bad_request", None);
             return self.reply_bad_request(&format!(
                 "Invalid param count sent: method={} sent={} needed={}",
                 api_name, param_count, &pcount,
             ));
         }
 
+        // Fill in declared defaults for omitted optional parameters
+        // so handlers don't have to.
+        method_def.apply_param_defaults(method_call.params_mut());
+
         // Verify paramter types are correct, at least superficially.
         // Do this after deserialization.
-        if let Some(param_defs) = method_def.params() {
-            for (idx, param_def) in param_defs.iter().enumerate() {
-                // There may be more param defs than parameters if
-                // some param are optional.
-                if let Some(param_val) = method_call.params().get(idx) {
-                    if idx >= pcount.minimum().into() && param_val.is_null() {
-                        // NULL placeholders for non-required parameters are
-                        // allowed.
-                        continue;
-                    }
-                    if !param_def.datatype.matches(param_val) {
-                        return self.reply_bad_request(&format!(
-                            "Invalid paramter type: wanted={} got={}",
-                            param_def.datatype,
-                            param_val.clone().dump()
-                        ));
-                    }
-                } else {
-                    // More defs than actual params. Verification complete.
-                    break;
-                }
+        if let Err(e) = method_def.check_param_types(method_call.params()) {
+            OsrfMetrics::get().server_request(&self.service, "bad_request", None);
+            return self.reply_bad_request(&e.to_string());
+        }
+
+        let chunk_size = method_def.max_chunk_size().unwrap_or(self.max_chunk_size);
+        self.session_mut().set_max_chunk_size(chunk_size);
+
+        self.session_mut().set_requestor(None);
+
+        if method_def.requires_auth() {
+            let mut editor = Editor::new(&self.client);
+
+            if !method_def.authorize(&mut editor, method_call.params())? {
+                log::info!(
+                    "{self} {} not authorized: {}",
+                    method_call.method(),
+                    editor.event()
+                );
+                return self.session_mut().respond_complete(editor.event());
             }
+
+            self.session_mut()
+                .set_requestor(editor.requestor().cloned());
         }
 
         // Call the API
-        if let Err(err) = (method_def.handler())(appworker, self.session_mut(), &method_call) {
+        let start = time::Instant::now();
+        let result = (method_def.handler())(appworker, self.session_mut(), method_call);
+
+        // Errors caused by the caller going away are not method errors.
+        let abandoned = self.session().abandoned();
+
+        let outcome = if abandoned {
+            "abandoned"
+        } else if result.is_err() {
+            "error"
+        } else {
+            "ok"
+        };
+
+        OsrfMetrics::get().server_request(&self.service, outcome, Some(start.elapsed()));
+
+        method::MethodStats::record(
+            method_def.name(),
+            start.elapsed(),
+            result
+                .as_ref()
+                .err()
+                .filter(|_| !abandoned)
+                .map(|e| e.to_string()),
+        );
+
+        if abandoned {
+            log::info!(
+                "{self} caller went away; abandoned {}",
+                method_call.method()
+            );
+            method::MethodStats::record_abandoned(method_def.name());
+            appworker.request_abandoned(method_call.method());
+            self.connected = false;
+            return Ok(());
+        }
+
+        if let Err(err) = result {
             let msg = format!("{self} method {} failed with {err}", method_call.method());
             log::error!("{msg}");
             appworker.api_call_error(&method_call, err);
@@ -569,9 +717,7 @@ impl Worker {
             msg,
         );
 
-        self.client_internal_mut()
-            .get_domain_bus(self.session().sender().domain())?
-            .send(tmsg)
+        self.session_mut().send_reply(tmsg)
     }
 
     fn reply_bad_request(&mut self, text: &str) -> EgResult<()> {
@@ -594,9 +740,7 @@ impl Worker {
             msg,
         );
 
-        self.client_internal_mut()
-            .get_domain_bus(self.session().sender().domain())?
-            .send(tmsg)
+        self.session_mut().send_reply(tmsg)
     }
 
     /// Notify the parent process of this worker's active state.
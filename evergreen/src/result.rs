@@ -58,6 +58,17 @@ pub enum EgError {
     /// fatal error strings.
     Debug(String),
     Event(EgEvent),
+
+    /// Communication with the message bus failed.
+    ///
+    /// Unlike other errors, the same request may succeed after
+    /// reconnecting.
+    Transport(String),
+
+    /// No response arrived before the caller's deadline.
+    ///
+    /// Distinct from a request which completed with no results.
+    Timeout(String),
 }
 
 impl std::error::Error for EgError {
@@ -69,6 +80,47 @@ impl std::error::Error for EgError {
 }
 
 impl EgError {
+    /// True if this error resulted from a message bus failure.
+    ///
+    /// ```
+    /// use evergreen::result::EgError;
+    ///
+    /// assert!(EgError::Transport("Bus connect error".to_string()).is_transport());
+    /// assert!(!EgError::Debug("Oops".to_string()).is_transport());
+    /// ```
+    pub fn is_transport(&self) -> bool {
+        matches!(self, EgError::Transport(_))
+    }
+
+    /// True if this error resulted from a request timing out.
+    ///
+    /// ```
+    /// use evergreen::result::EgError;
+    ///
+    /// assert!(EgError::Timeout("No response".to_string()).is_timeout());
+    /// assert!(!EgError::Debug("Oops".to_string()).is_timeout());
+    /// ```
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, EgError::Timeout(_))
+    }
+
+    /// The event which caused this error, if any.
+    ///
+    /// ```
+    /// use evergreen::result::EgError;
+    /// use evergreen::event::EgEvent;
+    ///
+    /// let err: EgError = EgEvent::new("ACTOR_USER_NOT_FOUND").into();
+    /// assert_eq!(err.event().map(|e| e.textcode()), Some("ACTOR_USER_NOT_FOUND"));
+    /// assert!(EgError::Transport("Bus connect error".to_string()).event().is_none());
+    /// ```
+    pub fn event(&self) -> Option<&EgEvent> {
+        match self {
+            EgError::Event(e) => Some(e),
+            _ => None,
+        }
+    }
+
     /// Coerce the EgError into an EgEvent regardless of its internal
     /// type.
     ///
@@ -78,7 +130,7 @@ impl EgError {
     pub fn event_or_default(&self) -> EgEvent {
         match self {
             EgError::Event(e) => e.clone(),
-            EgError::Debug(s) => {
+            EgError::Debug(s) | EgError::Transport(s) | EgError::Timeout(s) => {
                 let mut evt = EgEvent::new("INTERNAL_SERVER_ERROR");
                 // This is for debug purposes only -- i18n not needed.
                 evt.set_desc(&format!("Server Error: {s}"));
@@ -91,7 +143,7 @@ impl EgError {
 impl fmt::Display for EgError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
-            Self::Debug(ref m) => write!(f, "{m}"),
+            Self::Debug(ref m) | Self::Transport(ref m) | Self::Timeout(ref m) => write!(f, "{m}"),
             Self::Event(ref e) => write!(f, "{e}"),
         }
     }
@@ -116,7 +168,7 @@ impl From<&str> for EgError {
 impl From<EgError> for String {
     fn from(err: EgError) -> Self {
         match err {
-            EgError::Debug(m) => m.to_string(),
+            EgError::Debug(m) | EgError::Transport(m) | EgError::Timeout(m) => m.to_string(),
             EgError::Event(e) => e.to_string(),
         }
     }
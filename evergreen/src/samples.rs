@@ -1,6 +1,7 @@
 //! Evergreen sample data and tools
 use crate as eg;
 use eg::constants as C;
+use eg::date;
 use eg::Editor;
 use eg::EgResult;
 use eg::EgValue;
@@ -26,6 +27,16 @@ pub const AU_IDENT_TYPE: i64 = 3; // Other
 
 pub const AU_STAFF_ID: i64 = 195; // br1mclark
 
+pub const ACA_TYPE_CHECKOUT: i64 = 1; // Normal checkout
+pub const ACA_TYPE_CHECKIN: i64 = 2; // Normal checkin
+
+/// Prefix for barcodes, labels, etc. of sample data.
+pub const SAMPLE_PREFIX: &str = "_EG_TEST_";
+
+pub const CIRC_RECURRING_FINE: f64 = 0.10;
+pub const CIRC_MAX_FINE: f64 = 1.00;
+pub const CIRC_FINE_INTERVAL: &str = "1 day";
+
 pub struct SampleData {
     pub acn_creator: i64,
     pub acn_record: i64,
@@ -37,6 +48,10 @@ pub struct SampleData {
     pub au_barcode: String,
     pub au_profile: i64,
     pub au_ident_type: i64,
+
+    /// Appended to derived barcodes and labels so concurrent test
+    /// runs do not collide.
+    pub suffix: String,
 }
 
 impl SampleData {
@@ -52,6 +67,35 @@ impl SampleData {
             au_barcode: AU_BARCODE.to_string(),
             au_profile: AU_PROFILE,
             au_ident_type: AU_IDENT_TYPE,
+            suffix: String::new(),
+        }
+    }
+
+    /// Sample data whose barcodes and labels end with the provided
+    /// suffix.
+    ///
+    /// ```
+    /// let samples = evergreen::samples::SampleData::with_suffix("A");
+    /// assert_eq!(samples.acp_barcode, "_EG_TEST_A");
+    /// assert_eq!(samples.derive("FINES"), "_EG_TEST_FINES_A");
+    /// ```
+    pub fn with_suffix(suffix: &str) -> SampleData {
+        let mut samples = SampleData::new();
+
+        samples.suffix = suffix.to_string();
+        samples.acn_label = format!("{SAMPLE_PREFIX}{suffix}");
+        samples.acp_barcode = format!("{SAMPLE_PREFIX}{suffix}");
+        samples.au_barcode = format!("{SAMPLE_PREFIX}{suffix}");
+
+        samples
+    }
+
+    /// Derive a barcode, label, etc. from our prefix and suffix.
+    pub fn derive(&self, name: &str) -> String {
+        if self.suffix.is_empty() {
+            format!("{SAMPLE_PREFIX}{name}")
+        } else {
+            format!("{SAMPLE_PREFIX}{name}_{}", self.suffix)
         }
     }
 
@@ -71,31 +115,67 @@ impl SampleData {
     }
 
     pub fn create_default_acp(&self, e: &mut Editor, acn_id: i64) -> EgResult<EgValue> {
-        let mut acp = eg::hash! {
+        let barcode = self.acp_barcode.to_string();
+        let mut acps = self.create_acps(e, acn_id, &[barcode])?;
+
+        acps.pop()
+            .ok_or_else(|| "Cannot create default copy".into())
+    }
+
+    /// Create one copy per barcode, using the default copy values,
+    /// in a single batch.
+    ///
+    /// Returns the new copies in barcode order.
+    pub fn create_acps(
+        &self,
+        e: &mut Editor,
+        acn_id: i64,
+        barcodes: &[String],
+    ) -> EgResult<Vec<EgValue>> {
+        let mut acps = Vec::new();
+
+        for barcode in barcodes {
+            acps.push(self.acp_value(acn_id, barcode, ACP_STATUS)?);
+        }
+
+        e.create_batch(acps)
+    }
+
+    /// Create a copy with the provided barcode and copy status.
+    pub fn create_acp_with_barcode(
+        &self,
+        e: &mut Editor,
+        acn_id: i64,
+        barcode: &str,
+        status: i64,
+    ) -> EgResult<EgValue> {
+        let acp = self.acp_value(acn_id, barcode, status)?;
+        e.create(acp)
+    }
+
+    fn acp_value(&self, acn_id: i64, barcode: &str, status: i64) -> EgResult<EgValue> {
+        let acp = eg::hash! {
             call_number: acn_id,
             creator: self.acn_creator,
             editor: self.acn_creator,
-            status: ACP_STATUS,
+            status: status,
             circ_lib: self.aou_id,
             loan_duration: ACP_LOAN_DURATION,
             fine_level: ACP_FINE_LEVEL,
-            barcode: self.acp_barcode.to_string(),
+            barcode: barcode,
         };
 
-        acp.bless("acp")?;
-
-        e.create(acp)
+        EgValue::create("acp", acp)
     }
 
+    /// Delete all non-deleted call numbers with our default label.
     pub fn delete_default_acn(&self, e: &mut Editor) -> EgResult<()> {
-        let mut acns = e.search(
+        let acns = e.search(
             "acn",
             eg::hash! {label: self.acn_label.to_string(), deleted: "f"},
         )?;
 
-        if let Some(acn) = acns.pop() {
-            e.delete(acn)?;
-        }
+        e.delete_batch(acns)?;
 
         Ok(())
     }
@@ -109,10 +189,185 @@ impl SampleData {
         .ok_or_else(|| format!("Cannot find default copy").into())
     }
 
+    /// Returns the non-deleted copy with the provided barcode, if any.
+    pub fn get_acp(&self, e: &mut Editor, barcode: &str) -> EgResult<Option<EgValue>> {
+        Ok(e.search("acp", eg::hash! {barcode: barcode, deleted: "f"})?
+            .pop())
+    }
+
     pub fn delete_default_acp(&self, e: &mut Editor) -> EgResult<()> {
-        if let Ok(acp) = self.get_default_acp(e) {
-            e.delete(acp)?;
+        self.delete_acps(e, &[self.acp_barcode.to_string()])
+    }
+
+    /// Delete all non-deleted copies with the provided barcodes in a
+    /// single batch.
+    pub fn delete_acps(&self, e: &mut Editor, barcodes: &[String]) -> EgResult<()> {
+        let query = eg::hash! {
+            barcode: EgValue::from(barcodes.to_vec()),
+            deleted: "f",
+        };
+
+        let acps = e.search("acp", query)?;
+
+        e.delete_batch(acps)?;
+
+        Ok(())
+    }
+
+    /// Create an open circulation whose due date is now plus the
+    /// provided interval, e.g. "7 days".
+    ///
+    /// Use a negative interval, e.g. "-3 days", for an overdue circ.
+    /// Circ rules are set to "unlimited" and the copy status is left
+    /// unchanged.
+    pub fn create_circ(
+        &self,
+        e: &mut Editor,
+        copy_id: i64,
+        user_id: i64,
+        duration: &str,
+    ) -> EgResult<EgValue> {
+        let due_date = date::add_interval(date::now(), duration)?;
+
+        let circ = eg::hash! {
+            target_copy: copy_id,
+            usr: user_id,
+            circ_lib: self.aou_id,
+            circ_staff: AU_STAFF_ID,
+            duration: duration,
+            due_date: date::to_iso(&due_date),
+            duration_rule: C::CIRC_POLICY_UNLIMITED,
+            recurring_fine: CIRC_RECURRING_FINE,
+            recurring_fine_rule: C::CIRC_POLICY_UNLIMITED,
+            max_fine: CIRC_MAX_FINE,
+            max_fine_rule: C::CIRC_POLICY_UNLIMITED,
+            fine_interval: CIRC_FINE_INTERVAL,
+            renewal_remaining: 0,
+            grace_period: "0 seconds",
+        };
+
+        e.create(EgValue::create("circ", circ)?)
+    }
+
+    /// Delete all circulations, and their billings, for a copy.
+    ///
+    /// Safe to call when no circulations exist.
+    pub fn delete_circs(&self, e: &mut Editor, copy_id: i64) -> EgResult<()> {
+        let circs = e.search("circ", eg::hash! {target_copy: copy_id})?;
+
+        for circ in circs.iter() {
+            self.delete_billings(e, circ.id()?)?;
         }
+
+        e.delete_batch(circs)?;
+
+        Ok(())
+    }
+
+    /// Create a copy-level hold.
+    pub fn create_hold(
+        &self,
+        e: &mut Editor,
+        target: i64,
+        user_id: i64,
+        pickup_lib: i64,
+    ) -> EgResult<EgValue> {
+        self.create_hold_of_type(e, C::HOLD_TYPE_COPY, target, user_id, pickup_lib)
+    }
+
+    /// Create a hold of any type, e.g. C::HOLD_TYPE_TITLE.
+    pub fn create_hold_of_type(
+        &self,
+        e: &mut Editor,
+        hold_type: &str,
+        target: i64,
+        user_id: i64,
+        pickup_lib: i64,
+    ) -> EgResult<EgValue> {
+        let hold = eg::hash! {
+            hold_type: hold_type,
+            target: target,
+            usr: user_id,
+            requestor: AU_STAFF_ID,
+            pickup_lib: pickup_lib,
+            request_lib: pickup_lib,
+            selection_ou: pickup_lib,
+        };
+
+        e.create(EgValue::create("ahr", hold)?)
+    }
+
+    /// Delete all holds for a user.
+    ///
+    /// Safe to call when no holds exist.
+    pub fn delete_holds(&self, e: &mut Editor, user_id: i64) -> EgResult<()> {
+        let holds = e.search("ahr", eg::hash! {usr: user_id})?;
+
+        e.delete_batch(holds)?;
+
+        Ok(())
+    }
+
+    /// Add a billing of the provided billing type to a transaction.
+    pub fn create_billing(
+        &self,
+        e: &mut Editor,
+        xact_id: i64,
+        amount: f64,
+        btype: i64,
+    ) -> EgResult<EgValue> {
+        let cbt = e
+            .retrieve("cbt", btype)?
+            .ok_or_else(|| format!("No such billing type: {btype}"))?;
+
+        let bill = eg::hash! {
+            xact: xact_id,
+            amount: amount,
+            btype: btype,
+            billing_type: cbt["name"].clone(),
+            note: SAMPLE_PREFIX,
+        };
+
+        e.create(EgValue::create("mb", bill)?)
+    }
+
+    /// Delete all billings for a transaction.
+    ///
+    /// Safe to call when no billings exist.
+    pub fn delete_billings(&self, e: &mut Editor, xact_id: i64) -> EgResult<()> {
+        let bills = e.search("mb", eg::hash! {xact: xact_id})?;
+
+        e.delete_batch(bills)?;
+
+        Ok(())
+    }
+
+    /// Add a copy alert of the provided config.copy_alert_type.
+    pub fn create_copy_alert(
+        &self,
+        e: &mut Editor,
+        copy_id: i64,
+        alert_type: i64,
+        note: &str,
+    ) -> EgResult<EgValue> {
+        let alert = eg::hash! {
+            alert_type: alert_type,
+            copy: copy_id,
+            create_staff: AU_STAFF_ID,
+            note: note,
+        };
+
+        e.create(EgValue::create("aca", alert)?)
+    }
+
+    /// Delete all copy alerts for a copy.
+    ///
+    /// Safe to call when no alerts exist.
+    pub fn delete_copy_alerts(&self, e: &mut Editor, copy_id: i64) -> EgResult<()> {
+        let alerts = e.search("aca", eg::hash! {copy: copy_id})?;
+
+        e.delete_batch(alerts)?;
+
         Ok(())
     }
 
@@ -126,22 +381,47 @@ impl SampleData {
 
     /// Create default user with a default card.
     pub fn create_default_au(&self, e: &mut Editor) -> EgResult<EgValue> {
+        self.create_au(e, &self.au_barcode, None)
+    }
+
+    /// Create a user whose card expired yesterday.
+    ///
+    /// The card barcode and username are derived from "EXPIRED".
+    pub fn create_expired_au(&self, e: &mut Editor) -> EgResult<EgValue> {
+        let expire_date = date::subtract_interval(date::now(), "1 day")?;
+        self.create_au(e, &self.derive("EXPIRED"), Some(expire_date))
+    }
+
+    /// Create a user with a card using the provided barcode, which
+    /// also serves as the username and password.
+    ///
+    /// The user's expire date defaults to the database default.
+    pub fn create_au(
+        &self,
+        e: &mut Editor,
+        barcode: &str,
+        expire_date: Option<date::EgDate>,
+    ) -> EgResult<EgValue> {
         let mut au = eg::hash! {
             profile: self.au_profile,
-            usrname: self.au_barcode.to_string(),
-            passwd: self.au_barcode.to_string(),
+            usrname: barcode,
+            passwd: barcode,
             ident_type: self.au_ident_type,
-            first_given_name: "_EG_TEST_",
-            family_name: "_EG_TEST_",
+            first_given_name: SAMPLE_PREFIX,
+            family_name: SAMPLE_PREFIX,
             home_ou: self.aou_id,
         };
 
+        if let Some(d) = expire_date {
+            au["expire_date"] = date::to_iso(&d).into();
+        }
+
         au.bless("au")?;
 
         let mut au = e.create(au)?;
 
         let mut ac = eg::hash! {
-            barcode: self.au_barcode.to_string(),
+            barcode: barcode,
             usr: au["id"].clone(),
         };
 
@@ -157,9 +437,24 @@ impl SampleData {
     }
 
     /// Purge the default user, including its linked card, transactions, etc.
-    pub fn delete_default_au(&self, e: &mut Editor) -> EgResult<()> {
+    /// ID of the user linked to the default card.
+    pub fn get_default_au_id(&self, e: &mut Editor) -> EgResult<i64> {
         let cards = e.search("ac", eg::hash! {barcode: self.au_barcode.to_string()})?;
 
+        match cards.first() {
+            Some(ac) => ac["usr"].int(),
+            None => Err("Cannot find default user".into()),
+        }
+    }
+
+    pub fn delete_default_au(&self, e: &mut Editor) -> EgResult<()> {
+        self.delete_au(e, &self.au_barcode)
+    }
+
+    /// Purge the user linked to the card with the provided barcode.
+    pub fn delete_au(&self, e: &mut Editor, barcode: &str) -> EgResult<()> {
+        let cards = e.search("ac", eg::hash! {barcode: barcode})?;
+
         if let Some(ac) = cards.get(0) {
             // Purge the user, attached card, and any other data
             // linked to the user.
@@ -172,4 +467,27 @@ impl SampleData {
 
         Ok(())
     }
+
+    /// Delete the default user's holds, the default copy's
+    /// circulations, and the default copy, call number, and user in
+    /// a transaction of their own.
+    ///
+    /// Safe to call when some or all of them do not exist.
+    pub fn delete_default_assets(&self, e: &mut Editor) -> EgResult<()> {
+        e.xact_begin()?;
+
+        if let Ok(user_id) = self.get_default_au_id(e) {
+            self.delete_holds(e, user_id)?;
+        }
+
+        if let Some(acp) = self.get_acp(e, &self.acp_barcode)? {
+            self.delete_circs(e, acp.id()?)?;
+        }
+
+        self.delete_default_acp(e)?;
+        self.delete_default_acn(e)?;
+        self.delete_default_au(e)?;
+
+        e.commit()
+    }
 }
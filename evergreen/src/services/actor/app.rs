@@ -1,4 +1,4 @@
-use eg::osrf::app::{Application, ApplicationWorker, ApplicationWorkerFactory};
+use eg::osrf::app::{Application, ApplicationWorker, ApplicationWorkerFactory, WorkerCache};
 use eg::osrf::message;
 use eg::osrf::method::MethodDef;
 use eg::Client;
@@ -56,6 +56,7 @@ impl Application for RsActorApplication {
 pub struct RsActorWorker {
     client: Option<Client>,
     methods: Option<Arc<HashMap<String, MethodDef>>>,
+    cache: WorkerCache,
 }
 
 impl RsActorWorker {
@@ -63,6 +64,7 @@ impl RsActorWorker {
         RsActorWorker {
             client: None,
             methods: None,
+            cache: WorkerCache::new(),
         }
     }
 
@@ -86,6 +88,12 @@ impl RsActorWorker {
     pub fn client_mut(&mut self) -> &mut Client {
         self.client.as_mut().unwrap()
     }
+
+    /// Values cached by this worker across requests, e.g. org unit
+    /// setting type definitions.
+    pub fn cache_mut(&mut self) -> &mut WorkerCache {
+        &mut self.cache
+    }
 }
 
 impl ApplicationWorker for RsActorWorker {
@@ -107,6 +115,10 @@ impl ApplicationWorker for RsActorWorker {
         Ok(())
     }
 
+    fn worker_cache(&mut self) -> Option<&mut WorkerCache> {
+        Some(&mut self.cache)
+    }
+
     fn worker_idle_wake(&mut self, _connected: bool) -> EgResult<()> {
         Ok(())
     }
@@ -1,11 +1,12 @@
 use eg::common::penalty;
-use eg::common::settings::Settings;
+use eg::common::settings::{self, Settings};
 use eg::common::user;
 use eg::osrf::app::ApplicationWorker;
 use eg::osrf::message;
 use eg::osrf::method::{ParamCount, ParamDataType, StaticMethodDef, StaticParam};
 use eg::osrf::session::ServerSession;
 use eg::Editor;
+use eg::EgEvent;
 use eg::EgResult;
 use eg::EgValue;
 use evergreen as eg;
@@ -28,23 +29,31 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Org Unit ID",
                 datatype: ParamDataType::Number,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Context",
                 datatype: ParamDataType::String,
                 desc: "Options: actor, asset, serial, or booking",
+                default: None,
             },
             StaticParam {
                 name: "Barcode",
                 datatype: ParamDataType::String,
                 desc: "Whole barcode or a partial 'completable' barcode",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: true,
+        required_permissions: &["STAFF_LOGIN"],
+        perm_org_param: Some(1),
     },
     StaticMethodDef {
         name: "user_has_work_perm_at.batch",
@@ -56,41 +65,63 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authtoken",
+                default: None,
             },
             StaticParam {
                 name: "Permissions",
                 datatype: ParamDataType::Array,
                 desc: "List of permission codes",
+                default: None,
             },
             StaticParam {
                 name: "User ID",
                 datatype: ParamDataType::Number,
                 desc: "User ID to check permissions for; defaults to the API requestor",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "ou_setting.ancestor_default.batch",
-        desc: "Get org unit setting values",
-        param_count: ParamCount::Range(2, 3),
+        desc: "Get org unit setting values, falling back to ancestor org units.
+            Returns a map of setting name to {org, value}",
+        param_count: ParamCount::Range(2, 4),
         handler: ou_setting_ancestor_default_batch,
         params: &[
             StaticParam {
                 name: "Org Unit ID",
                 datatype: ParamDataType::Number,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Settings",
                 datatype: ParamDataType::Array,
                 desc: "List of setting names",
+                default: None,
             },
             StaticParam {
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authtoken.  Required for perm-protected settings",
+                default: None,
+            },
+            StaticParam {
+                name: "Strict",
+                datatype: ParamDataType::Boolish,
+                desc: "Return a permission failure event instead of omitting
+                    perm-protected settings the caller cannot view",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "settings.retrieve",
@@ -102,18 +133,25 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Settings",
                 datatype: ParamDataType::Array,
                 desc: "List of setting names",
+                default: None,
             },
             StaticParam {
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authtoken.  Required for workstation, user, and perm-protected settings",
+                default: None,
             },
             StaticParam {
                 name: "Org Unit ID",
                 datatype: ParamDataType::Number,
                 desc: "",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "user.opac.vital_stats",
@@ -125,13 +163,43 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "User ID",
                 datatype: ParamDataType::Number,
                 desc: "User ID whose stats to load; defaults to requestor",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
+    },
+    StaticMethodDef {
+        name: "user.summary.retrieve",
+        desc: "Fines owed, open checkout counts, and active hold counts for a user",
+        param_count: ParamCount::Exactly(2),
+        handler: user_summary_retrieve,
+        params: &[
+            StaticParam {
+                name: "Authtoken",
+                datatype: ParamDataType::String,
+                desc: "",
+                default: None,
+            },
+            StaticParam {
+                name: "User ID",
+                datatype: ParamDataType::Number,
+                desc: "",
+                default: None,
+            },
+        ],
+        strict_params: true,
+        requires_auth: true,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "user.penalties.update",
@@ -143,19 +211,26 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "User ID",
                 datatype: ParamDataType::Number,
                 desc: "User ID to Update",
+                default: None,
             },
             StaticParam {
                 name: "Only Penalties",
                 datatype: ParamDataType::Array,
                 desc: "Optionally limit to this list of penalties.
                     May be a list of strings (names) or numbers (IDs)",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "user.penalties.update_at_home",
@@ -167,19 +242,26 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "User ID",
                 datatype: ParamDataType::Number,
                 desc: "User ID to Update",
+                default: None,
             },
             StaticParam {
                 name: "Only Penalties",
                 datatype: ParamDataType::Array,
                 desc: "Optionally limit to this list of penalties.
                     May be a list of strings (names) or numbers (IDs)",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
 ];
 
@@ -199,16 +281,12 @@ pub fn get_barcodes(
     let context = method.param(2).str()?;
     let barcode = method.param(3).str()?;
 
-    let mut editor = Editor::with_auth(worker.client(), &authtoken);
-
-    // Auth check
-    if !editor.checkauth()? {
-        return session.respond(editor.event());
-    }
+    // The authtoken and STAFF_LOGIN permission at the org unit
+    // are verified before we are called.
+    let mut editor = Editor::with_auth(worker.client(), authtoken);
 
-    // Perm check
-    if !editor.allowed_at("STAFF_LOGIN", org_id)? {
-        return session.respond(editor.event());
+    if let Some(requestor) = session.requestor() {
+        editor.set_requestor(requestor);
     }
 
     // Inline JSON object construction
@@ -350,6 +428,11 @@ pub fn retrieve_cascade_settigs(
     Ok(())
 }
 
+/// Returns a map of setting name to {org, value}, where org is the
+/// org unit, or its nearest ancestor, where the value is set.
+///
+/// Perm-protected settings the caller may not view are omitted
+/// unless strict mode is requested.
 pub fn ou_setting_ancestor_default_batch(
     worker: &mut Box<dyn ApplicationWorker>,
     session: &mut ServerSession,
@@ -357,6 +440,7 @@ pub fn ou_setting_ancestor_default_batch(
 ) -> EgResult<()> {
     let worker = app::RsActorWorker::downcast(worker)?;
     let org_id = method.param(0).int()?;
+    let strict = method.param(3).boolish();
 
     let setting_names: Vec<&str> = method
         .param(1)
@@ -365,38 +449,35 @@ pub fn ou_setting_ancestor_default_batch(
         .map(|v| v.as_str().unwrap())
         .collect();
 
-    if setting_names.len() == 0 {
-        return Ok(());
-    }
-
     let mut editor = Editor::new(worker.client());
-    let mut settings = Settings::new(&editor);
-
-    settings.set_org_id(org_id);
 
-    // If available, apply the authtoken to the editor after we apply
-    // the org id to the Settings instance (above).  Otherwise, the
-    // workstation org unit could supersede the requested org id.
     if let Some(token) = method.param(2).as_str() {
         // Authtoken is only required for perm-lmited org settings.
         // If it's provided, though, we gotta check it.
         if !editor.apply_authtoken(token)? {
             return session.respond(editor.event());
         }
-        settings.set_user_id(editor.requestor_id()?);
     }
 
-    // Pre-cache the settings en masse, then pull each from the settings
-    // cache and return to the caller.
-    settings.fetch_values(setting_names.as_slice())?;
+    let mut types = settings::OrgSettingTypes::new(worker.cache_mut());
+    types.load(&mut editor, &setting_names)?;
+
+    let mut visible = Vec::new();
 
     for name in setting_names {
-        let mut obj = EgValue::new_object();
-        obj[name] = settings.get_value(name)?.clone();
-        session.respond(obj)?;
+        if types.can_view(&mut editor, name, org_id)? {
+            visible.push(name);
+        } else if strict {
+            if !editor.has_requestor() {
+                return session.respond(EgEvent::value("NO_SESSION"));
+            }
+            return session.respond(editor.event());
+        } else {
+            log::debug!("Omitting setting {name} the caller may not view");
+        }
     }
 
-    Ok(())
+    session.respond(settings::ancestor_values(&mut editor, org_id, &visible)?)
 }
 
 pub fn user_opac_vital_stats(
@@ -475,6 +556,41 @@ pub fn user_opac_vital_stats(
     session.respond(resp)
 }
 
+/// Returns fines, checkout counts, and hold counts for a user.
+///
+/// The authtoken is verified before we are called.  VIEW_USER is
+/// required at the user's home org unit unless the requestor is
+/// the user.
+pub fn user_summary_retrieve(
+    worker: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let worker = app::RsActorWorker::downcast(worker)?;
+    let authtoken = method.param(0).str()?;
+    let user_id = method.param(1).int()?;
+
+    let mut editor = Editor::with_auth(worker.client(), authtoken);
+
+    match session.requestor() {
+        Some(requestor) => editor.set_requestor(requestor),
+        None => return Err("Requestor required".into()),
+    }
+
+    if user_id != editor.requestor_id()? {
+        let user = match editor.retrieve("au", user_id)? {
+            Some(u) => u,
+            None => return session.respond(editor.event()),
+        };
+
+        if !editor.allowed_at("VIEW_USER", user["home_ou"].int()?)? {
+            return session.respond(editor.event());
+        }
+    }
+
+    session.respond(user::summary(&mut editor, user_id)?)
+}
+
 pub fn update_penalties(
     worker: &mut Box<dyn ApplicationWorker>,
     session: &mut ServerSession,
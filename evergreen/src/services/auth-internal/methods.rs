@@ -5,6 +5,7 @@ use eg::osrf::message;
 use eg::osrf::method::{ParamCount, ParamDataType, StaticMethodDef, StaticParam};
 use eg::osrf::session::ServerSession;
 use eg::Editor;
+use eg::EgError;
 use eg::EgEvent;
 use eg::EgResult;
 use eg::EgValue;
@@ -24,7 +25,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "Options",
             datatype: ParamDataType::Object,
             desc: "Hash of Login Options and Values",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "user.validate",
@@ -35,7 +41,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "Options",
             datatype: ParamDataType::Object,
             desc: "Hash of Login Options and Values",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
 ];
 
@@ -59,7 +70,15 @@ pub fn create_auth_session(
         workstation: options["workstation"].as_str().map(|v| v.to_string()),
     };
 
-    let auth_ses = auth::Session::internal_session(&mut editor, &args)?;
+    let auth_ses = match auth::Session::internal_session(&mut editor, &args) {
+        Ok(s) => s,
+        // Report missing workstations to the caller like other
+        // login failures.
+        Err(EgError::Event(evt)) if evt.textcode() == "WORKSTATION_NOT_FOUND" => {
+            return session.respond(&evt);
+        }
+        Err(e) => return Err(e),
+    };
 
     session.respond(eg::hash! {"authtime": auth_ses.authtime(), "authtoken": auth_ses.token()})
 }
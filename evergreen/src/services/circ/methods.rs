@@ -27,13 +27,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.", // TODO expand
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "checkin.override",
@@ -45,13 +51,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.", // TODO expand
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "checkout",
@@ -63,13 +75,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "checkout.override",
@@ -81,13 +99,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "checkout.inspect",
@@ -99,13 +123,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "renew",
@@ -117,13 +147,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "renew.override",
@@ -135,13 +171,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "authtoken",
                 datatype: ParamDataType::String,
                 desc: "Authentication Token",
+                default: None,
             },
             StaticParam {
                 name: "options",
                 datatype: ParamDataType::Object,
                 desc: "Options including copy_barcode, etc.",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "renewal_chain.retrieve_by_circ.summary",
@@ -153,13 +195,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Circ ID",
                 datatype: ParamDataType::Number,
                 desc: "Circulation ID to lookup",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "prev_renewal_chain.retrieve_by_circ.summary",
@@ -171,13 +219,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Authtoken",
                 datatype: ParamDataType::String,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Circ ID",
                 datatype: ParamDataType::Number,
                 desc: "Circulation ID to lookup",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
 ];
 
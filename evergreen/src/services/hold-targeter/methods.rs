@@ -22,7 +22,12 @@ pub static METHODS: &[StaticMethodDef] = &[StaticMethodDef {
         name: "options",
         datatype: ParamDataType::Object,
         desc: "Targeting Options",
+        default: None,
     }],
+    strict_params: false,
+    requires_auth: false,
+    required_permissions: &[],
+    perm_org_param: None,
 }];
 
 pub fn target(
@@ -22,18 +22,25 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Org Unit ID",
                 datatype: ParamDataType::Number,
                 desc: "Context Org Unit",
+                default: None,
             },
             StaticParam {
                 name: "Record IDs",
                 datatype: ParamDataType::Array,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Options",
                 datatype: ParamDataType::Object,
                 desc: "Options Hash",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "biblio.record.catalog_summary.staff",
@@ -45,18 +52,25 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "Org Unit ID",
                 datatype: ParamDataType::Number,
                 desc: "Context Org Unit",
+                default: None,
             },
             StaticParam {
                 name: "Record IDs",
                 datatype: ParamDataType::Array,
                 desc: "",
+                default: None,
             },
             StaticParam {
                 name: "Options",
                 datatype: ParamDataType::Object,
                 desc: "Options Hash",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
 ];
 
@@ -112,6 +112,16 @@ impl RsStoreApplication {
             .unwrap();
 
         methods.push(commit.into_method(APPNAME));
+
+        for api in ["savepoint.set", "savepoint.release", "savepoint.rollback"] {
+            let savepoint = methods::METHODS
+                .iter()
+                .filter(|m| m.name.eq(api))
+                .next()
+                .unwrap();
+
+            methods.push(savepoint.into_method(APPNAME));
+        }
     }
 }
 
@@ -23,6 +23,10 @@ pub static METHODS: &[StaticMethodDef] = &[
         param_count: ParamCount::Zero,
         handler: manage_xact,
         params: &[],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "transaction.rollback",
@@ -30,6 +34,10 @@ pub static METHODS: &[StaticMethodDef] = &[
         param_count: ParamCount::Zero,
         handler: manage_xact,
         params: &[],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     StaticMethodDef {
         name: "transaction.commit",
@@ -37,6 +45,58 @@ pub static METHODS: &[StaticMethodDef] = &[
         param_count: ParamCount::Zero,
         handler: manage_xact,
         params: &[],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
+    },
+    StaticMethodDef {
+        name: "savepoint.set",
+        desc: "Create a savepoint within the current transaction",
+        param_count: ParamCount::Exactly(1),
+        handler: manage_savepoint,
+        params: &[StaticParam {
+            name: "Name",
+            datatype: ParamDataType::String,
+            desc: "Savepoint name",
+            default: None,
+        }],
+        strict_params: true,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
+    },
+    StaticMethodDef {
+        name: "savepoint.release",
+        desc: "Release a savepoint",
+        param_count: ParamCount::Exactly(1),
+        handler: manage_savepoint,
+        params: &[StaticParam {
+            name: "Name",
+            datatype: ParamDataType::String,
+            desc: "Savepoint name",
+            default: None,
+        }],
+        strict_params: true,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
+    },
+    StaticMethodDef {
+        name: "savepoint.rollback",
+        desc: "Rollback to a savepoint",
+        param_count: ParamCount::Exactly(1),
+        handler: manage_savepoint,
+        params: &[StaticParam {
+            name: "Name",
+            datatype: ParamDataType::String,
+            desc: "Savepoint name",
+            default: None,
+        }],
+        strict_params: true,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.create calls.  Not directly published.
     StaticMethodDef {
@@ -48,7 +108,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "IDL Object",
             datatype: ParamDataType::Object,
             desc: "Object to update",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.retrieve calls. Not directly published.
     StaticMethodDef {
@@ -61,13 +126,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "primary-key",
                 datatype: ParamDataType::Scalar,
                 desc: "Primary Key Value",
+                default: None,
             },
             StaticParam {
                 name: "flesh",
                 datatype: ParamDataType::Object,
                 desc: "Flesh Fields Object",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.search calls. Not directly published.
     StaticMethodDef {
@@ -80,13 +151,19 @@ pub static METHODS: &[StaticMethodDef] = &[
                 name: "query",
                 datatype: ParamDataType::Object,
                 desc: "Query Object",
+                default: None,
             },
             StaticParam {
                 name: "flesh",
                 datatype: ParamDataType::Object,
                 desc: "Flesh Fields Object",
+                default: None,
             },
         ],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.update calls. Not directly published.
     StaticMethodDef {
@@ -98,7 +175,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "IDL Object",
             datatype: ParamDataType::Object,
             desc: "Object to update",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.delete calls.  Not directly published.
     StaticMethodDef {
@@ -110,7 +192,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "primary-key",
             datatype: ParamDataType::Scalar,
             desc: "Primary Key Value",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
     // Stub method for *.delete calls.  Not directly published.
     StaticMethodDef {
@@ -122,7 +209,12 @@ pub static METHODS: &[StaticMethodDef] = &[
             name: "query-object",
             datatype: ParamDataType::Object,
             desc: "JSON Query Object/Hash",
+            default: None,
         }],
+        strict_params: false,
+        requires_auth: false,
+        required_permissions: &[],
+        perm_org_param: None,
     },
 ];
 
@@ -292,6 +384,31 @@ pub fn manage_xact(
     session.respond(true)
 }
 
+/// set, release, and rollback savepoints within the transaction on
+/// our primary database connection.
+///
+/// All return Err() if no transaction is in progress.
+pub fn manage_savepoint(
+    worker: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    method: &message::MethodCall,
+) -> EgResult<()> {
+    let worker = app::RsStoreWorker::downcast(worker)?;
+    let db = worker.database();
+    let api = method.method();
+    let name = method.param(0).str()?;
+
+    if api.ends_with(".set") {
+        db.borrow_mut().savepoint_set(name)?;
+    } else if api.ends_with(".release") {
+        db.borrow_mut().savepoint_release(name)?;
+    } else if api.ends_with(".rollback") {
+        db.borrow_mut().savepoint_rollback(name)?;
+    }
+
+    session.respond(name)
+}
+
 // open-ils.rs-store.direct.actor.user.update
 pub fn json_query(
     worker: &mut Box<dyn ApplicationWorker>,
@@ -13,7 +13,7 @@ const TRANSPORT_MSG_JSON: &str = r#"{
             "threadTrace":1,
             "type":"REQUEST",
             "locale":"en-US",
-            "timezone":"America/New_York",
+            "tz":"America/New_York",
             "api_level":1,
             "ingress":"opensrf",
             "payload":{
@@ -261,6 +261,35 @@ pub fn lockfile(path: &str, action: &str) -> EgResult<bool> {
     }
 }
 
+/// Lockfile which is deleted when the guard is dropped, whether or
+/// not the locked work succeeded.
+pub struct LockfileGuard {
+    path: String,
+}
+
+impl LockfileGuard {
+    /// Create the lockfile, returning Err if it already exists.
+    pub fn create(path: &str) -> EgResult<LockfileGuard> {
+        if lockfile(path, "check")? {
+            return Err(format!("Remove lockfile first: {path}").into());
+        }
+
+        lockfile(path, "create")?;
+
+        Ok(LockfileGuard {
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Drop for LockfileGuard {
+    fn drop(&mut self) {
+        if let Err(e) = lockfile(&self.path, "delete") {
+            log::error!("Cannot remove lockfile {}: {e}", self.path);
+        }
+    }
+}
+
 /// Bind to the provided host:port while applying a read timeout to the
 /// TcpListener.
 ///
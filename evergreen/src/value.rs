@@ -962,6 +962,101 @@ impl EgValue {
             .ok_or_else(|| format!("{self} has no valid ID").into())
     }
 
+    /// Returns the value of the named field.
+    ///
+    /// Unlike indexing, this never panics and distinguishes an absent
+    /// field from a NULL one:  Err is returned if we are not a Hash or
+    /// Blessed value, if our IDL class has no such field, or if our
+    /// Hash has no such key.  IDL fields with no value are NULL.
+    pub fn field(&self, name: &str) -> EgResult<&EgValue> {
+        match self {
+            EgValue::Blessed(ref o) => {
+                if o.idl_class().has_field(name) {
+                    Ok(o.values().get(name).unwrap_or(&eg::NULL))
+                } else {
+                    Err(self.field_error(name, "does not exist"))
+                }
+            }
+            EgValue::Hash(ref h) => h
+                .get(name)
+                .ok_or_else(|| self.field_error(name, "does not exist")),
+            _ => Err(format!("Cannot access field {name} on non-object {self}").into()),
+        }
+    }
+
+    fn field_error(&self, name: &str, msg: &str) -> EgError {
+        format!("{}.{name} {msg}", self.classname().unwrap_or("hash")).into()
+    }
+
+    /// Numeric value of the named field, coercing numeric strings.
+    ///
+    /// Returns None if the field is NULL.
+    pub fn field_int(&self, name: &str) -> EgResult<Option<i64>> {
+        let v = self.field(name)?;
+        if v.is_null() {
+            return Ok(None);
+        }
+        v.as_int()
+            .map(Some)
+            .ok_or_else(|| self.field_error(name, &format!("is not an integer: {v}")))
+    }
+
+    /// Float value of the named field, coercing numeric strings.
+    ///
+    /// Returns None if the field is NULL.
+    pub fn field_float(&self, name: &str) -> EgResult<Option<f64>> {
+        let v = self.field(name)?;
+        if v.is_null() {
+            return Ok(None);
+        }
+        v.as_float()
+            .map(Some)
+            .ok_or_else(|| self.field_error(name, &format!("is not a float: {v}")))
+    }
+
+    /// String value of the named field.
+    ///
+    /// Returns None if the field is NULL.
+    pub fn field_str(&self, name: &str) -> EgResult<Option<&str>> {
+        let v = self.field(name)?;
+        if v.is_null() {
+            return Ok(None);
+        }
+        v.as_str()
+            .map(Some)
+            .ok_or_else(|| self.field_error(name, &format!("is not a string: {v}")))
+    }
+
+    /// Boolean value of the named field.
+    ///
+    /// Accepts JSON booleans and the "t"/"f" strings used by the
+    /// DB layer.  Returns None if the field is NULL.
+    pub fn field_bool(&self, name: &str) -> EgResult<Option<bool>> {
+        let v = self.field(name)?;
+        match v {
+            EgValue::Null => Ok(None),
+            EgValue::Boolean(b) => Ok(Some(*b)),
+            EgValue::String(ref s) => match s.as_str() {
+                "t" | "true" => Ok(Some(true)),
+                "f" | "false" => Ok(Some(false)),
+                _ => Err(self.field_error(name, &format!("is not a boolean: {v}"))),
+            },
+            _ => Err(self.field_error(name, &format!("is not a boolean: {v}"))),
+        }
+    }
+
+    /// ID of the object linked via the named field, whether the
+    /// field is fleshed or not.
+    ///
+    /// Returns None if the field is NULL.
+    pub fn field_id(&self, name: &str) -> EgResult<Option<i64>> {
+        let v = self.field(name)?;
+        if v.is_object() {
+            return v.id().map(Some);
+        }
+        self.field_int(name)
+    }
+
     /// Returns the idl::Field for the primary key if present.
     pub fn pkey_field(&self) -> Option<&idl::Field> {
         if let EgValue::Blessed(b) = self {
@@ -1663,3 +1758,30 @@ impl IndexMut<&String> for EgValue {
         &mut self[key.as_str()]
     }
 }
+
+#[test]
+fn field_accessors() {
+    let v = eg::hash! {
+        id: "12",
+        amount: "1.50",
+        name: "Branch 1",
+        active: "t",
+        parent: EgValue::Null,
+        owner: {id: 4},
+    };
+
+    assert_eq!(v.field_int("id").unwrap(), Some(12));
+    assert_eq!(v.field_float("amount").unwrap(), Some(1.5));
+    assert_eq!(v.field_str("name").unwrap(), Some("Branch 1"));
+    assert_eq!(v.field_bool("active").unwrap(), Some(true));
+    assert_eq!(v.field_id("owner").unwrap(), Some(4));
+    assert_eq!(v.field_id("id").unwrap(), Some(12));
+
+    // NULL fields are None; absent fields are errors.
+    assert_eq!(v.field_int("parent").unwrap(), None);
+    assert!(v.field_int("missing").is_err());
+
+    let err = v.field_int("name").unwrap_err().to_string();
+    assert!(err.contains("hash.name"));
+    assert!(v.field_bool("name").is_err());
+}
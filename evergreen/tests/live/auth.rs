@@ -1,6 +1,10 @@
 use crate::util;
 use eg::common::auth;
+use eg::osrf::app::ApplicationWorker;
 use eg::osrf::cache::Cache;
+use eg::osrf::message::MethodCall;
+use eg::osrf::method::{MethodDef, ParamCount};
+use eg::osrf::session::ServerSession;
 use eg::EgResult;
 use evergreen as eg;
 
@@ -71,5 +75,59 @@ pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     assert!(auth::Session::from_cache(ses2.token())?.is_none());
     tester.timer.log("Removed session from cache");
 
+    test_method_auth(tester, &args)?;
+
+    Ok(())
+}
+
+fn noop_handler(
+    _: &mut Box<dyn ApplicationWorker>,
+    _: &mut ServerSession,
+    _: &MethodCall,
+) -> EgResult<()> {
+    Ok(())
+}
+
+fn test_method_auth(tester: &mut util::Tester, args: &auth::InternalLoginArgs) -> EgResult<()> {
+    let mut method = MethodDef::new("test.method_auth", ParamCount::Exactly(2), noop_handler);
+    method.add_required_permission("STAFF_LOGIN");
+    method.set_perm_org_param(Some(1));
+
+    let ses = auth::Session::internal_session(&mut tester.editor, args)?;
+    let params = vec![ses.token().into(), tester.samples.aou_id.into()];
+
+    let mut editor = eg::Editor::new(&tester.client);
+    assert!(method.authorize(&mut editor, &params)?);
+    assert_eq!(editor.requestor_id()?, eg::samples::AU_STAFF_ID);
+    tester.timer.log("Authorized method call");
+
+    method.add_required_permission("_EG_TEST_NO_SUCH_PERM");
+
+    let mut editor = eg::Editor::new(&tester.client);
+    assert!(!method.authorize(&mut editor, &params)?);
+    assert_eq!(
+        editor.last_event().map(|e| e.textcode()),
+        Some("PERM_FAILURE")
+    );
+    assert_eq!(
+        editor.event()["ilsperm"].as_str(),
+        Some("_EG_TEST_NO_SUCH_PERM")
+    );
+    tester
+        .timer
+        .log("Rejected method call with missing permission");
+
+    ses.remove()?;
+
+    let mut editor = eg::Editor::new(&tester.client);
+    assert!(!method.authorize(&mut editor, &params)?);
+    assert_eq!(
+        editor.last_event().map(|e| e.textcode()),
+        Some("NO_SESSION")
+    );
+    tester
+        .timer
+        .log("Rejected method call with expired authtoken");
+
     Ok(())
 }
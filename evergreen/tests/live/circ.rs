@@ -1,6 +1,9 @@
 use crate::util;
+use eg::common::bib;
+use eg::common::circ;
 use eg::common::circulator::Circulator;
 use eg::constants as C;
+use eg::money::Money;
 use eg::result::EgResult;
 use eg::EgValue;
 use evergreen as eg;
@@ -16,6 +19,12 @@ pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     create_test_assets(tester)?;
     tester.timer.log("Created circ assets");
 
+    copy_display_fields(tester)?;
+    tester.timer.log("copy_display_fields()");
+
+    permit_tests(tester)?;
+    tester.timer.log("permit_tests()");
+
     checkout(tester)?;
     tester.timer.log("checkout()");
 
@@ -25,6 +34,25 @@ pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
     checkin_item_remote(tester)?;
     tester.timer.log("checkin_item_remote()");
 
+    delete_test_assets(tester)?;
+    create_test_assets(tester)?;
+
+    checkout(tester)?;
+    tester.timer.log("checkout()");
+
+    checkin_with_params(tester)?;
+    tester.timer.log("checkin_with_params()");
+
+    // Start over with a fresh checkout for the lost item tests.
+    delete_test_assets(tester)?;
+    create_test_assets(tester)?;
+
+    checkout(tester)?;
+    tester.timer.log("checkout()");
+
+    mark_item_lost(tester)?;
+    tester.timer.log("mark_item_lost()");
+
     delete_test_assets(tester)?;
     tester.timer.log("Deleted circ assets");
 
@@ -147,6 +175,56 @@ fn checkin_item_at_home(tester: &mut util::Tester) -> EgResult<()> {
     Ok(())
 }
 
+fn copy_display_fields(tester: &mut util::Tester) -> EgResult<()> {
+    let options = bib::DisplayFieldOptions::default();
+    let barcode = bib::CopyIdent::Barcode(&tester.samples.acp_barcode);
+
+    let fields =
+        bib::get_display_fields(&mut tester.editor, barcode, options)?.expect("Test copy exists");
+
+    assert_eq!(fields.barcode(), tester.samples.acp_barcode);
+    assert_eq!(fields.call_number(), eg::samples::ACN_LABEL);
+    assert_eq!(fields.record_id(), Some(eg::samples::ACN_RECORD));
+    assert_eq!(fields.circ_lib(), Some(eg::samples::AOU_BR1_SHORTNAME));
+    assert!(fields.title().is_some());
+
+    let batch = bib::get_display_fields_batch(
+        &mut tester.editor,
+        &[fields.copy_id(), -1, fields.copy_id()],
+        options,
+    )?;
+
+    assert_eq!(batch, vec![fields]);
+
+    Ok(())
+}
+
+fn checkin_with_params(tester: &mut util::Tester) -> EgResult<()> {
+    let mut params = circ::CheckinParams::for_barcode(&tester.samples.acp_barcode);
+    params.circ_lib = Some(eg::samples::AOU_BR2_ID);
+    params.backdate = Some(eg::date::now().format("%Y-%m-%d").to_string());
+
+    let result = circ::checkin(&mut tester.editor, &params)?;
+
+    assert!(result.is_ok());
+    assert!(result.needs_transit());
+    assert!(!result.hold_captured());
+    assert!(result.transit.is_some());
+
+    let copy = result.copy.as_ref().expect("Checkin returns the copy");
+    assert_eq!(copy["status"].int()?, C::COPY_STATUS_IN_TRANSIT);
+
+    let circ = result.circ.as_ref().expect("Checkin returns the circ");
+    assert!(!circ["checkin_time"].is_null());
+    assert_eq!(circ["stop_fines"].as_str(), Some("CHECKIN"));
+
+    // Nothing left to check in.
+    let result = circ::checkin(&mut tester.editor, &params)?;
+    assert!(result.circ.is_none());
+
+    Ok(())
+}
+
 fn checkin_item_remote(tester: &mut util::Tester) -> EgResult<()> {
     let mut options: HashMap<String, EgValue> = HashMap::new();
     options.insert(
@@ -187,3 +265,123 @@ fn checkin_item_remote(tester: &mut util::Tester) -> EgResult<()> {
 
     Ok(())
 }
+
+fn mark_item_lost(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let copy = tester.samples.get_default_acp(e)?;
+    let query = eg::hash! {target_copy: copy.id()?, checkin_time: EgValue::Null};
+
+    let circ = e
+        .search("circ", query)?
+        .pop()
+        .ok_or(format!("Cannot find open circulation"))?;
+
+    let circ_id = circ.id()?;
+
+    e.xact_begin()?;
+    circ::mark_item_lost(e, circ_id)?;
+    e.commit()?;
+
+    let copy = tester.samples.get_default_acp(e)?;
+    assert_eq!(copy["status"].int()?, C::COPY_STATUS_LOST);
+
+    let circ = e.retrieve("circ", circ_id)?.unwrap();
+    assert_eq!(circ["stop_fines"].as_str(), Some("LOST"));
+    assert!(circ["stop_fines_time"].is_string());
+
+    // Sample copies have no price, so the bill amount depends on the
+    // default / minimum price settings.  Verify the bill matches.
+    let price = eg::common::billing::get_copy_price(e, copy.id()?)?;
+    let query = eg::hash! {xact: circ_id, btype: C::BTYPE_LOST_MATERIALS};
+    let bills = e.search("mb", query)?;
+
+    if price.is_positive() {
+        assert_eq!(bills.len(), 1);
+        assert_eq!(Money::from_value(&bills[0]["amount"])?, price);
+    } else {
+        assert!(bills.is_empty());
+    }
+
+    Ok(())
+}
+
+/// Create a circ matrix matchpoint using the rules of an existing one.
+fn create_matchpoint(
+    e: &mut eg::Editor,
+    rules: &EgValue,
+    org_unit: i64,
+    grp: i64,
+    is_renewal: Option<bool>,
+    circulate: bool,
+) -> EgResult<i64> {
+    let ccmm = EgValue::create(
+        "ccmm",
+        eg::hash! {
+            active: "t",
+            org_unit: org_unit,
+            grp: grp,
+            is_renewal: is_renewal,
+            circulate: circulate,
+            duration_rule: rules["duration_rule"].clone(),
+            recurring_fine_rule: rules["recurring_fine_rule"].clone(),
+            max_fine_rule: rules["max_fine_rule"].clone(),
+        },
+    )?;
+
+    e.create(ccmm)?.id()
+}
+
+fn permit_tests(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let patron_id = tester.samples.get_default_au_id(e)?;
+    let copy_id = tester.samples.get_default_acp(e)?.id()?;
+    let br1 = tester.samples.aou_id;
+    let grp = tester.samples.au_profile;
+    let parent = e.retrieve("aou", br1)?.ok_or("BR1 exists")?["parent_ou"].int()?;
+
+    let result = circ::permit::test_checkout(e, patron_id, Some(copy_id), br1)?;
+    assert!(result.permitted());
+
+    let stock_mp = result.matchpoint().ok_or("Checkout has a matchpoint")?;
+    let rules = e.retrieve("ccmm", stock_mp)?.ok_or("Matchpoint exists")?;
+
+    // A rule for all transactions at the parent org unit, plus a
+    // renewal-only rule at BR1 which prevents circulation.
+    e.xact_begin()?;
+    let parent_mp = create_matchpoint(e, &rules, parent, grp, None, true)?;
+    let renew_mp = create_matchpoint(e, &rules, br1, grp, Some(true), false)?;
+    e.commit()?;
+
+    // Checkouts at BR1 inherit the parent org unit's rule and skip
+    // the renewal-only rule.
+    let result = circ::permit::test_checkout(e, patron_id, Some(copy_id), br1)?;
+    assert!(result.permitted());
+    assert_eq!(result.matchpoint(), Some(parent_mp));
+
+    let result = circ::permit::test_renewal(e, patron_id, copy_id, br1)?;
+    assert!(!result.permitted());
+    assert_eq!(result.matchpoint(), Some(renew_mp));
+
+    let block = result
+        .blocks()
+        .iter()
+        .find(|b| b.fail_part() == "config.circ_matrix_test.circulate")
+        .ok_or("Renewal is blocked by the circulate test")?;
+
+    assert_eq!(block.textcode(), "COPY_CIRC_NOT_ALLOWED");
+    assert_eq!(
+        result.first_event().map(|evt| evt.textcode().to_string()),
+        Some(result.blocks()[0].textcode().to_string())
+    );
+
+    e.xact_begin()?;
+    for id in [parent_mp, renew_mp] {
+        let mp = e.retrieve("ccmm", id)?.ok_or("Matchpoint exists")?;
+        e.delete(mp)?;
+    }
+    e.commit()?;
+
+    Ok(())
+}
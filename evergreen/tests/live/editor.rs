@@ -0,0 +1,509 @@
+use crate::util;
+use eg::result::EgResult;
+use eg::samples;
+use eg::EgValue;
+use evergreen as eg;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const BATCH_SIZE: usize = 5;
+const TEST_PERM: &str = "_EG_TEST_EDITOR_PERM";
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    delete_test_assets(tester)?;
+
+    batch_create_delete(tester)?;
+    tester.timer.log("batch_create_delete()");
+
+    batch_failure_rolls_back(tester)?;
+    tester.timer.log("batch_failure_rolls_back()");
+
+    savepoint_rollback(tester)?;
+    tester.timer.log("savepoint_rollback()");
+
+    delete_test_assets(tester)?;
+
+    xact_guard_rolls_back(tester)?;
+    tester.timer.log("xact_guard_rolls_back()");
+
+    xact_guard_commit(tester)?;
+    tester.timer.log("xact_guard_commit()");
+
+    delete_test_assets(tester)?;
+
+    delete_test_perm(&mut tester.editor)?;
+    allowed_at_child_org(tester)?;
+    tester.timer.log("allowed_at_child_org()");
+    delete_test_perm(&mut tester.editor)?;
+
+    query_batch(tester)?;
+    tester.timer.log("query_batch()");
+
+    query_batch_failure(tester)?;
+    tester.timer.log("query_batch_failure()");
+
+    search_paged(tester)?;
+    tester.timer.log("search_paged()");
+
+    delete_test_assets(tester)?;
+
+    idle_session_replaced(tester)?;
+    tester.timer.log("idle_session_replaced()");
+
+    nested_xact_refused(tester)?;
+    tester.timer.log("nested_xact_refused()");
+
+    xact_timeout_rolls_back(tester)?;
+    tester.timer.log("xact_timeout_rolls_back()");
+
+    update_diff_captured(tester)?;
+    tester.timer.log("update_diff_captured()");
+
+    delete_test_assets(tester)?;
+
+    Ok(())
+}
+
+fn barcodes() -> Vec<String> {
+    (0..BATCH_SIZE)
+        .map(|i| format!("_EG_TEST_BATCH_{i}"))
+        .collect()
+}
+
+fn delete_test_assets(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    tester.samples.delete_acps(e, &barcodes())?;
+    tester.samples.delete_default_acn(e)?;
+
+    e.commit()
+}
+
+fn batch_create_delete(tester: &mut util::Tester) -> EgResult<()> {
+    let barcodes = barcodes();
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn = tester.samples.create_default_acn(e)?;
+    let acps = tester.samples.create_acps(e, acn.id()?, &barcodes)?;
+
+    assert_eq!(acps.len(), BATCH_SIZE);
+
+    for (acp, barcode) in acps.iter().zip(barcodes.iter()) {
+        assert_eq!(acp["barcode"].str()?, barcode);
+        assert!(acp["id"].is_number());
+    }
+
+    e.commit()?;
+
+    e.xact_begin()?;
+    tester.samples.delete_acps(e, &barcodes)?;
+    e.commit()?;
+
+    let query = eg::hash! {barcode: barcodes.clone(), deleted: "f"};
+    assert!(e.search("acp", query)?.is_empty());
+
+    Ok(())
+}
+
+fn batch_failure_rolls_back(tester: &mut util::Tester) -> EgResult<()> {
+    // Duplicate barcodes violate a unique constraint on the last copy.
+    let mut barcodes = barcodes();
+    barcodes.push(barcodes[0].clone());
+
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn = tester.samples.create_default_acn(e)?;
+
+    let err = tester
+        .samples
+        .create_acps(e, acn.id()?, &barcodes)
+        .expect_err("Duplicate barcode should fail");
+
+    assert!(err.to_string().contains(&format!("index {BATCH_SIZE}")));
+
+    // The failure rolled back the transaction, including the acn.
+    assert!(!e.in_transaction());
+
+    let query = eg::hash! {barcode: barcodes.clone(), deleted: "f"};
+    assert!(e.search("acp", query)?.is_empty());
+
+    let query = eg::hash! {label: tester.samples.acn_label.as_str(), deleted: "f"};
+    assert!(e.search("acn", query)?.is_empty());
+
+    Ok(())
+}
+
+fn savepoint_rollback(tester: &mut util::Tester) -> EgResult<()> {
+    let barcodes = barcodes();
+    let e = &mut tester.editor;
+
+    assert!(e.savepoint("outside").is_err());
+
+    e.xact_begin()?;
+
+    let acn = tester.samples.create_default_acn(e)?;
+    let acn_id = acn.id()?;
+
+    tester.samples.create_acps(e, acn_id, &barcodes[0..1])?;
+
+    e.savepoint("outer")?;
+    tester.samples.create_acps(e, acn_id, &barcodes[1..2])?;
+
+    e.savepoint("inner")?;
+    tester.samples.create_acps(e, acn_id, &barcodes[2..3])?;
+
+    // Rolling back the outer savepoint unwinds the inner one too.
+    e.rollback_savepoint("outer")?;
+    assert_eq!(e.savepoints(), ["outer"]);
+    assert!(e.release_savepoint("inner").is_err());
+
+    // The outer savepoint survives the rollback.
+    e.rollback_savepoint("outer")?;
+    e.release_savepoint("outer")?;
+    assert!(e.savepoints().is_empty());
+
+    e.savepoint("sibling")?;
+    tester.samples.create_acps(e, acn_id, &barcodes[3..4])?;
+    e.release_savepoint("sibling")?;
+
+    e.commit()?;
+
+    let query = eg::hash! {barcode: barcodes.clone(), deleted: "f"};
+    let mut found: Vec<String> = e
+        .search("acp", query)?
+        .iter()
+        .map(|acp| acp["barcode"].string())
+        .collect::<EgResult<Vec<String>>>()?;
+
+    found.sort();
+
+    assert_eq!(found, vec![barcodes[0].clone(), barcodes[3].clone()]);
+
+    Ok(())
+}
+
+/// Creates a call number then fails before committing.
+fn create_acn_and_fail(tester: &mut util::Tester) -> EgResult<()> {
+    let mut e = tester.editor.xact_guard()?;
+
+    tester.samples.create_default_acn(&mut e)?;
+
+    // No such copy; returns early with the not-found event.
+    e.retrieve("acp", -1)?.ok_or_else(|| e.die_event())?;
+
+    e.commit()
+}
+
+fn xact_guard_rolls_back(tester: &mut util::Tester) -> EgResult<()> {
+    let err = create_acn_and_fail(tester).expect_err("Copy -1 should not exist");
+    assert!(err.to_string().contains("ASSET_COPY_NOT_FOUND"));
+
+    let e = &mut tester.editor;
+    assert!(!e.in_transaction());
+
+    let query = eg::hash! {label: tester.samples.acn_label.as_str(), deleted: "f"};
+    assert!(e.search("acn", query)?.is_empty());
+
+    // Dropping a guard after an early return without a failure
+    // event also rolls back.
+    let result: EgResult<()> = (|| {
+        let mut e = tester.editor.xact_guard()?;
+        tester.samples.create_default_acn(&mut e)?;
+        Err("Bailing out".into())
+    })();
+
+    assert!(result.is_err());
+    assert!(!tester.editor.in_transaction());
+
+    let query = eg::hash! {label: tester.samples.acn_label.as_str(), deleted: "f"};
+    assert!(tester.editor.search("acn", query)?.is_empty());
+
+    Ok(())
+}
+
+fn xact_guard_commit(tester: &mut util::Tester) -> EgResult<()> {
+    let acn_id = {
+        let mut e = tester.editor.xact_guard()?;
+        let acn = tester.samples.create_default_acn(&mut e)?;
+        e.commit()?;
+        acn.id()?
+        // Dropping the committed guard is a no-op.
+    };
+
+    let e = &mut tester.editor;
+    assert!(!e.in_transaction());
+    assert!(e.retrieve("acn", acn_id)?.is_some());
+
+    Ok(())
+}
+
+/// Safe to call when the test permission does not exist.
+fn delete_test_perm(e: &mut eg::Editor) -> EgResult<()> {
+    e.xact_begin()?;
+
+    let perms = e.search("ppl", eg::hash! {code: TEST_PERM})?;
+
+    for perm in perms.iter() {
+        let maps = e.search("pupm", eg::hash! {perm: perm.id()?})?;
+        e.delete_batch(maps)?;
+    }
+
+    e.delete_batch(perms)?;
+
+    e.commit()
+}
+
+fn allowed_at_child_org(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    let br1 = samples::AOU_BR1_ID;
+    let parent = e.retrieve("aou", br1)?.ok_or("BR1 exists")?["parent_ou"].int()?;
+    let depth = e
+        .retrieve_with_ops(
+            "aou",
+            br1,
+            eg::hash! {flesh: 1, flesh_fields: {aou: ["ou_type"]}},
+        )?
+        .ok_or("BR1 exists")?["ou_type"]["depth"]
+        .int()?;
+
+    // Grant the permission to our requestor at the depth of their
+    // home org unit, i.e. BR1 and below, but not its parent.
+    e.xact_begin()?;
+
+    let perm = e.create(EgValue::create(
+        "ppl",
+        eg::hash! {code: TEST_PERM, description: TEST_PERM},
+    )?)?;
+
+    e.create(EgValue::create(
+        "pupm",
+        eg::hash! {
+            usr: e.requestor_id()?,
+            perm: perm.id()?,
+            depth: depth,
+            grantable: "f",
+        },
+    )?)?;
+
+    e.commit()?;
+
+    e.clear_perm_cache();
+
+    assert!(e.allowed_at(TEST_PERM, br1)?);
+    assert!(!e.allowed_at(TEST_PERM, parent)?);
+
+    let evt = e.last_event().ok_or("Has PERM_FAILURE event")?;
+    assert_eq!(evt.textcode(), "PERM_FAILURE");
+    assert!(evt.to_string().contains(&format!("{TEST_PERM}@{parent}")));
+
+    assert_eq!(e.perm_cache_len(), 2);
+
+    // Revoke the permission.  Cached results are returned without
+    // asking the database again.
+    e.xact_begin()?;
+    let maps = e.search("pupm", eg::hash! {perm: perm.id()?})?;
+    e.delete_batch(maps)?;
+    e.commit()?;
+
+    assert!(e.allowed_at(TEST_PERM, br1)?);
+    assert_eq!(e.perm_cache_len(), 2);
+
+    e.clear_perm_cache();
+    assert!(!e.allowed_at(TEST_PERM, br1)?);
+
+    Ok(())
+}
+
+/// Batched queries return the same results as sequential queries.
+fn query_batch(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    let br1 = samples::AOU_BR1_ID;
+
+    let aou = e.retrieve("aou", br1)?.ok_or("BR1 exists")?;
+    let children = e.search("aou", eg::hash! {parent_ou: br1})?;
+    let rows = e.json_query(eg::hash! {select: {aou: ["id"]}, from: "aou", where: {id: br1}})?;
+
+    let mut batch = e.query_batch();
+
+    let aou_pos = batch.retrieve("aou", br1)?;
+    let missing_pos = batch.retrieve("aou", -1)?;
+    let children_pos = batch.search("aou", eg::hash! {parent_ou: br1})?;
+    let rows_pos =
+        batch.json_query(eg::hash! {select: {aou: ["id"]}, from: "aou", where: {id: br1}})?;
+
+    assert_eq!(batch.len(), 4);
+
+    let mut results = batch.join()?;
+
+    assert_eq!(
+        results.take_one(aou_pos).ok_or("BR1 found")?.id()?,
+        aou.id()?
+    );
+    assert!(results.take_one(missing_pos).is_none());
+    assert_eq!(results.take_list(children_pos)?.len(), children.len());
+    assert_eq!(results.take_list(rows_pos)?, rows);
+
+    // Results may only be taken once.
+    assert!(results.take_list(rows_pos).is_err());
+
+    Ok(())
+}
+
+/// Paged searches return every row, in order, across pages.
+fn search_paged(tester: &mut util::Tester) -> EgResult<()> {
+    let barcodes = barcodes();
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+    let acn = tester.samples.create_default_acn(e)?;
+    let acps = tester.samples.create_acps(e, acn.id()?, &barcodes)?;
+    e.commit()?;
+
+    let mut ids = Vec::new();
+    for acp in acps.iter() {
+        ids.push(acp.id()?);
+    }
+    ids.sort();
+
+    // Page size 2 ends on a short page, 5 ends on an empty one.
+    for page_size in [2, BATCH_SIZE] {
+        let query = eg::hash! {barcode: barcodes.clone(), deleted: "f"};
+
+        let mut paged = Vec::new();
+        for acp in e.search_paged("acp", query, eg::NULL, page_size)? {
+            paged.push(acp?.id()?);
+        }
+
+        assert_eq!(paged, ids);
+
+        let query = eg::hash! {
+            select: {acp: ["id"]},
+            from: "acp",
+            where: {barcode: barcodes.clone(), deleted: "f"},
+            order_by: [{class: "acp", field: "id"}],
+        };
+
+        let mut paged = Vec::new();
+        for row in e.json_query_paged(query, page_size) {
+            paged.push(row?.id()?);
+        }
+
+        assert_eq!(paged, ids);
+    }
+
+    Ok(())
+}
+
+/// A failed query fails the batch.
+fn query_batch_failure(tester: &mut util::Tester) -> EgResult<()> {
+    let mut batch = tester.editor.query_batch();
+
+    batch.retrieve("aou", samples::AOU_BR1_ID)?;
+    batch.json_query(eg::hash! {select: {aou: ["no_such_column"]}, from: "aou"})?;
+
+    assert!(batch.join().is_err());
+
+    // Our editor is unaffected.
+    assert!(tester
+        .editor
+        .retrieve("aou", samples::AOU_BR1_ID)?
+        .is_some());
+
+    Ok(())
+}
+
+/// A connected session left idle beyond the (shortened) idle timeout
+/// is replaced before the next transaction, even though its worker
+/// has not yet given up on it.
+fn idle_session_replaced(tester: &mut util::Tester) -> EgResult<()> {
+    let mut e = tester.editor.clone();
+    e.set_idle_timeout(Duration::from_millis(200));
+
+    // xact_commit() leaves the session connected.
+    e.xact_begin()?;
+    e.xact_commit()?;
+
+    std::thread::sleep(Duration::from_millis(300));
+
+    e.xact_begin()?;
+    assert!(e.in_transaction());
+    assert!(e.retrieve("aou", samples::AOU_BR1_ID)?.is_some());
+    e.rollback()?;
+
+    Ok(())
+}
+
+fn nested_xact_refused(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+    let acn = tester.samples.create_default_acn(e)?;
+
+    let err = e.xact_begin().expect_err("Nested transaction should fail");
+    assert!(err.to_string().contains("is open"));
+
+    // The original transaction is unaffected.
+    assert!(e.in_transaction());
+    assert!(e.retrieve("acn", acn.id()?)?.is_some());
+
+    e.rollback()
+}
+
+fn xact_timeout_rolls_back(tester: &mut util::Tester) -> EgResult<()> {
+    let mut e = tester.editor.clone();
+    e.set_xact_timeout(Some(Duration::from_millis(500)));
+
+    e.xact_begin()?;
+    tester.samples.create_default_acn(&mut e)?;
+
+    std::thread::sleep(Duration::from_millis(600));
+
+    let err = e.commit().expect_err("Commit should time out");
+    assert_eq!(
+        err.event().map(|e| e.textcode()),
+        Some("TRANSACTION_TIMEOUT")
+    );
+    assert!(!e.in_transaction());
+
+    let query = eg::hash! {label: tester.samples.acn_label.as_str(), deleted: "f"};
+    assert!(e.search("acn", query)?.is_empty());
+
+    Ok(())
+}
+
+fn update_diff_captured(tester: &mut util::Tester) -> EgResult<()> {
+    let diffs: Arc<Mutex<Vec<(String, String)>>> = Arc::new(Mutex::new(Vec::new()));
+    let captured = diffs.clone();
+
+    let mut e = tester.editor.clone();
+    e.set_capture_diffs(true);
+    e.set_diff_callback(Some(Arc::new(move |classname, _, changes| {
+        let mut captured = captured.lock().unwrap();
+        for change in changes {
+            captured.push((classname.to_string(), change.field().to_string()));
+        }
+    })));
+
+    e.xact_begin()?;
+
+    let mut acn = tester.samples.create_default_acn(&mut e)?;
+
+    // Unchanged values sent in another form are not changes.
+    acn["label"] = EgValue::from(format!("{}_DIFF", tester.samples.acn_label));
+    acn["owning_lib"] = EgValue::from(acn["owning_lib"].int()?.to_string());
+
+    e.update(acn)?;
+    e.rollback()?;
+
+    let diffs = diffs.lock().unwrap();
+    assert_eq!(*diffs, vec![("acn".to_string(), "label".to_string())]);
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use crate::util;
+use eg::common::holds;
+use eg::constants as C;
+use eg::result::EgResult;
+use eg::samples::SampleData;
+use eg::EgValue;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    util::login(tester)?;
+    tester.timer.start();
+
+    let samples = SampleData::with_suffix("QUEUE");
+    let e = &mut tester.editor;
+
+    samples.delete_default_assets(e)?;
+
+    e.xact_begin()?;
+
+    let acn = samples.create_default_acn(e)?;
+    let acp = samples.create_default_acp(e, acn.id()?)?;
+    let copy_id = acp.id()?;
+    let user_id = samples.create_default_au(e)?.id()?;
+
+    // A title hold placed before a copy hold on the same copy.
+    let title_hold = samples
+        .create_hold_of_type(
+            e,
+            C::HOLD_TYPE_TITLE,
+            samples.acn_record,
+            user_id,
+            samples.aou_id,
+        )?
+        .id()?;
+
+    let copy_hold = samples
+        .create_hold(e, copy_id, user_id, samples.aou_id)?
+        .id()?;
+
+    e.commit()?;
+
+    holds::retarget_holds(e, &[title_hold, copy_hold])?;
+    tester.timer.log("Created and targeted holds");
+
+    let copy_holds = holds::holds_for_copy(e, copy_id)?;
+    let ids: Vec<i64> = copy_holds
+        .iter()
+        .map(|h| h.id())
+        .collect::<EgResult<Vec<i64>>>()?;
+
+    assert!(ids.contains(&title_hold));
+    assert!(ids.contains(&copy_hold));
+    tester.timer.log("holds_for_copy()");
+
+    // Compare with the Perl API.
+    let authtoken = e.authtoken().ok_or("Logged in")?.to_string();
+
+    for hold_id in [title_hold, copy_hold] {
+        let stats = holds::hold_queue_stats(e, hold_id)?;
+
+        let perl = tester
+            .client
+            .send_recv_one(
+                "open-ils.circ",
+                "open-ils.circ.hold.queue_stats.retrieve",
+                vec![EgValue::from(authtoken.as_str()), EgValue::from(hold_id)],
+            )?
+            .ok_or("Queue stats returned a response")?;
+
+        assert_eq!(stats.queue_position() as i64, perl["queue_position"].int()?);
+        assert_eq!(stats.total_holds() as i64, perl["total_holds"].int()?);
+        assert_eq!(stats.potential_copies(), perl["potential_copies"].int()?);
+        assert_eq!(
+            stats.estimated_wait().unwrap_or(-1),
+            perl["estimated_wait"].int()?
+        );
+    }
+
+    tester
+        .timer
+        .log("Compared hold queue stats to the Perl API");
+
+    samples.delete_default_assets(e)?;
+    tester.timer.log("Deleted hold queue assets");
+
+    Ok(())
+}
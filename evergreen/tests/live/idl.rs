@@ -0,0 +1,74 @@
+//! Checks a few well-known classes in the stock IDL.
+//! NOTE Field names may change or be added that could affect these tests.
+use crate::util;
+use eg::idl;
+use eg::result::EgResult;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    known_classes()?;
+    tester.timer.log("known_classes()");
+
+    validate_objects()?;
+    tester.timer.log("validate_objects()");
+
+    Ok(())
+}
+
+fn known_classes() -> EgResult<()> {
+    let parser = idl::parser();
+
+    let mbts = parser.class("mbts")?;
+    assert_eq!(mbts.pkey(), Some("id"));
+    assert_eq!(
+        mbts.fieldmapper(),
+        Some("money::billable_transaction_summary")
+    );
+    assert!(mbts.has_field("balance_owed"));
+    assert_eq!(
+        mbts.get_field("balance_owed").map(|f| f.datatype()),
+        Some(&idl::DataType::Money)
+    );
+
+    let au = parser.class("au")?;
+    assert_eq!(au.pkey(), Some("id"));
+    assert_eq!(au.fieldmapper(), Some("actor::user"));
+    assert!(au.has_field("usrname"));
+    assert!(au.get_field("home_ou").is_some());
+
+    let acp = parser.class("acp")?;
+    assert_eq!(acp.fieldmapper(), Some("asset::copy"));
+    assert!(acp.has_field("barcode"));
+    assert!(!acp.has_field("balance_owed"));
+
+    assert!(parser.class("_no_such_class_").is_err());
+
+    Ok(())
+}
+
+fn validate_objects() -> EgResult<()> {
+    let parser = idl::parser();
+
+    let mut copy = eg::blessed! {
+        "_classname": "acp",
+        "barcode": "_EG_TEST_VALIDATE_",
+    }?;
+
+    let validation = parser.validate(&copy)?;
+    assert!(validation.unknown_fields().is_empty());
+
+    // Blessed values permit unknown keys via insert().
+    copy.insert("no_such_field", 1)?;
+
+    let validation = parser.validate(&copy)?;
+    assert_eq!(
+        validation.unknown_fields(),
+        &vec!["no_such_field".to_string()]
+    );
+
+    assert!(parser.validate(&eg::hash! {"barcode": "x"}).is_err());
+
+    Ok(())
+}
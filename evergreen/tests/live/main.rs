@@ -2,8 +2,16 @@ use evergreen as eg;
 mod auth;
 mod cache;
 mod circ;
+mod editor;
+mod holds;
+mod idl;
 mod json_query;
+mod org;
+mod osrf;
+mod settings;
 mod store;
+mod trigger;
+mod user;
 mod util;
 
 /// Set to 'ignored' by default since it requires a running system
@@ -32,10 +40,26 @@ fn main() -> eg::EgResult<()> {
 
     cache::run_live_tests(&mut tester)?;
 
+    idl::run_live_tests(&mut tester)?;
+
+    osrf::run_live_tests(&mut tester)?;
+
     auth::run_live_tests(&mut tester)?;
 
+    editor::run_live_tests(&mut tester)?;
+
+    org::run_live_tests(&mut tester)?;
+
     circ::run_live_tests(&mut tester)?;
 
+    user::run_live_tests(&mut tester)?;
+
+    holds::run_live_tests(&mut tester)?;
+
+    settings::run_live_tests(&mut tester)?;
+
+    trigger::run_live_tests(&mut tester)?;
+
     // open-ils.rs-store tester
     //store::run_live_tests(&mut tester)?;
 
@@ -0,0 +1,56 @@
+use crate::util;
+use eg::common::org;
+use eg::samples;
+use eg::EgResult;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    let editor = &mut tester.editor;
+
+    let tree = org::reload_tree(editor)?;
+
+    tester.timer.log("Loaded org tree");
+
+    let br1 = org::id_for_shortname(editor, samples::AOU_BR1_SHORTNAME)?;
+    let br2 = org::id_for_shortname(editor, samples::AOU_BR2_SHORTNAME)?;
+
+    assert_eq!(br1, samples::AOU_BR1_ID);
+    assert_eq!(br2, samples::AOU_BR2_ID);
+    assert_eq!(org::shortname(editor, br1)?, samples::AOU_BR1_SHORTNAME);
+
+    // The cached tree agrees with the database.
+    let mut expected = Vec::new();
+    for org_unit in editor.json_query(eg::hash! {"from": ["actor.org_unit_ancestors", br1]})? {
+        expected.push(org_unit.id()?);
+    }
+    let mut ancestors = org::ancestors(editor, br1)?;
+    expected.sort();
+    ancestors.sort();
+    assert_eq!(ancestors, expected);
+
+    let system = tree
+        .get(br1)
+        .and_then(|n| n.parent())
+        .expect("BR1 has a parent");
+    let root = tree.ancestors(br1)?[0];
+
+    assert_eq!(org::ancestor_at_depth(editor, br1, 0)?, Some(root));
+    assert!(org::descendants(editor, system)?.contains(&br2));
+    assert!(tree.is_descendant(br2, root)?);
+    assert!(!tree.is_descendant(br2, br1)?);
+
+    tester.timer.log("Navigated org tree");
+
+    // Settings applied at the consortium are visible to the branch.
+    let value = org::org_unit_setting(editor, br1, "lib.timezone")?;
+    let root_value = org::org_unit_setting(editor, root, "lib.timezone")?;
+    if !root_value.is_null() {
+        assert!(!value.is_null());
+    }
+
+    tester.timer.log("Org unit setting");
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use crate::util;
+use eg::osrf::app::ApplicationWorker;
+use eg::osrf::client::Client;
+use eg::osrf::conf;
+use eg::osrf::message::MethodCall;
+use eg::osrf::method::{MethodDef, ParamCount};
+use eg::osrf::session::ServerSession;
+use eg::osrf::worker::{Worker, WorkerState, WorkerStateEvent};
+use eg::{EgError, EgResult, EgValue};
+use evergreen as eg;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const SERVICE: &str = "open-ils.rs-actor";
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    echo_large_array(tester)?;
+    tester.timer.log("echo_large_array()");
+
+    echo_atomic(tester)?;
+    tester.timer.log("echo_atomic()");
+
+    router_info(tester)?;
+    tester.timer.log("router_info()");
+
+    settings_reload(tester)?;
+    tester.timer.log("settings_reload()");
+
+    worker_retire_mid_request(tester)?;
+    tester.timer.log("worker_retire_mid_request()");
+
+    Ok(())
+}
+
+/// Responses larger than the service's max_chunk_size are delivered
+/// as partial messages, but the caller still sees a single value.
+fn echo_large_array(tester: &mut util::Tester) -> EgResult<()> {
+    let mut list = EgValue::new_array();
+    for i in 0..200_000 {
+        list.push(format!("Echo Item {i}"))?;
+    }
+
+    let mut responses = Vec::new();
+    let iter = tester
+        .client
+        .send_recv_iter(SERVICE, "opensrf.system.echo", vec![list])?;
+
+    for resp in iter {
+        responses.push(resp?);
+    }
+
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].len(), 200_000);
+    assert_eq!(responses[0][199_999].as_str(), Some("Echo Item 199999"));
+
+    Ok(())
+}
+
+/// Calling a method's atomic variant returns all of its responses
+/// in a single array.
+fn echo_atomic(tester: &mut util::Tester) -> EgResult<()> {
+    let params = vec![EgValue::from(1), EgValue::from("two"), eg::hash! {three: 3}];
+
+    let mut streamed = Vec::new();
+    let iter = tester
+        .client
+        .send_recv_iter(SERVICE, "opensrf.system.echo", params.clone())?;
+
+    for resp in iter {
+        streamed.push(resp?);
+    }
+
+    assert_eq!(streamed.len(), 3);
+
+    let mut atomic = Vec::new();
+    let iter = tester
+        .client
+        .send_recv_iter(SERVICE, "opensrf.system.echo.atomic", params)?;
+
+    for resp in iter {
+        atomic.push(resp?);
+    }
+
+    assert_eq!(atomic.len(), 1);
+    assert_eq!(atomic[0], EgValue::from(streamed));
+
+    // Introspection lists the atomic variant.
+    let method = tester
+        .client
+        .send_recv_one(
+            SERVICE,
+            "opensrf.system.method.all.atomic",
+            "opensrf.system.echo",
+        )?
+        .expect("Introspection returns a list");
+
+    assert!(method
+        .members()
+        .any(|m| m["api_name"].as_str() == Some("opensrf.system.echo.atomic")));
+
+    Ok(())
+}
+
+/// Register a service that does not exist, confirm the router reports
+/// it, then remove it.
+fn router_info(tester: &mut util::Tester) -> EgResult<()> {
+    let service = "_eg-test.router-info";
+    let client = &tester.client;
+    let domain = client.domain().to_string();
+    let router = conf::config().client().router_name().to_string();
+    let address = client.address().as_str().to_string();
+
+    client.send_router_command(&router, &domain, "register", Some(service))?;
+
+    assert!(client.router_services()?.iter().any(|s| s == service));
+
+    let nodes = client.router_info("opensrf.router.info.class.nodes", Some(service))?;
+    assert_eq!(nodes["node_count"].as_usize(), Some(1));
+    assert_eq!(nodes["nodes"][0].as_str(), Some(address.as_str()));
+
+    let summary = client.router_info("opensrf.router.info.stats.class.summary", None)?;
+    assert_eq!(summary[service].as_usize(), Some(0));
+
+    let err = client
+        .router_info("opensrf.router.info.no-such-method", None)
+        .expect_err("Unknown router method should fail");
+    assert!(err.to_string().contains("Method Not Found"));
+
+    client.send_router_command(&router, &domain, "unregister", Some(service))?;
+
+    assert!(!client.router_services()?.iter().any(|s| s == service));
+
+    Ok(())
+}
+
+/// Our client lives on the private domain, so it may ask a service
+/// to reload its host settings.
+fn settings_reload(tester: &mut util::Tester) -> EgResult<()> {
+    let method = format!("{SERVICE}.settings.reload");
+
+    let first = tester
+        .client
+        .send_recv_one(SERVICE, &method, None)?
+        .expect("Reload returns a generation")
+        .int()?;
+
+    let second = tester
+        .client
+        .send_recv_one(SERVICE, &method, None)?
+        .expect("Reload returns a generation")
+        .int()?;
+
+    assert!(second > first);
+
+    Ok(())
+}
+
+const SLOW_SERVICE: &str = "_eg-test.slow";
+const SLOW_METHOD: &str = "eg-test.slow";
+
+/// Set once the slow method has sent its response.
+static SLOW_RESPONDED: AtomicBool = AtomicBool::new(false);
+
+/// Set by worker_end() if the slow method responded first.
+static ENDED_AFTER_RESPONSE: AtomicBool = AtomicBool::new(false);
+
+struct SlowWorker {
+    methods: Arc<HashMap<String, MethodDef>>,
+}
+
+impl ApplicationWorker for SlowWorker {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn methods(&self) -> &Arc<HashMap<String, MethodDef>> {
+        &self.methods
+    }
+    fn worker_start(
+        &mut self,
+        _: Client,
+        methods: Arc<HashMap<String, MethodDef>>,
+    ) -> EgResult<()> {
+        self.methods = methods;
+        Ok(())
+    }
+    fn start_session(&mut self) -> EgResult<()> {
+        Ok(())
+    }
+    fn end_session(&mut self) -> EgResult<()> {
+        Ok(())
+    }
+    fn keepalive_timeout(&mut self) -> EgResult<()> {
+        Ok(())
+    }
+    fn api_call_error(&mut self, _: &MethodCall, _: EgError) {}
+    fn worker_idle_wake(&mut self, _: bool) -> EgResult<()> {
+        Ok(())
+    }
+    fn worker_end(&mut self) -> EgResult<()> {
+        let responded = SLOW_RESPONDED.load(Ordering::SeqCst);
+        ENDED_AFTER_RESPONSE.store(responded, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn slow_method(
+    _: &mut Box<dyn ApplicationWorker>,
+    session: &mut ServerSession,
+    _: &MethodCall,
+) -> EgResult<()> {
+    thread::sleep(Duration::from_secs(2));
+    session.respond_complete("done")?;
+    SLOW_RESPONDED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// A worker asked to retire in the middle of a slow request, e.g.
+/// because the server decided it was past its idle timeout, finishes
+/// the request before it exits.
+fn worker_retire_mid_request(tester: &mut util::Tester) -> EgResult<()> {
+    let client = &tester.client;
+    let domain = client.domain().to_string();
+    let router = conf::config().client().router_name().to_string();
+
+    client.send_router_command(&router, &domain, "register", Some(SLOW_SERVICE))?;
+
+    let mut methods = HashMap::new();
+    methods.insert(
+        SLOW_METHOD.to_string(),
+        MethodDef::new(SLOW_METHOD, ParamCount::Zero, slow_method),
+    );
+
+    let (to_parent_tx, from_worker_rx) = mpsc::sync_channel::<WorkerStateEvent>(10);
+    let retire = Arc::new(AtomicBool::new(false));
+
+    let methods = Arc::new(methods);
+    let worker_retire = retire.clone();
+
+    // Like Server, create the Worker within its own thread, since
+    // its Client cannot be sent between threads.
+    let handle = thread::spawn(move || -> EgResult<()> {
+        let mut worker = Worker::new(
+            SLOW_SERVICE.to_string(),
+            1,
+            mptc::signals::SignalTracker::new(),
+            methods,
+            to_parent_tx,
+            worker_retire,
+        )?;
+
+        worker.listen(|| {
+            Box::new(SlowWorker {
+                methods: Arc::new(HashMap::new()),
+            })
+        });
+
+        Ok(())
+    });
+
+    let mut ses = client.session(SLOW_SERVICE);
+    let mut req = ses.request(SLOW_METHOD, None)?;
+
+    // Wait for the worker to pick up the request, then retire it.
+    let evt = from_worker_rx
+        .recv_timeout(Duration::from_secs(10))
+        .map_err(|e| format!("Worker never picked up the request: {e}"))?;
+
+    assert_eq!(evt.state(), WorkerState::Active);
+    assert!(!SLOW_RESPONDED.load(Ordering::SeqCst));
+
+    retire.store(true, Ordering::Relaxed);
+
+    let resp = req.recv_with_timeout(10)?.expect("Slow method responds");
+    assert_eq!(resp.as_str(), Some("done"));
+
+    handle.join().expect("Worker thread exits cleanly")?;
+
+    assert!(ENDED_AFTER_RESPONSE.load(Ordering::SeqCst));
+
+    let states: Vec<WorkerState> = from_worker_rx.try_iter().map(|e| e.state()).collect();
+    assert_eq!(states, vec![WorkerState::Idle, WorkerState::Exiting]);
+
+    client.send_router_command(&router, &domain, "unregister", Some(SLOW_SERVICE))?;
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::util;
+use eg::common::settings::{self, OrgSettingTypes};
+use eg::osrf::app::WorkerCache;
+use eg::result::EgResult;
+use eg::samples;
+use eg::EgValue;
+use evergreen as eg;
+
+const PARENT_SETTING: &str = "_EG_TEST_.parent_only";
+const PROTECTED_SETTING: &str = "_EG_TEST_.protected";
+const UNSET_SETTING: &str = "_EG_TEST_.unset";
+const VIEW_PERM: &str = "_EG_TEST_VIEW_SETTING";
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    let e = &mut tester.editor;
+    let br1 = samples::AOU_BR1_ID;
+    let parent = e.retrieve("aou", br1)?.ok_or("BR1 exists")?["parent_ou"].int()?;
+
+    delete_test_settings(e)?;
+    create_test_settings(e, parent)?;
+    tester.timer.log("Created test settings");
+
+    let names = [PARENT_SETTING, PROTECTED_SETTING, UNSET_SETTING];
+    let values = settings::ancestor_values(e, br1, &names)?;
+
+    // Found at the parent org unit, with the value JSON-parsed.
+    assert_eq!(values[PARENT_SETTING]["org"].int()?, parent);
+    assert_eq!(values[PARENT_SETTING]["value"].as_str(), Some("parent"));
+    assert_eq!(values[PROTECTED_SETTING]["org"].int()?, br1);
+    assert!(values[UNSET_SETTING].is_null());
+    tester.timer.log("Retrieved ancestor setting values");
+
+    let mut cache = WorkerCache::new();
+    let mut types = OrgSettingTypes::new(&mut cache);
+    types.load(e, &names)?;
+
+    assert_eq!(types.view_perm(PARENT_SETTING), None);
+    assert_eq!(types.view_perm(PROTECTED_SETTING), Some(VIEW_PERM));
+
+    // Our requestor has not been granted the view permission.
+    assert!(types.can_view(e, PARENT_SETTING, br1)?);
+    assert!(!types.can_view(e, PROTECTED_SETTING, br1)?);
+    assert_eq!(
+        e.last_event().map(|evt| evt.textcode()),
+        Some("PERM_FAILURE")
+    );
+    tester.timer.log("Checked setting view permissions");
+
+    delete_test_settings(e)?;
+    tester.timer.log("Deleted test settings");
+
+    Ok(())
+}
+
+fn create_test_settings(e: &mut eg::Editor, parent: i64) -> EgResult<()> {
+    e.xact_begin()?;
+
+    let perm = e.create(EgValue::create(
+        "ppl",
+        eg::hash! {code: VIEW_PERM, description: VIEW_PERM},
+    )?)?;
+
+    e.create(EgValue::create(
+        "coust",
+        eg::hash! {name: PARENT_SETTING, label: PARENT_SETTING, datatype: "string"},
+    )?)?;
+
+    e.create(EgValue::create(
+        "coust",
+        eg::hash! {
+            name: PROTECTED_SETTING,
+            label: PROTECTED_SETTING,
+            datatype: "string",
+            view_perm: perm.id()?,
+        },
+    )?)?;
+
+    e.create(EgValue::create(
+        "aous",
+        eg::hash! {org_unit: parent, name: PARENT_SETTING, value: "\"parent\""},
+    )?)?;
+
+    e.create(EgValue::create(
+        "aous",
+        eg::hash! {org_unit: samples::AOU_BR1_ID, name: PROTECTED_SETTING, value: "\"secret\""},
+    )?)?;
+
+    e.commit()
+}
+
+/// Safe to call when no test settings exist.
+fn delete_test_settings(e: &mut eg::Editor) -> EgResult<()> {
+    let names = eg::array![PARENT_SETTING, PROTECTED_SETTING];
+
+    e.xact_begin()?;
+
+    let values = e.search("aous", eg::hash! {name: names.clone()})?;
+    e.delete_batch(values)?;
+
+    let types = e.search("coust", eg::hash! {name: names})?;
+    e.delete_batch(types)?;
+
+    let perms = e.search("ppl", eg::hash! {code: VIEW_PERM})?;
+    e.delete_batch(perms)?;
+
+    e.commit()
+}
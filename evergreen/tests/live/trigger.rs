@@ -0,0 +1,471 @@
+use crate::util;
+use eg::common::trigger::{self, runner, Event, EventState, Processor};
+use eg::result::EgResult;
+use eg::samples::SampleData;
+use eg::EgValue;
+use evergreen as eg;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+const TEST_REACTOR: &str = "_EG_TEST_::Reactor";
+const TEST_EVENT_DEF_NAME: &str = "_EG_TEST_ Custom Reactor";
+const TEST_HOOK: &str = "_EG_TEST_.hook";
+const TEST_HOOK_DEF_NAME: &str = "_EG_TEST_ Hook Events";
+const TEST_CLEANUP: &str = "_EG_TEST_::Cleanup";
+const TEST_CLEANUP_DEF_NAME: &str = "_EG_TEST_ Failed Cleanup";
+const TEST_RUNNER_DEF_NAME: &str = "_EG_TEST_ Runner";
+const TEST_PASSIVE_DEF_NAME: &str = "_EG_TEST_ 1 Day Overdue";
+const TEST_INTERRUPTED_DEF_NAME: &str = "_EG_TEST_ Interrupted";
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    delete_test_assets(tester)?;
+
+    custom_reactor(tester)?;
+    tester.timer.log("custom_reactor()");
+
+    hook_events(tester)?;
+    tester.timer.log("hook_events()");
+
+    failed_cleanup(tester)?;
+    tester.timer.log("failed_cleanup()");
+
+    run_pending(tester)?;
+    tester.timer.log("run_pending()");
+
+    interrupted_events(tester)?;
+    tester.timer.log("interrupted_events()");
+
+    passive_events(tester)?;
+    tester.timer.log("passive_events()");
+
+    delete_test_assets(tester)?;
+
+    Ok(())
+}
+
+fn delete_test_assets(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let query = eg::hash! {
+        name: [
+            TEST_EVENT_DEF_NAME,
+            TEST_HOOK_DEF_NAME,
+            TEST_CLEANUP_DEF_NAME,
+            TEST_RUNNER_DEF_NAME,
+            TEST_PASSIVE_DEF_NAME,
+            TEST_INTERRUPTED_DEF_NAME,
+        ],
+        owner: eg::samples::AOU_BR1_ID,
+    };
+
+    for def in e.search("atevdef", query)? {
+        for event in e.search("atev", eg::hash! {event_def: def.id()?})? {
+            e.delete(event)?;
+        }
+        e.delete(def)?;
+    }
+
+    if let Some(hook) = e.retrieve("ath", TEST_HOOK)? {
+        e.delete(hook)?;
+    }
+
+    e.commit()
+}
+
+fn custom_reactor(tester: &mut util::Tester) -> EgResult<()> {
+    trigger::register_reactor(TEST_REACTOR, |proc, events| {
+        log::info!("{proc} custom reactor handling {} event(s)", events.len());
+        Ok(())
+    })?;
+
+    // Registering the same name twice is not allowed.
+    assert!(trigger::register_reactor(TEST_REACTOR, |_, _| Ok(())).is_err());
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let def = eg::hash! {
+        active: "f",
+        owner: eg::samples::AOU_BR1_ID,
+        name: TEST_EVENT_DEF_NAME,
+        hook: "checkout",
+        validator: "NOOP_True",
+        reactor: TEST_REACTOR,
+    };
+
+    let def = e.create(EgValue::create("atevdef", def)?)?;
+    let def_id = def.id()?;
+
+    let event = eg::hash! {
+        event_def: def_id,
+        target: 1,
+        run_time: "now",
+        state: "valid",
+    };
+
+    let event = e.create(EgValue::create("atev", event)?)?;
+    let event_id = event.id()?;
+
+    e.commit()?;
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    let mut event = Event::from_source(atev)?;
+
+    let mut proc = Processor::new(e, def_id)?;
+    proc.react(&mut [&mut event])?;
+
+    // No cleanup, so reacted events complete.
+    assert_eq!(event.state(), EventState::Complete);
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("complete"));
+
+    Ok(())
+}
+
+fn hook_events(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+
+    let org = e
+        .retrieve("aou", eg::samples::AOU_BR1_ID)?
+        .ok_or_else(|| e.die_event())?;
+
+    // No definitions for the hook yet.
+    let created = trigger::create_events_for_hook(e, TEST_HOOK, &org, org.id()?, None, None, true)?;
+    assert!(created.is_empty());
+
+    e.xact_begin()?;
+
+    let hook = eg::hash! {
+        key: TEST_HOOK,
+        core_type: "aou",
+        description: "Live test hook",
+        passive: "f",
+    };
+
+    e.create(EgValue::create("ath", hook)?)?;
+
+    let def = eg::hash! {
+        active: "t",
+        owner: eg::samples::AOU_BR1_ID,
+        name: TEST_HOOK_DEF_NAME,
+        hook: TEST_HOOK,
+        validator: "NOOP_True",
+        reactor: "NOOP_True",
+    };
+
+    let def = e.create(EgValue::create("atevdef", def)?)?;
+    let def_id = def.id()?;
+
+    e.commit()?;
+
+    // Definition has no granularity.
+    let created = trigger::create_events_for_hook(
+        e,
+        TEST_HOOK,
+        &org,
+        org.id()?,
+        Some("_EG_TEST_"),
+        None,
+        true,
+    )?;
+    assert!(created.is_empty());
+
+    let created = trigger::create_events_for_hook(e, TEST_HOOK, &org, org.id()?, None, None, true)?;
+
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].event_def(), def_id);
+    assert_eq!(created[0].events().len(), 1);
+
+    // Processed through Reacted to Complete.
+    let event = &created[0].events()[0];
+    assert_eq!(event.state(), EventState::Complete);
+
+    let atev = e.retrieve("atev", event.id())?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("complete"));
+
+    // Waiting requires committed events.
+    e.xact_begin()?;
+    assert!(
+        trigger::create_events_for_hook(e, TEST_HOOK, &org, org.id()?, None, None, true).is_err()
+    );
+    e.rollback()?;
+
+    Ok(())
+}
+
+fn failed_cleanup(tester: &mut util::Tester) -> EgResult<()> {
+    trigger::register_cleanup(TEST_CLEANUP, |proc, events| {
+        log::info!("{proc} failing cleanup for {} event(s)", events.len());
+        Err("Cleanup failed on purpose".into())
+    })?;
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let def = eg::hash! {
+        active: "f",
+        owner: eg::samples::AOU_BR1_ID,
+        name: TEST_CLEANUP_DEF_NAME,
+        hook: "checkout",
+        validator: "NOOP_True",
+        reactor: "NOOP_True",
+        cleanup_success: TEST_CLEANUP,
+    };
+
+    let def = e.create(EgValue::create("atevdef", def)?)?;
+
+    let event = eg::hash! {
+        event_def: def.id()?,
+        target: 1,
+        run_time: "now",
+        state: "valid",
+    };
+
+    let event = e.create(EgValue::create("atev", event)?)?;
+    let event_id = event.id()?;
+
+    e.commit()?;
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    let mut event = Event::from_source(atev)?;
+
+    let mut proc = Processor::new(e, def.id()?)?;
+    assert!(proc.react(&mut [&mut event]).is_err());
+
+    // The reaction succeeded, but the event did not complete.
+    assert_eq!(event.state(), EventState::Cleaning);
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("cleaning"));
+    assert!(atev["complete_time"].is_null());
+
+    let output = e.retrieve("ateo", atev["error_output"].clone())?.unwrap();
+    assert!(output["data"].str()?.contains("Cleanup failed on purpose"));
+
+    Ok(())
+}
+
+fn run_pending(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let def = eg::hash! {
+        active: "t",
+        owner: eg::samples::AOU_BR1_ID,
+        name: TEST_RUNNER_DEF_NAME,
+        hook: "checkout",
+        validator: "NOOP_True",
+        reactor: "NOOP_True",
+    };
+
+    let def_id = e.create(EgValue::create("atevdef", def)?)?.id()?;
+
+    let event = eg::hash! {
+        event_def: def_id,
+        target: 1,
+        run_time: "now",
+    };
+
+    let event_id = e.create(EgValue::create("atev", event)?)?.id()?;
+
+    e.commit()?;
+
+    let pending = runner::fetch_pending_event_ids(e, None, &[def_id], None, 10)?;
+    assert_eq!(pending, vec![event_id]);
+
+    // A dry run validates the event without reacting or storing
+    // any state changes.
+    let mut stats = HashMap::new();
+    let state = runner::process_event(e, event_id, true, &mut stats)?;
+
+    assert_eq!(state, EventState::Valid);
+    assert_eq!(stats[&def_id].reacted, 1);
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("pending"));
+
+    let mut stats = HashMap::new();
+    let state = runner::process_event(e, event_id, false, &mut stats)?;
+
+    assert_eq!(state, EventState::Complete);
+
+    let stats = runner::sorted_stats(stats);
+    assert_eq!(stats[0].reacted, 1);
+    assert!(!runner::any_errors(&stats));
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("complete"));
+
+    let pending = runner::fetch_pending_event_ids(e, None, &[def_id], None, 10)?;
+    assert!(pending.is_empty());
+
+    Ok(())
+}
+
+fn interrupted_events(tester: &mut util::Tester) -> EgResult<()> {
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    let def = eg::hash! {
+        active: "t",
+        owner: eg::samples::AOU_BR1_ID,
+        name: TEST_INTERRUPTED_DEF_NAME,
+        hook: "checkout",
+        validator: "NOOP_True",
+        reactor: "NOOP_True",
+    };
+
+    let def_id = e.create(EgValue::create("atevdef", def)?)?.id()?;
+
+    // Left reacting by this process, as if interrupted.
+    let event = eg::hash! {
+        event_def: def_id,
+        target: 1,
+        run_time: "now",
+        state: "reacting",
+        update_time: "now",
+        update_process: format!("{}-1", std::process::id()),
+    };
+
+    let event_id = e.create(EgValue::create("atev", event)?)?.id()?;
+
+    e.commit()?;
+
+    assert_eq!(runner::finalize_interrupted(e, &[event_id])?, 1);
+
+    let mut atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("pending"));
+    assert!(!atev["error_output"].is_null());
+
+    // Recently updated events are not stale.
+    e.xact_begin()?;
+    atev["state"] = EgValue::from("collecting");
+    atev["update_time"] = EgValue::from("now");
+    e.update(atev)?;
+    e.commit()?;
+
+    assert_eq!(
+        runner::recover_stale_events(e, "1 hour", None, &[def_id])?,
+        0
+    );
+
+    let mut atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("collecting"));
+
+    e.xact_begin()?;
+    atev["update_time"] = EgValue::from("2000-01-01T00:00:00Z");
+    e.update(atev)?;
+    e.commit()?;
+
+    assert_eq!(
+        runner::recover_stale_events(e, "1 hour", None, &[def_id])?,
+        1
+    );
+
+    let mut atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("pending"));
+
+    // Events are only finalized by the process which left them.
+    e.xact_begin()?;
+    atev["state"] = EgValue::from("reacting");
+    atev["update_process"] = EgValue::from("0-1");
+    e.update(atev)?;
+    e.commit()?;
+
+    assert_eq!(runner::finalize_interrupted(e, &[event_id])?, 0);
+
+    // Another runner changes the event after we read it, but before
+    // we return it to pending.  Its change wins.
+    let seen = e
+        .json_query(eg::hash! {
+            select: {atev: ["id", "state", "update_time"]},
+            from: "atev",
+            where: {id: event_id},
+        })?
+        .pop()
+        .expect("Event exists");
+
+    let mut other = eg::Editor::new(&tester.client);
+    other.xact_begin()?;
+    let mut atev = other.retrieve_for_update("atev", event_id)?.unwrap();
+
+    // The reset waits on the other runner's lock.
+    let handle = thread::spawn(move || -> EgResult<bool> {
+        let client = eg::init::init_from_parts()?;
+        let mut editor = eg::Editor::new(&client);
+        runner::reset_event_if_unchanged(&mut editor, &seen, "Lost the race")
+    });
+
+    thread::sleep(Duration::from_secs(1));
+
+    atev["state"] = EgValue::from("complete");
+    atev["update_time"] = EgValue::from("now");
+    other.update(atev)?;
+    other.commit()?;
+
+    assert!(!handle.join().expect("Reset thread exits cleanly")?);
+
+    let atev = e.retrieve("atev", event_id)?.unwrap();
+    assert_eq!(atev["state"].as_str(), Some("complete"));
+
+    Ok(())
+}
+
+fn passive_events(tester: &mut util::Tester) -> EgResult<()> {
+    let samples = SampleData::with_suffix("PASSIVE");
+    let e = &mut tester.editor;
+
+    samples.delete_default_assets(e)?;
+
+    e.xact_begin()?;
+
+    let acn = samples.create_default_acn(e)?;
+    let acp = samples.create_default_acp(e, acn.id()?)?;
+    let user_id = samples.create_default_au(e)?.id()?;
+
+    // Due yesterday.  Whole-day durations push the due time to the
+    // end of the day, which would not yet be a full day overdue.
+    let circ_id = samples
+        .create_circ(e, acp.id()?, user_id, "-25 hours")?
+        .id()?;
+
+    // Max delay keeps older overdue circs out of the test.
+    let def = eg::hash! {
+        active: "t",
+        owner: samples.aou_id,
+        name: TEST_PASSIVE_DEF_NAME,
+        hook: "checkout.due",
+        validator: "NOOP_True",
+        reactor: "NOOP_True",
+        delay: "1 day",
+        max_delay: "2 days",
+        delay_field: "due_date",
+    };
+
+    let def_id = e.create(EgValue::create("atevdef", def)?)?.id()?;
+
+    e.commit()?;
+
+    let counts = trigger::create_passive_events(e, None, &[def_id])?;
+    assert!(counts[&def_id] >= 1);
+
+    let events = e.search("atev", eg::hash! {event_def: def_id, target: circ_id})?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["state"].as_str(), Some("pending"));
+
+    // Targets with an existing event are skipped.
+    let counts = trigger::create_passive_events(e, None, &[def_id])?;
+    assert_eq!(counts[&def_id], 0);
+
+    let events = e.search("atev", eg::hash! {event_def: def_id, target: circ_id})?;
+    assert_eq!(events.len(), 1);
+
+    samples.delete_default_assets(e)?;
+
+    Ok(())
+}
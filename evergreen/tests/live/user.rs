@@ -0,0 +1,64 @@
+use crate::util;
+use eg::common::user;
+use eg::constants as C;
+use eg::result::EgResult;
+use eg::samples::SampleData;
+use eg::EgValue;
+use evergreen as eg;
+
+pub fn run_live_tests(tester: &mut util::Tester) -> EgResult<()> {
+    tester.timer.start();
+
+    let samples = SampleData::with_suffix("SUMMARY");
+    let e = &mut tester.editor;
+
+    samples.delete_default_assets(e)?;
+
+    e.xact_begin()?;
+
+    let acn = samples.create_default_acn(e)?;
+    let acp = samples.create_default_acp(e, acn.id()?)?;
+    let user_id = samples.create_default_au(e)?.id()?;
+
+    let circ = samples.create_circ(e, acp.id()?, user_id, "-3 days")?;
+    samples.create_billing(e, circ.id()?, 1.25, C::BTYPE_OVERDUE_MATERIALS)?;
+    samples.create_hold(e, acp.id()?, user_id, samples.aou_id)?;
+
+    e.commit()?;
+    tester.timer.log("Created user summary assets");
+
+    let summary = user::summary(e, user_id)?;
+    tester.timer.log("Retrieved user summary");
+
+    let circs = e.json_query(eg::hash! {
+        select: {circ: [{column: "id", transform: "count", aggregate: 1, alias: "count"}]},
+        from: "circ",
+        where: {usr: user_id, checkin_time: EgValue::Null, xact_finish: EgValue::Null},
+    })?;
+    let circ_count = circs[0]["count"].int()?;
+
+    assert_eq!(circ_count, 1);
+    assert_eq!(summary["checkouts"]["total_out"].int()?, circ_count);
+    assert_eq!(summary["checkouts"]["overdue"].int()?, 1);
+
+    let holds = e.search(
+        "ahr",
+        eg::hash! {usr: user_id, fulfillment_time: EgValue::Null, cancel_time: EgValue::Null},
+    )?;
+
+    assert_eq!(summary["holds"]["total"].int()?, holds.len() as i64);
+    assert_eq!(summary["holds"]["ready"].int()?, 0);
+    assert_eq!(summary["holds"]["pending"].int()?, holds.len() as i64);
+
+    let mous = e.search("mous", eg::hash! {usr: user_id})?;
+    let balance = mous[0]["balance_owed"].float()?;
+
+    assert_eq!(balance, 1.25);
+    assert_eq!(summary["fines"]["balance_owed"].float()?, balance);
+    tester.timer.log("Compared user summary to direct queries");
+
+    samples.delete_default_assets(e)?;
+    tester.timer.log("Deleted user summary assets");
+
+    Ok(())
+}
@@ -53,6 +53,12 @@ impl Server {
         self.max_worker_reqs = v;
     }
 
+    /// Returns a clone of our signal tracker so other threads can
+    /// request a reload or shutdown.
+    pub fn signal_tracker(&self) -> SignalTracker {
+        self.sig_tracker.clone()
+    }
+
     fn next_worker_id(&mut self) -> u64 {
         self.worker_id_gen += 1;
         self.worker_id_gen
@@ -0,0 +1,226 @@
+//! SIP client access controls: source address allowlists and
+//! per-account session limits.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// An IP address range in CIDR notation, e.g. 10.0.0.0/8.
+///
+/// A bare address is treated as a single-host range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parse an IPv4 or IPv6 address with optional prefix length.
+    pub fn parse(s: &str) -> Result<Cidr, String> {
+        let (addr_str, prefix_op) = match s.trim().split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s.trim(), None),
+        };
+
+        let addr: IpAddr = addr_str
+            .parse()
+            .map_err(|e| format!("Invalid address in CIDR '{s}': {e}"))?;
+
+        let max_prefix = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix = match prefix_op {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in CIDR '{s}'"))?,
+            None => max_prefix,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+
+    /// True if the address falls within this range.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        // Treat IPv4-mapped IPv6 addresses as IPv4.
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*addr),
+            _ => *addr,
+        };
+
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(a) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(a) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// True if the allowlist is empty (i.e. unrestricted) or contains
+/// a range matching the address.
+pub fn address_allowed(allowlist: &[Cidr], addr: &IpAddr) -> bool {
+    allowlist.is_empty() || allowlist.iter().any(|c| c.contains(addr))
+}
+
+/// Count of active logged-in sessions per SIP username, shared by all
+/// Sessions.  Cloning is cheap.
+#[derive(Debug, Clone, Default)]
+pub struct AccountSessions {
+    counts: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl AccountSessions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Claim a session slot for the SIP username.
+    ///
+    /// Returns None if the account is already at its session limit.
+    /// The slot is released when the returned guard is dropped.
+    pub fn acquire(&self, username: &str, max: Option<usize>) -> Option<AccountSessionGuard> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(username.to_string()).or_insert(0);
+
+        if let Some(m) = max {
+            if *count >= m {
+                return None;
+            }
+        }
+
+        *count += 1;
+
+        Some(AccountSessionGuard {
+            username: username.to_string(),
+            sessions: self.clone(),
+        })
+    }
+
+    /// Number of active sessions for the SIP username.
+    pub fn count(&self, username: &str) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(username)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Active session counts for all SIP usernames.
+    pub fn counts(&self) -> HashMap<String, usize> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    fn release(&self, username: &str) {
+        // A poisoned lock means another session panicked while
+        // holding it.  Clean up anyway.
+        let mut counts = match self.counts.lock() {
+            Ok(c) => c,
+            Err(e) => e.into_inner(),
+        };
+
+        if let Some(count) = counts.get_mut(username) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.remove(username);
+            }
+        }
+    }
+}
+
+/// Held by a logged-in Session.  Releases the account session slot
+/// when dropped, regardless of how the Session exits.
+#[derive(Debug)]
+pub struct AccountSessionGuard {
+    username: String,
+    sessions: AccountSessions,
+}
+
+impl Drop for AccountSessionGuard {
+    fn drop(&mut self) {
+        self.sessions.release(&self.username);
+    }
+}
+
+#[test]
+fn test_cidr_contains() {
+    let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+
+    let net = Cidr::parse("192.168.10.0/24").unwrap();
+    assert!(net.contains(&addr("192.168.10.1")));
+    assert!(net.contains(&addr("192.168.10.255")));
+    assert!(!net.contains(&addr("192.168.11.1")));
+    assert!(net.contains(&addr("::ffff:192.168.10.7")));
+    assert!(!net.contains(&addr("::1")));
+
+    let host = Cidr::parse("10.1.2.3").unwrap();
+    assert!(host.contains(&addr("10.1.2.3")));
+    assert!(!host.contains(&addr("10.1.2.4")));
+
+    let all = Cidr::parse("0.0.0.0/0").unwrap();
+    assert!(all.contains(&addr("8.8.8.8")));
+
+    let v6 = Cidr::parse("2001:db8::/32").unwrap();
+    assert!(v6.contains(&addr("2001:db8:1::1")));
+    assert!(!v6.contains(&addr("2001:db9::1")));
+
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+    assert!(Cidr::parse("10.0.0/8").is_err());
+    assert!(Cidr::parse("2001:db8::/129").is_err());
+
+    assert!(address_allowed(&[], &addr("8.8.8.8")));
+    assert!(!address_allowed(&[net.clone(), host], &addr("8.8.8.8")));
+    assert!(address_allowed(&[net], &addr("192.168.10.9")));
+}
+
+#[test]
+fn test_account_session_limits() {
+    let sessions = AccountSessions::new();
+
+    let g1 = sessions.acquire("sip-user", Some(2)).unwrap();
+    let g2 = sessions.acquire("sip-user", Some(2)).unwrap();
+    assert!(sessions.acquire("sip-user", Some(2)).is_none());
+    assert_eq!(sessions.count("sip-user"), 2);
+
+    // Other accounts are unaffected.
+    let other = sessions.acquire("other-user", Some(1)).unwrap();
+
+    drop(g1);
+    assert_eq!(sessions.count("sip-user"), 1);
+
+    let g3 = sessions.acquire("sip-user", Some(2)).unwrap();
+
+    // Slots are released even when a session thread fails.
+    let s2 = sessions.clone();
+    let result = std::thread::spawn(move || {
+        let _guard = s2.acquire("other-user", None).unwrap();
+        panic!("session failed");
+    })
+    .join();
+
+    assert!(result.is_err());
+    assert_eq!(sessions.count("other-user"), 1);
+
+    drop(g2);
+    drop(g3);
+    drop(other);
+
+    assert_eq!(sessions.count("sip-user"), 0);
+    assert_eq!(sessions.count("other-user"), 0);
+
+    // No limit.
+    let guards: Vec<_> = (0..10)
+        .map(|_| sessions.acquire("sip-user", None).unwrap())
+        .collect();
+    assert_eq!(sessions.count("sip-user"), 10);
+    drop(guards);
+    assert_eq!(sessions.count("sip-user"), 0);
+}
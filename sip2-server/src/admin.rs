@@ -0,0 +1,616 @@
+//! Line-based admin protocol for inspecting and controlling a running
+//! server.
+//!
+//! Listens on a Unix socket and/or a loopback TCP port.  Each
+//! connection is served in its own thread.  Each request is a single
+//! line and each response is a single line of JSON.
+//!
+//! status                  - Live sessions and their accounts.
+//! disable-account <user>  - Remove a SIP account and close its sessions.
+//! add-account <json>      - Add or replace a SIP account.
+//! drop-session <sesid>    - Close a single session.
+//! reload-config           - Reload the config file.
+//! shutdown                - Start a graceful shutdown.
+//!
+//! If an admin secret is configured, the first line of each connection
+//! must be "auth <secret>".
+//!
+//! Accounts added or disabled via the admin protocol only live until
+//! the next config reload.
+use super::conf::{Config, SharedConfig};
+use super::shutdown::ShutdownCoordinator;
+use mptc::signals::SignalTracker;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Wake this often to check for new connections and shutdown.
+const ADMIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Admin clients are disconnected after this long without a request.
+const ADMIN_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Requests longer than this are rejected.
+const ADMIN_MAX_LINE_LENGTH: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Status,
+    DisableAccount(String),
+    AddAccount(String),
+    DropSession(u64),
+    ReloadConfig,
+    Shutdown,
+}
+
+impl TryFrom<&str> for AdminCommand {
+    type Error = String;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        let line = line.trim();
+
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((c, a)) => (c, a.trim()),
+            None => (line, ""),
+        };
+
+        let require_arg = |name: &str| {
+            if arg.is_empty() {
+                Err(format!("{command} requires a {name}"))
+            } else {
+                Ok(arg.to_string())
+            }
+        };
+
+        match command {
+            "status" => Ok(Self::Status),
+            "disable-account" => Ok(Self::DisableAccount(require_arg("username")?)),
+            "add-account" => Ok(Self::AddAccount(require_arg("JSON account")?)),
+            "drop-session" => {
+                let id = require_arg("session ID")?;
+                id.parse::<u64>()
+                    .map(Self::DropSession)
+                    .map_err(|_| format!("Invalid session ID: {id}"))
+            }
+            "reload-config" => Ok(Self::ReloadConfig),
+            "shutdown" => Ok(Self::Shutdown),
+            _ => Err(format!("Unknown command: {command}")),
+        }
+    }
+}
+
+pub struct AdminListener {
+    unix_listener: Option<UnixListener>,
+    tcp_listener: Option<TcpListener>,
+
+    /// Path of our Unix socket, removed at exit.
+    socket_path: Option<String>,
+
+    secret: Option<String>,
+    sip_config: SharedConfig,
+    sip_config_file: String,
+    shutdown: ShutdownCoordinator,
+}
+
+impl AdminListener {
+    /// Create an admin listener if an admin socket or port is
+    /// configured.
+    pub fn from_config(
+        sip_config: SharedConfig,
+        sip_config_file: &str,
+        shutdown: ShutdownCoordinator,
+    ) -> Result<Option<Self>, String> {
+        let config = sip_config.current();
+
+        if config.admin_socket().is_none() && config.admin_port().is_none() {
+            return Ok(None);
+        }
+
+        let mut listener = AdminListener {
+            unix_listener: None,
+            tcp_listener: None,
+            socket_path: None,
+            secret: config.admin_secret().map(|s| s.to_string()),
+            sip_config: sip_config.clone(),
+            sip_config_file: sip_config_file.to_string(),
+            shutdown,
+        };
+
+        if let Some(path) = config.admin_socket() {
+            listener.unix_listener = Some(bind_unix_socket(path)?);
+            listener.socket_path = Some(path.to_string());
+            log::info!("Admin listener running at {path}");
+        }
+
+        if let Some(port) = config.admin_port() {
+            // Admin connections are local-only.
+            let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+                .map_err(|e| format!("Cannot bind admin port {port}: {e}"))?;
+
+            tcp.set_nonblocking(true)
+                .map_err(|e| format!("Cannot configure admin port: {e}"))?;
+
+            listener.tcp_listener = Some(tcp);
+            log::info!("Admin listener running at 127.0.0.1:{port}");
+        }
+
+        if listener.secret.is_none() {
+            log::warn!("No admin-secret configured; admin connections are not authenticated");
+        }
+
+        Ok(Some(listener))
+    }
+
+    /// Handle admin connections in a new thread until the server
+    /// shuts down.
+    pub fn spawn(self, signals: SignalTracker) {
+        thread::spawn(move || self.listen(signals));
+    }
+
+    fn listen(self, signals: SignalTracker) {
+        // Shared with the connection threads.
+        let admin = Arc::new(self);
+        admin.accept_loop(&signals);
+    }
+
+    fn accept_loop(self: &Arc<Self>, signals: &SignalTracker) {
+        while !self.shutdown.shutting_down() {
+            let mut accepted = false;
+
+            if let Some(listener) = self.unix_listener.as_ref() {
+                accepted |= self.handle_accept(signals, listener.accept());
+            }
+
+            if let Some(listener) = self.tcp_listener.as_ref() {
+                accepted |= self.handle_accept(signals, listener.accept());
+            }
+
+            if !accepted {
+                thread::sleep(ADMIN_POLL_INTERVAL);
+            }
+        }
+
+        if let Some(path) = self.socket_path.as_ref() {
+            std::fs::remove_file(path).ok();
+        }
+
+        log::debug!("Admin listener exiting");
+    }
+
+    /// Serve a newly accepted connection in a new thread, so an idle
+    /// client does not block other admin clients.
+    ///
+    /// Returns true if a connection was accepted.
+    fn handle_accept<S: AdminStream + Send + 'static, A>(
+        self: &Arc<Self>,
+        signals: &SignalTracker,
+        result: io::Result<(S, A)>,
+    ) -> bool {
+        let stream = match result {
+            Ok((s, _)) => s,
+            Err(e) => {
+                if e.kind() != ErrorKind::WouldBlock {
+                    log::error!("Admin listener accept() failed: {e}");
+                }
+                return false;
+            }
+        };
+
+        let reader = match stream.prepare() {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("Admin connection setup failed: {e}");
+                return true;
+            }
+        };
+
+        let admin = self.clone();
+        let signals = signals.clone();
+
+        thread::spawn(move || admin.serve(&signals, BufReader::new(reader), stream));
+
+        true
+    }
+
+    /// Respond to each request line until the client disconnects.
+    fn serve(&self, signals: &SignalTracker, mut reader: impl BufRead, mut writer: impl Write) {
+        let mut authenticated = self.secret.is_none();
+
+        loop {
+            let line = match read_line(&mut reader) {
+                Ok(Some(l)) => l,
+                Ok(None) => return,
+                Err(e) => {
+                    log::warn!("Admin connection failed: {e}");
+                    return;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = if authenticated {
+                self.handle_request(signals, &line)
+            } else if self.authenticate(&line) {
+                authenticated = true;
+                json::object! {status: "ok"}
+            } else {
+                log::warn!("Admin connection failed authentication");
+                let resp = error_response("Authentication required");
+                writeln!(writer, "{}", resp.dump()).ok();
+                return;
+            };
+
+            if let Err(e) = writeln!(writer, "{}", response.dump()) {
+                log::warn!("Cannot write admin response: {e}");
+                return;
+            }
+        }
+    }
+
+    /// True if the line is an "auth" request with the correct secret.
+    fn authenticate(&self, line: &str) -> bool {
+        let secret = match self.secret.as_ref() {
+            Some(s) => s,
+            None => return true,
+        };
+
+        match line.trim().split_once(char::is_whitespace) {
+            Some(("auth", given)) => secrets_match(given.trim(), secret),
+            _ => false,
+        }
+    }
+
+    fn handle_request(&self, signals: &SignalTracker, line: &str) -> json::JsonValue {
+        let command = match AdminCommand::try_from(line) {
+            Ok(c) => c,
+            Err(e) => return error_response(&e),
+        };
+
+        // Avoid logging account passwords.
+        match command {
+            AdminCommand::AddAccount(_) => log::info!("Admin request: add-account"),
+            _ => log::info!("Admin request: {}", line.trim()),
+        }
+
+        match self.handle_command(signals, command) {
+            Ok(mut resp) => {
+                resp["status"] = "ok".into();
+                resp
+            }
+            Err(e) => {
+                log::warn!("Admin request failed: {e}");
+                error_response(&e)
+            }
+        }
+    }
+
+    fn handle_command(
+        &self,
+        signals: &SignalTracker,
+        command: AdminCommand,
+    ) -> Result<json::JsonValue, String> {
+        match command {
+            AdminCommand::Status => Ok(self.status()),
+
+            AdminCommand::DisableAccount(username) => {
+                let removed = self
+                    .sip_config
+                    .update(|c| Ok(c.remove_account(&username)))?;
+
+                if !removed {
+                    return Err(format!("No such SIP account: {username}"));
+                }
+
+                let dropped = self.shutdown.registry().terminate_account(&username);
+
+                Ok(json::object! {account: username, dropped_sessions: dropped})
+            }
+
+            AdminCommand::AddAccount(text) => {
+                // JSON is a subset of YAML, so accounts use the same
+                // format as the config file.
+                let docs = yaml_rust::YamlLoader::load_from_str(&text)
+                    .map_err(|e| format!("Invalid account JSON: {e}"))?;
+
+                let account = docs
+                    .first()
+                    .ok_or_else(|| "Account JSON is empty".to_string())?;
+
+                self.sip_config.update(|c| c.add_account(account))?;
+
+                Ok(json::object! {account: account["sip-username"].as_str()})
+            }
+
+            AdminCommand::DropSession(id) => {
+                if !self.shutdown.registry().terminate(id) {
+                    return Err(format!("No such session: {id}"));
+                }
+                Ok(json::object! {session: id})
+            }
+
+            AdminCommand::ReloadConfig => {
+                // Verify the config loads so a broken file is reported
+                // here instead of just in the server logs.
+                let mut config = Config::new();
+                config.read_yaml(&self.sip_config_file)?;
+
+                signals.request_reload();
+
+                Ok(json::object! {config: self.sip_config_file.as_str()})
+            }
+
+            AdminCommand::Shutdown => {
+                signals.request_graceful_shutdown();
+                Ok(json::JsonValue::new_object())
+            }
+        }
+    }
+
+    fn status(&self) -> json::JsonValue {
+        let mut sessions = json::JsonValue::new_array();
+
+        for info in self.shutdown.registry().sessions() {
+            sessions
+                .push(json::object! {
+                    id: info.id,
+                    label: info.label,
+                    account: info.account,
+                    peer: info.peer,
+                    last_activity: info.last_activity,
+                    requests: info.requests,
+                })
+                .ok();
+        }
+
+        json::object! {
+            shutting_down: self.shutdown.shutting_down(),
+            accounts: self.sip_config.current().accounts().count(),
+            sessions: sessions,
+        }
+    }
+}
+
+/// Unix and TCP admin connections.
+trait AdminStream: Read + Write + Sized {
+    /// Make the stream blocking, with a read timeout, and return a
+    /// clone for reading.
+    fn prepare(&self) -> io::Result<Self>;
+}
+
+impl AdminStream for UnixStream {
+    fn prepare(&self) -> io::Result<Self> {
+        self.set_nonblocking(false)?;
+        self.set_read_timeout(Some(ADMIN_READ_TIMEOUT))?;
+        self.try_clone()
+    }
+}
+
+impl AdminStream for TcpStream {
+    fn prepare(&self) -> io::Result<Self> {
+        self.set_nonblocking(false)?;
+        self.set_read_timeout(Some(ADMIN_READ_TIMEOUT))?;
+        self.try_clone()
+    }
+}
+
+/// Bind the Unix socket, replacing any stale socket file left behind
+/// by a previous run.  Only the owner may connect.
+fn bind_unix_socket(path: &str) -> Result<UnixListener, String> {
+    if Path::new(path).exists() {
+        std::fs::remove_file(path)
+            .map_err(|e| format!("Cannot remove stale admin socket {path}: {e}"))?;
+    }
+
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("Cannot bind admin socket {path}: {e}"))?;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Cannot set admin socket permissions: {e}"))?;
+
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Cannot configure admin socket: {e}"))?;
+
+    Ok(listener)
+}
+
+/// Read one request line.  Returns None on EOF.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, String> {
+    let mut line = String::new();
+
+    let count = reader
+        .take(ADMIN_MAX_LINE_LENGTH as u64)
+        .read_line(&mut line)
+        .map_err(|e| format!("{e}"))?;
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with('\n') && count == ADMIN_MAX_LINE_LENGTH {
+        return Err("Admin request too large".to_string());
+    }
+
+    Ok(Some(line))
+}
+
+/// Compare every byte so timing does not reveal how much matched.
+fn secrets_match(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn error_response(error: &str) -> json::JsonValue {
+    json::object! {status: "error", error: error}
+}
+
+#[cfg(test)]
+fn test_listener(secret: Option<&str>) -> AdminListener {
+    let path = std::env::temp_dir().join(format!("eg-sip2-admin-test-{}.yml", std::process::id()));
+
+    std::fs::write(
+        &path,
+        "setting-groups:\n  - name: default\n    institution: example\n",
+    )
+    .unwrap();
+
+    let sip_config_file = path.to_str().unwrap().to_string();
+
+    let mut config = Config::new();
+    config.read_yaml(&sip_config_file).unwrap();
+
+    AdminListener {
+        unix_listener: None,
+        tcp_listener: None,
+        socket_path: None,
+        secret: secret.map(|s| s.to_string()),
+        sip_config: SharedConfig::new(config),
+        sip_config_file,
+        shutdown: ShutdownCoordinator::new(
+            super::registry::SessionRegistry::new(),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        ),
+    }
+}
+
+#[cfg(test)]
+fn run_requests(
+    listener: &AdminListener,
+    signals: &SignalTracker,
+    requests: &str,
+) -> Vec<json::JsonValue> {
+    let mut output = Vec::new();
+    listener.serve(signals, requests.as_bytes(), &mut output);
+
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| json::parse(l).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_parse_admin_command() {
+    assert_eq!(AdminCommand::try_from("status\n"), Ok(AdminCommand::Status));
+    assert_eq!(
+        AdminCommand::try_from("disable-account  sipuser "),
+        Ok(AdminCommand::DisableAccount("sipuser".to_string()))
+    );
+    assert_eq!(
+        AdminCommand::try_from("drop-session 12"),
+        Ok(AdminCommand::DropSession(12))
+    );
+    assert_eq!(
+        AdminCommand::try_from(r#"add-account {"sip-username": "x"}"#),
+        Ok(AdminCommand::AddAccount(
+            r#"{"sip-username": "x"}"#.to_string()
+        ))
+    );
+    assert!(AdminCommand::try_from("drop-session abc").is_err());
+    assert!(AdminCommand::try_from("disable-account").is_err());
+    assert!(AdminCommand::try_from("reboot").is_err());
+}
+
+#[test]
+fn test_admin_commands() {
+    let listener = test_listener(None);
+    let signals = SignalTracker::new();
+
+    let requests = [
+        r#"add-account {"sip-username": "sipuser", "sip-password": "sippass", "ils-username": "admin", "settings": "default"}"#,
+        r#"add-account {"sip-username": "other", "sip-password": "sippass", "ils-username": "admin", "settings": "nope"}"#,
+        "status",
+        "drop-session 99",
+        "disable-account sipuser",
+        "disable-account sipuser",
+        "reload-config",
+    ];
+
+    let responses = run_requests(&listener, &signals, &requests.join("\n"));
+
+    assert_eq!(responses.len(), requests.len());
+
+    assert_eq!(responses[0]["status"].as_str(), Some("ok"));
+    assert_eq!(responses[0]["account"].as_str(), Some("sipuser"));
+
+    // Unknown settings group
+    assert_eq!(responses[1]["status"].as_str(), Some("error"));
+
+    assert_eq!(responses[2]["accounts"].as_usize(), Some(1));
+    assert!(responses[2]["sessions"].is_array());
+
+    assert_eq!(responses[3]["status"].as_str(), Some("error"));
+
+    assert_eq!(responses[4]["status"].as_str(), Some("ok"));
+    assert_eq!(responses[4]["dropped_sessions"].as_usize(), Some(0));
+    assert_eq!(responses[5]["status"].as_str(), Some("error"));
+    assert!(listener
+        .sip_config
+        .current()
+        .get_account("sipuser")
+        .is_none());
+
+    assert_eq!(responses[6]["status"].as_str(), Some("ok"));
+    assert!(signals.reload_requested());
+
+    std::fs::remove_file(&listener.sip_config_file).ok();
+}
+
+#[test]
+fn test_admin_auth() {
+    let listener = test_listener(Some("s3cret"));
+    let signals = SignalTracker::new();
+
+    let responses = run_requests(&listener, &signals, "status\nstatus\n");
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0]["status"].as_str(), Some("error"));
+
+    let responses = run_requests(&listener, &signals, "auth wrong\nstatus\n");
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0]["status"].as_str(), Some("error"));
+
+    let responses = run_requests(&listener, &signals, "auth s3cret\nstatus\nshutdown\n");
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[1]["status"].as_str(), Some("ok"));
+    assert_eq!(responses[1]["shutting_down"].as_bool(), Some(false));
+    assert!(signals.graceful_shutdown_requested());
+}
+
+#[test]
+fn test_admin_concurrent_connections() {
+    let mut listener = test_listener(None);
+
+    let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    tcp.set_nonblocking(true).unwrap();
+    let addr = tcp.local_addr().unwrap();
+
+    listener.tcp_listener = Some(tcp);
+    listener.spawn(SignalTracker::new());
+
+    // An idle client does not hold up the next one.
+    let _idle = TcpStream::connect(addr).unwrap();
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    writeln!(client, "status").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(client).read_line(&mut line).unwrap();
+
+    let response = json::parse(&line).unwrap();
+    assert_eq!(response["shutting_down"].as_bool(), Some(false));
+}
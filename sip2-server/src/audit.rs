@@ -0,0 +1,538 @@
+//! Structured audit trail of SIP traffic.
+//!
+//! Each inbound and outbound SIP message is written as a single line
+//! of JSON, with sensitive field values redacted.
+use evergreen as eg;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+
+/// Replaces the value of redacted fields.
+pub const REDACTED: &str = "***";
+
+/// Fields which are always redacted: patron password, SIP login
+/// password, and the payment transaction ID, which may carry card data.
+pub const DEFAULT_REDACT_FIELDS: &[&str] = &["AD", "CO", "BK"];
+
+/// Syslog priority used when no facility is configured: LOCAL1 / INFO.
+const DEFAULT_SYSLOG_PRIORITY: u8 = (17 << 3) | 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditTarget {
+    File(String),
+    Syslog,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    target: AuditTarget,
+
+    /// Roll the log file over once it reaches this many bytes.
+    max_size: Option<u64>,
+
+    /// Fields whose values are replaced with REDACTED.
+    redact_fields: Vec<String>,
+
+    syslog_priority: u8,
+}
+
+impl AuditConfig {
+    pub fn new(target: AuditTarget) -> Self {
+        AuditConfig {
+            target,
+            max_size: None,
+            redact_fields: DEFAULT_REDACT_FIELDS
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            syslog_priority: DEFAULT_SYSLOG_PRIORITY,
+        }
+    }
+
+    /// Parse the audit-log config section.
+    ///
+    /// Returns None if audit logging is not enabled.
+    pub fn from_yaml(node: &yaml_rust::Yaml) -> Result<Option<Self>, String> {
+        if !node["enabled"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let target = if let Some(file) = node["file"].as_str() {
+            AuditTarget::File(file.to_string())
+        } else if node["syslog"].as_bool().unwrap_or(false) {
+            AuditTarget::Syslog
+        } else {
+            return Err("audit-log requires a file or syslog target".to_string());
+        };
+
+        let mut conf = AuditConfig::new(target);
+
+        if let Some(v) = node["max-size"].as_i64() {
+            conf.max_size = Some(v as u64);
+        }
+
+        if let Some(facility) = node["syslog-facility"].as_str() {
+            let facility = syslog_facility(facility)
+                .ok_or_else(|| format!("Invalid audit-log syslog-facility: {facility}"))?;
+            conf.syslog_priority = (facility << 3) | 6; // INFO
+        }
+
+        if let Some(fields) = node["redact-fields"].as_vec() {
+            for field in fields {
+                if let Some(code) = field.as_str() {
+                    conf.add_redact_field(code);
+                }
+            }
+        }
+
+        Ok(Some(conf))
+    }
+
+    pub fn target(&self) -> &AuditTarget {
+        &self.target
+    }
+
+    pub fn redact_fields(&self) -> &[String] {
+        &self.redact_fields
+    }
+
+    /// Add a field to redact on top of the defaults.
+    pub fn add_redact_field(&mut self, code: &str) {
+        if !self.redact_fields.iter().any(|f| f == code) {
+            self.redact_fields.push(code.to_string());
+        }
+    }
+}
+
+/// Maps e.g. "LOCAL1" to its syslog facility number.
+fn syslog_facility(name: &str) -> Option<u8> {
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("LOG_").unwrap_or(&name);
+
+    match name {
+        "USER" => Some(1),
+        "DAEMON" => Some(3),
+        "AUTH" => Some(4),
+        "LOCAL0" => Some(16),
+        "LOCAL1" => Some(17),
+        "LOCAL2" => Some(18),
+        "LOCAL3" => Some(19),
+        "LOCAL4" => Some(20),
+        "LOCAL5" => Some(21),
+        "LOCAL6" => Some(22),
+        "LOCAL7" => Some(23),
+        _ => None,
+    }
+}
+
+/// Who sent a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Session details included with each audit entry.
+pub struct AuditContext<'a> {
+    pub sesid: u64,
+    pub peer: Option<IpAddr>,
+    pub account: Option<&'a str>,
+    /// Log trace shared with the OpenSRF calls made for the message.
+    pub trace: Option<&'a str>,
+}
+
+enum Writer {
+    File(fs::File),
+    Syslog(UnixDatagram),
+}
+
+impl Writer {
+    fn open(config: &AuditConfig) -> Result<Writer, String> {
+        match config.target() {
+            AuditTarget::File(path) => fs::File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(Writer::File)
+                .map_err(|e| format!("Cannot open audit log {path}: {e}")),
+            AuditTarget::Syslog => eg::osrf::logging::Logger::writer().map(Writer::Syslog),
+        }
+    }
+}
+
+/// An enabled audit log.
+struct AuditState {
+    config: AuditConfig,
+    writer: Option<Writer>,
+}
+
+impl AuditState {
+    /// Build the JSON audit entry for a message, redacting as needed.
+    fn to_json(
+        &self,
+        ctx: &AuditContext,
+        direction: Direction,
+        msg: &sip2::Message,
+    ) -> json::JsonValue {
+        let mut fixed_fields = json::JsonValue::new_array();
+        for ff in msg.fixed_fields() {
+            fixed_fields.push(ff.value()).ok();
+        }
+
+        let mut fields = json::JsonValue::new_array();
+        for f in msg.fields() {
+            let value = if self.config.redact_fields().iter().any(|c| c == f.code()) {
+                REDACTED
+            } else {
+                f.value()
+            };
+
+            let mut field = json::JsonValue::new_object();
+            field[f.code()] = value.into();
+            fields.push(field).ok();
+        }
+
+        json::object! {
+            timestamp: eg::date::to_iso(&eg::date::now()),
+            sesid: ctx.sesid,
+            peer: ctx.peer.map(|p| p.to_string()),
+            account: ctx.account,
+            trace: ctx.trace,
+            direction: match direction {
+                Direction::Inbound => "inbound",
+                Direction::Outbound => "outbound",
+            },
+            code: msg.spec().code,
+            fixed_fields: fixed_fields,
+            fields: fields,
+        }
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), String> {
+        if self.writer.is_none() {
+            self.writer = Some(Writer::open(&self.config)?);
+        }
+
+        match self.writer.as_mut().unwrap() {
+            Writer::File(file) => {
+                file.write_all(format!("{line}\n").as_bytes())
+                    .map_err(|e| format!("{e}"))?;
+
+                self.maybe_rollover()?;
+            }
+            Writer::Syslog(socket) => {
+                let msg = format!("<{}>eg-sip2-audit: {line}", self.config.syslog_priority);
+                socket.send(msg.as_bytes()).map_err(|e| format!("{e}"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the log file aside once it reaches its max size.
+    ///
+    /// The previous file is kept with a ".1" suffix.
+    fn maybe_rollover(&mut self) -> Result<(), String> {
+        let (max_size, path) = match (self.config.max_size, self.config.target()) {
+            (Some(m), AuditTarget::File(p)) => (m, p),
+            _ => return Ok(()),
+        };
+
+        let size = match self.writer.as_ref() {
+            Some(Writer::File(f)) => f.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => return Ok(()),
+        };
+
+        if size < max_size {
+            return Ok(());
+        }
+
+        fs::rename(path, format!("{path}.1"))
+            .map_err(|e| format!("Cannot roll over audit log {path}: {e}"))?;
+
+        self.writer = Some(Writer::open(&self.config)?);
+
+        Ok(())
+    }
+}
+
+/// Writes audit entries.
+///
+/// Clones share one log, so the Server and all Sessions write through
+/// the same file handle, including across config reloads.
+#[derive(Clone, Default)]
+pub struct AuditLogger {
+    /// None if audit logging is disabled.
+    state: Arc<Mutex<Option<AuditState>>>,
+}
+
+impl AuditLogger {
+    /// Create a logger, which is disabled if no config is provided.
+    pub fn new(config: Option<AuditConfig>) -> Result<Self, String> {
+        let logger = AuditLogger::default();
+        logger.reload(config)?;
+        Ok(logger)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().is_some()
+    }
+
+    /// Apply a new config, or disable logging, in place.
+    ///
+    /// The log is reopened, so log rotation tools can signal us with
+    /// SIGHUP.  On error, the current log is left as-is.
+    pub fn reload(&self, config: Option<AuditConfig>) -> Result<(), String> {
+        // Verify our target is usable before replacing the current log.
+        let state = match config {
+            Some(config) => Some(AuditState {
+                writer: Some(Writer::open(&config)?),
+                config,
+            }),
+            None => None,
+        };
+
+        *self.state.lock().unwrap() = state;
+
+        Ok(())
+    }
+
+    /// Write an audit entry for a message, if enabled.
+    ///
+    /// Audit failures are logged but do not interrupt the Session.
+    pub fn log(&self, ctx: &AuditContext, direction: Direction, msg: &sip2::Message) {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        let line = state.to_json(ctx, direction, msg).dump();
+
+        if let Err(e) = state.write(&line) {
+            log::error!("Audit log write failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_log_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("sip2-audit-{name}-{}.log", std::process::id()))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[test]
+fn test_audit_redaction() {
+    let path = test_log_path("redact");
+
+    let mut config = AuditConfig::new(AuditTarget::File(path.clone()));
+    config.add_redact_field("XY");
+
+    let logger = AuditLogger::new(Some(config)).unwrap();
+
+    let ctx = AuditContext {
+        sesid: 7,
+        peer: Some("127.0.0.1".parse().unwrap()),
+        account: Some("sip-user"),
+        trace: Some("1700000000000-00001"),
+    };
+
+    let login = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", "sip-user"), ("CO", "sip-secret-pass")],
+    )
+    .unwrap();
+
+    let patron = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", "patron-barcode"),
+            ("AD", "patron-secret-pin"),
+            ("XY", "custom-secret"),
+        ],
+    )
+    .unwrap();
+
+    let payment = sip2::Message::from_values(
+        &sip2::spec::M_FEE_PAID,
+        &[&sip2::util::sip_date_now(), "01", "02", "USD"],
+        &[("AA", "patron-barcode"), ("BK", "4111111111111111")],
+    )
+    .unwrap();
+
+    logger.log(&ctx, Direction::Inbound, &login);
+    logger.log(&ctx, Direction::Inbound, &patron);
+    logger.log(&ctx, Direction::Inbound, &payment);
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).ok();
+
+    assert!(!text.contains("sip-secret-pass"));
+    assert!(!text.contains("patron-secret-pin"));
+    assert!(!text.contains("custom-secret"));
+    assert!(!text.contains("4111111111111111"));
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+
+    let entry = json::parse(lines[1]).unwrap();
+    assert_eq!(entry["sesid"].as_u64(), Some(7));
+    assert_eq!(entry["peer"].as_str(), Some("127.0.0.1"));
+    assert_eq!(entry["account"].as_str(), Some("sip-user"));
+    assert_eq!(entry["trace"].as_str(), Some("1700000000000-00001"));
+    assert_eq!(entry["direction"].as_str(), Some("inbound"));
+    assert_eq!(entry["code"].as_str(), Some("23"));
+    assert_eq!(entry["fields"][0]["AA"].as_str(), Some("patron-barcode"));
+    assert_eq!(entry["fields"][1]["AD"].as_str(), Some(REDACTED));
+    assert_eq!(entry["fields"][2]["XY"].as_str(), Some(REDACTED));
+}
+
+#[test]
+fn test_audit_redacts_cc_arg_fields() {
+    let path = test_log_path("cc-fields");
+    let conf_path = test_log_path("cc-fields-conf");
+
+    let yaml = format!(
+        r#"
+audit-log:
+  enabled: true
+  file: "{path}"
+setting-groups:
+  - name: default
+    institution: example
+    cc-arg-fields:
+      number: "ZC"
+accounts:
+  - sip-username: sip-user
+    sip-password: pass
+    ils-username: admin
+    settings: default
+"#
+    );
+
+    fs::write(&conf_path, yaml).unwrap();
+
+    let mut sip_config = crate::conf::Config::new();
+    let loaded = sip_config.read_yaml(&conf_path);
+    fs::remove_file(&conf_path).ok();
+    loaded.unwrap();
+
+    // Not listed in redact-fields, but mapped as a card field.
+    let config = sip_config.audit_log().cloned().unwrap();
+    assert!(config.redact_fields().iter().any(|f| f == "ZC"));
+
+    let logger = AuditLogger::new(Some(config)).unwrap();
+
+    let ctx = AuditContext {
+        sesid: 1,
+        peer: None,
+        account: Some("sip-user"),
+        trace: None,
+    };
+
+    let payment = sip2::Message::from_values(
+        &sip2::spec::M_FEE_PAID,
+        &[&sip2::util::sip_date_now(), "01", "02", "USD"],
+        &[("AA", "patron-barcode"), ("ZC", "1111")],
+    )
+    .unwrap();
+
+    logger.log(&ctx, Direction::Inbound, &payment);
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).ok();
+
+    let entry = json::parse(text.lines().next().unwrap()).unwrap();
+    assert_eq!(entry["fields"][0]["AA"].as_str(), Some("patron-barcode"));
+    assert_eq!(entry["fields"][1]["ZC"].as_str(), Some(REDACTED));
+}
+
+#[test]
+fn test_audit_rollover() {
+    let path = test_log_path("rollover");
+    let rolled = format!("{path}.1");
+
+    let mut config = AuditConfig::new(AuditTarget::File(path.clone()));
+    config.max_size = Some(100);
+
+    let logger = AuditLogger::new(Some(config)).unwrap();
+
+    let ctx = AuditContext {
+        sesid: 1,
+        peer: None,
+        account: None,
+        trace: None,
+    };
+
+    let msg = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", "sip-user"), ("CO", "sip-pass")],
+    )
+    .unwrap();
+
+    // Each entry exceeds the max size, forcing a rollover.
+    logger.log(&ctx, Direction::Inbound, &msg);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    assert!(fs::read_to_string(&rolled).unwrap().contains("\"CN\""));
+
+    logger.log(&ctx, Direction::Outbound, &msg);
+    assert!(fs::read_to_string(&rolled)
+        .unwrap()
+        .contains("\"outbound\""));
+
+    fs::remove_file(&path).ok();
+    fs::remove_file(&rolled).ok();
+}
+
+#[test]
+fn test_audit_reload() {
+    let path = test_log_path("reload");
+    let path2 = test_log_path("reload2");
+
+    let logger = AuditLogger::new(None).unwrap();
+    assert!(!logger.is_enabled());
+
+    // e.g. a Session started before the reload.
+    let session_logger = logger.clone();
+
+    let ctx = AuditContext {
+        sesid: 1,
+        peer: None,
+        account: None,
+        trace: None,
+    };
+
+    let msg = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", "sip-user"), ("CO", "sip-pass")],
+    )
+    .unwrap();
+
+    let config = AuditConfig::new(AuditTarget::File(path.clone()));
+    logger.reload(Some(config)).unwrap();
+    assert!(session_logger.is_enabled());
+
+    session_logger.log(&ctx, Direction::Inbound, &msg);
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+    let config = AuditConfig::new(AuditTarget::File(path2.clone()));
+    logger.reload(Some(config)).unwrap();
+
+    session_logger.log(&ctx, Direction::Outbound, &msg);
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+    assert_eq!(fs::read_to_string(&path2).unwrap().lines().count(), 1);
+
+    logger.reload(None).unwrap();
+    assert!(!session_logger.is_enabled());
+
+    fs::remove_file(&path).ok();
+    fs::remove_file(&path2).ok();
+}
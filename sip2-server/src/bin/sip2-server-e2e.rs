@@ -2,8 +2,22 @@ use eg::samples::SampleData;
 use eg::EgValue;
 use evergreen as eg;
 use getopts;
+use rand::Rng;
 use sip2;
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::panic;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Connections beyond the server's max-clients must be closed within
+/// this many seconds.
+const REJECT_TIMEOUT: u64 = 5;
+
+/// Declarative tests which run before the Rust tests.
+const LOGIN_SCRIPT: &str = include_str!("../../e2e/login.yml");
 
 fn is_zero(n: &str) -> bool {
     if let Ok(f) = n.parse::<f64>() {
@@ -33,6 +47,18 @@ impl Timer {
 }
 
 struct Tester {
+    sip_host: String,
+    tls: bool,
+    /// Server fails the first item info request of each session.
+    transport_failure: bool,
+    circ_status_map: Option<(i64, String)>,
+    /// Locale of the SIP account.
+    locale: Option<String>,
+    /// Time zone of the SIP account.
+    timezone: Option<String>,
+    /// Workstation of the SIP account, which allows automatic
+    /// workstation registration.
+    auto_workstation: Option<String>,
     sip_user: String,
     sip_pass: String,
     institution: String,
@@ -46,7 +72,65 @@ const HELP_TEXT: &str = r#"
     --sip-port
     --sip-user
     --sip-pass
+    --tls
+        Connect to the SIP server using TLS.  The server certificate
+        is not verified, so self-signed certificates may be used.
+    --circ-status-map <copy-status>:<sip-status>
+        The SIP account item-mapping maps this copy status to this
+        SIP circulation status, e.g. 15:08.  Verifies item information
+        responses use the mapping.
+    --locale <locale>
+        The SIP account is configured with this locale.  For es-ES,
+        verifies screen messages are translated using the es-ES
+        catalog shipped with the server.
+    --timezone <timezone>
+        The SIP account is configured with this timezone, e.g.
+        America/Denver.  Verifies checkout due dates are reported
+        in that time zone.
+    --auto-workstation <name>
+        The SIP account is configured with this workstation and
+        allow-auto-workstation: true.  Deletes the workstation, then
+        verifies a new SIP login registers it again and checkouts
+        record it.  No other SIP clients may be logged in to the
+        account.
+    --sample-suffix
+        Append this value to the barcodes of test copies and patrons,
+        so multiple testers can run against the same database.
+    --transport-failure
+        The SIP account is configured with
+        simulate-transport-failure: "17".  Verifies item information
+        requests succeed after the server reconnects to OpenSRF.
+    --script <file>
+        Run the declarative tests in this YAML file after the
+        built-in tests.  See sip2-server/e2e/login.yml for the
+        format.  May be repeated.  sip2-server/e2e/patron-register.yml
+        tests patron self-registration, which the SIP account must
+        allow.
+    --filter <substring>
+        Only run tests whose name contains this value.  Tests marked
+        "always" still run.
+    --repeat <count>
+        Run the selected tests this many times.
+    --stress
+        Instead of the functional tests, run a randomized mix of item
+        information, patron status, and patron information requests
+        on many concurrent SIP connections, then report latencies
+        per message type.
+    --stress-threads <count>
+        Number of concurrent SIP connections.  Defaults to 10.
+    --stress-duration <seconds>
+        Send requests for this long.  Defaults to 30.
+    --stress-requests <count>
+        Send this many requests per connection instead of running
+        for a duration.
+    --stress-max-clients <count>
+        The server's max-clients value.  After the stress run, open
+        this many connections, counting the tester's own, then verify
+        one more is rejected within 5 seconds.  No other SIP clients
+        may be connected.
     --help
+
+Exits with a non-zero status if any test fails.
 "#;
 
 fn main() -> Result<(), String> {
@@ -59,6 +143,21 @@ fn main() -> Result<(), String> {
     opts.optopt("", "sip-user", "", "");
     opts.optopt("", "sip-pass", "", "");
     opts.optopt("", "institution", "", "");
+    opts.optflag("", "tls", "");
+    opts.optflag("", "transport-failure", "");
+    opts.optopt("", "sample-suffix", "", "");
+    opts.optopt("", "circ-status-map", "", "");
+    opts.optopt("", "locale", "", "");
+    opts.optopt("", "timezone", "", "");
+    opts.optopt("", "auto-workstation", "", "");
+    opts.optmulti("", "script", "", "");
+    opts.optopt("", "filter", "", "");
+    opts.optopt("", "repeat", "", "");
+    opts.optflag("", "stress", "");
+    opts.optopt("", "stress-threads", "", "");
+    opts.optopt("", "stress-duration", "", "");
+    opts.optopt("", "stress-requests", "", "");
+    opts.optopt("", "stress-max-clients", "", "");
 
     let params = match opts.parse(&args[1..]) {
         Ok(p) => p,
@@ -83,18 +182,61 @@ fn main() -> Result<(), String> {
         .unwrap();
     let sip_host = format!("{host}:{port}");
 
-    let editor = eg::Editor::new(ctx.client());
+    let repeat = match params.opt_str("repeat") {
+        Some(r) => r
+            .parse::<usize>()
+            .map_err(|e| format!("Invalid --repeat value {r}: {e}"))?,
+        None => 1,
+    };
+
+    let stress = if params.opt_present("stress") {
+        Some(StressOptions::from_params(&params)?)
+    } else {
+        None
+    };
+
+    // Load scripts up front so errors are reported before any
+    // test assets are created.
+    let mut tests = parse_script(LOGIN_SCRIPT)?
+        .into_iter()
+        .map(TestCase::Script)
+        .collect::<Vec<_>>();
+
+    let mut script_tests = Vec::new();
+    for file in params.opt_strs("script") {
+        let text = std::fs::read_to_string(&file)
+            .map_err(|e| format!("Cannot read script {file}: {e}"))?;
+
+        let script = parse_script(&text).map_err(|e| format!("Script {file}: {e}"))?;
+
+        script_tests.extend(script.into_iter().map(TestCase::Script));
+    }
+
+    let editor = eg::Editor::new(&ctx);
 
     let t = Timer::new();
-    let sipcon = sip2::Connection::new(&sip_host).expect("Error creating SIP connection");
+    let sipcon = sip_connect(&sip_host, params.opt_present("tls"))?;
     t.done("SIP Connect");
 
     //std::thread::sleep(std::time::Duration::from_secs(15));
 
     let mut tester = Tester {
         sipcon,
+        tls: params.opt_present("tls"),
+        transport_failure: params.opt_present("transport-failure"),
+        circ_status_map: match params.opt_str("circ-status-map") {
+            Some(s) => Some(parse_circ_status_map(&s)?),
+            None => None,
+        },
+        locale: params.opt_str("locale"),
+        timezone: params.opt_str("timezone"),
+        auto_workstation: params.opt_str("auto-workstation"),
+        sip_host,
         editor,
-        samples: SampleData::new(),
+        samples: match params.opt_str("sample-suffix") {
+            Some(suffix) => SampleData::with_suffix(&suffix),
+            None => SampleData::new(),
+        },
         sip_user: params
             .opt_get_default("sip-user", "sip-user".to_string())
             .unwrap(),
@@ -116,9 +258,19 @@ fn main() -> Result<(), String> {
 
     println!("--------------------------------------");
 
-    if let Err(e) = run_tests(&mut tester) {
-        eprintln!("Tester exited with error: {e}");
-    };
+    tests.extend(rust_tests(&tester));
+    tests.extend(script_tests);
+
+    let filter = params.opt_str("filter");
+
+    let mut failures = 0;
+    if let Some(stress) = stress {
+        failures += run_stress(&tester, &stress);
+    } else {
+        for _ in 0..repeat {
+            failures += run_tests(&mut tester, &tests, filter.as_deref());
+        }
+    }
 
     println!("--------------------------------------");
 
@@ -128,406 +280,1872 @@ fn main() -> Result<(), String> {
 
     tester.sipcon.disconnect().ok();
 
+    if failures > 0 {
+        return Err(format!("{failures} test(s) failed"));
+    }
+
     Ok(())
 }
 
-fn run_tests(tester: &mut Tester) -> Result<(), String> {
-    test_invalid_login(tester)?;
-    test_valid_login(tester)?;
-
-    // Run whatever tests we can multiple times to get a sense of
-    // timing for multiple scenarios.
-
-    test_sc_status(tester)?;
-    test_invalid_item_info(tester)?;
-    test_item_info(tester, false)?;
-    test_patron_status(tester)?;
-    test_patron_info(tester, false)?;
+impl Tester {
+    /// Values for test script placeholders.
+    fn placeholders(&self) -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("sip_user", self.sip_user.clone()),
+            ("sip_pass", self.sip_pass.clone()),
+            ("institution", self.institution.clone()),
+            ("sip_date", sip2::util::sip_date_now()),
+            ("acp_barcode", self.samples.acp_barcode.clone()),
+            ("au_barcode", self.samples.au_barcode.clone()),
+            ("aou_shortname", self.samples.aou_shortname.clone()),
+            ("register_email", self.register_email()),
+        ])
+    }
 
-    test_checkout(tester)?;
-    test_item_info(tester, true)?;
-    test_patron_status(tester)?;
-    test_patron_info(tester, true)?;
+    /// Email address of staged users created by registration tests.
+    fn register_email(&self) -> String {
+        format!(
+            "{}@example.org",
+            self.samples.derive("REGISTER").to_lowercase()
+        )
+    }
+}
 
-    // Checkout a second time to force a renewal.
-    test_checkout(tester)?;
-    test_item_info(tester, true)?;
-    test_checkin(tester)?;
+fn parse_circ_status_map(s: &str) -> Result<(i64, String), String> {
+    let (stat, code) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid --circ-status-map: {s}"))?;
 
-    test_item_info(tester, false)?;
-    test_patron_status(tester)?;
-    test_patron_info(tester, false)?;
+    let stat = stat
+        .parse::<i64>()
+        .map_err(|e| format!("Invalid --circ-status-map copy status {stat}: {e}"))?;
 
-    test_checkout(tester)?;
-    test_item_info(tester, true)?;
-    test_patron_status(tester)?;
-    test_patron_info(tester, true)?;
-    test_checkin(tester)?;
+    Ok((stat, code.to_string()))
+}
 
-    test_checkin_with_transit(tester)?;
+fn sip_connect(sip_host: &str, tls: bool) -> Result<sip2::Connection, String> {
+    if tls {
+        sip2::Connection::new_tls(sip_host, false)
+    } else {
+        sip2::Connection::new(sip_host)
+    }
+    .map_err(|e| format!("Error creating SIP connection: {e}"))
+}
 
-    Ok(())
+/// A test run by the harness.
+enum TestCase {
+    Rust {
+        name: &'static str,
+        run: fn(&mut Tester) -> Result<(), String>,
+    },
+    Script(ScriptTest),
 }
 
-fn create_test_assets(tester: &mut Tester) -> Result<(), String> {
-    let e = &mut tester.editor;
+impl TestCase {
+    fn rust(name: &'static str, run: fn(&mut Tester) -> Result<(), String>) -> TestCase {
+        TestCase::Rust { name, run }
+    }
 
-    e.xact_begin()?;
+    fn name(&self) -> &str {
+        match self {
+            TestCase::Rust { name, .. } => name,
+            TestCase::Script(t) => &t.name,
+        }
+    }
 
-    let acn = tester.samples.create_default_acn(e)?;
-    tester.samples.create_default_acp(e, acn.id()?)?;
-    tester.samples.create_default_au(e)?;
+    /// True if the test runs regardless of --filter.
+    fn always(&self) -> bool {
+        match self {
+            TestCase::Rust { .. } => false,
+            TestCase::Script(t) => t.always,
+        }
+    }
 
-    e.commit().map_err(|e| e.to_string())
+    fn run(&self, tester: &mut Tester) -> Result<(), String> {
+        match self {
+            TestCase::Rust { run, .. } => run(tester),
+            TestCase::Script(t) => run_script_test(tester, t),
+        }
+    }
 }
 
-fn delete_test_assets(tester: &mut Tester) -> Result<(), String> {
-    let e = &mut tester.editor;
-
-    e.xact_begin()?;
+/// Response value check from a test script.
+enum Matcher {
+    Equals(String),
+    Contains(String),
+    Regex(regex::Regex),
+}
 
-    tester.samples.delete_default_acp(e)?;
-    tester.samples.delete_default_acn(e)?;
-    tester.samples.delete_default_au(e)?;
+/// Response value checked by a test script.
+enum Target {
+    Field(String),
+    FixedField(usize),
+}
 
-    e.commit()?;
+struct Assertion {
+    target: Target,
+    matcher: Matcher,
+}
 
-    Ok(())
+/// A request / response test loaded from a YAML test script.
+struct ScriptTest {
+    name: String,
+    always: bool,
+    spec: &'static sip2::spec::Message,
+    fixed_fields: Vec<String>,
+    fields: Vec<(String, String)>,
+    response_code: Option<String>,
+    fixed_field_count: Option<usize>,
+    assertions: Vec<Assertion>,
 }
 
-fn test_invalid_login(tester: &mut Tester) -> Result<(), String> {
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_LOGIN,
-        &[
-            "0", // UID algo
-            "0", // PW algo
-        ],
-        &[
-            ("CN", &format!("+23423+")), // SIP login username
-            ("CO", &format!("+29872+")), // SIP login password
-        ],
-    )
-    .unwrap();
+/// Parse a YAML test script.  See e2e/login.yml.
+fn parse_script(text: &str) -> Result<Vec<ScriptTest>, String> {
+    let mut docs =
+        YamlLoader::load_from_str(text).map_err(|e| format!("Invalid test script: {e}"))?;
 
-    let t = Timer::new();
-    let resp = tester
-        .sipcon
-        .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_invalid_login");
+    if docs.is_empty() {
+        return Ok(Vec::new());
+    }
 
-    assert_eq!(resp.spec().code, sip2::spec::M_LOGIN_RESP.code);
-    assert_eq!(resp.fixed_fields().len(), 1);
-    assert_eq!(resp.fixed_fields()[0].value(), "0");
+    let root = docs.remove(0);
 
-    Ok(())
-}
+    let entries = root
+        .as_vec()
+        .ok_or_else(|| "Test script must be a list of tests".to_string())?;
 
-fn test_valid_login(tester: &mut Tester) -> Result<(), String> {
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_LOGIN,
-        &[
-            "0", // UID algo
-            "0", // PW algo
-        ],
-        &[
-            ("CN", &tester.sip_user), // SIP login username
-            ("CO", &tester.sip_pass), // SIP login password
-        ],
-    )
-    .unwrap();
+    let mut tests = Vec::new();
 
-    let t = Timer::new();
-    let resp = tester
-        .sipcon
-        .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_valid_login");
+    for entry in entries {
+        let name = entry["name"]
+            .as_str()
+            .ok_or_else(|| format!("Test requires a name: {entry:?}"))?;
 
-    assert_eq!(resp.spec().code, sip2::spec::M_LOGIN_RESP.code);
-    assert_eq!(resp.fixed_fields().len(), 1);
-    assert_eq!(resp.fixed_fields()[0].value(), "1");
+        tests.push(parse_script_test(name, entry).map_err(|e| format!("Test {name}: {e}"))?);
+    }
 
-    Ok(())
+    Ok(tests)
 }
 
-fn test_sc_status(tester: &mut Tester) -> Result<(), String> {
-    let req = sip2::Message::from_ff_values(
-        &sip2::spec::M_SC_STATUS,
-        &[
-            "0",   // status code
-            "999", // max print width
-            &sip2::spec::SIP_PROTOCOL_VERSION,
-        ],
-    )
-    .unwrap();
+fn parse_script_test(name: &str, entry: &Yaml) -> Result<ScriptTest, String> {
+    let request = &entry["request"];
 
-    let t = Timer::new();
-    let resp = tester
-        .sipcon
-        .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_sc_status");
+    let code = yaml_string(&request["code"]).ok_or_else(|| "request code required".to_string())?;
 
-    assert!(resp.fixed_fields().len() > 0);
-    assert_eq!(resp.fixed_fields()[0].value(), "Y");
+    let spec = match request["extension"].as_vec() {
+        Some(lengths) => register_extension(&code, lengths)?,
+        None => sip2::spec::Message::from_code(&code)
+            .ok_or_else(|| format!("Unknown message code: {code}"))?,
+    };
 
-    Ok(())
-}
+    let mut fixed_fields = Vec::new();
+    if let Some(list) = request["fixed-fields"].as_vec() {
+        for value in list {
+            fixed_fields
+                .push(yaml_string(value).ok_or_else(|| format!("Invalid fixed field: {value:?}"))?);
+        }
+    }
 
-fn test_invalid_item_info(tester: &mut Tester) -> Result<(), String> {
-    let dummy = "I-AM-BAD-BARCODE";
+    let mut fields = Vec::new();
+    if let Some(hash) = request["fields"].as_hash() {
+        for (code, value) in hash {
+            let code = code
+                .as_str()
+                .ok_or_else(|| format!("Invalid field code: {code:?}"))?;
 
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_ITEM_INFO,
-        &[&sip2::util::sip_date_now()],
-        &[("AB", dummy), ("AO", &tester.institution)],
-    )
-    .unwrap();
+            let value = yaml_string(value)
+                .ok_or_else(|| format!("Invalid value for field {code}: {value:?}"))?;
 
-    let t = Timer::new();
-    let resp = tester
-        .sipcon
-        .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_invalid_item_info");
+            fields.push((code.to_string(), value));
+        }
+    }
 
-    let circ_status = resp.fixed_fields()[0].value();
-    let barcode = resp.get_field_value("AB");
-    let title = resp.get_field_value("AJ");
+    let expect = &entry["expect"];
 
-    // We should get title/barcode fields in the response.
-    assert!(barcode.is_some());
-    assert!(title.is_some());
+    if let (Some(code), Some(lengths)) =
+        (yaml_string(&expect["code"]), expect["extension"].as_vec())
+    {
+        register_extension(&code, lengths)?;
+    }
 
-    assert_eq!(barcode.unwrap(), dummy);
-    assert_eq!(title.unwrap(), "");
-    assert_eq!(circ_status, "01");
+    let mut assertions = Vec::new();
 
-    Ok(())
-}
+    if let Some(list) = expect["fixed-fields"].as_vec() {
+        for check in list {
+            let index = check["index"]
+                .as_i64()
+                .ok_or_else(|| format!("Fixed field check requires an index: {check:?}"))?;
 
-fn test_item_info(tester: &mut Tester, charged: bool) -> Result<(), String> {
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_ITEM_INFO,
-        &[&sip2::util::sip_date_now()],
-        &[
-            ("AB", &tester.samples.acp_barcode),
-            ("AO", &tester.institution),
-        ],
-    )
-    .unwrap();
+            assertions.push(Assertion {
+                target: Target::FixedField(index as usize),
+                matcher: parse_matcher(check)?,
+            });
+        }
+    }
 
-    let t = Timer::new();
-    let resp = tester
-        .sipcon
-        .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_item_info");
+    if let Some(list) = expect["fields"].as_vec() {
+        for check in list {
+            let code = check["code"]
+                .as_str()
+                .ok_or_else(|| format!("Field check requires a code: {check:?}"))?;
 
-    let circ_status = resp.fixed_fields()[0].value();
-    let barcode = resp.get_field_value("AB");
-    let title = resp.get_field_value("AJ");
+            assertions.push(Assertion {
+                target: Target::Field(code.to_string()),
+                matcher: parse_matcher(check)?,
+            });
+        }
+    }
 
-    // We should get title/barcode fields in the response.
-    assert!(barcode.is_some());
-    assert!(title.is_some());
+    Ok(ScriptTest {
+        name: name.to_string(),
+        always: entry["always"].as_bool().unwrap_or(false),
+        spec,
+        fixed_fields,
+        fields,
+        response_code: yaml_string(&expect["code"]),
+        fixed_field_count: expect["fixed-field-count"].as_i64().map(|c| c as usize),
+        assertions,
+    })
+}
 
-    assert_eq!(barcode.unwrap(), tester.samples.acp_barcode);
-    assert_ne!(title.unwrap(), "");
-    if charged {
-        assert_eq!(circ_status, "04");
+/// Register a vendor extension message whose fixed fields have these
+/// lengths, so scripts can send and receive custom message codes.
+fn register_extension(
+    code: &str,
+    lengths: &[Yaml],
+) -> Result<&'static sip2::spec::Message, String> {
+    let mut fixed_fields: Vec<&'static sip2::spec::FixedField> = Vec::new();
+
+    for length in lengths {
+        let length = length
+            .as_i64()
+            .filter(|l| *l > 0)
+            .ok_or_else(|| format!("Invalid fixed field length: {length:?}"))?;
+
+        // Message specs are static.  Leaking a few is fine here.
+        fixed_fields.push(Box::leak(Box::new(sip2::spec::FixedField {
+            label: "extension",
+            length: length as usize,
+        })));
+    }
+
+    sip2::spec::Message::register_extension(code, "Extension", fixed_fields.leak())
+        .map_err(|e| format!("Cannot register extension message {code}: {e}"))
+}
+
+fn parse_matcher(check: &Yaml) -> Result<Matcher, String> {
+    if let Some(v) = yaml_string(&check["equals"]) {
+        Ok(Matcher::Equals(v))
+    } else if let Some(v) = yaml_string(&check["contains"]) {
+        Ok(Matcher::Contains(v))
+    } else if let Some(v) = check["regex"].as_str() {
+        regex::Regex::new(v)
+            .map(Matcher::Regex)
+            .map_err(|e| format!("Invalid regex {v}: {e}"))
     } else {
-        // May be available or reshelving
-        assert!(circ_status.eq("03") || circ_status.eq("09"));
+        Err(format!(
+            "Check requires equals, contains, or regex: {check:?}"
+        ))
     }
+}
 
-    if let Some(dest) = resp.get_field_value("CT") {
-        assert_eq!(dest, tester.samples.aou_shortname);
+/// Numeric values are allowed where strings are expected, since
+/// e.g. an unquoted message code is a YAML integer.
+fn yaml_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) => Some(s.to_string()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Real(r) => Some(r.to_string()),
+        _ => None,
     }
+}
 
-    assert_eq!(
-        resp.get_field_value("BG").unwrap(),
-        tester.samples.aou_shortname
-    );
-    assert_eq!(
-        resp.get_field_value("AP").unwrap(),
-        tester.samples.aou_shortname
-    );
-    assert!(is_zero(resp.get_field_value("BV").unwrap())); // fee amount
+/// Replace {name} placeholders with their values.
+fn expand(value: &str, vars: &HashMap<&str, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = value;
 
-    if let Some(ql) = resp.get_field_value("CF") {
-        assert_eq!(ql, "0"); // hold queue len
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|e| start + e)
+            .ok_or_else(|| format!("Unterminated placeholder in '{value}'"))?;
+
+        let name = &rest[start + 1..end];
+
+        let var = vars
+            .get(name)
+            .ok_or_else(|| format!("Unknown placeholder {{{name}}} in '{value}'"))?;
+
+        result += &rest[..start];
+        result += var;
+        rest = &rest[end + 1..];
     }
 
-    assert_eq!(resp.get_field_value("CK").unwrap(), "001"); // media type
+    result += rest;
 
-    Ok(())
+    Ok(result)
 }
 
-fn test_patron_status(tester: &mut Tester) -> Result<(), String> {
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_PATRON_STATUS,
-        &["000", &sip2::util::sip_date_now()],
-        &[
-            ("AA", &tester.samples.au_barcode),
-            ("AD", &tester.samples.au_barcode),
-            ("AO", &tester.institution),
-        ],
-    )
-    .unwrap();
+/// A test from the built-in login script.
+fn builtin_test(name: &str) -> Result<ScriptTest, String> {
+    parse_script(LOGIN_SCRIPT)?
+        .into_iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| format!("No such built-in test: {name}"))
+}
+
+fn run_script_test(tester: &mut Tester, test: &ScriptTest) -> Result<(), String> {
+    let vars = tester.placeholders();
+
+    let fixed_fields = test
+        .fixed_fields
+        .iter()
+        .map(|v| expand(v, &vars))
+        .collect::<Result<Vec<String>, String>>()?;
+
+    let mut fields = Vec::new();
+    for (code, value) in &test.fields {
+        fields.push((code.as_str(), expand(value, &vars)?));
+    }
+
+    let ff_refs: Vec<&str> = fixed_fields.iter().map(|v| v.as_str()).collect();
+    let field_refs: Vec<(&str, &str)> = fields.iter().map(|(c, v)| (*c, v.as_str())).collect();
+
+    let req = sip2::Message::from_values(test.spec, &ff_refs, &field_refs)
+        .map_err(|e| format!("Cannot build request: {e}"))?;
 
     let t = Timer::new();
     let resp = tester
         .sipcon
         .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_patron_status");
-
-    assert_eq!(
-        resp.get_field_value("AA").unwrap(),
-        tester.samples.au_barcode
-    );
-    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
-    assert_eq!(resp.get_field_value("CQ").unwrap(), "Y"); // valid password
-
-    if let Some(fee) = resp.get_field_value("BV") {
-        assert!(is_zero(fee));
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done(&test.name);
+
+    if let Some(code) = test.response_code.as_deref() {
+        if resp.spec().code != code {
+            return Err(format!(
+                "Expected response {code}, got {}",
+                resp.spec().code
+            ));
+        }
     }
 
-    let status = resp.fixed_fields()[0].value();
-    assert_eq!(status.len(), 14);
+    if let Some(count) = test.fixed_field_count {
+        if resp.fixed_fields().len() != count {
+            return Err(format!(
+                "Expected {count} fixed fields, got {}",
+                resp.fixed_fields().len()
+            ));
+        }
+    }
 
-    // Legacy EG sip server will set a Y on the 'recall denied' field,
-    // regardless of patron, because it does not support recalls.
-    if status.contains("Y") {
-        assert_eq!(&status[2..3], "Y");
+    for assertion in &test.assertions {
+        let (label, value) = match &assertion.target {
+            Target::Field(code) => (format!("field {code}"), resp.get_field_value(code)),
+            Target::FixedField(idx) => (
+                format!("fixed field {idx}"),
+                resp.fixed_fields().get(*idx).map(|f| f.value()),
+            ),
+        };
+
+        let value = value.ok_or_else(|| format!("Response has no {label}"))?;
+
+        let ok = match &assertion.matcher {
+            Matcher::Equals(v) => value == expand(v, &vars)?,
+            Matcher::Contains(v) => value.contains(&expand(v, &vars)?),
+            Matcher::Regex(r) => r.is_match(value),
+        };
+
+        if !ok {
+            let wanted = match &assertion.matcher {
+                Matcher::Equals(v) => format!("equal to '{v}'"),
+                Matcher::Contains(v) => format!("containing '{v}'"),
+                Matcher::Regex(r) => format!("matching /{r}/"),
+            };
+
+            return Err(format!("Expected {label} {wanted}, got '{value}'"));
+        }
     }
 
     Ok(())
 }
 
-fn test_patron_info(tester: &mut Tester, charged: bool) -> Result<(), String> {
-    let summary = "          ";
+/// Run the tests matching the filter, returning the number of
+/// failures.
+///
+/// Failed tests, including those which panic, are reported and the
+/// remaining tests still run.
+fn run_tests(tester: &mut Tester, tests: &[TestCase], filter: Option<&str>) -> usize {
+    let mut failures = 0;
+
+    for test in tests {
+        if let Some(f) = filter {
+            if !test.always() && !test.name().contains(f) {
+                continue;
+            }
+        }
 
-    let req = sip2::Message::from_values(
-        &sip2::spec::M_PATRON_INFO,
-        &["000", &sip2::util::sip_date_now(), summary],
-        &[
-            ("AA", &tester.samples.au_barcode),
-            ("AD", &tester.samples.au_barcode),
-            ("AO", &tester.institution),
-        ],
-    )
-    .unwrap();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| test.run(tester)));
 
-    let t = Timer::new();
+        let error = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => e,
+            Err(p) => match p.downcast_ref::<String>() {
+                Some(s) => s.to_string(),
+                None => p.downcast_ref::<&str>().unwrap_or(&"panicked").to_string(),
+            },
+        };
+
+        eprintln!("FAIL\t{}: {error}", test.name());
+        failures += 1;
+    }
+
+    failures
+}
+
+struct StressOptions {
+    threads: usize,
+    duration: Duration,
+    /// Per thread.  Overrides duration.
+    requests: Option<usize>,
+    max_clients: Option<usize>,
+}
+
+impl StressOptions {
+    fn from_params(params: &getopts::Matches) -> Result<StressOptions, String> {
+        let number = |name: &str| -> Result<Option<usize>, String> {
+            match params.opt_str(name) {
+                Some(v) => v
+                    .parse::<usize>()
+                    .map(Some)
+                    .map_err(|e| format!("Invalid --{name} value {v}: {e}")),
+                None => Ok(None),
+            }
+        };
+
+        Ok(StressOptions {
+            threads: number("stress-threads")?.unwrap_or(10),
+            duration: Duration::from_secs(number("stress-duration")?.unwrap_or(30) as u64),
+            requests: number("stress-requests")?,
+            max_clients: number("stress-max-clients")?,
+        })
+    }
+}
+
+/// Read-only test data shared by the stress threads.
+struct StressFixtures {
+    sip_host: String,
+    tls: bool,
+    sip_user: String,
+    sip_pass: String,
+    institution: String,
+    acp_barcode: String,
+    au_barcode: String,
+}
+
+/// Outcome of one stress request.
+struct StressSample {
+    message: &'static str,
+    micros: u128,
+    ok: bool,
+}
+
+/// Run the stress test, returning the number of failures.
+fn run_stress(tester: &Tester, options: &StressOptions) -> usize {
+    let fixtures = Arc::new(StressFixtures {
+        sip_host: tester.sip_host.clone(),
+        tls: tester.tls,
+        sip_user: tester.sip_user.clone(),
+        sip_pass: tester.sip_pass.clone(),
+        institution: tester.institution.clone(),
+        acp_barcode: tester.samples.acp_barcode.clone(),
+        au_barcode: tester.samples.au_barcode.clone(),
+    });
+
+    let (tx, rx) = mpsc::channel();
+
+    let t = Timer::new();
+
+    for _ in 0..options.threads {
+        let fixtures = fixtures.clone();
+        let tx = tx.clone();
+        let duration = options.duration;
+        let requests = options.requests;
+
+        thread::spawn(move || stress_thread(&fixtures, duration, requests, tx));
+    }
+
+    // Our receiver stops once all threads drop their senders.
+    drop(tx);
+
+    let mut latencies: HashMap<&'static str, Vec<u128>> = HashMap::new();
+    let mut errors: HashMap<&'static str, usize> = HashMap::new();
+
+    for sample in rx {
+        if sample.ok {
+            latencies
+                .entry(sample.message)
+                .or_default()
+                .push(sample.micros);
+        } else {
+            *errors.entry(sample.message).or_default() += 1;
+        }
+    }
+
+    t.done(&format!("stress test with {} connections", options.threads));
+
+    print_stress_summary(&mut latencies, &errors);
+
+    let mut failures: usize = errors.values().sum();
+
+    if let Some(max) = options.max_clients {
+        if let Err(e) = check_max_clients(&fixtures, max) {
+            eprintln!("FAIL\tmax-clients: {e}");
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+/// Log in and send random requests until the duration has passed or
+/// the request count is reached, reconnecting after failures.
+fn stress_thread(
+    fixtures: &StressFixtures,
+    duration: Duration,
+    requests: Option<usize>,
+    tx: mpsc::Sender<StressSample>,
+) {
+    let started = Instant::now();
+    let mut rng = rand::thread_rng();
+    let mut sent = 0;
+    let mut sipcon: Option<sip2::Connection> = None;
+
+    loop {
+        match requests {
+            Some(count) if sent >= count => break,
+            None if started.elapsed() >= duration => break,
+            _ => {}
+        }
+
+        let con = match sipcon.as_mut() {
+            Some(c) => c,
+            None => {
+                let t = Instant::now();
+                let result = stress_login(fixtures);
+
+                let ok = result.is_ok();
+                tx.send(StressSample {
+                    message: "login",
+                    micros: t.elapsed().as_micros(),
+                    ok,
+                })
+                .ok();
+
+                match result {
+                    Ok(c) => sipcon.insert(c),
+                    Err(e) => {
+                        eprintln!("Stress login failed: {e}");
+                        // Avoid spinning on a server that is down.
+                        thread::sleep(Duration::from_secs(1));
+                        sent += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let (message, req) = match rng.gen_range(0..3) {
+            0 => (
+                "item-info",
+                sip2::Message::from_values(
+                    &sip2::spec::M_ITEM_INFO,
+                    &[&sip2::util::sip_date_now()],
+                    &[("AB", &fixtures.acp_barcode), ("AO", &fixtures.institution)],
+                ),
+            ),
+            1 => (
+                "patron-status",
+                sip2::Message::from_values(
+                    &sip2::spec::M_PATRON_STATUS,
+                    &["000", &sip2::util::sip_date_now()],
+                    &[
+                        ("AA", &fixtures.au_barcode),
+                        ("AD", &fixtures.au_barcode),
+                        ("AO", &fixtures.institution),
+                    ],
+                ),
+            ),
+            _ => (
+                "patron-info",
+                sip2::Message::from_values(
+                    &sip2::spec::M_PATRON_INFO,
+                    &["000", &sip2::util::sip_date_now(), "          "],
+                    &[
+                        ("AA", &fixtures.au_barcode),
+                        ("AD", &fixtures.au_barcode),
+                        ("AO", &fixtures.institution),
+                    ],
+                ),
+            ),
+        };
+
+        let req = req.expect("Valid stress request");
+
+        let t = Instant::now();
+        let result = con.sendrecv(&req);
+        let micros = t.elapsed().as_micros();
+
+        sent += 1;
+
+        if let Err(e) = &result {
+            eprintln!("Stress {message} request failed: {e}");
+            con.disconnect().ok();
+            sipcon = None;
+        }
+
+        tx.send(StressSample {
+            message,
+            micros,
+            ok: result.is_ok(),
+        })
+        .ok();
+    }
+
+    if let Some(con) = sipcon {
+        con.disconnect().ok();
+    }
+}
+
+fn stress_login(fixtures: &StressFixtures) -> Result<sip2::Connection, String> {
+    let mut con = sip_connect(&fixtures.sip_host, fixtures.tls)?;
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", &fixtures.sip_user), ("CO", &fixtures.sip_pass)],
+    )
+    .map_err(|e| e.to_string())?;
+
+    let resp = con
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+
+    match resp.fixed_fields().first().map(|f| f.value()) {
+        Some("1") => Ok(con),
+        _ => Err(format!("Login rejected: {}", resp.to_sip())),
+    }
+}
+
+/// Value at the given percentile of a sorted list.
+fn percentile(sorted: &[u128], pct: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let idx = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[idx.min(sorted.len() - 1)]
+}
+
+fn print_stress_summary(
+    latencies: &mut HashMap<&'static str, Vec<u128>>,
+    errors: &HashMap<&'static str, usize>,
+) {
+    let mut messages: Vec<&'static str> = latencies.keys().chain(errors.keys()).copied().collect();
+    messages.sort();
+    messages.dedup();
+
+    println!(
+        "{:<16}{:>8}{:>8}{:>12}{:>12}{:>12}",
+        "message", "count", "errors", "p50 ms", "p95 ms", "p99 ms"
+    );
+
+    for message in messages {
+        let list = latencies.entry(message).or_default();
+        list.sort();
+
+        let ms = |micros: u128| (micros as f64) / 1000.0;
+
+        println!(
+            "{:<16}{:>8}{:>8}{:>12.3}{:>12.3}{:>12.3}",
+            message,
+            list.len(),
+            errors.get(message).unwrap_or(&0),
+            ms(percentile(list, 50.0)),
+            ms(percentile(list, 95.0)),
+            ms(percentile(list, 99.0)),
+        );
+    }
+}
+
+/// Fill the server to max-clients, then verify one more connection
+/// is closed promptly instead of waiting for a free worker.
+fn check_max_clients(fixtures: &StressFixtures, max_clients: usize) -> Result<(), String> {
+    // Give the server a moment to end the stress sessions.
+    thread::sleep(Duration::from_secs(1));
+
+    // The tester's own connection holds one slot.
+    let mut held = Vec::new();
+    for _ in 1..max_clients {
+        held.push(stress_login(fixtures)?);
+    }
+
+    let t = Timer::new();
+    let started = Instant::now();
+
+    // Connection and TLS failures also count as rejections.
+    let problem = match sip_connect(&fixtures.sip_host, fixtures.tls) {
+        Ok(mut con) => extra_client_problem(&mut con, fixtures, max_clients),
+        Err(_) => None,
+    };
+
+    for con in held {
+        con.disconnect().ok();
+    }
+
+    if let Some(p) = problem {
+        return Err(p);
+    }
+
+    if started.elapsed() >= Duration::from_secs(REJECT_TIMEOUT) {
+        return Err(format!(
+            "Connection beyond max-clients={max_clients} took {} seconds to fail",
+            started.elapsed().as_secs()
+        ));
+    }
+
+    t.done("max-clients rejection");
+
+    Ok(())
+}
+
+/// Attempt a login on a connection beyond the server's max-clients,
+/// returning a description of the problem if the server does not
+/// close the connection.
+fn extra_client_problem(
+    con: &mut sip2::Connection,
+    fixtures: &StressFixtures,
+    max_clients: usize,
+) -> Option<String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", &fixtures.sip_user), ("CO", &fixtures.sip_pass)],
+    )
+    .expect("Valid login request");
+
+    if con.send(&req).is_err() {
+        return None;
+    }
+
+    match con.recv_with_timeout(REJECT_TIMEOUT) {
+        Ok(Some(_)) => Some(format!(
+            "Connection beyond max-clients={max_clients} was accepted"
+        )),
+        Ok(None) => Some(format!(
+            "Connection beyond max-clients={max_clients} hung for {REJECT_TIMEOUT} seconds"
+        )),
+        // Closed by the server.
+        Err(_) => None,
+    }
+}
+
+/// Tests written in Rust, in the order they run.
+fn rust_tests(tester: &Tester) -> Vec<TestCase> {
+    let mut tests = Vec::new();
+
+    // Run whatever tests we can multiple times to get a sense of
+    // timing for multiple scenarios.
+
+    tests.push(TestCase::rust("test_sc_status", test_sc_status));
+
+    if tester.auto_workstation.is_some() {
+        // Runs before any checkouts on the original connection, whose
+        // session refers to the workstation being deleted.
+        tests.push(TestCase::rust(
+            "test_auto_workstation",
+            test_auto_workstation,
+        ));
+    }
+    tests.push(TestCase::rust(
+        "test_invalid_item_info",
+        test_invalid_item_info,
+    ));
+    tests.push(TestCase::rust("test_item_info", |t| {
+        test_item_info(t, false)
+    }));
+
+    if tester.transport_failure {
+        tests.push(TestCase::rust(
+            "test_transport_reconnect",
+            test_transport_reconnect,
+        ));
+    }
+
+    tests.push(TestCase::rust("test_patron_status", test_patron_status));
+    tests.push(TestCase::rust(
+        "test_patron_status_bad_password",
+        test_patron_status_bad_password,
+    ));
+    tests.push(TestCase::rust(
+        "test_protocol_version_1",
+        test_protocol_version_1,
+    ));
+    tests.push(TestCase::rust("test_patron_info", |t| {
+        test_patron_info(t, false)
+    }));
+
+    tests.push(TestCase::rust("test_checkout", test_checkout));
+    tests.push(TestCase::rust("test_item_info", |t| {
+        test_item_info(t, true)
+    }));
+    tests.push(TestCase::rust("test_patron_status", test_patron_status));
+    tests.push(TestCase::rust("test_patron_info", |t| {
+        test_patron_info(t, true)
+    }));
+    tests.push(TestCase::rust(
+        "test_patron_info_charged_items",
+        test_patron_info_charged_items,
+    ));
+
+    // Checkout a second time to force a renewal.
+    tests.push(TestCase::rust("test_checkout", test_checkout));
+    tests.push(TestCase::rust("test_item_info", |t| {
+        test_item_info(t, true)
+    }));
+    tests.push(TestCase::rust("test_checkin", test_checkin));
+
+    tests.push(TestCase::rust("test_item_info", |t| {
+        test_item_info(t, false)
+    }));
+    tests.push(TestCase::rust("test_patron_status", test_patron_status));
+    tests.push(TestCase::rust("test_patron_info", |t| {
+        test_patron_info(t, false)
+    }));
+
+    tests.push(TestCase::rust("test_checkout", test_checkout));
+    tests.push(TestCase::rust("test_item_info", |t| {
+        test_item_info(t, true)
+    }));
+    tests.push(TestCase::rust("test_patron_status", test_patron_status));
+    tests.push(TestCase::rust("test_patron_info", |t| {
+        test_patron_info(t, true)
+    }));
+    tests.push(TestCase::rust("test_checkin", test_checkin));
+
+    tests.push(TestCase::rust(
+        "test_checkin_with_transit",
+        test_checkin_with_transit,
+    ));
+
+    tests.push(TestCase::rust(
+        "test_item_info_hold_queue",
+        test_item_info_hold_queue,
+    ));
+
+    if tester.circ_status_map.is_some() {
+        tests.push(TestCase::rust(
+            "test_item_info_status_mapping",
+            test_item_info_status_mapping,
+        ));
+    }
+
+    tests.push(TestCase::rust(
+        "test_patron_status_fines",
+        test_patron_status_fines,
+    ));
+    tests.push(TestCase::rust(
+        "test_checkin_no_block_backdate",
+        test_checkin_no_block_backdate,
+    ));
+    tests.push(TestCase::rust(
+        "test_patron_status_expired",
+        test_patron_status_expired,
+    ));
+    tests.push(TestCase::rust("test_copy_alerts", test_copy_alerts));
+
+    if tester.locale.as_deref() == Some("es-ES") {
+        tests.push(TestCase::rust(
+            "test_screen_message_locale",
+            test_screen_message_locale,
+        ));
+    }
+
+    if tester.timezone.is_some() {
+        tests.push(TestCase::rust(
+            "test_due_date_timezone",
+            test_due_date_timezone,
+        ));
+    }
+
+    tests
+}
+
+fn create_test_assets(tester: &mut Tester) -> Result<(), String> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn = tester.samples.create_default_acn(e)?;
+    tester.samples.create_default_acp(e, acn.id()?)?;
+    tester.samples.create_default_au(e)?;
+
+    e.commit().map_err(|e| e.to_string())
+}
+
+fn delete_test_assets(tester: &mut Tester) -> Result<(), String> {
+    let register_email = tester.register_email();
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let fines_barcode = tester.samples.derive("FINES");
+    let no_block_barcode = tester.samples.derive("NOBLOCK");
+    let alerts_barcode = tester.samples.derive("ALERTS");
+    let tz_barcode = tester.samples.derive("TZ");
+
+    for barcode in [
+        &fines_barcode,
+        &no_block_barcode,
+        &alerts_barcode,
+        &tz_barcode,
+    ] {
+        if let Some(acp) = tester.samples.get_acp(e, barcode)? {
+            tester.samples.delete_circs(e, acp.id()?)?;
+            tester.samples.delete_copy_alerts(e, acp.id()?)?;
+        }
+    }
+
+    tester.samples.delete_acps(
+        e,
+        &[fines_barcode, no_block_barcode, alerts_barcode, tz_barcode],
+    )?;
+    tester.samples.delete_default_acp(e)?;
+    tester.samples.delete_default_acn(e)?;
+    tester.samples.delete_default_au(e)?;
+    tester
+        .samples
+        .delete_au(e, &tester.samples.derive("EXPIRED"))?;
+
+    for stgu in e.search("stgu", eg::hash! {email: register_email.as_str()})? {
+        for stgma in e.search("stgma", eg::hash! {usrname: stgu["usrname"].clone()})? {
+            e.delete(stgma)?;
+        }
+        e.delete(stgu)?;
+    }
+
+    e.commit()?;
+
+    Ok(())
+}
+
+fn test_sc_status(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_ff_values(
+        &sip2::spec::M_SC_STATUS,
+        &[
+            "0",   // status code
+            "999", // max print width
+            &sip2::spec::SIP_PROTOCOL_VERSION,
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_sc_status");
+
+    assert!(resp.fixed_fields().len() > 0);
+    assert_eq!(resp.fixed_fields()[0].value(), "Y");
+
+    Ok(())
+}
+
+fn send_sc_status(tester: &mut Tester, version: &str) -> Result<sip2::Message, String> {
+    let req =
+        sip2::Message::from_ff_values(&sip2::spec::M_SC_STATUS, &["0", "999", version]).unwrap();
+
+    tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))
+}
+
+/// A SIP 1.00 client never receives fields added in SIP 2.00.
+fn test_protocol_version_1(tester: &mut Tester) -> Result<(), String> {
+    let resp = send_sc_status(tester, "1.00")?;
+
+    assert_eq!(resp.fixed_fields()[9].value(), "1.00");
+    assert!(resp.get_field_value("BX").is_none());
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", &tester.samples.au_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"));
+    t.done("test_protocol_version_1");
+
+    // Restore SIP 2.00 for the remaining tests, even if we failed.
+    let restored = send_sc_status(tester, sip2::spec::SIP_PROTOCOL_VERSION)?;
+    assert!(restored.get_field_value("BX").is_some());
+
+    let resp = resp?;
+
+    assert_eq!(
+        resp.get_field_value("AA").unwrap(),
+        tester.samples.au_barcode
+    );
+    assert!(resp.get_field_value("CQ").is_none());
+    assert!(resp.get_field_value("BL").is_none());
+
+    Ok(())
+}
+
+fn test_invalid_item_info(tester: &mut Tester) -> Result<(), String> {
+    let dummy = "I-AM-BAD-BARCODE";
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_ITEM_INFO,
+        &[&sip2::util::sip_date_now()],
+        &[("AB", dummy), ("AO", &tester.institution)],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_invalid_item_info");
+
+    let circ_status = resp.fixed_fields()[0].value();
+    let barcode = resp.get_field_value("AB");
+    let title = resp.get_field_value("AJ");
+
+    // We should get title/barcode fields in the response.
+    assert!(barcode.is_some());
+    assert!(title.is_some());
+
+    assert_eq!(barcode.unwrap(), dummy);
+    assert_eq!(title.unwrap(), "");
+    assert_eq!(circ_status, "01");
+
+    Ok(())
+}
+
+fn test_item_info(tester: &mut Tester, charged: bool) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_ITEM_INFO,
+        &[&sip2::util::sip_date_now()],
+        &[
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_item_info");
+
+    let circ_status = resp.fixed_fields()[0].value();
+    let barcode = resp.get_field_value("AB");
+    let title = resp.get_field_value("AJ");
+
+    // We should get title/barcode fields in the response.
+    assert!(barcode.is_some());
+    assert!(title.is_some());
+
+    assert_eq!(barcode.unwrap(), tester.samples.acp_barcode);
+    assert_ne!(title.unwrap(), "");
+    if charged {
+        assert_eq!(circ_status, "04");
+    } else {
+        // May be available or reshelving
+        assert!(circ_status.eq("03") || circ_status.eq("09"));
+    }
+
+    if let Some(dest) = resp.get_field_value("CT") {
+        assert_eq!(dest, tester.samples.aou_shortname);
+    }
+
+    assert_eq!(
+        resp.get_field_value("BG").unwrap(),
+        tester.samples.aou_shortname
+    );
+    assert_eq!(
+        resp.get_field_value("AP").unwrap(),
+        tester.samples.aou_shortname
+    );
+    assert!(is_zero(resp.get_field_value("BV").unwrap())); // fee amount
+
+    if let Some(ql) = resp.get_field_value("CF") {
+        assert_eq!(ql, "0"); // hold queue len
+    }
+
+    assert_eq!(resp.get_field_value("CK").unwrap(), "001"); // media type
+
+    Ok(())
+}
+
+/// Item information on a new SIP session, whose first item info
+/// request the server fails as if its OpenSRF connection dropped.
+fn test_transport_reconnect(tester: &mut Tester) -> Result<(), String> {
+    let sipcon = sip_connect(&tester.sip_host, tester.tls)?;
+    let orig_sipcon = std::mem::replace(&mut tester.sipcon, sipcon);
+
+    let t = Timer::new();
+    let result = builtin_test("test_valid_login")
+        .and_then(|login| run_script_test(tester, &login))
+        .and_then(|_| test_item_info(tester, false));
+    t.done("test_transport_reconnect");
+
+    tester.sipcon.disconnect().ok();
+    tester.sipcon = orig_sipcon;
+
+    result
+}
+
+/// Delete the account's workstation, then login on a new connection,
+/// which registers the workstation again.  The new connection
+/// replaces the original, whose session used the deleted workstation.
+fn test_auto_workstation(tester: &mut Tester) -> Result<(), String> {
+    let name = tester.auto_workstation.clone().unwrap();
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+
+    for ws in e.search("aws", eg::hash! {name: name.as_str()})? {
+        e.delete(ws)
+            .map_err(|err| format!("Cannot delete workstation {name}: {err}"))?;
+    }
+
+    e.commit()?;
+
+    let sipcon = sip_connect(&tester.sip_host, tester.tls)?;
+    let orig_sipcon = std::mem::replace(&mut tester.sipcon, sipcon);
+    orig_sipcon.disconnect().ok();
+
+    let t = Timer::new();
+    builtin_test("test_valid_login").and_then(|login| run_script_test(tester, &login))?;
+    t.done("test_auto_workstation");
+
+    let ws = tester
+        .editor
+        .search("aws", eg::hash! {name: name.as_str()})?
+        .pop()
+        .ok_or_else(|| format!("Workstation {name} was not registered"))?;
+
+    test_checkout(tester)?;
+
+    let acp = tester.samples.get_default_acp(&mut tester.editor)?;
+    let circ = tester
+        .editor
+        .search(
+            "circ",
+            eg::hash! {target_copy: acp.id()?, checkin_time: eg::NULL},
+        )?
+        .pop()
+        .ok_or_else(|| "Checkout did not create a circulation".to_string())?;
+
+    assert_eq!(circ["workstation"].int()?, ws.id()?);
+
+    test_checkin(tester)
+}
+
+fn test_patron_status(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", &tester.samples.au_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_patron_status");
+
+    assert_eq!(
+        resp.get_field_value("AA").unwrap(),
+        tester.samples.au_barcode
+    );
+    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
+    assert_eq!(resp.get_field_value("CQ").unwrap(), "Y"); // valid password
+
+    if let Some(fee) = resp.get_field_value("BV") {
+        assert!(is_zero(fee));
+    }
+
+    let status = resp.fixed_fields()[0].value();
+    assert_eq!(status.len(), 14);
+
+    // Legacy EG sip server will set a Y on the 'recall denied' field,
+    // regardless of patron, because it does not support recalls.
+    if status.contains("Y") {
+        assert_eq!(&status[2..3], "Y");
+    }
+
+    Ok(())
+}
+
+fn test_patron_status_bad_password(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", "_EG_TEST_BAD_PASSWORD_"),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_patron_status_bad_password");
+
+    // A bad password only affects the CQ field.
+    assert_eq!(
+        resp.get_field_value("AA").unwrap(),
+        tester.samples.au_barcode
+    );
+    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
+    assert_eq!(resp.get_field_value("CQ").unwrap(), "N"); // invalid password
+    assert_eq!(resp.fixed_fields()[0].value().len(), 14);
+
+    Ok(())
+}
+
+fn test_patron_info(tester: &mut Tester, charged: bool) -> Result<(), String> {
+    let summary = "          ";
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_INFO,
+        &["000", &sip2::util::sip_date_now(), summary],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", &tester.samples.au_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_patron_info");
+
+    assert_eq!(
+        resp.get_field_value("AA").unwrap(),
+        tester.samples.au_barcode
+    );
+    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
+    assert_eq!(resp.get_field_value("CQ").unwrap(), "Y"); // valid password
+
+    if let Some(fee) = resp.get_field_value("BV") {
+        assert!(is_zero(fee)); // fee amount
+    }
+
+    assert_eq!(
+        &resp.get_field_value("AQ").unwrap(),
+        &tester.samples.aou_shortname
+    );
+
+    let status = resp.fixed_fields()[0].value();
+    assert_eq!(status.len(), 14);
+
+    // Legacy EG sip server will set a Y on the 'recall denied' field,
+    // regardless of patron, because it does not support recalls.
+    if status.contains("Y") {
+        assert_eq!(&status[2..3], "Y");
+    }
+
+    // Summary counts.  Should all be zero since this is a new patron.
+    assert_eq!(resp.fixed_fields()[3].value(), "0000"); // holds
+    assert_eq!(resp.fixed_fields()[4].value(), "0000"); // overdue
+    if charged {
+        assert_eq!(resp.fixed_fields()[5].value(), "0001"); // charged
+    } else {
+        assert_eq!(resp.fixed_fields()[5].value(), "0000"); // charged
+    }
+    assert_eq!(resp.fixed_fields()[6].value(), "0000"); // fine count
+    assert_eq!(resp.fixed_fields()[7].value(), "0000"); // recall count
+    assert_eq!(resp.fixed_fields()[8].value(), "0000"); // unavail hold count
+
+    Ok(())
+}
+
+/// Requires the sample patron have the sample copy checked out.
+fn test_patron_info_charged_items(tester: &mut Tester) -> Result<(), String> {
+    // Charged items is the 3rd summary position.
+    let summary = "  Y       ";
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_INFO,
+        &["000", &sip2::util::sip_date_now(), summary],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", &tester.samples.au_barcode),
+            ("AO", &tester.institution),
+            ("BP", "1"),
+            ("BQ", "5"),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_patron_info_charged_items");
+
+    assert_eq!(resp.fixed_fields()[5].value(), "0001"); // charged
+
+    let charged: Vec<&str> = resp
+        .fields()
+        .iter()
+        .filter(|f| f.code() == "AU")
+        .map(|f| f.value())
+        .collect();
+
+    assert_eq!(charged.len(), 1);
+
+    // Depending on the account settings, the value is either the
+    // copy barcode or the title.
+    assert_ne!(charged[0], "");
+
+    // No other detail lists were requested.
+    assert!(resp.get_field_value("AS").is_none());
+    assert!(resp.get_field_value("AV").is_none());
+
+    Ok(())
+}
+
+fn test_checkout(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKOUT,
+        &[
+            "Y", // renewal allowed if needed
+            "N", // previously checked out offline / no block
+            &sip2::util::sip_date_now(),
+            "                  ", // no-block due date
+        ],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
     let resp = tester
         .sipcon
         .sendrecv(&req)
         .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_patron_info");
+    t.done("test_checkout");
+
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkout ok.
+
+    // This depends on which call to test_checkout() we're in the midst of.
+    // 'renewal ok' means it was renewed, not that it can be renewed later.
+    // assert_eq!(resp.fixed_fields()[1].value(), "Y"); // renewal ok.
 
     assert_eq!(
         resp.get_field_value("AA").unwrap(),
         tester.samples.au_barcode
     );
-    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
-    assert_eq!(resp.get_field_value("CQ").unwrap(), "Y"); // valid password
+    assert_eq!(
+        resp.get_field_value("AB").unwrap(),
+        tester.samples.acp_barcode
+    );
+    assert_ne!(resp.get_field_value("AJ").unwrap(), ""); // assume we have some kind of title
+
+    if let Some(da) = resp.get_field_value("BV") {
+        assert!(is_zero(da));
+    }
+
+    Ok(())
+}
+
+fn test_checkin(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKIN,
+        &[
+            "N", // renewal policy
+            &sip2::util::sip_date_now(),
+            &sip2::util::sip_date_now(),
+        ],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
+            ("AP", &tester.samples.aou_shortname),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_checkin");
+
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
+    assert_eq!(resp.fixed_fields()[1].value(), "Y"); // resensitize, i.e. not magnetic
+
+    assert_eq!(
+        resp.get_field_value("AB").unwrap(),
+        tester.samples.acp_barcode
+    );
+    assert_ne!(resp.get_field_value("AJ").unwrap(), ""); // assume we have some kind of title
+    assert_eq!(
+        resp.get_field_value("AQ").unwrap(),
+        tester.samples.aou_shortname
+    );
+
+    if let Some(da) = resp.get_field_value("BV") {
+        assert!(is_zero(da));
+    }
+
+    Ok(())
+}
+
+/// Same as test_checkin except the item needs to transit back home.
+fn test_checkin_with_transit(tester: &mut Tester) -> Result<(), String> {
+    // Change the circ lib for the copy to an alternate org unit
+    // and check it in "here".
+    tester.editor.xact_begin()?;
+
+    tester.samples.modify_default_acp(
+        &mut tester.editor,
+        eg::hash! {"circ_lib":  eg::samples::AOU_BR2_ID},
+    )?;
+
+    tester.editor.commit()?;
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKIN,
+        &[
+            "N", // renewal policy
+            &sip2::util::sip_date_now(),
+            &sip2::util::sip_date_now(),
+        ],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
+            ("AP", &tester.samples.aou_shortname),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_checkin_with_transit");
+
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
+
+    assert_eq!(
+        resp.get_field_value("AQ").unwrap(),
+        eg::samples::AOU_BR2_SHORTNAME
+    );
+
+    let copy = tester.samples.get_default_acp(&mut tester.editor)?;
+
+    // Verify the transit was created.
+    let query = eg::hash! {
+        "target_copy": copy["id"].clone(),
+        "dest_recv_time": EgValue::Null,
+        "cancel_time": EgValue::Null,
+    };
+
+    let mut results = tester.editor.search("atc", query)?;
+
+    assert_eq!(results.len(), 1);
+
+    let transit = results.pop().unwrap();
+
+    // Check it in again at the destination branch to
+    // complete the transit.
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKIN,
+        &[
+            "N", // renewal policy
+            &sip2::util::sip_date_now(),
+            &sip2::util::sip_date_now(),
+        ],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
+            ("AP", eg::samples::AOU_BR2_SHORTNAME),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
+    t.done("test_checkin_with_transit");
+
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
+
+    assert_eq!(
+        resp.get_field_value("AQ").unwrap(),
+        eg::samples::AOU_BR2_SHORTNAME
+    );
+
+    // Verify the transit was completed
+    let result = tester.editor.retrieve("atc", transit["id"].clone())?;
+    assert!(result.is_some());
+
+    let transit = result.unwrap();
+    assert!(!transit["dest_recv_time"].is_null());
+
+    Ok(())
+}
+
+/// Patron status reports the fines owed on an overdue circulation.
+fn test_patron_status_fines(tester: &mut Tester) -> Result<(), String> {
+    let amount = 1.50;
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    // Share the call number of the default copy.
+    let acn_id = tester.samples.get_default_acp(e)?["call_number"].int()?;
+    let user_id = tester.samples.get_default_au_id(e)?;
+
+    let acp = tester.samples.create_acp_with_barcode(
+        e,
+        acn_id,
+        &tester.samples.derive("FINES"),
+        eg::samples::ACP_STATUS,
+    )?;
+
+    let circ = tester
+        .samples
+        .create_circ(e, acp.id()?, user_id, "-3 days")?;
+
+    tester.samples.create_billing(
+        e,
+        circ.id()?,
+        amount,
+        eg::constants::BTYPE_OVERDUE_MATERIALS,
+    )?;
+
+    e.commit()?;
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", &tester.samples.au_barcode),
+            ("AD", &tester.samples.au_barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_patron_status_fines");
+
+    let fee = resp
+        .get_field_value("BV")
+        .expect("Fines should be reported");
+    let fee: f64 = fee.parse().map_err(|e| format!("Invalid fee {fee}: {e}"))?;
+
+    assert_eq!(fee, amount);
+
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+    tester.samples.delete_circs(e, acp.id()?)?;
+    tester
+        .samples
+        .delete_acps(e, &[tester.samples.derive("FINES")])?;
+    e.commit()?;
+
+    Ok(())
+}
+
+/// A checkin sent in no-block mode three days after the transaction
+/// happened stops fines at the transaction date.
+fn test_checkin_no_block_backdate(tester: &mut Tester) -> Result<(), String> {
+    let barcode = tester.samples.derive("NOBLOCK");
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn_id = tester.samples.get_default_acp(e)?["call_number"].int()?;
+    let user_id = tester.samples.get_default_au_id(e)?;
+
+    let acp =
+        tester
+            .samples
+            .create_acp_with_barcode(e, acn_id, &barcode, eg::samples::ACP_STATUS)?;
+
+    // Daily fines, due five days ago.
+    let circ = tester
+        .samples
+        .create_circ(e, acp.id()?, user_id, "-5 days")?;
+
+    e.commit()?;
+
+    let three_days_ago = eg::date::subtract_interval(eg::date::now(), "3 days")?;
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKIN,
+        &[
+            "Y", // no block
+            &sip2::util::sip_date_from_dt(&three_days_ago),
+            "                  ", // no return date
+        ],
+        &[
+            ("AB", &barcode),
+            ("AO", &tester.institution),
+            ("AP", &tester.samples.aou_shortname),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_checkin_no_block_backdate");
 
-    if let Some(fee) = resp.get_field_value("BV") {
-        assert!(is_zero(fee)); // fee amount
-    }
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
 
-    assert_eq!(
-        &resp.get_field_value("AQ").unwrap(),
-        &tester.samples.aou_shortname
-    );
+    let e = &mut tester.editor;
 
-    let status = resp.fixed_fields()[0].value();
-    assert_eq!(status.len(), 14);
+    let summary = e
+        .retrieve("mbts", circ.id()?)?
+        .ok_or_else(|| format!("No summary for circ {}", circ.id().unwrap()))?;
 
-    // Legacy EG sip server will set a Y on the 'recall denied' field,
-    // regardless of patron, because it does not support recalls.
-    if status.contains("Y") {
-        assert_eq!(&status[2..3], "Y");
-    }
+    let balance = summary["balance_owed"].float()?;
 
-    // Summary counts.  Should all be zero since this is a new patron.
-    assert_eq!(resp.fixed_fields()[3].value(), "0000"); // holds
-    assert_eq!(resp.fixed_fields()[4].value(), "0000"); // overdue
-    if charged {
-        assert_eq!(resp.fixed_fields()[5].value(), "0001"); // charged
-    } else {
-        assert_eq!(resp.fixed_fields()[5].value(), "0000"); // charged
-    }
-    assert_eq!(resp.fixed_fields()[6].value(), "0000"); // fine count
-    assert_eq!(resp.fixed_fields()[7].value(), "0000"); // recall count
-    assert_eq!(resp.fixed_fields()[8].value(), "0000"); // unavail hold count
+    // Two days of fines accrued before the backdate, where a checkin
+    // today would have five.
+    assert!(balance > 0.0);
+    assert!(balance <= 2.0 * eg::samples::CIRC_RECURRING_FINE + 0.001);
+
+    e.xact_begin()?;
+    tester.samples.delete_circs(e, acp.id()?)?;
+    tester.samples.delete_acps(e, &[barcode])?;
+    e.commit()?;
 
     Ok(())
 }
 
-fn test_checkout(tester: &mut Tester) -> Result<(), String> {
+/// Copy alerts are reported as screen messages, but only for the
+/// events they apply to.
+fn test_copy_alerts(tester: &mut Tester) -> Result<(), String> {
+    const CHECKIN_NOTE: &str = "Route to mending";
+    const CHECKOUT_NOTE: &str = "Check for disc";
+
+    let barcode = tester.samples.derive("ALERTS");
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn_id = tester.samples.get_default_acp(e)?["call_number"].int()?;
+
+    let acp =
+        tester
+            .samples
+            .create_acp_with_barcode(e, acn_id, &barcode, eg::samples::ACP_STATUS)?;
+
+    tester
+        .samples
+        .create_copy_alert(e, acp.id()?, eg::samples::ACA_TYPE_CHECKIN, CHECKIN_NOTE)?;
+    tester.samples.create_copy_alert(
+        e,
+        acp.id()?,
+        eg::samples::ACA_TYPE_CHECKOUT,
+        CHECKOUT_NOTE,
+    )?;
+
+    e.commit()?;
+
+    let screen_messages = |resp: &sip2::Message| -> Vec<String> {
+        resp.fields()
+            .iter()
+            .filter(|f| f.code() == "AF")
+            .map(|f| f.value().to_string())
+            .collect()
+    };
+
     let req = sip2::Message::from_values(
-        &sip2::spec::M_CHECKOUT,
+        &sip2::spec::M_ITEM_INFO,
+        &[&sip2::util::sip_date_now()],
+        &[("AB", &barcode), ("AO", &tester.institution)],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_copy_alerts");
+
+    // Item information reports every active alert.
+    let messages = screen_messages(&resp);
+    assert!(messages.iter().any(|m| m == CHECKIN_NOTE));
+    assert!(messages.iter().any(|m| m == CHECKOUT_NOTE));
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_CHECKIN,
         &[
-            "Y", // renewal allowed if needed
-            "N", // previously checked out offline / no block
+            "N",
+            &sip2::util::sip_date_now(),
             &sip2::util::sip_date_now(),
-            "                  ", // no-block due date
         ],
         &[
-            ("AA", &tester.samples.au_barcode),
-            ("AB", &tester.samples.acp_barcode),
+            ("AB", &barcode),
             ("AO", &tester.institution),
+            ("AP", &tester.samples.aou_shortname),
         ],
     )
     .unwrap();
 
-    let t = Timer::new();
     let resp = tester
         .sipcon
         .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_checkout");
-
-    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkout ok.
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
 
-    // This depends on which call to test_checkout() we're in the midst of.
-    // 'renewal ok' means it was renewed, not that it can be renewed later.
-    // assert_eq!(resp.fixed_fields()[1].value(), "Y"); // renewal ok.
+    // Checkout alerts do not appear at checkin.
+    let messages = screen_messages(&resp);
+    assert!(messages.iter().any(|m| m == CHECKIN_NOTE));
+    assert!(!messages.iter().any(|m| m == CHECKOUT_NOTE));
 
-    assert_eq!(
-        resp.get_field_value("AA").unwrap(),
-        tester.samples.au_barcode
-    );
-    assert_eq!(
-        resp.get_field_value("AB").unwrap(),
-        tester.samples.acp_barcode
-    );
-    assert_ne!(resp.get_field_value("AJ").unwrap(), ""); // assume we have some kind of title
+    let e = &mut tester.editor;
 
-    if let Some(da) = resp.get_field_value("BV") {
-        assert!(is_zero(da));
-    }
+    e.xact_begin()?;
+    tester.samples.delete_copy_alerts(e, acp.id()?)?;
+    tester.samples.delete_acps(e, &[barcode])?;
+    e.commit()?;
 
     Ok(())
 }
 
-fn test_checkin(tester: &mut Tester) -> Result<(), String> {
+/// Item information reports the SIP circulation status configured
+/// in the account item-mapping.
+fn test_item_info_status_mapping(tester: &mut Tester) -> Result<(), String> {
+    let (copy_status, sip_status) = tester.circ_status_map.clone().unwrap();
+
+    let orig_status = tester.samples.get_default_acp(&mut tester.editor)?["status"].int()?;
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+    tester
+        .samples
+        .modify_default_acp(e, eg::hash! {status: copy_status})?;
+    e.commit()?;
+
     let req = sip2::Message::from_values(
-        &sip2::spec::M_CHECKIN,
+        &sip2::spec::M_ITEM_INFO,
+        &[&sip2::util::sip_date_now()],
         &[
-            "N", // renewal policy
-            &sip2::util::sip_date_now(),
-            &sip2::util::sip_date_now(),
+            ("AB", &tester.samples.acp_barcode),
+            ("AO", &tester.institution),
         ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_item_info_status_mapping");
+
+    let e = &mut tester.editor;
+    e.xact_begin()?;
+    tester
+        .samples
+        .modify_default_acp(e, eg::hash! {status: orig_status})?;
+    e.commit()?;
+
+    assert_eq!(resp.fixed_fields()[0].value(), sip_status);
+
+    Ok(())
+}
+
+/// The hold queue length counts holds at any level which the copy
+/// could fill.
+fn test_item_info_hold_queue(tester: &mut Tester) -> Result<(), String> {
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let copy_id = tester.samples.get_default_acp(e)?.id()?;
+    let user_id = tester.samples.get_default_au_id(e)?;
+    let pickup_lib = tester.samples.aou_id;
+
+    tester
+        .samples
+        .create_hold(e, copy_id, user_id, pickup_lib)?;
+
+    tester.samples.create_hold_of_type(
+        e,
+        eg::constants::HOLD_TYPE_TITLE,
+        tester.samples.acn_record,
+        user_id,
+        pickup_lib,
+    )?;
+
+    e.commit()?;
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_ITEM_INFO,
+        &[&sip2::util::sip_date_now()],
         &[
-            ("AA", &tester.samples.au_barcode),
             ("AB", &tester.samples.acp_barcode),
             ("AO", &tester.institution),
-            ("AP", &tester.samples.aou_shortname),
         ],
     )
     .unwrap();
@@ -536,54 +2154,86 @@ fn test_checkin(tester: &mut Tester) -> Result<(), String> {
     let resp = tester
         .sipcon
         .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_checkin");
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_item_info_hold_queue");
 
-    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
-    assert_eq!(resp.fixed_fields()[1].value(), "Y"); // resensitize, i.e. not magnetic
+    let e = &mut tester.editor;
 
-    assert_eq!(
-        resp.get_field_value("AB").unwrap(),
-        tester.samples.acp_barcode
-    );
-    assert_ne!(resp.get_field_value("AJ").unwrap(), ""); // assume we have some kind of title
-    assert_eq!(
-        resp.get_field_value("AQ").unwrap(),
-        tester.samples.aou_shortname
-    );
+    e.xact_begin()?;
+    tester.samples.delete_holds(e, user_id)?;
+    e.commit()?;
 
-    if let Some(da) = resp.get_field_value("BV") {
-        assert!(is_zero(da));
-    }
+    assert_eq!(resp.get_field_value("CF").unwrap(), "2"); // hold queue len
+
+    // Holds are not captured by item information requests.
+    assert!(resp.get_field_value("CY").is_none());
+    assert!(resp.get_field_value("DA").is_none());
 
     Ok(())
 }
 
-/// Same as test_checkin except the item needs to transit back home.
-fn test_checkin_with_transit(tester: &mut Tester) -> Result<(), String> {
-    // Change the circ lib for the copy to an alternate org unit
-    // and check it in "here".
-    tester.editor.xact_begin()?;
+/// Patrons with expired cards are denied all privileges, but are
+/// still valid patrons.
+fn test_patron_status_expired(tester: &mut Tester) -> Result<(), String> {
+    let e = &mut tester.editor;
 
-    tester.samples.modify_default_acp(
-        &mut tester.editor,
-        eg::hash! {"circ_lib":  eg::samples::AOU_BR2_ID},
-    )?;
+    e.xact_begin()?;
+    tester.samples.create_expired_au(e)?;
+    e.commit()?;
 
-    tester.editor.commit()?;
+    let barcode = tester.samples.derive("EXPIRED");
 
     let req = sip2::Message::from_values(
-        &sip2::spec::M_CHECKIN,
+        &sip2::spec::M_PATRON_STATUS,
+        &["000", &sip2::util::sip_date_now()],
+        &[
+            ("AA", &barcode),
+            ("AD", &barcode),
+            ("AO", &tester.institution),
+        ],
+    )
+    .unwrap();
+
+    let t = Timer::new();
+    let resp = tester
+        .sipcon
+        .sendrecv(&req)
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_patron_status_expired");
+
+    assert_eq!(resp.get_field_value("AA").unwrap(), barcode);
+    assert_eq!(resp.get_field_value("BL").unwrap(), "Y"); // valid patron
+
+    // Charge, renewal, recall, and hold privileges denied.
+    let status = resp.fixed_fields()[0].value();
+    assert_eq!(&status[0..4], "YYYY");
+
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+    tester.samples.delete_au(e, &barcode)?;
+    e.commit()?;
+
+    Ok(())
+}
+
+/// Screen messages are translated into the SIP account's locale.
+///
+/// The sample patron owes nothing, so a payment fails with a screen
+/// message.
+fn test_screen_message_locale(tester: &mut Tester) -> Result<(), String> {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_FEE_PAID,
         &[
-            "N", // renewal policy
-            &sip2::util::sip_date_now(),
             &sip2::util::sip_date_now(),
+            "01",  // fee type: other/unknown
+            "00",  // payment type: cash
+            "USD", // currency
         ],
         &[
             ("AA", &tester.samples.au_barcode),
-            ("AB", &tester.samples.acp_barcode),
             ("AO", &tester.institution),
-            ("AP", &tester.samples.aou_shortname),
+            ("BV", "1.00"),
         ],
     )
     .unwrap();
@@ -592,46 +2242,71 @@ fn test_checkin_with_transit(tester: &mut Tester) -> Result<(), String> {
     let resp = tester
         .sipcon
         .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_checkin_with_transit");
-
-    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_screen_message_locale");
 
+    assert_eq!(resp.fixed_fields()[0].value(), "N"); // payment accepted
     assert_eq!(
-        resp.get_field_value("AQ").unwrap(),
-        eg::samples::AOU_BR2_SHORTNAME
+        resp.get_field_value("AF").unwrap(),
+        "No hay transacciones pendientes de pago"
     );
 
-    let copy = tester.samples.get_default_acp(&mut tester.editor)?;
+    Ok(())
+}
 
-    // Verify the transit was created.
-    let query = eg::hash! {
-        "target_copy": copy["id"].clone(),
-        "dest_recv_time": EgValue::Null,
-        "cancel_time": EgValue::Null,
-    };
+#[test]
+fn test_parse_script() {
+    let tests = parse_script(LOGIN_SCRIPT).unwrap();
+    assert_eq!(tests[1].name, "test_valid_login");
+    assert!(tests[1].always);
 
-    let mut results = tester.editor.search("atc", query)?;
+    let vars = HashMap::from([("sip_user", "sip-user".to_string())]);
+    assert_eq!(expand("{sip_user}", &vars).unwrap(), "sip-user");
+    assert!(expand("{nope}", &vars).is_err());
 
-    assert_eq!(results.len(), 1);
+    let bad = "- name: bad\n  request:\n    code: \"ZZ\"\n";
+    assert!(parse_script(bad).is_err());
 
-    let transit = results.pop().unwrap();
+    let tests = parse_script(include_str!("../../e2e/patron-register.yml")).unwrap();
+    assert_eq!(tests[0].spec.code, "85");
+    assert_eq!(tests[0].spec.fixed_fields[0].length, 18);
+    assert!(sip2::Message::from_sip("86120240101    120000AApatron|").is_ok());
+}
 
-    // Check it in again at the destination branch to
-    // complete the transit.
+#[test]
+fn test_percentile() {
+    let sorted: Vec<u128> = (1..=100).collect();
+    assert_eq!(percentile(&sorted, 50.0), 51);
+    assert_eq!(percentile(&sorted, 99.0), 99);
+    assert_eq!(percentile(&[7], 95.0), 7);
+    assert_eq!(percentile(&[], 50.0), 0);
+}
+
+/// Due dates are calculated and reported in the SIP account's time
+/// zone, which is sent with every backend request.
+fn test_due_date_timezone(tester: &mut Tester) -> Result<(), String> {
+    let timezone = tester.timezone.clone().unwrap();
+    let barcode = tester.samples.derive("TZ");
+    let e = &mut tester.editor;
+
+    e.xact_begin()?;
+
+    let acn_id = tester.samples.get_default_acp(e)?["call_number"].int()?;
+
+    let acp =
+        tester
+            .samples
+            .create_acp_with_barcode(e, acn_id, &barcode, eg::samples::ACP_STATUS)?;
+
+    e.commit()?;
 
     let req = sip2::Message::from_values(
-        &sip2::spec::M_CHECKIN,
-        &[
-            "N", // renewal policy
-            &sip2::util::sip_date_now(),
-            &sip2::util::sip_date_now(),
-        ],
+        &sip2::spec::M_CHECKOUT,
+        &["N", "N", &sip2::util::sip_date_now(), "                  "],
         &[
             ("AA", &tester.samples.au_barcode),
-            ("AB", &tester.samples.acp_barcode),
+            ("AB", &barcode),
             ("AO", &tester.institution),
-            ("AP", eg::samples::AOU_BR2_SHORTNAME),
         ],
     )
     .unwrap();
@@ -640,22 +2315,33 @@ fn test_checkin_with_transit(tester: &mut Tester) -> Result<(), String> {
     let resp = tester
         .sipcon
         .sendrecv(&req)
-        .or_else(|e| Err(format!("SIP sendrecv error: {e}")))?;
-    t.done("test_checkin_with_transit");
+        .map_err(|e| format!("SIP sendrecv error: {e}"))?;
+    t.done("test_due_date_timezone");
 
-    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkin ok.
+    assert_eq!(resp.fixed_fields()[0].value(), "1"); // checkout ok.
+
+    let e = &mut tester.editor;
+
+    let circ = e
+        .search(
+            "circ",
+            eg::hash! {target_copy: acp.id()?, checkin_time: EgValue::Null},
+        )?
+        .pop()
+        .ok_or("No circulation for time zone test copy")?;
+
+    let due_date = eg::date::parse_datetime(circ["due_date"].str()?)?;
+    let local_due = eg::date::set_timezone(due_date, &timezone)?;
 
     assert_eq!(
-        resp.get_field_value("AQ").unwrap(),
-        eg::samples::AOU_BR2_SHORTNAME
+        resp.get_field_value("AH").unwrap(),
+        local_due.format(sip2::spec::SIP_DATE_FORMAT).to_string()
     );
 
-    // Verify the transit was completed
-    let result = tester.editor.retrieve("atc", transit["id"].clone())?;
-    assert!(result.is_some());
-
-    let transit = result.unwrap();
-    assert!(!transit["dest_recv_time"].is_null());
+    e.xact_begin()?;
+    tester.samples.delete_circs(e, acp.id()?)?;
+    tester.samples.delete_acps(e, &[barcode])?;
+    e.commit()?;
 
     Ok(())
 }
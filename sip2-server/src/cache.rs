@@ -0,0 +1,244 @@
+//! Evergreen data cached across SIP sessions.
+//!
+//! The cache is owned by the Server and shared by all Sessions, so
+//! lookups made by one SIP client benefit the others.  It holds org
+//! units, org unit settings, and small IDL lookup tables (e.g. circ
+//! modifiers), all subject to the same TTL.
+use eg::EgValue;
+use evergreen as eg;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+struct CacheData {
+    /// Org units by ID.
+    orgs: HashMap<i64, EgValue>,
+
+    /// Org unit setting values by org unit ID and setting name.
+    org_settings: HashMap<(i64, String), EgValue>,
+
+    /// All rows of an IDL class by class name, e.g. "ccm".
+    lookups: HashMap<String, Vec<EgValue>>,
+
+    /// Cached data is discarded once it's this old.  None means never.
+    ttl: Option<Duration>,
+
+    /// When the current data was first cached.
+    loaded: Instant,
+}
+
+impl CacheData {
+    fn expired(&self) -> bool {
+        match self.ttl {
+            Some(ttl) => self.loaded.elapsed() >= ttl,
+            None => false,
+        }
+    }
+
+    /// Clear expired data before adding more.
+    fn clear_expired(&mut self) {
+        if self.expired() {
+            log::debug!("Shared cache expired; clearing");
+            self.clear();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.orgs.clear();
+        self.org_settings.clear();
+        self.lookups.clear();
+        self.loaded = Instant::now();
+    }
+}
+
+/// Process-wide cache of org units, org unit settings, and lookup
+/// tables.  Cloning is cheap.
+#[derive(Clone)]
+pub struct SharedCache {
+    data: Arc<RwLock<CacheData>>,
+}
+
+impl SharedCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        let data = CacheData {
+            orgs: HashMap::new(),
+            org_settings: HashMap::new(),
+            lookups: HashMap::new(),
+            ttl,
+            loaded: Instant::now(),
+        };
+
+        SharedCache {
+            data: Arc::new(RwLock::new(data)),
+        }
+    }
+
+    pub fn set_ttl(&self, ttl: Option<Duration>) {
+        self.data.write().unwrap().ttl = ttl;
+    }
+
+    /// Replace the cached org units.
+    pub fn load_orgs(&self, orgs: Vec<EgValue>) -> Result<(), String> {
+        let mut map = HashMap::new();
+
+        for org in orgs {
+            map.insert(org.id()?, org);
+        }
+
+        let mut data = self.data.write().unwrap();
+        data.orgs = map;
+        data.loaded = Instant::now();
+
+        Ok(())
+    }
+
+    /// Discard all cached data.
+    pub fn clear(&self) {
+        self.data.write().unwrap().clear();
+    }
+
+    pub fn org(&self, id: i64) -> Option<EgValue> {
+        let data = self.data.read().unwrap();
+
+        if data.expired() {
+            return None;
+        }
+
+        data.orgs.get(&id).cloned()
+    }
+
+    pub fn org_by_shortname(&self, sn: &str) -> Option<EgValue> {
+        let data = self.data.read().unwrap();
+
+        if data.expired() {
+            return None;
+        }
+
+        data.orgs
+            .values()
+            .find(|o| o["shortname"].as_str() == Some(sn))
+            .cloned()
+    }
+
+    /// Add an org unit to the cache.
+    ///
+    /// Expired data is discarded first.
+    pub fn insert_org(&self, org: EgValue) -> Result<(), String> {
+        let id = org.id()?;
+        let mut data = self.data.write().unwrap();
+
+        data.clear_expired();
+        data.orgs.insert(id, org);
+
+        Ok(())
+    }
+
+    /// Cached value of an org unit setting.  The outer None means the
+    /// setting is not cached; Some(EgValue::Null) means it has no value.
+    pub fn org_setting(&self, org_id: i64, name: &str) -> Option<EgValue> {
+        let data = self.data.read().unwrap();
+
+        if data.expired() {
+            return None;
+        }
+
+        data.org_settings.get(&(org_id, name.to_string())).cloned()
+    }
+
+    /// Add an org unit setting value to the cache.
+    ///
+    /// Expired data is discarded first.
+    pub fn insert_org_setting(&self, org_id: i64, name: &str, value: EgValue) {
+        let mut data = self.data.write().unwrap();
+
+        data.clear_expired();
+        data.org_settings.insert((org_id, name.to_string()), value);
+    }
+
+    /// All cached rows for an IDL class.
+    pub fn lookup_table(&self, classname: &str) -> Option<Vec<EgValue>> {
+        let data = self.data.read().unwrap();
+
+        if data.expired() {
+            return None;
+        }
+
+        data.lookups.get(classname).cloned()
+    }
+
+    /// Replace the cached rows for an IDL class.
+    ///
+    /// Expired data is discarded first.
+    pub fn load_lookup_table(&self, classname: &str, rows: Vec<EgValue>) {
+        let mut data = self.data.write().unwrap();
+
+        data.clear_expired();
+        data.lookups.insert(classname.to_string(), rows);
+    }
+}
+
+#[test]
+fn test_shared_cache() {
+    let org = |id: i64, sn: &str| eg::hash! {"id": id, "shortname": sn};
+
+    let cache = SharedCache::new(None);
+    cache
+        .load_orgs(vec![org(1, "CONS"), org(2, "SYS1")])
+        .unwrap();
+
+    // Clones share the same data.
+    let cache2 = cache.clone();
+    cache2.insert_org(org(4, "BR1")).unwrap();
+
+    assert_eq!(cache.org(2).unwrap()["shortname"].as_str(), Some("SYS1"));
+    assert_eq!(cache.org_by_shortname("BR1").unwrap().id().unwrap(), 4);
+    assert!(cache.org(3).is_none());
+    assert!(cache.org_by_shortname("BR2").is_none());
+
+    cache2.clear();
+    assert!(cache.org(1).is_none());
+
+    cache.load_orgs(vec![org(1, "CONS")]).unwrap();
+    cache.set_ttl(Some(Duration::ZERO));
+
+    // Expired data is never returned and is dropped on insert.
+    assert!(cache.org(1).is_none());
+    cache.insert_org(org(2, "SYS1")).unwrap();
+    cache.set_ttl(None);
+    assert!(cache.org(1).is_none());
+    assert!(cache.org(2).is_some());
+}
+
+#[test]
+fn test_shared_cache_settings_and_lookups() {
+    let cache = SharedCache::new(None);
+
+    assert!(cache.org_setting(1, "lib.timezone").is_none());
+    cache.insert_org_setting(1, "lib.timezone", "America/Chicago".into());
+    cache.insert_org_setting(2, "lib.timezone", EgValue::Null);
+
+    let tz = cache.org_setting(1, "lib.timezone").unwrap();
+    assert_eq!(tz.as_str(), Some("America/Chicago"));
+    assert!(cache.org_setting(2, "lib.timezone").unwrap().is_null());
+    assert!(cache.org_setting(3, "lib.timezone").is_none());
+
+    let ccm = vec![eg::hash! {"code": "BOOK", "sip2_media_type": "001"}];
+    cache.load_lookup_table("ccm", ccm);
+
+    assert_eq!(cache.lookup_table("ccm").unwrap().len(), 1);
+    assert!(cache.lookup_table("cit").is_none());
+
+    cache.clear();
+    assert!(cache.org_setting(1, "lib.timezone").is_none());
+    assert!(cache.lookup_table("ccm").is_none());
+
+    cache.load_lookup_table("ccm", vec![]);
+    cache.set_ttl(Some(Duration::ZERO));
+    assert!(cache.lookup_table("ccm").is_none());
+
+    // Expired data is dropped when new data is added.
+    cache.insert_org_setting(1, "lib.timezone", EgValue::Null);
+    cache.set_ttl(None);
+    assert!(cache.lookup_table("ccm").is_none());
+    assert!(cache.org_setting(1, "lib.timezone").is_some());
+}
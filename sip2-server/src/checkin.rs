@@ -1,6 +1,7 @@
+use super::copyalert::AlertEvent;
 use super::item;
 use super::session::Session;
-use chrono::NaiveDateTime;
+use eg::bindings::{ApiResponse, CheckinRequest};
 use eg::common::circulator::Circulator;
 use eg::constants as C;
 use eg::result::EgResult;
@@ -53,7 +54,7 @@ pub struct CheckinResult {
     patron_barcode: Option<String>,
     alert_type: Option<AlertType>,
     hold_patron_name: Option<String>,
-    hold_patron_barcode: Option<String>,
+    hold_patron_ident: Option<String>,
 }
 
 impl Session {
@@ -65,7 +66,7 @@ impl Session {
             .ok_or_else(|| format!("handle_item_info() missing item barcode"))?;
 
         let current_loc_op = msg.get_field_value("AP");
-        let return_date = &msg.fixed_fields()[2];
+        let backdate = self.checkin_backdate(msg);
 
         // KCLS only
         // cancel == un-fulfill hold this copy currently fulfills
@@ -92,7 +93,7 @@ impl Session {
             None => self.checkin(
                 &item,
                 current_loc_op,
-                return_date.value(),
+                backdate.as_deref(),
                 undo_hold_fulfillment,
                 self.account().settings().checkin_override_all(),
             )?,
@@ -105,7 +106,7 @@ impl Session {
                 sip2::util::sip_bool(!item.magnetic_media),        // resensitize
                 sip2::util::sip_bool(item.magnetic_media),         // magnetic
                 sip2::util::sip_bool(result.alert_type.is_some()), // alert
-                &sip2::util::sip_date_now(),
+                &self.sip_date_now(),
             ],
             &[
                 ("AB", &barcode),
@@ -129,16 +130,18 @@ impl Session {
         if let Some(ref loc) = result.destination_loc {
             resp.add_field("CT", loc);
         }
-        if let Some(ref bc) = result.hold_patron_barcode {
-            resp.add_field("CY", bc);
+        if let Some(ref ident) = result.hold_patron_ident {
+            resp.add_field("CY", ident);
         }
         if let Some(ref n) = result.hold_patron_name {
             resp.add_field("DA", n);
         }
         if blocked_on_co {
-            resp.add_field("AF", "Item Is Currently Checked Out");
+            resp.add_field("AF", &self.tr("Item Is Currently Checked Out"));
         }
 
+        self.add_copy_alerts(&mut resp, &item, AlertEvent::Checkin)?;
+
         Ok(resp)
     }
 
@@ -163,11 +166,11 @@ impl Session {
             patron_barcode: None,
             alert_type: Some(AlertType::Other),
             hold_patron_name: None,
-            hold_patron_barcode: None,
+            hold_patron_ident: None,
         })
     }
 
-    fn return_checkin_item_not_found(&self, barcode: &str) -> sip2::Message {
+    pub fn return_checkin_item_not_found(&self, barcode: &str) -> sip2::Message {
         sip2::Message::from_values(
             &sip2::spec::M_CHECKIN_RESP,
             &[
@@ -175,7 +178,7 @@ impl Session {
                 "N", // resensitize
                 "N", // magnetic
                 "N", // alert
-                &sip2::util::sip_date_now(),
+                &self.sip_date_now(),
             ],
             &[
                 ("AB", &barcode),
@@ -186,18 +189,48 @@ impl Session {
         .unwrap()
     }
 
+    /// Day the item was actually returned, if the client provided one.
+    ///
+    /// Uses the return date, falling back to the transaction date for
+    /// checkins which the client completed while in no-block (offline)
+    /// mode, so overdue fines are voided per policy.  Backdates apply
+    /// to the return day in the library's time zone.
+    fn checkin_backdate(&self, msg: &sip2::Message) -> Option<String> {
+        let no_block = msg.fixed_fields()[0].value();
+        let trans_date = msg.fixed_fields()[1].value();
+        let return_date = msg.fixed_fields()[2].value();
+
+        let mut sip_date = None;
+
+        if return_date.trim().len() == 18 {
+            match self.parse_sip_date(return_date) {
+                Ok(d) => sip_date = Some(d),
+                Err(e) => log::warn!("{self} Invalid checkin return date: {e}"),
+            }
+        }
+
+        if sip_date.is_none() {
+            sip_date = self.no_block_date(no_block, trans_date);
+            if sip_date.is_some() {
+                log::info!("{self} Checkin sent in no-block mode at {trans_date}");
+            }
+        }
+
+        sip_date.map(|d| d.format("%Y-%m-%d").to_string())
+    }
+
     fn checkin(
         &mut self,
         item: &item::Item,
         current_loc_op: Option<&str>,
-        return_date: &str,
+        backdate: Option<&str>,
         cancel: bool,
         ovride: bool,
     ) -> EgResult<CheckinResult> {
         if self.account().settings().use_native_checkin() {
-            self.checkin_native(item, current_loc_op, return_date, cancel, ovride)
+            self.checkin_native(item, current_loc_op, backdate, cancel, ovride)
         } else {
-            self.checkin_api(item, current_loc_op, return_date, cancel, ovride)
+            self.checkin_api(item, current_loc_op, backdate, cancel, ovride)
         }
     }
 
@@ -206,71 +239,50 @@ impl Session {
         &mut self,
         item: &item::Item,
         current_loc_op: Option<&str>,
-        return_date: &str,
+        backdate: Option<&str>,
         cancel: bool,
         ovride: bool,
     ) -> EgResult<CheckinResult> {
-        let mut args = eg::hash! {
-            copy_barcode: item.barcode.as_str(),
-            hold_as_transit: self.account().settings().checkin_holds_as_transits(),
-        };
+        let mut req = CheckinRequest::new(self.authtoken()?);
+        req.set_override(ovride)
+            .set_copy_barcode(&item.barcode)
+            .set_hold_as_transit(self.account().settings().checkin_holds_as_transits());
 
         if cancel {
-            args["revert_hold_fulfillment"] = EgValue::from(cancel);
+            req.set_revert_hold_fulfillment(cancel);
         }
 
-        if return_date.trim().len() == 18 {
-            let fmt = sip2::spec::SIP_DATE_FORMAT;
-
-            // Use NaiveDate since SIP dates don't typically include a
-            // time zone value.
-            if let Some(sip_date) = NaiveDateTime::parse_from_str(return_date, fmt).ok() {
-                let iso_date = sip_date.format("%Y-%m-%d").to_string();
-                log::info!("{self} Checking in with backdate: {iso_date}");
-
-                args["backdate"] = EgValue::from(iso_date);
-            } else {
-                log::warn!("{self} Invalid checkin return date: {return_date}");
-            }
+        if let Some(bd) = backdate {
+            log::info!("{self} Checking in with backdate: {bd}");
+            req.set_backdate(bd);
         }
 
+        let mut circ_lib = None;
         if let Some(sn) = current_loc_op {
             if let Some(org) = self.org_from_sn(sn)? {
-                args["circ_lib"] = org["id"].clone();
+                circ_lib = Some(org.id()?);
             }
         }
 
-        if !args.has_key("circ_lib") {
-            args["circ_lib"] = EgValue::from(self.get_ws_org_id()?);
-        }
-
-        let method = match ovride {
-            true => "open-ils.circ.checkin.override",
-            false => "open-ils.circ.checkin",
+        let circ_lib = match circ_lib {
+            Some(id) => id,
+            None => self.get_ws_org_id()?,
         };
 
-        let params = vec![EgValue::from(self.authtoken()?), args];
+        req.set_circ_lib(circ_lib);
 
-        let mut resp =
-            match self
-                .osrf_client_mut()
-                .send_recv_one("open-ils.circ", method, params)?
-            {
-                Some(r) => r,
-                None => Err(format!("API call {method} failed to return a response"))?,
-            };
+        let resp = self.execute(&req)?;
 
-        log::debug!("{self} Checkin of {} returned: {resp}", item.barcode);
+        log::debug!("{self} Checkin of {} returned: {resp:?}", item.barcode);
 
-        let evt_json = if resp.is_array() {
-            resp[0].take()
-        } else {
-            resp
+        let evt = match resp {
+            ApiResponse::Success(outcome) => outcome.into_event(),
+            ApiResponse::Events(_) => resp
+                .event()
+                .cloned()
+                .ok_or_else(|| format!("Checkin of {} returned no events", item.barcode))?,
         };
 
-        let evt = eg::event::EgEvent::parse(&evt_json)
-            .ok_or(format!("API call {method} failed to return an event"))?;
-
         if !ovride
             && self
                 .account()
@@ -278,7 +290,7 @@ impl Session {
                 .checkin_override()
                 .contains(&evt.textcode().to_string())
         {
-            return self.checkin(item, current_loc_op, return_date, cancel, true);
+            return self.checkin(item, current_loc_op, backdate, cancel, true);
         }
 
         let mut current_loc = item.current_loc.to_string(); // item.circ_lib
@@ -319,7 +331,7 @@ impl Session {
             patron_barcode: None,
             alert_type: None,
             hold_patron_name: None,
-            hold_patron_barcode: None,
+            hold_patron_ident: None,
         };
 
         let circ = &evt.payload()["circ"];
@@ -338,7 +350,7 @@ impl Session {
 
         self.handle_hold(&evt, &mut result)?;
 
-        if evt.textcode().eq("SUCCESS") || evt.textcode().eq("NO_CHANGE") {
+        if evt.is_success() {
             result.ok = true;
         } else if evt.textcode().eq("ROUTE_ITEM") {
             result.ok = true;
@@ -361,7 +373,7 @@ impl Session {
         &mut self,
         item: &item::Item,
         current_loc_op: Option<&str>,
-        return_date: &str,
+        backdate: Option<&str>,
         cancel: bool,
         ovride: bool,
     ) -> EgResult<CheckinResult> {
@@ -376,19 +388,9 @@ impl Session {
             options.insert("revert_hold_fulfillment".to_string(), EgValue::from(cancel));
         }
 
-        if return_date.trim().len() == 18 {
-            let fmt = sip2::spec::SIP_DATE_FORMAT;
-
-            // Use NaiveDate since SIP dates don't typically include a
-            // time zone value.
-            if let Some(sip_date) = NaiveDateTime::parse_from_str(return_date, fmt).ok() {
-                let iso_date = sip_date.format("%Y-%m-%d").to_string();
-                log::info!("{self} Checking in with backdate: {iso_date}");
-
-                options.insert("backdate".to_string(), EgValue::from(iso_date));
-            } else {
-                log::warn!("{self} Invalid checkin return date: {return_date}");
-            }
+        if let Some(bd) = backdate {
+            log::info!("{self} Checking in with backdate: {bd}");
+            options.insert("backdate".to_string(), EgValue::from(bd));
         }
 
         if let Some(sn) = current_loc_op {
@@ -439,7 +441,7 @@ impl Session {
                 .checkin_override()
                 .contains(&evt.textcode().to_string())
         {
-            return self.checkin(item, current_loc_op, return_date, cancel, true);
+            return self.checkin(item, current_loc_op, backdate, cancel, true);
         }
 
         let mut current_loc = item.current_loc.to_string(); // item.circ_lib
@@ -481,7 +483,7 @@ impl Session {
             patron_barcode: None,
             alert_type: None,
             hold_patron_name: None,
-            hold_patron_barcode: None,
+            hold_patron_ident: None,
         };
 
         let circ = &evt.payload()["circ"];
@@ -500,7 +502,7 @@ impl Session {
 
         self.handle_hold(&evt, &mut result)?;
 
-        if evt.textcode().eq("SUCCESS") || evt.textcode().eq("NO_CHANGE") {
+        if evt.is_success() {
             result.ok = true;
         } else if evt.textcode().eq("ROUTE_ITEM") {
             result.ok = true;
@@ -539,9 +541,7 @@ impl Session {
 
         if let Some(user) = self.get_user_and_card(hold["usr"].int()?)? {
             result.hold_patron_name = Some(self.format_user_name(&user));
-            if let Some(bc) = user["card"]["barcode"].as_str() {
-                result.hold_patron_barcode = Some(bc.to_string());
-            }
+            result.hold_patron_ident = self.hold_patron_identifier(&user)?;
         }
 
         let pickup_lib_id;
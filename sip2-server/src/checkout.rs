@@ -1,6 +1,8 @@
+use super::copyalert::AlertEvent;
 use super::item::Item;
 use super::patron::Patron;
 use super::session::Session;
+use eg::bindings::{ApiResponse, CheckoutRequest, CircOutcome, RenewRequest};
 use eg::common::circulator::Circulator;
 use eg::date;
 use eg::result::EgResult;
@@ -8,16 +10,56 @@ use eg::EgValue;
 use evergreen as eg;
 use std::collections::HashMap;
 
-const RENEW_METHOD: &str = "open-ils.circ.renew";
-const RENEW_OVERRIDE_METHOD: &str = "open-ils.circ.renew.override";
-const CHECKOUT_METHOD: &str = "open-ils.circ.checkout.full";
-const CHECKOUT_OVERRIDE_METHOD: &str = "open-ils.circ.checkout.full.override";
+/// Screen messages for checkout and renewal failures, keyed on the
+/// event textcode.  Includes the circ policy blocks reported by the
+/// circulation matrix tests.
+const CHECKOUT_FAILURE_MESSAGES: &[(&str, &str)] = &[
+    (
+        "OPEN_CIRCULATION_EXISTS",
+        "This item is already checked out",
+    ),
+    ("PATRON_BARRED", "Patron account is barred"),
+    ("PATRON_EXCEEDS_FINES", "Patron exceeds fine threshold"),
+    (
+        "PATRON_EXCEEDS_CHECKOUT_COUNT",
+        "Patron has too many items checked out",
+    ),
+    (
+        "PATRON_EXCEEDS_OVERDUE_COUNT",
+        "Patron has too many overdue items",
+    ),
+    (
+        "PATRON_EXCEEDS_LOST_COUNT",
+        "Patron has too many lost items",
+    ),
+    ("COPY_CIRC_NOT_ALLOWED", "This item does not circulate"),
+    ("COPY_NOT_AVAILABLE", "This item is not available"),
+    ("COPY_NEEDED_FOR_HOLD", "This item is needed for a hold"),
+    (
+        "MAX_RENEWALS_REACHED",
+        "This item has no renewals remaining",
+    ),
+    ("ITEM_NOT_CATALOGED", "Item not found"),
+];
+
+/// Used for failures with no specific screen message.
+const DEFAULT_CHECKOUT_FAILURE: &str = "Patron is not allowed to checkout the selected item";
+
+/// Screen message for a failed checkout or renewal event.
+fn checkout_failure_message(textcode: &str) -> &'static str {
+    CHECKOUT_FAILURE_MESSAGES
+        .iter()
+        .find(|(code, _)| *code == textcode)
+        .map(|(_, msg)| *msg)
+        .unwrap_or(DEFAULT_CHECKOUT_FAILURE)
+}
 
 pub struct CheckoutResult {
     /// Presence of a circ_id implies success.
     circ_id: Option<i64>,
     due_date: Option<String>,
     renewal_remaining: i64,
+    /// Translated when added to the response.
     screen_msg: Option<&'static str>,
     was_renewal: bool,
 }
@@ -34,6 +76,20 @@ impl CheckoutResult {
     }
 }
 
+/// A checkout our client already completed while in no-block
+/// (offline) mode.
+///
+/// These are recorded as-is: blocks are overridden and the circ
+/// starts at the transaction time.  The circ carries the SIP
+/// workstation and a backdated xact_start, which mark it as offline
+/// originated.  Applying the client's due date requires the
+/// CIRC_OVERRIDE_DUE_DATE permission.
+#[derive(Debug, Clone)]
+struct NoBlockCheckout {
+    checkout_time: String,
+    due_date: Option<String>,
+}
+
 impl Session {
     pub fn handle_checkout(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
         self.set_authtoken()?;
@@ -71,15 +127,28 @@ impl Session {
         let renew_ok = msg.fixed_fields()[0].value().eq("Y");
         let same_patron = item.circ_patron_id.unwrap_or(-1) == patron.id;
 
+        let no_block = self.no_block_checkout(msg);
+        let ovride = no_block.is_some() || self.account().settings().checkout_override_all();
+
         let result = self.checkout(
             &item_barcode,
             &patron_barcode,
             fee_ack_op.is_some(),
             renew_ok && same_patron, // is_renewal
-            self.account().settings().checkout_override_all(),
+            ovride,
+            no_block.as_ref(),
         )?;
 
-        self.compile_checkout_response(&item, &patron, &result)
+        let mut resp = self.compile_checkout_response(&item, &patron, &result)?;
+
+        let event = match result.was_renewal {
+            true => AlertEvent::Renewal,
+            false => AlertEvent::Checkout,
+        };
+
+        self.add_copy_alerts(&mut resp, &item, event)?;
+
+        Ok(resp)
     }
 
     fn compile_checkout_response(
@@ -97,7 +166,7 @@ impl Session {
                 sip2::util::sip_bool(result.was_renewal),       // renew ok
                 sip2::util::sip_bool(magnetic),                 // magnetic
                 sip2::util::sip_bool(!magnetic),                // desensitize
-                &sip2::util::sip_date_now(),                    // timestamp
+                &self.sip_date_now(),                           // timestamp
             ],
             &[
                 ("AA", &patron.barcode),
@@ -111,7 +180,7 @@ impl Session {
         )
         .unwrap();
 
-        resp.maybe_add_field("AF", result.screen_msg.as_deref());
+        resp.maybe_add_field("AF", result.screen_msg.map(|m| self.tr(m)).as_deref());
         resp.maybe_add_field("AH", result.due_date.as_deref());
 
         if let Some(id) = result.circ_id {
@@ -133,11 +202,11 @@ impl Session {
         sip2::Message::from_values(
             &sip2::spec::M_CHECKOUT_RESP,
             &[
-                "0",                         // checkin ok
-                "N",                         // renew ok
-                "N",                         // magnetic
-                "N",                         // desensitize
-                &sip2::util::sip_date_now(), // timestamp
+                "0",                  // checkin ok
+                "N",                  // renew ok
+                "N",                  // magnetic
+                "N",                  // desensitize
+                &self.sip_date_now(), // timestamp
             ],
             &[
                 ("AA", &patron_barcode),
@@ -148,6 +217,35 @@ impl Session {
         .unwrap()
     }
 
+    /// Details of a checkout sent in no-block mode, if any.
+    ///
+    /// The due date is the client's no-block due date when provided,
+    /// otherwise it is computed from the checkout time.
+    fn no_block_checkout(&self, msg: &sip2::Message) -> Option<NoBlockCheckout> {
+        let no_block = msg.fixed_fields()[1].value();
+        let trans_date = msg.fixed_fields()[2].value();
+        let nb_due_date = msg.fixed_fields()[3].value();
+
+        let checkout_time = self.no_block_date(no_block, trans_date)?;
+
+        let mut due_date = None;
+        if nb_due_date.trim().len() == 18 {
+            match self.parse_sip_date(nb_due_date) {
+                Ok(d) => due_date = Some(date::to_iso(&d)),
+                Err(e) => log::warn!("{self} Invalid no-block due date: {e}"),
+            }
+        }
+
+        log::info!(
+            "{self} Recording offline checkout from {trans_date} with due date {due_date:?}"
+        );
+
+        Some(NoBlockCheckout {
+            checkout_time: date::to_iso(&checkout_time),
+            due_date,
+        })
+    }
+
     fn checkout(
         &mut self,
         item_barcode: &str,
@@ -155,11 +253,26 @@ impl Session {
         fee_ack: bool,
         is_renewal: bool,
         ovride: bool,
+        no_block: Option<&NoBlockCheckout>,
     ) -> EgResult<CheckoutResult> {
         if self.account().settings().use_native_checkout() {
-            self.checkout_native(item_barcode, patron_barcode, fee_ack, is_renewal, ovride)
+            self.checkout_native(
+                item_barcode,
+                patron_barcode,
+                fee_ack,
+                is_renewal,
+                ovride,
+                no_block,
+            )
         } else {
-            self.checkout_api(item_barcode, patron_barcode, fee_ack, is_renewal, ovride)
+            self.checkout_api(
+                item_barcode,
+                patron_barcode,
+                fee_ack,
+                is_renewal,
+                ovride,
+                no_block,
+            )
         }
     }
 
@@ -171,68 +284,63 @@ impl Session {
         fee_ack: bool,
         is_renewal: bool,
         ovride: bool,
+        no_block: Option<&NoBlockCheckout>,
     ) -> EgResult<CheckoutResult> {
-        let params = vec![
-            EgValue::from(self.authtoken()?),
-            eg::hash! {
-                copy_barcode: item_barcode,
-                patron_barcode: patron_barcode,
-            },
-        ];
-
-        let method = match is_renewal {
-            true => match ovride {
-                true => RENEW_OVERRIDE_METHOD,
-                false => RENEW_METHOD,
-            },
-            false => match ovride {
-                true => CHECKOUT_OVERRIDE_METHOD,
-                false => CHECKOUT_METHOD,
-            },
-        };
+        let authtoken = self.authtoken()?.to_string();
+        let checkout_time = no_block.map(|nb| nb.checkout_time.as_str());
+        let due_date = no_block.and_then(|nb| nb.due_date.as_deref());
+
+        let resp = if is_renewal {
+            let mut req = RenewRequest::new(&authtoken);
+            req.set_override(ovride)
+                .set_copy_barcode(item_barcode)
+                .set_patron_barcode(patron_barcode);
+
+            if let Some(time) = checkout_time {
+                req.set_checkout_time(time);
+            }
+            if let Some(due) = due_date {
+                req.set_due_date(due);
+            }
 
-        let mut resp =
-            match self
-                .osrf_client_mut()
-                .send_recv_one("open-ils.circ", method, params)?
-            {
-                Some(r) => r,
-                None => Err(format!("API call {method} failed to return a response"))?,
-            };
+            self.execute(&req)?
+        } else {
+            let mut req = CheckoutRequest::new(&authtoken);
+            req.set_override(ovride)
+                .set_copy_barcode(item_barcode)
+                .set_patron_barcode(patron_barcode);
 
-        log::debug!("{self} Checkout of {item_barcode} returned: {resp}");
+            if let Some(time) = checkout_time {
+                req.set_checkout_time(time);
+            }
+            if let Some(due) = due_date {
+                req.set_due_date(due);
+            }
 
-        let event = if resp.is_array() {
-            resp[0].take()
-        } else {
-            resp
+            self.execute(&req)?
         };
 
+        log::debug!("{self} Checkout of {item_barcode} returned: {resp:?}");
+
         let mut result = CheckoutResult::new();
         result.was_renewal = is_renewal;
 
-        let evt = eg::event::EgEvent::parse(&event)
-            .ok_or_else(|| format!("API call {method} failed to return an event"))?;
-
-        if evt.is_success() {
-            let circ = &evt.payload()["circ"];
-
-            if circ.is_object() {
-                result.circ_id = Some(circ.id()?);
-                result.renewal_remaining = circ["renewal_remaining"].int()?;
-
-                let iso_date = circ["due_date"].as_str().unwrap(); // required
-                if self.account().settings().due_date_use_sip_date_format() {
-                    let due_dt = date::parse_datetime(iso_date)?;
-                    result.due_date = Some(sip2::util::sip_date_from_dt(&due_dt));
-                } else {
-                    result.due_date = Some(iso_date.to_string());
-                }
-
+        let evt = match resp {
+            ApiResponse::Success(outcome) => {
+                self.apply_circ_outcome(&outcome, &mut result)?;
                 return Ok(result);
-            } else {
-                log::error!("{self} checked out, but did not receive a circ object");
             }
+            ApiResponse::Events(ref events) => match resp.event() {
+                Some(e) => e.clone(),
+                None => Err(format!(
+                    "Checkout of {item_barcode} returned {} events",
+                    events.len()
+                ))?,
+            },
+        };
+
+        if evt.is_success() {
+            log::error!("{self} checked out, but did not receive a circ object");
         }
 
         let can_override = self
@@ -242,7 +350,14 @@ impl Session {
             .contains(&evt.textcode().to_string());
 
         if !ovride && can_override {
-            return self.checkout(item_barcode, patron_barcode, fee_ack, is_renewal, true);
+            return self.checkout(
+                item_barcode,
+                patron_barcode,
+                fee_ack,
+                is_renewal,
+                true,
+                no_block,
+            );
         }
 
         if !ovride && fee_ack {
@@ -250,22 +365,39 @@ impl Session {
             if evt.textcode().eq("ITEM_DEPOSIT_FEE_REQUIRED")
                 || evt.textcode().eq("ITEM_RENTAL_FEE_REQUIRED")
             {
-                return self.checkout(item_barcode, patron_barcode, fee_ack, is_renewal, true);
+                return self.checkout(
+                    item_barcode,
+                    patron_barcode,
+                    fee_ack,
+                    is_renewal,
+                    true,
+                    no_block,
+                );
             }
         }
 
-        // TODO gettext() can be used for these string literals below, but
-        // it's a massive dependency for just a couple of sentences.
-        // There's likely a better approach.
-        if evt.textcode().eq("OPEN_CIRCULATION_EXISTS") {
-            result.screen_msg = Some("This item is already checked out");
-        } else {
-            result.screen_msg = Some("Patron is not allowed to checkout the selected item");
+        if let Some((perm, org)) = evt.is_permission_failure() {
+            log::info!("{self} checkout failed on permission {perm} at org {org:?}");
         }
 
+        result.screen_msg = Some(checkout_failure_message(evt.textcode()));
+
         Ok(result)
     }
 
+    /// Copy the details of a successful checkout or renewal into
+    /// our result.
+    fn apply_circ_outcome(
+        &mut self,
+        outcome: &CircOutcome,
+        result: &mut CheckoutResult,
+    ) -> EgResult<()> {
+        result.circ_id = Some(outcome.circ_id()?);
+        result.renewal_remaining = outcome.renewal_remaining()?;
+        result.due_date = self.sip_due_date(outcome.due_date())?;
+        Ok(())
+    }
+
     /// Checkout that runs within the current thread as a direct
     /// Rust call.
     fn checkout_native(
@@ -275,12 +407,23 @@ impl Session {
         fee_ack: bool,
         is_renewal: bool,
         ovride: bool,
+        no_block: Option<&NoBlockCheckout>,
     ) -> EgResult<CheckoutResult> {
         let mut options: HashMap<String, EgValue> = HashMap::new();
 
         options.insert("copy_barcode".to_string(), item_barcode.into());
         options.insert("patron_barcode".to_string(), patron_barcode.into());
 
+        if let Some(nb) = no_block {
+            options.insert(
+                "checkout_time".to_string(),
+                nb.checkout_time.as_str().into(),
+            );
+            if let Some(ref due) = nb.due_date {
+                options.insert("due_date".to_string(), due.as_str().into());
+            }
+        }
+
         // Standalone transaction; cloning is just easier here.
         let mut editor = self.editor().clone();
 
@@ -320,19 +463,11 @@ impl Session {
         result.was_renewal = is_renewal;
 
         if evt.is_success() {
-            let circ = &evt.payload()["circ"];
-
-            if circ.is_object() {
+            if let Some(circ) = evt.payload_value("circ") {
                 result.circ_id = Some(circ.id()?);
                 result.renewal_remaining = circ["renewal_remaining"].int()?;
 
-                let iso_date = circ["due_date"].as_str().unwrap(); // required
-                if self.account().settings().due_date_use_sip_date_format() {
-                    let due_dt = date::parse_datetime(iso_date)?;
-                    result.due_date = Some(sip2::util::sip_date_from_dt(&due_dt));
-                } else {
-                    result.due_date = Some(iso_date.to_string());
-                }
+                result.due_date = self.sip_due_date(circ["due_date"].as_str())?;
 
                 return Ok(result);
             } else {
@@ -348,7 +483,14 @@ impl Session {
             .contains(&evt.textcode().to_string());
 
         if !ovride && can_override {
-            return self.checkout(item_barcode, patron_barcode, fee_ack, is_renewal, true);
+            return self.checkout(
+                item_barcode,
+                patron_barcode,
+                fee_ack,
+                is_renewal,
+                true,
+                no_block,
+            );
         }
 
         if !ovride && fee_ack {
@@ -356,19 +498,39 @@ impl Session {
             if evt.textcode().eq("ITEM_DEPOSIT_FEE_REQUIRED")
                 || evt.textcode().eq("ITEM_RENTAL_FEE_REQUIRED")
             {
-                return self.checkout(item_barcode, patron_barcode, fee_ack, is_renewal, true);
+                return self.checkout(
+                    item_barcode,
+                    patron_barcode,
+                    fee_ack,
+                    is_renewal,
+                    true,
+                    no_block,
+                );
             }
         }
 
-        // TODO gettext() can be used for these string literals below, but
-        // it's a massive dependency for just a couple of sentances.
-        // There's likely a better approach.
-        if evt.textcode().eq("OPEN_CIRCULATION_EXISTS") {
-            result.screen_msg = Some("This item is already checked out");
-        } else {
-            result.screen_msg = Some("Patron is not allowed to checkout the selected item");
+        if let Some((perm, org)) = evt.is_permission_failure() {
+            log::info!("{self} checkout failed on permission {perm} at org {org:?}");
         }
 
+        result.screen_msg = Some(checkout_failure_message(evt.textcode()));
+
         Ok(result)
     }
 }
+
+#[test]
+fn test_checkout_failure_message() {
+    assert_eq!(
+        checkout_failure_message("OPEN_CIRCULATION_EXISTS"),
+        "This item is already checked out"
+    );
+    assert_eq!(
+        checkout_failure_message("PATRON_EXCEEDS_FINES"),
+        "Patron exceeds fine threshold"
+    );
+    assert_eq!(
+        checkout_failure_message("SOME_OTHER_EVENT"),
+        DEFAULT_CHECKOUT_FAILURE
+    );
+}
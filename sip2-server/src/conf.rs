@@ -1,6 +1,37 @@
+use super::access::Cidr;
+use super::audit::AuditConfig;
+use super::confload::{ConfigProblem, ConfigSource};
+use super::custom::{FieldMapping, FieldSuppression};
+use super::itemmap::ItemMapping;
+use super::limits::PrintLineOverflow;
+use super::offline::OfflineConfig;
+use super::password;
+use super::ratelimit::RateLimitConfig;
+use super::register;
+use super::shutdown;
+use super::sipdate;
+use super::version::ProtocolVersion;
+use evergreen as eg;
+use evergreen::money::Money;
 use std::collections::HashMap;
-use std::fs;
-use yaml_rust::YamlLoader;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Parse a list of CIDR ranges from a yaml node.
+fn parse_allowlist(g: &yaml_rust::Yaml, k: &str) -> Result<Vec<Cidr>, String> {
+    let mut list = Vec::new();
+
+    if let Some(entries) = g[k].as_vec() {
+        for entry in entries {
+            let s = entry
+                .as_str()
+                .ok_or_else(|| format!("Invalid {k} entry: {entry:?}"))?;
+            list.push(Cidr::parse(s)?);
+        }
+    }
+
+    Ok(list)
+}
 
 // Shorthand for pulling a bool value from a yaml
 // node and applying it to a setting.
@@ -14,6 +45,34 @@ fn set_bool(g: &yaml_rust::Yaml, k: &str, f: &mut bool) {
 /// (or other) signal.
 pub const SIP_SHUTDOWN_POLL_INTERVAL: u64 = 3;
 
+/// Seconds a SIP client has to complete the TLS handshake.
+pub const DEFAULT_TLS_HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// Seconds to wait for an Evergreen API call before giving up.
+pub const DEFAULT_OSRF_REQUEST_TIMEOUT: u64 = 60;
+
+/// Vendor extension message codes for patron self-registration.
+pub const DEFAULT_PATRON_REGISTER_MESSAGE: &str = "85";
+pub const DEFAULT_PATRON_REGISTER_RESPONSE: &str = "86";
+
+/// SIP payment types mapped to Evergreen payment types, unless the
+/// setting group says otherwise.  '01' is "VISA"; '02' is "credit
+/// card"; '05' is "check".
+const DEFAULT_PAYMENT_TYPES: &[(&str, &str)] = &[
+    ("00", "cash_payment"),
+    ("01", "credit_card_payment"),
+    ("02", "credit_card_payment"),
+    ("05", "check_payment"),
+];
+
+/// Evergreen payment types a SIP payment type may map to.
+const PAYMENT_TYPES: &[&str] = &[
+    "cash_payment",
+    "check_payment",
+    "credit_card_payment",
+    "debit_card_payment",
+];
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Msg64HoldDatatype {
     Barcode,
@@ -26,6 +85,13 @@ pub enum Msg64SummaryDatatype {
     Title,
 }
 
+/// How hold patrons are identified in CY fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HoldPatronIdentifier {
+    Barcode,
+    Id,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AvFormat {
     Legacy,
@@ -61,26 +127,62 @@ impl FieldFilter {
     }
 }
 
+/// What to report in the CQ (valid patron password) field when the
+/// SIP client sends no patron password.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CqWithoutPassword {
+    Yes,
+    No,
+    Omit,
+}
+
+impl From<&str> for CqWithoutPassword {
+    fn from(s: &str) -> CqWithoutPassword {
+        match s.to_lowercase().as_str() {
+            "y" | "yes" => Self::Yes,
+            "omit" => Self::Omit,
+            _ => Self::No,
+        }
+    }
+}
+
 /// Named collection of SIP session settings.
 #[derive(Debug, Clone)]
 pub struct SipSettings {
     institution: String,
     due_date_use_sip_date_format: bool,
+    due_date_format: Option<String>,
     patron_status_permit_all: bool,
     patron_status_permit_loans: bool,
     msg64_hold_items_available: bool,
     checkin_holds_as_transits: bool,
     msg64_hold_datatype: Msg64HoldDatatype,
     msg64_summary_datatype: Msg64SummaryDatatype,
+    msg64_hold_ready_template: Option<String>,
+    msg64_hold_unavail_template: Option<String>,
+    hold_patron_identifier: HoldPatronIdentifier,
+    patron_expired_message: Option<String>,
+    patron_max_fine: Option<Money>,
+    patron_info_expose_dates: bool,
+    patron_info_concurrent_queries: bool,
+    juvenile_suppress_fields: Vec<String>,
+    timezone: Option<String>,
     av_format: AvFormat,
     checkout_override_all: bool,
     checkin_override_all: bool,
     checkout_override: Vec<String>,
     checkin_override: Vec<String>,
     field_filters: Vec<FieldFilter>,
+    print_line_overflow: PrintLineOverflow,
     sc_status_library_info: bool,
     use_native_checkin: bool,
     use_native_checkout: bool,
+    payment_receipt_balance: bool,
+    payment_receipt_xact_ids: bool,
+    payment_receipt_summary: bool,
+    payment_receipt_email: bool,
+    payment_types: Vec<(String, String)>,
+    cc_arg_fields: Vec<(String, String)>,
 }
 
 impl SipSettings {
@@ -88,12 +190,22 @@ impl SipSettings {
         SipSettings {
             institution: institution.to_string(),
             due_date_use_sip_date_format: true,
+            due_date_format: None,
             patron_status_permit_all: false,
             patron_status_permit_loans: false,
             msg64_hold_items_available: false,
             checkin_holds_as_transits: false,
             msg64_hold_datatype: Msg64HoldDatatype::Barcode,
             msg64_summary_datatype: Msg64SummaryDatatype::Barcode,
+            msg64_hold_ready_template: None,
+            msg64_hold_unavail_template: None,
+            hold_patron_identifier: HoldPatronIdentifier::Barcode,
+            patron_expired_message: None,
+            patron_max_fine: None,
+            patron_info_expose_dates: false,
+            patron_info_concurrent_queries: true,
+            juvenile_suppress_fields: Vec::new(),
+            timezone: None,
             av_format: AvFormat::ThreeM,
             checkout_override_all: false,
             checkin_override_all: false,
@@ -101,8 +213,18 @@ impl SipSettings {
             checkout_override: Vec::new(),
             checkin_override: Vec::new(),
             field_filters: Vec::new(),
+            print_line_overflow: PrintLineOverflow::Truncate,
             use_native_checkin: false,
             use_native_checkout: false,
+            payment_receipt_balance: false,
+            payment_receipt_xact_ids: false,
+            payment_receipt_summary: false,
+            payment_receipt_email: false,
+            payment_types: DEFAULT_PAYMENT_TYPES
+                .iter()
+                .map(|(code, ptype)| (code.to_string(), ptype.to_string()))
+                .collect(),
+            cc_arg_fields: Vec::new(),
         }
     }
     /// If true, uses the native Rust checkin API.
@@ -120,6 +242,44 @@ impl SipSettings {
     pub fn due_date_use_sip_date_format(&self) -> bool {
         self.due_date_use_sip_date_format
     }
+    /// strftime-style format for due dates, e.g. "%B %-d, %Y %-I:%M %p".
+    ///
+    /// Takes precedence over due_date_use_sip_date_format.
+    pub fn due_date_format(&self) -> Option<&str> {
+        self.due_date_format.as_deref()
+    }
+    /// Include the patron's new balance (BV) in fee paid responses.
+    pub fn payment_receipt_balance(&self) -> bool {
+        self.payment_receipt_balance
+    }
+    /// Include the IDs of the paid transactions (CG) in fee paid responses.
+    pub fn payment_receipt_xact_ids(&self) -> bool {
+        self.payment_receipt_xact_ids
+    }
+    /// Include print lines (AG) summarizing the payment in fee paid
+    /// responses.
+    pub fn payment_receipt_summary(&self) -> bool {
+        self.payment_receipt_summary
+    }
+    /// Email the patron a receipt for successful payments via the
+    /// payment receipt A/T hook.
+    pub fn payment_receipt_email(&self) -> bool {
+        self.payment_receipt_email
+    }
+    /// Evergreen payment type for a SIP payment type.  Unmapped
+    /// types are cash payments.
+    pub fn payment_type(&self, sip_type: &str) -> &str {
+        self.payment_types
+            .iter()
+            .find(|(code, _)| code == sip_type)
+            .map(|(_, ptype)| ptype.as_str())
+            .unwrap_or("cash_payment")
+    }
+    /// Credit card payment details (cc_args keys) and the SIP
+    /// extension fields they are read from.
+    pub fn cc_arg_fields(&self) -> &[(String, String)] {
+        &self.cc_arg_fields
+    }
     /// If true patrons are only reported as blocked if the account
     /// is expired.  Fines, overdues, etc. are ignored.
     pub fn patron_status_permit_all(&self) -> bool {
@@ -141,6 +301,52 @@ impl SipSettings {
     pub fn msg64_hold_datatype(&self) -> &Msg64HoldDatatype {
         &self.msg64_hold_datatype
     }
+    /// When set, hold items lists contain holds ready for pickup
+    /// formatted with this template.
+    ///
+    /// Supports {title}, {barcode}, {pickup_lib}, and {shelf_expire}.
+    pub fn msg64_hold_ready_template(&self) -> Option<&str> {
+        self.msg64_hold_ready_template.as_deref()
+    }
+    /// When set, unavailable hold items lists are formatted with this
+    /// template.
+    ///
+    /// Supports {title}, {pickup_lib}, {queue_position}, {queue_length},
+    /// and {wait_days}.
+    pub fn msg64_hold_unavail_template(&self) -> Option<&str> {
+        self.msg64_hold_unavail_template.as_deref()
+    }
+    /// Report hold patrons in CY fields by barcode or user ID.
+    pub fn hold_patron_identifier(&self) -> &HoldPatronIdentifier {
+        &self.hold_patron_identifier
+    }
+    /// AF screen message for patrons whose card has expired.
+    pub fn patron_expired_message(&self) -> Option<&str> {
+        self.patron_expired_message.as_deref()
+    }
+    /// Patrons owing more than this amount are reported as blocked,
+    /// whether or not they have a fines penalty.
+    pub fn patron_max_fine(&self) -> Option<Money> {
+        self.patron_max_fine
+    }
+    /// Include the PB birth date and PA expiration date fields in
+    /// patron information responses.
+    pub fn patron_info_expose_dates(&self) -> bool {
+        self.patron_info_expose_dates
+    }
+    /// Fetch patron status and information data with concurrent
+    /// Evergreen requests instead of one request at a time.
+    pub fn patron_info_concurrent_queries(&self) -> bool {
+        self.patron_info_concurrent_queries
+    }
+    /// Field codes removed from patron responses for juvenile patrons.
+    pub fn juvenile_suppress_fields(&self) -> &Vec<String> {
+        &self.juvenile_suppress_fields
+    }
+    /// Time zone for SIP dates, e.g. America/Chicago.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
     /// Format for fine items
     pub fn av_format(&self) -> &AvFormat {
         &self.av_format
@@ -175,36 +381,85 @@ impl SipSettings {
     pub fn sc_status_library_info(&self) -> bool {
         self.sc_status_library_info
     }
+    /// Truncate or split print lines wider than the client's print width.
+    pub fn print_line_overflow(&self) -> PrintLineOverflow {
+        self.print_line_overflow
+    }
+}
+
+/// How a SIP account password is stored in the configuration.
+#[derive(Debug, Clone)]
+pub enum SipPassword {
+    Plain(String),
+    Hashed(String),
 }
 
 #[derive(Debug, Clone)]
 pub struct SipAccount {
     settings: SipSettings,
     sip_username: String,
-    sip_password: String,
+    sip_password: SipPassword,
     ils_username: String,
     ils_user_id: Option<i64>,
     workstation: Option<String>,
+    allow_auto_workstation: bool,
+    workstation_org: Option<String>,
     activity_as: Option<String>,
     checkin_block_on_checked_out: bool,
+    allow_item_status_update: bool,
+    allow_patron_register: bool,
+    cq_without_password: CqWithoutPassword,
+    allowed_addresses: Vec<Cidr>,
+    max_sessions: Option<usize>,
+    field_mappings: Vec<FieldMapping>,
+    suppress_fields: Vec<FieldSuppression>,
+    simulate_transport_failure: Option<String>,
+    item_mapping: ItemMapping,
+    rate_limit: RateLimitConfig,
+    locale: Option<String>,
+    timezone: Option<String>,
+    encoding: Option<sip2::Encoding>,
+    name_format: Option<String>,
+    name_use_preferred: bool,
+    protocol_version: Option<ProtocolVersion>,
+    suppress_copy_alerts: bool,
 }
 
 impl SipAccount {
     pub fn new(
         settings: &SipSettings,
         sip_username: &str,
-        sip_password: &str,
+        sip_password: SipPassword,
         ils_username: &str,
     ) -> SipAccount {
         SipAccount {
             settings: settings.clone(),
             sip_username: sip_username.to_string(),
-            sip_password: sip_password.to_string(),
+            sip_password,
             ils_username: ils_username.to_string(),
             ils_user_id: None,
             workstation: None,
+            allow_auto_workstation: false,
+            workstation_org: None,
             activity_as: None,
             checkin_block_on_checked_out: false,
+            allow_item_status_update: false,
+            allow_patron_register: false,
+            cq_without_password: CqWithoutPassword::No,
+            allowed_addresses: Vec::new(),
+            max_sessions: None,
+            field_mappings: Vec::new(),
+            suppress_fields: Vec::new(),
+            simulate_transport_failure: None,
+            item_mapping: ItemMapping::default(),
+            rate_limit: RateLimitConfig::default(),
+            locale: None,
+            timezone: None,
+            encoding: None,
+            name_format: None,
+            name_use_preferred: false,
+            protocol_version: None,
+            suppress_copy_alerts: false,
         }
     }
 
@@ -214,8 +469,12 @@ impl SipAccount {
     pub fn sip_username(&self) -> &str {
         &self.sip_username
     }
-    pub fn sip_password(&self) -> &str {
-        &self.sip_password
+    /// True if the provided password matches the account password.
+    pub fn verify_sip_password(&self, password: &str) -> bool {
+        match &self.sip_password {
+            SipPassword::Plain(p) => p.eq(password),
+            SipPassword::Hashed(h) => password::verify_password(password, h),
+        }
     }
     pub fn ils_username(&self) -> &str {
         &self.ils_username
@@ -229,6 +488,15 @@ impl SipAccount {
     pub fn workstation(&self) -> Option<&str> {
         self.workstation.as_deref()
     }
+    /// Register the workstation in the ILS if it does not exist.
+    pub fn allow_auto_workstation(&self) -> bool {
+        self.allow_auto_workstation
+    }
+    /// Shortname of the org unit where an automatically registered
+    /// workstation lives.  Defaults to the ILS user's home org unit.
+    pub fn workstation_org(&self) -> Option<&str> {
+        self.workstation_org.as_deref()
+    }
     pub fn activity_as(&self) -> Option<&str> {
         self.activity_as.as_deref()
     }
@@ -236,6 +504,88 @@ impl SipAccount {
     pub fn checkin_block_on_checked_out(&self) -> bool {
         self.checkin_block_on_checked_out
     }
+    /// Allow Item Status Update (19) messages to modify items.
+    pub fn allow_item_status_update(&self) -> bool {
+        self.allow_item_status_update
+    }
+    /// Allow patron self-registration messages to create staged users.
+    pub fn allow_patron_register(&self) -> bool {
+        self.allow_patron_register
+    }
+    /// CQ value reported when no patron password is provided.
+    pub fn cq_without_password(&self) -> &CqWithoutPassword {
+        &self.cq_without_password
+    }
+    /// Clients may only login to this account from these addresses.
+    /// Empty means any address.
+    pub fn allowed_addresses(&self) -> &[Cidr] {
+        &self.allowed_addresses
+    }
+    /// Maximum number of concurrent sessions logged in to this account.
+    pub fn max_sessions(&self) -> Option<usize> {
+        self.max_sessions
+    }
+    /// Patron and item values to place in response fields.
+    pub fn field_mappings(&self) -> &[FieldMapping] {
+        &self.field_mappings
+    }
+    /// Fields removed from responses.
+    pub fn suppress_fields(&self) -> &[FieldSuppression] {
+        &self.suppress_fields
+    }
+    /// For testing.  The first message with this code in each session
+    /// fails as if the OpenSRF connection dropped.
+    pub fn simulate_transport_failure(&self) -> Option<&str> {
+        self.simulate_transport_failure.as_deref()
+    }
+    /// Copy status and circ modifier mappings for item responses.
+    ///
+    /// Starts with the global item-mapping and applies any
+    /// account-level overrides.
+    pub fn item_mapping(&self) -> &ItemMapping {
+        &self.item_mapping
+    }
+    /// Starts with the global rate-limit and applies any
+    /// account-level overrides.
+    pub fn rate_limit(&self) -> &RateLimitConfig {
+        &self.rate_limit
+    }
+    /// Screen messages are translated into this locale, e.g. es-ES.
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+    /// Time zone for SIP dates, overriding the setting group value.
+    ///
+    /// When unset, the lib.timezone org setting applies.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref().or(self.settings.timezone())
+    }
+    /// Character encoding used after login, overriding the global
+    /// ascii setting.
+    pub fn encoding(&self) -> Option<sip2::Encoding> {
+        self.encoding
+    }
+    /// When set, patron names in AE and DA fields are formatted with
+    /// this template.
+    ///
+    /// Supports {prefix}, {given}, {middle}, {family}, and {suffix}.
+    pub fn name_format(&self) -> Option<&str> {
+        self.name_format.as_deref()
+    }
+    /// Use the patron's preferred name parts where populated.
+    pub fn name_use_preferred(&self) -> bool {
+        self.name_use_preferred
+    }
+    /// SIP protocol version assumed when the client does not declare
+    /// one in SC Status.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+    /// Omit copy alert screen messages, e.g. for patron-facing
+    /// devices.
+    pub fn suppress_copy_alerts(&self) -> bool {
+        self.suppress_copy_alerts
+    }
 }
 
 /// Global SIP configuration.
@@ -251,6 +601,29 @@ pub struct Config {
     accounts: HashMap<String, SipAccount>,
     sc_status_before_login: bool,
     currency: String,
+    shutdown_drain_timeout: u64,
+    shutdown_force_timeout: u64,
+    enable_tls: bool,
+    tls_cert_chain: Option<String>,
+    tls_key: Option<String>,
+    tls_handshake_timeout: u64,
+    osrf_request_timeout: u64,
+    osrf_pool_min: usize,
+    osrf_pool_max: Option<usize>,
+    patron_register_message: String,
+    patron_register_response: String,
+    allowed_addresses: Vec<Cidr>,
+    audit_log: Option<AuditConfig>,
+    offline: Option<OfflineConfig>,
+    locale_dir: Option<String>,
+    item_mapping: ItemMapping,
+    rate_limit: RateLimitConfig,
+    cache_ttl: Option<u64>,
+    status_address: String,
+    status_port: Option<u16>,
+    admin_socket: Option<String>,
+    admin_port: Option<u16>,
+    admin_secret: Option<String>,
     source: Option<yaml_rust::Yaml>,
 }
 
@@ -267,26 +640,174 @@ impl Config {
             accounts: HashMap::new(),
             currency: "USD".to_string(),
             sc_status_before_login: false,
+            shutdown_drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+            shutdown_force_timeout: shutdown::DEFAULT_FORCE_TIMEOUT,
+            enable_tls: false,
+            tls_cert_chain: None,
+            tls_key: None,
+            tls_handshake_timeout: DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            osrf_request_timeout: DEFAULT_OSRF_REQUEST_TIMEOUT,
+            osrf_pool_min: 0,
+            osrf_pool_max: None,
+            patron_register_message: DEFAULT_PATRON_REGISTER_MESSAGE.to_string(),
+            patron_register_response: DEFAULT_PATRON_REGISTER_RESPONSE.to_string(),
+            allowed_addresses: Vec::new(),
+            audit_log: None,
+            offline: None,
+            locale_dir: None,
+            item_mapping: ItemMapping::default(),
+            rate_limit: RateLimitConfig::default(),
+            cache_ttl: None,
+            status_address: String::from("127.0.0.1"),
+            status_port: None,
+            admin_socket: None,
+            admin_port: None,
+            admin_secret: None,
             source: None,
         }
     }
 
-    /// Parse a YAML configuration file.
+    /// Parse a YAML configuration file and any files it includes.
+    ///
+    /// Any problem found while loading the files, e.g. a missing
+    /// environment variable, is an error.
     pub fn read_yaml(&mut self, filename: &str) -> Result<(), String> {
-        let yaml_text = fs::read_to_string(filename)
-            .or_else(|e| Err(format!("Error reading YAML configuration file: {e}")))?;
+        let source = ConfigSource::load(filename);
 
-        let mut yaml_docs = YamlLoader::load_from_str(&yaml_text)
-            .or_else(|e| Err(format!("Error parsing configuration file as YAML: {e}")))?;
+        if !source.problems().is_empty() {
+            return Err(join_problems(source.problems()));
+        }
 
-        let root = if yaml_docs.len() > 0 {
-            yaml_docs.remove(0)
-        } else {
-            return Err(format!(
-                "Error unpacking YAML document for config {filename}"
-            ));
+        let root = source.root().clone();
+
+        let errors = self.apply_globals(&root);
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.into_iter().map(|(_, e)| e).collect();
+            return Err(errors.join("\n"));
+        }
+
+        self.add_setting_groups(&root)?;
+        self.add_accounts(&root)?;
+        self.redact_cc_arg_fields();
+
+        self.source = Some(root);
+
+        Ok(())
+    }
+
+    /// Redact the SIP fields which carry credit card details in the
+    /// audit log.
+    ///
+    /// Accounts, including those added at runtime, get their cc-arg-fields
+    /// from a setting group, so covering every group covers every account.
+    fn redact_cc_arg_fields(&mut self) {
+        let Some(audit) = self.audit_log.as_mut() else {
+            return;
+        };
+
+        for group in self.setting_groups.values() {
+            for (_, code) in group.cc_arg_fields() {
+                audit.add_redact_field(code);
+            }
+        }
+    }
+
+    /// Validate a configuration file, returning every problem found.
+    ///
+    /// In addition to the checks performed by read_yaml, this reports
+    /// unknown settings and values of the wrong type.  With an editor,
+    /// ILS usernames, workstations, and workstation org units are
+    /// verified to exist.  Workstations which may be registered
+    /// automatically are not required to exist.
+    pub fn check_yaml(filename: &str, editor: Option<&mut eg::Editor>) -> Vec<ConfigProblem> {
+        let source = ConfigSource::load(filename);
+
+        let mut problems = source.problems().to_vec();
+        problems.extend(source.check_schema());
+
+        let root = source.root();
+        let mut config = Config::new();
+
+        for (key, e) in config.apply_globals(root) {
+            problems.push(source.problem(key, &e));
+        }
+
+        if let Some(groups) = root["setting-groups"].as_vec() {
+            for (idx, group) in groups.iter().enumerate() {
+                if let Err(e) = config.add_setting_group(group) {
+                    problems.push(source.problem(&format!("setting-groups[{idx}]"), &e));
+                }
+            }
+        }
+
+        let accounts = match root["accounts"].as_vec() {
+            Some(a) => a,
+            None => return problems,
+        };
+
+        for (idx, account) in accounts.iter().enumerate() {
+            if let Err(e) = config.add_account(account) {
+                problems.push(source.problem(&format!("accounts[{idx}]"), &e));
+            }
+        }
+
+        let Some(editor) = editor else {
+            return problems;
         };
 
+        for (idx, account) in accounts.iter().enumerate() {
+            let checks = [
+                ("ils-username", "au", "usrname", "ILS user"),
+                ("workstation", "aws", "name", "Workstation"),
+                (
+                    "workstation-org",
+                    "aou",
+                    "shortname",
+                    "Workstation org unit",
+                ),
+            ];
+
+            let auto_workstation = account["allow-auto-workstation"].as_bool() == Some(true);
+
+            for (key, class, field, label) in checks {
+                let Some(value) = account[key].as_str() else {
+                    continue;
+                };
+
+                if key == "workstation" && auto_workstation {
+                    continue;
+                }
+
+                let mut query = eg::hash! {};
+                query[field] = eg::EgValue::from(value);
+
+                if class == "au" {
+                    query["deleted"] = eg::EgValue::from("f");
+                }
+
+                let path = format!("accounts[{idx}].{key}");
+
+                match editor.search(class, query) {
+                    Ok(list) if list.is_empty() => {
+                        problems
+                            .push(source.problem(&path, &format!("{label} not found: {value}")));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        problems.push(source.problem(&path, &format!("Cannot verify {key}: {e}")));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Apply the global settings, returning the config key and
+    /// message of each invalid value.
+    fn apply_globals(&mut self, root: &yaml_rust::Yaml) -> Vec<(&'static str, String)> {
+        let mut errors = Vec::new();
+
         if let Some(v) = root["sip-address"].as_str() {
             self.sip_address = String::from(v);
         };
@@ -315,161 +836,544 @@ impl Config {
             self.sc_status_before_login = v;
         }
 
-        self.add_setting_groups(&root);
-        self.add_accounts(&root)?;
+        if let Some(v) = root["currency"].as_str() {
+            self.currency = v.to_string();
+        }
 
-        self.source = Some(root);
+        if let Some(v) = root["shutdown-drain-timeout"].as_i64() {
+            self.shutdown_drain_timeout = v as u64;
+        }
 
-        Ok(())
+        if let Some(v) = root["shutdown-force-timeout"].as_i64() {
+            self.shutdown_force_timeout = v as u64;
+        }
+
+        if let Some(v) = root["enable-tls"].as_bool() {
+            self.enable_tls = v;
+        }
+
+        if let Some(v) = root["tls-cert-chain"].as_str() {
+            self.tls_cert_chain = Some(v.to_string());
+        }
+
+        if let Some(v) = root["tls-key"].as_str() {
+            self.tls_key = Some(v.to_string());
+        }
+
+        if let Some(v) = root["tls-handshake-timeout"].as_i64() {
+            self.tls_handshake_timeout = v as u64;
+        }
+
+        if let Some(v) = root["osrf-request-timeout"].as_i64() {
+            if v > 0 {
+                self.osrf_request_timeout = v as u64;
+            } else {
+                errors.push((
+                    "osrf-request-timeout",
+                    format!("osrf-request-timeout must be positive: {v}"),
+                ));
+            }
+        }
+
+        if let Some(v) = root["osrf-pool-min"].as_i64() {
+            self.osrf_pool_min = v as usize;
+        }
+
+        if let Some(v) = root["osrf-pool-max"].as_i64() {
+            self.osrf_pool_max = Some(v as usize);
+        }
+
+        for (key, code) in [
+            ("patron-register-message", &mut self.patron_register_message),
+            (
+                "patron-register-response",
+                &mut self.patron_register_response,
+            ),
+        ] {
+            let Some(v) = root[key].as_str() else {
+                continue;
+            };
+
+            if v.len() == 2 && v.chars().all(|c| c.is_ascii_alphanumeric()) {
+                *code = v.to_string();
+            } else {
+                errors.push((key, format!("{key} must be a 2-character code: {v}")));
+            }
+        }
+
+        if self.patron_register_message == self.patron_register_response {
+            errors.push((
+                "patron-register-response",
+                "patron-register-message and patron-register-response must differ".to_string(),
+            ));
+        }
+
+        if self.enable_tls && (self.tls_cert_chain.is_none() || self.tls_key.is_none()) {
+            errors.push((
+                "enable-tls",
+                "enable-tls requires tls-cert-chain and tls-key".to_string(),
+            ));
+        }
+
+        if let Some(v) = root["cache-ttl"].as_i64() {
+            // 0 means cache forever.
+            self.cache_ttl = if v > 0 { Some(v as u64) } else { None };
+        }
+
+        if let Some(v) = root["status-address"].as_str() {
+            self.status_address = String::from(v);
+        }
+
+        if let Some(v) = root["status-port"].as_i64() {
+            self.status_port = Some(v as u16);
+        }
+
+        if let Some(v) = root["admin-socket"].as_str() {
+            self.admin_socket = Some(v.to_string());
+        }
+
+        if let Some(v) = root["admin-port"].as_i64() {
+            self.admin_port = Some(v as u16);
+        }
+
+        if let Some(v) = root["admin-secret"].as_str() {
+            self.admin_secret = Some(v.to_string());
+        }
+
+        match parse_allowlist(root, "allowed-addresses") {
+            Ok(list) => self.allowed_addresses = list,
+            Err(e) => errors.push(("allowed-addresses", e)),
+        }
+
+        match AuditConfig::from_yaml(&root["audit-log"]) {
+            Ok(a) => self.audit_log = a,
+            Err(e) => errors.push(("audit-log", e)),
+        }
+
+        match OfflineConfig::from_yaml(&root["offline"]) {
+            Ok(o) => self.offline = o,
+            Err(e) => errors.push(("offline", e)),
+        }
+
+        if let Some(v) = root["locale-dir"].as_str() {
+            self.locale_dir = Some(v.to_string());
+        }
+
+        if let Err(e) = self.item_mapping.apply_yaml(&root["item-mapping"]) {
+            errors.push(("item-mapping", format!("item-mapping: {e}")));
+        }
+
+        if let Err(e) = self.rate_limit.apply_yaml(&root["rate-limit"]) {
+            errors.push(("rate-limit", format!("rate-limit: {e}")));
+        }
+
+        errors
     }
 
-    fn add_setting_groups(&mut self, root: &yaml_rust::Yaml) {
-        if !root["setting-groups"].is_array() {
-            return;
+    fn add_setting_groups(&mut self, root: &yaml_rust::Yaml) -> Result<(), String> {
+        if let Some(groups) = root["setting-groups"].as_vec() {
+            for group in groups {
+                self.add_setting_group(group)?;
+            }
         }
 
-        for group in root["setting-groups"].as_vec().unwrap() {
-            let name = group["name"].as_str().expect("Setting group name required");
+        Ok(())
+    }
 
-            let inst = group["institution"]
-                .as_str()
-                .expect("Setting group institution required");
+    fn add_setting_group(&mut self, group: &yaml_rust::Yaml) -> Result<(), String> {
+        let name = group["name"]
+            .as_str()
+            .ok_or_else(|| "Setting group requires a name".to_string())?;
 
-            let mut grp = SipSettings::new(inst);
+        let inst = group["institution"]
+            .as_str()
+            .ok_or_else(|| format!("Setting group '{name}' requires an institution"))?;
 
-            set_bool(
-                group,
-                "due-date-use-sip-date-format",
-                &mut grp.due_date_use_sip_date_format,
-            );
-            set_bool(
-                group,
-                "patron-status-permit-all",
-                &mut grp.patron_status_permit_all,
-            );
-            set_bool(
-                group,
-                "patron-status-permit-loans",
-                &mut grp.patron_status_permit_loans,
-            );
-            set_bool(
-                group,
-                "msg64-hold-items-available",
-                &mut grp.msg64_hold_items_available,
-            );
-            set_bool(
-                group,
-                "checkin-holds-as-transits",
-                &mut grp.checkin_holds_as_transits,
-            );
-            set_bool(
-                group,
-                "checkout-override-all",
-                &mut grp.checkout_override_all,
-            );
-            set_bool(group, "checkin-override-all", &mut grp.checkin_override_all);
-            set_bool(
-                group,
-                "sc-status-library-info",
-                &mut grp.sc_status_library_info,
-            );
+        let mut grp = SipSettings::new(inst);
 
-            set_bool(group, "use-native-checkin", &mut grp.use_native_checkin);
-            set_bool(group, "use-native-checkout", &mut grp.use_native_checkout);
+        set_bool(
+            group,
+            "due-date-use-sip-date-format",
+            &mut grp.due_date_use_sip_date_format,
+        );
+        set_bool(
+            group,
+            "patron-status-permit-all",
+            &mut grp.patron_status_permit_all,
+        );
+        set_bool(
+            group,
+            "patron-status-permit-loans",
+            &mut grp.patron_status_permit_loans,
+        );
+        set_bool(
+            group,
+            "msg64-hold-items-available",
+            &mut grp.msg64_hold_items_available,
+        );
+        set_bool(
+            group,
+            "checkin-holds-as-transits",
+            &mut grp.checkin_holds_as_transits,
+        );
+        set_bool(
+            group,
+            "checkout-override-all",
+            &mut grp.checkout_override_all,
+        );
+        set_bool(group, "checkin-override-all", &mut grp.checkin_override_all);
+        set_bool(
+            group,
+            "sc-status-library-info",
+            &mut grp.sc_status_library_info,
+        );
 
-            if let Some(s) = group["msg64-hold-datatype"].as_str() {
-                if s.to_lowercase().starts_with("t") {
-                    grp.msg64_hold_datatype = Msg64HoldDatatype::Title;
-                }
+        set_bool(
+            group,
+            "patron-info-expose-dates",
+            &mut grp.patron_info_expose_dates,
+        );
+        set_bool(
+            group,
+            "patron-info-concurrent-queries",
+            &mut grp.patron_info_concurrent_queries,
+        );
+
+        set_bool(group, "use-native-checkin", &mut grp.use_native_checkin);
+        set_bool(group, "use-native-checkout", &mut grp.use_native_checkout);
+
+        set_bool(
+            group,
+            "payment-receipt-balance",
+            &mut grp.payment_receipt_balance,
+        );
+        set_bool(
+            group,
+            "payment-receipt-xact-ids",
+            &mut grp.payment_receipt_xact_ids,
+        );
+        set_bool(
+            group,
+            "payment-receipt-summary",
+            &mut grp.payment_receipt_summary,
+        );
+        set_bool(
+            group,
+            "payment-receipt-email",
+            &mut grp.payment_receipt_email,
+        );
+
+        if let Some(types) = group["payment-types"].as_hash() {
+            for (code, ptype) in types {
+                let code = match code {
+                    // Unquoted codes like 02 load as numbers.
+                    yaml_rust::Yaml::Integer(i) => format!("{i:02}"),
+                    _ => code.as_str().unwrap_or_default().to_string(),
+                };
+
+                let ptype = match ptype.as_str() {
+                    Some(t) if PAYMENT_TYPES.contains(&t) => t,
+                    _ => {
+                        return Err(format!(
+                            "Setting group '{name}' payment-types: invalid payment type \
+                            for '{code}': {ptype:?}"
+                        ))
+                    }
+                };
+
+                grp.payment_types.retain(|(c, _)| c != &code);
+                grp.payment_types.push((code, ptype.to_string()));
             }
-            if let Some(s) = group["msg64-summary-datatype"].as_str() {
-                if s.to_lowercase().starts_with("t") {
-                    grp.msg64_summary_datatype = Msg64SummaryDatatype::Title;
+        }
+
+        if let Some(fields) = group["cc-arg-fields"].as_hash() {
+            for (key, field) in fields {
+                match (key.as_str(), field.as_str()) {
+                    (Some(k), Some(f)) if f.len() == 2 => {
+                        grp.cc_arg_fields.push((k.to_string(), f.to_string()))
+                    }
+                    _ => {
+                        return Err(format!(
+                        "Setting group '{name}' cc-arg-fields: invalid mapping {key:?}: {field:?}"
+                    ))
+                    }
                 }
             }
-            if let Some(s) = group["av-format"].as_str() {
-                grp.av_format = s.into();
+        }
+
+        if let Some(s) = group["due-date-format"].as_str() {
+            sipdate::check_date_format(s)
+                .map_err(|e| format!("Setting group '{name}' due-date-format: {e}"))?;
+            grp.due_date_format = Some(s.to_string());
+        }
+
+        if let Some(s) = group["msg64-hold-datatype"].as_str() {
+            if s.to_lowercase().starts_with("t") {
+                grp.msg64_hold_datatype = Msg64HoldDatatype::Title;
+            }
+        }
+        if let Some(s) = group["msg64-summary-datatype"].as_str() {
+            if s.to_lowercase().starts_with("t") {
+                grp.msg64_summary_datatype = Msg64SummaryDatatype::Title;
             }
+        }
+        if let Some(s) = group["msg64-hold-ready-template"].as_str() {
+            grp.msg64_hold_ready_template = Some(s.to_string());
+        }
+        if let Some(s) = group["msg64-hold-unavail-template"].as_str() {
+            grp.msg64_hold_unavail_template = Some(s.to_string());
+        }
+        if let Some(s) = group["hold-patron-identifier"].as_str() {
+            if s.to_lowercase() == "id" {
+                grp.hold_patron_identifier = HoldPatronIdentifier::Id;
+            }
+        }
+        let max_fine = match &group["patron-max-fine"] {
+            yaml_rust::Yaml::Real(s) | yaml_rust::Yaml::String(s) => Some(Money::parse(s)),
+            yaml_rust::Yaml::Integer(i) => Some(Ok(Money::from_cents(i.saturating_mul(100)))),
+            _ => None,
+        };
+        match max_fine {
+            Some(Ok(m)) => grp.patron_max_fine = Some(m),
+            Some(Err(e)) => log::warn!("Invalid patron-max-fine for group '{name}': {e}"),
+            None => {}
+        }
+        if let Some(s) = group["timezone"].as_str() {
+            grp.timezone = Some(s.to_string());
+        }
+        if let Some(s) = group["patron-expired-message"].as_str() {
+            grp.patron_expired_message = Some(s.to_string());
+        }
+        if let Some(s) = group["av-format"].as_str() {
+            grp.av_format = s.into();
+        }
+        if let Some(s) = group["print-line-overflow"].as_str() {
+            grp.print_line_overflow = s.into();
+        }
 
-            if group["checkin-override"].is_array() {
-                for ovride in group["checkin-override"].as_vec().unwrap() {
-                    if let Some(code) = ovride.as_str() {
-                        grp.checkin_override.push(code.to_string());
-                    }
+        if group["checkin-override"].is_array() {
+            for ovride in group["checkin-override"].as_vec().unwrap() {
+                if let Some(code) = ovride.as_str() {
+                    grp.checkin_override.push(code.to_string());
                 }
             }
+        }
 
-            if group["checkout-override"].is_array() {
-                for ovride in group["checkout-override"].as_vec().unwrap() {
-                    if let Some(code) = ovride.as_str() {
-                        grp.checkout_override.push(code.to_string());
-                    }
+        if group["checkout-override"].is_array() {
+            for ovride in group["checkout-override"].as_vec().unwrap() {
+                if let Some(code) = ovride.as_str() {
+                    grp.checkout_override.push(code.to_string());
                 }
             }
+        }
 
-            if group["field-filters"].is_array() {
-                for filter in group["field-filters"].as_vec().unwrap() {
-                    if let Some(field) = filter["field-code"].as_str() {
-                        let mut mfilter = FieldFilter {
-                            field_code: field.to_string(),
-                            replace_with: None,
-                        };
+        if group["juvenile-suppress-fields"].is_array() {
+            for field in group["juvenile-suppress-fields"].as_vec().unwrap() {
+                if let Some(code) = field.as_str() {
+                    grp.juvenile_suppress_fields.push(code.to_string());
+                }
+            }
+        }
 
-                        if let Some(rw) = filter["replace-with"].as_str() {
-                            mfilter.replace_with = Some(rw.to_string());
-                        }
+        if group["field-filters"].is_array() {
+            for filter in group["field-filters"].as_vec().unwrap() {
+                if let Some(field) = filter["field-code"].as_str() {
+                    let mut mfilter = FieldFilter {
+                        field_code: field.to_string(),
+                        replace_with: None,
+                    };
 
-                        grp.field_filters.push(mfilter);
+                    if let Some(rw) = filter["replace-with"].as_str() {
+                        mfilter.replace_with = Some(rw.to_string());
                     }
+
+                    grp.field_filters.push(mfilter);
                 }
             }
-
-            log::debug!("Adding setting group '{name}'");
-            self.setting_groups.insert(name.to_string(), grp);
         }
+
+        log::debug!("Adding setting group '{name}'");
+        self.setting_groups.insert(name.to_string(), grp);
+
+        Ok(())
     }
 
     fn add_accounts(&mut self, root: &yaml_rust::Yaml) -> Result<(), String> {
-        if root["accounts"].is_array() {
-            for account in root["accounts"].as_vec().unwrap() {
-                let group_name = account["settings"].as_str().unwrap();
-                let sgroup = match self.setting_groups.get(group_name) {
-                    Some(s) => s,
-                    None => Err(format!("No such settings group: '{}'", group_name))?,
-                };
+        if let Some(accounts) = root["accounts"].as_vec() {
+            for account in accounts {
+                self.add_account(account)?;
+            }
+        }
 
-                let username = account["sip-username"].as_str().unwrap();
+        Ok(())
+    }
 
-                let mut acct = SipAccount::new(
-                    &sgroup,
-                    account["sip-username"].as_str().unwrap(),
-                    account["sip-password"].as_str().unwrap(),
-                    account["ils-username"].as_str().unwrap(),
-                );
+    /// Add a SIP account from its YAML configuration, replacing any
+    /// existing account with the same username.
+    ///
+    /// JSON objects are valid YAML, so accounts may also be added at
+    /// runtime from JSON.
+    pub fn add_account(&mut self, account: &yaml_rust::Yaml) -> Result<(), String> {
+        let username = account["sip-username"]
+            .as_str()
+            .ok_or_else(|| format!("SIP account requires a sip-username: {account:?}"))?;
 
-                if let Some(ws) = account["workstation"].as_str() {
-                    acct.workstation = Some(ws.to_string());
-                }
-                if let Some(ws) = account["activity-as"].as_str() {
-                    acct.activity_as = Some(ws.to_string());
-                }
+        let group_name = account["settings"]
+            .as_str()
+            .ok_or_else(|| format!("SIP account '{username}' requires settings"))?;
+
+        let sgroup = match self.setting_groups.get(group_name) {
+            Some(s) => s,
+            None => Err(format!("No such settings group: '{}'", group_name))?,
+        };
+
+        let ils_username = account["ils-username"]
+            .as_str()
+            .ok_or_else(|| format!("SIP account '{username}' requires an ils-username"))?;
+
+        let sip_password = if let Some(h) = account["sip-password-hash"].as_str() {
+            password::validate_hash(h).map_err(|e| format!("SIP account '{username}': {e}"))?;
+            SipPassword::Hashed(h.to_string())
+        } else if let Some(p) = account["sip-password"].as_str() {
+            log::warn!(
+                "SIP account '{username}' uses a plaintext password. \
+                Consider using sip-password-hash instead."
+            );
+            SipPassword::Plain(p.to_string())
+        } else {
+            return Err(format!(
+                "SIP account '{username}' requires sip-password or sip-password-hash"
+            ));
+        };
+
+        let mut acct = SipAccount::new(sgroup, username, sip_password, ils_username);
+
+        if let Some(ws) = account["workstation"].as_str() {
+            acct.workstation = Some(ws.to_string());
+        }
+        if let Some(sn) = account["workstation-org"].as_str() {
+            acct.workstation_org = Some(sn.to_string());
+        }
+        if let Some(ws) = account["activity-as"].as_str() {
+            acct.activity_as = Some(ws.to_string());
+        }
+        if let Some(locale) = account["locale"].as_str() {
+            acct.locale = Some(locale.to_string());
+        }
+        if let Some(tz) = account["timezone"].as_str() {
+            acct.timezone = Some(tz.to_string());
+        }
+        if let Some(format) = account["name-format"].as_str() {
+            acct.name_format = Some(format.to_string());
+        }
+        if let Some(v) = account["protocol-version"].as_str() {
+            acct.protocol_version = Some(ProtocolVersion::parse(v).ok_or_else(|| {
+                format!("SIP account '{username}': invalid protocol-version '{v}'")
+            })?);
+        }
+        if let Some(tz) = acct.timezone() {
+            evergreen::date::set_timezone(evergreen::date::now(), tz)
+                .map_err(|e| format!("SIP account '{username}': {e}"))?;
+        }
+        if let Some(enc) = account["encoding"].as_str() {
+            acct.encoding = Some(
+                enc.parse()
+                    .map_err(|e| format!("SIP account '{username}': {e}"))?,
+            );
+        }
+
+        set_bool(
+            account,
+            "checkin-block-on-checked-out",
+            &mut acct.checkin_block_on_checked_out,
+        );
+
+        set_bool(
+            account,
+            "allow-auto-workstation",
+            &mut acct.allow_auto_workstation,
+        );
+
+        set_bool(
+            account,
+            "allow-item-status-update",
+            &mut acct.allow_item_status_update,
+        );
+
+        set_bool(
+            account,
+            "allow-patron-register",
+            &mut acct.allow_patron_register,
+        );
+
+        set_bool(account, "name-use-preferred", &mut acct.name_use_preferred);
+
+        set_bool(
+            account,
+            "suppress-copy-alerts",
+            &mut acct.suppress_copy_alerts,
+        );
+
+        if let Some(s) = account["cq-without-password"].as_str() {
+            acct.cq_without_password = s.into();
+        }
+
+        acct.allowed_addresses = parse_allowlist(account, "allowed-addresses")
+            .map_err(|e| format!("SIP account '{username}': {e}"))?;
+
+        if let Some(v) = account["max-sessions"].as_i64() {
+            acct.max_sessions = Some(v as usize);
+        }
 
-                set_bool(
-                    &account,
-                    "checkin-block-on-checked-out",
-                    &mut acct.checkin_block_on_checked_out,
+        if let Some(mappings) = account["field-mappings"].as_vec() {
+            for mapping in mappings {
+                acct.field_mappings.push(
+                    FieldMapping::from_yaml(mapping)
+                        .map_err(|e| format!("SIP account '{username}': {e}"))?,
                 );
+            }
+        }
 
-                self.accounts.insert(username.to_string(), acct);
+        if let Some(code) = account["simulate-transport-failure"].as_str() {
+            log::warn!("SIP account '{username}' simulates transport failures");
+            acct.simulate_transport_failure = Some(code.to_string());
+        }
+
+        acct.item_mapping = self.item_mapping.clone();
+        acct.item_mapping
+            .apply_yaml(&account["item-mapping"])
+            .map_err(|e| format!("SIP account '{username}': item-mapping: {e}"))?;
+
+        acct.rate_limit = self.rate_limit.clone();
+        acct.rate_limit
+            .apply_yaml(&account["rate-limit"])
+            .map_err(|e| format!("SIP account '{username}': rate-limit: {e}"))?;
+
+        if let Some(fields) = account["suppress-fields"].as_vec() {
+            for field in fields {
+                acct.suppress_fields.push(
+                    FieldSuppression::from_yaml(field)
+                        .map_err(|e| format!("SIP account '{username}': {e}"))?,
+                );
             }
-        };
+        }
+
+        self.accounts.insert(username.to_string(), acct);
 
         Ok(())
     }
 
+    /// Remove a SIP account.  Returns false if there is no such account.
+    pub fn remove_account(&mut self, username: &str) -> bool {
+        self.accounts.remove(username).is_some()
+    }
+
     pub fn get_account(&self, username: &str) -> Option<&SipAccount> {
         self.accounts.get(username)
     }
+    pub fn accounts(&self) -> impl Iterator<Item = &SipAccount> {
+        self.accounts.values()
+    }
     pub fn currency(&self) -> &str {
         &self.currency
     }
@@ -488,10 +1392,181 @@ impl Config {
     pub fn max_worker_requests(&self) -> usize {
         self.max_worker_requests
     }
-    pub fn ascii(&self) -> bool {
-        self.ascii
+    /// Code of the vendor extension message for patron self-registration.
+    pub fn patron_register_message(&self) -> &str {
+        &self.patron_register_message
+    }
+    /// Code of the patron self-registration response.
+    pub fn patron_register_response(&self) -> &str {
+        &self.patron_register_response
+    }
+    /// Register the vendor extension messages we handle with the
+    /// SIP library so they can be parsed.
+    pub fn register_extensions(&self) -> Result<(), String> {
+        let extensions = [
+            (
+                &self.patron_register_message,
+                "Patron Register",
+                register::REQUEST_FIXED_FIELDS,
+            ),
+            (
+                &self.patron_register_response,
+                "Patron Register Response",
+                register::RESPONSE_FIXED_FIELDS,
+            ),
+        ];
+
+        for (code, label, fixed_fields) in extensions {
+            sip2::spec::Message::register_extension(code, label, fixed_fields)
+                .map_err(|e| format!("Cannot register {label} message {code}: {e}"))?;
+        }
+
+        Ok(())
+    }
+    /// Character encoding used before login and for accounts
+    /// with no encoding of their own.
+    pub fn encoding(&self) -> sip2::Encoding {
+        match self.ascii {
+            true => sip2::Encoding::Ascii,
+            false => sip2::Encoding::Utf8,
+        }
     }
     pub fn sc_status_before_login(&self) -> bool {
         self.sc_status_before_login
     }
+    /// Seconds active sessions have to finish their current request
+    /// at shutdown before they are forcibly closed.
+    pub fn shutdown_drain_timeout(&self) -> u64 {
+        self.shutdown_drain_timeout
+    }
+    /// Seconds to wait for forcibly closed sessions to exit.
+    pub fn shutdown_force_timeout(&self) -> u64 {
+        self.shutdown_force_timeout
+    }
+    /// If true, SIP clients must connect via TLS.
+    pub fn enable_tls(&self) -> bool {
+        self.enable_tls
+    }
+    /// Path to the PEM certificate chain file.
+    pub fn tls_cert_chain(&self) -> Option<&str> {
+        self.tls_cert_chain.as_deref()
+    }
+    /// Path to the PEM private key file.
+    pub fn tls_key(&self) -> Option<&str> {
+        self.tls_key.as_deref()
+    }
+    /// Seconds a SIP client has to complete the TLS handshake.
+    pub fn tls_handshake_timeout(&self) -> u64 {
+        self.tls_handshake_timeout
+    }
+    /// Seconds to wait for an Evergreen API call to complete before
+    /// replying to the SIP client with a failure.
+    pub fn osrf_request_timeout(&self) -> u64 {
+        self.osrf_request_timeout
+    }
+    /// Number of OpenSRF bus connections opened at startup.
+    pub fn osrf_pool_min(&self) -> usize {
+        self.osrf_pool_min
+    }
+    /// Max number of pooled OpenSRF bus connections.  Defaults to
+    /// max-clients.
+    pub fn osrf_pool_max(&self) -> usize {
+        self.osrf_pool_max.unwrap_or(self.max_clients)
+    }
+    /// SIP clients may only connect from these addresses.  Empty
+    /// means any address.
+    pub fn allowed_addresses(&self) -> &[Cidr] {
+        &self.allowed_addresses
+    }
+    /// Set if SIP traffic audit logging is enabled.
+    pub fn audit_log(&self) -> Option<&AuditConfig> {
+        self.audit_log.as_ref()
+    }
+    /// Directory of screen message translation catalogs.
+    pub fn locale_dir(&self) -> Option<&str> {
+        self.locale_dir.as_deref()
+    }
+    /// Set if offline transactions are enabled.
+    pub fn offline(&self) -> Option<&OfflineConfig> {
+        self.offline.as_ref()
+    }
+    /// Global rate limits.  SIP accounts may override them.
+    pub fn rate_limit(&self) -> &RateLimitConfig {
+        &self.rate_limit
+    }
+    /// How long data shared across Sessions may be cached.  None
+    /// means until the server is reloaded.
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl.map(Duration::from_secs)
+    }
+    /// Address of the HTTP status listener.
+    pub fn status_address(&self) -> &str {
+        &self.status_address
+    }
+    /// Port of the HTTP status listener.  None means disabled.
+    pub fn status_port(&self) -> Option<u16> {
+        self.status_port
+    }
+    /// Path of the admin Unix socket.  None means disabled.
+    pub fn admin_socket(&self) -> Option<&str> {
+        self.admin_socket.as_deref()
+    }
+    /// Loopback port for admin connections.  None means disabled.
+    pub fn admin_port(&self) -> Option<u16> {
+        self.admin_port
+    }
+    /// If set, admin connections must authenticate with this secret.
+    pub fn admin_secret(&self) -> Option<&str> {
+        self.admin_secret.as_deref()
+    }
+}
+
+/// The current configuration, shared by the Server, its Sessions, and
+/// the admin listener.  Cloning is cheap.
+///
+/// The configuration is swapped as a whole, so callers holding a copy
+/// of the Arc are not affected by a change.
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+
+    /// Apply a change to a copy of the current configuration and
+    /// make the copy current.
+    ///
+    /// The configuration is unchanged if the change fails.
+    pub fn update<T>(
+        &self,
+        change: impl FnOnce(&mut Config) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut lock = self.current.write().unwrap();
+        let mut config = Config::clone(&lock);
+
+        let result = change(&mut config)?;
+
+        *lock = Arc::new(config);
+
+        Ok(result)
+    }
+}
+
+/// One problem per line.
+fn join_problems(problems: &[ConfigProblem]) -> String {
+    let lines: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    lines.join("\n")
 }
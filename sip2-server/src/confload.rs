@@ -0,0 +1,967 @@
+//! Config file loading and validation.
+//!
+//! The main config file may include other files containing accounts
+//! and setting groups:
+//!
+//! include:
+//!   - "accounts/*.yml"
+//!
+//! Include paths are relative to the including file.  Wildcards are
+//! supported in the file name only.  Included files may contain
+//! accounts, setting-groups, and further includes.
+//!
+//! String values may refer to environment variables as ${NAME}.  A
+//! literal "${" is written as "$${".
+//!
+//! Problems are reported with the file and line where they occur.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Config sections which may appear in included files and are merged
+/// into the main config.
+const MERGED_SECTIONS: &[&str] = &["setting-groups", "accounts"];
+
+/// Expected type of a config value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Str,
+    Int,
+    Bool,
+    /// Integer or decimal
+    Num,
+    List,
+    Map,
+    /// String or list of strings
+    StrOrList,
+}
+
+impl Kind {
+    fn matches(&self, value: &Yaml) -> bool {
+        match self {
+            Kind::Str => value.as_str().is_some(),
+            Kind::Int => value.as_i64().is_some(),
+            Kind::Bool => value.as_bool().is_some(),
+            Kind::Num => matches!(value, Yaml::Integer(_) | Yaml::Real(_)),
+            Kind::List => value.is_array(),
+            Kind::Map => value.as_hash().is_some(),
+            Kind::StrOrList => value.as_str().is_some() || value.is_array(),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Kind::Str => "a string",
+            Kind::Int => "an integer",
+            Kind::Bool => "true or false",
+            Kind::Num => "a number",
+            Kind::List => "a list",
+            Kind::Map => "a mapping",
+            Kind::StrOrList => "a string or list",
+        }
+    }
+}
+
+const ROOT_KEYS: &[(&str, Kind)] = &[
+    ("include", Kind::StrOrList),
+    ("sip-address", Kind::Str),
+    ("sip-port", Kind::Int),
+    ("max-clients", Kind::Int),
+    ("min-workers", Kind::Int),
+    ("max-worker-requests", Kind::Int),
+    ("ascii", Kind::Bool),
+    ("sc-status-before-login", Kind::Bool),
+    ("currency", Kind::Str),
+    ("shutdown-drain-timeout", Kind::Int),
+    ("shutdown-force-timeout", Kind::Int),
+    ("cache-ttl", Kind::Int),
+    ("status-address", Kind::Str),
+    ("status-port", Kind::Int),
+    ("admin-socket", Kind::Str),
+    ("admin-port", Kind::Int),
+    ("admin-secret", Kind::Str),
+    ("enable-tls", Kind::Bool),
+    ("tls-cert-chain", Kind::Str),
+    ("tls-key", Kind::Str),
+    ("tls-handshake-timeout", Kind::Int),
+    ("osrf-request-timeout", Kind::Int),
+    ("osrf-pool-min", Kind::Int),
+    ("osrf-pool-max", Kind::Int),
+    ("patron-register-message", Kind::Str),
+    ("patron-register-response", Kind::Str),
+    ("allowed-addresses", Kind::List),
+    ("audit-log", Kind::Map),
+    ("locale-dir", Kind::Str),
+    ("offline", Kind::Map),
+    ("item-mapping", Kind::Map),
+    ("rate-limit", Kind::Map),
+    ("setting-groups", Kind::List),
+    ("accounts", Kind::List),
+];
+
+const GROUP_KEYS: &[(&str, Kind)] = &[
+    ("name", Kind::Str),
+    ("institution", Kind::Str),
+    ("sc-status-library-info", Kind::Bool),
+    ("due-date-use-sip-date-format", Kind::Bool),
+    ("due-date-format", Kind::Str),
+    ("patron-status-permit-all", Kind::Bool),
+    ("patron-status-permit-loans", Kind::Bool),
+    ("patron-max-fine", Kind::Num),
+    ("timezone", Kind::Str),
+    ("print-line-overflow", Kind::Str),
+    ("patron-expired-message", Kind::Str),
+    ("patron-info-expose-dates", Kind::Bool),
+    ("patron-info-concurrent-queries", Kind::Bool),
+    ("payment-receipt-balance", Kind::Bool),
+    ("payment-receipt-xact-ids", Kind::Bool),
+    ("payment-receipt-summary", Kind::Bool),
+    ("payment-receipt-email", Kind::Bool),
+    ("payment-types", Kind::Map),
+    ("cc-arg-fields", Kind::Map),
+    ("juvenile-suppress-fields", Kind::List),
+    ("msg64-hold-items-available", Kind::Bool),
+    ("checkin-holds-as-transits", Kind::Bool),
+    ("msg64-hold-datatype", Kind::Str),
+    ("msg64-summary-datatype", Kind::Str),
+    ("msg64-hold-ready-template", Kind::Str),
+    ("msg64-hold-unavail-template", Kind::Str),
+    ("hold-patron-identifier", Kind::Str),
+    ("av-format", Kind::Str),
+    ("checkin-override-all", Kind::Bool),
+    ("checkout-override-all", Kind::Bool),
+    ("use-native-checkin", Kind::Bool),
+    ("use-native-checkout", Kind::Bool),
+    ("checkin-override", Kind::List),
+    ("checkout-override", Kind::List),
+    ("field-filters", Kind::List),
+];
+
+const GROUP_REQUIRED: &[&str] = &["name", "institution"];
+
+const ACCOUNT_KEYS: &[(&str, Kind)] = &[
+    ("sip-username", Kind::Str),
+    ("sip-password", Kind::Str),
+    ("sip-password-hash", Kind::Str),
+    ("ils-username", Kind::Str),
+    ("settings", Kind::Str),
+    ("workstation", Kind::Str),
+    ("allow-auto-workstation", Kind::Bool),
+    ("workstation-org", Kind::Str),
+    ("activity-as", Kind::Str),
+    ("locale", Kind::Str),
+    ("timezone", Kind::Str),
+    ("encoding", Kind::Str),
+    ("name-format", Kind::Str),
+    ("name-use-preferred", Kind::Bool),
+    ("protocol-version", Kind::Str),
+    ("checkin-block-on-checked-out", Kind::Bool),
+    ("allow-item-status-update", Kind::Bool),
+    ("allow-patron-register", Kind::Bool),
+    ("suppress-copy-alerts", Kind::Bool),
+    ("cq-without-password", Kind::Str),
+    ("allowed-addresses", Kind::List),
+    ("max-sessions", Kind::Int),
+    ("field-mappings", Kind::List),
+    ("simulate-transport-failure", Kind::Str),
+    ("suppress-fields", Kind::List),
+    ("rate-limit", Kind::Map),
+    ("item-mapping", Kind::Map),
+];
+
+const ACCOUNT_REQUIRED: &[&str] = &["sip-username", "ils-username", "settings"];
+
+/// A config problem and where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    file: String,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(l) => write!(f, "{}:{}: {}", self.file, l, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Records the line of each mapping key and sequence entry by path,
+/// e.g. "accounts[2].sip-username".
+#[derive(Default)]
+struct LineCollector {
+    lines: HashMap<String, usize>,
+    /// Path and, for mappings, whether the next scalar is a key.
+    stack: Vec<Container>,
+}
+
+enum Container {
+    Map { path: String, key: Option<String> },
+    Seq { path: String, index: usize },
+}
+
+impl LineCollector {
+    /// Path of the value about to be parsed, recording its line.
+    fn value_path(&mut self, mark: &Marker) -> Option<String> {
+        let path = match self.stack.last_mut()? {
+            Container::Map { path, key } => {
+                let key = key.take()?;
+                join_path(path, &key)
+            }
+            Container::Seq { path, index } => {
+                let p = format!("{path}[{index}]");
+                *index += 1;
+                p
+            }
+        };
+
+        self.lines.entry(path.clone()).or_insert(mark.line());
+
+        Some(path)
+    }
+}
+
+impl MarkedEventReceiver for LineCollector {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Container::Map { path, key }) = self.stack.last_mut() {
+                    if key.is_none() {
+                        // Mapping key.  Record its line as the line of
+                        // its value, which may be on a later line.
+                        let p = join_path(path, &value);
+                        self.lines.insert(p, mark.line());
+                        *key = Some(value);
+                        return;
+                    }
+                }
+                self.value_path(&mark);
+            }
+            Event::Alias(_) => {
+                self.value_path(&mark);
+            }
+            Event::MappingStart(_) => {
+                let path = self.value_path(&mark).unwrap_or_default();
+                self.stack.push(Container::Map { path, key: None });
+            }
+            Event::SequenceStart(_) => {
+                let path = self.value_path(&mark).unwrap_or_default();
+                self.stack.push(Container::Seq { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// A single loaded config file.
+struct SourceFile {
+    path: String,
+    lines: HashMap<String, usize>,
+}
+
+/// The main config file with all included accounts and setting
+/// groups merged in, plus any problems found while loading.
+pub struct ConfigSource {
+    root: Yaml,
+    files: Vec<SourceFile>,
+    /// Maps the path of each merged section entry, e.g. "accounts[12]",
+    /// to the index of its file and its path within that file.
+    origins: HashMap<String, (usize, String)>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl ConfigSource {
+    /// Load a config file and its includes.
+    ///
+    /// Loading continues past problems where possible, so all of them
+    /// may be reported at once.
+    pub fn load(filename: &str) -> ConfigSource {
+        let mut source = ConfigSource {
+            root: Yaml::Hash(Hash::new()),
+            files: Vec::new(),
+            origins: HashMap::new(),
+            problems: Vec::new(),
+        };
+
+        source.load_file(Path::new(filename), &mut Vec::new(), None);
+        source.check_duplicates();
+
+        source
+    }
+
+    /// Merged config values.
+    pub fn root(&self) -> &Yaml {
+        &self.root
+    }
+
+    /// Problems found while loading, e.g. missing includes or
+    /// environment variables.
+    pub fn problems(&self) -> &[ConfigProblem] {
+        &self.problems
+    }
+
+    /// Create a problem for a value in the merged config, e.g.
+    /// "accounts[12].encoding", located in the file it came from.
+    ///
+    /// Locates the closest enclosing value with a known line.
+    pub fn problem(&self, path: &str, message: &str) -> ConfigProblem {
+        let (file_idx, file_path) = self.origin(path);
+
+        if file_idx < self.files.len() {
+            return self.file_problem(file_idx, &file_path, message);
+        }
+
+        // The main file could not be read.
+        ConfigProblem {
+            file: String::new(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Index of the file and the path within that file of a merged
+    /// config path.
+    fn origin(&self, path: &str) -> (usize, String) {
+        for section in MERGED_SECTIONS {
+            let Some(rest) = path.strip_prefix(section) else {
+                continue;
+            };
+
+            let Some(end) = rest.find(']') else {
+                continue;
+            };
+
+            let entry = &path[..section.len() + end + 1];
+
+            if let Some((idx, file_path)) = self.origins.get(entry) {
+                return (*idx, format!("{file_path}{}", &path[entry.len()..]));
+            }
+        }
+
+        (0, path.to_string())
+    }
+
+    /// Load a file, merging its sections into our root.
+    ///
+    /// `stack` contains the canonical paths of the files including
+    /// this one.  `included_by` is the file and path of the include
+    /// directive, used to locate problems with the file itself.
+    fn load_file(
+        &mut self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        included_by: Option<(usize, String)>,
+    ) {
+        let display = path.display().to_string();
+
+        let file_problem = |source: &mut ConfigSource, message: String| {
+            let problem = match &included_by {
+                Some((idx, p)) => source.file_problem(*idx, p, &message),
+                None => ConfigProblem {
+                    file: display.clone(),
+                    line: None,
+                    message,
+                },
+            };
+            source.problems.push(problem);
+        };
+
+        let canonical = match fs::canonicalize(path) {
+            Ok(c) => c,
+            Err(e) => {
+                file_problem(self, format!("Cannot read config file {display}: {e}"));
+                return;
+            }
+        };
+
+        if let Some(pos) = stack.iter().position(|p| p == &canonical) {
+            let cycle: Vec<String> = stack[pos..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+
+            file_problem(self, format!("Include cycle: {}", cycle.join(" -> ")));
+            return;
+        }
+
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => {
+                file_problem(self, format!("Cannot read config file {display}: {e}"));
+                return;
+            }
+        };
+
+        let mut root = match YamlLoader::load_from_str(&text) {
+            Ok(mut docs) if !docs.is_empty() => docs.remove(0),
+            Ok(_) => Yaml::Hash(Hash::new()),
+            Err(e) => {
+                self.problems.push(ConfigProblem {
+                    file: display,
+                    line: Some(e.marker().line()),
+                    message: format!("Error parsing configuration file as YAML: {e}"),
+                });
+                return;
+            }
+        };
+
+        let mut collector = LineCollector::default();
+        Parser::new(text.chars()).load(&mut collector, false).ok();
+
+        let file_idx = self.files.len();
+        self.files.push(SourceFile {
+            path: display,
+            lines: collector.lines,
+        });
+
+        if root.as_hash().is_none() && !root.is_null() {
+            self.problems.push(self.file_problem(
+                file_idx,
+                "",
+                "Config file must contain a mapping",
+            ));
+            return;
+        }
+
+        let mut missing = Vec::new();
+        interpolate_env(&mut root, "", &mut missing);
+
+        for (path, message) in missing {
+            self.problems
+                .push(self.file_problem(file_idx, &path, &message));
+        }
+
+        let is_main = stack.is_empty();
+
+        if let Some(hash) = root.as_hash() {
+            for key in hash.keys() {
+                let key = key.as_str().unwrap_or("");
+                if !is_main && key != "include" && !MERGED_SECTIONS.contains(&key) {
+                    self.problems.push(self.file_problem(
+                        file_idx,
+                        key,
+                        &format!("Included files may only contain include, accounts, and setting-groups: found {key}"),
+                    ));
+                }
+            }
+        }
+
+        for section in MERGED_SECTIONS {
+            self.merge_section(&root, file_idx, section);
+        }
+
+        if is_main {
+            if let Yaml::Hash(mut hash) = root.clone() {
+                for section in MERGED_SECTIONS {
+                    if let Some(v) = self.root[*section].as_vec() {
+                        hash.insert(Yaml::from_str(section), Yaml::Array(v.clone()));
+                    }
+                }
+                self.root = Yaml::Hash(hash);
+            }
+        }
+
+        let includes = match &root["include"] {
+            Yaml::String(s) => vec![(s.clone(), "include".to_string())],
+            Yaml::Array(list) => list
+                .iter()
+                .enumerate()
+                .filter_map(|(i, v)| v.as_str().map(|s| (s.to_string(), format!("include[{i}]"))))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if includes.is_empty() {
+            return;
+        }
+
+        let dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+        stack.push(canonical);
+
+        for (pattern, include_path) in includes {
+            match expand_include(&dir, &pattern) {
+                Ok(paths) => {
+                    for p in paths {
+                        self.load_file(&p, stack, Some((file_idx, include_path.clone())));
+                    }
+                }
+                Err(e) => {
+                    self.problems
+                        .push(self.file_problem(file_idx, &include_path, &e));
+                }
+            }
+        }
+
+        stack.pop();
+    }
+
+    /// Append the entries of a section to the merged root.
+    fn merge_section(&mut self, root: &Yaml, file_idx: usize, section: &str) {
+        let entries = match &root[section] {
+            Yaml::Array(a) => a,
+            Yaml::BadValue | Yaml::Null => return,
+            _ => {
+                self.problems.push(self.file_problem(
+                    file_idx,
+                    section,
+                    &format!("{section} must be a list"),
+                ));
+                return;
+            }
+        };
+
+        let Yaml::Hash(merged) = &mut self.root else {
+            return;
+        };
+
+        let key = Yaml::from_str(section);
+
+        let list = match merged.get_mut(&key) {
+            Some(Yaml::Array(l)) => l,
+            _ => {
+                merged.insert(key.clone(), Yaml::Array(Vec::new()));
+                match merged.get_mut(&key) {
+                    Some(Yaml::Array(l)) => l,
+                    _ => return,
+                }
+            }
+        };
+
+        for (idx, entry) in entries.iter().enumerate() {
+            self.origins.insert(
+                format!("{section}[{}]", list.len()),
+                (file_idx, format!("{section}[{idx}]")),
+            );
+            list.push(entry.clone());
+        }
+    }
+
+    fn file_problem(&self, file_idx: usize, path: &str, message: &str) -> ConfigProblem {
+        let file = &self.files[file_idx];
+
+        let mut path = path.to_string();
+        loop {
+            if let Some(line) = file.lines.get(&path) {
+                return ConfigProblem {
+                    file: file.path.clone(),
+                    line: Some(*line),
+                    message: message.to_string(),
+                };
+            }
+
+            match path.rfind(['.', '[']) {
+                Some(pos) => path.truncate(pos),
+                None => break,
+            }
+        }
+
+        ConfigProblem {
+            file: file.path.clone(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Accounts and setting groups must have unique names across
+    /// all files.
+    fn check_duplicates(&mut self) {
+        for (section, name_key) in [("setting-groups", "name"), ("accounts", "sip-username")] {
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            let mut problems = Vec::new();
+
+            for (idx, entry) in self.root[section]
+                .as_vec()
+                .unwrap_or(&Vec::new())
+                .iter()
+                .enumerate()
+            {
+                let Some(name) = entry[name_key].as_str() else {
+                    continue;
+                };
+
+                if let Some(first) = seen.get(name) {
+                    let first = self.problem(&format!("{section}[{first}]"), "");
+                    let location = match first.line {
+                        Some(l) => format!("{}:{l}", first.file),
+                        None => first.file,
+                    };
+
+                    problems.push(self.problem(
+                        &format!("{section}[{idx}].{name_key}"),
+                        &format!("Duplicate {name_key} '{name}', first defined at {location}"),
+                    ));
+                } else {
+                    seen.insert(name.to_string(), idx);
+                }
+            }
+
+            self.problems.extend(problems);
+        }
+    }
+
+    /// Report unknown keys, values of the wrong type, and missing
+    /// required values.
+    pub fn check_schema(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        self.check_keys("", &self.root, ROOT_KEYS, &[], &mut problems);
+
+        for (section, keys, required) in [
+            ("setting-groups", GROUP_KEYS, GROUP_REQUIRED),
+            ("accounts", ACCOUNT_KEYS, ACCOUNT_REQUIRED),
+        ] {
+            if let Some(entries) = self.root[section].as_vec() {
+                for (idx, entry) in entries.iter().enumerate() {
+                    let path = format!("{section}[{idx}]");
+                    self.check_keys(&path, entry, keys, required, &mut problems);
+                }
+            }
+        }
+
+        if let Some(accounts) = self.root["accounts"].as_vec() {
+            for (idx, account) in accounts.iter().enumerate() {
+                if account["sip-password"].is_badvalue()
+                    && account["sip-password-hash"].is_badvalue()
+                {
+                    problems.push(self.problem(
+                        &format!("accounts[{idx}]"),
+                        "Missing required value: sip-password or sip-password-hash",
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    fn check_keys(
+        &self,
+        path: &str,
+        node: &Yaml,
+        keys: &[(&str, Kind)],
+        required: &[&str],
+        problems: &mut Vec<ConfigProblem>,
+    ) {
+        let Some(hash) = node.as_hash() else {
+            problems.push(self.problem(path, "Expected a mapping"));
+            return;
+        };
+
+        for (key, value) in hash {
+            let key = key.as_str().unwrap_or("");
+            let key_path = join_path(path, key);
+
+            match keys.iter().find(|(k, _)| *k == key) {
+                Some((_, kind)) => {
+                    if !value.is_null() && !kind.matches(value) {
+                        problems.push(
+                            self.problem(&key_path, &format!("{key} must be {}", kind.label())),
+                        );
+                    }
+                }
+                None => problems.push(self.problem(&key_path, &format!("Unknown setting: {key}"))),
+            }
+        }
+
+        let present: HashSet<&str> = hash.keys().filter_map(|k| k.as_str()).collect();
+
+        for key in required {
+            if !present.contains(key) {
+                problems.push(self.problem(path, &format!("Missing required value: {key}")));
+            }
+        }
+    }
+}
+
+/// Find the files matching an include pattern, relative to `dir`.
+///
+/// Patterns without wildcards must name an existing file.
+fn expand_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = dir.join(pattern);
+
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid include: {pattern}"))?;
+
+    if !name.contains(['*', '?']) {
+        if !path.exists() {
+            return Err(format!("Included file does not exist: {}", path.display()));
+        }
+        return Ok(vec![path]);
+    }
+
+    let parent = path.parent().unwrap_or(dir);
+
+    if parent.to_string_lossy().contains(['*', '?']) {
+        return Err(format!(
+            "Wildcards are only supported in file names: {pattern}"
+        ));
+    }
+
+    let entries = fs::read_dir(parent)
+        .map_err(|e| format!("Cannot read include directory {}: {e}", parent.display()))?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| wildcard_match(name, n))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Match a file name against a pattern where * matches any number
+/// of characters and ? matches one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    fn matches(p: &[char], n: &[char]) -> bool {
+        match p.first() {
+            None => n.is_empty(),
+            Some('*') => (0..=n.len()).any(|i| matches(&p[1..], &n[i..])),
+            Some('?') => !n.is_empty() && matches(&p[1..], &n[1..]),
+            Some(c) => n.first() == Some(c) && matches(&p[1..], &n[1..]),
+        }
+    }
+
+    matches(&pattern, &name)
+}
+
+/// Replace ${NAME} references in string values with the value of
+/// the environment variable, adding a (path, message) to `problems`
+/// for each reference which cannot be resolved.
+fn interpolate_env(node: &mut Yaml, path: &str, problems: &mut Vec<(String, String)>) {
+    match node {
+        Yaml::String(s) => match interpolate(s, |name| std::env::var(name).ok()) {
+            Ok(value) => *s = value,
+            Err(e) => problems.push((path.to_string(), e)),
+        },
+        Yaml::Array(list) => {
+            for (idx, entry) in list.iter_mut().enumerate() {
+                interpolate_env(entry, &format!("{path}[{idx}]"), problems);
+            }
+        }
+        Yaml::Hash(hash) => {
+            for (key, value) in hash.iter_mut() {
+                let key_path = join_path(path, key.as_str().unwrap_or(""));
+                interpolate_env(value, &key_path, problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace ${NAME} references in a string with values from `lookup`.
+fn interpolate(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut missing = Vec::new();
+    let mut rest = value;
+
+    while let Some(pos) = rest.find('$') {
+        result += &rest[..pos];
+        rest = &rest[pos..];
+
+        if rest.starts_with("$${") {
+            result += "${";
+            rest = &rest[3..];
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("Unterminated variable reference in '{value}'"))?;
+
+            let name = &reference[..end];
+
+            match lookup(name) {
+                Some(v) => result += &v,
+                None => missing.push(name),
+            }
+
+            rest = &reference[end + 1..];
+        } else {
+            result += "$";
+            rest = &rest[1..];
+        }
+    }
+
+    result += rest;
+
+    if missing.is_empty() {
+        Ok(result)
+    } else {
+        Err(format!(
+            "Environment variable not set: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sip2-conf-{name}-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(dir.join("accounts")).unwrap();
+    dir
+}
+
+#[cfg(test)]
+const TEST_MAIN_CONFIG: &str = r#"
+sip-port: 6001
+include: "accounts/*.yml"
+setting-groups:
+  - name: default
+    institution: example
+accounts:
+  - sip-username: main
+    sip-password: "${SIP2_CONF_TEST_PASSWORD}"
+    ils-username: admin
+    settings: default
+"#;
+
+#[test]
+fn test_interpolate() {
+    let lookup = |name: &str| (name == "HOME").then(|| "/home/sip".to_string());
+
+    assert_eq!(interpolate("${HOME}/x", lookup).unwrap(), "/home/sip/x");
+    assert_eq!(interpolate("$pbkdf2$i=1", lookup).unwrap(), "$pbkdf2$i=1");
+    assert_eq!(interpolate("$${HOME}", lookup).unwrap(), "${HOME}");
+
+    let err = interpolate("${NOPE} ${HOME} ${ALSO_NOPE}", lookup).unwrap_err();
+    assert_eq!(err, "Environment variable not set: NOPE, ALSO_NOPE");
+
+    assert!(interpolate("${HOME", lookup).is_err());
+}
+
+#[test]
+fn test_wildcard_match() {
+    assert!(wildcard_match("*.yml", "branch1.yml"));
+    assert!(wildcard_match("br?.yml", "br1.yml"));
+    assert!(!wildcard_match("*.yml", "branch1.yaml"));
+    assert!(!wildcard_match("br?.yml", "br12.yml"));
+}
+
+#[test]
+fn test_includes_and_missing_env() {
+    let dir = test_dir("includes");
+
+    fs::write(dir.join("sip.yml"), TEST_MAIN_CONFIG).unwrap();
+
+    fs::write(
+        dir.join("accounts/br1.yml"),
+        "accounts:\n  - sip-username: br1\n    sip-password: pass\n    ils-username: admin\n    settings: default\n",
+    )
+    .unwrap();
+
+    fs::write(
+        dir.join("accounts/br2.yml"),
+        "accounts:\n  - sip-username: br2\n    sip-password: pass\n    ils-username: admin\n    settings: default\n    bogus: 1\n\n  - sip-username: br1\n    sip-password: pass\n    ils-username: admin\n    settings: default\n",
+    )
+    .unwrap();
+
+    let path = dir.join("sip.yml").to_string_lossy().to_string();
+    let source = ConfigSource::load(&path);
+
+    let usernames: Vec<&str> = source.root()["accounts"]
+        .as_vec()
+        .unwrap()
+        .iter()
+        .map(|a| a["sip-username"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(usernames, ["main", "br1", "br2", "br1"]);
+    assert_eq!(source.root()["sip-port"].as_i64(), Some(6001));
+
+    let problems: Vec<String> = source.problems().iter().map(|p| p.to_string()).collect();
+
+    // The env var is unset.
+    assert!(
+        problems[0].ends_with("sip.yml:9: Environment variable not set: SIP2_CONF_TEST_PASSWORD")
+    );
+
+    // Duplicate usernames are reported where the duplicate appears,
+    // pointing to the original.
+    assert!(problems[1].contains("br2.yml:8: Duplicate sip-username 'br1'"));
+    assert!(problems[1].ends_with("br1.yml:2"));
+    assert_eq!(problems.len(), 2);
+
+    // Schema problems are located in the included file.
+    let schema: Vec<String> = source
+        .check_schema()
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+    assert_eq!(schema.len(), 1);
+    assert!(schema[0].ends_with("br2.yml:6: Unknown setting: bogus"));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = test_dir("cycle");
+
+    fs::write(dir.join("sip.yml"), "include: accounts/a.yml\n").unwrap();
+    fs::write(dir.join("accounts/a.yml"), "include: b.yml\n").unwrap();
+    fs::write(dir.join("accounts/b.yml"), "include:\n  - a.yml\n").unwrap();
+
+    let path = dir.join("sip.yml").to_string_lossy().to_string();
+    let source = ConfigSource::load(&path);
+
+    assert_eq!(source.problems().len(), 1);
+
+    let problem = &source.problems()[0];
+    assert!(problem.message.starts_with("Include cycle: "));
+    // a.yml -> b.yml -> a.yml
+    let files: Vec<&str> = problem.message.split(" -> ").collect();
+    assert_eq!(files.len(), 3);
+    assert!(files[0].ends_with("a.yml"));
+    assert!(files[1].ends_with("b.yml"));
+    assert!(files[2].ends_with("a.yml"));
+    assert!(problem.file.ends_with("b.yml"));
+    assert_eq!(problem.line, Some(2));
+
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_example_config_schema() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/conf/eg-sip2-server.example.yml"
+    );
+    let source = ConfigSource::load(path);
+
+    assert!(source.problems().is_empty(), "{:?}", source.problems());
+
+    let schema = source.check_schema();
+    assert!(schema.is_empty(), "{:?}", schema);
+}
@@ -0,0 +1,393 @@
+//! Copy alerts reported as SIP screen messages.
+//!
+//! Staff use copy alerts (asset.copy_alert) and the legacy copy
+//! alert_message to flag items for special handling, e.g. routing
+//! damaged items to mending.  Unacknowledged alerts for the current
+//! event are added to the response as AF fields, one per alert.
+use super::item::Item;
+use super::session::Session;
+use eg::common::org;
+use eg::constants as C;
+use eg::result::EgResult;
+use eg::EgValue;
+use evergreen as eg;
+
+/// Screen message field.
+const SCREEN_MESSAGE: &str = "AF";
+
+/// What is happening to the item.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertEvent {
+    /// Item information.  All active alerts apply.
+    ItemInfo,
+    Checkin,
+    Checkout,
+    Renewal,
+}
+
+/// Claims states come from the open circulation, not the copy status.
+const CLAIMS_STATES: &[&str] = &["CLAIMSRETURNED", "CLAIMSNEVERCHECKEDOUT"];
+
+/// Where and in what state the alerted copy is found.
+struct AlertCopy {
+    /// config.copy_alert_type.state value for the copy.
+    state: &'static str,
+    /// Workstation org unit, i.e. where the transaction happens.
+    ws_org: i64,
+    circ_lib: i64,
+    owning_lib: i64,
+}
+
+/// Alert type state for a copy status.  Copies in any other status,
+/// including checked out, are in the NORMAL state.
+fn copy_alert_state(copy_status: i64) -> &'static str {
+    match copy_status {
+        C::COPY_STATUS_LOST => "LOST",
+        C::COPY_STATUS_LOST_AND_PAID => "LOST_AND_PAID",
+        C::COPY_STATUS_LONG_OVERDUE => "LONGOVERDUE",
+        C::COPY_STATUS_MISSING => "MISSING",
+        C::COPY_STATUS_DAMAGED => "DAMAGED",
+        _ => "NORMAL",
+    }
+}
+
+/// True if alerts of this config.copy_alert_type apply to the event.
+///
+/// Like the circulator, the alert type must match the copy state,
+/// and at_circ / at_owning limit the alert to transactions at (or,
+/// with invert_location, away from) the copy's circ / owning library.
+fn alert_type_applies(atype: &EgValue, event: AlertEvent, copy: &AlertCopy) -> bool {
+    if !atype["active"].boolish() {
+        return false;
+    }
+
+    if atype["state"].as_str().unwrap_or("NORMAL") != copy.state {
+        return false;
+    }
+
+    let invert = atype["invert_location"].boolish();
+
+    if atype["at_circ"].boolish() && (copy.ws_org == copy.circ_lib) == invert {
+        return false;
+    }
+
+    if atype["at_owning"].boolish() && (copy.ws_org == copy.owning_lib) == invert {
+        return false;
+    }
+
+    let in_renew = atype["in_renew"].boolish();
+    let alert_event = atype["event"].as_str();
+
+    match event {
+        AlertEvent::ItemInfo => true,
+        AlertEvent::Renewal => in_renew,
+        AlertEvent::Checkin => !in_renew && alert_event.unwrap_or("CHECKIN") == "CHECKIN",
+        AlertEvent::Checkout => !in_renew && alert_event.unwrap_or("CHECKOUT") == "CHECKOUT",
+    }
+}
+
+/// Screen message for a copy alert: its note, or the alert type name
+/// when no note was entered.
+fn alert_message(alert: &EgValue) -> Option<&str> {
+    alert["note"]
+        .as_str()
+        .map(|n| n.trim())
+        .filter(|n| !n.is_empty())
+        .or_else(|| alert["alert_type"]["name"].as_str())
+}
+
+impl Session {
+    /// Messages for the item's copy alerts which apply to the event.
+    pub fn copy_alert_messages(&mut self, item: &Item, event: AlertEvent) -> EgResult<Vec<String>> {
+        let query = eg::hash! {
+            copy: item.id,
+            ack_time: EgValue::Null,
+        };
+
+        let flesh = eg::hash! {
+            flesh: 1,
+            flesh_fields: {aca: ["alert_type"]}
+        };
+
+        let alerts = self.editor_mut().search_with_ops("aca", query, flesh)?;
+
+        let mut messages = Vec::new();
+
+        if !alerts.is_empty() {
+            if self.editor().requestor().is_none() {
+                // Item information does not otherwise need our login.
+                self.set_authtoken()?;
+            }
+
+            // actor.copy_alert_suppress
+            let ws_org = self.get_ws_org_id()?;
+            let org_path = org::full_path(self.editor_mut(), ws_org, None)?;
+            let suppressions = self
+                .editor_mut()
+                .search("acas", eg::hash! {org: org_path})?;
+
+            let copy = AlertCopy {
+                state: self.copy_alert_state(item, &alerts)?,
+                ws_org,
+                circ_lib: item.circ_lib,
+                owning_lib: item.owning_lib,
+            };
+
+            for alert in alerts.iter() {
+                let atype = &alert["alert_type"];
+
+                if !alert_type_applies(atype, event, &copy) {
+                    continue;
+                }
+
+                if suppressions.iter().any(|s| s["alert_type"] == atype["id"]) {
+                    continue;
+                }
+
+                if let Some(msg) = alert_message(alert) {
+                    messages.push(msg.to_string());
+                }
+            }
+        }
+
+        // Like the circulator, the legacy alert message is not
+        // reported on renewal.
+        if event != AlertEvent::Renewal {
+            if let Some(msg) = item.alert_message.as_deref() {
+                messages.push(msg.to_string());
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Alert type state of the item.
+    ///
+    /// A checked out copy is in a claims state when its open circulation
+    /// was stopped by a claim.  The circulation is only looked up when
+    /// one of the alerts is for a claims state.
+    fn copy_alert_state(&mut self, item: &Item, alerts: &[EgValue]) -> EgResult<&'static str> {
+        let state = copy_alert_state(item.copy_status);
+
+        if item.copy_status != C::COPY_STATUS_CHECKED_OUT {
+            return Ok(state);
+        }
+
+        let wants_claims = alerts.iter().any(|a| {
+            let atype_state = a["alert_type"]["state"].as_str().unwrap_or("");
+            CLAIMS_STATES.contains(&atype_state)
+        });
+
+        if !wants_claims {
+            return Ok(state);
+        }
+
+        let query = eg::hash! {
+            target_copy: item.id,
+            checkin_time: EgValue::Null,
+            stop_fines: ["CLAIMSRETURNED", "CLAIMSNEVERCHECKEDOUT"],
+        };
+
+        let circs = self.editor_mut().search("circ", query)?;
+
+        let stop_fines = circs.first().and_then(|c| c["stop_fines"].as_str());
+
+        Ok(CLAIMS_STATES
+            .iter()
+            .find(|s| Some(**s) == stop_fines)
+            .copied()
+            .unwrap_or(state))
+    }
+
+    /// Add the item's copy alerts to a response as screen messages,
+    /// unless the account suppresses them.
+    pub fn add_copy_alerts(
+        &mut self,
+        resp: &mut sip2::Message,
+        item: &Item,
+        event: AlertEvent,
+    ) -> EgResult<()> {
+        if self.account().suppress_copy_alerts() {
+            return Ok(());
+        }
+
+        for msg in self.copy_alert_messages(item, event)? {
+            resp.add_field(SCREEN_MESSAGE, &msg);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_alert_copy(state: &'static str, ws_org: i64) -> AlertCopy {
+    AlertCopy {
+        state,
+        ws_org,
+        circ_lib: 4,
+        owning_lib: 5,
+    }
+}
+
+#[test]
+fn test_alert_type_applies() {
+    let atype = |event: &str, in_renew: &str| {
+        eg::hash! {
+            active: "t",
+            state: "NORMAL",
+            event: event,
+            in_renew: in_renew,
+            at_circ: "f",
+            at_owning: "f",
+            invert_location: "f",
+        }
+    };
+
+    let copy = test_alert_copy("NORMAL", 4);
+
+    let checkin = atype("CHECKIN", "f");
+    assert!(alert_type_applies(&checkin, AlertEvent::Checkin, &copy));
+    assert!(alert_type_applies(&checkin, AlertEvent::ItemInfo, &copy));
+    assert!(!alert_type_applies(&checkin, AlertEvent::Checkout, &copy));
+    assert!(!alert_type_applies(&checkin, AlertEvent::Renewal, &copy));
+
+    let checkout = atype("CHECKOUT", "f");
+    assert!(alert_type_applies(&checkout, AlertEvent::Checkout, &copy));
+    assert!(!alert_type_applies(&checkout, AlertEvent::Checkin, &copy));
+
+    let renew = atype("CHECKOUT", "t");
+    assert!(alert_type_applies(&renew, AlertEvent::Renewal, &copy));
+    assert!(!alert_type_applies(&renew, AlertEvent::Checkout, &copy));
+
+    let mut inactive = atype("CHECKIN", "f");
+    inactive["active"] = EgValue::from("f");
+    assert!(!alert_type_applies(&inactive, AlertEvent::Checkin, &copy));
+    assert!(!alert_type_applies(&inactive, AlertEvent::ItemInfo, &copy));
+}
+
+#[test]
+fn test_alert_type_state() {
+    assert_eq!(copy_alert_state(C::COPY_STATUS_AVAILABLE), "NORMAL");
+    assert_eq!(copy_alert_state(C::COPY_STATUS_CHECKED_OUT), "NORMAL");
+    assert_eq!(copy_alert_state(C::COPY_STATUS_LOST), "LOST");
+    assert_eq!(copy_alert_state(C::COPY_STATUS_DAMAGED), "DAMAGED");
+
+    let lost = eg::hash! {active: "t", state: "LOST", event: "CHECKIN", in_renew: "f"};
+
+    let normal_copy = test_alert_copy("NORMAL", 4);
+    let lost_copy = test_alert_copy("LOST", 4);
+
+    assert!(!alert_type_applies(
+        &lost,
+        AlertEvent::Checkin,
+        &normal_copy
+    ));
+    assert!(!alert_type_applies(
+        &lost,
+        AlertEvent::ItemInfo,
+        &normal_copy
+    ));
+    assert!(alert_type_applies(&lost, AlertEvent::Checkin, &lost_copy));
+
+    let normal = eg::hash! {active: "t", state: "NORMAL", event: "CHECKIN", in_renew: "f"};
+    assert!(!alert_type_applies(
+        &normal,
+        AlertEvent::Checkin,
+        &lost_copy
+    ));
+}
+
+#[test]
+fn test_alert_type_scope() {
+    let atype = |at_circ: &str, at_owning: &str, invert: &str| {
+        eg::hash! {
+            active: "t",
+            state: "NORMAL",
+            event: "CHECKIN",
+            in_renew: "f",
+            at_circ: at_circ,
+            at_owning: at_owning,
+            invert_location: invert,
+        }
+    };
+
+    // Copy circ lib is 4, owning lib is 5.
+    let at_circ_lib = test_alert_copy("NORMAL", 4);
+    let at_owning_lib = test_alert_copy("NORMAL", 5);
+    let elsewhere = test_alert_copy("NORMAL", 6);
+
+    let anywhere = atype("f", "f", "f");
+    assert!(alert_type_applies(
+        &anywhere,
+        AlertEvent::Checkin,
+        &elsewhere
+    ));
+
+    let at_circ = atype("t", "f", "f");
+    assert!(alert_type_applies(
+        &at_circ,
+        AlertEvent::Checkin,
+        &at_circ_lib
+    ));
+    assert!(!alert_type_applies(
+        &at_circ,
+        AlertEvent::Checkin,
+        &at_owning_lib
+    ));
+    assert!(!alert_type_applies(
+        &at_circ,
+        AlertEvent::Checkin,
+        &elsewhere
+    ));
+
+    let not_at_circ = atype("t", "f", "t");
+    assert!(!alert_type_applies(
+        &not_at_circ,
+        AlertEvent::Checkin,
+        &at_circ_lib
+    ));
+    assert!(alert_type_applies(
+        &not_at_circ,
+        AlertEvent::Checkin,
+        &elsewhere
+    ));
+
+    let at_owning = atype("f", "t", "f");
+    assert!(alert_type_applies(
+        &at_owning,
+        AlertEvent::Checkin,
+        &at_owning_lib
+    ));
+    assert!(!alert_type_applies(
+        &at_owning,
+        AlertEvent::Checkin,
+        &at_circ_lib
+    ));
+
+    let not_at_owning = atype("f", "t", "t");
+    assert!(!alert_type_applies(
+        &not_at_owning,
+        AlertEvent::Checkin,
+        &at_owning_lib
+    ));
+    assert!(alert_type_applies(
+        &not_at_owning,
+        AlertEvent::Checkin,
+        &elsewhere
+    ));
+}
+
+#[test]
+fn test_alert_message() {
+    let alert = eg::hash! {
+        note: "  Route to mending ",
+        alert_type: {name: "Normal checkin"},
+    };
+    assert_eq!(alert_message(&alert), Some("Route to mending"));
+
+    let alert = eg::hash! {
+        note: "",
+        alert_type: {name: "Normal checkin"},
+    };
+    assert_eq!(alert_message(&alert), Some("Normal checkin"));
+}
@@ -0,0 +1,309 @@
+//! Per-account customization of SIP response fields.
+//!
+//! Accounts may copy patron and item attributes into arbitrary
+//! response fields and suppress fields their SIP clients do not want.
+use super::item::Item;
+use super::patron::Patron;
+use super::session::Session;
+use std::collections::HashMap;
+
+/// Patron and item attributes which may be mapped to response fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldSource {
+    PatronBarcode,
+    PatronName,
+    PatronEmail,
+    PatronPhone,
+    PatronAddress,
+    PatronHomeLib,
+    PatronDob,
+    PatronExpireDate,
+    PatronNetAccess,
+    PatronProfile,
+    ItemBarcode,
+    ItemTitle,
+    ItemMediaType,
+    ItemCircStatus,
+    ItemDueDate,
+    ItemCurrentLoc,
+    ItemPermanentLoc,
+    ItemDestinationLoc,
+    ItemOwningLoc,
+    ItemHoldPickupDate,
+    ItemHoldPatronBarcode,
+}
+
+/// Config names for each FieldSource.
+const FIELD_SOURCES: &[(&str, FieldSource)] = &[
+    ("patron.barcode", FieldSource::PatronBarcode),
+    ("patron.name", FieldSource::PatronName),
+    ("patron.email", FieldSource::PatronEmail),
+    ("patron.phone", FieldSource::PatronPhone),
+    ("patron.address", FieldSource::PatronAddress),
+    ("patron.home_lib", FieldSource::PatronHomeLib),
+    ("patron.dob", FieldSource::PatronDob),
+    ("patron.expire_date", FieldSource::PatronExpireDate),
+    ("patron.net_access", FieldSource::PatronNetAccess),
+    ("patron.profile", FieldSource::PatronProfile),
+    ("item.barcode", FieldSource::ItemBarcode),
+    ("item.title", FieldSource::ItemTitle),
+    ("item.media_type", FieldSource::ItemMediaType),
+    ("item.circ_status", FieldSource::ItemCircStatus),
+    ("item.due_date", FieldSource::ItemDueDate),
+    ("item.current_loc", FieldSource::ItemCurrentLoc),
+    ("item.permanent_loc", FieldSource::ItemPermanentLoc),
+    ("item.destination_loc", FieldSource::ItemDestinationLoc),
+    ("item.owning_loc", FieldSource::ItemOwningLoc),
+    ("item.hold_pickup_date", FieldSource::ItemHoldPickupDate),
+    (
+        "item.hold_patron_barcode",
+        FieldSource::ItemHoldPatronBarcode,
+    ),
+];
+
+impl TryFrom<&str> for FieldSource {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<FieldSource, String> {
+        FIELD_SOURCES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, source)| *source)
+            .ok_or_else(|| format!("Unknown field mapping source: '{s}'"))
+    }
+}
+
+/// Put the value of a patron or item attribute into a response field.
+#[derive(Debug, Clone)]
+pub struct FieldMapping {
+    /// Response message code, e.g. "64".
+    message: String,
+    /// 2-char SIP field code
+    field: String,
+    source: FieldSource,
+}
+
+impl FieldMapping {
+    pub fn new(message: &str, field: &str, source: FieldSource) -> Self {
+        FieldMapping {
+            message: message.to_string(),
+            field: field.to_string(),
+            source,
+        }
+    }
+
+    /// Parse a field-mappings entry.
+    pub fn from_yaml(node: &yaml_rust::Yaml) -> Result<Self, String> {
+        let message = node["message"]
+            .as_str()
+            .ok_or_else(|| format!("Field mapping requires a message: {node:?}"))?;
+
+        let field = node["field"]
+            .as_str()
+            .ok_or_else(|| format!("Field mapping requires a field: {node:?}"))?;
+
+        let source = node["source"]
+            .as_str()
+            .ok_or_else(|| format!("Field mapping requires a source: {node:?}"))?;
+
+        Ok(FieldMapping::new(message, field, source.try_into()?))
+    }
+}
+
+/// Remove a field from responses.
+#[derive(Debug, Clone)]
+pub struct FieldSuppression {
+    /// Response message code.  None means all responses.
+    message: Option<String>,
+    field: String,
+}
+
+impl FieldSuppression {
+    pub fn new(message: Option<&str>, field: &str) -> Self {
+        FieldSuppression {
+            message: message.map(|m| m.to_string()),
+            field: field.to_string(),
+        }
+    }
+
+    /// Parse a suppress-fields entry.
+    pub fn from_yaml(node: &yaml_rust::Yaml) -> Result<Self, String> {
+        let field = node["field"]
+            .as_str()
+            .ok_or_else(|| format!("Suppressed field requires a field: {node:?}"))?;
+
+        Ok(FieldSuppression::new(node["message"].as_str(), field))
+    }
+}
+
+/// Attribute values of the patron and item loaded while processing
+/// the current SIP request.
+#[derive(Debug, Default)]
+pub struct FieldValues {
+    values: HashMap<FieldSource, String>,
+}
+
+impl FieldValues {
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn get(&self, source: FieldSource) -> Option<&str> {
+        self.values.get(&source).map(|v| v.as_str())
+    }
+
+    fn set(&mut self, source: FieldSource, value: Option<&str>) {
+        match value {
+            Some(v) => self.values.insert(source, v.to_string()),
+            None => self.values.remove(&source),
+        };
+    }
+
+    pub fn add_patron(&mut self, patron: &Patron) {
+        self.set(FieldSource::PatronBarcode, Some(&patron.barcode));
+        self.set(FieldSource::PatronName, Some(&patron.name));
+        self.set(FieldSource::PatronEmail, patron.email.as_deref());
+        self.set(FieldSource::PatronPhone, patron.phone.as_deref());
+        self.set(FieldSource::PatronAddress, patron.address.as_deref());
+        self.set(FieldSource::PatronHomeLib, patron.home_lib.as_deref());
+        self.set(FieldSource::PatronDob, patron.dob.as_deref());
+        self.set(FieldSource::PatronExpireDate, patron.expire_date.as_deref());
+        self.set(FieldSource::PatronNetAccess, patron.net_access.as_deref());
+        self.set(FieldSource::PatronProfile, patron.profile.as_deref());
+    }
+
+    pub fn add_item(&mut self, item: &Item) {
+        self.set(FieldSource::ItemBarcode, Some(&item.barcode));
+        self.set(FieldSource::ItemTitle, Some(&item.title));
+        self.set(FieldSource::ItemMediaType, Some(&item.media_type));
+        self.set(FieldSource::ItemCircStatus, Some(&item.circ_status));
+        self.set(FieldSource::ItemDueDate, item.due_date.as_deref());
+        self.set(FieldSource::ItemCurrentLoc, Some(&item.current_loc));
+        self.set(FieldSource::ItemPermanentLoc, Some(&item.permanent_loc));
+        self.set(FieldSource::ItemDestinationLoc, Some(&item.destination_loc));
+        self.set(FieldSource::ItemOwningLoc, Some(&item.owning_loc));
+        self.set(
+            FieldSource::ItemHoldPickupDate,
+            item.hold_pickup_date.as_deref(),
+        );
+        self.set(
+            FieldSource::ItemHoldPatronBarcode,
+            item.hold_patron_barcode.as_deref(),
+        );
+    }
+}
+
+/// Apply field mappings, then suppressions, to a response.
+///
+/// A mapped field replaces the value of an existing field with the
+/// same code or is added if none exists.  Mappings whose source has
+/// no value are skipped.
+pub fn apply_customizations(
+    mappings: &[FieldMapping],
+    suppressions: &[FieldSuppression],
+    values: &FieldValues,
+    resp: &mut sip2::Message,
+) {
+    let code = resp.spec().code;
+
+    for mapping in mappings.iter().filter(|m| m.message == code) {
+        let value = match values.get(mapping.source) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        match resp
+            .fields_mut()
+            .iter_mut()
+            .find(|f| f.code() == mapping.field)
+        {
+            Some(field) => field.set_value(value),
+            None => resp.add_field(&mapping.field, value),
+        }
+    }
+
+    for sup in suppressions {
+        if sup.message.as_deref().map(|m| m == code).unwrap_or(true) {
+            resp.remove_field(&sup.field, true);
+        }
+    }
+}
+
+impl Session {
+    /// Apply our account's field mappings and suppressions to a
+    /// response before it's sent.
+    pub fn apply_field_customizations(&self, resp: &mut sip2::Message) {
+        if !self.has_account() {
+            // Can happen if this is a pre-login SC response.
+            return;
+        }
+
+        let account = self.account();
+
+        apply_customizations(
+            account.field_mappings(),
+            account.suppress_fields(),
+            self.field_values(),
+            resp,
+        );
+    }
+}
+
+#[test]
+fn test_field_customizations() {
+    assert_eq!(
+        FieldSource::try_from("patron.email"),
+        Ok(FieldSource::PatronEmail)
+    );
+    assert!(FieldSource::try_from("patron.shoe_size").is_err());
+
+    let mut patron = Patron::new("patron-barcode", "Jane Doe".to_string());
+    patron.email = Some("jane@example.org".to_string());
+    patron.net_access = Some("Filtered".to_string());
+
+    let mut values = FieldValues::default();
+    values.add_patron(&patron);
+
+    let mappings = vec![
+        FieldMapping::new("64", "BE", FieldSource::PatronEmail),
+        FieldMapping::new("64", "PI", FieldSource::PatronNetAccess),
+        FieldMapping::new("64", "BF", FieldSource::PatronPhone), // no value
+        FieldMapping::new("24", "PI", FieldSource::PatronNetAccess),
+    ];
+
+    let suppressions = vec![
+        FieldSuppression::new(Some("64"), "BD"),
+        FieldSuppression::new(None, "AF"),
+    ];
+
+    let mut resp = sip2::Message::from_ff_values(
+        &sip2::spec::M_PATRON_INFO_RESP,
+        &[
+            "              ",
+            "000",
+            &sip2::util::sip_date_now(),
+            "0000",
+            "0000",
+            "0000",
+            "0000",
+            "0000",
+            "0000",
+        ],
+    )
+    .unwrap();
+
+    resp.add_field("AA", "patron-barcode");
+    resp.add_field("BE", "old@example.org");
+    resp.add_field("BD", "123 Main St");
+    resp.add_field("AF", "Hello");
+
+    apply_customizations(&mappings, &suppressions, &values, &mut resp);
+
+    assert_eq!(resp.get_field_value("BE"), Some("jane@example.org"));
+    assert_eq!(resp.get_field_value("PI"), Some("Filtered"));
+    assert_eq!(resp.get_field_value("BF"), None);
+    assert_eq!(resp.get_field_value("BD"), None);
+    assert_eq!(resp.get_field_value("AF"), None);
+    assert_eq!(resp.get_field_value("AA"), Some("patron-barcode"));
+    assert_eq!(resp.fields().iter().filter(|f| f.code() == "BE").count(), 1);
+}
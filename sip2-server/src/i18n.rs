@@ -0,0 +1,357 @@
+//! Screen message translation.
+//!
+//! Translations are read at startup from gettext PO files laid out
+//! like a gettext locale directory:
+//!
+//! ```text
+//! {locale-dir}/{locale}/LC_MESSAGES/eg-sip2-server.po
+//! ```
+//!
+//! Each SIP account may specify a locale.  Screen messages sent to
+//! clients of the account are translated into that locale, falling
+//! back to the language alone (es-ES -> es), then to the untranslated
+//! message.
+//!
+//! Messages which contain values use positional placeholders, {0},
+//! {1}, etc., so translations may reorder them.
+use super::session::Session;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Our gettext text domain, i.e. the name of our PO files.
+pub const TEXT_DOMAIN: &str = "eg-sip2-server";
+
+/// Translations for a single locale.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Parse the contents of a PO file.
+    ///
+    /// Supports single and multi-line msgid and msgstr values.
+    /// Plural forms and message contexts are not used by the SIP
+    /// server and are ignored, as are fuzzy and untranslated entries.
+    pub fn parse(po: &str) -> Result<Self, String> {
+        let mut catalog = Catalog::default();
+
+        let mut msgid: Option<String> = None;
+        let mut msgstr: Option<String> = None;
+        let mut fuzzy = false;
+        let mut skip = false;
+
+        // Which value continuation lines append to.
+        let mut in_msgstr = false;
+
+        let mut add = |msgid: Option<String>, msgstr: Option<String>, fuzzy: bool, skip: bool| {
+            if let (Some(id), Some(s)) = (msgid, msgstr) {
+                // The empty msgid is the PO header.
+                if !id.is_empty() && !s.is_empty() && !fuzzy && !skip {
+                    catalog.messages.insert(id, s);
+                }
+            }
+        };
+
+        for (idx, line) in po.lines().enumerate() {
+            let line = line.trim();
+            let err = |e: String| format!("PO line {}: {e}", idx + 1);
+
+            if line.is_empty() {
+                continue;
+            }
+
+            // Comments and flags belong to the entry which follows.
+            let new_entry =
+                line.starts_with('#') || line.starts_with("msgctxt ") || line.starts_with("msgid ");
+
+            if new_entry && msgid.is_some() {
+                add(msgid.take(), msgstr.take(), fuzzy, skip);
+                fuzzy = false;
+                skip = false;
+            }
+
+            if line.starts_with("#,") {
+                fuzzy = line.contains("fuzzy");
+                continue;
+            }
+
+            if line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("msgid ") {
+                msgid = Some(unquote(rest).map_err(err)?);
+                in_msgstr = false;
+            } else if let Some(rest) = line.strip_prefix("msgstr ") {
+                msgstr = Some(unquote(rest).map_err(err)?);
+                in_msgstr = true;
+            } else if line.starts_with("msgctxt ")
+                || line.starts_with("msgid_plural ")
+                || line.starts_with("msgstr[")
+            {
+                skip = true;
+            } else if line.starts_with('"') {
+                let value = unquote(line).map_err(err)?;
+                let target = if in_msgstr {
+                    msgstr.as_mut()
+                } else {
+                    msgid.as_mut()
+                };
+
+                match target {
+                    Some(t) => t.push_str(&value),
+                    None if skip => {}
+                    None => return Err(err("String without msgid or msgstr".to_string())),
+                }
+            } else {
+                return Err(err(format!("Unexpected content: {line}")));
+            }
+        }
+
+        add(msgid, msgstr, fuzzy, skip);
+
+        Ok(catalog)
+    }
+
+    pub fn get(&self, msgid: &str) -> Option<&str> {
+        self.messages.get(msgid).map(|s| s.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+/// Remove the quotes from a PO string value and unescape it.
+fn unquote(value: &str) -> Result<String, String> {
+    let inner = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| format!("Invalid PO string: {value}"))?;
+
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some('"') => unquoted.push('"'),
+            Some('\\') => unquoted.push('\\'),
+            Some(c) => return Err(format!("Invalid escape \\{c} in PO string: {value}")),
+            None => return Err(format!("Invalid PO string: {value}")),
+        }
+    }
+
+    Ok(unquoted)
+}
+
+/// Translation catalogs for all configured locales.
+#[derive(Debug, Default)]
+pub struct Translator {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Translator {
+    /// Load the catalogs for every locale in the locale directory.
+    pub fn load(locale_dir: &str) -> Result<Self, String> {
+        let mut translator = Translator::default();
+
+        let entries = fs::read_dir(locale_dir)
+            .map_err(|e| format!("Cannot read locale directory {locale_dir}: {e}"))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Cannot read locale directory: {e}"))?;
+
+            let path = entry
+                .path()
+                .join("LC_MESSAGES")
+                .join(format!("{TEXT_DOMAIN}.po"));
+
+            if !path.is_file() {
+                continue;
+            }
+
+            let locale = entry.file_name().to_string_lossy().to_string();
+
+            translator.add_catalog(&locale, &path)?;
+        }
+
+        Ok(translator)
+    }
+
+    fn add_catalog(&mut self, locale: &str, path: &Path) -> Result<(), String> {
+        let po = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read catalog {}: {e}", path.display()))?;
+
+        let catalog =
+            Catalog::parse(&po).map_err(|e| format!("Invalid catalog {}: {e}", path.display()))?;
+
+        log::info!("Loaded {} translations for locale {locale}", catalog.len());
+
+        self.catalogs.insert(locale.to_string(), catalog);
+
+        Ok(())
+    }
+
+    pub fn has_locale(&self, locale: &str) -> bool {
+        self.catalog(locale).is_some()
+    }
+
+    /// Catalog for the locale or, failing that, its language.
+    fn catalog(&self, locale: &str) -> Option<&Catalog> {
+        self.catalogs.get(locale).or_else(|| {
+            let language = locale.split(['-', '_']).next().unwrap_or(locale);
+            self.catalogs.get(language)
+        })
+    }
+
+    /// Translate a message into the locale.
+    ///
+    /// Returns the message as-is if we have no translation.
+    pub fn translate<'a>(&'a self, locale: Option<&str>, msgid: &'a str) -> &'a str {
+        locale
+            .and_then(|l| self.catalog(l))
+            .and_then(|c| c.get(msgid))
+            .unwrap_or(msgid)
+    }
+}
+
+/// Replace positional {0}, {1}, ... placeholders with values.
+///
+/// Placeholders without a matching value are left as-is.
+pub fn format_message(template: &str, values: &[&str]) -> String {
+    let mut formatted = template.to_string();
+
+    for (idx, value) in values.iter().enumerate() {
+        formatted = formatted.replace(&format!("{{{idx}}}"), value);
+    }
+
+    formatted
+}
+
+impl Session {
+    /// Translate a screen message into our account's locale.
+    pub fn tr(&self, msgid: &str) -> String {
+        let locale = if self.has_account() {
+            self.account().locale()
+        } else {
+            None
+        };
+
+        self.translator().translate(locale, msgid).to_string()
+    }
+
+    /// Translate a screen message then replace its positional
+    /// placeholders with the provided values.
+    pub fn tr_args(&self, msgid: &str, values: &[&str]) -> String {
+        format_message(&self.tr(msgid), values)
+    }
+}
+
+#[test]
+fn test_parse_catalog() {
+    let po = r#"
+# Spanish translations
+msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+
+msgid "Item not found"
+msgstr "Artículo no encontrado"
+
+# Multi-line values are joined.
+msgid ""
+"Patron is not allowed to "
+"checkout the selected item"
+msgstr ""
+"No se permite al usuario "
+"prestar el artículo"
+
+#, fuzzy
+msgid "Payment could not be completed"
+msgstr "Pago no completado"
+
+msgid "No translation"
+msgstr ""
+
+msgctxt "button"
+msgid "Pay"
+msgstr "Pagar"
+
+msgid "{0} of {1} \"items\""
+msgstr "{1} \"artículos\", {0}"
+"#;
+
+    let catalog = Catalog::parse(po).unwrap();
+
+    assert_eq!(catalog.len(), 3);
+    assert_eq!(
+        catalog.get("Item not found"),
+        Some("Artículo no encontrado")
+    );
+    assert_eq!(
+        catalog.get("Patron is not allowed to checkout the selected item"),
+        Some("No se permite al usuario prestar el artículo")
+    );
+    assert_eq!(catalog.get("Payment could not be completed"), None);
+    assert_eq!(catalog.get("No translation"), None);
+    assert_eq!(catalog.get("Pay"), None);
+
+    let translated = catalog.get("{0} of {1} \"items\"").unwrap();
+    assert_eq!(
+        format_message(translated, &["3", "5"]),
+        "5 \"artículos\", 3"
+    );
+
+    assert!(Catalog::parse("msgid \"unterminated").is_err());
+    assert!(Catalog::parse("msgid \"a\"\nmsgstr \"b\"\nbogus").is_err());
+}
+
+#[test]
+fn test_translate() {
+    let mut translator = Translator::default();
+
+    translator.catalogs.insert(
+        "es".to_string(),
+        Catalog::parse("msgid \"Item not found\"\nmsgstr \"Artículo no encontrado\"").unwrap(),
+    );
+
+    assert_eq!(
+        translator.translate(Some("es-ES"), "Item not found"),
+        "Artículo no encontrado"
+    );
+    assert_eq!(
+        translator.translate(Some("es"), "Item not found"),
+        "Artículo no encontrado"
+    );
+    assert_eq!(
+        translator.translate(Some("fr-CA"), "Item not found"),
+        "Item not found"
+    );
+    assert_eq!(
+        translator.translate(None, "Item not found"),
+        "Item not found"
+    );
+    assert_eq!(translator.translate(Some("es"), "Other"), "Other");
+}
+
+#[test]
+fn test_load_locale_dir() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("locale");
+    let translator = Translator::load(&dir.to_string_lossy()).unwrap();
+
+    assert!(translator.has_locale("es-ES"));
+    assert_eq!(
+        translator.translate(Some("es-ES"), "Item not found"),
+        "Artículo no encontrado"
+    );
+}
@@ -1,4 +1,6 @@
+use super::copyalert::AlertEvent;
 use super::session::Session;
+use eg::common::holds;
 use eg::constants as C;
 use eg::date;
 use eg::result::EgResult;
@@ -7,11 +9,14 @@ use evergreen as eg;
 
 /// A copy object with SIP-related data collected and attached.
 pub struct Item {
+    pub id: i64,
     pub barcode: String,
     pub circ_lib: i64,
+    /// Owning library of the call number.
+    pub owning_lib: i64,
     pub due_date: Option<String>,
     pub copy_status: i64,
-    pub circ_status: &'static str,
+    pub circ_status: String,
     pub fee_type: &'static str,
     pub title: String,
     pub current_loc: String,
@@ -24,7 +29,12 @@ pub struct Item {
     pub media_type: String,
     pub hold_pickup_date: Option<String>,
     pub hold_patron_barcode: Option<String>,
+    /// CY value for the hold patron.
+    pub hold_patron_ident: Option<String>,
+    pub hold_patron_name: Option<String>,
     pub circ_patron_id: Option<i64>,
+    /// Legacy copy alert message.
+    pub alert_message: Option<String>,
 }
 
 impl Session {
@@ -38,8 +48,7 @@ impl Session {
         let flesh = eg::hash! {
             flesh: 3,
             flesh_fields: {
-                acp: ["circ_lib", "call_number",
-                    "stat_cat_entry_copy_maps", "circ_modifier"],
+                acp: ["circ_lib", "call_number", "stat_cat_entry_copy_maps"],
                 acn: ["owning_lib", "record"],
                 bre: ["simple_record"],
                 ascecm: ["stat_cat", "stat_cat_entry"],
@@ -62,14 +71,7 @@ impl Session {
         if let Some(circ) = self.get_copy_circ(&copy, copy_status)? {
             circ_patron_id = Some(circ["usr"].int()?);
 
-            if let Some(iso_date) = circ["due_date"].as_str() {
-                if self.account().settings().due_date_use_sip_date_format() {
-                    let due_dt = date::parse_datetime(iso_date)?;
-                    due_date = Some(sip2::util::sip_date_from_dt(&due_dt));
-                } else {
-                    due_date = Some(iso_date.to_string());
-                }
-            }
+            due_date = self.sip_due_date(circ["due_date"].as_str())?;
         }
 
         let circ_lib_id = copy["circ_lib"].id()?;
@@ -87,11 +89,12 @@ impl Session {
 
         let mut hold_pickup_date_op: Option<String> = None;
         let mut hold_patron_barcode_op: Option<String> = None;
-        let mut hold_queue_length = 0;
-
-        if let Some(hold) = self.get_copy_hold(copy, &transit_op, copy_status)? {
-            hold_queue_length = 1; // copying SIPServer
+        let mut hold_patron_ident_op: Option<String> = None;
+        let mut hold_patron_name_op: Option<String> = None;
+        let copy_holds = holds::holds_for_copy(self.editor_mut(), copy.id()?)?;
+        let hold_queue_length = copy_holds.len();
 
+        if let Some(hold) = self.get_copy_hold(copy, &copy_holds, &transit_op, copy_status)? {
             dest_location = hold["pickup_lib"]["shortname"]
                 .as_str()
                 .unwrap()
@@ -99,12 +102,15 @@ impl Session {
 
             if let Some(date) = hold["shelf_expire_time"].as_str() {
                 let pu_date = date::parse_datetime(date)?;
-                hold_pickup_date_op = Some(sip2::util::sip_date_from_dt(&pu_date));
+                hold_pickup_date_op = Some(self.sip_date(&pu_date)?);
             }
 
             if let Some(bc) = hold["usr"]["card"]["barcode"].as_str() {
                 hold_patron_barcode_op = Some(bc.to_string());
             }
+
+            hold_patron_ident_op = self.hold_patron_identifier(&hold["usr"])?;
+            hold_patron_name_op = Some(self.format_user_name(&hold["usr"]));
         }
 
         let deposit_amount = copy["deposit_amount"].float()?;
@@ -116,21 +122,38 @@ impl Session {
             }
         }
 
-        let circ_status = self.circ_status(copy_status);
-        let media_type = copy["circ_modifier"]["sip2_media_type"]
-            .as_str()
-            .unwrap_or("001");
-        let magnetic_media = copy["circ_modifier"]["magnetic_media"].boolish();
+        // Circ modifiers come from the shared cache instead of
+        // fleshing them on every copy.
+        let circ_modifier = match copy["circ_modifier"].as_str() {
+            Some(code) => self
+                .lookup_table("ccm")?
+                .into_iter()
+                .find(|m| m["code"].as_str() == Some(code)),
+            None => None,
+        };
+        let circ_modifier = circ_modifier.unwrap_or(EgValue::Null);
+
+        let mapping = self.account().item_mapping();
+        let circ_status = mapping.circ_status(copy_status).to_string();
+        let media_type = mapping
+            .media_type(
+                copy["circ_modifier"].as_str(),
+                circ_modifier["sip2_media_type"].as_str(),
+            )
+            .to_string();
+        let magnetic_media = circ_modifier["magnetic_media"].boolish();
 
         let (title, _) = self.get_copy_title_author(&copy)?;
         let title = title.unwrap_or(String::new());
 
-        Ok(Some(Item {
+        let item = Item {
+            id: copy.id()?,
             barcode: barcode.to_string(),
             due_date,
             title,
             copy_status: copy_status,
             circ_lib: circ_lib_id,
+            owning_lib: copy["call_number"]["owning_lib"].id()?,
             deposit_amount,
             hold_queue_length,
             magnetic_media,
@@ -140,11 +163,22 @@ impl Session {
             permanent_loc: circ_lib.to_string(),
             destination_loc: dest_location,
             owning_loc: owning_lib.to_string(),
-            media_type: media_type.to_string(),
+            media_type,
             hold_pickup_date: hold_pickup_date_op,
             hold_patron_barcode: hold_patron_barcode_op,
+            hold_patron_ident: hold_patron_ident_op,
+            hold_patron_name: hold_patron_name_op,
             circ_patron_id,
-        }))
+            alert_message: copy["alert_message"]
+                .as_str()
+                .map(|m| m.trim())
+                .filter(|m| !m.is_empty())
+                .map(|m| m.to_string()),
+        };
+
+        self.field_values_mut().add_item(&item);
+
+        Ok(Some(item))
     }
 
     pub fn handle_item_info(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
@@ -165,10 +199,10 @@ impl Session {
         let mut resp = sip2::Message::from_values(
             &sip2::spec::M_ITEM_INFO_RESP,
             &[
-                item.circ_status,
+                &item.circ_status,
                 "02", // security marker
                 &item.fee_type,
-                &sip2::util::sip_date_now(),
+                &self.sip_date_now(),
             ],
             &[
                 ("AB", &item.barcode),
@@ -186,17 +220,116 @@ impl Session {
         .unwrap();
 
         resp.maybe_add_field("CM", item.hold_pickup_date.as_deref());
-        resp.maybe_add_field("CY", item.hold_patron_barcode.as_deref());
+        resp.maybe_add_field("CY", item.hold_patron_ident.as_deref());
+        resp.maybe_add_field("DA", item.hold_patron_name.as_deref());
         resp.maybe_add_field("AH", item.due_date.as_deref());
 
+        self.add_copy_alerts(&mut resp, &item, AlertEvent::ItemInfo)?;
+
+        Ok(resp)
+    }
+
+    /// Record an inventory scan of an item.
+    ///
+    /// The scan is logged against the workstation of the SIP account,
+    /// which becomes the item's last-seen location.
+    pub fn handle_item_status_update(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
+        let barcode = msg.get_field_value("AB").unwrap_or("");
+
+        log::info!("{self} Item Status Update {barcode}");
+
+        if !self.account().allow_item_status_update() {
+            log::warn!("{self} Item status updates are not allowed for this account");
+            return Ok(self.item_status_update_response(
+                None,
+                barcode,
+                Some("Item status updates are not allowed"),
+            ));
+        }
+
+        let item = match self.get_item_details(barcode)? {
+            Some(i) => i,
+            None => {
+                log::debug!("{self} No copy found with barcode: {barcode}");
+                return Ok(self.item_status_update_response(None, barcode, Some("Item not found")));
+            }
+        };
+
+        let ws_id = match self.editor().requestor_ws_id() {
+            Some(id) => id,
+            None => {
+                log::warn!("{self} Item status update requires an account workstation");
+                return Ok(self.item_status_update_response(
+                    Some(&item),
+                    barcode,
+                    Some("Item status update not available"),
+                ));
+            }
+        };
+
+        let inventory = eg::hash! {
+            copy: item.id,
+            inventory_workstation: ws_id,
+            inventory_date: "now",
+        };
+
+        let inventory = EgValue::create("aci", inventory)?;
+
+        {
+            let mut editor = self.editor_mut().xact_guard()?;
+
+            editor.create(inventory)?;
+            editor.commit()?;
+        }
+
+        let mut resp = self.item_status_update_response(Some(&item), barcode, None);
+
+        // Item properties (CH) are vendor-specific and not supported.
+        // The inventory scan is still recorded.
+        if msg.get_field_value("CH").is_some() {
+            log::info!("{self} Item properties for {barcode} were not applied");
+            resp.add_field("AF", &self.tr("Item properties were not applied"));
+        }
+
         Ok(resp)
     }
 
-    /// Find an active hold linked to the copy.  The copy must be on
-    /// the holds shelf or in transit to the holds shelf.
+    /// Item Status Update Response.  The update succeeded if no
+    /// screen message is provided.
+    fn item_status_update_response(
+        &self,
+        item: Option<&Item>,
+        barcode: &str,
+        screen_msg: Option<&str>,
+    ) -> sip2::Message {
+        let mut resp = sip2::Message::from_values(
+            &sip2::spec::M_ITEM_STATUS_UPDATE_RESP,
+            &[
+                sip2::util::num_bool(screen_msg.is_none()),
+                &self.sip_date_now(),
+            ],
+            &[("AB", barcode)],
+        )
+        .unwrap();
+
+        if let Some(item) = item {
+            resp.add_field("AJ", &item.title);
+            resp.add_field("AQ", &item.permanent_loc);
+        }
+
+        resp.maybe_add_field("AF", screen_msg.map(|m| self.tr(m)).as_deref());
+
+        resp
+    }
+
+    /// Find the hold captured for the copy.  The copy must be on the
+    /// holds shelf or in transit to the holds shelf.
+    ///
+    /// Captured holds sort first in the list of holds for a copy.
     fn get_copy_hold(
         &mut self,
         copy: &EgValue,
+        copy_holds: &[EgValue],
         transit: &Option<EgValue>,
         copy_status: i64,
     ) -> EgResult<Option<EgValue>> {
@@ -213,24 +346,21 @@ impl Session {
             }
         }
 
-        let copy_id = copy.id()?;
-
-        let search = eg::hash! {
-            current_copy: copy_id,
-            capture_time: {"!=": EgValue::Null},
-            cancel_time: EgValue::Null,
-            fulfillment_time: EgValue::Null,
+        let hold_id = match copy_holds.first() {
+            Some(h)
+                if !h["capture_time"].is_null() && h["current_copy"].as_int() == copy.id().ok() =>
+            {
+                h.id()?
+            }
+            _ => return Ok(None),
         };
 
         let flesh = eg::hash! {
-            limit: 1,
             flesh: 2,
             flesh_fields: {ahr: ["pickup_lib", "usr"], au: ["card"]},
         };
 
-        let mut holds = self.editor_mut().search_with_ops("ahr", search, flesh)?;
-
-        Ok(holds.pop())
+        self.editor_mut().retrieve_with_ops("ahr", hold_id, flesh)
     }
 
     /// Find the active transit for a copy if one exists.
@@ -257,21 +387,6 @@ impl Session {
         Ok(transits.pop())
     }
 
-    fn circ_status(&self, copy_status: i64) -> &'static str {
-        match copy_status {
-            C::COPY_STATUS_ON_ORDER => "02",
-            C::COPY_STATUS_AVAILABLE => "03",
-            C::COPY_STATUS_CHECKED_OUT => "04",
-            C::COPY_STATUS_IN_PROCESS => "06",
-            C::COPY_STATUS_ON_HOLDS_SHELF => "08",
-            C::COPY_STATUS_RESHELVING => "09",
-            C::COPY_STATUS_IN_TRANSIT => "10",
-            C::COPY_STATUS_LOST | C::COPY_STATUS_LOST_AND_PAID => "12",
-            C::COPY_STATUS_MISSING => "13",
-            _ => "01", // unknown
-        }
-    }
-
     /// Returns a basic response with an empty title, which indicates
     /// (to some SIP clients, at least) that the item was not found.
     fn return_item_not_found(&self, barcode: &str) -> sip2::Message {
@@ -283,7 +398,7 @@ impl Session {
                 "01", // circ status
                 "01", // security marker
                 "01", // fee type
-                &sip2::util::sip_date_now(),
+                &self.sip_date_now(),
             ],
             &[
                 ("AB", &barcode),
@@ -0,0 +1,207 @@
+//! Mapping of Evergreen copy data to SIP item codes.
+//!
+//! Copy statuses map to SIP circulation status codes (the first
+//! fixed field of the Item Information Response) and circulation
+//! modifiers map to CK media types.  The global mapping may be
+//! overridden per account.
+use eg::constants as C;
+use evergreen as eg;
+use std::collections::HashMap;
+
+/// SIP circulation status codes defined by the SIP2 spec.
+const SIP_CIRC_STATUSES: &[&str] = &[
+    "01", "02", "03", "04", "05", "06", "07", "08", "09", "10", "11", "12", "13",
+];
+
+/// SIP media type codes defined by the SIP2 spec.
+const SIP_MEDIA_TYPES: &[&str] = &[
+    "000", "001", "002", "003", "004", "005", "006", "007", "008", "009", "010",
+];
+
+/// Built-in copy status to SIP circulation status mapping.
+const DEFAULT_CIRC_STATUSES: &[(i64, &str)] = &[
+    (C::COPY_STATUS_ON_ORDER, "02"),
+    (C::COPY_STATUS_AVAILABLE, "03"),
+    (C::COPY_STATUS_CHECKED_OUT, "04"),
+    (C::COPY_STATUS_IN_PROCESS, "06"),
+    (C::COPY_STATUS_ON_HOLDS_SHELF, "08"),
+    (C::COPY_STATUS_RESHELVING, "09"),
+    (C::COPY_STATUS_IN_TRANSIT, "10"),
+    (C::COPY_STATUS_LOST, "12"),
+    (C::COPY_STATUS_LOST_AND_PAID, "12"),
+    (C::COPY_STATUS_MISSING, "13"),
+];
+
+/// Circulation status for copy statuses with no mapping: "other".
+const DEFAULT_CIRC_STATUS: &str = "01";
+
+/// Media type for copies with no mapping: "book".
+const DEFAULT_MEDIA_TYPE: &str = "001";
+
+fn check_circ_status(code: &str) -> Result<(), String> {
+    if SIP_CIRC_STATUSES.contains(&code) {
+        Ok(())
+    } else {
+        Err(format!("Invalid SIP circulation status: '{code}'"))
+    }
+}
+
+fn check_media_type(code: &str) -> Result<(), String> {
+    if SIP_MEDIA_TYPES.contains(&code) {
+        Ok(())
+    } else {
+        Err(format!("Invalid SIP media type: '{code}'"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ItemMapping {
+    circ_statuses: HashMap<i64, String>,
+    media_types: HashMap<String, String>,
+    default_circ_status: String,
+    default_media_type: String,
+}
+
+impl Default for ItemMapping {
+    fn default() -> Self {
+        ItemMapping {
+            circ_statuses: DEFAULT_CIRC_STATUSES
+                .iter()
+                .map(|(stat, code)| (*stat, code.to_string()))
+                .collect(),
+            media_types: HashMap::new(),
+            default_circ_status: DEFAULT_CIRC_STATUS.to_string(),
+            default_media_type: DEFAULT_MEDIA_TYPE.to_string(),
+        }
+    }
+}
+
+impl ItemMapping {
+    /// Apply an item-mapping config block on top of our current values.
+    ///
+    /// ```yaml
+    /// item-mapping:
+    ///   default-circ-status: "01"
+    ///   default-media-type: "001"
+    ///   circ-statuses:
+    ///     - copy-status: 15
+    ///       sip-status: "08"
+    ///   media-types:
+    ///     - circ-modifier: "CD"
+    ///       sip-media-type: "006"
+    /// ```
+    pub fn apply_yaml(&mut self, node: &yaml_rust::Yaml) -> Result<(), String> {
+        if let Some(code) = node["default-circ-status"].as_str() {
+            check_circ_status(code)?;
+            self.default_circ_status = code.to_string();
+        }
+
+        if let Some(code) = node["default-media-type"].as_str() {
+            check_media_type(code)?;
+            self.default_media_type = code.to_string();
+        }
+
+        if let Some(entries) = node["circ-statuses"].as_vec() {
+            for entry in entries {
+                let stat = entry["copy-status"].as_i64().ok_or_else(|| {
+                    format!("Circ status mapping requires a copy-status: {entry:?}")
+                })?;
+
+                let code = entry["sip-status"].as_str().ok_or_else(|| {
+                    format!("Circ status mapping requires a sip-status: {entry:?}")
+                })?;
+
+                self.set_circ_status(stat, code)?;
+            }
+        }
+
+        if let Some(entries) = node["media-types"].as_vec() {
+            for entry in entries {
+                let modifier = entry["circ-modifier"].as_str().ok_or_else(|| {
+                    format!("Media type mapping requires a circ-modifier: {entry:?}")
+                })?;
+
+                let code = entry["sip-media-type"].as_str().ok_or_else(|| {
+                    format!("Media type mapping requires a sip-media-type: {entry:?}")
+                })?;
+
+                self.set_media_type(modifier, code)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_circ_status(&mut self, copy_status: i64, code: &str) -> Result<(), String> {
+        check_circ_status(code)?;
+        self.circ_statuses.insert(copy_status, code.to_string());
+        Ok(())
+    }
+
+    pub fn set_media_type(&mut self, circ_modifier: &str, code: &str) -> Result<(), String> {
+        check_media_type(code)?;
+        self.media_types
+            .insert(circ_modifier.to_string(), code.to_string());
+        Ok(())
+    }
+
+    /// SIP circulation status for a copy status.
+    pub fn circ_status(&self, copy_status: i64) -> &str {
+        self.circ_statuses
+            .get(&copy_status)
+            .unwrap_or(&self.default_circ_status)
+    }
+
+    /// SIP media type for a circulation modifier.
+    ///
+    /// * `db_media_type` - The sip2_media_type of the circulation
+    ///   modifier, used when the modifier has no configured mapping.
+    pub fn media_type<'a>(
+        &'a self,
+        circ_modifier: Option<&str>,
+        db_media_type: Option<&'a str>,
+    ) -> &'a str {
+        circ_modifier
+            .and_then(|m| self.media_types.get(m))
+            .map(|m| m.as_str())
+            .or(db_media_type)
+            .unwrap_or(&self.default_media_type)
+    }
+}
+
+#[test]
+fn test_item_mapping() {
+    let mut mapping = ItemMapping::default();
+
+    assert_eq!(mapping.circ_status(C::COPY_STATUS_AVAILABLE), "03");
+    assert_eq!(mapping.circ_status(C::COPY_STATUS_ON_RESV_SHELF), "01");
+    assert_eq!(mapping.media_type(Some("CD"), None), "001");
+    assert_eq!(mapping.media_type(Some("CD"), Some("002")), "002");
+
+    let yaml = yaml_rust::YamlLoader::load_from_str(
+        r#"
+        default-circ-status: "13"
+        circ-statuses:
+          - copy-status: 15
+            sip-status: "08"
+        media-types:
+          - circ-modifier: "CD"
+            sip-media-type: "006"
+        "#,
+    )
+    .unwrap()
+    .remove(0);
+
+    mapping.apply_yaml(&yaml).unwrap();
+
+    assert_eq!(mapping.circ_status(C::COPY_STATUS_ON_RESV_SHELF), "08");
+    assert_eq!(mapping.circ_status(C::COPY_STATUS_DAMAGED), "13");
+    assert_eq!(mapping.circ_status(C::COPY_STATUS_AVAILABLE), "03");
+    assert_eq!(mapping.media_type(Some("CD"), Some("002")), "006");
+    assert_eq!(mapping.media_type(Some("DVD"), None), "001");
+
+    assert!(mapping.set_circ_status(1, "14").is_err());
+    assert!(mapping.set_circ_status(1, "4").is_err());
+    assert!(mapping.set_media_type("CD", "011").is_err());
+    assert!(mapping.set_media_type("CD", "06").is_err());
+}
@@ -0,0 +1,244 @@
+//! Length limits for SIP response fields.
+//!
+//! SIP clients declare a max print width in their SC Status request.
+//! Some clients crash or wrap badly when print lines (AG) or screen
+//! messages (AF) exceed it.  Other variable-length fields are limited
+//! to the 255 characters allowed by the SIP2 specification.
+//!
+//! Lengths are counted in grapheme clusters, so values are never cut
+//! in the middle of a character.
+use super::session::Session;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Max length of a variable-length field per the SIP2 specification.
+pub const SIP_MAX_FIELD_LENGTH: usize = 255;
+
+/// Screen message field.
+const SCREEN_MESSAGE: &str = "AF";
+
+/// Print line field.
+const PRINT_LINE: &str = "AG";
+
+/// What to do with print lines which exceed the client's print width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrintLineOverflow {
+    /// Discard the remainder of the line.
+    Truncate,
+    /// Continue the remainder of the line in additional AG fields.
+    Split,
+}
+
+impl From<&str> for PrintLineOverflow {
+    fn from(s: &str) -> PrintLineOverflow {
+        match s.to_lowercase().as_str() {
+            "split" => Self::Split,
+            _ => Self::Truncate,
+        }
+    }
+}
+
+/// Returns the leading portion of the value containing at most
+/// max_len graphemes.
+pub fn truncate_graphemes(value: &str, max_len: usize) -> &str {
+    match value.grapheme_indices(true).nth(max_len) {
+        Some((idx, _)) => &value[..idx],
+        None => value,
+    }
+}
+
+/// Split a value into lines of at most max_len graphemes.
+///
+/// Lines are broken at whitespace where possible.  Whitespace at a
+/// line break is discarded.
+pub fn split_graphemes(value: &str, max_len: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if max_len == 0 {
+        return lines;
+    }
+
+    let graphemes: Vec<&str> = value.graphemes(true).collect();
+    let mut start = 0;
+
+    while start < graphemes.len() {
+        if graphemes.len() - start <= max_len {
+            lines.push(graphemes[start..].concat());
+            break;
+        }
+
+        let window = &graphemes[start..start + max_len + 1];
+
+        // Break at the last whitespace that leaves a non-empty line.
+        let (end, next) = match window.iter().rposition(|g| g.trim().is_empty()) {
+            Some(pos) if pos > 0 => (start + pos, start + pos + 1),
+            _ => (start + max_len, start + max_len),
+        };
+
+        lines.push(graphemes[start..end].concat().trim_end().to_string());
+
+        start = next;
+        while start < graphemes.len() && graphemes[start].trim().is_empty() {
+            start += 1;
+        }
+    }
+
+    lines
+}
+
+/// Apply print width and field length limits to a response.
+///
+/// When set, print_width applies to AF and AG fields.
+pub fn apply_length_limits(
+    resp: &mut sip2::Message,
+    print_width: Option<usize>,
+    overflow: PrintLineOverflow,
+) {
+    let mut fields = Vec::with_capacity(resp.fields().len());
+
+    for field in resp.fields_mut().drain(..) {
+        let code = field.code();
+        let value = field.value();
+
+        let max_len = match print_width {
+            Some(w) if code == SCREEN_MESSAGE || code == PRINT_LINE => w.min(SIP_MAX_FIELD_LENGTH),
+            _ => SIP_MAX_FIELD_LENGTH,
+        };
+
+        if value.graphemes(true).nth(max_len).is_none() {
+            // Already fits.
+            fields.push(sip2::Field::new(code, value));
+            continue;
+        }
+
+        if code == PRINT_LINE && overflow == PrintLineOverflow::Split {
+            for line in split_graphemes(value, max_len) {
+                fields.push(sip2::Field::new(code, &line));
+            }
+        } else {
+            fields.push(sip2::Field::new(code, truncate_graphemes(value, max_len)));
+        }
+    }
+
+    *resp.fields_mut() = fields;
+}
+
+impl Session {
+    /// Apply our client's print width and the SIP field length limits
+    /// to a response before it's sent.
+    pub fn apply_response_length_limits(&self, resp: &mut sip2::Message) {
+        let overflow = match self.has_account() {
+            true => self.account().settings().print_line_overflow(),
+            false => PrintLineOverflow::Truncate,
+        };
+
+        apply_length_limits(resp, self.max_print_width(), overflow);
+    }
+}
+
+#[cfg(test)]
+fn overdue_response(message: &str) -> sip2::Message {
+    let mut resp = sip2::Message::from_values(
+        &sip2::spec::M_CHECKOUT_RESP,
+        &["0", "N", "N", "N", &sip2::util::sip_date_now()],
+        &[("AO", "example"), ("AA", "patron"), ("AB", "item")],
+    )
+    .unwrap();
+
+    resp.add_field("AF", message);
+    resp.add_field("AG", message);
+    resp
+}
+
+#[test]
+fn test_truncate_graphemes() {
+    assert_eq!(truncate_graphemes("abcdef", 3), "abc");
+    assert_eq!(truncate_graphemes("abc", 10), "abc");
+    assert_eq!(truncate_graphemes("", 10), "");
+
+    // "e" followed by a combining acute accent is one grapheme.
+    assert_eq!(
+        truncate_graphemes("Les Mise\u{301}rables", 8),
+        "Les Mise\u{301}"
+    );
+    assert_eq!(truncate_graphemes("Война и мир", 5), "Война");
+    assert_eq!(truncate_graphemes("吾輩は猫である", 2), "吾輩");
+}
+
+#[test]
+fn test_split_graphemes() {
+    assert_eq!(
+        split_graphemes("The quick brown fox", 10),
+        vec!["The quick", "brown fox"]
+    );
+    assert_eq!(split_graphemes("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(
+        split_graphemes("吾輩は猫である", 3),
+        vec!["吾輩は", "猫であ", "る"]
+    );
+    assert!(split_graphemes("", 10).is_empty());
+}
+
+#[test]
+fn test_overdue_message_width_40() {
+    let message = "This item is overdue. Please return it to the library as soon as \
+        possible. Overdue fines accrue daily until the item is returned, and your \
+        borrowing privileges may be suspended if fines exceed limits.";
+
+    assert_eq!(message.chars().count(), 200);
+
+    let mut resp = overdue_response(message);
+    apply_length_limits(&mut resp, Some(40), PrintLineOverflow::Truncate);
+
+    assert_eq!(
+        resp.get_field_value("AF"),
+        Some("This item is overdue. Please return it t")
+    );
+    assert_eq!(resp.get_field_value("AG"), resp.get_field_value("AF"));
+    assert_eq!(resp.fields().len(), 5);
+
+    let mut resp = overdue_response(message);
+    apply_length_limits(&mut resp, Some(40), PrintLineOverflow::Split);
+
+    // Screen messages are always truncated.
+    assert_eq!(resp.get_field_value("AF").unwrap().chars().count(), 40);
+
+    let lines: Vec<&str> = resp
+        .fields()
+        .iter()
+        .filter(|f| f.code() == "AG")
+        .map(|f| f.value())
+        .collect();
+
+    assert!(lines.len() >= 5);
+    assert!(lines.iter().all(|l| l.chars().count() <= 40));
+    assert_eq!(lines[0], "This item is overdue. Please return it");
+    assert_eq!(lines.join(" "), message);
+
+    // Other fields are untouched.
+    assert_eq!(resp.get_field_value("AA"), Some("patron"));
+}
+
+#[test]
+fn test_spec_max_field_length() {
+    let long = "x".repeat(300);
+
+    let mut resp = overdue_response("short");
+    resp.add_field("AJ", &long);
+
+    apply_length_limits(&mut resp, None, PrintLineOverflow::Split);
+
+    assert_eq!(
+        resp.get_field_value("AJ").unwrap().len(),
+        SIP_MAX_FIELD_LENGTH
+    );
+    assert_eq!(resp.get_field_value("AF"), Some("short"));
+
+    // A wide print width does not exceed the spec limit.
+    let mut resp = overdue_response(&long);
+    apply_length_limits(&mut resp, Some(999), PrintLineOverflow::Truncate);
+
+    assert_eq!(
+        resp.get_field_value("AG").unwrap().len(),
+        SIP_MAX_FIELD_LENGTH
+    );
+}
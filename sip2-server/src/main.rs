@@ -1,24 +1,107 @@
 use evergreen as eg;
 use mptc;
 use std::env;
+use std::io::BufRead;
 use std::path::Path;
 
+mod access;
+mod admin;
+mod audit;
+mod cache;
 mod checkin;
 mod checkout;
 mod conf;
+mod confload;
+mod copyalert;
+mod custom;
+mod i18n;
 mod item;
+mod itemmap;
+mod limits;
+mod offline;
+mod password;
 mod patron;
 mod payment;
+mod ratelimit;
+mod register;
+mod registry;
 mod server;
 mod session;
+mod shutdown;
+mod sipdate;
+mod stats;
+mod tls;
 mod util;
+mod version;
 
 const DEFAULT_CONFIG_1: &str = "/usr/local/etc/eg-sip2-server.yml";
 const DEFAULT_CONFIG_2: &str = "./sip2-server/conf/eg-sip2-server.yml";
 const DEFAULT_CONFIG_3: &str = "/usr/local/etc/eg-sip2-server.example.yml";
 const DEFAULT_CONFIG_4: &str = "./sip2-server/conf/eg-sip2-server.example.yml";
 
+/// Read a password from STDIN and print its hash, suitable for use
+/// as a sip-password-hash config value.
+fn print_password_hash() {
+    eprintln!("Enter the SIP account password:");
+
+    let mut line = String::new();
+    if let Err(e) = std::io::stdin().lock().read_line(&mut line) {
+        eprintln!("Cannot read password: {e}");
+        std::process::exit(1);
+    }
+
+    let plain = line.trim_end_matches(['\r', '\n']);
+
+    if plain.is_empty() {
+        eprintln!("Password cannot be empty");
+        std::process::exit(1);
+    }
+
+    match password::hash_password(plain) {
+        Ok(hash) => println!("{hash}"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Validate the config file, print any problems, and exit.
+///
+/// Account ILS usernames and workstations are verified when
+/// Evergreen is reachable.
+fn check_config(config_file: &str) -> ! {
+    let problems = match eg::init() {
+        Ok(client) => {
+            let mut editor = eg::Editor::new(&client);
+            conf::Config::check_yaml(config_file, Some(&mut editor))
+        }
+        Err(e) => {
+            eprintln!("Cannot connect to Evergreen; skipping ILS checks: {e}");
+            conf::Config::check_yaml(config_file, None)
+        }
+    };
+
+    if problems.is_empty() {
+        println!("{config_file}: OK");
+        std::process::exit(0);
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    eprintln!("{config_file}: {} problem(s) found", problems.len());
+
+    std::process::exit(1);
+}
+
 fn main() {
+    if env::args().any(|a| a == "--hash-password") {
+        print_password_hash();
+        return;
+    }
+
     let file_op = env::var("EG_SIP2_SERVER_CONFIG");
 
     let config_file = if let Ok(ref file) = file_op {
@@ -35,11 +118,15 @@ fn main() {
         panic!("No viable SIP2 Server Configuration Found");
     };
 
+    if env::args().any(|a| a == "--check-config") {
+        check_config(config_file);
+    }
+
     let ctx = eg::init().expect("Evergreen Init");
 
     log::info!("SIP2 Server starting with config {config_file}");
 
-    let stream = match server::Server::setup(config_file, ctx) {
+    let mut stream = match server::Server::setup(config_file, ctx) {
         Ok(s) => s,
         Err(e) => {
             log::error!("SIP Server exited with error: {e}");
@@ -52,11 +139,17 @@ fn main() {
     let min_workers = stream.sip_config().min_workers();
     let max_worker_requests = stream.sip_config().max_worker_requests();
 
+    let admin_listener = stream.take_admin_listener();
+
     let mut s = mptc::Server::new(Box::new(stream));
 
     s.set_max_workers(max_workers);
     s.set_min_workers(min_workers);
     s.set_max_worker_requests(max_worker_requests);
 
+    if let Some(admin) = admin_listener {
+        admin.spawn(s.signal_tracker());
+    }
+
     s.run();
 }
@@ -0,0 +1,901 @@
+//! Offline transactions.
+//!
+//! When Evergreen cannot be reached, checkouts and renewals are
+//! appended to a local journal, one JSON object per line, and the SIP
+//! client is told they succeeded.  Item and patron lookups report what
+//! little we know.  Other requests get an ok=0 response.
+//!
+//! The journal is replayed against Evergreen once it can be reached
+//! again, either periodically by a background thread or on request
+//! via the status listener.  The byte offset of the last processed
+//! entry is stored alongside the journal, so entries are replayed
+//! once, even across server restarts.  Entries Evergreen rejects are
+//! moved to a dead-letter file for staff review.
+use super::session::Session;
+use eg::common::auth;
+use eg::common::auth::Session as AuthSession;
+use eg::date;
+use eg::event::EgEvent;
+use eg::result::{EgError, EgResult};
+use eg::EgValue;
+use evergreen as eg;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_LOAN_PERIOD: &str = "14 days";
+
+pub const DEFAULT_SCREEN_MSG: &str =
+    "Offline checkout.  This item will be checked out to you when the system is available.";
+
+/// Screen message for requests we cannot answer offline.
+pub const UNAVAILABLE_SCREEN_MSG: &str =
+    "This request is not available while the system is offline.  Please see staff.";
+
+/// Seconds between automatic journal replays.
+pub const DEFAULT_REPLAY_INTERVAL: u64 = 60;
+
+/// Once a Session fails to reconnect to OpenSRF, it answers requests
+/// offline for this long before trying to reconnect again.
+pub const OFFLINE_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Seconds to wait for each Evergreen API call during replay.
+const REPLAY_API_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the replay thread checks for shutdown.
+const REPLAY_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+const CHECKOUT_METHOD: &str = "open-ils.circ.checkout.full.override";
+const RENEW_METHOD: &str = "open-ils.circ.renew.override";
+
+#[derive(Debug, Clone)]
+pub struct OfflineConfig {
+    journal: String,
+    dead_letter: String,
+    loan_period: String,
+    screen_msg: String,
+    replay_interval: u64,
+}
+
+impl OfflineConfig {
+    pub fn new(journal: &str) -> Self {
+        OfflineConfig {
+            journal: journal.to_string(),
+            dead_letter: format!("{journal}.failed"),
+            loan_period: DEFAULT_LOAN_PERIOD.to_string(),
+            screen_msg: DEFAULT_SCREEN_MSG.to_string(),
+            replay_interval: DEFAULT_REPLAY_INTERVAL,
+        }
+    }
+
+    /// Parse the offline config section.
+    ///
+    /// Returns None if offline mode is not enabled.
+    pub fn from_yaml(node: &yaml_rust::Yaml) -> Result<Option<Self>, String> {
+        if !node["enabled"].as_bool().unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let journal = node["journal"]
+            .as_str()
+            .ok_or_else(|| "offline mode requires a journal file".to_string())?;
+
+        let mut conf = OfflineConfig::new(journal);
+
+        if let Some(v) = node["dead-letter"].as_str() {
+            conf.dead_letter = v.to_string();
+        }
+
+        if let Some(v) = node["loan-period"].as_str() {
+            date::interval_to_seconds(v)
+                .map_err(|e| format!("Invalid offline loan-period '{v}': {e}"))?;
+            conf.loan_period = v.to_string();
+        }
+
+        if let Some(v) = node["screen-message"].as_str() {
+            conf.screen_msg = v.to_string();
+        }
+
+        if let Some(v) = node["replay-interval"].as_i64() {
+            conf.replay_interval = v.max(0) as u64;
+        }
+
+        Ok(Some(conf))
+    }
+
+    /// Path to the journal of offline transactions.
+    pub fn journal(&self) -> &str {
+        &self.journal
+    }
+    /// Path to the file of journal entries which failed to replay.
+    pub fn dead_letter(&self) -> &str {
+        &self.dead_letter
+    }
+    /// Path to the file which stores the replay position.
+    pub fn offset_file(&self) -> String {
+        format!("{}.offset", self.journal)
+    }
+    /// Offline checkouts are due this long after checkout.
+    pub fn loan_period(&self) -> &str {
+        &self.loan_period
+    }
+    /// AF message added to offline responses.
+    pub fn screen_msg(&self) -> &str {
+        &self.screen_msg
+    }
+    /// Seconds between automatic replays.  0 means replay only on
+    /// request.
+    pub fn replay_interval(&self) -> u64 {
+        self.replay_interval
+    }
+}
+
+/// A checkout or renewal performed while Evergreen was unreachable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineEntry {
+    pub timestamp: String,
+    /// SIP account username.
+    pub account: String,
+    /// Replay logs in as this ILS user.
+    pub ils_username: String,
+    pub workstation: Option<String>,
+    pub patron_barcode: String,
+    pub item_barcode: String,
+    /// ISO8601 due date reported to the SIP client.
+    pub due_date: String,
+    /// If the item is already checked out to the patron, renew it.
+    pub renew_ok: bool,
+    /// Renew the item instead of checking it out.
+    pub renewal: bool,
+}
+
+impl OfflineEntry {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            timestamp: self.timestamp.as_str(),
+            account: self.account.as_str(),
+            ils_username: self.ils_username.as_str(),
+            workstation: self.workstation.as_deref(),
+            patron_barcode: self.patron_barcode.as_str(),
+            item_barcode: self.item_barcode.as_str(),
+            due_date: self.due_date.as_str(),
+            renew_ok: self.renew_ok,
+            renewal: self.renewal,
+        }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Result<Self, String> {
+        let string = |key: &str| {
+            value[key]
+                .as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| format!("Offline entry has no {key}: {}", value.dump()))
+        };
+
+        Ok(OfflineEntry {
+            timestamp: string("timestamp")?,
+            account: string("account")?,
+            ils_username: string("ils_username")?,
+            workstation: value["workstation"].as_str().map(|s| s.to_string()),
+            patron_barcode: string("patron_barcode")?,
+            item_barcode: string("item_barcode")?,
+            due_date: string("due_date")?,
+            renew_ok: value["renew_ok"].as_bool().unwrap_or(false),
+            renewal: value["renewal"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// Result of replaying a single journal entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayOutcome {
+    Success,
+    /// Evergreen rejected the entry.  It will not be tried again.
+    Failed(String),
+    /// Evergreen could not be reached.  Stop and try again later.
+    Retry(String),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplaySummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Set if the replay stopped early because Evergreen could not
+    /// be reached.
+    pub interrupted: Option<String>,
+}
+
+impl ReplaySummary {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            succeeded: self.succeeded,
+            failed: self.failed,
+            interrupted: self.interrupted.as_deref(),
+        }
+    }
+}
+
+/// Offline transaction journal.  Shared by all Sessions.
+pub struct OfflineJournal {
+    config: OfflineConfig,
+
+    /// Serializes journal appends.
+    append_lock: Mutex<()>,
+
+    /// Only one replay runs at a time.
+    replay_lock: Mutex<()>,
+}
+
+impl OfflineJournal {
+    pub fn new(config: OfflineConfig) -> Result<Self, String> {
+        let journal = OfflineJournal {
+            config,
+            append_lock: Mutex::new(()),
+            replay_lock: Mutex::new(()),
+        };
+
+        // Verify the journal is writable at startup.
+        journal.open_append(journal.config.journal())?;
+
+        Ok(journal)
+    }
+
+    pub fn config(&self) -> &OfflineConfig {
+        &self.config
+    }
+
+    fn open_append(&self, path: &str) -> Result<fs::File, String> {
+        fs::File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open offline journal {path}: {e}"))
+    }
+
+    /// Add an entry to the journal.
+    ///
+    /// The entry is flushed to disk before we return, since the SIP
+    /// client will be told the checkout succeeded.
+    pub fn append(&self, entry: &OfflineEntry) -> Result<(), String> {
+        let _lock = self.append_lock.lock().unwrap();
+        let path = self.config.journal();
+
+        let mut file = self.open_append(path)?;
+
+        file.write_all(format!("{}\n", entry.to_json().dump()).as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Cannot write offline journal {path}: {e}"))
+    }
+
+    /// Byte offset of the first unprocessed journal entry.
+    fn read_offset(&self) -> u64 {
+        fs::read_to_string(self.config.offset_file())
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(0)
+    }
+
+    /// Store the replay offset.
+    ///
+    /// Writes a temp file and renames it over the offset file, so
+    /// the stored offset is never partially written.
+    fn write_offset(&self, offset: u64) -> Result<(), String> {
+        let path = self.config.offset_file();
+        let tmp = format!("{path}.tmp");
+
+        fs::write(&tmp, format!("{offset}\n"))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| format!("Cannot write offline offset file {path}: {e}"))
+    }
+
+    fn dead_letter(&self, line: &str, error: &str) -> Result<(), String> {
+        let path = self.config.dead_letter();
+        let mut file = self.open_append(path)?;
+
+        let entry = json::object! {
+            failed: date::to_iso(&date::now()),
+            error: error,
+            entry: line,
+        };
+
+        file.write_all(format!("{}\n", entry.dump()).as_bytes())
+            .map_err(|e| format!("Cannot write offline dead-letter file {path}: {e}"))
+    }
+
+    /// Journal lines after the replay offset, each paired with the
+    /// offset following the line.
+    ///
+    /// A final line with no newline may still be being written and
+    /// is left for the next replay.
+    fn pending(&self, offset: u64) -> Result<Vec<(String, u64)>, String> {
+        let path = self.config.journal();
+
+        let mut file = match fs::File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Cannot read offline journal {path}: {e}")),
+        };
+
+        let mut text = String::new();
+
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_to_string(&mut text))
+            .map_err(|e| format!("Cannot read offline journal {path}: {e}"))?;
+
+        let mut lines = Vec::new();
+        let mut end = offset;
+
+        for line in text.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                break;
+            }
+            end += line.len() as u64;
+            lines.push((line.trim_end().to_string(), end));
+        }
+
+        Ok(lines)
+    }
+
+    /// Number of journal entries waiting to be replayed.
+    pub fn pending_count(&self) -> Result<usize, String> {
+        Ok(self.pending(self.valid_offset()?)?.len())
+    }
+
+    /// The stored offset, reset to 0 if the journal has been replaced
+    /// with a shorter file.
+    fn valid_offset(&self) -> Result<u64, String> {
+        let offset = self.read_offset();
+
+        let len = match fs::metadata(self.config.journal()) {
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        };
+
+        Ok(if offset > len { 0 } else { offset })
+    }
+
+    /// Replay unprocessed entries in journal order.
+    ///
+    /// The offset is advanced after each processed entry, so an
+    /// interrupted replay resumes where it left off.
+    pub fn replay<F>(&self, mut handler: F) -> Result<ReplaySummary, String>
+    where
+        F: FnMut(&OfflineEntry) -> ReplayOutcome,
+    {
+        let _lock = self.replay_lock.lock().unwrap();
+
+        let mut summary = ReplaySummary::default();
+
+        for (line, next_offset) in self.pending(self.valid_offset()?)? {
+            if line.is_empty() {
+                self.write_offset(next_offset)?;
+                continue;
+            }
+
+            let outcome = match json::parse(&line) {
+                Ok(v) => match OfflineEntry::from_json(&v) {
+                    Ok(entry) => handler(&entry),
+                    Err(e) => ReplayOutcome::Failed(e),
+                },
+                Err(e) => ReplayOutcome::Failed(format!("Invalid journal entry: {e}")),
+            };
+
+            match outcome {
+                ReplayOutcome::Success => {
+                    log::info!("Offline replay succeeded: {line}");
+                    summary.succeeded += 1;
+                }
+                ReplayOutcome::Failed(e) => {
+                    log::warn!("Offline replay failed: {e}: {line}");
+                    self.dead_letter(&line, &e)?;
+                    summary.failed += 1;
+                }
+                ReplayOutcome::Retry(e) => {
+                    log::warn!("Offline replay interrupted: {e}");
+                    summary.interrupted = Some(e);
+                    break;
+                }
+            }
+
+            self.write_offset(next_offset)?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Replay unprocessed entries against Evergreen.
+    pub fn replay_to_evergreen(&self, client: &eg::Client) -> Result<ReplaySummary, String> {
+        let mut replayer = Replayer::new(client);
+        let summary = self.replay(|entry| replayer.replay(entry));
+        replayer.logout();
+        summary
+    }
+
+    /// Replay the journal every replay-interval seconds until the
+    /// provided function says to stop.
+    pub fn spawn_replay_thread(self: Arc<Self>, should_stop: impl Fn() -> bool + Send + 'static) {
+        let interval = self.config.replay_interval();
+        if interval == 0 {
+            return;
+        }
+
+        thread::spawn(move || {
+            let interval = Duration::from_secs(interval);
+            let mut client: Option<eg::Client> = None;
+            let mut last_run = Instant::now();
+
+            while !should_stop() {
+                thread::sleep(REPLAY_POLL_INTERVAL);
+
+                if last_run.elapsed() < interval {
+                    continue;
+                }
+
+                last_run = Instant::now();
+
+                match self.pending_count() {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("{e}");
+                        continue;
+                    }
+                }
+
+                if client.is_none() {
+                    match eg::Client::connect() {
+                        Ok(c) => client = Some(c),
+                        Err(e) => {
+                            log::info!("Offline replay cannot connect to OpenSRF: {e}");
+                            continue;
+                        }
+                    }
+                }
+
+                match self.replay_to_evergreen(client.as_ref().unwrap()) {
+                    Ok(summary) => {
+                        log::info!("Offline replay: {}", summary.to_json().dump());
+
+                        if summary.interrupted.is_some() {
+                            // Reconnect on the next run.
+                            client = None;
+                        }
+                    }
+                    Err(e) => log::error!("Offline replay failed: {e}"),
+                }
+            }
+
+            log::debug!("Offline replay thread exiting");
+        });
+    }
+}
+
+/// Replays journal entries via the Evergreen circulation APIs.
+struct Replayer<'a> {
+    client: &'a eg::Client,
+
+    /// Auth tokens keyed on ILS username and workstation.
+    tokens: HashMap<(String, Option<String>), String>,
+}
+
+impl<'a> Replayer<'a> {
+    fn new(client: &'a eg::Client) -> Self {
+        Replayer {
+            client,
+            tokens: HashMap::new(),
+        }
+    }
+
+    fn replay(&mut self, entry: &OfflineEntry) -> ReplayOutcome {
+        let result = if entry.renewal {
+            self.renew(entry)
+        } else {
+            self.checkout(entry)
+        };
+
+        match result {
+            Ok(outcome) => outcome,
+            Err(e) if e.is_transport() || e.is_timeout() => ReplayOutcome::Retry(e.to_string()),
+            Err(e) => ReplayOutcome::Failed(e.to_string()),
+        }
+    }
+
+    fn checkout(&mut self, entry: &OfflineEntry) -> EgResult<ReplayOutcome> {
+        let authtoken = self.authtoken(entry)?;
+
+        let args = eg::hash! {
+            copy_barcode: entry.item_barcode.as_str(),
+            patron_barcode: entry.patron_barcode.as_str(),
+            checkout_time: entry.timestamp.as_str(),
+            due_date: entry.due_date.as_str(),
+        };
+
+        let evt = self.call(CHECKOUT_METHOD, &authtoken, args)?;
+
+        if evt.is_success() {
+            return Ok(ReplayOutcome::Success);
+        }
+
+        if evt.textcode() == "OPEN_CIRCULATION_EXISTS" && entry.renew_ok {
+            return self.renew(entry);
+        }
+
+        Ok(ReplayOutcome::Failed(format!("Checkout failed: {evt}")))
+    }
+
+    fn renew(&mut self, entry: &OfflineEntry) -> EgResult<ReplayOutcome> {
+        let authtoken = self.authtoken(entry)?;
+
+        let args = eg::hash! {
+            copy_barcode: entry.item_barcode.as_str(),
+            patron_barcode: entry.patron_barcode.as_str(),
+        };
+
+        let evt = self.call(RENEW_METHOD, &authtoken, args)?;
+
+        if evt.is_success() {
+            return Ok(ReplayOutcome::Success);
+        }
+
+        Ok(ReplayOutcome::Failed(format!("Renewal failed: {evt}")))
+    }
+
+    fn call(&self, method: &str, authtoken: &str, args: EgValue) -> EgResult<EgEvent> {
+        let params = vec![EgValue::from(authtoken), args];
+
+        let resp = self
+            .client
+            .send_recv_one_with_timeout("open-ils.circ", method, params, REPLAY_API_TIMEOUT)?
+            .ok_or_else(|| EgError::Timeout(format!("API call {method} returned no response")))?;
+
+        let mut events = EgEvent::parse_array(&resp)
+            .ok_or_else(|| format!("API call {method} failed to return an event"))?;
+
+        // Report the first failure, if any.
+        let pos = events.iter().position(|e| !e.is_success()).unwrap_or(0);
+
+        Ok(events.remove(pos))
+    }
+
+    /// Login as the entry's ILS user, reusing tokens from earlier
+    /// entries in the same replay.
+    fn authtoken(&mut self, entry: &OfflineEntry) -> EgResult<String> {
+        let key = (entry.ils_username.clone(), entry.workstation.clone());
+
+        if let Some(token) = self.tokens.get(&key) {
+            return Ok(token.to_string());
+        }
+
+        let mut editor = eg::Editor::new(self.client);
+
+        let search = eg::hash! {usrname: entry.ils_username.as_str(), deleted: "f"};
+
+        let user_id = match editor.search("au", search)?.first() {
+            Some(u) => u.id()?,
+            None => return Err(format!("No such user: {}", entry.ils_username).into()),
+        };
+
+        let mut args = auth::InternalLoginArgs::new(user_id, auth::LoginType::Staff);
+        args.workstation = entry.workstation.clone();
+
+        let token = match AuthSession::internal_session_api(self.client, &args)? {
+            Some(s) => s.token().to_string(),
+            None => return Err("Internal Login failed".into()),
+        };
+
+        self.tokens.insert(key, token.clone());
+
+        Ok(token)
+    }
+
+    fn logout(&self) {
+        for token in self.tokens.values() {
+            AuthSession::logout(self.client, token).ok();
+        }
+    }
+}
+
+impl Session {
+    /// Answer a SIP request without Evergreen.
+    ///
+    /// Checkouts and renewals are added to the offline journal.  Other
+    /// requests we cannot answer offline get an ok=0 response.  Returns
+    /// None if offline transactions are disabled or the message type
+    /// has no such response.
+    pub fn offline_response(&self, msg: &sip2::Message) -> EgResult<Option<sip2::Message>> {
+        let journal = match self.offline_journal() {
+            Some(j) => j,
+            None => return Ok(None),
+        };
+
+        if !self.has_account() {
+            return Ok(None);
+        }
+
+        let screen_msg = &self.tr(journal.config().screen_msg());
+        let sipdate = self.sip_date_now();
+        let institution = self.account().settings().institution();
+        let item_barcode = msg.get_field_value("AB").unwrap_or("");
+        let patron_barcode = msg.get_field_value("AA").unwrap_or("");
+
+        let resp = match msg.spec().code {
+            code @ ("11" | "29") => {
+                if item_barcode.is_empty() || patron_barcode.is_empty() {
+                    return Ok(self.offline_failure_response(msg));
+                }
+
+                let renewal = code == "29";
+
+                let now = date::now();
+                let due_date = date::add_interval(now, journal.config().loan_period())?;
+
+                let entry = OfflineEntry {
+                    timestamp: date::to_iso(&now),
+                    account: self.account().sip_username().to_string(),
+                    ils_username: self.account().ils_username().to_string(),
+                    workstation: self.account().workstation().map(|w| w.to_string()),
+                    patron_barcode: patron_barcode.to_string(),
+                    item_barcode: item_barcode.to_string(),
+                    due_date: date::to_iso(&due_date),
+                    renew_ok: renewal || msg.fixed_fields()[0].value() == "Y",
+                    renewal,
+                };
+
+                journal.append(&entry)?;
+
+                let action = if renewal { "renewal" } else { "checkout" };
+                log::info!("{self} journaled offline {action} of {item_barcode}");
+
+                let due_date = self
+                    .sip_due_date(Some(&entry.due_date))?
+                    .unwrap_or_default();
+
+                // ok, renewal ok, magnetic media, desensitize
+                let (spec, ff) = if renewal {
+                    (&sip2::spec::M_RENEW_RESP, ["1", "Y", "N", "Y"])
+                } else {
+                    (&sip2::spec::M_CHECKOUT_RESP, ["1", "N", "N", "Y"])
+                };
+
+                sip2::Message::from_values(
+                    spec,
+                    &[ff[0], ff[1], ff[2], ff[3], &sipdate],
+                    &[
+                        ("AA", patron_barcode),
+                        ("AB", item_barcode),
+                        ("AJ", ""),
+                        ("AO", institution),
+                        ("AH", &due_date),
+                        ("AF", screen_msg),
+                    ],
+                )
+            }
+            // Item and patron status are unknown.
+            "17" | "23" | "63" => return Ok(self.unavailable_response(msg, screen_msg)),
+            _ => return Ok(self.offline_failure_response(msg)),
+        };
+
+        Ok(Some(resp.map_err(|e| {
+            format!("Cannot build offline response: {e}")
+        })?))
+    }
+
+    /// ok=0 response for a request we cannot answer offline.
+    ///
+    /// Returns None for message types with no such response.
+    fn offline_failure_response(&self, msg: &sip2::Message) -> Option<sip2::Message> {
+        self.unavailable_response(msg, &self.tr(UNAVAILABLE_SCREEN_MSG))
+    }
+
+    /// ok=0 or blocked response, with the provided screen message,
+    /// for a request we will not pass along to Evergreen.
+    ///
+    /// Returns None for message types with no such response.
+    pub fn unavailable_response(
+        &self,
+        msg: &sip2::Message,
+        screen_msg: &str,
+    ) -> Option<sip2::Message> {
+        if let Some(resp) = self.failure_response(msg, screen_msg) {
+            return Some(resp);
+        }
+
+        let institution = match self.has_account() {
+            true => self.account().settings().institution(),
+            // Only a login may be answered before the client logs in.
+            false if msg.spec().code == sip2::spec::M_LOGIN.code => "",
+            false => return None,
+        };
+
+        unavailable_response(msg, institution, &self.sip_date_now(), screen_msg)
+    }
+}
+
+/// ok=0 or blocked response for messages which are not handled by
+/// Session::failure_response().
+///
+/// Returns None for message types with no such response.
+pub fn unavailable_response(
+    msg: &sip2::Message,
+    institution: &str,
+    sipdate: &str,
+    screen_msg: &str,
+) -> Option<sip2::Message> {
+    let item_barcode = msg.get_field_value("AB").unwrap_or("");
+    let patron_barcode = msg.get_field_value("AA").unwrap_or("");
+
+    let resp = match msg.spec().code {
+        "15" => sip2::Message::from_values(
+            &sip2::spec::M_HOLD_RESP,
+            &["0", "N", sipdate],
+            &[("AO", institution), ("AA", patron_barcode)],
+        ),
+        "17" => sip2::Message::from_values(
+            &sip2::spec::M_ITEM_INFO_RESP,
+            &[
+                "01", // circ status: other
+                "01", // security marker: other
+                "01", // fee type: other
+                sipdate,
+            ],
+            &[("AB", item_barcode), ("AJ", "")],
+        ),
+        "19" => sip2::Message::from_values(
+            &sip2::spec::M_ITEM_STATUS_UPDATE_RESP,
+            &["0", sipdate],
+            &[("AB", item_barcode)],
+        ),
+        code @ ("23" | "63") => {
+            let spec = if code == "23" {
+                &sip2::spec::M_PATRON_STATUS_RESP
+            } else {
+                &sip2::spec::M_PATRON_INFO_RESP
+            };
+
+            // Patron status is unknown, so report no blocks and
+            // leave out the valid patron fields.
+            let mut ff = vec!["              ", "000", sipdate];
+            if code == "63" {
+                ff.extend(["0000"; 6]);
+            }
+
+            sip2::Message::from_values(
+                spec,
+                &ff,
+                &[("AO", institution), ("AA", patron_barcode), ("AE", "")],
+            )
+        }
+        "29" => sip2::Message::from_values(
+            &sip2::spec::M_RENEW_RESP,
+            &["0", "N", "N", "N", sipdate],
+            &[
+                ("AO", institution),
+                ("AA", patron_barcode),
+                ("AB", item_barcode),
+                ("AJ", ""),
+            ],
+        ),
+        "35" => sip2::Message::from_values(
+            &sip2::spec::M_END_PATRON_SESSION_RESP,
+            &["N", sipdate],
+            &[("AO", institution), ("AA", patron_barcode)],
+        ),
+        "65" => sip2::Message::from_values(
+            &sip2::spec::M_RENEW_ALL_RESP,
+            &["0", "0000", "0000", sipdate],
+            &[("AO", institution)],
+        ),
+        "93" => sip2::Message::from_values(&sip2::spec::M_LOGIN_RESP, &["0"], &[]),
+        _ => return None,
+    };
+
+    match resp {
+        Ok(mut resp) => {
+            if resp.spec().code != sip2::spec::M_LOGIN_RESP.code {
+                resp.add_field("AF", screen_msg);
+            }
+            Some(resp)
+        }
+        Err(e) => {
+            log::error!("cannot build unavailable response: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_journal(name: &str) -> OfflineJournal {
+    let path = std::env::temp_dir()
+        .join(format!("sip2-offline-{name}-{}.jsonl", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+
+    let config = OfflineConfig::new(&path);
+
+    fs::remove_file(config.journal()).ok();
+    fs::remove_file(config.dead_letter()).ok();
+    fs::remove_file(config.offset_file()).ok();
+
+    OfflineJournal::new(config).unwrap()
+}
+
+#[cfg(test)]
+fn test_entry(item_barcode: &str) -> OfflineEntry {
+    OfflineEntry {
+        timestamp: "2024-01-01T10:00:00-0500".to_string(),
+        account: "sip-user".to_string(),
+        ils_username: "admin".to_string(),
+        workstation: None,
+        patron_barcode: "patron".to_string(),
+        item_barcode: item_barcode.to_string(),
+        due_date: "2024-01-15T10:00:00-0500".to_string(),
+        renew_ok: false,
+        renewal: false,
+    }
+}
+
+#[test]
+fn test_offline_replay() {
+    let journal = test_journal("replay");
+
+    for barcode in ["item1", "item2", "item3", "item4"] {
+        journal.append(&test_entry(barcode)).unwrap();
+    }
+
+    assert_eq!(journal.pending_count().unwrap(), 4);
+
+    // Evergreen goes away after the second entry.
+    let summary = journal
+        .replay(|e| match e.item_barcode.as_str() {
+            "item1" => ReplayOutcome::Success,
+            "item2" => ReplayOutcome::Failed("ITEM_NOT_CATALOGED".to_string()),
+            _ => ReplayOutcome::Retry("Transport error".to_string()),
+        })
+        .unwrap();
+
+    assert_eq!(summary.succeeded, 1);
+    assert_eq!(summary.failed, 1);
+    assert!(summary.interrupted.is_some());
+    assert_eq!(journal.pending_count().unwrap(), 2);
+
+    let dead = fs::read_to_string(journal.config().dead_letter()).unwrap();
+    assert!(dead.contains("ITEM_NOT_CATALOGED"));
+    assert!(dead.contains("item2"));
+
+    // A new journal instance, as after a restart, picks up where the
+    // last replay left off.
+    let journal = OfflineJournal::new(journal.config().clone()).unwrap();
+
+    let mut replayed = Vec::new();
+    let summary = journal
+        .replay(|e| {
+            replayed.push(e.item_barcode.clone());
+            ReplayOutcome::Success
+        })
+        .unwrap();
+
+    assert_eq!(replayed, ["item3", "item4"]);
+    assert_eq!(summary.succeeded, 2);
+    assert_eq!(journal.pending_count().unwrap(), 0);
+
+    // Nothing left to replay.
+    let summary = journal.replay(|_| panic!("Nothing to replay")).unwrap();
+    assert_eq!(summary, ReplaySummary::default());
+}
+
+#[test]
+fn test_offline_entry_json() {
+    let mut entry = test_entry("item1");
+    entry.workstation = Some("BR1-sip".to_string());
+    entry.renew_ok = true;
+    entry.renewal = true;
+
+    assert_eq!(OfflineEntry::from_json(&entry.to_json()).unwrap(), entry);
+
+    let mut value = entry.to_json();
+    value.remove("item_barcode");
+    assert!(OfflineEntry::from_json(&value).is_err());
+}
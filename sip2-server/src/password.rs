@@ -0,0 +1,113 @@
+//! SIP account password hashing.
+//!
+//! Hashes are Argon2id, stored in PHC string format:
+//!
+//! $argon2id$v=19$m=<memory>,t=<iterations>,p=<lanes>$<base64 salt>$<base64 hash>
+//!
+//! PBKDF2-SHA256 hashes ($pbkdf2-sha256$...) created by earlier
+//! versions are still verified.
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::{PasswordHasher, PasswordVerifier};
+use argon2::Argon2;
+use pbkdf2::Pbkdf2;
+
+/// Hash a password using a random salt and the default Argon2id
+/// parameters.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    hash_password_with(&Argon2::default(), password)
+}
+
+fn hash_password_with(argon2: &Argon2, password: &str) -> Result<String, String> {
+    argon2
+        .hash_password(password.as_bytes())
+        .map(|h| h.to_string())
+        .map_err(|e| format!("Cannot hash password: {e}"))
+}
+
+/// Hash algorithms we can verify.
+enum HashKind {
+    Argon2,
+    Pbkdf2,
+}
+
+fn parse_hash(hash_str: &str) -> Result<(HashKind, PasswordHash), String> {
+    let hash = PasswordHash::new(hash_str)
+        .map_err(|e| format!("Unsupported password hash format: {e}"))?;
+
+    let alg = hash.algorithm.as_str();
+
+    let kind = if alg.starts_with("argon2") {
+        argon2::Params::try_from(&hash)
+            .map_err(|e| format!("Invalid password hash parameters: {e}"))?;
+        HashKind::Argon2
+    } else if alg.starts_with("pbkdf2-") {
+        pbkdf2::Params::try_from(&hash)
+            .map_err(|e| format!("Invalid password hash parameters: {e}"))?;
+        HashKind::Pbkdf2
+    } else {
+        return Err(format!("Unsupported password hash algorithm: {alg}"));
+    };
+
+    if hash.salt.is_none() || hash.hash.is_none() {
+        return Err(format!("Incomplete password hash: {hash_str}"));
+    }
+
+    Ok((kind, hash))
+}
+
+/// Returns Err if the hash string is not a usable password hash.
+pub fn validate_hash(hash_str: &str) -> Result<(), String> {
+    parse_hash(hash_str).map(|_| ())
+}
+
+/// True if the password matches the hash.
+///
+/// Malformed hashes never match.
+pub fn verify_password(password: &str, hash_str: &str) -> bool {
+    let (kind, hash) = match parse_hash(hash_str) {
+        Ok(h) => h,
+        Err(e) => {
+            log::error!("{e}");
+            return false;
+        }
+    };
+
+    // Parameters and salt come from the hash string.
+    let result = match kind {
+        HashKind::Argon2 => Argon2::default().verify_password(password.as_bytes(), &hash),
+        HashKind::Pbkdf2 => Pbkdf2::default().verify_password(password.as_bytes(), &hash),
+    };
+
+    result.is_ok()
+}
+
+#[test]
+fn test_hash_and_verify() {
+    // Cheap parameters keep the test fast.
+    let argon2 = Argon2::from(argon2::Params::new(1024, 1, 1, None).unwrap());
+    let hash = hash_password_with(&argon2, "sip-pass").unwrap();
+
+    assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert!(validate_hash(&hash).is_ok());
+
+    assert!(verify_password("sip-pass", &hash));
+    assert!(!verify_password("sip-pass2", &hash));
+    assert!(!verify_password("", &hash));
+
+    // Same password, different salt.
+    assert_ne!(hash, hash_password_with(&argon2, "sip-pass").unwrap());
+
+    assert!(validate_hash("sip-pass").is_err());
+    assert!(validate_hash("$md5$abc$def").is_err());
+    assert!(!verify_password("sip-pass", "sip-pass"));
+}
+
+#[test]
+fn test_verify_legacy_pbkdf2() {
+    let hash = "$pbkdf2-sha256$i=1000$bGVnYWN5LXNhbHQtMTIzNA\
+        $TBoqmYKR2W8sCPkP1hvfv0xuH5/UXXpkZwI7djw604E";
+
+    assert!(validate_hash(hash).is_ok());
+    assert!(verify_password("sip-pass", hash));
+    assert!(!verify_password("sip-pass2", hash));
+}
@@ -1,6 +1,11 @@
 use super::conf;
 use super::session::Session;
+use super::sipdate;
+use eg::common::bib;
+use eg::common::holds;
+use eg::constants as C;
 use eg::date;
+use eg::money::Money;
 use eg::result::EgResult;
 use eg::EgValue;
 use evergreen as eg;
@@ -8,6 +13,24 @@ use evergreen as eg;
 const EG_NULL: EgValue = EgValue::Null;
 const DEFAULT_LIST_ITEM_SIZE: usize = 10;
 
+/// Screen message for patrons blocked by the max-fine setting.
+const MAX_FINE_BLOCK_MSG: &str = "Patron exceeds fine threshold";
+
+/// Patron data fetched with queries which do not depend on each other.
+#[derive(Debug, Default)]
+struct PatronData {
+    /// Money summary (mous)
+    summary: Option<EgValue>,
+    /// Open circulation ID lists (ocirclist)
+    circ_list: Option<EgValue>,
+    penalties: Vec<PatronPenalty>,
+    /// Transactions with a balance.
+    xacts: Vec<EgValue>,
+    hold_ids: Vec<i64>,
+    unavail_hold_ids: Vec<i64>,
+    recall_ids: Vec<i64>,
+}
+
 /// SIP clients can request detail info for specific types of data.
 /// These are the options.
 #[derive(Debug, Clone)]
@@ -17,6 +40,7 @@ pub enum SummaryListType {
     ChargedItems,
     OverdueItems,
     FineItems,
+    RecallItems,
     Unsupported,
 }
 
@@ -45,18 +69,21 @@ impl SummaryListOptions {
         }
     }
 
-    /// Returns zero-based limit from 1-based SIP "end item" value.
+    /// Returns the number of items to return from the 1-based,
+    /// inclusive SIP "end item" value.
     pub fn limit(&self) -> usize {
-        if let Some(e) = self.end_item {
-            if e > 0 {
-                e - 1
-            } else {
-                DEFAULT_LIST_ITEM_SIZE
-            }
-        } else {
-            DEFAULT_LIST_ITEM_SIZE
+        match self.end_item {
+            Some(e) if e > 0 => e.saturating_sub(self.offset()),
+            _ => DEFAULT_LIST_ITEM_SIZE,
         }
     }
+
+    /// Returns the requested page of a list of values.
+    pub fn page<'a, T>(&self, values: &'a [T]) -> &'a [T] {
+        let offset = self.offset().min(values.len());
+        let end = (offset + self.limit()).min(values.len());
+        &values[offset..end]
+    }
 }
 
 #[derive(Debug)]
@@ -68,15 +95,23 @@ pub struct Patron {
     pub recall_denied: bool,
     pub holds_denied: bool,
     pub card_lost: bool,
+    pub card_expired: bool,
+    pub juvenile: bool,
+    pub max_charged: bool,
     pub max_overdue: bool,
+    pub max_lost: bool,
     pub max_fines: bool,
     pub recall_overdue: bool,
     pub max_bills: bool,
     pub valid: bool,
     pub card_active: bool,
-    pub balance_owed: f64,
-    pub password_verified: bool,
+    pub balance_owed: Money,
+    /// Why the patron is blocked, reported as screen messages.
+    pub block_messages: Vec<String>,
+    /// None if no password was provided.
+    pub password_verified: Option<bool>,
     pub recall_count: usize,
+    pub recall_ids: Vec<i64>,
     pub holds_count: usize,
     pub hold_ids: Vec<i64>,
     pub unavail_hold_ids: Vec<i64>,
@@ -111,14 +146,19 @@ impl Patron {
             recall_denied: false,
             holds_denied: false,
             card_lost: false,
+            card_expired: false,
+            juvenile: false,
+            max_charged: false,
             max_overdue: false,
+            max_lost: false,
             max_fines: false,
             recall_overdue: false,
             max_bills: false,
             valid: false,
             card_active: false,
-            balance_owed: 0.0,
-            password_verified: false,
+            balance_owed: Money::zero(),
+            block_messages: Vec::new(),
+            password_verified: None,
             recall_count: 0,
             holds_count: 0,
             unavail_holds_count: 0,
@@ -126,6 +166,7 @@ impl Patron {
             items_out_count: 0,
             fine_count: 0,
             hold_ids: Vec::new(),
+            recall_ids: Vec::new(),
             unavail_hold_ids: Vec::new(),
             items_overdue_ids: Vec::new(),
             items_out_ids: Vec::new(),
@@ -164,10 +205,14 @@ impl Session {
         let mut patron = Patron::new(barcode, self.format_user_name(&user));
 
         patron.id = user.id()?;
-        patron.password_verified = self.check_password(patron.id, password_op)?;
+        patron.valid = !user["deleted"].boolish() && user["active"].boolish();
+        patron.juvenile = user["juvenile"].boolish();
+        patron.password_verified = self.check_password(patron.id, password_op);
 
-        if let Some(summary) = self.editor_mut().retrieve("mous", patron.id)? {
-            patron.balance_owed = summary["balance_owed"].float()?;
+        let data = self.get_patron_data(patron.id)?;
+
+        if let Some(summary) = data.summary.as_ref() {
+            patron.balance_owed = Money::from_value(&summary["balance_owed"])?;
         }
 
         if user["billing_address"].is_object() {
@@ -186,11 +231,15 @@ impl Session {
             patron.home_lib = Some(sn.to_string());
         }
 
+        let expose_dates = self.account().settings().patron_info_expose_dates();
+
         // DoB is stored in the database as a YYYY-MM-DD value / no time.
         // SIP wants YYYYMMDD instead.
-        if let Some(dob) = user["dob"].as_str() {
-            let ymd = dob.replace("-", "");
-            patron.dob = Some(ymd);
+        if let Some(dob) = user["dob"].as_str().filter(|_| expose_dates) {
+            match sipdate::format_sip_ymd(dob) {
+                Ok(ymd) => patron.dob = Some(ymd),
+                Err(e) => log::warn!("{self} invalid patron birth date: {e}"),
+            }
         }
 
         if let Some(net) = user["net_access_level"]["name"].as_str() {
@@ -211,14 +260,16 @@ impl Session {
             patron.phone = Some(phone.to_string());
         }
 
-        if let Some(expire) = user["expire_date"].as_str() {
+        if let Some(expire) = user["expire_date"].as_str().filter(|_| expose_dates) {
             if let Ok(date) = date::parse_datetime(expire) {
+                // Report the expire date as a calendar day in our time zone.
+                let date = date::set_timezone(date, self.timezone())?;
                 patron.expire_date = Some(date.format("%Y%m%d").to_string());
             }
         }
 
-        self.set_patron_privileges(&user, &mut patron)?;
-        self.set_patron_summary_items(&mut patron)?;
+        self.set_patron_privileges(&user, &mut patron, &data.penalties)?;
+        set_patron_summary_items(&mut patron, data);
 
         if let Some(ops) = summary_list_options {
             self.set_patron_summary_list_items(&mut patron, ops)?;
@@ -226,6 +277,8 @@ impl Session {
 
         self.log_activity(patron.id)?;
 
+        self.field_values_mut().add_patron(&patron);
+
         Ok(Some(patron))
     }
 
@@ -242,15 +295,14 @@ impl Session {
             ]
         };
 
-        self.editor_mut().xact_begin()?;
+        let mut editor = self.editor_mut().xact_guard()?;
 
-        let resp = self.editor_mut().json_query(query)?;
+        let resp = editor.json_query(query)?;
 
         if resp.len() > 0 {
-            self.editor_mut().commit()?;
-            Ok(())
+            editor.commit()
         } else {
-            self.editor_mut().rollback()?;
+            // Dropping the guard rolls back the transaction.
             Err(format!("Patron activity logging returned no response").into())
         }
     }
@@ -269,6 +321,7 @@ impl Session {
             SL::ChargedItems => self.add_items_out(patron, summary_ops)?,
             SL::OverdueItems => self.add_overdue_items(patron, summary_ops)?,
             SL::FineItems => self.add_fine_items(patron, summary_ops)?,
+            SL::RecallItems => self.add_recall_items(patron, summary_ops)?,
             SL::Unsupported => {} // NO-OP not necessarily an error.
         }
 
@@ -298,7 +351,7 @@ impl Session {
         let last_btype = xact["last_billing_type"].as_str().unwrap(); // required
 
         let xact_id = xact.id()?;
-        let balance_owed = xact["balance_owed"].float()?;
+        let balance_owed = Money::from_value(&xact["balance_owed"])?;
 
         let mut title: Option<String> = None;
         let mut author: Option<String> = None;
@@ -323,7 +376,7 @@ impl Session {
 
         match self.account().settings().av_format() {
             conf::AvFormat::Legacy => {
-                line = format!("{:.2} {}", balance_owed, last_btype);
+                line = format!("{} {}", balance_owed, last_btype);
                 if is_circ {
                     line += &format!(" {} / {}", title, author);
                 }
@@ -341,7 +394,7 @@ impl Session {
 
             conf::AvFormat::SwyerB => {
                 line = format!(
-                    "Charge-Number: {}, Amount-Due: {:.2}, Fine-Type: {}",
+                    "Charge-Number: {}, Amount-Due: {}, Fine-Type: {}",
                     xact_id, balance_owed, fee_type
                 );
 
@@ -380,106 +433,238 @@ impl Session {
         patron: &mut Patron,
         summary_ops: &SummaryListOptions,
     ) -> EgResult<()> {
-        let all_circ_ids: Vec<&i64> =
-            [patron.items_overdue_ids.iter(), patron.items_out_ids.iter()]
-                .into_iter()
-                .flatten()
-                .collect();
+        let all_circ_ids: Vec<i64> = patron
+            .items_overdue_ids
+            .iter()
+            .chain(patron.items_out_ids.iter())
+            .copied()
+            .collect();
 
-        let offset = summary_ops.offset();
-        let limit = summary_ops.limit();
+        patron.detail_items = Some(self.circ_ids_to_values(summary_ops.page(&all_circ_ids))?);
 
-        let mut circs: Vec<String> = Vec::new();
+        Ok(())
+    }
 
-        for idx in offset..(offset + limit) {
-            if let Some(id) = all_circ_ids.get(idx) {
-                circs.push(self.circ_id_to_value(**id)?);
+    fn add_overdue_items(
+        &mut self,
+        patron: &mut Patron,
+        summary_ops: &SummaryListOptions,
+    ) -> EgResult<()> {
+        patron.detail_items =
+            Some(self.circ_ids_to_values(summary_ops.page(&patron.items_overdue_ids))?);
+
+        Ok(())
+    }
+
+    /// Copy barcodes or titles for a list of circulations, per the
+    /// msg64 summary datatype setting.
+    fn circ_ids_to_values(&mut self, ids: &[i64]) -> EgResult<Vec<String>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query = eg::hash! {
+            select: {circ: ["id", "target_copy"]},
+            from: "circ",
+            where: {id: ids},
+        };
+
+        let circs = self.editor_mut().json_query(query)?;
+
+        // Returned rows are not necessarily in the requested order.
+        let mut copy_ids = Vec::new();
+        for id in ids {
+            if let Some(circ) = circs.iter().find(|c| c["id"].as_int() == Some(*id)) {
+                copy_ids.push(circ["target_copy"].int()?);
             }
         }
 
-        patron.detail_items = Some(circs);
+        let options = bib::DisplayFieldOptions {
+            nfc: true,
+            ascii: self.encoding() == sip2::Encoding::Ascii,
+        };
+
+        let fields = bib::get_display_fields_batch(self.editor_mut(), &copy_ids, options)?;
+
+        let use_barcode = self.account().settings().msg64_summary_datatype()
+            == &conf::Msg64SummaryDatatype::Barcode;
+
+        let values = fields
+            .into_iter()
+            .map(|f| match use_barcode {
+                true => f.barcode().to_string(),
+                // Titles are unlikely, but not impossible, to be missing.
+                false => f.title().unwrap_or("").to_string(),
+            })
+            .collect();
+
+        Ok(values)
+    }
+
+    /// Collect details on holds.
+    fn add_hold_items(
+        &mut self,
+        patron: &mut Patron,
+        summary_ops: &SummaryListOptions,
+        unavail: bool,
+    ) -> EgResult<()> {
+        if unavail {
+            if let Some(template) = self.account().settings().msg64_hold_unavail_template() {
+                let template = template.to_string();
+                return self.add_unavail_hold_items(patron, summary_ops, &template);
+            }
+        } else if let Some(template) = self.account().settings().msg64_hold_ready_template() {
+            let template = template.to_string();
+            return self.add_ready_hold_items(patron, summary_ops, &template);
+        }
+
+        let hold_ids = match unavail {
+            true => summary_ops.page(&patron.unavail_hold_ids).to_vec(),
+            false => summary_ops.page(&patron.hold_ids).to_vec(),
+        };
+
+        patron.detail_items = Some(self.hold_ids_to_values(&hold_ids)?);
 
         Ok(())
     }
 
-    fn add_overdue_items(
+    /// Collect holds ready for pickup, formatted with the configured
+    /// msg64-hold-ready-template.
+    fn add_ready_hold_items(
         &mut self,
         patron: &mut Patron,
         summary_ops: &SummaryListOptions,
+        template: &str,
     ) -> EgResult<()> {
-        let offset = summary_ops.offset();
-        let limit = summary_ops.limit();
+        let hold_ids = self.get_hold_ids(patron.id, Some(true), None, None)?;
 
-        let mut circs: Vec<String> = Vec::new();
+        let mut hold_items = Vec::new();
 
-        for idx in offset..(offset + limit) {
-            if let Some(id) = patron.items_overdue_ids.get(idx) {
-                circs.push(self.circ_id_to_value(*id)?);
+        for hold_id in summary_ops.page(&hold_ids) {
+            if let Some(value) = self.ready_hold_to_value(*hold_id, template)? {
+                hold_items.push(value);
             }
         }
 
-        patron.detail_items = Some(circs);
+        patron.detail_items = Some(hold_items);
 
         Ok(())
     }
 
-    fn circ_id_to_value(&mut self, id: i64) -> EgResult<String> {
-        let format = self.account().settings().msg64_summary_datatype();
+    fn ready_hold_to_value(&mut self, hold_id: i64, template: &str) -> EgResult<Option<String>> {
+        let flesh = eg::hash! {
+            flesh: 1,
+            flesh_fields: {ahr: ["pickup_lib", "current_copy"]}
+        };
+
+        let hold = match self.editor_mut().retrieve_with_ops("ahr", hold_id, flesh)? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
 
-        if format == &conf::Msg64SummaryDatatype::Barcode {
-            let flesh = eg::hash! {
-                flesh: 1,
-                flesh_fields: {circ: ["target_copy"]},
-            };
+        let title = self.find_title_for_hold(&hold)?.unwrap_or_default();
 
-            // If we have a circ ID, we have to have a circ.
-            let circ = self
-                .editor_mut()
-                .retrieve_with_ops("circ", id, flesh)?
-                .unwrap();
+        let shelf_expire = match hold["shelf_expire_time"].as_str() {
+            Some(d) => date::set_timezone(date::parse_datetime(d)?, self.timezone())?
+                .format("%Y-%m-%d")
+                .to_string(),
+            None => String::new(),
+        };
 
-            // If we have a circ, we have to have copy barcode.
-            let bc = circ["target_copy"]["barcode"].as_str().unwrap();
+        let values = [
+            ("title", title.as_str()),
+            (
+                "barcode",
+                hold["current_copy"]["barcode"].as_str().unwrap_or(""),
+            ),
+            (
+                "pickup_lib",
+                hold["pickup_lib"]["shortname"].as_str().unwrap_or(""),
+            ),
+            ("shelf_expire", shelf_expire.as_str()),
+        ];
+
+        Ok(Some(format_hold_template(template, &values)))
+    }
 
-            return Ok(bc.to_string());
-        }
+    /// Collect holds not yet available for pickup, formatted with the
+    /// configured msg64-hold-unavail-template.
+    fn add_unavail_hold_items(
+        &mut self,
+        patron: &mut Patron,
+        summary_ops: &SummaryListOptions,
+        template: &str,
+    ) -> EgResult<()> {
+        let hold_ids = summary_ops.page(&patron.unavail_hold_ids).to_vec();
 
-        let (title, _) = self.get_circ_title_author(id)?;
+        let mut hold_items = Vec::new();
 
-        if let Some(t) = title {
-            Ok(t)
-        } else {
-            Ok(String::new()) // unlikely, but not impossible
+        for hold_id in hold_ids {
+            if let Some(value) = self.unavail_hold_to_value(hold_id, template)? {
+                hold_items.push(value);
+            }
         }
+
+        patron.detail_items = Some(hold_items);
+
+        Ok(())
     }
 
-    /// Collect details on holds.
-    fn add_hold_items(
+    fn unavail_hold_to_value(&mut self, hold_id: i64, template: &str) -> EgResult<Option<String>> {
+        let flesh = eg::hash! {
+            flesh: 1,
+            flesh_fields: {ahr: ["pickup_lib"]}
+        };
+
+        let hold = match self.editor_mut().retrieve_with_ops("ahr", hold_id, flesh)? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        let title = self.find_title_for_hold(&hold)?.unwrap_or_default();
+        let stats = holds::hold_queue_stats(self.editor_mut(), hold_id)?;
+
+        // Round up so a wait of a few hours still reads as 1 day.
+        let wait_days = match stats.estimated_wait() {
+            Some(secs) => ((secs + 86399) / 86400).to_string(),
+            None => String::new(),
+        };
+
+        let values = [
+            ("title", title.as_str()),
+            (
+                "pickup_lib",
+                hold["pickup_lib"]["shortname"].as_str().unwrap_or(""),
+            ),
+            ("queue_position", &stats.queue_position().to_string()),
+            ("queue_length", &stats.total_holds().to_string()),
+            ("wait_days", wait_days.as_str()),
+        ];
+
+        Ok(Some(format_hold_template(template, &values)))
+    }
+
+    /// Collect details on recall holds for items charged to the patron.
+    fn add_recall_items(
         &mut self,
         patron: &mut Patron,
         summary_ops: &SummaryListOptions,
-        unavail: bool,
     ) -> EgResult<()> {
-        let format = self.account().settings().msg64_hold_datatype().clone();
+        let hold_ids = summary_ops.page(&patron.recall_ids).to_vec();
 
-        let hold_ids = match unavail {
-            true => &patron.unavail_hold_ids,
-            false => &patron.hold_ids,
-        };
+        patron.detail_items = Some(self.hold_ids_to_values(&hold_ids)?);
 
-        let mut trimmed_hold_ids = Vec::new();
-        let limit = summary_ops.limit();
-        let offset = summary_ops.offset();
+        Ok(())
+    }
 
-        for idx in offset..(offset + limit) {
-            if let Some(id) = hold_ids.get(idx) {
-                trimmed_hold_ids.push(id);
-            }
-        }
+    /// Translate hold IDs into barcodes or titles, depending on
+    /// the configured hold datatype.
+    fn hold_ids_to_values(&mut self, hold_ids: &[i64]) -> EgResult<Vec<String>> {
+        let format = self.account().settings().msg64_hold_datatype().clone();
 
         let mut hold_items: Vec<String> = Vec::new();
 
-        for hold_id in trimmed_hold_ids {
+        for hold_id in hold_ids {
             if let Some(hold) = self.editor_mut().retrieve("ahr", *hold_id)? {
                 if format == conf::Msg64HoldDatatype::Barcode {
                     if let Some(copy) = self.find_copy_for_hold(&hold)? {
@@ -493,9 +678,7 @@ impl Session {
             }
         }
 
-        patron.detail_items = Some(hold_items);
-
-        Ok(())
+        Ok(hold_items)
     }
 
     fn find_title_for_hold(&mut self, hold: &EgValue) -> EgResult<Option<String>> {
@@ -586,38 +769,52 @@ impl Session {
         Ok(copies.pop())
     }
 
-    fn set_patron_summary_items(&mut self, patron: &mut Patron) -> EgResult<()> {
-        self.set_patron_hold_ids(patron, false, None, None)?;
-        self.set_patron_hold_ids(patron, true, None, None)?;
-
-        if let Some(summary) = self.editor_mut().retrieve("ocirclist", patron.id)? {
-            // overdue and out are packaged as comma-separated ID values.
-            let overdue: Vec<i64> = summary["overdue"]
-                .as_str()
-                .unwrap()
-                .split(",")
-                .map(|id| id.parse::<i64>().unwrap())
-                .filter(|id| id > &0)
-                .collect();
-
-            let outs: Vec<i64> = summary["out"]
-                .as_str()
-                .unwrap()
-                .split(",")
-                .map(|id| id.parse::<i64>().unwrap())
-                .filter(|id| id > &0)
-                .collect();
-
-            patron.items_overdue_count = overdue.len();
-            patron.items_out_count = outs.len();
-            patron.items_overdue_ids = overdue;
-            patron.items_out_ids = outs;
+    /// Run the independent patron queries.
+    ///
+    /// Queries run concurrently, each on its own session, unless
+    /// disabled by the patron-info-concurrent-queries setting.
+    fn get_patron_data(&mut self, patron_id: i64) -> EgResult<PatronData> {
+        let penalties = self.penalties_query(patron_id)?;
+        let holds = hold_ids_query(patron_id, self.summary_hold_ready(), None, None)?;
+        let unavail_holds = hold_ids_query(patron_id, Some(false), None, None)?;
+        let recalls = recall_ids_query(patron_id);
+        let (xacts, xacts_ops) = xacts_search(patron_id, None);
+
+        if !self.account().settings().patron_info_concurrent_queries() {
+            let editor = self.editor_mut();
+
+            return Ok(PatronData {
+                summary: editor.retrieve("mous", patron_id)?,
+                circ_list: editor.retrieve("ocirclist", patron_id)?,
+                penalties: to_penalties(editor.json_query(penalties)?)?,
+                xacts: editor.search_with_ops("mbts", xacts, xacts_ops)?,
+                hold_ids: to_ids(editor.json_query(holds)?)?,
+                unavail_hold_ids: to_ids(editor.json_query(unavail_holds)?)?,
+                recall_ids: to_ids(editor.json_query(recalls)?)?,
+            });
         }
 
-        let summaries = self.get_patron_xacts(&patron, None)?;
-        patron.fine_count = summaries.len();
-
-        Ok(())
+        let mut batch = self.editor().query_batch();
+
+        let summary = batch.retrieve("mous", patron_id)?;
+        let circ_list = batch.retrieve("ocirclist", patron_id)?;
+        let penalties = batch.json_query(penalties)?;
+        let xacts = batch.search_with_ops("mbts", xacts, xacts_ops)?;
+        let holds = batch.json_query(holds)?;
+        let unavail_holds = batch.json_query(unavail_holds)?;
+        let recalls = batch.json_query(recalls)?;
+
+        let mut results = batch.join()?;
+
+        Ok(PatronData {
+            summary: results.take_one(summary),
+            circ_list: results.take_one(circ_list),
+            penalties: to_penalties(results.take_list(penalties)?)?,
+            xacts: results.take_list(xacts)?,
+            hold_ids: to_ids(results.take_list(holds)?)?,
+            unavail_hold_ids: to_ids(results.take_list(unavail_holds)?)?,
+            recall_ids: to_ids(results.take_list(recalls)?)?,
+        })
     }
 
     pub fn get_patron_xacts(
@@ -625,84 +822,46 @@ impl Session {
         patron: &Patron,
         summary_ops: Option<&SummaryListOptions>,
     ) -> EgResult<Vec<EgValue>> {
-        let search = eg::hash! {
-            usr: patron.id,
-            balance_owed: {"<>": 0},
-            total_owed: {">": 0},
-        };
+        let (search, ops) = xacts_search(patron.id, summary_ops);
 
-        let mut ops = eg::hash! {
-            order_by: {mbts: "xact_start"}
-        };
+        self.editor_mut().search_with_ops("mbts", search, ops)
+    }
 
-        if let Some(sum_ops) = summary_ops {
-            ops["limit"] = EgValue::from(sum_ops.limit());
-            ops["offset"] = EgValue::from(sum_ops.offset());
+    /// Which holds to include in the patron's hold items.
+    ///
+    /// See hold_ids_query().
+    fn summary_hold_ready(&self) -> Option<bool> {
+        if self.account().settings().msg64_hold_items_available() {
+            Some(true)
+        } else {
+            None
         }
-
-        self.editor_mut().search_with_ops("mbts", search, ops)
     }
 
-    fn set_patron_hold_ids(
+    fn get_hold_ids(
         &mut self,
-        patron: &mut Patron,
-        unavail: bool,
+        patron_id: i64,
+        ready: Option<bool>,
         limit: Option<usize>,
         offset: Option<usize>,
-    ) -> EgResult<()> {
-        let mut search = eg::hash! {
-            usr: patron.id,
-            fulfillment_time: EG_NULL,
-            cancel_time: EG_NULL,
-        };
-
-        if unavail {
-            search["-or"] = eg::array! [
-              {current_shelf_lib: EG_NULL},
-              {current_shelf_lib: {"!=": {"+ahr": "pickup_lib"}}}
-            ];
-        } else if self.account().settings().msg64_hold_items_available() {
-            search["current_shelf_lib"] = eg::hash! {"=": {"+ahr": "pickup_lib"}};
-        }
-
-        let mut query = eg::hash! {
-            select: {ahr: ["id"]},
-            from: "ahr",
-            where: {"+ahr": search},
-        };
-
-        if let Some(l) = limit {
-            query["limit"] = EgValue::from(l);
-        }
-        if let Some(o) = offset {
-            query["offset"] = EgValue::from(o);
-        }
+    ) -> EgResult<Vec<i64>> {
+        let query = hold_ids_query(patron_id, ready, limit, offset)?;
 
-        let id_hash_list = self.editor_mut().json_query(query)?;
-
-        for hash in id_hash_list {
-            let hold_id = hash.id()?;
-            if unavail {
-                patron.unavail_hold_ids.push(hold_id);
-            } else {
-                patron.hold_ids.push(hold_id);
-            }
-        }
-
-        if unavail {
-            patron.unavail_holds_count = patron.unavail_hold_ids.len();
-        } else {
-            patron.holds_count = patron.hold_ids.len();
-        }
-
-        Ok(())
+        to_ids(self.editor_mut().json_query(query)?)
     }
 
-    fn set_patron_privileges(&mut self, user: &EgValue, patron: &mut Patron) -> EgResult<()> {
-        let expire_date_str = user["expire_date"].as_str().unwrap(); // required
-        let expire_date = date::parse_datetime(&expire_date_str)?;
+    fn set_patron_privileges(
+        &mut self,
+        user: &EgValue,
+        patron: &mut Patron,
+        penalties: &[PatronPenalty],
+    ) -> EgResult<()> {
+        let expire_date = user["expire_date"].as_str().unwrap(); // required
+
+        patron.card_active = user["card"]["active"].boolish();
+        patron.card_expired = card_expired(expire_date, &date::now())?;
 
-        if expire_date < eg::date::now() {
+        if patron.card_expired {
             // Patron is expired.  Don't bother checking other penalties, etc.
 
             patron.charge_denied = true;
@@ -719,36 +878,24 @@ impl Session {
             return Ok(());
         }
 
-        let penalties = self.get_patron_penalties(patron.id)?;
-
-        patron.max_fines = self.penalties_contain(1, &penalties)?; // PATRON_EXCEEDS_FINES
-        patron.max_overdue = self.penalties_contain(2, &penalties)?; // PATRON_EXCEEDS_OVERDUE_COUNT
-        patron.card_active = user["card"]["active"].boolish();
-
-        let blocked = user["barred"].boolish() || !user["active"].boolish() || !patron.card_active;
+        // Checkout and renewal blocks are ignored with permit-loans.
+        let permit_loans = self.account().settings().patron_status_permit_loans();
 
-        let mut block_tags = String::new();
-        for pen in penalties.iter() {
-            if let Some(tag) = pen["block_tag"].as_str() {
-                block_tags += tag;
-            }
-        }
+        apply_penalties(patron, penalties, permit_loans);
 
-        if !blocked && block_tags.len() == 0 {
-            // No blocks, etc. left to inspect.  All done.
-            return Ok(());
+        if let Some(max_fine) = self.account().settings().patron_max_fine() {
+            apply_max_fine(patron, max_fine, permit_loans);
         }
 
-        patron.holds_denied = blocked || block_tags.contains("HOLDS");
+        if user["barred"].boolish() || !user["active"].boolish() || !patron.card_active {
+            patron.holds_denied = true;
 
-        if self.account().settings().patron_status_permit_loans() {
-            // We're going to ignore checkout, renewals blocks for now.
-            return Ok(());
+            if !permit_loans {
+                patron.charge_denied = true;
+                patron.renew_denied = true;
+            }
         }
 
-        patron.charge_denied = blocked || block_tags.contains("CIRC");
-        patron.renew_denied = blocked || block_tags.contains("RENEW");
-
         // In evergreen, patrons cannot create Recall holds directly, but that
         // doesn't mean they would not have said privilege if the functionality
         // existed.  Base the ability to perform recalls on whether they have
@@ -758,21 +905,13 @@ impl Session {
         Ok(())
     }
 
-    fn penalties_contain(&self, penalty_id: i64, penalties: &Vec<EgValue>) -> EgResult<bool> {
-        for pen in penalties.iter() {
-            let pen_id = pen.id()?;
-            if pen_id == penalty_id {
-                return Ok(true);
-            }
-        }
-        Ok(false)
-    }
-
-    fn get_patron_penalties(&mut self, user_id: i64) -> EgResult<Vec<EgValue>> {
+    /// Query for standing penalties which apply to the patron at our
+    /// workstation org unit, with their blocks.
+    fn penalties_query(&self, user_id: i64) -> EgResult<EgValue> {
         let ws_org = self.get_ws_org_id()?;
 
         let search = eg::hash! {
-            select: {csp: ["id", "block_list"]},
+            select: {csp: ["id", "name", "label", "block_list"]},
             from: {ausp: "csp"},
             where: {
                 "+ausp": {
@@ -798,7 +937,7 @@ impl Session {
             }
         };
 
-        self.editor_mut().json_query(search)
+        Ok(search)
     }
 
     fn get_user(&mut self, barcode: &str) -> EgResult<Option<EgValue>> {
@@ -826,14 +965,28 @@ impl Session {
         Ok(Some(user))
     }
 
-    fn check_password(&mut self, user_id: i64, password_op: Option<&str>) -> EgResult<bool> {
-        let password = match password_op {
-            Some(p) => p,
-            None => return Ok(false),
-        };
+    /// Verify the patron password against Evergreen.
+    ///
+    /// Returns None if no password was provided.  Failure to verify
+    /// the password is treated as a password mismatch instead of an
+    /// error so the rest of the patron response is unaffected.
+    fn check_password(&mut self, user_id: i64, password_op: Option<&str>) -> Option<bool> {
+        let password = password_op?;
 
         log::debug!("{self} verifying password for user ID {user_id}");
-        eg::common::user::verify_migrated_password(self.editor_mut(), user_id, password, false)
+
+        let verified =
+            eg::common::user::verify_migrated_password(self.editor_mut(), user_id, password, false)
+                .unwrap_or_else(|e| {
+                    log::error!("{self} password verification failed for user {user_id}: {e}");
+                    false
+                });
+
+        if !verified {
+            log::info!("{self} invalid password provided for user ID {user_id}");
+        }
+
+        Some(verified)
     }
 
     pub fn handle_patron_status(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
@@ -844,11 +997,17 @@ impl Session {
         let password_op = msg.get_field_value("AD"); // optional
 
         let patron_op = self.get_patron_details(&barcode, password_op.as_deref(), None)?;
-        self.patron_response_common(
+        let mut resp = self.patron_response_common(
             &sip2::spec::M_PATRON_STATUS_RESP,
             &barcode,
             patron_op.as_ref(),
-        )
+        )?;
+
+        if let Some(patron) = patron_op.as_ref() {
+            self.suppress_juvenile_fields(patron, &mut resp);
+        }
+
+        Ok(resp)
     }
 
     pub fn handle_patron_info(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
@@ -891,6 +1050,7 @@ impl Session {
                 1 => SummaryListType::OverdueItems,
                 2 => SummaryListType::ChargedItems,
                 3 => SummaryListType::FineItems,
+                4 => SummaryListType::RecallItems,
                 5 => SummaryListType::UnavailHoldItems,
                 _ => SummaryListType::Unsupported,
             },
@@ -924,12 +1084,13 @@ impl Session {
         resp.maybe_add_field("PI", patron.net_access.as_deref());
         resp.maybe_add_field("PC", patron.profile.as_deref());
 
-        if let Some(detail_items) = patron.detail_items {
+        if let Some(detail_items) = patron.detail_items.as_ref() {
             let code = match list_type {
                 SummaryListType::HoldItems => "AS",
                 SummaryListType::OverdueItems => "AT",
                 SummaryListType::ChargedItems => "AU",
                 SummaryListType::FineItems => "AV",
+                SummaryListType::RecallItems => "BU",
                 SummaryListType::UnavailHoldItems => "CD",
                 _ => "",
             };
@@ -937,9 +1098,24 @@ impl Session {
             detail_items.iter().for_each(|i| resp.add_field(code, i));
         };
 
+        self.suppress_juvenile_fields(&patron, &mut resp);
+
         Ok(resp)
     }
 
+    /// Remove the account's juvenile-suppress-fields from responses
+    /// for juvenile patrons.
+    fn suppress_juvenile_fields(&self, patron: &Patron, resp: &mut sip2::Message) {
+        if !patron.juvenile {
+            return;
+        }
+
+        let suppress = self.account().settings().juvenile_suppress_fields();
+
+        resp.fields_mut()
+            .retain(|f| !suppress.iter().any(|code| code == f.code()));
+    }
+
     fn patron_response_common(
         &self,
         msg_spec: &'static sip2::spec::Message,
@@ -947,7 +1123,7 @@ impl Session {
         patron_op: Option<&Patron>,
     ) -> EgResult<sip2::Message> {
         let sbool = |v| sip2::util::space_bool(v); // local shorthand
-        let sipdate = sip2::util::sip_date_now();
+        let sipdate = self.sip_date_now();
 
         if patron_op.is_none() {
             log::warn!("Replying to patron lookup for not-found patron");
@@ -987,11 +1163,11 @@ impl Session {
             sbool(patron.recall_denied),
             sbool(patron.holds_denied),
             sbool(!patron.card_active),
-            " ", // max charged
+            sbool(patron.max_charged),
             sbool(patron.max_overdue),
             " ", // max renewals
             " ", // max claims returned
-            " ", // max lost
+            sbool(patron.max_lost),
             sbool(patron.max_fines),
             sbool(patron.max_fines),
             " ", // recall overdue
@@ -1016,24 +1192,44 @@ impl Session {
                 ("AA", barcode),
                 ("AE", &patron.name),
                 ("BH", self.sip_config().currency()),
-                ("BL", sip2::util::sip_bool(true)), // valid patron
-                ("BV", &format!("{:.2}", patron.balance_owed)),
-                ("CQ", sip2::util::sip_bool(patron.password_verified)),
+                ("BL", sip2::util::sip_bool(patron.valid)), // valid patron
+                ("BV", &patron.balance_owed.to_string()),
                 ("XI", &format!("{}", patron.id)),
             ],
         )
         .unwrap();
 
+        let cq = match patron.password_verified {
+            Some(v) => Some(v),
+            None => match self.account().cq_without_password() {
+                conf::CqWithoutPassword::Yes => Some(true),
+                conf::CqWithoutPassword::No => Some(false),
+                conf::CqWithoutPassword::Omit => None,
+            },
+        };
+
+        resp.maybe_add_field("CQ", cq.map(sip2::util::sip_bool));
+
         resp.maybe_add_field("BD", patron.address.as_deref());
         resp.maybe_add_field("BE", patron.email.as_deref());
 
+        if patron.card_expired {
+            let msg = self.account().settings().patron_expired_message();
+            resp.maybe_add_field("AF", msg.map(|m| self.tr(m)).as_deref());
+        }
+
+        // Penalty labels are translated when the catalog has them.
+        for msg in patron.block_messages.iter() {
+            resp.add_field("AF", &self.tr(msg));
+        }
+
         Ok(resp)
     }
 
     pub fn handle_end_patron_session(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
         let resp = sip2::Message::from_values(
             &sip2::spec::M_END_PATRON_SESSION_RESP,
-            &[sip2::util::sip_bool(true), &sip2::util::sip_date_now()],
+            &[sip2::util::sip_bool(true), &self.sip_date_now()],
             &[
                 ("AO", self.account().settings().institution()),
                 ("AA", msg.get_field_value("AA").unwrap_or("")),
@@ -1044,3 +1240,443 @@ impl Session {
         Ok(resp)
     }
 }
+
+/// A standing penalty applied to a patron.
+#[derive(Debug, Clone)]
+pub struct PatronPenalty {
+    pub id: i64,
+    pub name: String,
+    pub label: String,
+    /// Block tags, e.g. CIRC, RENEW, HOLD.
+    pub blocks: Vec<String>,
+}
+
+impl PatronPenalty {
+    fn from_value(value: &EgValue) -> EgResult<Self> {
+        Ok(PatronPenalty {
+            id: value.id()?,
+            name: value["name"].as_str().unwrap_or("").to_string(),
+            label: value["label"].as_str().unwrap_or("").to_string(),
+            blocks: value["block_list"]
+                .as_str()
+                .unwrap_or("")
+                .split('|')
+                .filter(|b| !b.is_empty())
+                .map(|b| b.to_string())
+                .collect(),
+        })
+    }
+
+    pub fn blocks(&self, tag: &str) -> bool {
+        self.blocks.iter().any(|b| b == tag)
+    }
+}
+
+/// Set the patron's hold, recall, circulation, and fine counts and
+/// ID lists from the fetched patron data.
+fn set_patron_summary_items(patron: &mut Patron, data: PatronData) {
+    patron.holds_count = data.hold_ids.len();
+    patron.hold_ids = data.hold_ids;
+    patron.unavail_holds_count = data.unavail_hold_ids.len();
+    patron.unavail_hold_ids = data.unavail_hold_ids;
+    patron.recall_count = data.recall_ids.len();
+    patron.recall_ids = data.recall_ids;
+
+    if let Some(summary) = data.circ_list {
+        // overdue and out are packaged as comma-separated ID values.
+        let overdue = circ_list_ids(&summary["overdue"]);
+        let outs = circ_list_ids(&summary["out"]);
+
+        // Charged items include overdue items.
+        patron.items_overdue_count = overdue.len();
+        patron.items_out_count = overdue.len() + outs.len();
+        patron.items_overdue_ids = overdue;
+        patron.items_out_ids = outs;
+    }
+
+    patron.fine_count = data.xacts.len();
+}
+
+fn circ_list_ids(ids: &EgValue) -> Vec<i64> {
+    ids.as_str()
+        .unwrap_or("")
+        .split(",")
+        .filter_map(|id| id.parse::<i64>().ok())
+        .filter(|id| id > &0)
+        .collect()
+}
+
+/// Query for IDs of a patron's holds which have not been fulfilled,
+/// canceled, or expired.
+///
+/// * `ready` - Some(true) limits the list to holds sitting on the
+///   hold shelf at the pickup library.  Some(false) limits the list
+///   to all other holds, including holds in transit to the pickup
+///   library.  None returns both.
+fn hold_ids_query(
+    patron_id: i64,
+    ready: Option<bool>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+) -> EgResult<EgValue> {
+    let mut search = eg::hash! {
+        usr: patron_id,
+        fulfillment_time: EG_NULL,
+        cancel_time: EG_NULL,
+        "-and": [{
+            "-or": [
+                {expire_time: EG_NULL},
+                {expire_time: {">": date::to_iso(&date::now())}}
+            ]
+        }]
+    };
+
+    match ready {
+        Some(true) => {
+            search["shelf_time"] = eg::hash! {"!=": EG_NULL};
+            search["current_shelf_lib"] = eg::hash! {"=": {"+ahr": "pickup_lib"}};
+        }
+        Some(false) => {
+            search["-and"].push(eg::hash! {
+                "-or": [
+                    {shelf_time: EG_NULL},
+                    {current_shelf_lib: EG_NULL},
+                    {current_shelf_lib: {"!=": {"+ahr": "pickup_lib"}}}
+                ]
+            })?;
+        }
+        None => {}
+    }
+
+    let mut query = eg::hash! {
+        select: {ahr: ["id"]},
+        from: "ahr",
+        where: {"+ahr": search},
+    };
+
+    if let Some(l) = limit {
+        query["limit"] = EgValue::from(l);
+    }
+    if let Some(o) = offset {
+        query["offset"] = EgValue::from(o);
+    }
+
+    Ok(query)
+}
+
+/// Query for IDs of recall holds on items charged to the patron.
+///
+/// These are open holds of type 'R', placed by other patrons, whose
+/// current copy is checked out to this patron.
+fn recall_ids_query(patron_id: i64) -> EgValue {
+    eg::hash! {
+        select: {ahr: ["id"]},
+        from: {
+            ahr: {
+                circ: {
+                    field: "target_copy",
+                    fkey: "current_copy",
+                }
+            }
+        },
+        where: {
+            "+ahr": {
+                hold_type: "R",
+                fulfillment_time: EG_NULL,
+                cancel_time: EG_NULL,
+            },
+            "+circ": {
+                usr: patron_id,
+                checkin_time: EG_NULL,
+            }
+        },
+        order_by: [{class: "ahr", field: "id"}],
+    }
+}
+
+/// Search and options for a patron's transactions with a balance.
+fn xacts_search(patron_id: i64, summary_ops: Option<&SummaryListOptions>) -> (EgValue, EgValue) {
+    let search = eg::hash! {
+        usr: patron_id,
+        balance_owed: {"<>": 0},
+        total_owed: {">": 0},
+    };
+
+    let mut ops = eg::hash! {
+        order_by: {mbts: "xact_start"}
+    };
+
+    if let Some(sum_ops) = summary_ops {
+        ops["limit"] = EgValue::from(sum_ops.limit());
+        ops["offset"] = EgValue::from(sum_ops.offset());
+    }
+
+    (search, ops)
+}
+
+fn to_ids(rows: Vec<EgValue>) -> EgResult<Vec<i64>> {
+    rows.iter().map(|r| r.id()).collect()
+}
+
+fn to_penalties(rows: Vec<EgValue>) -> EgResult<Vec<PatronPenalty>> {
+    rows.iter().map(PatronPenalty::from_value).collect()
+}
+
+/// Set the patron's status flags from their standing penalties.
+///
+/// Penalties which cause a block add their label to the patron's
+/// block messages.
+fn apply_penalties(patron: &mut Patron, penalties: &[PatronPenalty], permit_loans: bool) {
+    for pen in penalties {
+        match pen.id {
+            C::PENALTY_PATRON_EXCEEDS_FINES => patron.max_fines = true,
+            C::PENALTY_PATRON_EXCEEDS_OVERDUE_COUNT => patron.max_overdue = true,
+            C::PENALTY_PATRON_EXCEEDS_CHECKOUT_COUNT => patron.max_charged = true,
+            C::PENALTY_PATRON_EXCEEDS_LOST_COUNT => patron.max_lost = true,
+            _ => {}
+        }
+
+        let mut blocking = false;
+
+        if pen.blocks("HOLD") {
+            patron.holds_denied = true;
+            blocking = true;
+        }
+
+        if !permit_loans {
+            if pen.blocks("CIRC") {
+                patron.charge_denied = true;
+                blocking = true;
+            }
+            if pen.blocks("RENEW") {
+                patron.renew_denied = true;
+                blocking = true;
+            }
+        }
+
+        if !blocking {
+            continue;
+        }
+
+        log::debug!("Patron {} blocked by penalty {}", patron.barcode, pen.name);
+
+        if !pen.label.is_empty() && !patron.block_messages.contains(&pen.label) {
+            patron.block_messages.push(pen.label.to_string());
+        }
+    }
+}
+
+/// Block patrons owing more than the configured max fine, whether
+/// or not Evergreen has applied a fines penalty.
+fn apply_max_fine(patron: &mut Patron, max_fine: Money, permit_loans: bool) {
+    if patron.balance_owed <= max_fine {
+        return;
+    }
+
+    patron.max_fines = true;
+    patron.holds_denied = true;
+
+    if !permit_loans {
+        patron.charge_denied = true;
+        patron.renew_denied = true;
+    }
+
+    if patron.block_messages.is_empty() {
+        patron.block_messages.push(MAX_FINE_BLOCK_MSG.to_string());
+    }
+}
+
+/// True if a patron card with this expire date has expired.
+///
+/// Expire dates carry a time zone offset and are compared with now
+/// as points in time, so the result does not depend on the time zone
+/// of either value.  Dates without a time expire at the start of the
+/// day, local time.
+fn card_expired(expire_date: &str, now: &date::EgDate) -> EgResult<bool> {
+    Ok(date::parse_datetime(expire_date)? < *now)
+}
+
+/// Replace {name} placeholders in a hold template with their values.
+///
+/// Unknown placeholders are left as-is.
+fn format_hold_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut formatted = template.to_string();
+
+    for (name, value) in values {
+        formatted = formatted.replace(&format!("{{{name}}}"), value);
+    }
+
+    formatted
+}
+
+#[test]
+fn test_apply_penalties() {
+    let penalty = |id, label: &str, blocks: &[&str]| PatronPenalty {
+        id,
+        name: String::new(),
+        label: label.to_string(),
+        blocks: blocks.iter().map(|b| b.to_string()).collect(),
+    };
+
+    let penalties = [
+        penalty(
+            C::PENALTY_PATRON_EXCEEDS_FINES,
+            "Patron exceeds fine threshold",
+            &["CIRC", "HOLD", "RENEW"],
+        ),
+        penalty(
+            C::PENALTY_PATRON_EXCEEDS_OVERDUE_COUNT,
+            "Patron exceeds overdue threshold",
+            &["CIRC", "RENEW"],
+        ),
+        penalty(20, "Alerting note", &[]),
+    ];
+
+    let mut patron = Patron::new("patron", "Patron".to_string());
+    apply_penalties(&mut patron, &penalties, false);
+
+    assert!(patron.max_fines);
+    assert!(patron.max_overdue);
+    assert!(!patron.max_charged);
+    assert!(patron.charge_denied);
+    assert!(patron.renew_denied);
+    assert!(patron.holds_denied);
+    assert_eq!(
+        patron.block_messages,
+        [
+            "Patron exceeds fine threshold",
+            "Patron exceeds overdue threshold"
+        ]
+    );
+
+    // Only the hold block applies with permit-loans.
+    let mut patron = Patron::new("patron", "Patron".to_string());
+    apply_penalties(&mut patron, &penalties[1..], true);
+
+    assert!(patron.max_overdue);
+    assert!(!patron.charge_denied);
+    assert!(!patron.holds_denied);
+    assert!(patron.block_messages.is_empty());
+
+    // Fines over the configured max block the patron without a penalty.
+    let mut patron = Patron::new("patron", "Patron".to_string());
+    patron.balance_owed = Money::from_cents(1001);
+    apply_max_fine(&mut patron, Money::from_cents(1000), false);
+
+    assert!(patron.max_fines);
+    assert!(patron.charge_denied);
+    assert_eq!(patron.block_messages, [MAX_FINE_BLOCK_MSG]);
+
+    let mut patron = Patron::new("patron", "Patron".to_string());
+    patron.balance_owed = Money::from_cents(1000);
+    apply_max_fine(&mut patron, Money::from_cents(1000), false);
+
+    assert!(!patron.max_fines);
+    assert!(!patron.charge_denied);
+}
+
+#[test]
+fn test_card_expired() {
+    let now = date::parse_datetime("2024-03-01T12:00:00-0500").unwrap();
+
+    assert!(card_expired("2024-03-01T11:59:59-0500", &now).unwrap());
+    assert!(!card_expired("2024-03-01T12:00:01-0500", &now).unwrap());
+
+    // Same instant as now in another time zone.
+    assert!(!card_expired("2024-03-01T17:00:00+0000", &now).unwrap());
+    assert!(card_expired("2024-03-01T16:59:59+0000", &now).unwrap());
+
+    // Later wall clock time, but an earlier instant.
+    assert!(card_expired("2024-03-01T13:00:00-0300", &now).unwrap());
+
+    assert!(card_expired("2024-03-01 HOWDY", &now).is_err());
+}
+
+#[test]
+fn test_format_hold_template() {
+    let values = [
+        ("title", "Moby Dick"),
+        ("pickup_lib", "BR1"),
+        ("shelf_expire", "2024-05-01"),
+    ];
+
+    assert_eq!(
+        format_hold_template("{title} at {pickup_lib} until {shelf_expire}", &values),
+        "Moby Dick at BR1 until 2024-05-01"
+    );
+
+    assert_eq!(
+        format_hold_template("{barcode} {title}", &values),
+        "{barcode} Moby Dick"
+    );
+}
+
+#[test]
+fn test_summary_list_paging() {
+    let ops = |start_item, end_item| SummaryListOptions {
+        list_type: SummaryListType::ChargedItems,
+        start_item,
+        end_item,
+    };
+
+    let ids: Vec<i64> = (1..=25).collect();
+
+    // No range requested returns the default page size.
+    assert_eq!(ops(None, None).page(&ids), &ids[0..10]);
+
+    // Start and end items are 1-based and inclusive.
+    assert_eq!(ops(Some(1), Some(5)).page(&ids), &[1, 2, 3, 4, 5]);
+    assert_eq!(ops(Some(3), Some(4)).page(&ids), &[3, 4]);
+
+    // Ranges extending past the end of the list are trimmed.
+    assert_eq!(ops(Some(24), Some(30)).page(&ids), &[24, 25]);
+    assert!(ops(Some(30), Some(40)).page(&ids).is_empty());
+
+    // Inverted ranges return nothing.
+    assert!(ops(Some(5), Some(2)).page(&ids).is_empty());
+}
+
+#[test]
+fn test_set_patron_summary_items() {
+    let mut patron = Patron::new("1234", "Test".to_string());
+
+    let data = PatronData {
+        circ_list: Some(eg::hash! {overdue: "5,6", out: "7,,8,9"}),
+        xacts: vec![eg::hash! {id: 1}],
+        hold_ids: vec![10, 11],
+        unavail_hold_ids: vec![12],
+        recall_ids: vec![13],
+        ..Default::default()
+    };
+
+    set_patron_summary_items(&mut patron, data);
+
+    assert_eq!(patron.items_overdue_ids, [5, 6]);
+    assert_eq!(patron.items_out_ids, [7, 8, 9]);
+    assert_eq!(patron.items_out_count, 5);
+    assert_eq!(patron.items_overdue_count, 2);
+    assert_eq!(patron.fine_count, 1);
+    assert_eq!(patron.holds_count, 2);
+    assert_eq!(patron.unavail_holds_count, 1);
+    assert_eq!(patron.recall_count, 1);
+
+    // Patrons with nothing checked out have no circ list.
+    let mut patron = Patron::new("1234", "Test".to_string());
+    set_patron_summary_items(&mut patron, PatronData::default());
+    assert_eq!(patron.items_out_count, 0);
+}
+
+#[test]
+fn test_recall_ids_query() {
+    let query = recall_ids_query(42);
+
+    // Recalls are found through the patron's open circulations, not
+    // the patron's own holds, which are reported as hold items.
+    assert!(!query["where"]["+ahr"].has_key("usr"));
+    assert_eq!(query["where"]["+circ"]["usr"].as_int(), Some(42));
+    assert!(query["where"]["+circ"].has_key("checkin_time"));
+    assert_eq!(
+        query["from"]["ahr"]["circ"]["fkey"].as_str(),
+        Some("current_copy")
+    );
+}
@@ -1,13 +1,25 @@
 use super::patron::Patron;
 use super::session::Session;
-use eg::result::EgResult;
+use eg::bindings::{ApiResponse, PaymentRequest};
+use eg::common::trigger;
+use eg::money::Money;
+use eg::result::{EgError, EgResult};
 use eg::EgValue;
 use evergreen as eg;
 
+/// A/T hook for emailed payment receipts.
+const PAYMENT_RECEIPT_HOOK: &str = "money.format.payment_receipt.email";
+
 pub struct PaymentResult {
     success: bool,
     patron_barcode: String,
     screen_msg: Option<String>,
+    /// (transaction ID, amount) of each applied payment.
+    payments: Vec<(i64, Money)>,
+    /// IDs of the payments created, if the payment API reported them.
+    payment_ids: Vec<i64>,
+    /// Patron balance after the payment was applied.
+    balance: Option<Money>,
 }
 
 impl PaymentResult {
@@ -16,6 +28,9 @@ impl PaymentResult {
             success: false,
             screen_msg: None,
             patron_barcode: patron_barcode.to_string(),
+            payments: Vec::new(),
+            payment_ids: Vec::new(),
+            balance: None,
         }
     }
 }
@@ -42,9 +57,9 @@ impl Session {
             }
         };
 
-        let pay_amount: f64 = match pay_amount_str.parse() {
-            Ok(v) => v,
-            Err(_) => {
+        let pay_amount = match Money::parse(pay_amount_str) {
+            Ok(v) if !v.is_negative() => v,
+            _ => {
                 log::error!("Invalid payment amount: '{pay_amount_str}'");
                 return Ok(self.compile_payment_response(&result));
             }
@@ -53,7 +68,7 @@ impl Session {
         // credit card, cash, etc.
         let pay_type = msg.fixed_fields()[2].value();
 
-        let terminal_xact_op = msg.get_field_value("BK"); // optional
+        let cc_args = collect_cc_args(msg, self.account().settings().cc_arg_fields());
 
         // Envisionware extensions for relaying information about
         // payments made via credit card kiosk or cash register.
@@ -72,7 +87,7 @@ impl Session {
         let mut user = cards[0]["usr"].take();
         user["card"] = cards.remove(0);
 
-        let payments: Vec<(i64, f64)>;
+        let payments: Vec<(i64, Money)>;
 
         // Caller can request to pay toward a specific transaction or have
         // the back-end select transactions to pay.
@@ -96,23 +111,41 @@ impl Session {
             &user,
             &mut result,
             pay_type,
-            terminal_xact_op,
+            &cc_args,
             check_number_op,
             register_login_op,
-            payments,
+            &payments,
         )?;
 
+        if result.success {
+            result.payments = payments;
+
+            let settings = self.account().settings();
+            if settings.payment_receipt_balance() || settings.payment_receipt_summary() {
+                result.balance = Some(self.get_balance(user.id()?)?);
+            }
+
+            if self.account().settings().payment_receipt_email() {
+                // The payment stands regardless of the receipt.
+                if let Err(e) = self.send_payment_receipt(&result.payment_ids) {
+                    log::warn!("{self} could not create payment receipt event: {e}");
+                }
+            }
+        }
+
         Ok(self.compile_payment_response(&result))
     }
 
+    /// Payment response for a payment which was not applied.
+    pub fn payment_failed(&self, patron_barcode: &str) -> sip2::Message {
+        self.compile_payment_response(&PaymentResult::new(patron_barcode))
+    }
+
     /// Create the SIP response message
     fn compile_payment_response(&self, result: &PaymentResult) -> sip2::Message {
         let mut resp = sip2::Message::from_values(
             &sip2::spec::M_FEE_PAID_RESP,
-            &[
-                sip2::util::sip_bool(result.success),
-                &sip2::util::sip_date_now(),
-            ],
+            &[sip2::util::sip_bool(result.success), &self.sip_date_now()],
             &[
                 ("AA", &result.patron_barcode),
                 ("AO", self.account().settings().institution()),
@@ -120,20 +153,107 @@ impl Session {
         )
         .unwrap();
 
-        resp.maybe_add_field("AF", result.screen_msg.as_deref());
+        resp.maybe_add_field(
+            "AF",
+            result.screen_msg.as_deref().map(|m| self.tr(m)).as_deref(),
+        );
+
+        if !result.success {
+            return resp;
+        }
+
+        let settings = self.account().settings();
+
+        if settings.payment_receipt_balance() {
+            if let Some(balance) = result.balance {
+                resp.add_field("BV", &balance.to_string());
+            }
+        }
+
+        if settings.payment_receipt_xact_ids() && !result.payments.is_empty() {
+            let ids: Vec<String> = result.payments.iter().map(|p| p.0.to_string()).collect();
+            resp.add_field("CG", &ids.join(","));
+        }
+
+        if settings.payment_receipt_summary() {
+            for line in self.payment_receipt_lines(result) {
+                resp.add_field("AG", &line);
+            }
+        }
 
         resp
     }
 
+    /// Print lines summarizing a payment for receipts.
+    fn payment_receipt_lines(&self, result: &PaymentResult) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut total = Money::default();
+
+        for (xact_id, amount) in result.payments.iter() {
+            total += *amount;
+            lines.push(self.tr_args(
+                "Paid {0} on transaction {1}",
+                &[&amount.to_string(), &xact_id.to_string()],
+            ));
+        }
+
+        lines.push(self.tr_args("Total paid: {0}", &[&total.to_string()]));
+
+        if let Some(balance) = result.balance {
+            lines.push(self.tr_args("Remaining balance: {0}", &[&balance.to_string()]));
+        }
+
+        lines
+    }
+
+    /// Create events for the payment receipt A/T hook, which the A/T
+    /// runner turns into an email to the patron.
+    fn send_payment_receipt(&mut self, payment_ids: &[i64]) -> EgResult<()> {
+        if payment_ids.is_empty() {
+            log::warn!("{self} payment API did not report payment IDs; no receipt sent");
+            return Ok(());
+        }
+
+        let org_id = self.get_ws_org_id()?;
+
+        let payments = self
+            .editor_mut()
+            .search("mp", eg::hash! {id: payment_ids.to_vec()})?;
+
+        let created = trigger::create_events_for_hook(
+            self.editor_mut(),
+            PAYMENT_RECEIPT_HOOK,
+            &EgValue::from(payments),
+            org_id,
+            None,
+            None,
+            false,
+        )?;
+
+        let count: usize = created.iter().map(|h| h.events().len()).sum();
+
+        log::info!("{self} created {count} payment receipt event(s) for payments {payment_ids:?}");
+
+        Ok(())
+    }
+
+    /// Total balance owed by a patron.
+    fn get_balance(&mut self, user_id: i64) -> EgResult<Money> {
+        match self.editor_mut().retrieve("mous", user_id)? {
+            Some(summary) => Money::from_value(&summary["balance_owed"]),
+            None => Ok(Money::default()),
+        }
+    }
+
     /// Caller wants to pay a specific transaction by ID.  Make sure that's
     /// a viable choice.
     fn compile_one_xact(
         &mut self,
         user: &EgValue,
         xact_id: i64,
-        pay_amount: f64,
+        pay_amount: Money,
         result: &mut PaymentResult,
-    ) -> EgResult<Vec<(i64, f64)>> {
+    ) -> EgResult<Vec<(i64, Money)>> {
         let sum = match self.editor_mut().retrieve("mbts", xact_id)? {
             Some(s) => s,
             None => {
@@ -142,12 +262,14 @@ impl Session {
             }
         };
 
-        if sum["usr"].int()? != user.id()? {
+        if sum.field_id("usr")? != Some(user.id()?) {
             log::warn!("{self} Payment transaction {xact_id} does not link to provided user");
             return Ok(Vec::new());
         }
 
-        if pay_amount > sum["balance_owed"].float()? {
+        let balance_owed = Money::from_value_opt(&sum["balance_owed"])?.unwrap_or_default();
+
+        if pay_amount > balance_owed {
             result.screen_msg = Some("Overpayment not allowed".to_string());
             return Ok(Vec::new());
         }
@@ -159,10 +281,9 @@ impl Session {
     fn compile_multi_xacts(
         &mut self,
         user: &EgValue,
-        pay_amount: f64,
+        pay_amount: Money,
         result: &mut PaymentResult,
-    ) -> EgResult<Vec<(i64, f64)>> {
-        let mut payments: Vec<(i64, f64)> = Vec::new();
+    ) -> EgResult<Vec<(i64, Money)>> {
         let mut patron = Patron::new(&result.patron_barcode, self.format_user_name(&user));
 
         patron.id = user.id()?;
@@ -171,54 +292,29 @@ impl Session {
 
         if xacts.len() == 0 {
             result.screen_msg = Some("No transactions to pay".to_string());
-            return Ok(payments);
+            return Ok(Vec::new());
         }
 
-        let mut amount_remaining = pay_amount;
+        let mut balances = Vec::new();
         for xact in xacts {
-            let xact_id = xact.id()?;
-            let balance_owed = xact["balance_owed"].float()?;
-
-            if balance_owed < 0.0 {
-                continue;
-            }
-
-            let payment;
+            let balance_owed = Money::from_value_opt(&xact["balance_owed"])?.unwrap_or_default();
+            balances.push((xact.id()?, balance_owed));
+        }
 
-            if balance_owed >= amount_remaining {
-                // We owe as much or more than the amount of money
-                // we have left to distribute.  Pay what we can.
-                payment = amount_remaining;
-                amount_remaining = 0.0;
-            } else {
-                // Less is owed on this transaction than we have to
-                // distribute, so pay the full amount on this one.
-                payment = balance_owed;
-                amount_remaining = (amount_remaining * 100.00 - balance_owed * 100.00) / 100.00;
+        match distribute_payment(&balances, pay_amount) {
+            Some(payments) => {
+                for (xact_id, payment) in payments.iter() {
+                    log::info!("{self} applying payment of {payment} for xact {xact_id}");
+                }
+                Ok(payments)
             }
-
-            log::info!(
-                "{self} applying payment of {:.2} for xact {} with a
-                transaction balance of {:.2} and amount remaining {:.2}",
-                payment,
-                xact_id,
-                balance_owed,
-                amount_remaining
-            );
-
-            payments.push((xact_id, payment));
-
-            if amount_remaining == 0.0 {
-                break;
+            None => {
+                // Apply nothing instead of accepting the money and
+                // only applying part of it.
+                result.screen_msg = Some("Overpayment not allowed".to_string());
+                Ok(Vec::new())
             }
         }
-
-        if amount_remaining > 0.0 {
-            result.screen_msg = Some("Overpayment not allowed".to_string());
-            return Ok(payments);
-        }
-
-        Ok(payments)
     }
 
     /// Send payment data to the server for processing.
@@ -227,10 +323,10 @@ impl Session {
         user: &EgValue,
         result: &mut PaymentResult,
         pay_type: &str,
-        terminal_xact_op: Option<&str>,
+        cc_args: &[(String, String)],
         check_number_op: Option<&str>,
         register_login_op: Option<&str>,
-        payments: Vec<(i64, f64)>,
+        payments: &[(i64, Money)],
     ) -> EgResult<()> {
         log::info!("{self} applying payments: {payments:?}");
 
@@ -253,64 +349,278 @@ impl Session {
             String::from("VIA SIP2")
         };
 
-        let mut pay_array = eg::array![];
-        for p in payments {
-            let sub_array = eg::array![p.0, p.1];
-            pay_array.push(sub_array).ok();
-        }
-
-        let mut args = eg::hash! {
-            userid: user.id()?,
-            note: note,
-            payments: pay_array,
-        };
+        let user_id = user.id()?;
+        let payment_type = self.account().settings().payment_type(pay_type).to_string();
+        let mut relogin_attempted = false;
 
-        match pay_type {
-            "01" | "02" => {
-                // '01' is "VISA"; '02' is "credit card"
+        loop {
+            let mut req = PaymentRequest::new(self.authtoken()?);
+            req.set_user_id(user_id)
+                .set_note(&note)
+                .set_payment_type(&payment_type);
 
-                args["cc_args"]["terminal_xact"] = match terminal_xact_op {
-                    Some(tx) => EgValue::from(tx),
-                    None => EgValue::from("Not provided by SIP client"),
-                };
+            for (xact_id, amount) in payments.iter() {
+                req.add_payment(*xact_id, &amount.to_string());
+            }
 
-                args["payment_type"] = EgValue::from("credit_card_payment");
+            match payment_type.as_str() {
+                "credit_card_payment" => {
+                    for (key, value) in cc_args {
+                        req.set_cc_arg(key, value);
+                    }
+                }
+                "check_payment" => {
+                    req.set_check_number(check_number_op.unwrap_or("Not provided by SIP client"));
+                }
+                _ => {}
             }
 
-            "05" => {
-                // Check payment
-                args["payment_type"] = EgValue::from("check_payment");
-                args["check_number"] = match check_number_op {
-                    Some(s) => EgValue::from(s),
-                    None => EgValue::from("Not provided by SIP client"),
-                };
+            // Always use the most recent last_xact_id, since it may
+            // have changed since the user was retrieved.
+            let last_xact_id = self.get_last_xact_id(user_id)?;
+            req.set_last_xact_id(&last_xact_id);
+
+            // The payment API applies every payment in one transaction
+            // of its own on the server.  We hold no editor transaction
+            // here, so there is nothing to wrap in xact_guard() or
+            // per-payment savepoints; a bad payment fails the whole
+            // call instead.
+            let resp = match self.execute(&req) {
+                Ok(r) => r,
+                Err(EgError::Timeout(msg)) => {
+                    // No response arrived in time.  The payment may
+                    // still have been applied, so don't report failure.
+                    log::error!("{self} Payment API timed out: {msg}");
+                    result.screen_msg =
+                        Some("Payment status is unknown; please see staff".to_string());
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+
+            let evt = match resp {
+                ApiResponse::Events(_) => match resp.event() {
+                    Some(e) => e.clone(),
+                    None => {
+                        log::error!("{self} Payment API returned an empty event list");
+                        result.screen_msg = Some("Payment could not be completed".to_string());
+                        return Ok(());
+                    }
+                },
+                ApiResponse::Success(outcome) => {
+                    // A non-event response contains the list of payment IDs.
+                    let applied = match outcome.payment_ids {
+                        Some(ids) => {
+                            let applied = ids.len();
+                            result.payment_ids = ids;
+                            applied
+                        }
+                        None => payments.len(),
+                    };
+
+                    if applied == payments.len() {
+                        result.success = true;
+                    } else {
+                        log::error!(
+                            "{self} Payment API applied {applied} of {} payments",
+                            payments.len()
+                        );
+                        result.screen_msg = Some(self.tr_args(
+                            "Only {0} of {1} payments were applied",
+                            &[&applied.to_string(), &payments.len().to_string()],
+                        ));
+                    }
+
+                    return Ok(());
+                }
+            };
+
+            if evt.textcode().eq("NO_SESSION") && !relogin_attempted {
+                // Our authtoken expired.  Login again and retry.
+                log::info!("{self} Payment API returned NO_SESSION; retrying after login");
+                relogin_attempted = true;
+                self.set_authtoken()?;
+                continue;
             }
-            _ => {
-                args["payment_type"] = EgValue::from("cash_payment");
+
+            if evt.textcode().eq("INCOMPLETE") {
+                // Some payments may have been applied before the
+                // failure, so the patron's balance is uncertain.
+                log::error!("{self} Payment API returned INCOMPLETE: {evt}");
+                result.screen_msg =
+                    Some("Payment was not fully applied; please see staff".to_string());
+                return Ok(());
             }
+
+            log::warn!("{self} Payment failed with event: {evt}");
+
+            result.screen_msg = match evt.desc() {
+                Some(d) => Some(d.to_string()),
+                None => Some(evt.textcode().to_string()),
+            };
+
+            return Ok(());
         }
+    }
 
-        let authtoken = EgValue::from(self.authtoken()?);
-        let last_xact_id = user["last_xact_id"].as_str().unwrap(); // required
+    /// Fetch the current last_xact_id for a user.
+    ///
+    /// The payment API requires this value to match the user's
+    /// current value to prevent duplicate payments.
+    fn get_last_xact_id(&mut self, user_id: i64) -> EgResult<String> {
+        let user = self
+            .editor_mut()
+            .retrieve("au", user_id)?
+            .ok_or_else(|| format!("No such user: {user_id}"))?;
+
+        match user.field_str("last_xact_id")? {
+            Some(id) => Ok(id.to_string()),
+            None => Err(format!("User {user_id} has no last_xact_id").into()),
+        }
+    }
+}
 
-        let resp = self.osrf_client_mut().send_recv_one(
-            "open-ils.circ",
-            "open-ils.circ.money.payment",
-            vec![authtoken, args, EgValue::from(last_xact_id)],
-        )?;
+/// Credit card payment details (cc_args) from a Fee Paid message.
+///
+/// The card was processed by the SIP client, so the terminal
+/// transaction ID doubles as the approval code unless the approval
+/// code arrives in a mapped extension field.
+fn collect_cc_args(msg: &sip2::Message, fields: &[(String, String)]) -> Vec<(String, String)> {
+    let terminal_xact = msg
+        .get_field_value("BK")
+        .unwrap_or("Not provided by SIP client");
+
+    let mut cc_args = vec![
+        ("terminal_xact".to_string(), terminal_xact.to_string()),
+        ("approval_code".to_string(), terminal_xact.to_string()),
+    ];
+
+    for (key, code) in fields {
+        if let Some(value) = msg.get_field_value(code) {
+            cc_args.retain(|(k, _)| k != key);
+            cc_args.push((key.to_string(), value.to_string()));
+        }
+    }
 
-        let resp = resp.ok_or_else(|| format!("Payment API returned no response"))?;
+    cc_args
+}
 
-        if let Some(evt) = eg::event::EgEvent::parse(&resp) {
-            if let Some(d) = evt.desc() {
-                result.screen_msg = Some(d.to_string());
-            } else {
-                result.screen_msg = Some(evt.textcode().to_string());
-            }
-        } else {
-            result.success = true;
+/// Spread a payment across transactions in the order provided.
+///
+/// Takes a list of (transaction ID, balance owed) and returns a list
+/// of (transaction ID, payment amount).
+///
+/// Returns None if the payment exceeds the total amount owed.
+fn distribute_payment(balances: &[(i64, Money)], pay_amount: Money) -> Option<Vec<(i64, Money)>> {
+    let mut payments = Vec::new();
+    let mut amount_remaining = pay_amount;
+
+    for (xact_id, balance_owed) in balances {
+        if amount_remaining.is_zero() {
+            break;
         }
 
-        Ok(())
+        if !balance_owed.is_positive() {
+            continue;
+        }
+
+        // Pay the full balance or whatever we have left.
+        let payment = amount_remaining.min(*balance_owed);
+
+        amount_remaining -= payment;
+        payments.push((*xact_id, payment));
+    }
+
+    if amount_remaining.is_positive() {
+        None
+    } else {
+        Some(payments)
     }
 }
+
+#[cfg(test)]
+fn cents(list: &[(i64, i64)]) -> Vec<(i64, Money)> {
+    list.iter()
+        .map(|(id, c)| (*id, Money::from_cents(*c)))
+        .collect()
+}
+
+#[test]
+fn test_distribute_payment_three_xacts() {
+    let balances = cents(&[(1, 500), (2, 250), (3, 1000)]);
+
+    let payments = distribute_payment(&balances, Money::from_cents(1200)).unwrap();
+    assert_eq!(payments, cents(&[(1, 500), (2, 250), (3, 450)]));
+
+    let total: Money = payments.iter().map(|p| p.1).sum();
+    assert_eq!(total.to_string(), "12.00");
+}
+
+#[test]
+fn test_distribute_payment_sub_cent_edge() {
+    let balances = [
+        (1, Money::from_f64(3.37).unwrap()),
+        (2, Money::from_f64(3.37).unwrap()),
+        (3, Money::from_f64(3.36).unwrap()),
+    ];
+
+    let payments = distribute_payment(&balances, Money::parse("10.10").unwrap()).unwrap();
+    assert_eq!(payments, cents(&[(1, 337), (2, 337), (3, 336)]));
+}
+
+#[test]
+fn test_distribute_payment_overpayment_boundary() {
+    let balances = cents(&[(1, 337), (2, -100), (3, 336)]);
+
+    // Exactly the amount owed.  Negative balances are skipped.
+    let payments = distribute_payment(&balances, Money::from_cents(673)).unwrap();
+    assert_eq!(payments, cents(&[(1, 337), (3, 336)]));
+
+    // One cent too much.
+    assert_eq!(distribute_payment(&balances, Money::from_cents(674)), None);
+}
+
+#[test]
+fn test_collect_cc_args() {
+    let msg = sip2::Message::from_values(
+        &sip2::spec::M_FEE_PAID,
+        &["20240101    120000", "01", "02", "USD"],
+        &[
+            ("AA", "2100"),
+            ("BV", "5.00"),
+            ("BK", "T123"),
+            ("ZA", "A987"),
+            ("ZC", "4242"),
+        ],
+    )
+    .unwrap();
+
+    let fields = vec![
+        ("approval_code".to_string(), "ZA".to_string()),
+        ("processor".to_string(), "ZB".to_string()),
+        ("number".to_string(), "ZC".to_string()),
+    ];
+
+    let mut cc_args = collect_cc_args(&msg, &fields);
+    cc_args.sort();
+
+    let expect = [
+        ("approval_code", "A987"),
+        ("number", "4242"),
+        ("terminal_xact", "T123"),
+    ];
+
+    let expect: Vec<(String, String)> = expect
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    assert_eq!(cc_args, expect);
+
+    // Without mappings the terminal transaction ID is the approval code.
+    let cc_args = collect_cc_args(&msg, &[]);
+    assert_eq!(
+        cc_args[1],
+        ("approval_code".to_string(), "T123".to_string())
+    );
+}
@@ -0,0 +1,596 @@
+//! SIP request rate limiting.
+//!
+//! Each Session has its own token bucket and each SIP account has a
+//! bucket shared by all of its Sessions.  Requests which exceed a limit
+//! by a little are delayed.  Requests which would have to wait longer
+//! than max-delay-ms are rejected, and a Session which is rejected
+//! max-rejections times in a row is disconnected.
+use super::session::Session;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default longest we'll delay a request before rejecting it.
+const DEFAULT_MAX_DELAY_MS: u64 = 1000;
+
+/// Default consecutive rejections before we disconnect a client.
+const DEFAULT_MAX_REJECTIONS: usize = 10;
+
+/// Screen message returned for rejected requests.
+pub const RATE_LIMIT_SCREEN_MSG: &str = "Too many requests.  Please wait and try again.";
+
+/// Requests per second with a burst allowance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+impl Rate {
+    pub fn new(per_second: f64, burst: f64) -> Result<Rate, String> {
+        if per_second <= 0.0 || !per_second.is_finite() {
+            return Err(format!("Invalid requests-per-second: {per_second}"));
+        }
+
+        // A burst below 1 would never allow a request.
+        Ok(Rate {
+            per_second,
+            burst: burst.max(1.0),
+        })
+    }
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+    pub fn burst(&self) -> f64 {
+        self.burst
+    }
+}
+
+/// Rate limit settings, defined globally with per-account overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    session: Option<Rate>,
+    account: Option<Rate>,
+    max_delay: Duration,
+    max_rejections: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            session: None,
+            account: None,
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+            max_rejections: DEFAULT_MAX_REJECTIONS,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Apply settings from a rate-limit config block.  Values which
+    /// are not present are left unchanged.
+    ///
+    /// ```yaml
+    /// rate-limit:
+    ///   requests-per-second: 5
+    ///   burst: 10
+    ///   account-requests-per-second: 20
+    ///   account-burst: 40
+    ///   max-delay-ms: 1000
+    ///   max-rejections: 10
+    /// ```
+    ///
+    /// A requests-per-second of 0 disables the limit.
+    pub fn apply_yaml(&mut self, node: &yaml_rust::Yaml) -> Result<(), String> {
+        self.session = apply_rate(node, "requests-per-second", "burst", self.session)?;
+
+        self.account = apply_rate(
+            node,
+            "account-requests-per-second",
+            "account-burst",
+            self.account,
+        )?;
+
+        if let Some(v) = node["max-delay-ms"].as_i64() {
+            self.max_delay = Duration::from_millis(v.max(0) as u64);
+        }
+
+        if let Some(v) = node["max-rejections"].as_i64() {
+            self.max_rejections = v.max(1) as usize;
+        }
+
+        Ok(())
+    }
+
+    /// Limit for each Session.
+    pub fn session(&self) -> Option<Rate> {
+        self.session
+    }
+    /// Limit shared by all Sessions of a SIP account.
+    pub fn account(&self) -> Option<Rate> {
+        self.account
+    }
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+    pub fn max_rejections(&self) -> usize {
+        self.max_rejections
+    }
+}
+
+/// Numbers may be written as integers or decimals.
+fn yaml_number(node: &yaml_rust::Yaml) -> Option<f64> {
+    node.as_f64().or_else(|| node.as_i64().map(|v| v as f64))
+}
+
+fn apply_rate(
+    node: &yaml_rust::Yaml,
+    rate_key: &str,
+    burst_key: &str,
+    current: Option<Rate>,
+) -> Result<Option<Rate>, String> {
+    let per_second = match yaml_number(&node[rate_key]) {
+        Some(0.0) => return Ok(None),
+        Some(v) => v,
+        None => match current {
+            Some(r) => r.per_second,
+            None => return Ok(None),
+        },
+    };
+
+    let burst = yaml_number(&node[burst_key])
+        .or(current.map(|r| r.burst))
+        .unwrap_or(per_second);
+
+    Rate::new(per_second, burst)
+        .map(Some)
+        .map_err(|e| format!("{rate_key}: {e}"))
+}
+
+/// Token bucket which allows a burst of requests, then refills at
+/// a steady rate.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: Rate,
+    /// Goes negative when requests are admitted after a delay.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A new bucket starts full.
+    pub fn new(rate: Rate, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate.burst(),
+            updated: now,
+        }
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second()).min(self.rate.burst());
+        self.updated = now;
+    }
+
+    /// How long a request arriving now has to wait for a token.
+    pub fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate.per_second())
+        }
+    }
+
+    /// Take a token for a request which will wait as needed.
+    pub fn consume(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// Per-account token buckets shared by all Sessions.  Cloning is cheap.
+#[derive(Debug, Clone, Default)]
+pub struct AccountRateLimits {
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+impl AccountRateLimits {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Run a function with the account's bucket while holding the
+    /// lock, so checking and taking a token happen together.
+    ///
+    /// The bucket is replaced if the account's rate has changed.
+    fn with_bucket<T>(
+        &self,
+        username: &str,
+        rate: Rate,
+        now: Instant,
+        f: impl FnOnce(&mut TokenBucket) -> T,
+    ) -> T {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets
+            .entry(username.to_string())
+            .or_insert_with(|| TokenBucket::new(rate, now));
+
+        if bucket.rate() != rate {
+            *bucket = TokenBucket::new(rate, now);
+        }
+
+        f(bucket)
+    }
+}
+
+/// Source of time for rate limiting, replaceable for testing.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateDecision {
+    /// Handle the request, which was delayed this long.
+    Proceed(Duration),
+    /// Refuse the request.
+    Reject,
+    /// Too many rejections in a row.  Drop the connection.
+    Disconnect,
+}
+
+/// Rate limits applied to a single Session.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Option<TokenBucket>,
+    account_limits: AccountRateLimits,
+    /// Consecutive rejected requests.
+    rejections: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        config: &RateLimitConfig,
+        account_limits: AccountRateLimits,
+        clock: Arc<dyn Clock>,
+    ) -> RateLimiter {
+        let mut limiter = RateLimiter {
+            config: RateLimitConfig::default(),
+            bucket: None,
+            account_limits,
+            rejections: 0,
+            clock,
+        };
+
+        limiter.configure(config);
+        limiter
+    }
+
+    /// Apply new settings, e.g. the overrides for an account after
+    /// the client logs in.
+    pub fn configure(&mut self, config: &RateLimitConfig) {
+        if self.config.session() != config.session() || self.bucket.is_none() {
+            let now = self.clock.now();
+            self.bucket = config.session().map(|r| TokenBucket::new(r, now));
+        }
+
+        self.config = config.clone();
+    }
+
+    /// Wait as needed for a new request to be allowed.
+    ///
+    /// The account limit applies if an account username is provided.
+    pub fn pace(&mut self, username: Option<&str>) -> RateDecision {
+        let now = self.clock.now();
+        let max_delay = self.config.max_delay();
+
+        let mut session_wait = match self.bucket.as_mut() {
+            Some(b) => b.wait_time(now),
+            None => Duration::ZERO,
+        };
+
+        let mut admitted = session_wait <= max_delay;
+
+        if let (true, Some(name), Some(rate)) = (admitted, username, self.config.account()) {
+            self.account_limits.with_bucket(name, rate, now, |bucket| {
+                let wait = bucket.wait_time(now).max(session_wait);
+
+                if wait <= max_delay {
+                    bucket.consume();
+                    session_wait = wait;
+                } else {
+                    admitted = false;
+                }
+            });
+        }
+
+        if !admitted {
+            self.rejections += 1;
+
+            if self.rejections >= self.config.max_rejections() {
+                return RateDecision::Disconnect;
+            }
+
+            return RateDecision::Reject;
+        }
+
+        if let Some(b) = self.bucket.as_mut() {
+            b.consume();
+        }
+
+        self.rejections = 0;
+
+        if !session_wait.is_zero() {
+            self.clock.sleep(session_wait);
+        }
+
+        RateDecision::Proceed(session_wait)
+    }
+}
+
+impl Session {
+    /// Rate limits for this Session, including any account overrides.
+    pub fn rate_limit_config(&self) -> &RateLimitConfig {
+        match self.has_account() {
+            true => self.account().rate_limit(),
+            false => self.sip_config().rate_limit(),
+        }
+    }
+
+    /// Wait as needed for our client's latest request to be allowed
+    /// by the session and account rate limits.
+    pub fn pace_request(&mut self) -> RateDecision {
+        let username = match self.has_account() {
+            true => Some(self.account().sip_username().to_string()),
+            false => None,
+        };
+
+        let decision = self.rate_limiter_mut().pace(username.as_deref());
+
+        match decision {
+            RateDecision::Proceed(delay) => {
+                if !delay.is_zero() {
+                    log::debug!("{self} request delayed {delay:?} by rate limits");
+                    self.stats().rate_limit_delay();
+                }
+            }
+            RateDecision::Reject => {
+                log::info!("{self} request rejected by rate limits");
+                self.stats().rate_limit_rejection();
+            }
+            RateDecision::Disconnect => {
+                let peer = match self.peer_addr() {
+                    Some(a) => a.to_string(),
+                    None => "unknown".to_string(),
+                };
+
+                log::warn!(
+                    "{self} disconnecting client for exceeding rate limits: peer={peer} account={}",
+                    username.as_deref().unwrap_or("none")
+                );
+
+                self.stats().rate_limit_disconnect();
+            }
+        }
+
+        decision
+    }
+}
+
+#[cfg(test)]
+struct TestClock {
+    now: Mutex<Instant>,
+    slept: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl TestClock {
+    fn new() -> Arc<TestClock> {
+        Arc::new(TestClock {
+            now: Mutex::new(Instant::now()),
+            slept: Mutex::new(Duration::ZERO),
+        })
+    }
+
+    fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    fn slept(&self) -> Duration {
+        *self.slept.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        *self.slept.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+fn test_config(yaml: &str) -> RateLimitConfig {
+    let doc = yaml_rust::YamlLoader::load_from_str(yaml).unwrap();
+    let mut config = RateLimitConfig::default();
+    config.apply_yaml(&doc[0]).unwrap();
+    config
+}
+
+#[test]
+fn test_rate_limit_config() {
+    let mut config = test_config(
+        "requests-per-second: 5\nburst: 10\naccount-requests-per-second: 0.5\nmax-delay-ms: 250",
+    );
+
+    assert_eq!(config.session(), Some(Rate::new(5.0, 10.0).unwrap()));
+    assert_eq!(config.account(), Some(Rate::new(0.5, 1.0).unwrap()));
+    assert_eq!(config.max_delay(), Duration::from_millis(250));
+    assert_eq!(config.max_rejections(), DEFAULT_MAX_REJECTIONS);
+
+    // Account overrides only replace the values they contain.
+    let doc =
+        yaml_rust::YamlLoader::load_from_str("burst: 2\naccount-requests-per-second: 0").unwrap();
+    config.apply_yaml(&doc[0]).unwrap();
+
+    assert_eq!(config.session(), Some(Rate::new(5.0, 2.0).unwrap()));
+    assert_eq!(config.account(), None);
+
+    assert_eq!(RateLimitConfig::default().session(), None);
+
+    let doc = yaml_rust::YamlLoader::load_from_str("requests-per-second: -1").unwrap();
+    assert!(RateLimitConfig::default().apply_yaml(&doc[0]).is_err());
+}
+
+#[test]
+fn test_session_burst_pacing() {
+    let clock = TestClock::new();
+    let config =
+        test_config("requests-per-second: 10\nburst: 5\nmax-delay-ms: 500\nmax-rejections: 3");
+
+    let mut limiter = RateLimiter::new(&config, AccountRateLimits::new(), clock.clone());
+
+    // The burst is allowed through without delay.
+    for _ in 0..5 {
+        assert_eq!(limiter.pace(None), RateDecision::Proceed(Duration::ZERO));
+    }
+
+    // Then requests are paced at the configured rate.
+    for _ in 0..5 {
+        assert_eq!(
+            limiter.pace(None),
+            RateDecision::Proceed(Duration::from_millis(100))
+        );
+    }
+
+    assert_eq!(clock.slept(), Duration::from_millis(500));
+}
+
+#[test]
+fn test_sustained_abuse() {
+    let clock = TestClock::new();
+    let config =
+        test_config("requests-per-second: 10\nburst: 5\nmax-delay-ms: 50\nmax-rejections: 3");
+
+    let mut limiter = RateLimiter::new(&config, AccountRateLimits::new(), clock.clone());
+
+    for _ in 0..5 {
+        assert_eq!(limiter.pace(None), RateDecision::Proceed(Duration::ZERO));
+    }
+
+    // A small overrun is delayed.
+    clock.advance(Duration::from_millis(60));
+    assert_eq!(
+        limiter.pace(None),
+        RateDecision::Proceed(Duration::from_millis(40))
+    );
+
+    // Requests which would wait longer than max-delay-ms are rejected
+    // until the client is disconnected.
+    assert_eq!(limiter.pace(None), RateDecision::Reject);
+    assert_eq!(limiter.pace(None), RateDecision::Reject);
+    assert_eq!(limiter.pace(None), RateDecision::Disconnect);
+
+    assert_eq!(clock.slept(), Duration::from_millis(40));
+
+    // A pause lets the client recover.
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(limiter.pace(None), RateDecision::Proceed(Duration::ZERO));
+    assert_eq!(limiter.pace(None), RateDecision::Proceed(Duration::ZERO));
+}
+
+#[test]
+fn test_account_limit_shared() {
+    let clock = TestClock::new();
+    let config = test_config("account-requests-per-second: 2\naccount-burst: 4\nmax-delay-ms: 0");
+    let account_limits = AccountRateLimits::new();
+
+    let mut limiter1 = RateLimiter::new(&config, account_limits.clone(), clock.clone());
+    let mut limiter2 = RateLimiter::new(&config, account_limits.clone(), clock.clone());
+
+    // No account limit before login.
+    for _ in 0..10 {
+        assert_eq!(limiter1.pace(None), RateDecision::Proceed(Duration::ZERO));
+    }
+
+    // Both sessions draw from the same bucket.
+    assert!(matches!(
+        limiter1.pace(Some("sip1")),
+        RateDecision::Proceed(_)
+    ));
+    assert!(matches!(
+        limiter2.pace(Some("sip1")),
+        RateDecision::Proceed(_)
+    ));
+    assert!(matches!(
+        limiter1.pace(Some("sip1")),
+        RateDecision::Proceed(_)
+    ));
+    assert!(matches!(
+        limiter2.pace(Some("sip1")),
+        RateDecision::Proceed(_)
+    ));
+    assert_eq!(limiter1.pace(Some("sip1")), RateDecision::Reject);
+    assert_eq!(limiter2.pace(Some("sip1")), RateDecision::Reject);
+
+    // Other accounts are unaffected.
+    assert!(matches!(
+        limiter2.pace(Some("sip2")),
+        RateDecision::Proceed(_)
+    ));
+
+    // The account bucket refills at 2 per second.
+    clock.advance(Duration::from_millis(500));
+    assert!(matches!(
+        limiter1.pace(Some("sip1")),
+        RateDecision::Proceed(_)
+    ));
+    assert_eq!(limiter2.pace(Some("sip1")), RateDecision::Reject);
+
+    assert_eq!(clock.slept(), Duration::ZERO);
+}
+
+#[test]
+fn test_rejected_patron_info_response() {
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_PATRON_INFO,
+        &["000", "20240101    120000", "          "],
+        &[("AO", "example"), ("AA", "patron")],
+    )
+    .unwrap();
+
+    let resp = super::offline::unavailable_response(
+        &req,
+        "example",
+        "20240101    120000",
+        RATE_LIMIT_SCREEN_MSG,
+    )
+    .unwrap();
+
+    assert_eq!(resp.spec().code, sip2::spec::M_PATRON_INFO_RESP.code);
+    assert_eq!(resp.get_field_value("AA"), Some("patron"));
+    assert_eq!(resp.get_field_value("AF"), Some(RATE_LIMIT_SCREEN_MSG));
+}
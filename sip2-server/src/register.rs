@@ -0,0 +1,393 @@
+//! Patron self-registration via a vendor extension message.
+//!
+//! Registrations create staged users (stgu) which staff review and
+//! approve before they become patron accounts.  The message codes are
+//! configured with patron-register-message and patron-register-response.
+use super::session::Session;
+use eg::result::EgResult;
+use eg::EgValue;
+use evergreen as eg;
+
+/// Registration request fixed fields: transaction date.
+pub const REQUEST_FIXED_FIELDS: &[&sip2::spec::FixedField] = &[&sip2::spec::FF_DATE];
+
+/// Registration response fixed fields: ok, transaction date.
+pub const RESPONSE_FIXED_FIELDS: &[&sip2::spec::FixedField] =
+    &[&sip2::spec::FF_OK, &sip2::spec::FF_DATE];
+
+const MIN_PIN_LENGTH: usize = 4;
+const MIN_PHONE_DIGITS: usize = 7;
+const MAX_PHONE_DIGITS: usize = 15;
+
+/// Staged usernames start with this, followed by random digits.  The
+/// username is the temporary identifier returned to the SIP client.
+const USRNAME_PREFIX: &str = "sipreg-";
+
+/// Mailing address parsed from the free-form BD field.
+#[derive(Debug, PartialEq)]
+struct Address {
+    street1: String,
+    city: String,
+    state: Option<String>,
+    post_code: String,
+}
+
+/// Validated and normalized registration values.
+#[derive(Debug, PartialEq)]
+struct Registration {
+    first_given_name: String,
+    second_given_name: Option<String>,
+    family_name: String,
+    email: String,
+    phone: Option<String>,
+    address: Option<Address>,
+    pin: Option<String>,
+}
+
+impl Registration {
+    /// Collect the registration values from the request, returning
+    /// the screen message for the first invalid value.
+    ///
+    /// AE name, BE email, BF phone, BD address, AD PIN.
+    fn from_message(msg: &sip2::Message) -> Result<Registration, &'static str> {
+        let (first_given_name, second_given_name, family_name) =
+            split_name(msg.get_field_value("AE").unwrap_or(""))
+                .ok_or("A first and last name are required")?;
+
+        let email = normalize_email(msg.get_field_value("BE").unwrap_or(""))
+            .ok_or("A valid email address is required")?;
+
+        let phone = match msg.get_field_value("BF").map(|p| p.trim()) {
+            Some(p) if !p.is_empty() => Some(normalize_phone(p).ok_or("Invalid phone number")?),
+            _ => None,
+        };
+
+        let pin = match msg.get_field_value("AD") {
+            Some("") => None,
+            Some(p) if p.chars().count() < MIN_PIN_LENGTH => return Err("PIN is too short"),
+            p => p.map(|p| p.to_string()),
+        };
+
+        Ok(Registration {
+            first_given_name,
+            second_given_name,
+            family_name,
+            email,
+            phone,
+            address: msg.get_field_value("BD").and_then(parse_address),
+            pin,
+        })
+    }
+}
+
+/// Split "Family, Given Second" or "Given Second Family" into
+/// given, second given, and family names.
+fn split_name(name: &str) -> Option<(String, Option<String>, String)> {
+    let name = name.trim();
+
+    let (given, family) = match name.split_once(',') {
+        Some((family, given)) => (given.trim(), family.trim()),
+        None => name.rsplit_once(char::is_whitespace)?,
+    };
+
+    let mut given = given.split_whitespace();
+
+    let first = given.next()?.to_string();
+    let second = given.collect::<Vec<&str>>().join(" ");
+
+    if family.is_empty() {
+        return None;
+    }
+
+    Some((
+        first,
+        Some(second).filter(|s| !s.is_empty()),
+        family.to_string(),
+    ))
+}
+
+/// Lowercased email address, if it looks deliverable.
+fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+
+    let (local, domain) = email.split_once('@')?;
+
+    let valid = !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && domain.split('.').all(|part| !part.is_empty())
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control());
+
+    valid.then_some(email)
+}
+
+/// Digits of the phone number, if it has a plausible number of them.
+fn normalize_phone(phone: &str) -> Option<String> {
+    if phone
+        .chars()
+        .any(|c| !c.is_ascii_digit() && !" +-.()".contains(c))
+    {
+        return None;
+    }
+
+    let digits: String = phone.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    (MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS)
+        .contains(&digits.len())
+        .then_some(digits)
+}
+
+/// Split "street, city, state post_code" into its parts.  Addresses
+/// in any other form are kept whole as the street.
+fn parse_address(address: &str) -> Option<Address> {
+    let address = address.trim();
+
+    if address.is_empty() {
+        return None;
+    }
+
+    let parts: Vec<&str> = address.split(',').map(|p| p.trim()).collect();
+
+    if parts.len() < 3 || parts.iter().any(|p| p.is_empty()) {
+        return Some(Address {
+            street1: address.to_string(),
+            city: String::new(),
+            state: None,
+            post_code: String::new(),
+        });
+    }
+
+    let last = parts[parts.len() - 1];
+
+    let (state, post_code) = match last.rsplit_once(' ') {
+        Some((state, code)) if code.chars().any(|c| c.is_ascii_digit()) => {
+            (Some(state.trim()), code)
+        }
+        _ if last.chars().any(|c| c.is_ascii_digit()) => (None, last),
+        _ => (Some(last), ""),
+    };
+
+    Some(Address {
+        street1: parts[..parts.len() - 2].join(", "),
+        city: parts[parts.len() - 2].to_string(),
+        state: state.map(|s| s.to_string()),
+        post_code: post_code.to_string(),
+    })
+}
+
+impl Session {
+    /// Stage a patron self-registration for staff approval.
+    ///
+    /// Repeat registrations with the same email and name, e.g. from
+    /// a double-tap, return the existing staged user.
+    pub fn handle_patron_register(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
+        log::info!("{self} Patron Register");
+
+        if !self.account().allow_patron_register() {
+            log::warn!("{self} Patron registration is not allowed for this account");
+            return Ok(self.patron_register_response(None, "Patron registration is not available"));
+        }
+
+        let registration = match Registration::from_message(msg) {
+            Ok(r) => r,
+            Err(screen_msg) => {
+                log::info!("{self} Patron registration rejected: {screen_msg}");
+                return Ok(self.patron_register_response(None, screen_msg));
+            }
+        };
+
+        let usrname = match self.find_staged_user(&registration)? {
+            Some(u) => {
+                log::info!("{self} Patron registration matches staged user {u}");
+                u
+            }
+            None => self.create_staged_user(&registration)?,
+        };
+
+        Ok(self.patron_register_response(
+            Some(&usrname),
+            "Registration received.  Please see staff to activate your account.",
+        ))
+    }
+
+    /// Username of an unapproved staged user with the same email
+    /// and name.
+    fn find_staged_user(&mut self, registration: &Registration) -> EgResult<Option<String>> {
+        let query = eg::hash! {
+            email: registration.email.as_str(),
+            first_given_name: registration.first_given_name.as_str(),
+            family_name: registration.family_name.as_str(),
+            complete: "f",
+        };
+
+        let staged = self.editor_mut().search("stgu", query)?;
+
+        staged.first().map(|u| u["usrname"].string()).transpose()
+    }
+
+    fn create_staged_user(&mut self, registration: &Registration) -> EgResult<String> {
+        let usrname = format!("{USRNAME_PREFIX}{}", eg::util::random_number(10));
+
+        let user = eg::hash! {
+            usrname: usrname.as_str(),
+            email: registration.email.as_str(),
+            passwd: registration.pin.as_deref(),
+            first_given_name: registration.first_given_name.as_str(),
+            second_given_name: registration.second_given_name.as_deref(),
+            family_name: registration.family_name.as_str(),
+            day_phone: registration.phone.as_deref(),
+            home_ou: self.get_ws_org_id()?,
+        };
+
+        let user = EgValue::create("stgu", user)?;
+
+        let address = match &registration.address {
+            Some(a) => Some(EgValue::create(
+                "stgma",
+                eg::hash! {
+                    usrname: usrname.as_str(),
+                    street1: a.street1.as_str(),
+                    city: a.city.as_str(),
+                    state: a.state.as_deref(),
+                    post_code: a.post_code.as_str(),
+                },
+            )?),
+            None => None,
+        };
+
+        {
+            let mut editor = self.editor_mut().xact_guard()?;
+
+            editor.create(user)?;
+
+            if let Some(address) = address {
+                editor.create(address)?;
+            }
+
+            editor.commit()?;
+        }
+
+        log::info!("{self} created staged user {usrname}");
+
+        Ok(usrname)
+    }
+
+    /// Patron Register Response.  The registration succeeded if a
+    /// staged username is provided.
+    pub fn patron_register_response(
+        &self,
+        usrname: Option<&str>,
+        screen_msg: &str,
+    ) -> sip2::Message {
+        // Registered when the config was loaded.
+        let spec =
+            sip2::spec::Message::from_code(self.sip_config().patron_register_response()).unwrap();
+
+        let mut resp = sip2::Message::from_values(
+            spec,
+            &[
+                sip2::util::num_bool(usrname.is_some()),
+                &self.sip_date_now(),
+            ],
+            &[("AO", self.account().settings().institution())],
+        )
+        .unwrap();
+
+        resp.maybe_add_field("AA", usrname);
+        resp.add_field("AF", &self.tr(screen_msg));
+
+        resp
+    }
+}
+
+#[test]
+fn test_split_name() {
+    let name = |first: &str, second: Option<&str>, family: &str| {
+        Some((
+            first.to_string(),
+            second.map(|s| s.to_string()),
+            family.to_string(),
+        ))
+    };
+
+    assert_eq!(split_name("Jane Doe"), name("Jane", None, "Doe"));
+    assert_eq!(split_name(" Doe,  Jane Q "), name("Jane", Some("Q"), "Doe"));
+    assert_eq!(
+        split_name("Jane Quinn  Doe"),
+        name("Jane", Some("Quinn"), "Doe")
+    );
+    assert_eq!(split_name("Jane"), None);
+    assert_eq!(split_name("Doe,"), None);
+    assert_eq!(split_name(", Jane"), None);
+}
+
+#[test]
+fn test_normalize_contact() {
+    assert_eq!(
+        normalize_email(" Jane.Doe@Example.ORG "),
+        Some("jane.doe@example.org".to_string())
+    );
+    assert_eq!(normalize_email("jane@localhost"), None);
+    assert_eq!(normalize_email("jane@@example.org"), None);
+    assert_eq!(normalize_email("jane doe@example.org"), None);
+    assert_eq!(normalize_email("@example.org"), None);
+
+    assert_eq!(
+        normalize_phone("(555) 123-4567"),
+        Some("5551234567".to_string())
+    );
+    assert_eq!(
+        normalize_phone("+1 555.123.4567"),
+        Some("15551234567".to_string())
+    );
+    assert_eq!(normalize_phone("555-CALL"), None);
+    assert_eq!(normalize_phone("12345"), None);
+}
+
+#[test]
+fn test_parse_address() {
+    let addr = parse_address("1 Main St, Apt 2, Springfield, MA 01101").unwrap();
+    assert_eq!(addr.street1, "1 Main St, Apt 2");
+    assert_eq!(addr.city, "Springfield");
+    assert_eq!(addr.state.as_deref(), Some("MA"));
+    assert_eq!(addr.post_code, "01101");
+
+    let addr = parse_address("1 Main St, Springfield, MA").unwrap();
+    assert_eq!(addr.state.as_deref(), Some("MA"));
+    assert_eq!(addr.post_code, "");
+
+    let addr = parse_address("General Delivery").unwrap();
+    assert_eq!(addr.street1, "General Delivery");
+    assert_eq!(addr.city, "");
+
+    assert_eq!(parse_address("  "), None);
+}
+
+#[test]
+fn test_registration_from_message() {
+    let msg = |fields: &[(&str, &str)]| {
+        let mut all = vec![("AE", "Doe, Jane"), ("BE", "jane@example.org")];
+        all.extend_from_slice(fields);
+        sip2::Message::from_values(
+            &sip2::spec::M_PATRON_STATUS,
+            &["000", "20240101    120000"],
+            &all,
+        )
+        .unwrap()
+    };
+
+    let reg = Registration::from_message(&msg(&[("BF", "555-123-4567"), ("AD", "1234")])).unwrap();
+    assert_eq!(reg.family_name, "Doe");
+    assert_eq!(reg.phone.as_deref(), Some("5551234567"));
+    assert_eq!(reg.pin.as_deref(), Some("1234"));
+    assert_eq!(reg.address, None);
+
+    assert_eq!(
+        Registration::from_message(&msg(&[("AD", "12")])),
+        Err("PIN is too short")
+    );
+    assert_eq!(
+        Registration::from_message(&msg(&[("BF", "555")])),
+        Err("Invalid phone number")
+    );
+}
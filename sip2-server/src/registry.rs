@@ -0,0 +1,332 @@
+//! Registry of connected SIP sessions.
+//!
+//! Shared by the Server, its Sessions, the admin listener, and the
+//! status listener.  Sessions are spread across shards so sessions
+//! connecting and disconnecting on different threads rarely contend
+//! for the same lock.  Per-request updates are atomic stores made via
+//! the session's own registration and take no lock at all.
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of session map shards.
+const SHARD_COUNT: usize = 16;
+
+/// Describes a registered session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub label: String,
+    pub account: Option<String>,
+    pub peer: Option<String>,
+
+    /// Epoch seconds of the most recent SIP request, or of
+    /// registration if no requests have arrived.
+    pub last_activity: i64,
+
+    /// SIP requests handled by the session.
+    pub requests: u64,
+}
+
+/// Session details which change at login.
+struct SessionDetails {
+    label: String,
+
+    /// SIP account username once logged in.
+    account: Option<String>,
+}
+
+/// A session registered with the registry.
+struct SessionEntry {
+    id: u64,
+    details: Mutex<SessionDetails>,
+
+    /// Address of the SIP client.
+    peer: Option<String>,
+
+    /// Clone of the session's socket, used to wake the session when
+    /// it's blocked waiting on its SIP client.
+    waker: Option<TcpStream>,
+
+    last_activity: AtomicI64,
+    requests: AtomicU64,
+
+    /// Set when the session has been asked to exit.
+    terminate: AtomicBool,
+}
+
+impl SessionEntry {
+    fn info(&self) -> SessionInfo {
+        let details = self.details.lock().unwrap();
+
+        SessionInfo {
+            id: self.id,
+            label: details.label.clone(),
+            account: details.account.clone(),
+            peer: self.peer.clone(),
+            last_activity: self.last_activity.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+        }
+    }
+
+    fn account_is(&self, username: &str) -> bool {
+        self.details.lock().unwrap().account.as_deref() == Some(username)
+    }
+
+    /// Shut down the session's socket.
+    ///
+    /// Errors here just mean the socket is already closed.
+    fn wake(&self, how: Shutdown) {
+        if let Some(stream) = self.waker.as_ref() {
+            stream.shutdown(how).ok();
+        }
+    }
+
+    /// Ask the session to exit and close its socket so it notices.
+    fn terminate(&self) {
+        self.terminate.store(true, Ordering::Relaxed);
+        self.wake(Shutdown::Both);
+    }
+}
+
+type Shard = Mutex<HashMap<u64, Arc<SessionEntry>>>;
+
+struct Inner {
+    id_gen: AtomicU64,
+    shards: Vec<Shard>,
+}
+
+/// Shared by the Server and all Sessions; cloning is cheap.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    inner: Arc<Inner>,
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        SessionRegistry {
+            inner: Arc::new(Inner {
+                id_gen: AtomicU64::new(0),
+                shards: (0..SHARD_COUNT).map(|_| Default::default()).collect(),
+            }),
+        }
+    }
+
+    fn shard(&self, id: u64) -> &Shard {
+        &self.inner.shards[id as usize % SHARD_COUNT]
+    }
+
+    /// Apply `f` to every registered session, one shard at a time.
+    fn for_each(&self, mut f: impl FnMut(&SessionEntry)) {
+        for shard in self.inner.shards.iter() {
+            for entry in shard.lock().unwrap().values() {
+                f(entry);
+            }
+        }
+    }
+
+    /// Register a new session.
+    ///
+    /// The session is deregistered when the returned registration is
+    /// dropped.
+    pub fn register(&self, label: &str, waker: Option<TcpStream>) -> SessionRegistration {
+        let id = self.inner.id_gen.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let peer = waker
+            .as_ref()
+            .and_then(|s| s.peer_addr().ok())
+            .map(|a| a.to_string());
+
+        let entry = Arc::new(SessionEntry {
+            id,
+            details: Mutex::new(SessionDetails {
+                label: label.to_string(),
+                account: None,
+            }),
+            peer,
+            waker,
+            last_activity: AtomicI64::new(epoch_secs()),
+            requests: AtomicU64::new(0),
+            terminate: AtomicBool::new(false),
+        });
+
+        self.shard(id).lock().unwrap().insert(id, entry.clone());
+
+        SessionRegistration {
+            entry,
+            registry: self.clone(),
+        }
+    }
+
+    /// Number of registered sessions.
+    pub fn len(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|s| s.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Details of all registered sessions, ordered by ID.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut list = Vec::new();
+        self.for_each(|e| list.push(e.info()));
+        list.sort_by_key(|s| s.id);
+        list
+    }
+
+    /// Labels of all registered sessions.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        self.for_each(|e| labels.push(e.details.lock().unwrap().label.clone()));
+        labels.sort();
+        labels
+    }
+
+    /// Logged-in session counts and request totals per SIP account.
+    pub fn account_activity(&self) -> HashMap<String, (usize, u64)> {
+        let mut activity: HashMap<String, (usize, u64)> = HashMap::new();
+
+        self.for_each(|e| {
+            if let Some(account) = e.details.lock().unwrap().account.as_ref() {
+                let counts = activity.entry(account.to_string()).or_default();
+                counts.0 += 1;
+                counts.1 += e.requests.load(Ordering::Relaxed);
+            }
+        });
+
+        activity
+    }
+
+    /// Ask a single session to exit, closing its socket.
+    ///
+    /// Returns false if no such session is registered.
+    pub fn terminate(&self, id: u64) -> bool {
+        match self.shard(id).lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.terminate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ask all sessions logged in with the account to exit.
+    ///
+    /// Returns the number of sessions terminated.
+    pub fn terminate_account(&self, username: &str) -> usize {
+        let mut count = 0;
+
+        self.for_each(|e| {
+            if e.account_is(username) {
+                e.terminate();
+                count += 1;
+            }
+        });
+
+        count
+    }
+
+    /// Shut down the socket for every registered session.
+    pub fn wake_all(&self, how: Shutdown) {
+        self.for_each(|e| e.wake(how));
+    }
+}
+
+/// Held by a Session for the duration of its connection.
+pub struct SessionRegistration {
+    entry: Arc<SessionEntry>,
+    registry: SessionRegistry,
+}
+
+impl SessionRegistration {
+    /// Unique ID for this session.
+    pub fn id(&self) -> u64 {
+        self.entry.id
+    }
+
+    /// True if the session has been asked to exit.
+    pub fn terminate_requested(&self) -> bool {
+        self.entry.terminate.load(Ordering::Relaxed)
+    }
+
+    /// Record a SIP request.
+    pub fn touch(&self) {
+        self.entry
+            .last_activity
+            .store(epoch_secs(), Ordering::Relaxed);
+        self.entry.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Update the label reported for this session, e.g. after login.
+    pub fn set_label(&self, label: &str) {
+        self.entry.details.lock().unwrap().label = label.to_string();
+    }
+
+    /// Update the SIP account reported for this session.
+    pub fn set_account(&self, account: Option<&str>) {
+        self.entry.details.lock().unwrap().account = account.map(|a| a.to_string());
+    }
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut shard) = self.registry.shard(self.entry.id).lock() {
+            shard.remove(&self.entry.id);
+        }
+    }
+}
+
+fn epoch_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_registry() {
+    let registry = SessionRegistry::new();
+
+    let sessions: Vec<SessionRegistration> = (0..40)
+        .map(|n| registry.register(&format!("s{n}"), None))
+        .collect();
+
+    assert_eq!(registry.len(), 40);
+
+    sessions[0].set_account(Some("sip-user"));
+    sessions[1].set_account(Some("sip-user"));
+    sessions[1].touch();
+    sessions[1].touch();
+
+    let info = registry.sessions().remove(1);
+    assert_eq!(info.id, sessions[1].id());
+    assert_eq!(info.requests, 2);
+    assert_eq!(info.account.as_deref(), Some("sip-user"));
+    assert!(info.last_activity > 0);
+
+    assert_eq!(registry.account_activity().get("sip-user"), Some(&(2, 2)));
+
+    assert!(!registry.terminate(1000));
+    assert!(registry.terminate(sessions[2].id()));
+    assert!(sessions[2].terminate_requested());
+    assert!(!sessions[3].terminate_requested());
+
+    assert_eq!(registry.terminate_account("sip-user"), 2);
+    assert!(sessions[0].terminate_requested());
+
+    drop(sessions);
+    assert!(registry.is_empty());
+}
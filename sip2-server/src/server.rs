@@ -1,15 +1,28 @@
+use super::access;
+use super::access::AccountSessions;
+use super::admin::AdminListener;
+use super::audit::AuditLogger;
+use super::cache::SharedCache;
 use super::conf;
-use super::conf::Config;
-use super::session::Session;
-use eg::osrf;
+use super::conf::{Config, SharedConfig};
+use super::i18n::Translator;
+use super::offline::OfflineJournal;
+use super::ratelimit::AccountRateLimits;
+use super::registry::SessionRegistry;
+use super::session::{Session, SharedState};
+use super::shutdown::ShutdownCoordinator;
+use super::stats::{ServerStats, StatusListener};
+use super::tls::TlsAcceptor;
+use eg::osrf::events::ServiceEvents;
+use eg::osrf::pool::BusPool;
 use eg::EgValue;
 use evergreen as eg;
 use mptc;
 use std::any::Any;
-use std::collections::HashMap;
 use std::net::{TcpListener, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// If we get this many TCP errors in a row, with no successful connections
 /// in between, exit.
@@ -18,6 +31,26 @@ const MAX_TCP_ERRORS: usize = 100;
 /// Wraps the TCP stream created by the initial connection from a SIP client.
 struct SipConnectRequest {
     stream: Option<TcpStream>,
+
+    /// Held until the session ends.
+    _slot: ConnectionSlot,
+}
+
+/// Counts a client connection as open from accept() until the
+/// request which carries it is dropped at the end of its session.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn new(open: &Arc<AtomicUsize>) -> Self {
+        open.fetch_add(1, Ordering::Relaxed);
+        ConnectionSlot(open.clone())
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl SipConnectRequest {
@@ -35,30 +68,27 @@ impl mptc::Request for SipConnectRequest {
 }
 
 pub struct SessionFactory {
-    shutdown: Arc<AtomicBool>,
+    /// Passed to each Session.
+    shared: SharedState,
 
-    sip_config: Arc<Config>,
+    /// Set if SIP clients connect via TLS.
+    tls_acceptor: Option<TlsAcceptor>,
 
-    /// OpenSRF bus.
-    osrf_bus: Option<eg::osrf::bus::Bus>,
+    sip_config: SharedConfig,
 
-    /// Cache of org unit shortnames and IDs.
-    org_cache: HashMap<i64, EgValue>,
+    /// OpenSRF bus connections shared by all workers.
+    bus_pool: BusPool,
 }
 
 impl mptc::RequestHandler for SessionFactory {
     fn worker_start(&mut self) -> Result<(), String> {
-        let bus = eg::osrf::bus::Bus::new(osrf::conf::config().client())?;
-        self.osrf_bus = Some(bus);
-
-        log::debug!("SessionFactory connected OK to opensrf");
-
+        self.shared.stats.worker_started();
         Ok(())
     }
 
     fn worker_end(&mut self) -> Result<(), String> {
         log::debug!("SessionFactory worker_end()");
-        // OpenSRF bus will disconnect and cleanup once
+        self.shared.stats.worker_ended();
         Ok(())
     }
 
@@ -67,18 +97,45 @@ impl mptc::RequestHandler for SessionFactory {
     fn process(&mut self, mut request: Box<dyn mptc::Request>) -> Result<(), String> {
         let request = SipConnectRequest::downcast(&mut request);
 
-        let sip_conf = self.sip_config.clone();
-        let org_cache = self.org_cache.clone();
-        let shutdown = self.shutdown.clone();
+        if self.shared.shutdown.shutting_down() {
+            // Connection arrived as we were shutting down.  Dropping
+            // the stream closes the connection.
+            log::info!("Shutdown in progress; dropping new SIP connection");
+            return Ok(());
+        }
 
-        // Set in worker_start
-        let osrf_bus = self.osrf_bus.take().unwrap();
+        let sip_conf = self.sip_config.current();
 
         // request.stream is set in the call to next() that produced
         // this request.
         let stream = request.stream.take().unwrap();
 
-        let mut session = Session::new(sip_conf, osrf_bus, stream, shutdown, org_cache);
+        let sip_stream: Box<dyn sip2::SipStream> = match self.tls_acceptor.as_ref() {
+            Some(acceptor) => match acceptor.accept(stream) {
+                Ok(s) => Box::new(s),
+                Err(e) => {
+                    // Dropping the stream closes the connection.
+                    log::error!("{e}");
+                    self.shared.stats.tls_error();
+                    return Ok(());
+                }
+            },
+            None => Box::new(stream),
+        };
+
+        // Returns the bus to the pool when dropped.
+        let mut pooled = self.bus_pool.checkout()?;
+
+        let osrf_bus = match pooled.as_mut() {
+            Some(p) => p.take_bus(),
+            None => {
+                // Pool is maxed out.  Use a dedicated connection which
+                // goes away with the session.
+                eg::osrf::bus::Bus::new(eg::osrf::conf::config().client())?
+            }
+        };
+
+        let mut session = Session::new(sip_conf, osrf_bus, sip_stream, self.shared.clone());
 
         if let Err(e) = session.start() {
             // This is not necessarily an error.  The client may simply
@@ -87,19 +144,12 @@ impl mptc::RequestHandler for SessionFactory {
             log::info!("{session} exited with message: {e}");
         }
 
-        // Take our bus back so we don't have to reconnect in between
-        // SIP clients.  This SIP Session is done with it.
-        let mut bus = session.take_bus();
-
-        // Remove any trailing data on the Bus.
-        bus.clear_bus()?;
-
-        // Apply a new Bus address to prevent any possibility of
-        // trailing message cross-talk.  (Note, it wouldn't do anything,
-        // since messages would refer to unknown sessions, but still..).
-        bus.generate_address();
-
-        self.osrf_bus = Some(bus);
+        // Give the bus back to the pool so the next SIP client does
+        // not have to reconnect.  The pool clears any trailing data
+        // and applies a new bus address to prevent cross-talk.
+        if let Some(p) = pooled.as_mut() {
+            p.put_bus(session.take_bus());
+        }
 
         Ok(())
     }
@@ -108,33 +158,105 @@ impl mptc::RequestHandler for SessionFactory {
 /// Listens for SIP client connections and passes them off to mptc:: for
 /// relaying to a Session worker.
 pub struct Server {
-    eg_ctx: eg::init::Context,
+    eg_ctx: eg::Client,
 
     /// Parsed config
-    sip_config: Arc<Config>,
+    ///
+    /// Shared with our Sessions and the admin listener.
+    sip_config: SharedConfig,
 
     /// Path the SIP config so it can be reloaded on request.
     sip_config_file: String,
 
-    /// Set to true of the mptc::Server tells us it's time to shutdown.
+    /// Coordinates shutdown once the mptc::Server tells us it's
+    /// time to shutdown.
+    ///
+    /// Shared with our Sessions
+    shutdown: ShutdownCoordinator,
+
+    /// Set if SIP clients connect via TLS.
+    tls_acceptor: Option<TlsAcceptor>,
+
+    /// Logged-in session counts per SIP account.
     ///
-    /// Read by our Sessions
-    shutdown: Arc<AtomicBool>,
+    /// Shared with our Sessions
+    account_sessions: AccountSessions,
 
-    /// Cache of org unit shortnames and IDs.
-    org_cache: Option<HashMap<i64, EgValue>>,
+    /// SIP traffic audit log, which may be disabled.
+    ///
+    /// Shared with our Sessions
+    audit_log: AuditLogger,
+
+    /// Screen message translations.
+    ///
+    /// Shared with our Sessions
+    translator: Arc<Translator>,
+
+    /// Set if offline transactions are enabled.
+    ///
+    /// Shared with our Sessions and the status listener.
+    offline: Option<Arc<OfflineJournal>>,
+
+    /// Org units, etc. shared by all Sessions.
+    cache: SharedCache,
+
+    /// Shared with our Sessions and the status listener.
+    stats: ServerStats,
+
+    /// Per-account request rate limits.
+    ///
+    /// Shared with our Sessions
+    rate_limits: AccountRateLimits,
+
+    /// OpenSRF bus connections shared by our Sessions.
+    bus_pool: BusPool,
 
     tcp_error_count: usize,
 
+    /// Number of client connections accepted whose sessions have not
+    /// yet ended.
+    open_connections: Arc<AtomicUsize>,
+
+    /// Set if the admin listener is enabled.  Taken by the caller
+    /// before the server starts.
+    admin_listener: Option<AdminListener>,
+
     /// Inbound SIP connections start here.
     tcp_listener: TcpListener,
 }
 
 impl mptc::RequestStream for Server {
     fn next(&mut self) -> Result<Option<Box<dyn mptc::Request>>, String> {
+        if self.shutdown.shutting_down() {
+            return Ok(None);
+        }
+
         let stream = match self.tcp_listener.accept() {
-            Ok((stream, _addr)) => {
+            Ok((stream, addr)) => {
                 self.tcp_error_count = 0;
+
+                if !access::address_allowed(
+                    self.sip_config.current().allowed_addresses(),
+                    &addr.ip(),
+                ) {
+                    // Dropping the stream closes the connection.
+                    log::warn!("Rejecting SIP connection from unlisted address {addr}");
+                    self.stats.connection_rejected();
+                    return Ok(None);
+                }
+
+                let max_clients = self.sip_config.current().max_clients();
+
+                if self.open_connections.load(Ordering::Relaxed) >= max_clients {
+                    // Otherwise the connection waits, possibly for a
+                    // long time, for a worker to become available.
+                    log::warn!("Rejecting SIP connection from {addr}: max-clients reached");
+                    self.stats.connection_rejected();
+                    return Ok(None);
+                }
+
+                self.stats.connection_accepted();
+
                 stream
             }
             Err(e) => {
@@ -151,6 +273,7 @@ impl mptc::RequestStream for Server {
                             self.tcp_error_count
                         );
                         self.tcp_error_count += 1;
+                        self.stats.tcp_error();
 
                         if self.tcp_error_count > MAX_TCP_ERRORS {
                             // Net IO errors can happen for all kinds of reasons.
@@ -170,15 +293,27 @@ impl mptc::RequestStream for Server {
 
         Ok(Some(Box::new(SipConnectRequest {
             stream: Some(stream),
+            _slot: ConnectionSlot::new(&self.open_connections),
         })))
     }
 
     fn new_handler(&mut self) -> Box<dyn mptc::RequestHandler> {
-        let sf = SessionFactory {
+        let shared = SharedState {
             shutdown: self.shutdown.clone(),
+            account_sessions: self.account_sessions.clone(),
+            audit_log: self.audit_log.clone(),
+            offline: self.offline.clone(),
+            translator: self.translator.clone(),
+            cache: self.cache.clone(),
+            stats: self.stats.clone(),
+            rate_limits: self.rate_limits.clone(),
+        };
+
+        let sf = SessionFactory {
+            shared,
+            tls_acceptor: self.tls_acceptor.clone(),
             sip_config: self.sip_config.clone(),
-            osrf_bus: None, // set in worker_start
-            org_cache: self.org_cache.as_ref().unwrap().clone(),
+            bus_pool: self.bus_pool.clone(),
         };
 
         Box::new(sf)
@@ -186,11 +321,28 @@ impl mptc::RequestStream for Server {
 
     fn reload(&mut self) -> Result<(), String> {
         match Server::load_config(&self.sip_config_file) {
-            Ok(c) => self.sip_config = Arc::new(c),
+            Ok(c) => match Server::tls_acceptor(&c) {
+                Ok(a) => {
+                    self.tls_acceptor = a;
+                    self.sip_config.replace(c);
+                    self.reload_audit_log();
+                    self.reload_translator();
+                }
+                Err(e) => log::error!("Error reloading TLS config.  Using old config. {e}"),
+            },
             Err(e) => log::error!("Error reloading config.  Using old config. {e}"),
         }
 
+        // Sessions read host settings as needed, so refreshing them
+        // here applies the new values to the next request.
+        if let Err(e) = eg::HostSettings::refresh(&self.eg_ctx) {
+            log::error!("Error reloading host settings.  Using old settings. {e}");
+        }
+
+        // Discard cached data, which may be stale, and load it fresh.
         // Fails if we cannot talk to OpenSRF.
+        self.cache.set_ttl(self.sip_config.current().cache_ttl());
+        self.cache.clear();
         self.precache()?;
 
         // No need to inform our worker sessions that we're reloading.
@@ -207,17 +359,31 @@ impl mptc::RequestStream for Server {
         // own idle workers.
         log::info!("Server received mptc shutdown request");
 
-        self.shutdown.store(true, Ordering::Relaxed);
-        self.eg_ctx.client().clear().ok();
+        let report = self.shutdown.shutdown();
+
+        if !report.clean() {
+            log::warn!(
+                "Server force-closed sessions at shutdown: {:?}",
+                report.lingering
+            );
+        }
+
+        self.eg_ctx.clear().ok();
     }
 }
 
 impl Server {
-    pub fn sip_config(&self) -> &Config {
-        &self.sip_config
+    pub fn sip_config(&self) -> Arc<Config> {
+        self.sip_config.current()
+    }
+
+    /// Take ownership of the admin listener, if enabled, so it can be
+    /// spawned once the mptc::Server is built.
+    pub fn take_admin_listener(&mut self) -> Option<AdminListener> {
+        self.admin_listener.take()
     }
 
-    pub fn setup(sip_config_file: &str, eg_ctx: eg::init::Context) -> Result<Server, String> {
+    pub fn setup(sip_config_file: &str, eg_ctx: eg::Client) -> Result<Server, String> {
         let sip_config = Server::load_config(sip_config_file)?;
 
         let tcp_listener = eg::util::tcp_listener(
@@ -226,14 +392,79 @@ impl Server {
             conf::SIP_SHUTDOWN_POLL_INTERVAL,
         )?;
 
+        let shutdown = ShutdownCoordinator::new(
+            SessionRegistry::new(),
+            Duration::from_secs(sip_config.shutdown_drain_timeout()),
+            Duration::from_secs(sip_config.shutdown_force_timeout()),
+        );
+
+        let tls_acceptor = Server::tls_acceptor(&sip_config)?;
+
+        let audit_log = AuditLogger::new(sip_config.audit_log().cloned())?;
+
+        let translator = Arc::new(Server::translator(&sip_config)?);
+
+        let offline = match sip_config.offline() {
+            Some(c) => Some(Arc::new(OfflineJournal::new(c.clone())?)),
+            None => None,
+        };
+
+        if let Some(journal) = offline.as_ref() {
+            let shutdown = shutdown.clone();
+            journal
+                .clone()
+                .spawn_replay_thread(move || shutdown.shutting_down());
+        }
+
+        let cache = SharedCache::new(sip_config.cache_ttl());
+        let account_sessions = AccountSessions::new();
+        let stats = ServerStats::new();
+
+        let bus_pool = BusPool::new(sip_config.osrf_pool_min(), sip_config.osrf_pool_max());
+        bus_pool.fill()?;
+
+        if let Some(port) = sip_config.status_port() {
+            let mut listener = StatusListener::new(
+                sip_config.status_address(),
+                port,
+                stats.clone(),
+                bus_pool.clone(),
+                shutdown.clone(),
+                account_sessions.clone(),
+                offline.clone(),
+            )?;
+
+            if let Some(events) = Server::subscribe_service_events(&eg_ctx) {
+                listener.set_service_events(events);
+            }
+
+            listener.spawn();
+        }
+
+        let sip_config_file = sip_config_file.to_string();
+        let sip_config = SharedConfig::new(sip_config);
+
+        let admin_listener =
+            AdminListener::from_config(sip_config.clone(), &sip_config_file, shutdown.clone())?;
+
         let mut server = Server {
             eg_ctx,
+            shutdown,
+            tls_acceptor,
             tcp_listener,
-            sip_config: Arc::new(sip_config),
-            sip_config_file: sip_config_file.to_string(),
-            org_cache: None,
+            account_sessions,
+            audit_log,
+            offline,
+            translator,
+            sip_config,
+            sip_config_file,
+            cache,
+            stats,
+            rate_limits: AccountRateLimits::new(),
+            bus_pool,
             tcp_error_count: 0,
-            shutdown: Arc::new(AtomicBool::new(false)),
+            open_connections: Arc::new(AtomicUsize::new(0)),
+            admin_listener,
         };
 
         server.precache()?;
@@ -241,29 +472,107 @@ impl Server {
         Ok(server)
     }
 
+    /// Subscribe to router service events for the status listener.
+    ///
+    /// Health checks work without them, so failures are only logged.
+    fn subscribe_service_events(eg_ctx: &eg::Client) -> Option<ServiceEvents> {
+        match eg_ctx.subscribe_service_events() {
+            Ok(events) => Some(events),
+            Err(e) => {
+                log::warn!("Cannot subscribe to router service events: {e}");
+                None
+            }
+        }
+    }
+
+    /// Build a TLS acceptor if TLS is enabled.
+    fn tls_acceptor(sip_config: &Config) -> Result<Option<TlsAcceptor>, String> {
+        if !sip_config.enable_tls() {
+            return Ok(None);
+        }
+
+        // Presence of both is verified when the config is loaded.
+        let cert_chain = sip_config.tls_cert_chain().unwrap();
+        let key = sip_config.tls_key().unwrap();
+
+        log::info!("SIP clients must connect via TLS");
+
+        Ok(Some(TlsAcceptor::new(
+            cert_chain,
+            key,
+            sip_config.tls_handshake_timeout(),
+        )?))
+    }
+
+    /// Apply the audit log settings from a newly loaded config.
+    ///
+    /// The audit log is shared with all Sessions, including those
+    /// which started before the reload, and is reopened so log
+    /// rotation tools can signal us with SIGHUP.
+    fn reload_audit_log(&mut self) {
+        let config = self.sip_config.current().audit_log().cloned();
+
+        if let Err(e) = self.audit_log.reload(config) {
+            log::error!("Cannot reload audit log.  Using old audit log. {e}");
+        }
+    }
+
+    /// Load the screen message translations from the locale directory.
+    fn translator(sip_config: &Config) -> Result<Translator, String> {
+        let translator = match sip_config.locale_dir() {
+            Some(dir) => Translator::load(dir)?,
+            None => Translator::default(),
+        };
+
+        for account in sip_config.accounts() {
+            if let Some(locale) = account.locale() {
+                if !translator.has_locale(locale) {
+                    log::warn!(
+                        "No translations for locale {locale} used by SIP account {}",
+                        account.sip_username()
+                    );
+                }
+            }
+        }
+
+        Ok(translator)
+    }
+
+    /// Load translations for a newly loaded config.  Sessions which
+    /// started before the reload continue using the old translations.
+    fn reload_translator(&mut self) {
+        match Server::translator(&self.sip_config.current()) {
+            Ok(t) => self.translator = Arc::new(t),
+            Err(e) => log::error!("Cannot load translations.  Using old translations. {e}"),
+        }
+    }
+
     fn load_config(filename: &str) -> Result<Config, String> {
         let mut sip_conf = conf::Config::new();
         sip_conf.read_yaml(filename)?;
+        sip_conf.register_extensions()?;
         Ok(sip_conf)
     }
 
     /// Pre-cache data that's universally useful.
     fn precache(&mut self) -> Result<(), String> {
-        let mut e = eg::Editor::new(self.eg_ctx.client());
+        let mut e = eg::Editor::new(&self.eg_ctx);
 
         let search = eg::hash! {
             "id": {"!=": EgValue::Null},
         };
 
-        let mut orgs = e.search("aou", search)?;
+        let orgs = e.search("aou", search)?;
 
-        let mut map = HashMap::new();
+        self.cache.load_orgs(orgs)?;
 
-        for org in orgs.drain(..) {
-            map.insert(org.id()?, org);
-        }
+        let search = eg::hash! {
+            "code": {"!=": EgValue::Null},
+        };
+
+        let modifiers = e.search("ccm", search)?;
 
-        self.org_cache = Some(map);
+        self.cache.load_lookup_table("ccm", modifiers);
 
         Ok(())
     }
@@ -1,15 +1,30 @@
+use super::access;
+use super::access::{AccountSessionGuard, AccountSessions};
+use super::audit::{AuditContext, AuditLogger, Direction};
+use super::cache::SharedCache;
 use super::conf;
-use eg::auth;
-use eg::auth::AuthSession;
-use eg::result::EgResult;
+use super::custom::FieldValues;
+use super::i18n::Translator;
+use super::offline::{self, OfflineJournal};
+use super::ratelimit::{
+    AccountRateLimits, RateDecision, RateLimiter, SystemClock, RATE_LIMIT_SCREEN_MSG,
+};
+use super::shutdown::{SessionHandle, ShutdownCoordinator};
+use super::stats::ServerStats;
+use super::version::ProtocolVersion;
+use eg::bindings::{ApiRequest, ApiResponse};
+use eg::common::auth;
+use eg::common::auth::Session as AuthSession;
+use eg::osrf::logging::Logger;
+use eg::result::{EgError, EgResult};
 use eg::EgValue;
 use evergreen as eg;
 use sip2;
-use std::collections::HashMap;
 use std::fmt;
-use std::net;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /* --------------------------------------------------------- */
 // By order of appearance in the INSTITUTION_SUPPORTS string:
@@ -29,17 +44,58 @@ use std::sync::Arc;
 // hold
 // renew
 // renew all
-const INSTITUTION_SUPPORTS: &str = "YYYNYNYYNYYNNNYN";
+const INSTITUTION_SUPPORTS: &str = "YYYNYNYYNYYYNNYN";
 /* --------------------------------------------------------- */
 
+/// Attempts to reconnect to OpenSRF after a transport failure.
+const OSRF_RECONNECT_ATTEMPTS: u32 = 4;
+
+/// Wait this long before the first reconnect attempt, doubling
+/// after each failed attempt.
+const OSRF_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Extend our auth session once it has fewer than this many seconds
+/// remaining, so long-lived SIP sessions don't have to login again.
+const AUTH_REFRESH_THRESHOLD: i64 = 300;
+
+/// Screen message returned when an Evergreen API call takes too long.
+const OSRF_TIMEOUT_SCREEN_MSG: &str =
+    "The system is not responding.  Please try again or see staff.";
+
+/// Server-wide state shared by all Sessions.  Cloning is cheap.
+#[derive(Clone)]
+pub struct SharedState {
+    pub shutdown: ShutdownCoordinator,
+
+    /// Logged-in session counts per SIP account.
+    pub account_sessions: AccountSessions,
+
+    /// SIP traffic audit log, which may be disabled.
+    pub audit_log: AuditLogger,
+
+    /// Set if offline transactions are enabled.
+    pub offline: Option<Arc<OfflineJournal>>,
+
+    /// Screen message translations.
+    pub translator: Arc<Translator>,
+
+    /// Org units, etc.
+    pub cache: SharedCache,
+
+    pub stats: ServerStats,
+
+    /// Per-account request rate limits.
+    pub rate_limits: AccountRateLimits,
+}
+
 /// Manages a single SIP client connection.
 ///
 /// May process multiple connections over time.
 pub struct Session {
     sip_connection: sip2::Connection,
 
-    /// If true, the server is shutting down, so we should exit.
-    shutdown: Arc<AtomicBool>,
+    /// Tells us when the server is shutting down, so we should exit.
+    shutdown: SessionHandle,
 
     sip_config: Arc<conf::Config>,
 
@@ -54,36 +110,113 @@ pub struct Session {
     /// SIP account, set after the client logs in.
     account: Option<conf::SipAccount>,
 
-    /// Cache of org unit shortnames and IDs.
-    org_cache: HashMap<i64, EgValue>,
+    /// Shared count of logged-in sessions per SIP account.
+    account_sessions: AccountSessions,
+
+    /// Holds our slot in account_sessions while logged in.
+    account_session: Option<AccountSessionGuard>,
+
+    /// Address of our SIP client.
+    peer_addr: Option<IpAddr>,
+
+    /// SIP traffic audit log, which may be disabled.
+    audit_log: AuditLogger,
+
+    /// Set if offline transactions are enabled.
+    offline: Option<Arc<OfflineJournal>>,
+
+    /// Set while we are answering requests offline because we could
+    /// not reach OpenSRF.  We try to reconnect once it passes.
+    offline_until: Option<Instant>,
+
+    /// Screen message translations.
+    translator: Arc<Translator>,
+
+    /// lib.timezone org setting for our ILS login, used for SIP
+    /// dates when the SIP account has no timezone.
+    org_timezone: Option<String>,
+
+    /// Max print width declared by our SIP client in its SC Status
+    /// request.  None if unknown or unlimited.
+    max_print_width: Option<usize>,
+
+    /// Protocol version declared by our SIP client in its SC Status
+    /// request.
+    protocol_version: Option<ProtocolVersion>,
+
+    /// Org units, etc. shared by all Sessions.
+    cache: SharedCache,
+
+    stats: ServerStats,
+
+    /// Paces our client's requests.
+    rate_limiter: RateLimiter,
+
+    /// Patron and item values loaded for the current SIP request,
+    /// used for response field mappings.
+    field_values: FieldValues,
+
+    /// True once we have faked a transport failure for testing.
+    transport_failure_simulated: bool,
 }
 
 impl Session {
     pub fn new(
         sip_config: Arc<conf::Config>,
         osrf_bus: eg::osrf::bus::Bus,
-        stream: net::TcpStream,
-        shutdown: Arc<AtomicBool>,
-        org_cache: HashMap<i64, EgValue>,
+        stream: Box<dyn sip2::SipStream>,
+        shared: SharedState,
     ) -> Self {
-        if let Ok(a) = stream.peer_addr() {
+        let mut label = String::from("SIPSession");
+        let mut peer_addr = None;
+
+        if let Ok(a) = stream.tcp_stream().peer_addr() {
             log::info!("New SIP connection from {a}");
+            label = format!("SIPSession({a})");
+            peer_addr = Some(a.ip());
         }
 
-        let mut con = sip2::Connection::from_stream(stream);
-        con.set_ascii(sip_config.ascii());
+        // The coordinator uses a clone of our socket to wake us
+        // when the server is shutting down.
+        let shutdown = shared
+            .shutdown
+            .register(&label, stream.tcp_stream().try_clone().ok());
+
+        let mut con = sip2::Connection::from_sip_stream(stream);
+        con.set_encoding(sip_config.encoding());
 
         let osrf_client = eg::Client::from_bus(osrf_bus);
 
-        let editor = eg::Editor::new(&osrf_client);
+        let mut editor = eg::Editor::new(&osrf_client);
+        editor.set_timeout(sip_config.osrf_request_timeout() as i32);
+
+        let rate_limiter = RateLimiter::new(
+            sip_config.rate_limit(),
+            shared.rate_limits,
+            Arc::new(SystemClock),
+        );
 
         Session {
             editor,
+            rate_limiter,
             shutdown,
             sip_config,
             osrf_client,
-            org_cache,
+            peer_addr,
+            cache: shared.cache,
+            stats: shared.stats,
+            account_sessions: shared.account_sessions,
+            audit_log: shared.audit_log,
+            offline: shared.offline,
+            offline_until: None,
+            translator: shared.translator,
+            org_timezone: None,
+            max_print_width: None,
+            protocol_version: None,
             account: None,
+            account_session: None,
+            field_values: FieldValues::default(),
+            transport_failure_simulated: false,
             sip_connection: con,
         }
     }
@@ -94,12 +227,41 @@ impl Session {
         self.osrf_client.take_bus()
     }
 
-    pub fn org_cache(&self) -> &HashMap<i64, EgValue> {
-        &self.org_cache
+    pub fn cache(&self) -> &SharedCache {
+        &self.cache
     }
 
-    pub fn org_cache_mut(&mut self) -> &mut HashMap<i64, EgValue> {
-        &mut self.org_cache
+    pub fn translator(&self) -> &Translator {
+        &self.translator
+    }
+
+    pub fn org_timezone(&self) -> Option<&str> {
+        self.org_timezone.as_deref()
+    }
+
+    pub fn offline_journal(&self) -> Option<&OfflineJournal> {
+        self.offline.as_deref()
+    }
+
+    pub fn field_values(&self) -> &FieldValues {
+        &self.field_values
+    }
+
+    pub fn field_values_mut(&mut self) -> &mut FieldValues {
+        &mut self.field_values
+    }
+
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    pub fn rate_limiter_mut(&mut self) -> &mut RateLimiter {
+        &mut self.rate_limiter
+    }
+
+    /// Address of our SIP client.
+    pub fn peer_addr(&self) -> Option<IpAddr> {
+        self.peer_addr
     }
 
     /// True if our SIP client has successfully logged in.
@@ -121,8 +283,26 @@ impl Session {
         &self.sip_config
     }
 
-    pub fn osrf_client_mut(&mut self) -> &mut eg::Client {
-        &mut self.osrf_client
+    /// Max print width declared by our SIP client, if any.
+    pub fn max_print_width(&self) -> Option<usize> {
+        self.max_print_width
+    }
+
+    /// Protocol version declared by our SIP client, if any.
+    pub fn declared_protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+
+    /// Character encoding of our SIP connection.
+    pub fn encoding(&self) -> sip2::Encoding {
+        self.sip_connection.encoding()
+    }
+
+    /// Validate and send a typed API request, giving up once our
+    /// configured OpenSRF request timeout passes.
+    pub fn execute<R: ApiRequest>(&mut self, req: &R) -> EgResult<ApiResponse<R::Output>> {
+        let timeout = Duration::from_secs(self.sip_config.osrf_request_timeout());
+        req.execute_with_timeout(&self.osrf_client, timeout)
     }
 
     pub fn editor_mut(&mut self) -> &mut eg::editor::Editor {
@@ -139,6 +319,19 @@ impl Session {
     /// Returns Err if we fail to verify the token or login as needed.
     pub fn set_authtoken(&mut self) -> EgResult<()> {
         if self.editor.authtoken().is_some() {
+            let expiring = self
+                .editor
+                .auth_seconds_remaining()
+                .map(|s| s < AUTH_REFRESH_THRESHOLD)
+                .unwrap_or(false);
+
+            if expiring && !self.editor.refresh_auth()? {
+                // Auth session is gone, e.g. the auth cache was
+                // flushed.  There's nothing to logout.
+                log::info!("{self} auth session expired; logging in again");
+                return self.login();
+            }
+
             // If we have an authtoken, verify it's still valid.
             if self.editor.checkauth()? {
                 return Ok(());
@@ -189,7 +382,7 @@ impl Session {
     /// Create a internal auth session in the ILS
     fn login(&mut self) -> EgResult<()> {
         let ils_user_id = self.get_ils_user_id()?;
-        let mut args = auth::AuthInternalLoginArgs::new(ils_user_id, "staff");
+        let mut args = auth::InternalLoginArgs::new(ils_user_id, auth::LoginType::Staff);
 
         if self.has_account() {
             if let Some(w) = self.account().workstation() {
@@ -197,19 +390,148 @@ impl Session {
             }
         }
 
-        let auth_ses = match AuthSession::internal_session(&self.osrf_client, &args)? {
+        let auth_ses = match AuthSession::internal_session_api(&self.osrf_client, &args) {
+            Err(EgError::Event(evt))
+                if evt.textcode() == "WORKSTATION_NOT_FOUND"
+                    && self.has_account()
+                    && self.account().allow_auto_workstation() =>
+            {
+                self.register_workstation(ils_user_id)?;
+
+                // Try once more now that the workstation exists.
+                AuthSession::internal_session_api(&self.osrf_client, &args)?
+            }
+            result => result?,
+        };
+
+        let auth_ses = match auth_ses {
             Some(s) => s,
             None => Err(format!("Internal Login failed"))?,
         };
 
-        self.editor.set_authtoken(auth_ses.token());
+        self.editor.apply_auth_session(&auth_ses);
 
         // Set editor.requestor
         self.editor.checkauth()?;
 
+        if self.has_account() && self.account().timezone().is_none() {
+            self.set_org_timezone()?;
+        }
+
+        self.set_request_locale();
+
+        Ok(())
+    }
+
+    /// Register our account's workstation in the ILS.
+    ///
+    /// The workstation is created under an internal session without
+    /// a workstation, which is removed once the workstation exists.
+    fn register_workstation(&mut self, ils_user_id: i64) -> EgResult<()> {
+        let args = auth::InternalLoginArgs::new(ils_user_id, auth::LoginType::Staff);
+
+        let auth_ses = match AuthSession::internal_session_api(&self.osrf_client, &args)? {
+            Some(s) => s,
+            None => Err("Internal Login failed".to_string())?,
+        };
+
+        self.editor.apply_auth_session(&auth_ses);
+
+        let result = self
+            .editor
+            .checkauth()
+            .and_then(|_| auth_ses.user()["home_ou"].int())
+            .and_then(|home_ou| self.create_workstation(home_ou));
+
+        if let Err(e) = AuthSession::logout(&self.osrf_client, auth_ses.token()) {
+            log::warn!("{self} could not remove workstation registration session: {e}");
+        }
+
+        result
+    }
+
+    /// Create the account's workstation at its workstation-org, or at
+    /// the ILS user's home org unit.
+    ///
+    /// A workstation by the same name at a different org unit belongs
+    /// to another location and is left alone.
+    fn create_workstation(&mut self, home_ou: i64) -> EgResult<()> {
+        let name = self
+            .account()
+            .workstation()
+            .ok_or_else(|| format!("{self} has no workstation to register"))?
+            .to_string();
+
+        let org_id = match self.account().workstation_org().map(|s| s.to_string()) {
+            Some(sn) => match self.org_from_sn(&sn)? {
+                Some(org) => org.id()?,
+                None => Err(format!("No such workstation org unit: {sn}"))?,
+            },
+            None => home_ou,
+        };
+
+        {
+            let mut editor = self.editor.xact_guard()?;
+
+            let existing = editor.search("aws", eg::hash! {name: name.as_str()})?;
+
+            if let Some(ws) = existing.first() {
+                // Registered by someone else since our login attempt.
+                editor.rollback()?;
+
+                let owning_lib = ws["owning_lib"].int()?;
+                if owning_lib != org_id {
+                    return Err(format!(
+                        "Workstation {name} is registered at org unit {owning_lib}, not {org_id}"
+                    )
+                    .into());
+                }
+
+                return Ok(());
+            }
+
+            let aws = EgValue::create("aws", eg::hash! {name: name.as_str(), owning_lib: org_id})?;
+
+            editor.create(aws)?;
+            editor.commit()?;
+        }
+
+        log::info!("{self} registered workstation {name} at org unit {org_id}");
+
+        Ok(())
+    }
+
+    /// Load the lib.timezone setting for the workstation (or home)
+    /// org unit of our ILS login.
+    fn set_org_timezone(&mut self) -> EgResult<()> {
+        let org_id = self.get_ws_org_id()?;
+
+        self.org_timezone = self
+            .org_setting(org_id, "lib.timezone")?
+            .as_str()
+            .map(|tz| tz.to_string());
+
+        if let Some(tz) = self.org_timezone.as_deref() {
+            log::debug!("{self} using org unit timezone {tz}");
+        }
+
         Ok(())
     }
 
+    /// Send our account's locale and our SIP time zone with every
+    /// backend request, so the ILS renders text and dates (e.g. due
+    /// dates) the way our client expects.  Clears both when we have
+    /// no account.
+    fn set_request_locale(&self) {
+        if self.has_account() {
+            self.osrf_client.set_locale(self.account().locale());
+            self.osrf_client.set_timezone(Some(self.timezone()));
+        } else {
+            self.osrf_client.set_locale(None);
+            self.osrf_client.set_timezone(None);
+        }
+    }
+
     /// Wait for SIP requests in a loop and send replies.
     ///
     /// Exits when the shutdown signal is set or on unrecoverable error.
@@ -217,7 +539,7 @@ impl Session {
         log::debug!("{self} starting");
 
         loop {
-            if self.shutdown.load(Ordering::Relaxed) {
+            if self.shutdown.should_stop() {
                 log::debug!("{self} Shutdown notice received, exiting listen loop");
                 break;
             }
@@ -240,16 +562,51 @@ impl Session {
                 None => continue,
             };
 
+            // One log trace per SIP message, carried through to any
+            // OpenSRF calls made on its behalf.
+            Logger::mk_log_trace();
+
+            log::debug!("{self} received SIP message {}", sip_req.spec().code);
             log::trace!("{self} Read SIP message: {:?}", sip_req);
 
-            let mut sip_resp = self.handle_sip_request(&sip_req)?;
+            self.audit(Direction::Inbound, &sip_req);
+
+            self.field_values.clear();
+
+            let mut sip_resp = match self.pace_request() {
+                RateDecision::Proceed(_) => match self.handle_sip_request_with_retry(&sip_req) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        self.stats.request_error();
+                        return Err(e);
+                    }
+                },
+                RateDecision::Reject => {
+                    let screen_msg = self.tr(RATE_LIMIT_SCREEN_MSG);
+                    match self.unavailable_response(&sip_req, &screen_msg) {
+                        Some(r) => r,
+                        // No response for this message type.  Let the
+                        // client time out.
+                        None => continue,
+                    }
+                }
+                RateDecision::Disconnect => break,
+            };
 
             log::trace!("{self} server replying with {sip_resp:?}");
 
+            self.apply_field_customizations(&mut sip_resp);
+
+            self.apply_response_length_limits(&mut sip_resp);
+
+            self.apply_protocol_version(&mut sip_resp);
+
             self.redact_sip_response(&mut sip_resp);
 
             log::trace!("{self} server response after redaction: {sip_resp:?}");
 
+            self.audit(Direction::Outbound, &sip_resp);
+
             // Send the SIP response back to the SIP client
             self.sip_connection
                 .send(&sip_resp)
@@ -266,6 +623,10 @@ impl Session {
             AuthSession::logout(&self.osrf_client, self.authtoken()?).ok();
         }
 
+        // Our osrf client outlives this SIP connection.
+        self.osrf_client.set_locale(None);
+        self.osrf_client.set_timezone(None);
+
         // Remove any cruft we may have left on the bus.
         self.osrf_client.clear()?;
 
@@ -304,10 +665,245 @@ impl Session {
         }
     }
 
-    /// Process a single SIP request.
+    /// Process a SIP request.  If it fails because our OpenSRF
+    /// connection dropped, reconnect and try it once more.
+    ///
+    /// Timeouts and API events produce an ok=0 response where the
+    /// message type has one.  Any other error ends the session.
+    ///
+    /// If we cannot reconnect and offline transactions are enabled,
+    /// the request is answered offline.
+    fn handle_sip_request_with_retry(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
+        if let Some(resp) = self.offline_request(msg)? {
+            return Ok(resp);
+        }
+
+        let err = match self.handle_sip_request(msg) {
+            Ok(resp) => return Ok(resp),
+            Err(e) => e,
+        };
+
+        if err.is_timeout() {
+            log::warn!("{self} OpenSRF request timed out: {err}");
+            self.stats.timeout();
+            self.reset_after_timeout()?;
+
+            if let Some(resp) = self.failure_response(msg, &self.tr(OSRF_TIMEOUT_SCREEN_MSG)) {
+                return Ok(resp);
+            }
+        }
+
+        if let Some(evt) = err
+            .event()
+            .filter(|e| e.textcode() == "TRANSACTION_TIMEOUT")
+        {
+            // The editor already rolled back the transaction.
+            log::warn!("{self} database transaction timed out: {evt}");
+            self.stats.timeout();
+
+            if let Some(resp) = self.failure_response(msg, &self.tr(OSRF_TIMEOUT_SCREEN_MSG)) {
+                return Ok(resp);
+            }
+        }
+
+        if let Some(evt) = err.event() {
+            // The request was understood, but failed.  Tell the SIP
+            // client why instead of dropping the connection.
+            log::info!("{self} SIP message {} failed: {evt}", msg.spec().code);
+
+            let screen_msg = evt.desc().unwrap_or(evt.textcode());
+
+            if let Some(resp) = self.failure_response(msg, screen_msg) {
+                return Ok(resp);
+            }
+        }
+
+        if !err.is_transport() {
+            return Err(err);
+        }
+
+        log::warn!("{self} OpenSRF transport error: {err}");
+
+        self.stats.transport_error();
+
+        if let Err(e) = self.reconnect() {
+            if self.offline.is_none() {
+                return Err(e);
+            }
+
+            log::warn!("{self} answering requests offline: {e}");
+            self.offline_until = Some(Instant::now() + offline::OFFLINE_RECHECK_INTERVAL);
+
+            if let Some(resp) = self.offline_response(msg)? {
+                return Ok(resp);
+            }
+
+            return Err(e);
+        }
+
+        log::info!("{self} retrying SIP message {}", msg.spec().code);
+
+        self.handle_sip_request(msg)
+    }
+
+    /// Answer the request offline if we recently failed to reach
+    /// OpenSRF.  Once the offline period passes, try to reconnect.
+    fn offline_request(&mut self, msg: &sip2::Message) -> EgResult<Option<sip2::Message>> {
+        let until = match self.offline_until {
+            Some(u) => u,
+            None => return Ok(None),
+        };
+
+        if Instant::now() >= until {
+            if self.reconnect().is_ok() {
+                log::info!("{self} back online");
+                self.offline_until = None;
+                return Ok(None);
+            }
+
+            self.offline_until = Some(Instant::now() + offline::OFFLINE_RECHECK_INTERVAL);
+        }
+
+        match self.offline_response(msg)? {
+            Some(resp) => Ok(Some(resp)),
+            None => Err(EgError::Transport(format!(
+                "{self} cannot answer SIP message {} offline",
+                msg.spec().code
+            ))),
+        }
+    }
+
+    /// Abandon any state left over from a timed out API call, keeping
+    /// our authtoken.
+    fn reset_after_timeout(&mut self) -> EgResult<()> {
+        let authtoken = self.editor.authtoken().map(|a| a.to_string());
+
+        self.editor = eg::Editor::new(&self.osrf_client);
+        self.editor
+            .set_timeout(self.sip_config.osrf_request_timeout() as i32);
+
+        if let Some(token) = authtoken {
+            self.editor.set_authtoken(&token);
+        }
+
+        // Discard anything which arrived for the abandoned call.
+        self.osrf_client.clear()
+    }
+
+    /// ok=0 response for SIP messages which failed without losing
+    /// our OpenSRF connection, e.g. a backend timeout or an API event,
+    /// with the provided AF screen message.
+    ///
+    /// Returns None for message types with no failure response.
+    pub fn failure_response(&self, msg: &sip2::Message, screen_msg: &str) -> Option<sip2::Message> {
+        if !self.has_account() {
+            return None;
+        }
+
+        let item_barcode = msg.get_field_value("AB").unwrap_or("");
+        let patron_barcode = msg.get_field_value("AA").unwrap_or("");
+
+        let mut resp = match msg.spec().code {
+            "09" => self.return_checkin_item_not_found(item_barcode),
+            "11" => self.checkout_item_not_found(item_barcode, patron_barcode),
+            "37" => self.payment_failed(patron_barcode),
+            c if c == self.sip_config.patron_register_message() => {
+                // Has its own screen message.
+                return Some(self.patron_register_response(None, screen_msg));
+            }
+            _ => return None,
+        };
+
+        resp.add_field("AF", screen_msg);
+
+        Some(resp)
+    }
+
+    /// Replace our OpenSRF bus connection and login again.
+    ///
+    /// Retries with increasing delays until we connect or run out of
+    /// attempts.
+    fn reconnect(&mut self) -> EgResult<()> {
+        let mut delay = OSRF_RECONNECT_DELAY;
+
+        for attempt in 1..=OSRF_RECONNECT_ATTEMPTS {
+            if self.shutdown.should_stop() {
+                break;
+            }
+
+            thread::sleep(delay);
+            delay *= 2;
+
+            let mut bus = match eg::osrf::bus::Bus::new(eg::osrf::conf::config().client()) {
+                Ok(b) => b,
+                Err(e) => {
+                    log::warn!("{self} OpenSRF reconnect attempt {attempt} failed: {e}");
+                    continue;
+                }
+            };
+
+            // Keep our address so existing Client references stay valid.
+            bus.set_address(self.osrf_client.address());
+            self.osrf_client.set_bus(bus);
+            self.osrf_client.clear()?;
+
+            // Our authtoken and any editor state went with the old
+            // connection.
+            self.editor = eg::Editor::new(&self.osrf_client);
+            self.editor
+                .set_timeout(self.sip_config.osrf_request_timeout() as i32);
+
+            if self.has_account() {
+                self.login()?;
+            }
+
+            log::info!("{self} reconnected to OpenSRF");
+
+            return Ok(());
+        }
+
+        Err(EgError::Transport(format!(
+            "{self} unable to reconnect to OpenSRF"
+        )))
+    }
+
     fn handle_sip_request(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
         let code = msg.spec().code;
 
+        self.stats.message_received(code);
+        self.shutdown.touch();
+
+        let start = Instant::now();
+        let result = self.dispatch_sip_request(msg);
+
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(e) if e.is_timeout() => "timeout",
+            Err(e) if e.is_transport() => "transport",
+            Err(e) if e.event().is_some() => "event",
+            Err(_) => "error",
+        };
+
+        self.stats.message_handled(code, outcome, start.elapsed());
+
+        result
+    }
+
+    /// Route a SIP request to its handler.
+    fn dispatch_sip_request(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
+        let code = msg.spec().code;
+
+        if self.simulate_transport_failure(code) {
+            return Err(EgError::Transport(format!(
+                "Simulated transport failure on message {code}"
+            )));
+        }
+
+        // Avoid starting any new backend calls once shutdown is underway.
+        if self.shutdown.should_stop() {
+            Err(format!("{self} shutting down; ignoring SIP message {code}"))?;
+        }
+
         if code.eq("99") {
             // May not require an existing login / account
             return self.handle_sc_status(msg);
@@ -327,16 +923,40 @@ impl Session {
             "09" => self.handle_checkin(msg),
             "11" => self.handle_checkout(msg),
             "17" => self.handle_item_info(msg),
+            "19" => self.handle_item_status_update(msg),
             "23" => self.handle_patron_status(msg),
             "35" => self.handle_end_patron_session(msg),
             "37" => self.handle_payment(msg),
             "63" => self.handle_patron_info(msg),
+            c if c == self.sip_config.patron_register_message() => self.handle_patron_register(msg),
             _ => Err(format!("Unsupported SIP message code={}", msg.spec().code).into()),
         }
     }
 
+    /// True if this message should fail as if the OpenSRF connection
+    /// dropped, so the reconnect logic can be tested.  Fails only once
+    /// per Session.
+    fn simulate_transport_failure(&mut self, code: &str) -> bool {
+        if self.transport_failure_simulated || !self.has_account() {
+            return false;
+        }
+
+        if self.account().simulate_transport_failure() != Some(code) {
+            return false;
+        }
+
+        log::warn!("{self} simulating transport failure on message {code}");
+
+        self.transport_failure_simulated = true;
+
+        true
+    }
+
     fn handle_login(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
         self.account = None;
+        self.shutdown.set_account(None);
+        self.account_session = None; // release any previous login slot
+        self.org_timezone = None;
         let mut login_ok = "0";
 
         if let Some(username) = msg.get_field_value("CN") {
@@ -344,9 +964,14 @@ impl Session {
                 // Caller sent enough values to attempt login
 
                 if let Some(account) = self.sip_config().get_account(&username) {
-                    if account.sip_password().eq(password) {
-                        login_ok = "1";
-                        self.account = Some(account.clone());
+                    if account.verify_sip_password(password) {
+                        if let Some(guard) = self.check_login_access(account) {
+                            login_ok = "1";
+                            self.account = Some(account.clone());
+                            self.account_session = Some(guard);
+                            self.shutdown.set_label(&self.to_string());
+                            self.shutdown.set_account(Some(username));
+                        }
                     }
                 } else {
                     log::warn!("No such SIP account: {username}");
@@ -358,14 +983,93 @@ impl Session {
             log::warn!("Login called with no username");
         }
 
+        let encoding = match self.account.as_ref().and_then(|a| a.encoding()) {
+            Some(enc) => enc,
+            None => self.sip_config().encoding(),
+        };
+
+        self.sip_connection.set_encoding(encoding);
+
+        // Refined once the ILS login reveals the org unit time zone.
+        self.set_request_locale();
+
+        let rate_limit = self.rate_limit_config().clone();
+        self.rate_limiter.configure(&rate_limit);
+
         Ok(sip2::Message::from_ff_values(&sip2::spec::M_LOGIN_RESP, &[login_ok]).unwrap())
     }
 
-    fn handle_sc_status(&mut self, _msg: &sip2::Message) -> EgResult<sip2::Message> {
+    /// Add a message to the audit log, if enabled.
+    fn audit(&self, direction: Direction, msg: &sip2::Message) {
+        if self.audit_log.is_enabled() {
+            let trace = Logger::get_log_trace();
+
+            let ctx = AuditContext {
+                sesid: self.shutdown.id(),
+                peer: self.peer_addr,
+                account: self.account.as_ref().map(|a| a.sip_username()),
+                trace: Some(&trace),
+            };
+
+            self.audit_log.log(&ctx, direction, msg);
+        }
+    }
+
+    /// Apply the account's address allowlist and session limit.
+    ///
+    /// Returns the guard holding our account session slot on success.
+    fn check_login_access(&self, account: &conf::SipAccount) -> Option<AccountSessionGuard> {
+        let username = account.sip_username();
+        let peer = self
+            .peer_addr
+            .map(|a| a.to_string())
+            .unwrap_or("<unknown>".to_string());
+
+        if !account.allowed_addresses().is_empty() {
+            let allowed = self
+                .peer_addr
+                .map(|a| access::address_allowed(account.allowed_addresses(), &a))
+                .unwrap_or(false);
+
+            if !allowed {
+                log::warn!("Login to SIP account {username} rejected from unlisted address {peer}");
+                return None;
+            }
+        }
+
+        let guard = self
+            .account_sessions
+            .acquire(username, account.max_sessions());
+
+        if guard.is_none() {
+            log::warn!(
+                "Login to SIP account {username} from {peer} rejected: \
+                {} of max-sessions={} already active",
+                self.account_sessions.count(username),
+                account.max_sessions().unwrap_or(0)
+            );
+        }
+
+        guard
+    }
+
+    fn handle_sc_status(&mut self, msg: &sip2::Message) -> EgResult<sip2::Message> {
         if self.account.is_none() && !self.sip_config().sc_status_before_login() {
             Err(format!("SC Status before login disabled"))?;
         }
 
+        // A width of 0 (or junk) means the client did not say.
+        self.max_print_width = msg
+            .fixed_fields()
+            .get(1)
+            .and_then(|ff| ff.value().trim().parse::<usize>().ok())
+            .filter(|w| *w > 0);
+
+        self.protocol_version = msg
+            .fixed_fields()
+            .get(2)
+            .and_then(|ff| ProtocolVersion::parse(ff.value()));
+
         let mut resp = sip2::Message::from_values(
             &sip2::spec::M_ACS_STATUS,
             &[
@@ -377,8 +1081,8 @@ impl Session {
                 "N",   // offline ok
                 "999", // timeout
                 "999", // max retries
-                &sip2::util::sip_date_now(),
-                "2.00", // SIP version
+                &self.sip_date_now(),
+                sip2::spec::SIP_PROTOCOL_VERSION,
             ],
             &[("BX", INSTITUTION_SUPPORTS)],
         )
@@ -393,8 +1097,8 @@ impl Session {
                 self.set_authtoken()?;
 
                 if let Some(org) = self.org_from_id(self.get_ws_org_id()?)? {
-                    resp.add_field("AM", org["name"].as_str().unwrap());
-                    resp.add_field("AN", org["shortname"].as_str().unwrap());
+                    resp.add_field("AM", org.field_str("name")?.unwrap_or(""));
+                    resp.add_field("AN", org.field_str("shortname")?.unwrap_or(""));
                 }
             }
         }
@@ -0,0 +1,362 @@
+//! Phased shutdown coordination between the Server and its Sessions.
+//!
+//! Shutdown proceeds through these phases:
+//!
+//! 1. StopAccepting -- No new SIP connections are handed to Sessions.
+//! 2. Drain -- Sessions finish the SIP message they are processing,
+//!    then exit.  Idle sessions are woken immediately.
+//! 3. Force -- Sessions still active once the drain deadline passes
+//!    have their sockets closed.
+use super::registry::{SessionRegistration, SessionRegistry};
+use std::fmt;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default amount of time active sessions have to finish their
+/// current request before being forcibly closed.
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 10;
+
+/// Default amount of time to wait for forcibly closed sessions to exit.
+pub const DEFAULT_FORCE_TIMEOUT: u64 = 5;
+
+/// How often we check for exited sessions while waiting.
+const SESSION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum ShutdownPhase {
+    Running,
+    StopAccepting,
+    Drain,
+    Force,
+}
+
+impl From<u8> for ShutdownPhase {
+    fn from(n: u8) -> ShutdownPhase {
+        match n {
+            0 => Self::Running,
+            1 => Self::StopAccepting,
+            2 => Self::Drain,
+            _ => Self::Force,
+        }
+    }
+}
+
+impl From<ShutdownPhase> for u8 {
+    fn from(p: ShutdownPhase) -> u8 {
+        match p {
+            ShutdownPhase::Running => 0,
+            ShutdownPhase::StopAccepting => 1,
+            ShutdownPhase::Drain => 2,
+            ShutdownPhase::Force => 3,
+        }
+    }
+}
+
+/// What happened during a shutdown.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// Sessions still active when the drain deadline passed.
+    pub lingering: Vec<String>,
+
+    /// Sessions still active after being forcibly closed.
+    pub abandoned: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// True if every session exited within the drain deadline.
+    pub fn clean(&self) -> bool {
+        self.lingering.is_empty()
+    }
+}
+
+struct Inner {
+    phase: AtomicU8,
+    registry: SessionRegistry,
+    drain_timeout: Duration,
+    force_timeout: Duration,
+}
+
+/// Shared by the Server and all Sessions; cloning is cheap.
+#[derive(Clone)]
+pub struct ShutdownCoordinator {
+    inner: Arc<Inner>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(
+        registry: SessionRegistry,
+        drain_timeout: Duration,
+        force_timeout: Duration,
+    ) -> Self {
+        ShutdownCoordinator {
+            inner: Arc::new(Inner {
+                phase: AtomicU8::new(ShutdownPhase::Running.into()),
+                registry,
+                drain_timeout,
+                force_timeout,
+            }),
+        }
+    }
+
+    /// Registry of the sessions we coordinate.
+    pub fn registry(&self) -> &SessionRegistry {
+        &self.inner.registry
+    }
+
+    pub fn phase(&self) -> ShutdownPhase {
+        self.inner.phase.load(Ordering::Relaxed).into()
+    }
+
+    /// True once any shutdown phase has begun.
+    pub fn shutting_down(&self) -> bool {
+        self.phase() > ShutdownPhase::Running
+    }
+
+    /// Phases only move forward.
+    fn advance(&self, phase: ShutdownPhase) {
+        self.inner.phase.fetch_max(phase.into(), Ordering::Relaxed);
+    }
+
+    /// Register a session so it can be told to exit.
+    ///
+    /// The session is deregistered when the returned handle is dropped.
+    pub fn register(&self, label: &str, waker: Option<TcpStream>) -> SessionHandle {
+        // A session created mid-shutdown should exit right away, but
+        // still needs to be tracked until it does.
+        SessionHandle {
+            registration: self.inner.registry.register(label, waker),
+            coordinator: self.clone(),
+        }
+    }
+
+    /// Labels of all registered sessions.
+    pub fn active_sessions(&self) -> Vec<String> {
+        self.inner.registry.labels()
+    }
+
+    /// Wait up to `timeout` for all sessions to exit.
+    ///
+    /// Returns true if all sessions exited.
+    fn wait_for_sessions(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            if self.inner.registry.is_empty() {
+                return true;
+            }
+
+            if start.elapsed() >= timeout {
+                return false;
+            }
+
+            thread::sleep(SESSION_POLL_INTERVAL);
+        }
+    }
+
+    /// Run all shutdown phases, returning once all sessions have
+    /// exited or the force deadline has passed.
+    pub fn shutdown(&self) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+
+        log::info!("Shutdown: no longer accepting new SIP connections");
+        self.advance(ShutdownPhase::StopAccepting);
+
+        log::info!(
+            "Shutdown: draining {} active session(s)",
+            self.active_sessions().len()
+        );
+        self.advance(ShutdownPhase::Drain);
+
+        // Closing the read side wakes sessions blocked waiting for a
+        // SIP message without interfering with any response a busy
+        // session has yet to send.
+        self.inner.registry.wake_all(Shutdown::Read);
+
+        if self.wait_for_sessions(self.inner.drain_timeout) {
+            log::info!("Shutdown: all sessions exited cleanly");
+            return report;
+        }
+
+        report.lingering = self.active_sessions();
+
+        log::warn!(
+            "Shutdown: sessions still active after drain deadline: {:?}",
+            report.lingering
+        );
+
+        self.advance(ShutdownPhase::Force);
+        self.inner.registry.wake_all(Shutdown::Both);
+
+        if !self.wait_for_sessions(self.inner.force_timeout) {
+            report.abandoned = self.active_sessions();
+            log::error!(
+                "Shutdown: sessions did not exit after force-close: {:?}",
+                report.abandoned
+            );
+        }
+
+        report
+    }
+}
+
+/// Held by a Session for the duration of its connection.
+///
+/// The session is deregistered when the handle is dropped.
+pub struct SessionHandle {
+    registration: SessionRegistration,
+    coordinator: ShutdownCoordinator,
+}
+
+impl SessionHandle {
+    /// Unique ID for this session.
+    pub fn id(&self) -> u64 {
+        self.registration.id()
+    }
+
+    /// True if the session should stop processing SIP messages and exit.
+    ///
+    /// Sessions check this between SIP messages and before making
+    /// backend calls.
+    pub fn should_stop(&self) -> bool {
+        self.coordinator.phase() >= ShutdownPhase::Drain || self.registration.terminate_requested()
+    }
+
+    /// Record a SIP request.
+    pub fn touch(&self) {
+        self.registration.touch();
+    }
+
+    /// Update the label reported for this session, e.g. after login.
+    pub fn set_label(&self, label: &str) {
+        self.registration.set_label(label);
+    }
+
+    /// Update the SIP account reported for this session.
+    pub fn set_account(&self, account: Option<&str>) {
+        self.registration.set_account(account);
+    }
+}
+
+impl fmt::Display for SessionHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SessionHandle({})", self.id())
+    }
+}
+
+#[cfg(test)]
+fn connected_pair() -> (TcpStream, TcpStream) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (server, client)
+}
+
+#[test]
+fn test_idle_session_drains() {
+    use std::io::Read;
+
+    let coordinator = ShutdownCoordinator::new(
+        SessionRegistry::new(),
+        Duration::from_secs(2),
+        Duration::from_secs(1),
+    );
+    let (server, _client) = connected_pair();
+
+    let handle = coordinator.register("idle", Some(server.try_clone().unwrap()));
+
+    // Idle session blocked waiting for its client.
+    let session = thread::spawn(move || {
+        let mut server = server;
+        let mut buf = [0u8; 16];
+        while !handle.should_stop() {
+            if let Ok(0) = server.read(&mut buf) {
+                // Woken by the coordinator.
+                continue;
+            }
+        }
+    });
+
+    let report = coordinator.shutdown();
+    session.join().unwrap();
+
+    assert!(report.clean());
+    assert!(coordinator.active_sessions().is_empty());
+    assert_eq!(coordinator.phase(), ShutdownPhase::Drain);
+}
+
+#[test]
+fn test_slow_session_force_closed() {
+    use std::io::{Read, Write};
+
+    let coordinator = ShutdownCoordinator::new(
+        SessionRegistry::new(),
+        Duration::from_millis(100),
+        Duration::from_secs(2),
+    );
+    let (server, _client) = connected_pair();
+
+    let handle = coordinator.register("slow", Some(server.try_clone().unwrap()));
+
+    let session = thread::spawn(move || {
+        let mut server = server;
+
+        // Simulate a backend call which outlasts the drain deadline,
+        // ignoring the shutdown request in the meantime.
+        thread::sleep(Duration::from_millis(300));
+
+        // Our socket has been force-closed, so the response fails.
+        let mut buf = [0u8; 16];
+        let write_failed = server.write_all(b"98YYYYNN").is_err();
+        let read_closed = matches!(server.read(&mut buf), Ok(0) | Err(_));
+
+        drop(handle);
+        write_failed && read_closed
+    });
+
+    let report = coordinator.shutdown();
+
+    assert_eq!(report.lingering, vec!["slow".to_string()]);
+    assert!(report.abandoned.is_empty());
+    assert_eq!(coordinator.phase(), ShutdownPhase::Force);
+    assert!(session.join().unwrap());
+}
+
+#[test]
+fn test_drop_session() {
+    use std::io::Read;
+
+    let coordinator = ShutdownCoordinator::new(
+        SessionRegistry::new(),
+        Duration::from_secs(1),
+        Duration::from_secs(1),
+    );
+    let (server, mut client) = connected_pair();
+
+    let handle = coordinator.register("sip", Some(server.try_clone().unwrap()));
+    handle.set_account(Some("sip-user"));
+
+    let other = coordinator.register("other", None);
+
+    let registry = coordinator.registry();
+    let sessions = registry.sessions();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].id, handle.id());
+    assert_eq!(sessions[0].account.as_deref(), Some("sip-user"));
+    assert!(sessions[0].peer.is_some());
+    assert_eq!(sessions[1].peer, None);
+
+    assert_eq!(registry.terminate_account("nobody"), 0);
+    assert!(!registry.terminate(other.id() + 100));
+    assert!(registry.terminate(handle.id()));
+    assert!(handle.should_stop());
+    assert!(!other.should_stop());
+
+    // The client sees the connection close.
+    let mut buf = [0u8; 16];
+    assert!(matches!(client.read(&mut buf), Ok(0) | Err(_)));
+
+    assert_eq!(registry.terminate_account("sip-user"), 1);
+}
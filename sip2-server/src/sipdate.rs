@@ -0,0 +1,333 @@
+//! SIP date handling.
+//!
+//! Evergreen timestamps carry a time zone.  SIP dates are 18-character
+//! YYYYMMDDZZZZHHMMSS values where ZZZZ is blank for local time or
+//! "   Z" for UTC.  We report dates in the library's local time, so a
+//! due date of 23:59:59 in Chicago is not reported as 00:59:59 the
+//! next day by a server running in New York.
+//!
+//! The time zone comes from the account's timezone setting, then the
+//! lib.timezone org setting for the SIP account's workstation, then
+//! the server's local time zone.
+use super::session::Session;
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use eg::date;
+use eg::date::EgDate;
+use eg::result::EgResult;
+use evergreen as eg;
+use sip2::spec::SIP_DATE_FORMAT;
+
+/// Time zone used when none is configured.
+pub const DEFAULT_TIMEZONE: &str = "local";
+
+/// Format a timestamp as a SIP date in the time zone.
+pub fn format_sip_date(dt: &EgDate, timezone: &str) -> EgResult<String> {
+    let local = date::set_timezone(*dt, timezone)?;
+    Ok(local.format(SIP_DATE_FORMAT).to_string())
+}
+
+/// Format an Evergreen due date for the AH field.
+///
+/// Returns None for items which have no due date, e.g. non-circulating
+/// items.  When `sip_format` is false, the Evergreen value is returned
+/// as-is.
+pub fn format_due_date(
+    due_date: Option<&str>,
+    timezone: &str,
+    sip_format: bool,
+) -> EgResult<Option<String>> {
+    let iso_date = match due_date.filter(|d| !d.is_empty()) {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+
+    if !sip_format {
+        return Ok(Some(iso_date.to_string()));
+    }
+
+    let due_dt = date::parse_datetime(iso_date)?;
+
+    Ok(Some(format_sip_date(&due_dt, timezone)?))
+}
+
+/// Format an Evergreen due date for the AH field with a strftime-style
+/// format, e.g. "%B %-d, %Y %-I:%M %p" for "March 3, 2025 11:59 PM".
+///
+/// The format must have passed check_date_format().
+pub fn format_due_date_custom(
+    due_date: Option<&str>,
+    timezone: &str,
+    format: &str,
+) -> EgResult<Option<String>> {
+    let iso_date = match due_date.filter(|d| !d.is_empty()) {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+
+    let due_dt = date::set_timezone(date::parse_datetime(iso_date)?, timezone)?;
+
+    Ok(Some(due_dt.format(format).to_string()))
+}
+
+/// Verify a strftime-style date format can be applied.
+///
+/// Invalid formats cause formatting to panic, so they are rejected
+/// when the config is loaded.
+pub fn check_date_format(format: &str) -> Result<(), String> {
+    if format.is_empty() || StrftimeItems::new(format).any(|i| matches!(i, Item::Error)) {
+        return Err(format!("Invalid date format: '{format}'"));
+    }
+
+    Ok(())
+}
+
+/// Format an Evergreen date-only value, e.g. a birth date, as YYYYMMDD.
+///
+/// Date-only values are calendar days and are not shifted between
+/// time zones.
+pub fn format_sip_ymd(value: &str) -> EgResult<String> {
+    let ymd = value.get(..10).unwrap_or(value);
+
+    let day = NaiveDate::parse_from_str(ymd, "%Y-%m-%d")
+        .map_err(|e| format!("Invalid date value: {value} {e}"))?;
+
+    Ok(day.format("%Y%m%d").to_string())
+}
+
+/// Parse a SIP date into a timestamp.
+///
+/// Dates with a blank time zone are read as local time in the
+/// provided time zone.
+pub fn parse_sip_date(value: &str, timezone: &str) -> EgResult<EgDate> {
+    if value.len() != 18 || !value.is_char_boundary(8) || !value.is_char_boundary(12) {
+        return Err(format!("Invalid SIP date: '{value}'").into());
+    }
+
+    let zone = value[8..12].trim();
+    let naive_str = format!("{}    {}", &value[..8], &value[12..]);
+
+    let naive = NaiveDateTime::parse_from_str(&naive_str, SIP_DATE_FORMAT)
+        .map_err(|e| format!("Invalid SIP date: '{value}' {e}"))?;
+
+    match zone {
+        "" => date::localize(naive, timezone),
+        "Z" => date::localize(naive, "UTC"),
+        _ => Err(format!("Unsupported SIP date time zone: '{value}'").into()),
+    }
+}
+
+/// Transaction date of a message sent in no-block mode.
+///
+/// Clients running offline queue transactions and later send them
+/// with the no-block flag set and the time the transaction actually
+/// happened.  Returns the transaction date if the no-block flag is
+/// set and the date is in the past.
+pub fn no_block_date(no_block: &str, value: &str, timezone: &str) -> EgResult<Option<EgDate>> {
+    if no_block != "Y" {
+        return Ok(None);
+    }
+
+    let dt = parse_sip_date(value, timezone)?;
+
+    if dt < date::now() {
+        Ok(Some(dt))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Session {
+    /// Time zone used for SIP dates sent to and read from our client.
+    pub fn timezone(&self) -> &str {
+        if self.has_account() {
+            if let Some(tz) = self.account().timezone() {
+                return tz;
+            }
+        }
+
+        self.org_timezone().unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    /// Format a timestamp as a SIP date in our time zone.
+    pub fn sip_date(&self, dt: &EgDate) -> EgResult<String> {
+        format_sip_date(dt, self.timezone())
+    }
+
+    /// The current time as a SIP date in our time zone.
+    pub fn sip_date_now(&self) -> String {
+        match self.sip_date(&date::now()) {
+            Ok(d) => d,
+            Err(e) => {
+                log::error!("{self} cannot format SIP date: {e}");
+                sip2::util::sip_date_now()
+            }
+        }
+    }
+
+    /// Format a due date per our account settings.
+    pub fn sip_due_date(&self, due_date: Option<&str>) -> EgResult<Option<String>> {
+        if let Some(format) = self.account().settings().due_date_format() {
+            return format_due_date_custom(due_date, self.timezone(), format);
+        }
+
+        format_due_date(
+            due_date,
+            self.timezone(),
+            self.account().settings().due_date_use_sip_date_format(),
+        )
+    }
+
+    /// Parse a SIP date sent by our client.
+    pub fn parse_sip_date(&self, value: &str) -> EgResult<EgDate> {
+        parse_sip_date(value, self.timezone())
+    }
+
+    /// Transaction date of a no-block message, if it is in the past.
+    ///
+    /// Invalid dates are logged and ignored.
+    pub fn no_block_date(&self, no_block: &str, value: &str) -> Option<EgDate> {
+        match no_block_date(no_block, value, self.timezone()) {
+            Ok(d) => d,
+            Err(e) => {
+                log::warn!("{self} Invalid no-block transaction date: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn test_format_sip_date() {
+    let tz = "America/New_York";
+
+    // Evergreen reports timestamps in the database time zone.
+    let dt = date::parse_datetime("2024-07-01T22:59:59-0500").unwrap();
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20240701    235959");
+    assert_eq!(format_sip_date(&dt, "UTC").unwrap(), "20240702    035959");
+
+    // Either side of the spring and fall clock changes.
+    let dt = date::parse_datetime("2024-03-10T06:59:59Z").unwrap();
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20240310    015959");
+    let dt = date::parse_datetime("2024-03-10T07:00:00Z").unwrap();
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20240310    030000");
+    let dt = date::parse_datetime("2024-11-03T05:30:00Z").unwrap();
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20241103    013000");
+    let dt = date::parse_datetime("2024-11-03T06:30:00Z").unwrap();
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20241103    013000");
+
+    assert!(format_sip_date(&dt, "Nowhere/Special").is_err());
+}
+
+#[test]
+fn test_format_due_date() {
+    let tz = "America/Chicago";
+
+    assert_eq!(format_due_date(None, tz, true).unwrap(), None);
+    assert_eq!(format_due_date(Some(""), tz, true).unwrap(), None);
+
+    let due = "2024-11-04T00:59:59-0500";
+    assert_eq!(
+        format_due_date(Some(due), tz, true).unwrap().as_deref(),
+        Some("20241103    235959")
+    );
+    assert_eq!(
+        format_due_date(Some(due), tz, false).unwrap().as_deref(),
+        Some(due)
+    );
+
+    assert!(format_due_date(Some("tomorrow"), tz, true).is_err());
+
+    // End of day in Chicago is an hour earlier in Denver.
+    assert_eq!(
+        format_due_date(Some(due), "America/Denver", true)
+            .unwrap()
+            .as_deref(),
+        Some("20241103    225959")
+    );
+}
+
+#[test]
+fn test_format_due_date_custom() {
+    let tz = "America/New_York";
+    let due = "2025-03-04T04:59:59Z";
+    let human = "%B %-d, %Y %-I:%M %p";
+
+    assert_eq!(
+        format_due_date_custom(Some(due), tz, human)
+            .unwrap()
+            .as_deref(),
+        Some("March 3, 2025 11:59 PM")
+    );
+    assert_eq!(
+        format_due_date_custom(Some(due), tz, "%m/%d/%Y")
+            .unwrap()
+            .as_deref(),
+        Some("03/03/2025")
+    );
+    assert_eq!(format_due_date_custom(None, tz, human).unwrap(), None);
+    assert_eq!(format_due_date_custom(Some(""), tz, human).unwrap(), None);
+
+    assert!(check_date_format(human).is_ok());
+    assert!(check_date_format("%Y%m%d    %H%M%S").is_ok());
+    assert!(check_date_format("%Q").is_err());
+    assert!(check_date_format("%").is_err());
+    assert!(check_date_format("").is_err());
+}
+
+#[test]
+fn test_format_sip_ymd() {
+    assert_eq!(format_sip_ymd("1931-02-28").unwrap(), "19310228");
+    assert_eq!(format_sip_ymd("1969-12-31").unwrap(), "19691231");
+    assert_eq!(
+        format_sip_ymd("1899-07-04T00:00:00-0456").unwrap(),
+        "18990704"
+    );
+    assert!(format_sip_ymd("02/28/1931").is_err());
+}
+
+#[test]
+fn test_parse_sip_date() {
+    let tz = "America/New_York";
+
+    let dt = parse_sip_date("20240701    120000", tz).unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-07-01T12:00:00-0400");
+
+    let dt = parse_sip_date("20240115    120000", tz).unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-01-15T12:00:00-0500");
+
+    let dt = parse_sip_date("20240701   Z120000", tz).unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-07-01T12:00:00+0000");
+
+    // Repeated and skipped times around clock changes.
+    let dt = parse_sip_date("20241103    013000", tz).unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-11-03T01:30:00-0400");
+    let dt = parse_sip_date("20240310    023000", tz).unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-03-10T03:30:00-0400");
+
+    // Round trip
+    assert_eq!(format_sip_date(&dt, tz).unwrap(), "20240310    033000");
+
+    assert!(parse_sip_date("20240701 120000", tz).is_err());
+    assert!(parse_sip_date("20241301    120000", tz).is_err());
+    assert!(parse_sip_date("20240701 EST120000", tz).is_err());
+}
+
+#[test]
+fn test_no_block_date() {
+    let tz = "America/New_York";
+    let past = "20240701    120000";
+
+    let dt = no_block_date("Y", past, tz).unwrap().unwrap();
+    assert_eq!(date::to_iso(&dt), "2024-07-01T12:00:00-0400");
+
+    assert!(no_block_date("N", past, tz).unwrap().is_none());
+    assert!(no_block_date(" ", "", tz).unwrap().is_none());
+
+    let tomorrow = date::add_interval(date::now(), "1 day").unwrap();
+    let future = format_sip_date(&tomorrow, tz).unwrap();
+    assert!(no_block_date("Y", &future, tz).unwrap().is_none());
+
+    assert!(no_block_date("Y", "20240701", tz).is_err());
+}
@@ -0,0 +1,653 @@
+//! Server statistics and the HTTP status listener which reports them.
+use super::access::AccountSessions;
+use super::offline::OfflineJournal;
+use super::shutdown::ShutdownCoordinator;
+use eg::osrf::events::{ServiceEventKind, ServiceEvents};
+use eg::osrf::metrics;
+use eg::osrf::pool::BusPool;
+use evergreen as eg;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wake this often to check for shutdown.
+const STATUS_POLL_INTERVAL: u64 = 3;
+
+/// Status clients have this long to send their request.
+const STATUS_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait this long for the health check API call to respond.
+const HEALTH_CHECK_TIMEOUT: i32 = 5;
+
+/// We are unhealthy while this service is not registered.
+const REQUIRED_SERVICE: &str = "open-ils.actor";
+
+#[derive(Default)]
+struct Counters {
+    /// SIP messages received by message code.
+    messages: Mutex<HashMap<String, u64>>,
+    connections_accepted: AtomicU64,
+    connections_rejected: AtomicU64,
+    tcp_errors: AtomicU64,
+    tls_errors: AtomicU64,
+    request_errors: AtomicU64,
+    transport_errors: AtomicU64,
+    timeouts: AtomicU64,
+    /// SIP requests delayed, rejected, or disconnected by rate limits.
+    rate_limit_delays: AtomicU64,
+    rate_limit_rejections: AtomicU64,
+    rate_limit_disconnects: AtomicU64,
+    /// Worker threads currently running.
+    workers: AtomicUsize,
+}
+
+/// Prometheus metrics, registered with the process-wide registry.
+struct Metrics {
+    messages: metrics::Counter,
+    request_duration: metrics::Histogram,
+    connections: metrics::Counter,
+    errors: metrics::Counter,
+    rate_limits: metrics::Counter,
+    sessions: metrics::Gauge,
+    workers: metrics::Gauge,
+    osrf_pool: metrics::Gauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = metrics::registry();
+
+        Metrics {
+            messages: registry.counter(
+                "sip_messages_total",
+                "SIP messages handled by message code and outcome",
+                &["code", "outcome"],
+            ),
+            request_duration: registry.histogram(
+                "sip_request_duration_seconds",
+                "Time spent handling SIP messages by message code",
+                &["code"],
+                metrics::LATENCY_BUCKETS,
+            ),
+            connections: registry.counter(
+                "sip_connections_total",
+                "SIP client connections by result",
+                &["result"],
+            ),
+            errors: registry.counter("sip_errors_total", "SIP server errors by kind", &["kind"]),
+            rate_limits: registry.counter(
+                "sip_rate_limit_actions_total",
+                "SIP requests delayed, rejected, or disconnected by rate limits",
+                &["action"],
+            ),
+            sessions: registry.gauge("sip_sessions", "Connected SIP sessions", &[]),
+            workers: registry.gauge("sip_workers", "SIP worker threads by state", &["state"]),
+            osrf_pool: registry.gauge(
+                "sip_osrf_pool_connections",
+                "Pooled OpenSRF connections by state",
+                &["state"],
+            ),
+        }
+    }
+}
+
+/// Counters shared by the Server and all Sessions.  Cloning is cheap.
+#[derive(Clone)]
+pub struct ServerStats {
+    started: Instant,
+    counters: Arc<Counters>,
+    metrics: Arc<Metrics>,
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        ServerStats {
+            started: Instant::now(),
+            counters: Default::default(),
+            metrics: Arc::new(Metrics::new()),
+        }
+    }
+
+    pub fn message_received(&self, code: &str) {
+        *self
+            .counters
+            .messages
+            .lock()
+            .unwrap()
+            .entry(code.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// A SIP message was handled.  Message codes are limited to the
+    /// codes we can parse, which keeps the metric labels bounded.
+    ///
+    /// outcome is one of "ok", "event", "timeout", "transport", or
+    /// "error".
+    pub fn message_handled(&self, code: &str, outcome: &str, duration: Duration) {
+        self.metrics.messages.inc(&[code, outcome]);
+        self.metrics
+            .request_duration
+            .observe_duration(&[code], duration);
+    }
+
+    pub fn connection_accepted(&self) {
+        self.counters
+            .connections_accepted
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.connections.inc(&["accepted"]);
+    }
+
+    pub fn connection_rejected(&self) {
+        self.counters
+            .connections_rejected
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.connections.inc(&["rejected"]);
+    }
+
+    pub fn tcp_error(&self) {
+        self.counters.tcp_errors.fetch_add(1, Ordering::Relaxed);
+        self.metrics.errors.inc(&["tcp"]);
+    }
+
+    pub fn tls_error(&self) {
+        self.counters.tls_errors.fetch_add(1, Ordering::Relaxed);
+        self.metrics.errors.inc(&["tls"]);
+    }
+
+    /// A SIP request failed, ending its Session.
+    pub fn request_error(&self) {
+        self.counters.request_errors.fetch_add(1, Ordering::Relaxed);
+        self.metrics.errors.inc(&["request"]);
+    }
+
+    /// A SIP request failed because the OpenSRF connection dropped.
+    pub fn transport_error(&self) {
+        self.counters
+            .transport_errors
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.errors.inc(&["transport"]);
+    }
+
+    /// An Evergreen API call did not complete in time.
+    pub fn timeout(&self) {
+        self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+        self.metrics.errors.inc(&["timeout"]);
+    }
+
+    /// A SIP request was delayed to stay within its rate limits.
+    pub fn rate_limit_delay(&self) {
+        self.counters
+            .rate_limit_delays
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.rate_limits.inc(&["delayed"]);
+    }
+
+    /// A SIP request was refused for exceeding its rate limits.
+    pub fn rate_limit_rejection(&self) {
+        self.counters
+            .rate_limit_rejections
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.rate_limits.inc(&["rejected"]);
+    }
+
+    /// A SIP client was disconnected for exceeding its rate limits.
+    pub fn rate_limit_disconnect(&self) {
+        self.counters
+            .rate_limit_disconnects
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics.rate_limits.inc(&["disconnected"]);
+    }
+
+    pub fn worker_started(&self) {
+        self.counters.workers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn worker_ended(&self) {
+        self.counters.workers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Render our metrics, and those of the OpenSRF client, in the
+    /// Prometheus text format.
+    ///
+    /// Session, worker, and connection pool gauges are sampled now.
+    pub fn to_prometheus(&self, shutdown: &ShutdownCoordinator, bus_pool: &BusPool) -> String {
+        let active = shutdown.registry().len();
+        let workers = self.counters.workers.load(Ordering::Relaxed);
+        let pool = bus_pool.stats();
+        let m = &self.metrics;
+
+        m.sessions.set(&[], active as f64);
+        m.workers.set(&["active"], active.min(workers) as f64);
+        m.workers
+            .set(&["idle"], workers.saturating_sub(active) as f64);
+        m.osrf_pool.set(&["in_use"], pool.in_use as f64);
+        m.osrf_pool.set(&["idle"], pool.idle as f64);
+
+        metrics::registry().render()
+    }
+
+    /// Report our stats along with active session info.
+    ///
+    /// Each connected Session occupies one worker thread.
+    pub fn to_json(
+        &self,
+        shutdown: &ShutdownCoordinator,
+        account_sessions: &AccountSessions,
+        bus_pool: &BusPool,
+    ) -> json::JsonValue {
+        let c = &self.counters;
+
+        let mut messages = json::JsonValue::new_object();
+        for (code, count) in c.messages.lock().unwrap().iter() {
+            messages[code.as_str()] = (*count).into();
+        }
+
+        let mut accounts = json::JsonValue::new_object();
+        for (username, count) in account_sessions.counts() {
+            accounts[username.as_str()] = count.into();
+        }
+
+        let registry = shutdown.registry();
+
+        // Requests handled by the currently connected sessions.
+        let mut requests = json::JsonValue::new_object();
+        for (username, (_, count)) in registry.account_activity() {
+            requests[username.as_str()] = count.into();
+        }
+
+        let active = registry.len();
+        let workers = c.workers.load(Ordering::Relaxed);
+        let pool = bus_pool.stats();
+
+        json::object! {
+            uptime: self.started.elapsed().as_secs(),
+            active_sessions: active,
+            account_sessions: accounts,
+            account_requests: requests,
+            messages: messages,
+            connections: {
+                accepted: c.connections_accepted.load(Ordering::Relaxed),
+                rejected: c.connections_rejected.load(Ordering::Relaxed),
+            },
+            errors: {
+                tcp: c.tcp_errors.load(Ordering::Relaxed),
+                tls: c.tls_errors.load(Ordering::Relaxed),
+                request: c.request_errors.load(Ordering::Relaxed),
+                transport: c.transport_errors.load(Ordering::Relaxed),
+                timeout: c.timeouts.load(Ordering::Relaxed),
+            },
+            rate_limits: {
+                delayed: c.rate_limit_delays.load(Ordering::Relaxed),
+                rejected: c.rate_limit_rejections.load(Ordering::Relaxed),
+                disconnected: c.rate_limit_disconnects.load(Ordering::Relaxed),
+            },
+            workers: {
+                total: workers,
+                active: active.min(workers),
+                idle: workers.saturating_sub(active),
+            },
+            osrf_pool: {
+                in_use: pool.in_use,
+                idle: pool.idle,
+                created: pool.created,
+                recycled: pool.recycled,
+                discarded: pool.discarded,
+            },
+        }
+    }
+}
+
+/// Minimal HTTP listener reporting server stats.
+///
+/// GET /stats returns our stats as JSON.
+/// GET /metrics returns our stats in the Prometheus text format.
+/// GET /healthz returns 200 if we can reach OpenSRF, 503 otherwise.
+/// GET /offline/replay replays the offline journal, if enabled, and
+/// returns a summary.
+pub struct StatusListener {
+    listener: TcpListener,
+    stats: ServerStats,
+    bus_pool: BusPool,
+    shutdown: ShutdownCoordinator,
+    account_sessions: AccountSessions,
+    offline: Option<Arc<OfflineJournal>>,
+
+    /// Router service events, if the subscription succeeded.
+    service_events: Option<ServiceEvents>,
+
+    /// False once the router announces our required service is gone.
+    service_available: Arc<AtomicBool>,
+}
+
+impl StatusListener {
+    pub fn new(
+        address: &str,
+        port: u16,
+        stats: ServerStats,
+        bus_pool: BusPool,
+        shutdown: ShutdownCoordinator,
+        account_sessions: AccountSessions,
+        offline: Option<Arc<OfflineJournal>>,
+    ) -> Result<Self, String> {
+        let listener = eg::util::tcp_listener(address, port, STATUS_POLL_INTERVAL)?;
+
+        log::info!("Status listener running at {address}:{port}");
+
+        Ok(StatusListener {
+            listener,
+            stats,
+            bus_pool,
+            shutdown,
+            account_sessions,
+            offline,
+            service_events: None,
+            // The service was reachable when the server started.
+            service_available: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// Report unhealthy when the router announces our required
+    /// service has gone away.
+    pub fn set_service_events(&mut self, events: ServiceEvents) {
+        self.service_events = Some(events);
+    }
+
+    /// Handle status requests in a new thread until the server
+    /// shuts down.
+    pub fn spawn(mut self) {
+        if let Some(events) = self.service_events.take() {
+            let available = self.service_available.clone();
+            let shutdown = self.shutdown.clone();
+            thread::spawn(move || watch_service(events, available, shutdown));
+        }
+
+        thread::spawn(move || self.listen());
+    }
+
+    fn listen(self) {
+        // Used for health checks.  Connected on demand, within our
+        // thread, since OpenSRF clients cannot be shared across threads.
+        let mut osrf_client = None;
+
+        while !self.shutdown.shutting_down() {
+            let stream = match self.listener.accept() {
+                Ok((s, _)) => s,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        log::error!("Status listener accept() failed: {e}");
+                    }
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.handle_request(stream, &mut osrf_client) {
+                log::warn!("Status request failed: {e}");
+            }
+        }
+
+        log::debug!("Status listener exiting");
+    }
+
+    fn handle_request(
+        &self,
+        mut stream: TcpStream,
+        osrf_client: &mut Option<eg::Client>,
+    ) -> Result<(), String> {
+        stream
+            .set_read_timeout(Some(STATUS_READ_TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+
+        let path = read_request_path(&mut stream)?;
+
+        if path.as_deref() == Some("/metrics") {
+            let body = self.stats.to_prometheus(&self.shutdown, &self.bus_pool);
+            return write_response(&mut stream, "200 OK", metrics::CONTENT_TYPE, &body);
+        }
+
+        let (status, body) = match path.as_deref() {
+            Some("/stats") => (
+                "200 OK",
+                self.stats
+                    .to_json(&self.shutdown, &self.account_sessions, &self.bus_pool)
+                    .dump(),
+            ),
+            Some("/healthz") => match self.check_health(osrf_client) {
+                Ok(()) => ("200 OK", json::object! {status: "ok"}.dump()),
+                Err(e) => {
+                    log::warn!("Health check failed: {e}");
+                    (
+                        "503 Service Unavailable",
+                        json::object! {status: "error", error: e}.dump(),
+                    )
+                }
+            },
+            Some("/offline/replay") => match self.replay_offline(osrf_client) {
+                Ok(summary) => ("200 OK", summary.dump()),
+                Err(e) => {
+                    log::warn!("Offline replay failed: {e}");
+                    (
+                        "500 Internal Server Error",
+                        json::object! {status: "error", error: e}.dump(),
+                    )
+                }
+            },
+            Some(_) => ("404 Not Found", String::new()),
+            None => ("400 Bad Request", String::new()),
+        };
+
+        write_response(&mut stream, status, "application/json", &body)
+    }
+}
+
+fn write_response(
+    stream: &mut impl Write,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<(), String> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| format!("{e}"))
+}
+
+impl StatusListener {
+    /// Replay the offline journal now.
+    fn replay_offline(
+        &self,
+        osrf_client: &mut Option<eg::Client>,
+    ) -> Result<json::JsonValue, String> {
+        let journal = self
+            .offline
+            .as_ref()
+            .ok_or_else(|| "Offline transactions are not enabled".to_string())?;
+
+        if osrf_client.is_none() {
+            *osrf_client = Some(eg::Client::connect()?);
+        }
+
+        let summary = journal.replay_to_evergreen(osrf_client.as_ref().unwrap())?;
+
+        if summary.interrupted.is_some() {
+            // Reconnect on the next request.
+            *osrf_client = None;
+        }
+
+        let mut json = summary.to_json();
+        json["pending"] = journal.pending_count()?.into();
+
+        Ok(json)
+    }
+}
+
+/// Track registration of our required service via router events.
+///
+/// The service is considered unavailable once its last instance on any
+/// node unregisters, until an instance registers again.
+fn watch_service(events: ServiceEvents, available: Arc<AtomicBool>, shutdown: ShutdownCoordinator) {
+    while !shutdown.shutting_down() {
+        let event = match events.recv_timeout(Duration::from_secs(STATUS_POLL_INTERVAL)) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        if event.service() != REQUIRED_SERVICE {
+            continue;
+        }
+
+        let registered = event.kind() == ServiceEventKind::Registered;
+
+        if registered {
+            log::info!("{REQUIRED_SERVICE} registered on {}", event.node());
+        } else {
+            log::warn!("{REQUIRED_SERVICE} unregistered from {}", event.node());
+        }
+
+        available.store(registered, Ordering::Relaxed);
+    }
+}
+
+impl StatusListener {
+    /// Verify our required service is registered and that we can
+    /// reach a service via the OpenSRF router.
+    fn check_health(&self, osrf_client: &mut Option<eg::Client>) -> Result<(), String> {
+        if !self.service_available.load(Ordering::Relaxed) {
+            return Err(format!(
+                "{REQUIRED_SERVICE} is not registered with the router"
+            ));
+        }
+
+        check_router(osrf_client)
+    }
+}
+
+/// Verify we can reach a service via the OpenSRF router.
+fn check_router(osrf_client: &mut Option<eg::Client>) -> Result<(), String> {
+    if osrf_client.is_none() {
+        *osrf_client = Some(eg::Client::connect()?);
+    }
+
+    let result = osrf_client
+        .as_ref()
+        .unwrap()
+        .session("opensrf.settings")
+        .request("opensrf.system.echo", "healthz")
+        .and_then(|mut req| req.first_with_timeout(HEALTH_CHECK_TIMEOUT));
+
+    let error = match result {
+        Ok(Some(_)) => return Ok(()),
+        Ok(None) => "Health check request timed out".to_string(),
+        Err(e) => e.to_string(),
+    };
+
+    // Reconnect on the next check.
+    *osrf_client = None;
+
+    Err(error)
+}
+
+/// Read an HTTP GET request and return its path.
+///
+/// Returns None if the request is not a GET request.
+fn read_request_path(stream: &mut impl Read) -> Result<Option<String>, String> {
+    let mut text = String::new();
+    let mut buf = [0u8; 1024];
+
+    while !text.contains("\r\n\r\n") {
+        let count = stream.read(&mut buf).map_err(|e| format!("{e}"))?;
+        if count == 0 {
+            break;
+        }
+
+        text.push_str(&String::from_utf8_lossy(&buf[..count]));
+
+        if text.len() > 8192 {
+            return Err("Status request too large".to_string());
+        }
+    }
+
+    let mut parts = text.lines().next().unwrap_or("").split_whitespace();
+
+    if parts.next() != Some("GET") {
+        return Ok(None);
+    }
+
+    // Ignore any query string
+    Ok(parts
+        .next()
+        .map(|p| p.split('?').next().unwrap_or(p).to_string()))
+}
+
+#[test]
+fn test_server_stats() {
+    let stats = ServerStats::new();
+    let shutdown = ShutdownCoordinator::new(
+        super::registry::SessionRegistry::new(),
+        Duration::from_secs(1),
+        Duration::from_secs(1),
+    );
+    let account_sessions = AccountSessions::new();
+    let bus_pool = BusPool::new(0, 2);
+
+    stats.worker_started();
+    stats.worker_started();
+    stats.connection_accepted();
+    stats.connection_rejected();
+    stats.message_received("93");
+    stats.message_received("23");
+    stats.message_received("23");
+    stats.request_error();
+    stats.timeout();
+
+    let session = shutdown.register("SIPSession", None);
+    session.set_account(Some("sip-user"));
+    session.touch();
+    let _guard = account_sessions.acquire("sip-user", None).unwrap();
+
+    let report = stats.to_json(&shutdown, &account_sessions, &bus_pool);
+
+    assert_eq!(report["active_sessions"].as_usize(), Some(1));
+    assert_eq!(report["account_sessions"]["sip-user"].as_usize(), Some(1));
+    assert_eq!(report["account_requests"]["sip-user"].as_u64(), Some(1));
+    assert_eq!(report["messages"]["23"].as_u64(), Some(2));
+    assert_eq!(report["messages"]["93"].as_u64(), Some(1));
+    assert_eq!(report["connections"]["accepted"].as_u64(), Some(1));
+    assert_eq!(report["connections"]["rejected"].as_u64(), Some(1));
+    assert_eq!(report["errors"]["request"].as_u64(), Some(1));
+    assert_eq!(report["errors"]["timeout"].as_u64(), Some(1));
+    assert_eq!(report["workers"]["total"].as_usize(), Some(2));
+    assert_eq!(report["workers"]["idle"].as_usize(), Some(1));
+    assert_eq!(report["osrf_pool"]["in_use"].as_usize(), Some(0));
+    assert_eq!(report["osrf_pool"]["created"].as_u64(), Some(0));
+
+    stats.message_handled("23", "ok", Duration::from_millis(20));
+
+    let text = stats.to_prometheus(&shutdown, &bus_pool);
+
+    assert!(text.contains("# TYPE sip_messages_total counter\n"));
+    assert!(text.contains("sip_messages_total{code=\"23\",outcome=\"ok\"} "));
+    assert!(text.contains("sip_request_duration_seconds_bucket{code=\"23\",le=\"0.025\"} "));
+    assert!(text.contains("sip_sessions 1\n"));
+    assert!(text.contains("sip_workers{state=\"idle\"} 1\n"));
+}
+
+#[test]
+fn test_read_request_path() {
+    let mut req = "GET /stats?pretty=1 HTTP/1.1\r\nHost: localhost\r\n\r\n".as_bytes();
+    assert_eq!(
+        read_request_path(&mut req).unwrap().as_deref(),
+        Some("/stats")
+    );
+
+    let mut req = "POST /stats HTTP/1.1\r\n\r\n".as_bytes();
+    assert_eq!(read_request_path(&mut req).unwrap(), None);
+}
@@ -0,0 +1,265 @@
+//! TLS wrapping for inbound SIP connections.
+use openssl::ssl::{ErrorCode, HandshakeError, SslAcceptor, SslFiletype, SslMethod, SslStream};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait before retrying a handshake stalled on a write.
+const WRITE_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Performs the server side of the TLS handshake for SIP clients.
+///
+/// Cloning is cheap.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: Arc<SslAcceptor>,
+    handshake_timeout: Duration,
+}
+
+impl TlsAcceptor {
+    pub fn new(cert_chain: &str, key: &str, handshake_timeout: u64) -> Result<Self, String> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())
+            .map_err(|e| format!("Cannot create TLS acceptor: {e}"))?;
+
+        builder
+            .set_certificate_chain_file(cert_chain)
+            .map_err(|e| format!("Cannot load TLS certificate chain {cert_chain}: {e}"))?;
+
+        builder
+            .set_private_key_file(key, SslFiletype::PEM)
+            .map_err(|e| format!("Cannot load TLS private key {key}: {e}"))?;
+
+        builder
+            .check_private_key()
+            .map_err(|e| format!("TLS private key does not match certificate: {e}"))?;
+
+        Ok(TlsAcceptor {
+            acceptor: Arc::new(builder.build()),
+            handshake_timeout: Duration::from_secs(handshake_timeout),
+        })
+    }
+
+    /// Complete the TLS handshake with a newly connected SIP client.
+    ///
+    /// Fails if the handshake does not complete within the handshake
+    /// timeout so a stalled client cannot hold a worker indefinitely.
+    ///
+    /// The timeout applies to the handshake as a whole.  The socket is
+    /// non-blocking while handshaking, so each time OpenSSL runs out of
+    /// data we wait for more for no longer than the time remaining.
+    /// A client dribbling out one byte at a time cannot extend it.
+    pub fn accept(&self, stream: TcpStream) -> Result<SslStream<TcpStream>, String> {
+        let peer = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or("<unknown>".to_string());
+
+        let deadline = Instant::now() + self.handshake_timeout;
+
+        stream
+            .set_nonblocking(true)
+            .map_err(|e| format!("Cannot start TLS handshake with {peer}: {e}"))?;
+
+        let mut result = self.acceptor.accept(stream);
+
+        let tls_stream = loop {
+            let mid = match result {
+                Ok(s) => break s,
+                Err(HandshakeError::WouldBlock(mid)) => mid,
+                Err(e) => return Err(format!("TLS handshake with {peer} failed: {e}")),
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Err(format!("TLS handshake with {peer} timed out"));
+            }
+
+            if mid.error().code() == ErrorCode::WANT_READ {
+                TlsAcceptor::wait_readable(mid.get_ref(), remaining)
+                    .map_err(|e| format!("TLS handshake with {peer} failed: {e}"))?;
+            } else {
+                // Our handshake messages are small enough that a full
+                // send buffer is unusual.  Give the client a moment.
+                thread::sleep(remaining.min(WRITE_RETRY_INTERVAL));
+            }
+
+            result = mid.handshake();
+        };
+
+        // Read timeouts are managed per-read by the sip2::Connection.
+        tls_stream
+            .get_ref()
+            .set_nonblocking(false)
+            .map_err(|e| format!("Cannot finish TLS handshake with {peer}: {e}"))?;
+
+        Ok(tls_stream)
+    }
+
+    /// Wait up to `timeout` for data to arrive on a non-blocking socket.
+    ///
+    /// Returns Ok at end of file as well, since the next handshake
+    /// attempt will report it.
+    fn wait_readable(stream: &TcpStream, timeout: Duration) -> Result<(), String> {
+        stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(timeout)))
+            .map_err(|e| format!("Cannot set handshake timeout: {e}"))?;
+
+        let peeked = stream.peek(&mut [0u8; 1]);
+
+        stream
+            .set_read_timeout(None)
+            .and_then(|_| stream.set_nonblocking(true))
+            .map_err(|e| format!("Cannot resume handshake: {e}"))?;
+
+        match peeked {
+            Ok(_) => Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Err("timed out".to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// Create a self-signed certificate and key, returning their file paths.
+#[cfg(test)]
+fn self_signed_cert_files(name: &str) -> (String, String) {
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509NameBuilder, X509};
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_text("CN", "localhost").unwrap();
+    let subject = subject.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_issuer_name(&subject).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let cert_path = dir.join(format!("sip2-server-{name}-{pid}.crt"));
+    let key_path = dir.join(format!("sip2-server-{name}-{pid}.key"));
+
+    std::fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+    (
+        cert_path.to_string_lossy().to_string(),
+        key_path.to_string_lossy().to_string(),
+    )
+}
+
+#[test]
+fn test_tls_sip_roundtrip() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    let (cert, key) = self_signed_cert_files("roundtrip");
+    let acceptor = TlsAcceptor::new(&cert, &key, 5).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let tls_stream = acceptor.accept(stream).unwrap();
+
+        let mut con = sip2::Connection::from_sip_stream(Box::new(tls_stream));
+        let req = con.recv().unwrap();
+
+        let resp = sip2::Message::from_ff_values(&sip2::spec::M_LOGIN_RESP, &["1"]).unwrap();
+        con.send(&resp).unwrap();
+
+        req
+    });
+
+    let mut client = sip2::Connection::new_tls(&addr, false).unwrap();
+
+    let req = sip2::Message::from_values(
+        &sip2::spec::M_LOGIN,
+        &["0", "0"],
+        &[("CN", "sip-user"), ("CO", "sip-pass")],
+    )
+    .unwrap();
+
+    let resp = client.sendrecv(&req).unwrap();
+
+    assert_eq!(resp.spec().code, "94");
+    assert_eq!(resp.fixed_fields()[0].value(), "1");
+    assert_eq!(
+        server.join().unwrap().get_field_value("CN"),
+        Some("sip-user")
+    );
+
+    std::fs::remove_file(cert).ok();
+    std::fs::remove_file(key).ok();
+}
+
+#[test]
+fn test_tls_handshake_timeout() {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    let (cert, key) = self_signed_cert_files("timeout");
+    let acceptor = TlsAcceptor::new(&cert, &key, 1).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Connect, but never start the handshake.
+    let _client = TcpStream::connect(addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    let start = Instant::now();
+    let result = acceptor.accept(stream);
+
+    assert!(result.is_err());
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    // Start a handshake, then send one byte of a (large) TLS record
+    // at a time, each well within the handshake timeout.
+    let dripper = thread::spawn(move || {
+        let mut client = TcpStream::connect(addr).unwrap();
+        let record = [0x16, 0x03, 0x01, 0x02, 0x00];
+        let started = Instant::now();
+
+        for byte in record.iter().chain([0u8; 512].iter()) {
+            if started.elapsed() > Duration::from_secs(6) || client.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+
+    let start = Instant::now();
+    let result = acceptor.accept(stream);
+
+    assert!(result.is_err());
+    assert!(start.elapsed() < Duration::from_secs(3));
+
+    dripper.join().unwrap();
+
+    std::fs::remove_file(cert).ok();
+    std::fs::remove_file(key).ok();
+}
@@ -1,4 +1,6 @@
+use super::conf;
 use super::session::Session;
+use eg::common::settings::Settings;
 use eg::result::EgResult;
 use eg::EgValue;
 use evergreen as eg;
@@ -36,37 +38,74 @@ impl Session {
         Ok(resp)
     }
 
-    pub fn org_from_id(&mut self, id: i64) -> EgResult<Option<&EgValue>> {
-        if self.org_cache().contains_key(&id) {
-            return Ok(self.org_cache().get(&id));
+    pub fn org_from_id(&mut self, id: i64) -> EgResult<Option<EgValue>> {
+        if let Some(org) = self.cache().org(id) {
+            return Ok(Some(org));
         }
 
         if let Some(org) = self.editor_mut().retrieve("aou", id)? {
-            self.org_cache_mut().insert(id, org);
-            return Ok(self.org_cache().get(&id));
+            self.cache().insert_org(org.clone())?;
+            return Ok(Some(org));
         }
 
         Ok(None)
     }
 
-    pub fn org_from_sn(&mut self, sn: &str) -> EgResult<Option<&EgValue>> {
-        for (id, org) in self.org_cache() {
-            if org["shortname"].as_str().unwrap().eq(sn) {
-                return Ok(self.org_cache().get(id));
-            }
+    pub fn org_from_sn(&mut self, sn: &str) -> EgResult<Option<EgValue>> {
+        if let Some(org) = self.cache().org_by_shortname(sn) {
+            return Ok(Some(org));
         }
 
         let mut orgs = self.editor_mut().search("aou", eg::hash! {shortname: sn})?;
 
         if let Some(org) = orgs.pop() {
-            let id = org.id()?;
-            self.org_cache_mut().insert(id, org);
-            return Ok(self.org_cache().get(&id));
+            self.cache().insert_org(org.clone())?;
+            return Ok(Some(org));
         }
 
         return Ok(None);
     }
 
+    /// Value of an org unit setting, as seen from the provided org unit.
+    ///
+    /// Values are cached across Sessions, including null values.
+    pub fn org_setting(&mut self, org_id: i64, name: &str) -> EgResult<EgValue> {
+        if let Some(value) = self.cache().org_setting(org_id, name) {
+            return Ok(value);
+        }
+
+        let value = Settings::new(self.editor())
+            .get_value_at_org(name, org_id)?
+            .clone();
+
+        self.cache().insert_org_setting(org_id, name, value.clone());
+
+        Ok(value)
+    }
+
+    /// All rows for a small IDL lookup table, e.g. "ccm".
+    ///
+    /// Tables are cached across Sessions.
+    pub fn lookup_table(&mut self, classname: &str) -> EgResult<Vec<EgValue>> {
+        if let Some(rows) = self.cache().lookup_table(classname) {
+            return Ok(rows);
+        }
+
+        let pkey = eg::idl::get_class(classname)?
+            .pkey()
+            .ok_or_else(|| format!("Class {classname} has no primary key"))?
+            .to_string();
+
+        let mut search = EgValue::new_object();
+        search[&pkey] = eg::hash! {"!=": EgValue::Null};
+
+        let rows = self.editor_mut().search(classname, search)?;
+
+        self.cache().load_lookup_table(classname, rows.clone());
+
+        Ok(rows)
+    }
+
     /// Panics if this session is not authenticated.
     pub fn get_ws_org_id(&self) -> EgResult<i64> {
         let requestor = self
@@ -91,18 +130,59 @@ impl Session {
         self.editor_mut().retrieve_with_ops("au", user_id, ops)
     }
 
+    /// CY value for a hold patron, per the hold-patron-identifier
+    /// setting.
+    ///
+    /// The user must have its card fleshed.
+    pub fn hold_patron_identifier(&self, user: &EgValue) -> EgResult<Option<String>> {
+        match self.account().settings().hold_patron_identifier() {
+            conf::HoldPatronIdentifier::Id => Ok(Some(user.id()?.to_string())),
+            conf::HoldPatronIdentifier::Barcode => {
+                Ok(user["card"]["barcode"].as_str().map(|bc| bc.to_string()))
+            }
+        }
+    }
+
+    /// Patron name for AE and DA fields, per the account name-format
+    /// and name-use-preferred settings.
     pub fn format_user_name(&self, user: &EgValue) -> String {
+        let use_preferred = self.account().name_use_preferred();
+
+        let part = |field: &str| {
+            let preferred = user[format!("pref_{field}").as_str()].as_str();
+            match preferred {
+                Some(p) if use_preferred && !p.trim().is_empty() => Some(p),
+                _ => user[field].as_str(),
+            }
+        };
+
+        let given = part("first_given_name");
+        let middle = part("second_given_name");
+        let family = part("family_name");
+
+        if let Some(template) = self.account().name_format() {
+            let values = [
+                ("prefix", part("prefix").unwrap_or("")),
+                ("given", given.unwrap_or("")),
+                ("middle", middle.unwrap_or("")),
+                ("family", family.unwrap_or("")),
+                ("suffix", part("suffix").unwrap_or("")),
+            ];
+
+            return format_name_template(template, &values);
+        }
+
         let mut name = String::new();
 
-        if let Some(n) = user["first_given_name"].as_str() {
+        if let Some(n) = given {
             name += n;
         }
 
-        if let Some(n) = user["second_given_name"].as_str() {
+        if let Some(n) = middle {
             name += &format!(" {n}");
         }
 
-        if let Some(n) = user["family_name"].as_str() {
+        if let Some(n) = family {
             name += &format!(" {n}");
         }
 
@@ -168,3 +248,57 @@ impl Session {
         }
     }
 }
+
+/// Replace {name} placeholders in a name template with their values,
+/// then drop the spaces and commas left around any empty values.
+fn format_name_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut formatted = template.to_string();
+
+    for (name, value) in values {
+        formatted = formatted.replace(&format!("{{{name}}}"), value.trim());
+    }
+
+    let mut formatted = formatted
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace(" ,", ",");
+
+    while formatted.contains(",,") {
+        formatted = formatted.replace(",,", ",");
+    }
+
+    formatted
+        .trim_matches(|c: char| c == ',' || c.is_whitespace())
+        .to_string()
+}
+
+#[test]
+fn test_format_name_template() {
+    let values = [
+        ("prefix", ""),
+        ("given", "Jane"),
+        ("middle", ""),
+        ("family", "Doe"),
+        ("suffix", "Jr."),
+    ];
+
+    assert_eq!(
+        format_name_template("{family}, {given} {middle}", &values),
+        "Doe, Jane"
+    );
+    assert_eq!(
+        format_name_template("{prefix} {given} {middle} {family}, {suffix}", &values),
+        "Jane Doe, Jr."
+    );
+    assert_eq!(
+        format_name_template("{family}, {prefix}, {given}", &values),
+        "Doe, Jane"
+    );
+
+    let values = [("given", "José"), ("family", ""), ("middle", "María")];
+    assert_eq!(
+        format_name_template("{family}, {given} {middle}", &values),
+        "José María"
+    );
+}
@@ -0,0 +1,189 @@
+//! SIP protocol version negotiation.
+//!
+//! SIP clients declare the protocol version they speak in their SC
+//! Status request.  SIP 1.00 clients may reject responses containing
+//! fields added in SIP 2.00, so those are removed before the response
+//! is sent.  Accounts may set a protocol-version for clients which do
+//! not send SC Status.
+use super::session::Session;
+
+/// SIP protocol versions we know how to speak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProtocolVersion {
+    /// Parse a protocol version as it appears in SC Status, e.g. "1.00".
+    pub fn parse(s: &str) -> Option<ProtocolVersion> {
+        match s.trim() {
+            "1.00" => Some(Self::V1),
+            "2.00" => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::V1 => "1.00",
+            Self::V2 => "2.00",
+        }
+    }
+}
+
+/// Variable-length fields added in SIP 2.00, by response message code.
+///
+/// Messages which only exist in SIP 2.00, e.g. Patron Information
+/// Response, are listed where they share fields with SIP 1.00 messages,
+/// so a SIP 1.00 client never sees those fields.
+const V2_ONLY_FIELDS: &[(&str, &[&str])] = &[
+    // ACS Status: supported messages
+    ("98", &["BX"]),
+    // Patron Status Response: valid patron, valid patron password,
+    // currency type, fee amount
+    ("24", &["BL", "CQ", "BH", "BV"]),
+    // Patron Information Response
+    ("64", &["BL", "CQ", "BH", "BV"]),
+    // Checkout Response: fee type, security inhibit, currency type,
+    // fee amount, media type, item properties, transaction id
+    ("12", &["BT", "CI", "BH", "BV", "CK", "CH", "BK"]),
+    // Checkin Response: sort bin, media type, item properties,
+    // patron identifier
+    ("10", &["CL", "CK", "CH", "AA"]),
+];
+
+/// Fixed fields added in SIP 2.00, by response message code.
+const V2_ONLY_FIXED_FIELDS: &[(&str, &[&str])] =
+    &[("12", &["magnetic media"]), ("10", &["magnetic media"])];
+
+/// Remove or adjust response values the client's protocol version
+/// does not support.
+pub fn filter_response(version: ProtocolVersion, resp: &mut sip2::Message) {
+    if version == ProtocolVersion::V2 {
+        return;
+    }
+
+    let code = resp.spec().code;
+
+    if let Some((_, fields)) = V2_ONLY_FIELDS.iter().find(|(c, _)| *c == code) {
+        resp.fields_mut().retain(|f| !fields.contains(&f.code()));
+    }
+
+    if let Some((_, labels)) = V2_ONLY_FIXED_FIELDS.iter().find(|(c, _)| *c == code) {
+        resp.fixed_fields_mut()
+            .retain(|ff| !labels.contains(&ff.spec().label));
+    }
+
+    for ff in resp.fixed_fields_mut().iter_mut() {
+        if ff.spec().label == sip2::spec::FF_PROTOCOL_VERSION.label {
+            *ff = sip2::FixedField::new(ff.spec(), version.as_str()).unwrap();
+        }
+    }
+}
+
+impl Session {
+    /// Protocol version declared by our client, then the account's
+    /// protocol-version, defaulting to SIP 2.00.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.declared_protocol_version()
+            .or_else(|| {
+                self.has_account()
+                    .then(|| self.account().protocol_version())
+                    .flatten()
+            })
+            .unwrap_or(ProtocolVersion::V2)
+    }
+
+    /// Remove response values our client's protocol version does not
+    /// support.
+    pub fn apply_protocol_version(&self, resp: &mut sip2::Message) {
+        filter_response(self.protocol_version(), resp);
+    }
+}
+
+#[cfg(test)]
+fn acs_status_response() -> sip2::Message {
+    sip2::Message::from_values(
+        &sip2::spec::M_ACS_STATUS,
+        &[
+            "Y",
+            "Y",
+            "Y",
+            "Y",
+            "N",
+            "N",
+            "999",
+            "999",
+            "20240101    120000",
+            "2.00",
+        ],
+        &[("BX", "YYYYYYYYYYYYYYYY"), ("AO", "example")],
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn patron_status_response() -> sip2::Message {
+    sip2::Message::from_values(
+        &sip2::spec::M_PATRON_STATUS_RESP,
+        &["              ", "000", "20240101    120000"],
+        &[
+            ("AA", "123"),
+            ("AE", "Jane Doe"),
+            ("BL", "Y"),
+            ("CQ", "Y"),
+            ("BV", "0.00"),
+        ],
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_parse_protocol_version() {
+    assert_eq!(ProtocolVersion::parse("1.00"), Some(ProtocolVersion::V1));
+    assert_eq!(ProtocolVersion::parse("2.00"), Some(ProtocolVersion::V2));
+    assert_eq!(ProtocolVersion::parse("    "), None);
+}
+
+#[test]
+fn test_v1_omits_v2_fields() {
+    let mut resp = acs_status_response();
+    filter_response(ProtocolVersion::V1, &mut resp);
+
+    assert_eq!(resp.get_field_value("BX"), None);
+    assert_eq!(resp.get_field_value("AO"), Some("example"));
+    assert_eq!(resp.fixed_fields()[9].value(), "1.00");
+
+    let mut resp = patron_status_response();
+    filter_response(ProtocolVersion::V1, &mut resp);
+
+    assert_eq!(resp.get_field_value("CQ"), None);
+    assert_eq!(resp.get_field_value("BL"), None);
+    assert_eq!(resp.get_field_value("BV"), None);
+    assert_eq!(resp.get_field_value("AE"), Some("Jane Doe"));
+
+    let mut resp = sip2::Message::from_values(
+        &sip2::spec::M_CHECKOUT_RESP,
+        &["1", "N", "U", "N", "20240101    120000"],
+        &[("AB", "123"), ("CK", "001")],
+    )
+    .unwrap();
+    filter_response(ProtocolVersion::V1, &mut resp);
+
+    assert_eq!(resp.fixed_fields().len(), 4);
+    assert_eq!(resp.get_field_value("CK"), None);
+}
+
+#[test]
+fn test_v2_keeps_v2_fields() {
+    let mut resp = acs_status_response();
+    filter_response(ProtocolVersion::V2, &mut resp);
+
+    assert_eq!(resp, acs_status_response());
+
+    let mut resp = patron_status_response();
+    filter_response(ProtocolVersion::V2, &mut resp);
+
+    assert_eq!(resp.get_field_value("CQ"), Some("Y"));
+}
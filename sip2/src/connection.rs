@@ -1,22 +1,34 @@
+use super::encoding::Encoding;
 use super::error::Error;
 use super::spec;
 use super::Message;
-use deunicode::deunicode;
 use std::io::prelude::*;
 use std::net::{Shutdown, TcpStream};
-use std::str;
 use std::time::Duration;
 
 // Read data from the socket in chunks this size.
 const READ_BUFSIZE: usize = 256;
 
+/// A stream capable of carrying SIP messages, e.g. a plain TcpStream
+/// or a TLS stream layered over a TcpStream.
+pub trait SipStream: Read + Write + Send {
+    /// The underlying TCP socket, used for timeouts, shutdown, etc.
+    fn tcp_stream(&self) -> &TcpStream;
+}
+
+impl SipStream for TcpStream {
+    fn tcp_stream(&self) -> &TcpStream {
+        self
+    }
+}
+
 /// Manages a TCP connection to a SIP server and handles message sending
 /// and receiving.
 pub struct Connection {
-    tcp_stream: TcpStream,
+    stream: Box<dyn SipStream>,
 
-    // If set, non-ASCII chars are removed from outbound messages.
-    ascii: bool,
+    // Character encoding for outbound and inbound messages.
+    encoding: Encoding,
 }
 
 impl Connection {
@@ -33,10 +45,7 @@ impl Connection {
         log::debug!("Connection::new() connecting to: {}", sip_host);
 
         match TcpStream::connect(sip_host) {
-            Ok(stream) => Ok(Connection {
-                tcp_stream: stream,
-                ascii: false,
-            }),
+            Ok(stream) => Ok(Connection::from_sip_stream(Box::new(stream))),
             Err(s) => {
                 log::error!("Connection::new() failed: {}", s);
                 return Err(Error::NetworkError);
@@ -45,21 +54,45 @@ impl Connection {
     }
 
     pub fn from_stream(tcp_stream: TcpStream) -> Self {
+        Connection::from_sip_stream(Box::new(tcp_stream))
+    }
+
+    /// Create a connection from any SipStream, e.g. a TLS stream.
+    pub fn from_sip_stream(stream: Box<dyn SipStream>) -> Self {
         Connection {
-            ascii: false,
-            tcp_stream: tcp_stream,
+            encoding: Encoding::Utf8,
+            stream,
         }
     }
 
+    /// The underlying TCP socket.
+    pub fn tcp_stream(&self) -> &TcpStream {
+        self.stream.tcp_stream()
+    }
+
+    /// If set, non-ASCII chars are transliterated in outbound messages.
+    ///
+    /// Shortcut for set_encoding(Encoding::Ascii) / set_encoding(Encoding::Utf8).
     pub fn set_ascii(&mut self, ascii: bool) {
-        self.ascii = ascii;
+        self.encoding = match ascii {
+            true => Encoding::Ascii,
+            false => Encoding::Utf8,
+        };
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
     }
 
     /// Shutdown the TCP connection with the SIP server.
     pub fn disconnect(&self) -> Result<(), Error> {
         log::debug!("Connection::disconnect()");
 
-        match self.tcp_stream.shutdown(Shutdown::Both) {
+        match self.tcp_stream().shutdown(Shutdown::Both) {
             Ok(_) => Ok(()),
             Err(s) => {
                 log::error!("disconnect() failed: {}", s);
@@ -70,18 +103,12 @@ impl Connection {
 
     /// Send a SIP message
     pub fn send(&mut self, msg: &Message) -> Result<(), Error> {
-        let mut msg_sip = msg.to_sip() + spec::LINE_TERMINATOR;
-
-        if self.ascii {
-            // https://crates.io/crates/deunicode
-            // "Some transliterations do produce \n characters."
-            msg_sip = deunicode(&msg_sip).replace("\n", "");
-        }
+        let msg_sip = self.encoding.message_to_sip(msg) + spec::LINE_TERMINATOR;
 
         // No need to redact here since SIP replies do not include passwords.
         log::info!("OUTBOUND: {}", msg_sip);
 
-        match self.tcp_stream.write(&msg_sip.as_bytes()) {
+        match self.stream.write_all(&self.encoding.encode(&msg_sip)) {
             Ok(_) => Ok(()),
             Err(s) => {
                 log::error!("send() failed: {}", s);
@@ -110,17 +137,17 @@ impl Connection {
     fn recv_internal(&mut self, timeout: Option<Duration>) -> Result<Option<Message>, Error> {
         log::trace!("recv_internal() with timeout {:?}", timeout);
 
-        if let Err(e) = self.tcp_stream.set_read_timeout(timeout) {
+        if let Err(e) = self.tcp_stream().set_read_timeout(timeout) {
             log::error!("Invalid timeout: {timeout:?} {e}");
             return Err(Error::NetworkError);
         }
 
-        let mut text = String::from("");
+        let mut bytes: Vec<u8> = Vec::new();
 
         loop {
             let mut buf: [u8; READ_BUFSIZE] = [0; READ_BUFSIZE];
 
-            let num_bytes = match self.tcp_stream.read(&mut buf) {
+            let num_bytes = match self.stream.read(&mut buf) {
                 Ok(num) => num,
                 Err(e) => match e.kind() {
                     std::io::ErrorKind::WouldBlock => {
@@ -138,28 +165,24 @@ impl Connection {
                 break;
             }
 
-            let chunk = match str::from_utf8(&buf) {
-                Ok(s) => s,
-                Err(s) => {
-                    log::error!("recv() got non-utf data: {}", s);
-                    return Err(Error::MessageFormatError);
-                }
-            };
-
-            text.push_str(chunk);
+            // Decode once the whole message has arrived, since
+            // multi-byte characters may span reads.
+            bytes.extend_from_slice(&buf[..num_bytes]);
 
             if num_bytes < READ_BUFSIZE {
                 break;
             }
         }
 
-        if text.len() == 0 {
+        if bytes.is_empty() {
             // Receiving none with no timeout indicates either an error
             // or the client simply disconnected.
             log::debug!("Reading TCP stream returned 0 bytes");
             return Err(Error::NoResponseError);
         }
 
+        let text = self.encoding.decode(&bytes)?;
+
         // Discard the line terminator and any junk after it.
         let mut parts = text.split(spec::LINE_TERMINATOR);
 
@@ -0,0 +1,179 @@
+//! Character encodings for SIP messages on the wire.
+//!
+//! Older SIP devices cannot cope with UTF-8.  Values which cannot be
+//! represented in the selected encoding are transliterated, e.g.
+//! é => e and ß => ss, before the message is serialized, so the
+//! field delimiters and fixed field widths of the final message are
+//! not disturbed.
+use super::error::Error;
+use super::message::Message;
+use super::spec;
+use deunicode::deunicode_char;
+use std::fmt;
+use std::str::FromStr;
+
+/// Used when a character has no transliteration.
+const UNKNOWN_CHAR: &str = "?";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    /// Send and receive UTF-8 as-is.
+    #[default]
+    Utf8,
+    /// ISO-8859-1.  Characters beyond Latin-1 are transliterated.
+    Latin1,
+    /// 7-bit ASCII.  All non-ASCII characters are transliterated.
+    Ascii,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    /// ```
+    /// use sip2::Encoding;
+    /// assert_eq!("latin1".parse::<Encoding>(), Ok(Encoding::Latin1));
+    /// assert_eq!("UTF-8".parse::<Encoding>(), Ok(Encoding::Utf8));
+    /// assert!("ebcdic".parse::<Encoding>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(Self::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Self::Latin1),
+            "ascii" => Ok(Self::Ascii),
+            _ => Err(format!("Unsupported SIP encoding: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Utf8 => "utf8",
+            Self::Latin1 => "latin1",
+            Self::Ascii => "ascii",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Encoding {
+    /// True if the character can be sent as-is.
+    fn supports(&self, c: char) -> bool {
+        match self {
+            Self::Utf8 => true,
+            Self::Latin1 => (c as u32) <= 0xFF,
+            Self::Ascii => c.is_ascii(),
+        }
+    }
+
+    /// Replace characters this encoding cannot represent.
+    ///
+    /// Transliterations never introduce field delimiters or line
+    /// breaks.
+    ///
+    /// ```
+    /// use sip2::Encoding;
+    /// assert_eq!(Encoding::Ascii.transliterate("Straße"), "Strasse");
+    /// assert_eq!(Encoding::Latin1.transliterate("Straße"), "Straße");
+    /// assert_eq!(Encoding::Latin1.transliterate("Łódź"), "Lódz");
+    /// ```
+    pub fn transliterate(&self, value: &str) -> String {
+        if value.chars().all(|c| self.supports(c)) {
+            return value.to_string();
+        }
+
+        let mut s = String::with_capacity(value.len());
+
+        for c in value.chars() {
+            if self.supports(c) {
+                s.push(c);
+                continue;
+            }
+
+            let replacement = deunicode_char(c).unwrap_or(UNKNOWN_CHAR);
+
+            s.extend(
+                replacement
+                    .chars()
+                    .filter(|r| !matches!(r, '|' | '\r' | '\n')),
+            );
+        }
+
+        s
+    }
+
+    /// Create the SIP string for a message with all values
+    /// transliterated for this encoding.
+    ///
+    /// Fixed fields are limited to ASCII and are padded or truncated
+    /// as needed to retain their required lengths.
+    ///
+    /// ```
+    /// use sip2::{Encoding, Message};
+    /// let msg = Message::from_sip("9300CNЖюль|COpass|").unwrap();
+    /// assert_eq!(Encoding::Ascii.message_to_sip(&msg), "9300CNZhiul'|COpass|");
+    /// assert_eq!(Encoding::Utf8.message_to_sip(&msg), msg.to_sip());
+    /// ```
+    pub fn message_to_sip(&self, msg: &Message) -> String {
+        if *self == Self::Utf8 {
+            return msg.to_sip();
+        }
+
+        let mut s = msg.spec().code.to_string();
+
+        for ff in msg.fixed_fields() {
+            s += &fit_to_length(&Self::Ascii.transliterate(&ff.to_sip()), ff.spec());
+        }
+
+        for f in msg.fields() {
+            s += &self.transliterate(&f.to_sip());
+        }
+
+        s
+    }
+
+    /// Encode SIP text as bytes.
+    ///
+    /// Characters which cannot be represented are transliterated.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Ascii | Self::Latin1 => self
+                .transliterate(text)
+                .chars()
+                // Transliteration leaves only single-byte chars.
+                .map(|c| c as u32 as u8)
+                .collect(),
+        }
+    }
+
+    /// Decode bytes read from the wire.
+    ///
+    /// ASCII devices which send 8-bit characters anyway are assumed
+    /// to be sending Latin-1, unless the bytes are valid UTF-8.
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, Error> {
+        match self {
+            Self::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(s) => Ok(s.to_string()),
+                Err(e) => {
+                    log::error!("Received non-UTF-8 data: {e}");
+                    Err(Error::MessageFormatError)
+                }
+            },
+            Self::Latin1 => Ok(latin1_to_string(bytes)),
+            Self::Ascii => match std::str::from_utf8(bytes) {
+                Ok(s) => Ok(s.to_string()),
+                Err(_) => Ok(latin1_to_string(bytes)),
+            },
+        }
+    }
+}
+
+/// Every Latin-1 byte maps to the Unicode code point of the same value.
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn fit_to_length(value: &str, ff_spec: &spec::FixedField) -> String {
+    format!("{value:<0$.0$}", ff_spec.length)
+}
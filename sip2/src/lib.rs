@@ -1,4 +1,6 @@
 pub use self::connection::Connection;
+pub use self::connection::SipStream;
+pub use self::encoding::Encoding;
 pub use self::error::Error;
 pub use self::message::Field;
 pub use self::message::FixedField;
@@ -12,6 +14,7 @@ pub mod util;
 
 mod client;
 mod connection;
+mod encoding;
 mod error;
 mod message;
 mod params;
@@ -19,5 +22,8 @@ mod params;
 #[cfg(feature = "json")]
 mod message_json;
 
+#[cfg(feature = "tls")]
+mod tls;
+
 #[cfg(test)]
 mod tests;
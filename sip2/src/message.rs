@@ -257,6 +257,10 @@ impl Message {
         &self.fixed_fields
     }
 
+    pub fn fixed_fields_mut(&mut self) -> &mut Vec<FixedField> {
+        &mut self.fixed_fields
+    }
+
     /// Create a SIP string of a message.
     ///
     /// ```
@@ -357,7 +361,7 @@ impl Message {
         let mut msg_text = &text[2..];
 
         for ff_spec in msg_spec.fixed_fields.iter() {
-            if msg_text.len() < ff_spec.length {
+            if msg_text.len() < ff_spec.length || !msg_text.is_char_boundary(ff_spec.length) {
                 // Fixed Fields must match known values.
 
                 warn!(
@@ -382,7 +386,7 @@ impl Message {
         }
 
         for part in msg_text.split("|") {
-            if part.len() > 1 {
+            if part.len() > 1 && part.is_char_boundary(2) {
                 let val = match part.len() > 2 {
                     true => &part[2..],
                     _ => "",
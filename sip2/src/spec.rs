@@ -1,5 +1,7 @@
 //! SIP2 Specification as a collection of static values.
+use super::error::Error;
 use std::fmt;
+use std::sync::RwLock;
 
 pub const SIP_PROTOCOL_VERSION: &str = "2.00";
 pub const LINE_TERMINATOR: &str = "\r";
@@ -163,6 +165,17 @@ impl Message {
     /// assert_eq!(msg2.code, msg.code);
     /// ```
     pub fn from_code(code: &str) -> Option<&'static Message> {
+        Message::standard_from_code(code).or_else(|| {
+            EXTENSIONS
+                .read()
+                .unwrap()
+                .iter()
+                .find(|m| m.code == code)
+                .copied()
+        })
+    }
+
+    fn standard_from_code(code: &str) -> Option<&'static Message> {
         match code {
             m if m == M_SC_STATUS.code => Some(&M_SC_STATUS),
             m if m == M_ACS_STATUS.code => Some(&M_ACS_STATUS),
@@ -170,6 +183,8 @@ impl Message {
             m if m == M_LOGIN_RESP.code => Some(&M_LOGIN_RESP),
             m if m == M_ITEM_INFO.code => Some(&M_ITEM_INFO),
             m if m == M_ITEM_INFO_RESP.code => Some(&M_ITEM_INFO_RESP),
+            m if m == M_ITEM_STATUS_UPDATE.code => Some(&M_ITEM_STATUS_UPDATE),
+            m if m == M_ITEM_STATUS_UPDATE_RESP.code => Some(&M_ITEM_STATUS_UPDATE_RESP),
             m if m == M_PATRON_STATUS.code => Some(&M_PATRON_STATUS),
             m if m == M_PATRON_STATUS_RESP.code => Some(&M_PATRON_STATUS_RESP),
             m if m == M_PATRON_INFO.code => Some(&M_PATRON_INFO),
@@ -195,8 +210,56 @@ impl Message {
             _ => None,
         }
     }
+
+    /// Registers a vendor extension message so it can be parsed and
+    /// built like any other message.
+    ///
+    /// Registering an already registered code returns the existing
+    /// spec.  Codes used by standard messages cannot be registered.
+    ///
+    /// ```
+    /// use sip2::spec;
+    /// let msg = spec::Message::register_extension("Z1", "Example", &[&spec::FF_DATE]).unwrap();
+    /// assert_eq!(spec::Message::from_code("Z1").unwrap(), msg);
+    /// assert!(spec::Message::register_extension("93", "Login", &[]).is_err());
+    /// ```
+    pub fn register_extension(
+        code: &str,
+        label: &str,
+        fixed_fields: &'static [&'static FixedField],
+    ) -> Result<&'static Message, Error> {
+        if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            log::error!("Invalid extension message code: {code}");
+            return Err(Error::UnknownMessageError);
+        }
+
+        let mut extensions = EXTENSIONS.write().unwrap();
+
+        if let Some(msg) = extensions.iter().find(|m| m.code == code) {
+            return Ok(msg);
+        }
+
+        if Message::standard_from_code(code).is_some() {
+            log::error!("Extension message code {code} is already in use");
+            return Err(Error::UnknownMessageError);
+        }
+
+        // Extensions live for the life of the process.
+        let msg: &'static Message = Box::leak(Box::new(Message {
+            code: Box::leak(code.to_string().into_boxed_str()),
+            label: Box::leak(label.to_string().into_boxed_str()),
+            fixed_fields,
+        }));
+
+        extensions.push(msg);
+
+        Ok(msg)
+    }
 }
 
+/// Vendor extension messages registered at runtime.
+static EXTENSIONS: RwLock<Vec<&'static Message>> = RwLock::new(Vec::new());
+
 // -------------------------------------------------------------------------
 // Fixed Fields
 // -------------------------------------------------------------------------
@@ -391,6 +454,10 @@ pub const FF_END_PATRON_SESSION: FF = FF {
     length: 1,
     label: "end session",
 };
+pub const FF_ITEM_PROPERTIES_OK: FF = FF {
+    length: 1,
+    label: "item properties ok",
+};
 
 // -------------------------------------------------------------------------
 // Fields
@@ -760,6 +827,20 @@ pub const M_ITEM_INFO_RESP: Message = Message {
     ],
 };
 
+/// Message 19
+pub const M_ITEM_STATUS_UPDATE: Message = Message {
+    code: "19",
+    label: "Item Status Update",
+    fixed_fields: &[&FF_DATE],
+};
+
+/// Message 20
+pub const M_ITEM_STATUS_UPDATE_RESP: Message = Message {
+    code: "20",
+    label: "Item Status Update Response",
+    fixed_fields: &[&FF_ITEM_PROPERTIES_OK, &FF_DATE],
+};
+
 /// Message 23
 pub const M_PATRON_STATUS: Message = Message {
     code: "23",
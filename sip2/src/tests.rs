@@ -2,6 +2,7 @@ use super::message::Field;
 use super::message::FixedField;
 use super::message::Message;
 use super::spec;
+use super::Encoding;
 
 #[test]
 fn invalid_fixed_field() {
@@ -51,3 +52,110 @@ fn fixed_field_to_str() {
     let ff = FixedField::new(&spec::FF_MAX_PRINT_WIDTH, "999").unwrap();
     assert_eq!(ff.to_sip(), "999");
 }
+
+/// Encode an item info response with a non-Latin title, read it back,
+/// and confirm the message framing survived.
+fn encoded_round_trip(encoding: Encoding, title: &str) -> Message {
+    let msg = Message::from_values(
+        &spec::M_ITEM_INFO_RESP,
+        &["03", "02", "01", "20240701    120000"],
+        &[("AB", "30001"), ("AJ", title), ("AP", "BR1")],
+    )
+    .unwrap();
+
+    let bytes = encoding.encode(&(encoding.message_to_sip(&msg) + spec::LINE_TERMINATOR));
+
+    match encoding {
+        Encoding::Ascii => assert!(bytes.is_ascii()),
+        Encoding::Latin1 => assert_eq!(
+            bytes.len(),
+            encoding.message_to_sip(&msg).chars().count() + 1
+        ),
+        Encoding::Utf8 => assert_eq!(bytes, (msg.to_sip() + "\r").as_bytes()),
+    }
+
+    // Exactly 3 field delimiters and a single terminator at the end.
+    assert_eq!(bytes.iter().filter(|b| **b == b'|').count(), 3);
+    assert_eq!(
+        bytes.iter().position(|b| *b == b'\r'),
+        Some(bytes.len() - 1)
+    );
+
+    let text = encoding.decode(&bytes[..bytes.len() - 1]).unwrap();
+    let parsed = Message::from_sip(&text).unwrap();
+
+    assert_eq!(parsed.fixed_fields(), msg.fixed_fields());
+    assert_eq!(parsed.fields().len(), 3);
+    assert_eq!(parsed.get_field_value("AB"), Some("30001"));
+    assert_eq!(parsed.get_field_value("AP"), Some("BR1"));
+
+    parsed
+}
+
+#[test]
+fn encoding_cyrillic_title() {
+    let title = "Война и мир";
+
+    let msg = encoded_round_trip(Encoding::Utf8, title);
+    assert_eq!(msg.get_field_value("AJ"), Some(title));
+
+    let msg = encoded_round_trip(Encoding::Latin1, title);
+    assert_eq!(msg.get_field_value("AJ"), Some("Voina i mir"));
+
+    let msg = encoded_round_trip(Encoding::Ascii, title);
+    assert_eq!(msg.get_field_value("AJ"), Some("Voina i mir"));
+}
+
+#[test]
+fn encoding_cjk_title() {
+    let title = "吾輩は猫である";
+
+    let msg = encoded_round_trip(Encoding::Utf8, title);
+    assert_eq!(msg.get_field_value("AJ"), Some(title));
+
+    for encoding in [Encoding::Latin1, Encoding::Ascii] {
+        let msg = encoded_round_trip(encoding, title);
+        let value = msg.get_field_value("AJ").unwrap();
+        assert!(!value.is_empty());
+        assert!(value.is_ascii());
+    }
+}
+
+#[test]
+fn encoding_latin1_accents() {
+    let title = "Les Misérables, Straße";
+
+    let msg = encoded_round_trip(Encoding::Latin1, title);
+    assert_eq!(msg.get_field_value("AJ"), Some(title));
+
+    let msg = encoded_round_trip(Encoding::Ascii, title);
+    assert_eq!(msg.get_field_value("AJ"), Some("Les Miserables, Strasse"));
+
+    // Latin-1 uses one byte per character.
+    let bytes = Encoding::Latin1.encode("é");
+    assert_eq!(bytes, vec![0xE9]);
+    assert_eq!(Encoding::Latin1.decode(&bytes).unwrap(), "é");
+
+    // 8-bit input from ASCII devices is read as Latin-1.
+    assert_eq!(Encoding::Ascii.decode(&bytes).unwrap(), "é");
+    assert!(Encoding::Utf8.decode(&bytes).is_err());
+}
+
+#[test]
+fn extension_message() {
+    assert!(Message::from_sip("Z9AApatron|").is_err());
+
+    let spec = spec::Message::register_extension("Z9", "Test Extension", &[&spec::FF_OK]).unwrap();
+
+    // Registering again returns the same spec.
+    let again = spec::Message::register_extension("Z9", "Test Extension", &[]).unwrap();
+    assert!(std::ptr::eq(spec, again));
+
+    let msg = Message::from_sip("Z91AApatron|").unwrap();
+    assert_eq!(msg.spec().code, "Z9");
+    assert_eq!(msg.fixed_fields()[0].value(), "1");
+    assert_eq!(msg.get_field_value("AA"), Some("patron"));
+
+    assert!(spec::Message::register_extension("11", "Checkout", &[]).is_err());
+    assert!(spec::Message::register_extension("Z", "Short", &[]).is_err());
+}
@@ -0,0 +1,49 @@
+//! TLS support for SIP connections.
+use super::connection::SipStream;
+use super::error::Error;
+use super::Connection;
+use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode};
+use std::net::TcpStream;
+
+impl SipStream for SslStream<TcpStream> {
+    fn tcp_stream(&self) -> &TcpStream {
+        self.get_ref()
+    }
+}
+
+impl Connection {
+    /// Creates a new SIP client and opens a TLS connection to the server.
+    ///
+    /// * `sip_host` - SIP server host/ip and port
+    /// * `verify` - If false, the server certificate is not verified,
+    ///   e.g. for testing against a self-signed certificate.
+    pub fn new_tls(sip_host: &str, verify: bool) -> Result<Self, Error> {
+        log::debug!("Connection::new_tls() connecting to: {}", sip_host);
+
+        let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(|e| {
+            log::error!("Cannot create TLS connector: {e}");
+            Error::NetworkError
+        })?;
+
+        if !verify {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+
+        let connector = builder.build();
+
+        let stream = TcpStream::connect(sip_host).map_err(|e| {
+            log::error!("Connection::new_tls() failed: {e}");
+            Error::NetworkError
+        })?;
+
+        // Hostname is used for SNI and certificate verification.
+        let domain = sip_host.rsplit_once(':').map(|p| p.0).unwrap_or(sip_host);
+
+        let tls_stream = connector.connect(domain, stream).map_err(|e| {
+            log::error!("TLS handshake with {sip_host} failed: {e}");
+            Error::NetworkError
+        })?;
+
+        Ok(Connection::from_sip_stream(Box::new(tls_stream)))
+    }
+}